@@ -90,4 +90,27 @@ Basically copied from https://github.com/bitnami/charts/blob/master/bitnami/comm
 - mountPath: {{ .Values.daemonset.hostContainerRuntimeEndpoint }}
   name: container-runtime
 {{- end }}
+{{- if .Values.daemonset.kdumpEvents }}
+- name: kdump-volume
+  mountPath: {{ .Values.daemonset.kdumpDirectory }}
+  readOnly: true
+- name: pstore-volume
+  mountPath: /sys/fs/pstore
+  readOnly: true
+{{- end }}
+{{- if .Values.daemonset.pauseConfigMap }}
+- name: pause-volume
+  mountPath: /etc/core-dump-handler/pause
+  readOnly: true
+{{- end }}
+{{- if .Values.daemonset.gcsCredentialsSecret }}
+- name: gcs-credentials-volume
+  mountPath: /etc/core-dump-handler/gcs
+  readOnly: true
+{{- end }}
+{{- if eq .Values.daemonset.corePatternMode "file" }}
+- name: core-file-volume
+  mountPath: {{ .Values.daemonset.coreFileDirectory }}
+  mountPropagation: Bidirectional
+{{- end }}
 {{- end -}}
@@ -0,0 +1,424 @@
+use data_encoding::HEXLOWER;
+use ring::digest::{Context, SHA256};
+use serde_json::Value;
+use std::fs;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::Poll;
+use tokio::io::AsyncWrite;
+
+/// The composer's archives, encrypted ones with ENCRYPT_RECIPIENTS.
+const ARCHIVE_SUFFIXES: [&str; 4] = [".zip", ".tar", ".tar.age", ".tar.gpg"];
+
+/// S3 allows at most 10 tags per object and 256 characters per value.
+const MAX_TAG_VALUE: usize = 256;
+
+/// Reads the dump-info document the composer stored in the archive, the
+/// native one when `-dump-info.json` holds the IBM fields. Encrypted
+/// archives are read from the plaintext copy written beside them.
+pub fn read_dump_info(path: &Path) -> Result<Value, anyhow::Error> {
+    match fs::read(dump_info_path(path)) {
+        Ok(v) => return Ok(serde_json::from_slice(&v)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    let file = File::open(path)?;
+    let mut archive = tar::Archive::new(file);
+    let mut dump_info = None;
+    // Seeking over the entries skips the core rather than reading it.
+    for entry in archive.entries_with_seek()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_str().unwrap_or_default().to_string();
+        let native = name.ends_with("-dump-info-native.json");
+        if native || name.ends_with("-dump-info.json") {
+            let mut content = String::new();
+            entry.read_to_string(&mut content)?;
+            dump_info = Some(serde_json::from_str(&content)?);
+            if native {
+                break;
+            }
+        }
+    }
+    dump_info.ok_or_else(|| anyhow::anyhow!("No dump-info found in {}", path.display()))
+}
+
+/// The stable id of a capture. Archives from before dump ids were recorded
+/// fall back to their uuid, which the composer uses as the dump id.
+pub fn dump_id(dump_info: &Value) -> Option<String> {
+    dump_info["dump_id"]
+        .as_str()
+        .or_else(|| dump_info["uuid"].as_str())
+        .filter(|v| !v.is_empty())
+        .map(|v| v.to_string())
+}
+
+/// Replaces characters S3 does not accept in tag values.
+fn tag_value(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || " +-=._:/@".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .take(MAX_TAG_VALUE)
+        .collect()
+}
+
+/// The object tags set on an uploaded archive so bucket lifecycle rules can
+/// select dumps by where they came from.
+///
+/// The signature falls back to `<exe>-<signal>` when the composer did not
+/// record one.
+pub fn upload_tags(dump_info: &Value, data_class: &str) -> Vec<(String, String)> {
+    let mut tags = vec![];
+    if let Some(dump_id) = dump_id(dump_info) {
+        tags.push(("dump_id".to_string(), tag_value(&dump_id)));
+    }
+    if let Some(namespace) = dump_info["namespace"].as_str() {
+        tags.push(("namespace".to_string(), tag_value(namespace)));
+    }
+    if let Some(pod) = dump_info["podname"].as_str() {
+        tags.push(("pod".to_string(), tag_value(pod)));
+    }
+    let signature = match dump_info["signature"].as_str() {
+        Some(v) => v.to_string(),
+        None => format!(
+            "{}-{}",
+            dump_info["exe"].as_str().unwrap_or_default(),
+            dump_info["signal"].as_str().unwrap_or_default()
+        ),
+    };
+    if signature != "-" {
+        tags.push(("signature".to_string(), tag_value(&signature)));
+    }
+    if dump_info["oom_correlated"].as_bool() == Some(true) {
+        tags.push(("oom_correlated".to_string(), "true".to_string()));
+    }
+    let data_class = dump_info["data_class"].as_str().unwrap_or(data_class);
+    if !data_class.is_empty() {
+        tags.push(("data_class".to_string(), tag_value(data_class)));
+    }
+    tags
+}
+
+/// Finds the archive `reupload` and `verify` act on. `target` is either a
+/// path, or the uuid or dump id of a capture still held in the core
+/// directory. Names that don't carry the id are matched by the dump id in
+/// their dump-info.
+pub fn resolve(core_dir: &str, target: &str) -> Result<PathBuf, anyhow::Error> {
+    if target.is_empty() {
+        return Err(anyhow::anyhow!("Pass the uuid or path of an archive"));
+    }
+    let path = PathBuf::from(target);
+    if path.is_file() {
+        return Ok(path);
+    }
+    let archives: Vec<PathBuf> = fs::read_dir(core_dir)?
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| {
+            p.file_name()
+                .and_then(|n| n.to_str())
+                .map(|n| ARCHIVE_SUFFIXES.iter().any(|s| n.ends_with(s)))
+                .unwrap_or(false)
+        })
+        .collect();
+    let mut found: Vec<PathBuf> = archives
+        .iter()
+        .filter(|p| {
+            p.file_name()
+                .and_then(|n| n.to_str())
+                .map(|n| n.contains(target))
+                .unwrap_or(false)
+        })
+        .cloned()
+        .collect();
+    if found.is_empty() {
+        found = archives
+            .into_iter()
+            .filter(|p| {
+                read_dump_info(p)
+                    .ok()
+                    .and_then(|d| dump_id(&d))
+                    .map(|id| id == target)
+                    .unwrap_or(false)
+            })
+            .collect();
+    }
+    match found.len() {
+        1 => Ok(found.remove(0)),
+        0 => Err(anyhow::anyhow!("No archive for {} in {}", target, core_dir)),
+        n => Err(anyhow::anyhow!(
+            "{} archives in {} match {}, pass a path instead",
+            n,
+            core_dir,
+            target
+        )),
+    }
+}
+
+/// The suffix of the checksum the composer writes beside each archive.
+pub const CHECKSUM_SUFFIX: &str = ".sha256";
+
+/// The suffix of the plaintext dump-info the composer writes beside an
+/// encrypted archive.
+pub const DUMP_INFO_SUFFIX: &str = ".dump-info.json";
+
+/// The suffix of the record the composer leaves for a capture it abandoned,
+/// kept on the node for monitoring.
+pub const FAILURE_SUFFIX: &str = ".failure.json";
+
+/// Where the composer wrote the checksum of the archive at `zip_path`.
+pub fn checksum_path(zip_path: &Path) -> PathBuf {
+    let mut name = zip_path.as_os_str().to_owned();
+    name.push(CHECKSUM_SUFFIX);
+    PathBuf::from(name)
+}
+
+/// Where the composer wrote the dump-info of the encrypted archive at
+/// `zip_path`.
+pub fn dump_info_path(zip_path: &Path) -> PathBuf {
+    let mut name = zip_path.as_os_str().to_owned();
+    name.push(DUMP_INFO_SUFFIX);
+    PathBuf::from(name)
+}
+
+/// Reads the digest from the `sha256sum` style line beside the archive.
+/// Archives of composers older than the checksum have none.
+pub fn read_checksum(zip_path: &Path) -> Result<Option<String>, anyhow::Error> {
+    let content = match fs::read_to_string(checksum_path(zip_path)) {
+        Ok(v) => v,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    match content.split_whitespace().next() {
+        Some(digest) => Ok(Some(digest.to_lowercase())),
+        None => Err(anyhow::anyhow!(
+            "{} is empty",
+            checksum_path(zip_path).display()
+        )),
+    }
+}
+
+/// Hashes a download as it streams so the remote copy can be checked
+/// without staging it on disk.
+pub struct Sha256Writer {
+    context: Context,
+    pub len: u64,
+}
+
+impl Sha256Writer {
+    pub fn new() -> Sha256Writer {
+        Sha256Writer {
+            context: Context::new(&SHA256),
+            len: 0,
+        }
+    }
+
+    /// Lower case hex, the same format `sha256::try_digest` returns.
+    pub fn finish(self) -> String {
+        HEXLOWER.encode(self.context.finish().as_ref())
+    }
+}
+
+impl Default for Sha256Writer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AsyncWrite for Sha256Writer {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        self.context.update(buf);
+        self.len += buf.len() as u64;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::archive::{
+        checksum_path, dump_info_path, read_checksum, read_dump_info, resolve, upload_tags,
+        Sha256Writer,
+    };
+    use serde_json::json;
+    use std::fs;
+    use uuid::Uuid;
+
+    #[test]
+    fn upload_tags_test() {
+        let dump_info = json!({
+            "exe": "node",
+            "signal": "11",
+            "namespace": "default",
+            "podname": "crashing-app-699c49b4ff-86wrh",
+            "data_class": null
+        });
+        let tags = upload_tags(&dump_info, "confidential");
+        let tags: Vec<(&str, &str)> = tags.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        assert_eq!(
+            tags,
+            vec![
+                ("namespace", "default"),
+                ("pod", "crashing-app-699c49b4ff-86wrh"),
+                ("signature", "node-11"),
+                ("data_class", "confidential"),
+            ]
+        );
+
+        // The classification recorded at capture time wins over the agent's.
+        let dump_info = json!({
+            "dump_id": "5ad2ea44",
+            "exe": "a*b",
+            "signal": "6",
+            "data_class": "restricted",
+            "oom_correlated": true
+        });
+        let tags = upload_tags(&dump_info, "confidential");
+        assert_eq!(tags[0], ("dump_id".to_string(), "5ad2ea44".to_string()));
+        assert_eq!(tags[1], ("signature".to_string(), "a_b-6".to_string()));
+        assert_eq!(tags[2], ("oom_correlated".to_string(), "true".to_string()));
+        assert_eq!(
+            tags[3],
+            ("data_class".to_string(), "restricted".to_string())
+        );
+    }
+
+    #[test]
+    fn read_dump_info_test() {
+        let path = std::env::temp_dir().join(format!("archive-test-{}.tar", Uuid::new_v4()));
+        let content = br#"{"exe":"node","signal":"11"}"#;
+        let mut builder = tar::Builder::new(fs::File::create(&path).unwrap());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "core/abc-dump-info.json", &content[..])
+            .unwrap();
+        builder.finish().unwrap();
+
+        let dump_info = read_dump_info(&path).unwrap();
+        assert_eq!(dump_info["exe"], "node");
+
+        // DUMP_INFO_FORMAT both, the IBM fields come first.
+        let mut builder = tar::Builder::new(fs::File::create(&path).unwrap());
+        for (name, content) in [
+            ("core/abc-dump-info.json", &br#"{"exe":"node"}"#[..]),
+            (
+                "core/abc-dump-info-native.json",
+                br#"{"exe":"node","dump_id":"abc"}"#,
+            ),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, name, content).unwrap();
+        }
+        builder.finish().unwrap();
+        assert_eq!(read_dump_info(&path).unwrap()["dump_id"], "abc");
+        fs::remove_file(&path).unwrap();
+
+        // An encrypted archive is not a tar, its dump-info lies beside it.
+        let path = path.with_extension("tar.age");
+        fs::write(&path, b"age-encryption.org/v1").unwrap();
+        assert!(read_dump_info(&path).is_err());
+        fs::write(dump_info_path(&path), br#"{"exe":"node","dump_id":"def"}"#).unwrap();
+        assert_eq!(read_dump_info(&path).unwrap()["dump_id"], "def");
+        fs::remove_file(dump_info_path(&path)).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn read_checksum_test() {
+        let path = std::env::temp_dir().join(format!("checksum-test-{}.tar", Uuid::new_v4()));
+        assert_eq!(
+            checksum_path(&path).to_str().unwrap(),
+            format!("{}.sha256", path.display())
+        );
+        assert_eq!(read_checksum(&path).unwrap(), None);
+        fs::write(checksum_path(&path), "9F86D081  core.tar\n").unwrap();
+        assert_eq!(read_checksum(&path).unwrap().as_deref(), Some("9f86d081"));
+        fs::write(checksum_path(&path), "").unwrap();
+        assert!(read_checksum(&path).is_err());
+        fs::remove_file(checksum_path(&path)).unwrap();
+    }
+
+    #[test]
+    fn resolve_test() {
+        let dir = std::env::temp_dir().join(format!("resolve-test-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let core_dir = dir.to_str().unwrap();
+        let uuid = "5ad2ea44-9e4f-4d36-b6b0-3bb8ef4e73ff";
+        let name = format!("{uuid}-dump-1706263200-node-node-1-11.zip");
+        fs::write(dir.join(&name), b"zip").unwrap();
+        fs::write(dir.join(format!("{uuid}-event.json")), b"{}").unwrap();
+
+        assert_eq!(resolve(core_dir, uuid).unwrap(), dir.join(&name));
+        let path = dir.join(&name);
+        assert_eq!(resolve(core_dir, path.to_str().unwrap()).unwrap(), path);
+        assert!(resolve(core_dir, "missing").is_err());
+        assert!(resolve(core_dir, "").is_err());
+
+        fs::write(
+            dir.join(format!("{uuid}-dump-1706263201-node-node-1-6.tar")),
+            b"tar",
+        )
+        .unwrap();
+        assert!(resolve(core_dir, uuid).is_err());
+
+        // A template without the uuid is found through its dump-info.
+        let content = br#"{"dump_id":"0f3e9b2c"}"#;
+        let mut builder = tar::Builder::new(fs::File::create(dir.join("default.tar")).unwrap());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "default-dump-info.json", &content[..])
+            .unwrap();
+        builder.finish().unwrap();
+        assert_eq!(
+            resolve(core_dir, "0f3e9b2c").unwrap(),
+            dir.join("default.tar")
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn sha256_writer_test() {
+        use tokio::io::AsyncWriteExt;
+        let path = std::env::temp_dir().join(format!("digest-test-{}", Uuid::new_v4()));
+        fs::write(&path, b"a core dump archive").unwrap();
+        let mut writer = Sha256Writer::new();
+        writer.write_all(b"a core dump ").await.unwrap();
+        writer.write_all(b"archive").await.unwrap();
+        assert_eq!(writer.len, 19);
+        assert_eq!(writer.finish(), sha256::try_digest(path.as_path()).unwrap());
+        fs::remove_file(&path).unwrap();
+    }
+}
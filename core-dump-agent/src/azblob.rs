@@ -0,0 +1,556 @@
+//! Azure Blob Storage backend, selected with `STORAGE_BACKEND=azblob`.
+//!
+//! Only the handful of REST calls the agent needs are implemented: Put Block
+//! and Put Block List to stream an archive, Put Blob for the health probe,
+//! Get Blob for `verify` and Create Container. Requests are signed with the
+//! account key of a connection string or carry a managed identity token.
+
+use anyhow::anyhow;
+use data_encoding::BASE64;
+use log::info;
+use ring::hmac;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+const API_VERSION: &str = "2020-10-02";
+/// Archives are sent in blocks of this size so the whole file is never
+/// held in memory.
+const BLOCK_SIZE: usize = 8 << 20;
+const IMDS_TOKEN_URL: &str = "http://169.254.169.254/metadata/identity/oauth2/token";
+const STORAGE_RESOURCE: &str = "https://storage.azure.com/";
+
+#[derive(Clone)]
+enum Credential {
+    SharedKey {
+        key: Vec<u8>,
+    },
+    /// The node's managed identity, or the user assigned one with this
+    /// client id.
+    ManagedIdentity {
+        client_id: Option<String>,
+        token: Arc<Mutex<Option<(String, u64)>>>,
+    },
+}
+
+#[derive(Clone)]
+pub struct Container {
+    pub account: String,
+    pub name: String,
+    endpoint: String,
+    credential: Credential,
+    client: reqwest::Client,
+}
+
+/// The fields of a storage account connection string.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ConnectionString {
+    pub account: String,
+    pub key: Vec<u8>,
+    pub endpoint: String,
+}
+
+impl ConnectionString {
+    pub fn parse(s: &str) -> Result<ConnectionString, anyhow::Error> {
+        let fields: BTreeMap<&str, &str> = s
+            .split(';')
+            .filter_map(|f| f.split_once('='))
+            .map(|(k, v)| (k.trim(), v.trim()))
+            .collect();
+        let account = fields
+            .get("AccountName")
+            .ok_or_else(|| anyhow!("Connection string has no AccountName"))?
+            .to_string();
+        let key = fields
+            .get("AccountKey")
+            .ok_or_else(|| anyhow!("Connection string has no AccountKey"))?;
+        let key = BASE64
+            .decode(key.as_bytes())
+            .map_err(|e| anyhow!("AccountKey is not base64: {}", e))?;
+        let endpoint = match fields.get("BlobEndpoint") {
+            Some(v) => v.trim_end_matches('/').to_string(),
+            None => format!(
+                "{}://{}.blob.{}",
+                fields.get("DefaultEndpointsProtocol").unwrap_or(&"https"),
+                account,
+                fields.get("EndpointSuffix").unwrap_or(&"core.windows.net")
+            ),
+        };
+        Ok(ConnectionString {
+            account,
+            key,
+            endpoint,
+        })
+    }
+}
+
+/// Percent encodes everything but the unreserved characters, and `/` in
+/// paths.
+fn encode(value: &str, path: bool) -> String {
+    let mut encoded = String::new();
+    for b in value.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(b as char)
+            }
+            b'/' if path => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{b:02X}")),
+        }
+    }
+    encoded
+}
+
+/// The blob tags header value, `k1=v1&k2=v2`.
+pub fn tags_header(tags: &[(String, String)]) -> String {
+    tags.iter()
+        .map(|(k, v)| format!("{}={}", encode(k, false), encode(v, false)))
+        .collect::<Vec<String>>()
+        .join("&")
+}
+
+/// The Shared Key string to sign, see
+/// https://learn.microsoft.com/rest/api/storageservices/authorize-with-shared-key
+pub fn string_to_sign(
+    method: &str,
+    content_length: usize,
+    headers: &BTreeMap<String, String>,
+    account: &str,
+    path: &str,
+    query: &[(&str, String)],
+) -> String {
+    let header = |name: &str| headers.get(name).map(|v| v.as_str()).unwrap_or("");
+    let length = if content_length == 0 {
+        String::new()
+    } else {
+        content_length.to_string()
+    };
+    let mut s = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}\n\n{}\n{}\n{}\n{}\n{}\n",
+        method,
+        header("content-encoding"),
+        header("content-language"),
+        length,
+        header("content-md5"),
+        header("content-type"),
+        header("if-modified-since"),
+        header("if-match"),
+        header("if-none-match"),
+        header("if-unmodified-since"),
+        header("range"),
+    );
+    for (name, value) in headers.iter().filter(|(n, _)| n.starts_with("x-ms-")) {
+        s.push_str(&format!("{}:{}\n", name, value.trim()));
+    }
+    s.push_str(&format!("/{account}{path}"));
+    let mut query: Vec<&(&str, String)> = query.iter().collect();
+    query.sort();
+    for (name, value) in query {
+        s.push_str(&format!("\n{}:{}", name.to_lowercase(), value));
+    }
+    s
+}
+
+pub fn sign(key: &[u8], string_to_sign: &str) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, key);
+    BASE64.encode(hmac::sign(&key, string_to_sign.as_bytes()).as_ref())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+impl Container {
+    /// Builds the container from the `{prefix}_*` env vars:
+    /// `CONNECTION_STRING` for an account key, otherwise `ACCOUNT` and the
+    /// optional `CLIENT_ID` of a managed identity. `BUCKET_NAME` is the
+    /// container and `ENDPOINT` overrides the blob endpoint.
+    pub fn from_vars<F: Fn(&str) -> String>(var: F) -> Result<Container, anyhow::Error> {
+        let name = var("BUCKET_NAME");
+        if name.is_empty() {
+            return Err(anyhow!("No container name in BUCKET_NAME"));
+        }
+        let connection_string = var("CONNECTION_STRING");
+        let (account, mut endpoint, credential) = if connection_string.is_empty() {
+            let account = var("ACCOUNT");
+            if account.is_empty() {
+                return Err(anyhow!("Neither CONNECTION_STRING nor ACCOUNT is set"));
+            }
+            let client_id = Some(var("CLIENT_ID")).filter(|v| !v.is_empty());
+            let endpoint = format!("https://{account}.blob.core.windows.net");
+            let credential = Credential::ManagedIdentity {
+                client_id,
+                token: Arc::new(Mutex::new(None)),
+            };
+            (account, endpoint, credential)
+        } else {
+            let cs = ConnectionString::parse(&connection_string)?;
+            (
+                cs.account,
+                cs.endpoint,
+                Credential::SharedKey { key: cs.key },
+            )
+        };
+        let custom_endpoint = var("ENDPOINT");
+        if !custom_endpoint.is_empty() {
+            info!("Setting blob endpoint location to: {}", custom_endpoint);
+            endpoint = custom_endpoint.trim_end_matches('/').to_string();
+        }
+        Ok(Container {
+            account,
+            name,
+            endpoint,
+            credential,
+            client: reqwest::Client::new(),
+        })
+    }
+
+    pub fn url(&self, blob: Option<&str>) -> String {
+        match blob {
+            Some(b) => format!("{}/{}/{}", self.endpoint, self.name, encode(b, true)),
+            None => format!("{}/{}", self.endpoint, self.name),
+        }
+    }
+
+    async fn token(&self, client_id: &Option<String>) -> Result<String, anyhow::Error> {
+        if let Credential::ManagedIdentity { token, .. } = &self.credential {
+            if let Some((value, expires)) = token.lock().unwrap().as_ref() {
+                if *expires > now() + 300 {
+                    return Ok(value.clone());
+                }
+            }
+        }
+        let mut query = vec![
+            ("api-version", "2018-02-01".to_string()),
+            ("resource", STORAGE_RESOURCE.to_string()),
+        ];
+        if let Some(id) = client_id {
+            query.push(("client_id", id.clone()));
+        }
+        let response = self
+            .client
+            .get(IMDS_TOKEN_URL)
+            .query(&query)
+            .header("Metadata", "true")
+            .send()
+            .await?;
+        let status = response.status();
+        let body = response.text().await?;
+        if !status.is_success() {
+            return Err(anyhow!(
+                "Managed identity token returned {}: {}",
+                status,
+                body
+            ));
+        }
+        let json: serde_json::Value = serde_json::from_str(&body)?;
+        let value = json["access_token"]
+            .as_str()
+            .ok_or_else(|| anyhow!("Managed identity response has no access_token"))?
+            .to_string();
+        let expires = json["expires_on"]
+            .as_str()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or_else(now);
+        if let Credential::ManagedIdentity { token, .. } = &self.credential {
+            *token.lock().unwrap() = Some((value.clone(), expires));
+        }
+        Ok(value)
+    }
+
+    /// Sends a signed request and returns the response, whatever its status.
+    async fn send(
+        &self,
+        method: reqwest::Method,
+        blob: Option<&str>,
+        query: &[(&str, String)],
+        mut headers: BTreeMap<String, String>,
+        body: Vec<u8>,
+    ) -> Result<reqwest::Response, anyhow::Error> {
+        headers.insert(
+            "x-ms-date".to_string(),
+            httpdate::fmt_http_date(SystemTime::now()),
+        );
+        headers.insert("x-ms-version".to_string(), API_VERSION.to_string());
+        let authorization = match &self.credential {
+            Credential::SharedKey { key } => {
+                // The endpoint path is part of the signed resource, emulators
+                // carry the account name there.
+                let url = reqwest::Url::parse(&self.url(blob))?;
+                let to_sign = string_to_sign(
+                    method.as_str(),
+                    body.len(),
+                    &headers,
+                    &self.account,
+                    url.path(),
+                    query,
+                );
+                format!("SharedKey {}:{}", self.account, sign(key, &to_sign))
+            }
+            Credential::ManagedIdentity { client_id, .. } => {
+                format!("Bearer {}", self.token(client_id).await?)
+            }
+        };
+        let mut request = self
+            .client
+            .request(method, self.url(blob))
+            .query(query)
+            .header("Authorization", authorization)
+            .header("Content-Length", body.len());
+        for (name, value) in &headers {
+            request = request.header(name.as_str(), value.as_str());
+        }
+        Ok(request.body(body).send().await?)
+    }
+
+    /// Turns a failed response into an error carrying the storage error
+    /// code, the way the S3 path reports a failed status.
+    async fn check(
+        response: reqwest::Response,
+        what: &str,
+    ) -> Result<reqwest::Response, anyhow::Error> {
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+        let code = response
+            .headers()
+            .get("x-ms-error-code")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        let body = response.text().await.unwrap_or_default();
+        Err(anyhow!(
+            "{} returned {} {}: {}",
+            what,
+            status.as_u16(),
+            code,
+            body
+        ))
+    }
+
+    /// Creates the container, an existing one is left alone.
+    pub async fn create(&self) -> Result<(), anyhow::Error> {
+        let query = [("restype", "container".to_string())];
+        let response = self
+            .send(reqwest::Method::PUT, None, &query, BTreeMap::new(), vec![])
+            .await?;
+        if response.status() == reqwest::StatusCode::CONFLICT {
+            return Ok(());
+        }
+        Container::check(response, &format!("Creating container {}", self.name)).await?;
+        info!("Created container {}", self.name);
+        Ok(())
+    }
+
+    async fn put_block(&self, blob: &str, id: &str, data: Vec<u8>) -> Result<(), anyhow::Error> {
+        let query = [("comp", "block".to_string()), ("blockid", id.to_string())];
+        let response = self
+            .send(
+                reqwest::Method::PUT,
+                Some(blob),
+                &query,
+                BTreeMap::new(),
+                data,
+            )
+            .await?;
+        Container::check(response, &format!("Block {id} of {blob}")).await?;
+        Ok(())
+    }
+
+    /// Streams the file at `path` to `blob` block by block and commits it
+    /// with `tags`. A missing container is created on the first block.
+    pub async fn put_file(
+        &self,
+        path: &Path,
+        blob: &str,
+        tags: &[(String, String)],
+    ) -> Result<u16, anyhow::Error> {
+        let mut file = tokio::fs::File::open(path).await?;
+        if file.metadata().await?.len() == 0 {
+            // Put Block rejects empty blocks.
+            return self.put_blob(blob, &[]).await;
+        }
+        let mut ids = vec![];
+        loop {
+            let mut chunk = Vec::with_capacity(BLOCK_SIZE);
+            (&mut file)
+                .take(BLOCK_SIZE as u64)
+                .read_to_end(&mut chunk)
+                .await?;
+            if chunk.is_empty() {
+                break;
+            }
+            let last = chunk.len() < BLOCK_SIZE;
+            let id = BASE64.encode(format!("{:08}", ids.len()).as_bytes());
+            match self.put_block(blob, &id, chunk.clone()).await {
+                Ok(_) => {}
+                Err(e) if ids.is_empty() && e.to_string().contains("ContainerNotFound") => {
+                    self.create().await?;
+                    self.put_block(blob, &id, chunk).await?;
+                }
+                Err(e) => return Err(e),
+            }
+            ids.push(id);
+            if last {
+                break;
+            }
+        }
+
+        let mut list = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?><BlockList>");
+        for id in &ids {
+            list.push_str(&format!("<Latest>{id}</Latest>"));
+        }
+        list.push_str("</BlockList>");
+        let mut headers = BTreeMap::new();
+        if !tags.is_empty() {
+            headers.insert("x-ms-tags".to_string(), tags_header(tags));
+        }
+        let query = [("comp", "blocklist".to_string())];
+        let response = self
+            .send(
+                reqwest::Method::PUT,
+                Some(blob),
+                &query,
+                headers,
+                list.into_bytes(),
+            )
+            .await?;
+        let response = Container::check(response, &format!("Upload of {blob}")).await?;
+        Ok(response.status().as_u16())
+    }
+
+    /// Writes a small blob in one request, used by the health probe.
+    pub async fn put_blob(&self, blob: &str, data: &[u8]) -> Result<u16, anyhow::Error> {
+        let mut headers = BTreeMap::new();
+        headers.insert("x-ms-blob-type".to_string(), "BlockBlob".to_string());
+        let response = self
+            .send(
+                reqwest::Method::PUT,
+                Some(blob),
+                &[],
+                headers,
+                data.to_vec(),
+            )
+            .await?;
+        let response = Container::check(response, &format!("Upload of {blob}")).await?;
+        Ok(response.status().as_u16())
+    }
+
+    pub async fn delete_blob(&self, blob: &str) -> Result<u16, anyhow::Error> {
+        let response = self
+            .send(
+                reqwest::Method::DELETE,
+                Some(blob),
+                &[],
+                BTreeMap::new(),
+                vec![],
+            )
+            .await?;
+        if response.status().as_u16() == 404 {
+            return Ok(404);
+        }
+        let response = Container::check(response, &format!("Deleting {blob}")).await?;
+        Ok(response.status().as_u16())
+    }
+
+    /// Streams `blob` into `writer` and returns the status.
+    pub async fn get_blob<W: AsyncWrite + Unpin>(
+        &self,
+        blob: &str,
+        writer: &mut W,
+    ) -> Result<u16, anyhow::Error> {
+        let response = self
+            .send(
+                reqwest::Method::GET,
+                Some(blob),
+                &[],
+                BTreeMap::new(),
+                vec![],
+            )
+            .await?;
+        let mut response = Container::check(response, &format!("Fetching {blob}")).await?;
+        while let Some(chunk) = response.chunk().await? {
+            writer.write_all(&chunk).await?;
+        }
+        writer.flush().await?;
+        Ok(response.status().as_u16())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::azblob::{sign, string_to_sign, tags_header, ConnectionString, Container};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn connection_string_test() {
+        let cs = ConnectionString::parse(
+            "DefaultEndpointsProtocol=https;AccountName=cores;AccountKey=a2V5;EndpointSuffix=core.chinacloudapi.cn",
+        )
+        .unwrap();
+        assert_eq!(cs.account, "cores");
+        assert_eq!(cs.key, b"key");
+        assert_eq!(cs.endpoint, "https://cores.blob.core.chinacloudapi.cn");
+        let cs = ConnectionString::parse(
+            "AccountName=devstoreaccount1;AccountKey=a2V5;BlobEndpoint=http://127.0.0.1:10000/devstoreaccount1/",
+        )
+        .unwrap();
+        assert_eq!(cs.endpoint, "http://127.0.0.1:10000/devstoreaccount1");
+        assert!(ConnectionString::parse("AccountName=cores").is_err());
+        assert!(ConnectionString::parse("AccountName=cores;AccountKey=!!").is_err());
+    }
+
+    #[test]
+    fn from_vars_test() {
+        let container = Container::from_vars(|name| match name {
+            "BUCKET_NAME" => "cores".to_string(),
+            "ACCOUNT" => "mo".to_string(),
+            _ => String::new(),
+        })
+        .unwrap();
+        assert_eq!(
+            container.url(Some("a b.zip")),
+            "https://mo.blob.core.windows.net/cores/a%20b.zip"
+        );
+        assert!(Container::from_vars(|name| match name {
+            "BUCKET_NAME" => "cores".to_string(),
+            _ => String::new(),
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn shared_key_test() {
+        let mut headers = BTreeMap::new();
+        headers.insert(
+            "x-ms-date".to_string(),
+            "Fri, 26 Jun 2015 23:39:12 GMT".to_string(),
+        );
+        headers.insert("x-ms-version".to_string(), "2020-10-02".to_string());
+        let query = [
+            ("comp", "block".to_string()),
+            ("blockid", "MDAwMDAwMDA=".to_string()),
+        ];
+        let to_sign = string_to_sign("PUT", 3, &headers, "mo", "/cores/a.zip", &query);
+        assert_eq!(
+            to_sign,
+            "PUT\n\n\n3\n\n\n\n\n\n\n\n\nx-ms-date:Fri, 26 Jun 2015 23:39:12 GMT\nx-ms-version:2020-10-02\n/mo/cores/a.zip\nblockid:MDAwMDAwMDA=\ncomp:block"
+        );
+        assert_eq!(
+            sign(b"key", &to_sign),
+            "cp4Bv+KB4bTNfhaWpZNDzQvyd4JjdJYJYp0Gd8wWpLw="
+        );
+        assert_eq!(
+            tags_header(&[
+                ("namespace".to_string(), "mo".to_string()),
+                ("pod".to_string(), "a b".to_string())
+            ]),
+            "namespace=mo&pod=a%20b"
+        );
+    }
+}
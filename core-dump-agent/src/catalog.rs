@@ -0,0 +1,237 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const CATALOG_FILE: &str = "catalog.ndjson";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Placement {
+    /// Uploaded to the backend the policy asked for.
+    Stored,
+    /// Uploaded to a secondary because the backend was unhealthy.
+    Failover,
+    /// Waiting on the node for an unhealthy backend or a mirror.
+    Queued,
+    /// A queued copy that reached its backend later.
+    Reconciled,
+    /// Removed from the backend, see retention.rs.
+    Deleted,
+}
+
+/// One line of the catalog. An archive has a line for every backend it was
+/// placed in, the last line per archive and backend is current.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CatalogEntry {
+    /// The stable id of the capture, missing in lines written before
+    /// archives carried one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dump_id: Option<String>,
+    pub archive: String,
+    /// The namespace of the crashed pod and the key the archive is stored
+    /// as, both missing in older lines.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    pub backend: String,
+    pub placement: Placement,
+    pub time: u64,
+}
+
+pub struct Catalog {
+    path: PathBuf,
+}
+
+impl Catalog {
+    pub fn new(host_dir: &Path) -> Catalog {
+        Catalog {
+            path: host_dir.join(CATALOG_FILE),
+        }
+    }
+
+    /// Records `archive` as placed in `backend` under `key`, with the dump
+    /// id and namespace of its `dump_info`.
+    pub fn record(
+        &self,
+        archive: &Path,
+        dump_info: Option<&Value>,
+        backend: &str,
+        key: &str,
+        placement: Placement,
+    ) -> Result<(), anyhow::Error> {
+        let entry = CatalogEntry {
+            dump_id: dump_info.and_then(crate::archive::dump_id),
+            archive: archive
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            namespace: dump_info
+                .and_then(|d| d["namespace"].as_str())
+                .filter(|n| !n.is_empty() && *n != "unknown")
+                .map(str::to_string),
+            key: Some(key.to_string()),
+            backend: backend.to_string(),
+            placement,
+            time: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+        };
+        self.append(&entry)
+    }
+
+    fn append(&self, entry: &CatalogEntry) -> Result<(), anyhow::Error> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", serde_json::to_string(entry)?)?;
+        Ok(())
+    }
+
+    /// Records the copy of `entry` as deleted from its backend.
+    pub fn record_deleted(&self, entry: &CatalogEntry) -> Result<(), anyhow::Error> {
+        self.append(&CatalogEntry {
+            placement: Placement::Deleted,
+            time: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            ..entry.clone()
+        })
+    }
+
+    /// The current line of every archive and backend, by dump id when the
+    /// archive has one.
+    fn current(&self) -> Vec<CatalogEntry> {
+        let mut current = BTreeMap::new();
+        for entry in fs::read_to_string(&self.path)
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|l| serde_json::from_str::<CatalogEntry>(l).ok())
+        {
+            let archive = entry.dump_id.clone().unwrap_or(entry.archive.clone());
+            current.insert((archive, entry.backend.clone()), entry);
+        }
+        current.into_values().collect()
+    }
+
+    /// The copies in backends of the archives of `namespace`. Queued ones
+    /// aren't in their backend yet.
+    pub fn stored_in(&self, namespace: &str) -> Vec<CatalogEntry> {
+        self.current()
+            .into_iter()
+            .filter(|e| e.namespace.as_deref() == Some(namespace))
+            .filter(|e| !matches!(e.placement, Placement::Queued | Placement::Deleted))
+            .collect()
+    }
+
+    /// The namespaces with copies in a backend.
+    pub fn namespaces(&self) -> BTreeSet<String> {
+        self.current()
+            .into_iter()
+            .filter(|e| !matches!(e.placement, Placement::Queued | Placement::Deleted))
+            .filter_map(|e| e.namespace)
+            .collect()
+    }
+
+    /// The lines of an archive, found by its dump id when it has one so a
+    /// renamed archive keeps its history.
+    pub fn entries(&self, archive: &str, dump_id: Option<&str>) -> Vec<CatalogEntry> {
+        fs::read_to_string(&self.path)
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|l| serde_json::from_str::<CatalogEntry>(l).ok())
+            .filter(|e| match (dump_id, e.dump_id.as_deref()) {
+                (Some(id), Some(entry_id)) => id == entry_id,
+                _ => e.archive == archive,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::catalog::{Catalog, Placement, CATALOG_FILE};
+    use std::fs;
+    use std::path::Path;
+    use uuid::Uuid;
+
+    #[test]
+    fn catalog_test() {
+        let dir = std::env::temp_dir().join(format!("catalog-test-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let catalog = Catalog::new(&dir);
+        let archive = Path::new("/cores/abc-dump.zip");
+        catalog
+            .record(archive, None, "ONPREM", "abc-dump.zip", Placement::Failover)
+            .unwrap();
+        catalog
+            .record(archive, None, "S3", "abc-dump.zip", Placement::Queued)
+            .unwrap();
+        catalog
+            .record(
+                Path::new("other.zip"),
+                None,
+                "S3",
+                "other.zip",
+                Placement::Stored,
+            )
+            .unwrap();
+        catalog
+            .record(archive, None, "S3", "abc-dump.zip", Placement::Reconciled)
+            .unwrap();
+
+        let entries = catalog.entries("abc-dump.zip", None);
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].backend, "ONPREM");
+        assert_eq!(entries[0].placement, Placement::Failover);
+        assert_eq!(entries[2].placement, Placement::Reconciled);
+        let line = fs::read_to_string(dir.join("catalog.ndjson")).unwrap();
+        assert!(line.contains(r#""placement":"failover""#));
+        assert!(!line.contains("dump_id"));
+
+        fs::write(
+            dir.join(CATALOG_FILE),
+            concat!(
+                r#"{"dump_id":"5ad2ea44","archive":"old-name.tar","backend":"S3","placement":"stored","time":1}"#,
+                "\n",
+                r#"{"dump_id":"0f3e9b2c","archive":"new-name.tar","backend":"S3","placement":"stored","time":2}"#,
+                "\n"
+            ),
+        )
+        .unwrap();
+        let entries = catalog.entries("new-name.tar", Some("5ad2ea44"));
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].archive, "old-name.tar");
+
+        fs::write(
+            dir.join(CATALOG_FILE),
+            concat!(
+                r#"{"dump_id":"a","archive":"a.tar","namespace":"mo","key":"a.tar","backend":"S3","placement":"stored","time":1}"#,
+                "\n",
+                r#"{"dump_id":"a","archive":"a.tar","namespace":"mo","key":"a.tar","backend":"GCS","placement":"queued","time":1}"#,
+                "\n",
+                r#"{"dump_id":"b","archive":"b.tar","namespace":"mo","key":"b.tar","backend":"S3","placement":"failover","time":2}"#,
+                "\n",
+                r#"{"dump_id":"c","archive":"c.tar","namespace":"tenant","key":"c.tar","backend":"S3","placement":"stored","time":3}"#,
+                "\n",
+                r#"{"archive":"old.tar","backend":"S3","placement":"stored","time":0}"#,
+                "\n"
+            ),
+        )
+        .unwrap();
+        let stored = catalog.stored_in("mo");
+        assert_eq!(stored.len(), 2);
+        assert_eq!(catalog.namespaces().len(), 2);
+        for entry in &stored {
+            catalog.record_deleted(entry).unwrap();
+        }
+        assert!(catalog.stored_in("mo").is_empty());
+        assert_eq!(
+            catalog.namespaces().into_iter().collect::<Vec<_>>(),
+            vec!["tenant"]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,231 @@
+use anyhow::anyhow;
+use inotify::{EventMask, Inotify, WatchMask};
+use log::{error, info, warn};
+use regex::Regex;
+use std::fs;
+use std::fs::File;
+use std::path::Path;
+use std::process::Command;
+use std::time::UNIX_EPOCH;
+
+pub const DEFAULT_CORE_FILE_PATTERN: &str = "core.%e.%p.%t";
+
+/// The core_pattern specifiers we can recover from a file name. They map one
+/// to one onto the composer flags of the same letter.
+const SUPPORTED_SPECIFIERS: [char; 7] = ['c', 'e', 'E', 'p', 's', 't', 'h'];
+
+/// Parameters recovered from the file name of a core written by a plain
+/// file based `kernel.core_pattern`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct CoreFileParams {
+    pub values: Vec<(char, String)>,
+}
+
+impl CoreFileParams {
+    pub fn parse(pattern: &str, file_name: &str) -> Result<CoreFileParams, anyhow::Error> {
+        let mut expr = String::from("^");
+        let mut order = vec![];
+        let mut chars = pattern.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                expr.push_str(&regex::escape(&c.to_string()));
+                continue;
+            }
+            match chars.next() {
+                Some('%') => expr.push('%'),
+                Some(spec) if SUPPORTED_SPECIFIERS.contains(&spec) => {
+                    if order.contains(&spec) {
+                        return Err(anyhow!("Specifier %{spec} used twice in {pattern}"));
+                    }
+                    match spec {
+                        'c' | 'p' | 's' | 't' => expr.push_str(r"(\d+)"),
+                        _ => expr.push_str("(.+?)"),
+                    }
+                    order.push(spec);
+                }
+                Some(spec) => {
+                    return Err(anyhow!("Unsupported specifier %{spec} in {pattern}"));
+                }
+                None => return Err(anyhow!("Trailing % in {pattern}")),
+            }
+        }
+        expr.push('$');
+        let re = Regex::new(&expr)?;
+        let caps = re
+            .captures(file_name)
+            .ok_or_else(|| anyhow!("{file_name} does not match pattern {pattern}"))?;
+        let values = order
+            .iter()
+            .enumerate()
+            .map(|(i, spec)| (*spec, caps[i + 1].to_string()))
+            .collect();
+        Ok(CoreFileParams { values })
+    }
+
+    pub fn get(&self, spec: char) -> Option<&str> {
+        self.values
+            .iter()
+            .find(|(s, _)| *s == spec)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Builds the argument list the kernel would have passed to the composer
+    /// through a pipe core_pattern.
+    pub fn composer_args(&self, core_dir: &str) -> Vec<String> {
+        let mut args: Vec<String> = self
+            .values
+            .iter()
+            .map(|(spec, value)| format!("-{spec}={value}"))
+            .collect();
+        args.push(format!("-d={core_dir}"));
+        args
+    }
+}
+
+/// Runs a core file written by the kernel through the composer as if it had
+/// been piped to it and removes the raw core once the archive is written.
+pub fn compose_core_file(
+    path: &Path,
+    pattern: &str,
+    composer: &str,
+    core_dir: &str,
+) -> Result<(), anyhow::Error> {
+    let file_name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| anyhow!("Failed to get file name for {}", path.display()))?;
+    let mut params = CoreFileParams::parse(pattern, file_name)?;
+    if params.get('t').is_none() {
+        // Fall back to the time the kernel finished writing the core.
+        let modified = fs::metadata(path)?
+            .modified()?
+            .duration_since(UNIX_EPOCH)?
+            .as_secs();
+        params.values.push(('t', modified.to_string()));
+    }
+    info!("Composing {} with {:?}", path.display(), params.values);
+    let core = File::open(path)?;
+    let status = Command::new(composer)
+        .args(params.composer_args(core_dir))
+        .stdin(core)
+        .status()?;
+    if !status.success() {
+        return Err(anyhow!(
+            "Composer failed for {} with {}",
+            path.display(),
+            status
+        ));
+    }
+    fs::remove_file(path)?;
+    Ok(())
+}
+
+/// Processes any cores left in the directory, e.g. written while the agent
+/// was restarting.
+pub fn compose_existing(dir: &str, pattern: &str, composer: &str, core_dir: &str) {
+    let entries = match fs::read_dir(dir) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Failed to read core file directory {}: {}", dir, e);
+            return;
+        }
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            continue;
+        }
+        if let Err(e) = compose_core_file(&path, pattern, composer, core_dir) {
+            warn!("Skipping {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Blocks watching the directory for cores the kernel has finished writing.
+pub fn watch(dir: &str, pattern: &str, composer: &str, core_dir: &str) {
+    let mut inotify = match Inotify::init() {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Inotify init failed for core files: {e}");
+            return;
+        }
+    };
+    if let Err(e) = inotify.add_watch(dir, WatchMask::CLOSE_WRITE) {
+        error!("Add watch failed for {dir}: {e}");
+        return;
+    }
+    let mut buffer = [0; 4096];
+    loop {
+        let events = match inotify.read_events_blocking(&mut buffer) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("read events failed: {}", e);
+                continue;
+            }
+        };
+        for event in events {
+            if event.mask.contains(EventMask::ISDIR) {
+                continue;
+            }
+            if let Some(name) = event.name {
+                let path = Path::new(dir).join(name);
+                if let Err(e) = compose_core_file(&path, pattern, composer, core_dir) {
+                    error!("Failed to compose {}: {}", path.display(), e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::corefile::{CoreFileParams, DEFAULT_CORE_FILE_PATTERN};
+
+    #[test]
+    fn default_pattern_test() {
+        let params =
+            CoreFileParams::parse(DEFAULT_CORE_FILE_PATTERN, "core.mo-service.42.1588462466")
+                .unwrap();
+        assert_eq!(params.get('e'), Some("mo-service"));
+        assert_eq!(params.get('p'), Some("42"));
+        assert_eq!(params.get('t'), Some("1588462466"));
+        assert_eq!(params.get('h'), None);
+    }
+
+    #[test]
+    fn exe_with_dots_test() {
+        let params =
+            CoreFileParams::parse(DEFAULT_CORE_FILE_PATTERN, "core.node.js.7.1588462466").unwrap();
+        assert_eq!(params.get('e'), Some("node.js"));
+        assert_eq!(params.get('p'), Some("7"));
+    }
+
+    #[test]
+    fn hostname_and_signal_test() {
+        let params = CoreFileParams::parse(
+            "core.%h.%e.%p.%s.%t",
+            "core.crashing-app-699c49b4ff-86wrh.node.4.11.1588462466",
+        )
+        .unwrap();
+        assert_eq!(params.get('h'), Some("crashing-app-699c49b4ff-86wrh"));
+        assert_eq!(params.get('s'), Some("11"));
+        assert_eq!(
+            params.composer_args("/cores"),
+            vec![
+                "-h=crashing-app-699c49b4ff-86wrh",
+                "-e=node",
+                "-p=4",
+                "-s=11",
+                "-t=1588462466",
+                "-d=/cores"
+            ]
+        );
+    }
+
+    #[test]
+    fn mismatch_test() {
+        assert!(CoreFileParams::parse(DEFAULT_CORE_FILE_PATTERN, "vmcore").is_err());
+        assert!(CoreFileParams::parse(DEFAULT_CORE_FILE_PATTERN, "core.node.x.1").is_err());
+        assert!(CoreFileParams::parse("core.%u", "core.0").is_err());
+    }
+}
@@ -0,0 +1,110 @@
+use anyhow::anyhow;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// See core-dump-composer/src/delta.rs for the stream format.
+const MAGIC: &[u8; 8] = b"CDHDLT01";
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut b = [0u8; 8];
+    reader.read_exact(&mut b)?;
+    Ok(u64::from_le_bytes(b))
+}
+
+/// Rebuilds a core stored as `.core.delta` from the raw core of the capture
+/// it was encoded against. Both inputs must already be decompressed.
+pub fn apply<B: Read + Seek, D: Read, W: Write>(
+    base: &mut B,
+    delta: &mut D,
+    out: &mut W,
+) -> Result<u64, anyhow::Error> {
+    let mut magic = [0u8; 8];
+    delta.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(anyhow!("Not a core delta"));
+    }
+    let mut written = 0u64;
+    loop {
+        let mut tag = [0u8; 1];
+        delta.read_exact(&mut tag)?;
+        match tag[0] {
+            b'C' => {
+                let offset = read_u64(delta)?;
+                let len = read_u64(delta)?;
+                base.seek(SeekFrom::Start(offset))?;
+                let copied = io::copy(&mut base.take(len), out)?;
+                if copied != len {
+                    return Err(anyhow!("Base core is shorter than the delta expects"));
+                }
+                written += len;
+            }
+            b'L' => {
+                let len = read_u64(delta)?;
+                let copied = io::copy(&mut delta.take(len), out)?;
+                if copied != len {
+                    return Err(anyhow!("Truncated literal in core delta"));
+                }
+                written += len;
+            }
+            b'E' => {
+                let total = read_u64(delta)?;
+                if total != written {
+                    return Err(anyhow!("Reconstructed {written} bytes, expected {total}"));
+                }
+                out.flush()?;
+                return Ok(written);
+            }
+            t => return Err(anyhow!("Unknown record {t} in core delta")),
+        }
+    }
+}
+
+pub fn reconstruct(base: &str, delta: &str, output: &str) -> Result<u64, anyhow::Error> {
+    if base.is_empty() || delta.is_empty() || output.is_empty() {
+        return Err(anyhow!("reconstruct requires <base core> <delta> <output>"));
+    }
+    let mut base = BufReader::new(File::open(Path::new(base))?);
+    let mut delta = BufReader::new(File::open(Path::new(delta))?);
+    let mut out = BufWriter::new(File::create(Path::new(output))?);
+    apply(&mut base, &mut delta, &mut out)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::delta::apply;
+    use std::io::Cursor;
+
+    #[test]
+    fn apply_test() {
+        let base = b"0123456789".to_vec();
+        let mut delta = b"CDHDLT01".to_vec();
+        delta.push(b'C');
+        delta.extend(5u64.to_le_bytes());
+        delta.extend(5u64.to_le_bytes());
+        delta.push(b'L');
+        delta.extend(3u64.to_le_bytes());
+        delta.extend(b"abc");
+        delta.push(b'C');
+        delta.extend(0u64.to_le_bytes());
+        delta.extend(2u64.to_le_bytes());
+        delta.push(b'E');
+        delta.extend(10u64.to_le_bytes());
+
+        let mut out = vec![];
+        let size = apply(&mut Cursor::new(&base), &mut delta.as_slice(), &mut out).unwrap();
+        assert_eq!(size, 10);
+        assert_eq!(out, b"56789abc01");
+
+        let mut truncated = delta.clone();
+        truncated.truncate(delta.len() - 9);
+        assert!(apply(
+            &mut Cursor::new(&base),
+            &mut truncated.as_slice(),
+            &mut vec![]
+        )
+        .is_err());
+        assert!(apply(&mut Cursor::new(&base), &mut &b"nope"[..], &mut vec![]).is_err());
+    }
+}
@@ -0,0 +1,83 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const HEALTH_FILE: &str = "backend-health.json";
+/// Object written by each node to check a backend accepts uploads.
+pub const PROBE_PREFIX: &str = ".core-dump-handler/health";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BackendHealth {
+    pub healthy: bool,
+    pub checked: u64,
+    pub error: Option<String>,
+}
+
+/// The last probe result per backend, kept on the node so the sweep command
+/// and the agent agree on where to upload.
+pub struct HealthFile {
+    path: PathBuf,
+}
+
+impl HealthFile {
+    pub fn new(host_dir: &Path) -> HealthFile {
+        HealthFile {
+            path: host_dir.join(HEALTH_FILE),
+        }
+    }
+
+    pub fn load(&self) -> BTreeMap<String, BackendHealth> {
+        fs::read_to_string(&self.path)
+            .ok()
+            .and_then(|c| serde_json::from_str(&c).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, health: &BTreeMap<String, BackendHealth>) -> Result<(), anyhow::Error> {
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(health)?)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    /// Backends that were never probed count as healthy.
+    pub fn is_healthy(&self, backend: &str) -> bool {
+        self.load().get(backend).map(|h| h.healthy).unwrap_or(true)
+    }
+}
+
+pub fn probe_key(node_name: &str) -> String {
+    format!("{PROBE_PREFIX}/{node_name}")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::health::{BackendHealth, HealthFile};
+    use std::collections::BTreeMap;
+    use std::fs;
+    use uuid::Uuid;
+
+    #[test]
+    fn health_file_test() {
+        let dir = std::env::temp_dir().join(format!("health-test-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let health = HealthFile::new(&dir);
+        assert!(health.is_healthy("S3"));
+
+        let mut state = BTreeMap::new();
+        state.insert(
+            "S3".to_string(),
+            BackendHealth {
+                healthy: false,
+                checked: 1706263200,
+                error: Some("503".to_string()),
+            },
+        );
+        health.save(&state).unwrap();
+        assert!(!health.is_healthy("S3"));
+        assert!(health.is_healthy("ONPREM"));
+        assert_eq!(health.load(), state);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,229 @@
+//! Generic HTTP upload backend, selected with `STORAGE_BACKEND=http`.
+//!
+//! Archives are sent as the request body to a crash intake service with a
+//! bearer token or basic auth. The tags go along as `X-Core-Dump-*`
+//! headers, and `verify` fetches the archive back with a GET on the same
+//! URL.
+
+use anyhow::anyhow;
+use log::info;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+const DEFAULT_CONTENT_TYPE: &str = "application/octet-stream";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    Post,
+    Put,
+}
+
+impl FromStr for Method {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "" | "post" => Ok(Method::Post),
+            "put" => Ok(Method::Put),
+            other => Err(anyhow!("Unknown METHOD {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Auth {
+    None,
+    Bearer(String),
+    Basic(String, String),
+}
+
+#[derive(Clone)]
+pub struct HttpTarget {
+    /// `{name}` is replaced with the archive name.
+    pub url: String,
+    pub method: Method,
+    pub auth: Auth,
+    pub content_type: String,
+    client: reqwest::Client,
+}
+
+impl HttpTarget {
+    /// Builds the target from the `{prefix}_*` env vars: `URL`, `METHOD`,
+    /// `TOKEN` (or `TOKEN_FILE`), `USERNAME` and `PASSWORD`, and
+    /// `CONTENT_TYPE`.
+    pub fn from_vars<F: Fn(&str) -> String>(var: F) -> Result<HttpTarget, anyhow::Error> {
+        let url = var("URL");
+        if url.is_empty() {
+            return Err(anyhow!("No upload url in URL"));
+        }
+        let token = match (var("TOKEN"), var("TOKEN_FILE")) {
+            (token, file) if token.is_empty() && !file.is_empty() => fs::read_to_string(&file)
+                .map_err(|e| anyhow!("Reading {} failed: {}", file, e))?
+                .trim()
+                .to_string(),
+            (token, _) => token,
+        };
+        let auth = if !token.is_empty() {
+            Auth::Bearer(token)
+        } else if !var("USERNAME").is_empty() {
+            Auth::Basic(var("USERNAME"), var("PASSWORD"))
+        } else {
+            Auth::None
+        };
+        let content_type = match var("CONTENT_TYPE") {
+            v if v.is_empty() => DEFAULT_CONTENT_TYPE.to_string(),
+            v => v,
+        };
+        Ok(HttpTarget {
+            url,
+            method: var("METHOD").parse()?,
+            auth,
+            content_type,
+            client: reqwest::Client::new(),
+        })
+    }
+
+    pub fn url(&self, name: &str) -> String {
+        self.url.replace("{name}", name)
+    }
+
+    fn request(&self, method: reqwest::Method, name: &str) -> reqwest::RequestBuilder {
+        let request = self.client.request(method, self.url(name));
+        match &self.auth {
+            Auth::None => request,
+            Auth::Bearer(token) => request.bearer_auth(token),
+            Auth::Basic(user, password) => request.basic_auth(user, Some(password)),
+        }
+    }
+
+    fn upload(&self, name: &str) -> reqwest::RequestBuilder {
+        let method = match self.method {
+            Method::Post => reqwest::Method::POST,
+            Method::Put => reqwest::Method::PUT,
+        };
+        self.request(method, name)
+            .header("Content-Type", &self.content_type)
+    }
+
+    async fn check(
+        response: reqwest::Response,
+        what: &str,
+    ) -> Result<reqwest::Response, anyhow::Error> {
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+        let body = response.text().await.unwrap_or_default();
+        Err(anyhow!(
+            "{} returned {}: {}",
+            what,
+            status.as_u16(),
+            body.trim()
+        ))
+    }
+
+    /// Streams the file at `path` as `name` with `tags` as headers.
+    pub async fn put_file(
+        &self,
+        path: &Path,
+        name: &str,
+        tags: &[(String, String)],
+    ) -> Result<u16, anyhow::Error> {
+        let file = tokio::fs::File::open(path).await?;
+        let len = file.metadata().await?.len();
+        let mut request = self
+            .upload(name)
+            .header("Content-Length", len)
+            .header("X-Core-Dump-Name", name);
+        for (key, value) in tags {
+            request = request.header(format!("X-Core-Dump-{key}"), value);
+        }
+        let response = request.body(file).send().await?;
+        let response = HttpTarget::check(response, &format!("Upload of {name}")).await?;
+        Ok(response.status().as_u16())
+    }
+
+    /// Sends a small body in one request, used by the health probe.
+    pub async fn put(&self, name: &str, data: &[u8]) -> Result<u16, anyhow::Error> {
+        let response = self
+            .upload(name)
+            .header("X-Core-Dump-Name", name)
+            .body(data.to_vec())
+            .send()
+            .await?;
+        let response = HttpTarget::check(response, &format!("Upload of {name}")).await?;
+        Ok(response.status().as_u16())
+    }
+
+    /// Sends a DELETE for `name`, for endpoints that take one.
+    pub async fn delete(&self, name: &str) -> Result<u16, anyhow::Error> {
+        let response = self.request(reqwest::Method::DELETE, name).send().await?;
+        if response.status().as_u16() == 404 {
+            return Ok(404);
+        }
+        let response = HttpTarget::check(response, &format!("Deleting {name}")).await?;
+        Ok(response.status().as_u16())
+    }
+
+    /// Streams `name` into `writer` and returns the status.
+    pub async fn get_stream<W: AsyncWrite + Unpin>(
+        &self,
+        name: &str,
+        writer: &mut W,
+    ) -> Result<u16, anyhow::Error> {
+        let response = self.request(reqwest::Method::GET, name).send().await?;
+        let mut response = HttpTarget::check(response, &format!("Fetching {name}")).await?;
+        while let Some(chunk) = response.chunk().await? {
+            writer.write_all(&chunk).await?;
+        }
+        writer.flush().await?;
+        info!("Fetched {} from {}", name, self.url(name));
+        Ok(response.status().as_u16())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http::{Auth, HttpTarget, Method};
+    use std::fs;
+    use uuid::Uuid;
+
+    #[test]
+    fn from_vars_test() {
+        assert!(HttpTarget::from_vars(|_| String::new()).is_err());
+        let target = HttpTarget::from_vars(|name| match name {
+            "URL" => "https://intake.mo.local/cores/{name}".to_string(),
+            "USERNAME" => "agent".to_string(),
+            "PASSWORD" => "s3cr3t".to_string(),
+            "METHOD" => "PUT".to_string(),
+            _ => String::new(),
+        })
+        .unwrap();
+        assert_eq!(target.method, Method::Put);
+        assert_eq!(
+            target.auth,
+            Auth::Basic("agent".to_string(), "s3cr3t".to_string())
+        );
+        assert_eq!(target.content_type, "application/octet-stream");
+        assert_eq!(target.url("a.tar"), "https://intake.mo.local/cores/a.tar");
+
+        let token_file = std::env::temp_dir().join(format!("http-test-{}", Uuid::new_v4()));
+        fs::write(&token_file, "abc\n").unwrap();
+        let target = HttpTarget::from_vars(|name| match name {
+            "URL" => "https://intake.mo.local/upload".to_string(),
+            "TOKEN_FILE" => token_file.to_string_lossy().to_string(),
+            "USERNAME" => "agent".to_string(),
+            "CONTENT_TYPE" => "application/x-tar".to_string(),
+            _ => String::new(),
+        })
+        .unwrap();
+        fs::remove_file(&token_file).unwrap();
+        assert_eq!(target.method, Method::Post);
+        assert_eq!(target.auth, Auth::Bearer("abc".to_string()));
+        assert_eq!(target.content_type, "application/x-tar");
+        assert_eq!(target.url("a.tar"), "https://intake.mo.local/upload");
+        assert!("patch".parse::<Method>().is_err());
+    }
+}
@@ -0,0 +1,325 @@
+//! `inspect <uuid>` lists what an archive holds. `inspect --gdb <uuid>`
+//! unpacks the core together with the executable and libraries the
+//! composer copied with `CAPTURE_BINARIES` and writes a `gdbinit` that opens
+//! them, `--launch` then starts gdb on it. A delta core is rebuilt against
+//! its base, see [`find_base`].
+//!
+//! gdbserver can't serve a core, so a remote debugger has to be pointed at
+//! the unpacked directory instead.
+
+use crate::{archive, delta};
+use anyhow::anyhow;
+use core_dump_archive::{Archive, DeltaBase};
+use log::{info, warn};
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+
+/// One line per file in the archive.
+pub fn summary(archive: &Archive) -> Vec<String> {
+    let info = archive.dump_info();
+    let field = |v: &Option<String>| v.clone().unwrap_or_else(|| "unknown".to_string());
+    let mut lines = vec![format!(
+        "{} {} (pid {}) signal {} in {}/{}",
+        archive.dump_id().unwrap_or("unknown"),
+        field(&info.exe),
+        field(&info.real_pid),
+        field(&info.signal),
+        field(&info.namespace),
+        field(&info.podname)
+    )];
+    if let Some(result) = archive.capture_result() {
+        lines.push(format!("Capture {:?}", result.status));
+        for error in &result.errors {
+            lines.push(format!("  {}: {}", error.stage, error.error));
+        }
+    }
+    for entry in archive.entries() {
+        lines.push(format!("{:>12} {}", entry.size, entry.name));
+    }
+    lines
+}
+
+pub fn gdbinit(sysroot: &Path, solib_dirs: &[PathBuf], exe: Option<&Path>, core: &Path) -> String {
+    let mut init = format!("set sysroot {}\n", sysroot.display());
+    if !solib_dirs.is_empty() {
+        let dirs: Vec<String> = solib_dirs.iter().map(|d| d.display().to_string()).collect();
+        init.push_str(&format!("set solib-search-path {}\n", dirs.join(":")));
+    }
+    if let Some(exe) = exe {
+        init.push_str(&format!("file {}\n", exe.display()));
+    }
+    init.push_str(&format!("core-file {}\n", core.display()));
+    init
+}
+
+/// Where the raw core a delta was encoded against is read from.
+pub enum Base {
+    /// The base the composer still keeps on the node.
+    Stored(PathBuf),
+    /// The archive of the base's capture.
+    Archive(Box<Archive>),
+}
+
+/// Finds the base of a delta core: `target` when it's given, else the
+/// node's `delta-bases` in `base_dir` while it still holds that capture's
+/// core, else the capture's archive in the core directory.
+pub fn find_base(
+    delta_base: &DeltaBase,
+    target: Option<&str>,
+    base_dir: &Path,
+    core_dir: &str,
+) -> Result<Base, anyhow::Error> {
+    if let Some(target) = target {
+        let path = archive::resolve(core_dir, target)?;
+        return Ok(Base::Archive(Box::new(Archive::open(path)?)));
+    }
+    let stored = fs::read(base_dir.join(format!("{}.json", delta_base.build_id)))
+        .ok()
+        .and_then(|content| serde_json::from_slice::<DeltaBase>(&content).ok());
+    if stored.is_some_and(|stored| stored.uuid == delta_base.uuid) {
+        return Ok(Base::Stored(
+            base_dir.join(format!("{}.core", delta_base.build_id)),
+        ));
+    }
+    let path = archive::resolve(core_dir, &delta_base.uuid).map_err(|e| {
+        anyhow!(
+            "{}, pass the archive holding {} with --base",
+            e,
+            delta_base.dump_file
+        )
+    })?;
+    Ok(Base::Archive(Box::new(Archive::open(path)?)))
+}
+
+/// Rebuilds the delta core of `archive` against `base` into `core`. The
+/// base is read out of order, so one from an archive is unpacked first.
+fn rebuild(archive: &Archive, base: &Base, dir: &Path, core: &Path) -> Result<u64, anyhow::Error> {
+    let delta_path = dir.join("core.delta");
+    archive.extract_delta(&mut File::create(&delta_path)?)?;
+    let base_path = match base {
+        Base::Stored(path) => path.clone(),
+        Base::Archive(base) => {
+            let path = dir.join("base.core");
+            base.extract_core(&mut File::create(&path)?)?;
+            path
+        }
+    };
+    let size = delta::apply(
+        &mut BufReader::new(File::open(&base_path)?),
+        &mut BufReader::new(File::open(&delta_path)?),
+        &mut BufWriter::new(File::create(core)?),
+    );
+    let _ = fs::remove_file(&delta_path);
+    if let Base::Archive(_) = base {
+        let _ = fs::remove_file(&base_path);
+    }
+    size
+}
+
+pub struct GdbSession {
+    pub dir: PathBuf,
+    pub gdbinit: PathBuf,
+}
+
+/// Unpacks the core and the binaries of `archive` into `dir` and writes the
+/// `gdbinit` next to them. A delta core is rebuilt against `base`.
+pub fn prepare(
+    archive: &Archive,
+    base: Option<&Base>,
+    dir: &Path,
+) -> Result<GdbSession, anyhow::Error> {
+    let sysroot = dir.join("sysroot");
+    fs::create_dir_all(&sysroot)?;
+    let core = dir.join(format!("{}.core", archive.dump_id().unwrap_or("dump")));
+    match base {
+        Some(base) if archive.is_delta() => rebuild(archive, base, dir, &core)?,
+        _ => archive.extract_core(&mut File::create(&core)?)?,
+    };
+
+    let mut solib_dirs: Vec<PathBuf> = vec![];
+    let mut unpacked = vec![];
+    for (path, entry) in archive.binaries() {
+        let target = sysroot.join(path.trim_start_matches('/'));
+        let parent = target
+            .parent()
+            .ok_or_else(|| anyhow!("{} has no parent", target.display()))?;
+        fs::create_dir_all(parent)?;
+        io::copy(&mut archive.reader(entry)?, &mut File::create(&target)?)?;
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o755))?;
+        if !solib_dirs.iter().any(|d| d == parent) {
+            solib_dirs.push(parent.to_path_buf());
+        }
+        unpacked.push((path.to_string(), target));
+    }
+    if unpacked.is_empty() {
+        warn!("No binaries in the archive, enable composer.captureBinaries to capture them");
+    }
+    // The executable is the first file the process mapped when the
+    // archive predates exe_path.
+    let exe = match &archive.dump_info().exe_path {
+        Some(exe_path) => unpacked.iter().find(|(p, _)| p == exe_path),
+        None => unpacked.first(),
+    };
+    let gdbinit_path = dir.join("gdbinit");
+    fs::write(
+        &gdbinit_path,
+        gdbinit(&sysroot, &solib_dirs, exe.map(|(_, t)| t.as_path()), &core),
+    )?;
+    info!(
+        "Unpacked {} and {} binaries into {}",
+        core.display(),
+        unpacked.len(),
+        dir.display()
+    );
+    Ok(GdbSession {
+        dir: dir.to_path_buf(),
+        gdbinit: gdbinit_path,
+    })
+}
+
+pub fn launch(session: &GdbSession) -> Result<ExitStatus, anyhow::Error> {
+    Command::new("gdb")
+        .arg("-x")
+        .arg(&session.gdbinit)
+        .current_dir(&session.dir)
+        .status()
+        .map_err(|e| anyhow!("Starting gdb failed: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::inspect::{find_base, gdbinit, prepare, Base};
+    use core_dump_archive::Archive;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use uuid::Uuid;
+
+    fn write_archive(path: &Path, files: &[(&str, &[u8])]) {
+        let mut builder = tar::Builder::new(fs::File::create(path).unwrap());
+        for (name, content) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, format!("core/{name}"), *content)
+                .unwrap();
+        }
+        builder.finish().unwrap();
+    }
+
+    #[test]
+    fn delta_test() {
+        let dir = std::env::temp_dir().join(format!("inspect-test-{}", Uuid::new_v4()));
+        let core_dir = dir.join("cores");
+        let base_dir = dir.join("delta-bases");
+        fs::create_dir_all(&core_dir).unwrap();
+        fs::create_dir_all(&base_dir).unwrap();
+        write_archive(
+            &core_dir.join("0f3e9b2c-dump.tar"),
+            &[
+                ("b.core", b"0123456789"),
+                (
+                    "b-dump-info.json",
+                    br#"{"uuid":"0f3e9b2c","dump_file":"b.core","compression":"none"}"#,
+                ),
+            ],
+        );
+        let mut delta = b"CDHDLT01".to_vec();
+        delta.push(b'C');
+        delta.extend(5u64.to_le_bytes());
+        delta.extend(5u64.to_le_bytes());
+        delta.push(b'L');
+        delta.extend(3u64.to_le_bytes());
+        delta.extend(b"abc");
+        delta.push(b'C');
+        delta.extend(0u64.to_le_bytes());
+        delta.extend(2u64.to_le_bytes());
+        delta.push(b'E');
+        delta.extend(10u64.to_le_bytes());
+        let delta_path = core_dir.join("5ad2ea44-dump.tar");
+        write_archive(
+            &delta_path,
+            &[
+                ("a.core.delta", &delta),
+                (
+                    "a-dump-info.json",
+                    br#"{"uuid":"5ad2ea44","dump_file":"a.core.delta","compression":"none","delta_base":{"build_id":"4f1e","uuid":"0f3e9b2c","dump_file":"b.core"}}"#,
+                ),
+            ],
+        );
+        let archive = Archive::open(&delta_path).unwrap();
+        let delta_base = archive.dump_info().delta_base.clone().unwrap();
+        let core_dir = core_dir.to_str().unwrap();
+        let session_dir = dir.join("session");
+
+        let base = find_base(&delta_base, None, &base_dir, core_dir).unwrap();
+        assert!(matches!(base, Base::Archive(_)));
+        prepare(&archive, Some(&base), &session_dir).unwrap();
+        let core = session_dir.join("5ad2ea44.core");
+        assert_eq!(fs::read(&core).unwrap(), b"56789abc01");
+        assert!(!session_dir.join("base.core").exists());
+        assert!(!session_dir.join("core.delta").exists());
+        assert!(prepare(&archive, None, &session_dir).is_err());
+
+        // The node's copy is used while it's the same capture's.
+        fs::write(base_dir.join("4f1e.core"), b"0123456789").unwrap();
+        fs::write(
+            base_dir.join("4f1e.json"),
+            br#"{"build_id":"4f1e","uuid":"0f3e9b2c","dump_file":"b.core"}"#,
+        )
+        .unwrap();
+        let base = find_base(&delta_base, None, &base_dir, core_dir).unwrap();
+        assert!(matches!(base, Base::Stored(_)));
+        prepare(&archive, Some(&base), &session_dir).unwrap();
+        assert_eq!(fs::read(&core).unwrap(), b"56789abc01");
+
+        fs::write(
+            base_dir.join("4f1e.json"),
+            br#"{"build_id":"4f1e","uuid":"7c1d","dump_file":"c.core"}"#,
+        )
+        .unwrap();
+        fs::remove_file(dir.join("cores/0f3e9b2c-dump.tar")).unwrap();
+        let err = find_base(&delta_base, None, &base_dir, core_dir)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("--base"), "{err}");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn gdbinit_test() {
+        let init = gdbinit(
+            Path::new("/tmp/d/sysroot"),
+            &[
+                PathBuf::from("/tmp/d/sysroot/usr/bin"),
+                PathBuf::from("/tmp/d/sysroot/usr/lib"),
+            ],
+            Some(Path::new("/tmp/d/sysroot/usr/bin/node")),
+            Path::new("/tmp/d/5ad2ea44.core"),
+        );
+        assert_eq!(
+            init,
+            "set sysroot /tmp/d/sysroot
+set solib-search-path /tmp/d/sysroot/usr/bin:/tmp/d/sysroot/usr/lib
+file /tmp/d/sysroot/usr/bin/node
+core-file /tmp/d/5ad2ea44.core
+"
+        );
+        let init = gdbinit(
+            Path::new("/tmp/d/sysroot"),
+            &[],
+            None,
+            Path::new("/tmp/d/a.core"),
+        );
+        assert_eq!(
+            init,
+            "set sysroot /tmp/d/sysroot\ncore-file /tmp/d/a.core\n"
+        );
+    }
+}
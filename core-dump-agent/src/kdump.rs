@@ -0,0 +1,190 @@
+use log::{info, warn};
+use serde::Serialize;
+use std::fs;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+pub static DEFAULT_KDUMP_DIR: &str = "/var/crash";
+pub static DEFAULT_PSTORE_DIR: &str = "/sys/fs/pstore";
+static SEEN_FILE: &str = "node-crash.seen";
+
+#[derive(Serialize, Debug)]
+pub struct CrashReference {
+    pub kind: String,
+    pub path: String,
+    pub size: u64,
+    pub modified: u64,
+}
+
+/// Metadata only event for a kernel crash found on the node at start up.
+/// The dump itself stays on the node, the event just references it.
+#[derive(Serialize, Debug)]
+pub struct NodeCrashEvent {
+    pub event_type: String,
+    pub uuid: Uuid,
+    pub node_hostname: String,
+    pub detected_at: u64,
+    pub dumps: Vec<CrashReference>,
+}
+
+fn modified_secs(metadata: &fs::Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn is_kdump_file(name: &str) -> bool {
+    name.starts_with("vmcore") || name.starts_with("dmesg")
+}
+
+/// Finds kdump output (`<dir>/<crash>/vmcore*`, `dmesg*`) and pstore records.
+pub fn find_crashes(kdump_dir: &str, pstore_dir: &str) -> Vec<CrashReference> {
+    let mut found = vec![];
+    if let Ok(entries) = fs::read_dir(kdump_dir) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let files: Vec<_> = if path.is_dir() {
+                match fs::read_dir(&path) {
+                    Ok(v) => v.flatten().map(|e| e.path()).collect(),
+                    Err(_) => continue,
+                }
+            } else {
+                vec![path]
+            };
+            for file in files {
+                let name = file
+                    .file_name()
+                    .map(|n| n.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                if !is_kdump_file(&name) {
+                    continue;
+                }
+                if let Ok(metadata) = fs::metadata(&file) {
+                    found.push(CrashReference {
+                        kind: "kdump".to_string(),
+                        path: file.display().to_string(),
+                        size: metadata.len(),
+                        modified: modified_secs(&metadata),
+                    });
+                }
+            }
+        }
+    }
+    if let Ok(entries) = fs::read_dir(pstore_dir) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if let Ok(metadata) = fs::metadata(&path) {
+                if metadata.is_file() {
+                    found.push(CrashReference {
+                        kind: "pstore".to_string(),
+                        path: path.display().to_string(),
+                        size: metadata.len(),
+                        modified: modified_secs(&metadata),
+                    });
+                }
+            }
+        }
+    }
+    found
+}
+
+/// Writes a node crash event for any dumps that have not been reported by a
+/// previous start of the agent. Reported paths are kept in the host dir.
+pub fn report_node_crashes(
+    host_dir: &str,
+    event_dir: &str,
+    kdump_dir: &str,
+    pstore_dir: &str,
+    node_hostname: &str,
+) -> Result<Option<NodeCrashEvent>, anyhow::Error> {
+    let seen_path = format!("{host_dir}/{SEEN_FILE}");
+    let seen = fs::read_to_string(&seen_path).unwrap_or_default();
+    let seen: Vec<&str> = seen.lines().collect();
+
+    let dumps: Vec<CrashReference> = find_crashes(kdump_dir, pstore_dir)
+        .into_iter()
+        .filter(|d| !seen.contains(&d.path.as_str()))
+        .collect();
+    if dumps.is_empty() {
+        info!("No new kernel crash dumps found");
+        return Ok(None);
+    }
+
+    let event = NodeCrashEvent {
+        event_type: "node_crash".to_string(),
+        uuid: Uuid::new_v4(),
+        node_hostname: node_hostname.to_string(),
+        detected_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default(),
+        dumps,
+    };
+    fs::create_dir_all(event_dir)?;
+    let event_path = Path::new(event_dir).join(format!("{}-node-crash-event.json", event.uuid));
+    let file = File::create(&event_path)?;
+    serde_json::to_writer(&file, &event)?;
+    warn!(
+        "Kernel crash detected on node, {} dump(s) referenced in {}",
+        event.dumps.len(),
+        event_path.display()
+    );
+
+    let mut seen_file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&seen_path)?;
+    for dump in &event.dumps {
+        writeln!(seen_file, "{}", dump.path)?;
+    }
+    Ok(Some(event))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::kdump::report_node_crashes;
+    use std::fs;
+    use uuid::Uuid;
+
+    #[test]
+    fn reports_each_crash_once_test() {
+        let base = std::env::temp_dir().join(format!("kdump-test-{}", Uuid::new_v4()));
+        let host = base.join("host");
+        let events = base.join("events");
+        let kdump = base.join("crash");
+        let pstore = base.join("pstore");
+        fs::create_dir_all(kdump.join("127.0.0.1-2024-01-26-10:00:00")).unwrap();
+        fs::create_dir_all(&host).unwrap();
+        fs::create_dir_all(&pstore).unwrap();
+        fs::write(kdump.join("127.0.0.1-2024-01-26-10:00:00/vmcore"), "core").unwrap();
+        fs::write(kdump.join("127.0.0.1-2024-01-26-10:00:00/notes.txt"), "x").unwrap();
+        fs::write(pstore.join("dmesg-ramoops-0"), "panic").unwrap();
+
+        let args = (
+            host.to_str().unwrap(),
+            events.to_str().unwrap(),
+            kdump.to_str().unwrap(),
+            pstore.to_str().unwrap(),
+        );
+        let event = report_node_crashes(args.0, args.1, args.2, args.3, "node1")
+            .unwrap()
+            .expect("a node crash event");
+        assert_eq!(event.event_type, "node_crash");
+        assert_eq!(event.dumps.len(), 2);
+        assert!(event.dumps.iter().any(|d| d.kind == "kdump"));
+        assert!(event.dumps.iter().any(|d| d.kind == "pstore"));
+        assert_eq!(fs::read_dir(&events).unwrap().count(), 1);
+
+        // A restart of the agent must not report the same crash again.
+        let again = report_node_crashes(args.0, args.1, args.2, args.3, "node1").unwrap();
+        assert!(again.is_none());
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+}
@@ -0,0 +1,340 @@
+//! Posts a `CoreDumped` Event against the crashing pod, enabled with
+//! `POD_EVENTS=true`, so `kubectl describe pod` shows the crash and where
+//! its archive went.
+//!
+//! The composer runs outside the cluster without credentials, so the agent
+//! posts the Event with its service account once the archive is stored.
+//!
+//! With `WORKLOAD_ANNOTATIONS=true` it also annotates the workload that
+//! owns the pod, the Deployment, StatefulSet, DaemonSet or CronJob, with
+//! the last crash, so GitOps diffs and dashboards show it next to the
+//! workload's definition.
+
+use crate::sftp::civil_date;
+use anyhow::anyhow;
+use serde_json::{json, Value};
+use std::env;
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SERVICE_ACCOUNT_DIR: &str = "/var/run/secrets/kubernetes.io/serviceaccount";
+const COMPONENT: &str = "core-dump-handler";
+pub const LAST_CRASH_TIME: &str = "coredump.matrixorigin.io/last-crash-time";
+pub const LAST_CRASH_SIGNATURE: &str = "coredump.matrixorigin.io/last-crash-signature";
+pub const LAST_CRASH_DUMP_ID: &str = "coredump.matrixorigin.io/last-crash-dump-id";
+/// Pods, ReplicaSets and Jobs are owned by what is annotated, the chain is
+/// never deeper than this.
+const MAX_OWNERS: usize = 3;
+
+/// `YYYY-MM-DDTHH:MM:SSZ`, the format of Event timestamps.
+fn rfc3339(secs: u64) -> String {
+    format!(
+        "{}T{:02}:{:02}:{:02}Z",
+        civil_date(secs),
+        secs % 86400 / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+/// The Event for the capture described by `dump_info`, None for host
+/// processes that have no pod.
+pub fn core_event(dump_info: &Value, archive: &str, node: &str, now: u64) -> Option<Value> {
+    let field = |key: &str| {
+        dump_info[key]
+            .as_str()
+            .filter(|v| !v.is_empty() && *v != "unknown")
+    };
+    let namespace = field("namespace")?;
+    let pod = field("podname")?;
+    let message = format!(
+        "{} (pid {}) dumped core on signal {}, archive {}",
+        field("exe").unwrap_or("unknown"),
+        field("real_pid").unwrap_or("unknown"),
+        field("signal").unwrap_or("unknown"),
+        archive
+    );
+    let time = rfc3339(now);
+    let mut involved = json!({
+        "apiVersion": "v1",
+        "kind": "Pod",
+        "namespace": namespace,
+        "name": pod,
+    });
+    if let Some(uid) = field("pod_uid") {
+        involved["uid"] = json!(uid);
+    }
+    Some(json!({
+        "apiVersion": "v1",
+        "kind": "Event",
+        "metadata": {
+            "generateName": format!("{pod}."),
+            "namespace": namespace,
+        },
+        "involvedObject": involved,
+        "reason": "CoreDumped",
+        "message": message,
+        "type": "Warning",
+        "source": {"component": COMPONENT, "host": node},
+        "reportingComponent": COMPONENT,
+        "reportingInstance": node,
+        "firstTimestamp": time,
+        "lastTimestamp": time,
+        "count": 1,
+    }))
+}
+
+/// The merge patch annotating a workload with the crash in `dump_info`,
+/// None for host processes. A crash without a signature removes the one of
+/// an earlier crash.
+pub fn crash_annotations(dump_info: &Value, now: u64) -> Option<Value> {
+    let field = |key: &str| {
+        dump_info[key]
+            .as_str()
+            .filter(|v| !v.is_empty() && *v != "unknown")
+    };
+    field("namespace")?;
+    field("podname")?;
+    let time = field("timestamp")
+        .and_then(|t| t.parse::<u64>().ok())
+        .unwrap_or(now);
+    Some(json!({
+        "metadata": {
+            "annotations": {
+                LAST_CRASH_TIME: rfc3339(time),
+                LAST_CRASH_SIGNATURE: field("signature"),
+                LAST_CRASH_DUMP_ID: field("dump_id"),
+            }
+        }
+    }))
+}
+
+/// The API path of an object of a kind that can own pods.
+pub fn object_path(namespace: &str, kind: &str, name: &str) -> Option<String> {
+    let (group, resource) = match kind {
+        "Pod" => ("api/v1", "pods"),
+        "ReplicaSet" => ("apis/apps/v1", "replicasets"),
+        "Deployment" => ("apis/apps/v1", "deployments"),
+        "StatefulSet" => ("apis/apps/v1", "statefulsets"),
+        "DaemonSet" => ("apis/apps/v1", "daemonsets"),
+        "Job" => ("apis/batch/v1", "jobs"),
+        "CronJob" => ("apis/batch/v1", "cronjobs"),
+        _ => return None,
+    };
+    Some(format!("/{group}/namespaces/{namespace}/{resource}/{name}"))
+}
+
+/// The kind and name of the controller owning `object`.
+fn controller(object: &Value) -> Option<(String, String)> {
+    object["metadata"]["ownerReferences"]
+        .as_array()?
+        .iter()
+        .find(|o| o["controller"].as_bool().unwrap_or(false))
+        .and_then(|o| {
+            Some((
+                o["kind"].as_str()?.to_string(),
+                o["name"].as_str()?.to_string(),
+            ))
+        })
+}
+
+/// The API server as seen from inside the agent pod.
+pub struct InCluster {
+    server: String,
+    token: String,
+    client: reqwest::Client,
+}
+
+impl InCluster {
+    pub fn from_env() -> Result<InCluster, anyhow::Error> {
+        let host = env::var("KUBERNETES_SERVICE_HOST")
+            .map_err(|_| anyhow!("KUBERNETES_SERVICE_HOST is not set, not running in a pod"))?;
+        let port = env::var("KUBERNETES_SERVICE_PORT").unwrap_or_else(|_| "443".to_string());
+        let read = |name: &str| {
+            let path = format!("{SERVICE_ACCOUNT_DIR}/{name}");
+            fs::read(&path).map_err(|e| anyhow!("Reading {} failed: {}", path, e))
+        };
+        let token = String::from_utf8_lossy(&read("token")?).trim().to_string();
+        let ca = reqwest::Certificate::from_pem(&read("ca.crt")?)?;
+        let host = if host.contains(':') {
+            format!("[{host}]")
+        } else {
+            host
+        };
+        Ok(InCluster {
+            server: format!("https://{host}:{port}"),
+            token,
+            client: reqwest::Client::builder()
+                .add_root_certificate(ca)
+                .build()?,
+        })
+    }
+
+    pub async fn create_event(&self, event: &Value) -> Result<u16, anyhow::Error> {
+        let namespace = event["metadata"]["namespace"].as_str().unwrap_or_default();
+        let response = self
+            .client
+            .post(format!(
+                "{}/api/v1/namespaces/{}/events",
+                self.server, namespace
+            ))
+            .bearer_auth(&self.token)
+            .header("Content-Type", "application/json")
+            .body(event.to_string())
+            .send()
+            .await?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(anyhow!("Creating event returned {}: {}", status, body));
+        }
+        Ok(status.as_u16())
+    }
+
+    /// GETs `path` from the API server. The body of a watch streams.
+    pub async fn get(&self, path: &str) -> Result<reqwest::Response, anyhow::Error> {
+        let response = self
+            .client
+            .get(format!("{}{}", self.server, path))
+            .bearer_auth(&self.token)
+            .send()
+            .await?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(anyhow!("GET {} returned {}: {}", path, status, body));
+        }
+        Ok(response)
+    }
+}
+
+impl InCluster {
+    /// The kind, name and path of the workload running the pod, the pod
+    /// itself when nothing known owns it.
+    pub async fn workload(
+        &self,
+        namespace: &str,
+        pod: &str,
+    ) -> Result<(String, String, String), anyhow::Error> {
+        let mut kind = "Pod".to_string();
+        let mut name = pod.to_string();
+        let mut path = object_path(namespace, &kind, &name).unwrap_or_default();
+        for _ in 0..MAX_OWNERS {
+            let object: Value = serde_json::from_str(&self.get(&path).await?.text().await?)?;
+            let Some((owner_kind, owner_name)) = controller(&object) else {
+                break;
+            };
+            let Some(owner_path) = object_path(namespace, &owner_kind, &owner_name) else {
+                break;
+            };
+            (kind, name, path) = (owner_kind, owner_name, owner_path);
+        }
+        Ok((kind, name, path))
+    }
+
+    /// Applies a JSON merge patch to the object at `path`.
+    pub async fn merge_patch(&self, path: &str, patch: &Value) -> Result<u16, anyhow::Error> {
+        let response = self
+            .client
+            .patch(format!("{}{}", self.server, path))
+            .bearer_auth(&self.token)
+            .header("Content-Type", "application/merge-patch+json")
+            .body(patch.to_string())
+            .send()
+            .await?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(anyhow!("PATCH {} returned {}: {}", path, status, body));
+        }
+        Ok(status.as_u16())
+    }
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::kube::{
+        controller, core_event, crash_annotations, object_path, rfc3339, LAST_CRASH_DUMP_ID,
+        LAST_CRASH_SIGNATURE, LAST_CRASH_TIME,
+    };
+    use serde_json::json;
+
+    #[test]
+    fn core_event_test() {
+        assert_eq!(rfc3339(1588462466), "2020-05-02T23:34:26Z");
+        let dump_info = json!({
+            "namespace": "mo",
+            "podname": "crashing-app-699c49b4ff-86wrh",
+            "pod_uid": "0c65ce05-bd3a-4db2-ad79-131186dc2086",
+            "exe": "node",
+            "real_pid": "4",
+            "signal": "11"
+        });
+        let event = core_event(&dump_info, "a.tar", "node-1", 1588462466).unwrap();
+        assert_eq!(event["metadata"]["namespace"], "mo");
+        assert_eq!(
+            event["involvedObject"]["name"],
+            "crashing-app-699c49b4ff-86wrh"
+        );
+        assert_eq!(
+            event["involvedObject"]["uid"],
+            "0c65ce05-bd3a-4db2-ad79-131186dc2086"
+        );
+        assert_eq!(event["reason"], "CoreDumped");
+        assert_eq!(
+            event["message"],
+            "node (pid 4) dumped core on signal 11, archive a.tar"
+        );
+        assert_eq!(event["lastTimestamp"], "2020-05-02T23:34:26Z");
+
+        let host = json!({"namespace": "unknown", "podname": "unknown"});
+        assert_eq!(core_event(&host, "a.tar", "node-1", 0), None);
+    }
+
+    #[test]
+    fn workload_test() {
+        let dump_info = json!({
+            "namespace": "mo",
+            "podname": "mo-0",
+            "dump_id": "mo-0-1706263200-3b8a",
+            "timestamp": "1588462466",
+            "signature": "9f1c2d"
+        });
+        let patch = crash_annotations(&dump_info, 0).unwrap();
+        let annotations = &patch["metadata"]["annotations"];
+        assert_eq!(annotations[LAST_CRASH_TIME], "2020-05-02T23:34:26Z");
+        assert_eq!(annotations[LAST_CRASH_SIGNATURE], "9f1c2d");
+        assert_eq!(annotations[LAST_CRASH_DUMP_ID], "mo-0-1706263200-3b8a");
+        let unsigned = json!({"namespace": "mo", "podname": "mo-0"});
+        let patch = crash_annotations(&unsigned, 1588462466).unwrap();
+        assert!(patch["metadata"]["annotations"][LAST_CRASH_SIGNATURE].is_null());
+        assert_eq!(
+            patch["metadata"]["annotations"][LAST_CRASH_TIME],
+            "2020-05-02T23:34:26Z"
+        );
+        assert_eq!(crash_annotations(&json!({"namespace": "unknown"}), 0), None);
+
+        let pod = json!({"metadata": {"ownerReferences": [
+            {"kind": "Node", "name": "node-1"},
+            {"kind": "ReplicaSet", "name": "crashing-app-699c49b4ff", "controller": true}
+        ]}});
+        let (kind, name) = controller(&pod).unwrap();
+        assert_eq!(
+            object_path("mo", &kind, &name).unwrap(),
+            "/apis/apps/v1/namespaces/mo/replicasets/crashing-app-699c49b4ff"
+        );
+        assert_eq!(
+            object_path("mo", "CronJob", "nightly").unwrap(),
+            "/apis/batch/v1/namespaces/mo/cronjobs/nightly"
+        );
+        assert_eq!(object_path("mo", "Rollout", "canary"), None);
+        assert_eq!(controller(&json!({"metadata": {}})), None);
+    }
+}
@@ -2,16 +2,20 @@ extern crate dotenv;
 extern crate s3;
 
 use advisory_lock::{AdvisoryFileLock, FileLockMode};
+use anyhow::anyhow;
 use env_logger::Env;
 use inotify::{EventMask, Inotify, WatchMask};
-use log::{error, info, warn};
+use log::{debug, error, info, warn};
 use s3::bucket::Bucket;
 use s3::creds::Credentials;
 use s3::region::Region;
+use serde_json::Value;
+use sha256::try_digest;
 use std::env;
 use std::fs;
-use std::fs::File;
+use std::fs::{File, OpenOptions};
 use std::io::prelude::*;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
 use std::path::Path;
 use std::path::PathBuf;
 use std::process;
@@ -19,7 +23,28 @@ use std::process::Command;
 use std::time::Duration;
 use thiserror::Error;
 use tokio_cron_scheduler::{Job, JobScheduler};
-use sha256::try_digest;
+
+mod archive;
+mod azblob;
+mod catalog;
+mod corefile;
+mod delta;
+mod gcs;
+mod health;
+mod http;
+mod inspect;
+mod kdump;
+mod kube;
+mod pause;
+mod podcache;
+mod policy;
+mod retention;
+mod sftp;
+mod spool;
+mod storage;
+mod subscribe;
+mod support;
+mod symbols;
 
 #[allow(dead_code)]
 struct Storage {
@@ -38,9 +63,15 @@ pub enum Error {
 
 const BIN_PATH: &str = "/bin:/sbin:/usr/bin:/usr/sbin:/usr/local/bin";
 const CDC_NAME: &str = "cdc";
+/// kernel.core_pipe_limit without MAX_CONCURRENT_CAPTURES.
+const DEFAULT_CORE_PIPE_LIMIT: &str = "128";
+const STARTUP_REPORT: &str = "startup-report.json";
 static DEFAULT_BASE_DIR: &str = "/var/mnt/core-dump-handler";
 static DEFAULT_CORE_DIR: &str = "/var/mnt/core-dump-handler/cores";
+static DEFAULT_CORE_FILE_DIR: &str = "/cores";
 
+static DEFAULT_DICTIONARY_NAME: &str = "zstd.dict";
+static COLLECTORS_FILE: &str = "collectors.json";
 static DEFAULT_SUID_DUMPABLE: &str = "2";
 
 #[tokio::main]
@@ -69,6 +100,10 @@ async fn main() -> Result<(), anyhow::Error> {
         .unwrap_or_else(|_| "false".to_string())
         .to_lowercase();
 
+    let core_pattern_mode = env::var("CORE_PATTERN_MODE")
+        .unwrap_or_else(|_| "pipe".to_string())
+        .to_lowercase();
+
     let host_location = host_dir.as_str();
     let pattern: String = std::env::args().nth(1).unwrap_or_default();
 
@@ -83,7 +118,7 @@ async fn main() -> Result<(), anyhow::Error> {
     if pattern == "sweep" {
         let file = std::env::args().nth(2).unwrap_or_default();
         if !file.is_empty() {
-            let bucket = match get_bucket() {
+            let backends = match get_backends() {
                 Ok(v) => v,
                 Err(e) => {
                     error!("Bucket creation failed in sweep: {}", e);
@@ -92,13 +127,159 @@ async fn main() -> Result<(), anyhow::Error> {
             };
             let p = Path::new(&file);
             info!("Uploading {}", file);
-            process_file(p, &bucket).await;
+            process_file(p, &backends).await;
         } else {
             info!("Uploading all content in {}", core_dir_command);
             run_polling_agent().await;
         }
         process::exit(0);
     }
+    if pattern == "reupload" || pattern == "verify" {
+        let target = std::env::args().nth(2).unwrap_or_default();
+        let zip_path = archive::resolve(&core_dir_command, &target)?;
+        let backends = get_backends()?;
+        let catalog = catalog::Catalog::new(&backends.host_dir);
+        let name = zip_path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let dump_info = archive::read_dump_info(&zip_path).ok();
+        let dump_id = dump_info.as_ref().and_then(archive::dump_id);
+        for entry in catalog.entries(&name, dump_id.as_deref()) {
+            info!(
+                "Catalog: {:?} in {} at {}",
+                entry.placement, entry.backend, entry.time
+            );
+        }
+        let mut matched = true;
+        for backend in &backends.backends {
+            if pattern == "reupload" {
+                info!("Re-uploading {} to {}", zip_path.display(), backend.name);
+                upload_archive(&zip_path, dump_info.as_ref(), &backend.store).await?;
+                let key = archive_key(&zip_path, dump_info.as_ref(), &backend.store)?;
+                catalog.record(
+                    &zip_path,
+                    dump_info.as_ref(),
+                    &backend.name,
+                    &key,
+                    catalog::Placement::Stored,
+                )?;
+            }
+            if verify_archive(&zip_path, dump_info.as_ref(), &backend.store).await? {
+                info!("{} copy of {} matches", backend.name, zip_path.display());
+            } else {
+                error!(
+                    "{} copy of {} does not match",
+                    backend.name,
+                    zip_path.display()
+                );
+                matched = false;
+            }
+        }
+        if !matched {
+            process::exit(1);
+        }
+        // A verified archive still queued in the core directory would
+        // otherwise be uploaded again by the next sweep.
+        if pattern == "reupload" && zip_path.parent() == Some(Path::new(&core_dir_command)) {
+            fs::remove_file(&zip_path)?;
+        }
+        process::exit(0);
+    }
+    if pattern == "inspect" {
+        let args: Vec<String> = std::env::args().skip(2).collect();
+        let flag = |name: &str| args.iter().any(|a| a == name);
+        let base_target = args
+            .iter()
+            .position(|a| a == "--base")
+            .map(|at| {
+                args.get(at + 1)
+                    .ok_or_else(|| anyhow::anyhow!("--base takes the uuid or path of an archive"))
+            })
+            .transpose()?;
+        let target = args
+            .iter()
+            .enumerate()
+            .find(|(at, a)| !a.starts_with("--") && (*at == 0 || args[at - 1] != "--base"))
+            .map(|(_, a)| a.clone())
+            .unwrap_or_default();
+        let zip_path = archive::resolve(&core_dir_command, &target)?;
+        let archive = core_dump_archive::Archive::open(&zip_path)?;
+        if !flag("--gdb") {
+            for line in inspect::summary(&archive) {
+                println!("{line}");
+            }
+            process::exit(0);
+        }
+        let dir = env::temp_dir().join(format!(
+            "core-dump-{}",
+            archive.dump_id().unwrap_or("inspect")
+        ));
+        let base = archive
+            .dump_info()
+            .delta_base
+            .as_ref()
+            .map(|delta_base| {
+                inspect::find_base(
+                    delta_base,
+                    base_target.map(String::as_str),
+                    &Path::new(host_location).join("delta-bases"),
+                    &core_dir_command,
+                )
+            })
+            .transpose()?;
+        let session = inspect::prepare(&archive, base.as_ref(), &dir)?;
+        if flag("--launch") {
+            let status = inspect::launch(&session)?;
+            process::exit(status.code().unwrap_or(1));
+        }
+        println!("gdb -x {}", session.gdbinit.display());
+        process::exit(0);
+    }
+    if pattern == "support-bundle" {
+        let args: Vec<String> = std::env::args().skip(2).collect();
+        let hours = match args.iter().position(|a| a == "--hours") {
+            Some(at) => args
+                .get(at + 1)
+                .and_then(|h| h.parse::<u64>().ok())
+                .ok_or_else(|| anyhow::anyhow!("--hours takes a number of hours"))?,
+            None => support::DEFAULT_HOURS,
+        };
+        let now = kube::now();
+        let output = args
+            .iter()
+            .enumerate()
+            .find(|(at, a)| !a.starts_with("--") && (*at == 0 || args[at - 1] != "--hours"))
+            .map(|(_, a)| PathBuf::from(a))
+            .unwrap_or_else(|| PathBuf::from(format!("{host_location}/support-bundle-{now}.tar")));
+        let bundle = support::Bundle {
+            host_dir: PathBuf::from(host_location),
+            core_dir: PathBuf::from(&core_dir_command),
+            event_dir: PathBuf::from(
+                env::var("COMP_CORE_EVENT_DIR")
+                    .unwrap_or_else(|_| format!("{host_location}/events")),
+            ),
+            hours,
+        };
+        bundle.write(&output, now)?;
+        println!("{}", output.display());
+        process::exit(0);
+    }
+    if pattern == "reconstruct" {
+        let arg = |n| std::env::args().nth(n).unwrap_or_default();
+        let size = delta::reconstruct(&arg(2), &arg(3), &arg(4))?;
+        info!("Reconstructed {} bytes into {}", size, arg(4));
+        process::exit(0);
+    }
+
+    if pattern == "train-dictionary" {
+        let samples = std::env::args().nth(2).unwrap_or_default();
+        let output = std::env::args()
+            .nth(3)
+            .unwrap_or_else(|| format!("{host_location}/{DEFAULT_DICTIONARY_NAME}"));
+        train_dictionary(&samples, &output)?;
+        process::exit(0);
+    }
     info!("Setting host location to: {}", host_location);
     info!(
         "Current Directory for setup is {}",
@@ -113,17 +294,28 @@ async fn main() -> Result<(), anyhow::Error> {
         copy_crictl_to_hostdir(host_location)?;
     }
     copy_core_dump_composer_to_hostdir(host_location)?;
+    let core_file_dir =
+        env::var("CORE_FILE_DIR").unwrap_or_else(|_| DEFAULT_CORE_FILE_DIR.to_string());
+    let core_file_pattern = env::var("CORE_FILE_PATTERN")
+        .unwrap_or_else(|_| corefile::DEFAULT_CORE_FILE_PATTERN.to_string());
+    let core_pattern = if core_pattern_mode == "file" {
+        // Some hardened nodes forbid pipe patterns so the kernel writes the
+        // core to disk and the agent feeds it to the composer instead.
+        format!("{core_file_dir}/{core_file_pattern}")
+    } else {
+        format!(
+            "|{host_location}/{CDC_NAME} -c=%c -e=%e -p=%p -P=%P -s=%s -t=%t -d={core_dir_command} -h=%h -E=%E")
+    };
     apply_sysctl(
         "kernel.core_pattern",
         format!("{host_location}/core_pattern.bak").as_str(),
-        format!(
-            "|{host_location}/{CDC_NAME} -c=%c -e=%e -p=%p -s=%s -t=%t -d={core_dir_command} -h=%h -E=%E")
-            .as_str(),
+        core_pattern.as_str(),
     )?;
+    let max_concurrent_captures = env::var("COMP_MAX_CONCURRENT_CAPTURES").unwrap_or_default();
     apply_sysctl(
         "kernel.core_pipe_limit",
         format!("{host_location}/core_pipe_limit.bak").as_str(),
-        "128",
+        &core_pipe_limit(&max_concurrent_captures),
     )?;
 
     apply_sysctl(
@@ -133,6 +325,185 @@ async fn main() -> Result<(), anyhow::Error> {
     )?;
 
     create_env_file(host_location)?;
+    if let Err(e) = write_startup_report(host_location, &max_concurrent_captures) {
+        error!("Writing the startup report failed: {}", e);
+    }
+
+    let capture_user = env::var("COMP_CAPTURE_USER").unwrap_or_default();
+    if !capture_user.is_empty() {
+        if let Err(e) = prepare_capture_user(host_location, &core_dir_command, &capture_user) {
+            error!(
+                "Preparing the host for CAPTURE_USER {} failed: {}",
+                capture_user, e
+            );
+        }
+    }
+
+    let policy_source = env::var("POLICY_SOURCE").unwrap_or_default();
+    if !policy_source.is_empty() {
+        let ttl = env::var("POLICY_TTL")
+            .unwrap_or_default()
+            .parse::<u64>()
+            .unwrap_or(policy::DEFAULT_POLICY_TTL);
+        let ttl = Duration::from_secs(ttl.max(1));
+        if let Err(e) = apply_policy(host_location, &policy_source, ttl).await {
+            error!("Applying policy from {} failed: {}", policy_source, e);
+        }
+        let l_host_location = host_location.to_string();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(ttl).await;
+                // Start from the chart settings so keys dropped from the
+                // policy fall back to them.
+                if let Err(e) = create_env_file(&l_host_location) {
+                    error!("Recreating composer .env failed: {}", e);
+                    continue;
+                }
+                if let Err(e) = apply_policy(&l_host_location, &policy_source, ttl).await {
+                    error!("Applying policy from {} failed: {}", policy_source, e);
+                }
+            }
+        });
+    }
+
+    let kdump_events = env::var("KDUMP_EVENTS")
+        .unwrap_or_else(|_| "false".to_string())
+        .to_lowercase();
+    if kdump_events == "true" {
+        let event_dir = env::var("EVENT_DIR").unwrap_or_else(|_| format!("{host_location}/events"));
+        let kdump_dir =
+            env::var("KDUMP_DIR").unwrap_or_else(|_| kdump::DEFAULT_KDUMP_DIR.to_string());
+        let pstore_dir =
+            env::var("PSTORE_DIR").unwrap_or_else(|_| kdump::DEFAULT_PSTORE_DIR.to_string());
+        let node_name = env::var("NODE_NAME").unwrap_or_else(|_| "unknown".to_string());
+        if let Err(e) = kdump::report_node_crashes(
+            host_location,
+            &event_dir,
+            &kdump_dir,
+            &pstore_dir,
+            &node_name,
+        ) {
+            error!("Failed to report kernel crash dumps: {}", e);
+        }
+    }
+
+    if core_pattern_mode == "file" {
+        fs::create_dir_all(&core_file_dir)?;
+        let composer = format!("{host_location}/{CDC_NAME}");
+        let l_core_dir = core_dir_command.clone();
+        info!(
+            "Watching {} for cores matching {}",
+            core_file_dir, core_file_pattern
+        );
+        std::thread::spawn(move || {
+            corefile::compose_existing(&core_file_dir, &core_file_pattern, &composer, &l_core_dir);
+            corefile::watch(&core_file_dir, &core_file_pattern, &composer, &l_core_dir);
+        });
+    }
+
+    let event_grpc_addr = env::var("EVENT_GRPC_ADDR").unwrap_or_default();
+    if !event_grpc_addr.is_empty() {
+        let addr = event_grpc_addr
+            .parse()
+            .map_err(|e| anyhow!("Invalid EVENT_GRPC_ADDR {}: {}", event_grpc_addr, e))?;
+        let event_dir =
+            env::var("COMP_CORE_EVENT_DIR").unwrap_or_else(|_| format!("{host_location}/events"));
+        fs::create_dir_all(&event_dir)?;
+        tokio::spawn(subscribe::serve(addr, event_dir));
+    }
+
+    let core_events = env::var("COMP_CORE_EVENTS")
+        .unwrap_or_else(|_| "false".to_string())
+        .to_lowercase();
+    if core_events == "true" {
+        let spool = Path::new(host_location).join(spool::EVENT_SPOOL_DIR);
+        let event_dir = PathBuf::from(
+            env::var("COMP_CORE_EVENT_DIR").unwrap_or_else(|_| format!("{host_location}/events")),
+        );
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = spool::drain(&spool, &event_dir) {
+                    error!("Draining event spool failed: {}", e);
+                }
+                tokio::time::sleep(Duration::from_secs(30)).await;
+            }
+        });
+    }
+
+    let pause_source = env::var("PAUSE_SOURCE").unwrap_or_default();
+    if !pause_source.is_empty() {
+        let pause_file = get_pause_file(host_location);
+        info!("Mirroring {} to {}", pause_source, pause_file);
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = pause::sync(Path::new(&pause_source), Path::new(&pause_file)) {
+                    error!("Updating pause file failed: {}", e);
+                }
+                tokio::time::sleep(Duration::from_secs(5)).await;
+            }
+        });
+    }
+
+    let pod_cache_interval = env::var("POD_CACHE_INTERVAL")
+        .unwrap_or_default()
+        .parse::<u64>()
+        .unwrap_or(0);
+    if pod_cache_interval > 0 {
+        let endpoint = env::var("CRIO_ENDPOINT")
+            .unwrap_or_else(|_| "unix:///run/containerd/containerd.sock".to_string());
+        let host_dir = PathBuf::from(host_location);
+        let max_age = pod_cache_interval * podcache::MAX_AGE_INTERVALS;
+        info!("Caching pod metadata every {}s", pod_cache_interval);
+        tokio::spawn(async move {
+            loop {
+                let (endpoint, host_dir) = (endpoint.clone(), host_dir.clone());
+                let refreshed = tokio::task::spawn_blocking(move || {
+                    podcache::refresh("./crictl", &endpoint, &host_dir, max_age)
+                })
+                .await;
+                match refreshed {
+                    Ok(Ok(cached)) => debug!("Cached the pods of {} containers", cached),
+                    Ok(Err(e)) => error!("Refreshing the pod cache failed: {}", e),
+                    Err(e) => error!("Refreshing the pod cache panicked: {}", e),
+                }
+                tokio::time::sleep(Duration::from_secs(pod_cache_interval)).await;
+            }
+        });
+    }
+
+    let health_interval = env::var("HEALTH_INTERVAL")
+        .unwrap_or_default()
+        .parse::<u64>()
+        .unwrap_or(0);
+    if health_interval > 0 {
+        let node_name = env::var("NODE_NAME").unwrap_or_else(|_| "unknown".to_string());
+        info!("Probing storage backends every {}s", health_interval);
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = probe_backends(&node_name).await {
+                    error!("Backend health probe failed: {}", e);
+                }
+                tokio::time::sleep(Duration::from_secs(health_interval)).await;
+            }
+        });
+    }
+    let gc_grace_minutes = env::var("NAMESPACE_GC_GRACE_MINUTES")
+        .unwrap_or_default()
+        .parse::<u64>()
+        .unwrap_or(0);
+    if gc_grace_minutes > 0 {
+        match get_backends() {
+            Ok(backends) => {
+                let retention = retention::Retention {
+                    grace: gc_grace_minutes * 60,
+                    core_dir: PathBuf::from(&core_dir_command),
+                    backends,
+                };
+                tokio::spawn(retention.run());
+            }
+            Err(e) => error!("Namespace retention is off, no backends: {}", e),
+        }
+    }
     // Run polling agent on startup to clean up files.
 
     let interval = env::var("INTERVAL").unwrap_or_else(|_| String::from(""));
@@ -256,7 +627,7 @@ async fn main() -> Result<(), anyhow::Error> {
                         if event.mask.contains(EventMask::ISDIR) {
                             warn!("Unknown Directory created: {:?}", event.name);
                         } else {
-                            let bucket = match get_bucket() {
+                            let backends = match get_backends() {
                                 Ok(v) => v,
                                 Err(e) => {
                                     error!("Bucket creation failed in event: {}", e);
@@ -271,7 +642,7 @@ async fn main() -> Result<(), anyhow::Error> {
                                         s.to_str().unwrap_or_default()
                                     );
                                     let p = Path::new(&file);
-                                    process_file(p, &bucket).await
+                                    process_file(p, &backends).await
                                 }
                                 None => {
                                     continue;
@@ -288,12 +659,32 @@ async fn main() -> Result<(), anyhow::Error> {
     Ok(())
 }
 
-async fn process_file(zip_path: &Path, bucket: &Bucket) {
+async fn process_file(zip_path: &Path, backends: &storage::Backends) {
+    // Checksums and the dump-info of encrypted archives go with their
+    // archive, one left without it is removed.
+    if let Some(archive_path) = zip_path.to_str().and_then(|p| {
+        p.strip_suffix(archive::CHECKSUM_SUFFIX)
+            .or_else(|| p.strip_suffix(archive::DUMP_INFO_SUFFIX))
+    }) {
+        if !Path::new(archive_path).exists() {
+            if let Err(e) = fs::remove_file(zip_path) {
+                error!("Removing {} failed: {}", zip_path.display(), e);
+            }
+        }
+        return;
+    }
+    if zip_path
+        .to_str()
+        .is_some_and(|p| p.ends_with(archive::FAILURE_SUFFIX))
+    {
+        debug!("Leaving failure record {}", zip_path.display());
+        return;
+    }
     info!("Uploading: {}", zip_path.display());
 
     let f = File::open(zip_path).expect("no file found");
 
-    match f.try_lock(FileLockMode::Shared) {
+    match AdvisoryFileLock::try_lock(&f, FileLockMode::Shared) {
         Ok(_) => { /* If we can lock then we are ok */ }
         Err(e) => {
             let l_inotify = env::var("USE_INOTIFY")
@@ -317,49 +708,555 @@ async fn process_file(zip_path: &Path, bucket: &Bucket) {
             return;
         }
     };
-    let upload_file_name: &str = match zip_path.file_name().unwrap().to_str() {
+    // Read once, the tags, keys, catalog and pod helpers all share it.
+    let dump_info = match archive::read_dump_info(zip_path) {
+        Ok(v) => Some(v),
+        Err(e) => {
+            warn!("No dump-info for {}: {}", zip_path.display(), e);
+            None
+        }
+    };
+    if let Err(e) = upload_with_policy(zip_path, dump_info.as_ref(), backends).await {
+        error!("Upload Failed {}", e);
+        return;
+    }
+    if env::var("POD_EVENTS").unwrap_or_default().to_lowercase() == "true" {
+        post_pod_event(zip_path, dump_info.as_ref()).await;
+    }
+    if env::var("WORKLOAD_ANNOTATIONS")
+        .unwrap_or_default()
+        .to_lowercase()
+        == "true"
+    {
+        annotate_workload(zip_path, dump_info.as_ref()).await;
+    }
+    let symbol_store = env::var("SYMBOL_STORE").unwrap_or_default();
+    if !symbol_store.is_empty() {
+        upload_symbols(zip_path, &symbol_store, &backends.host_dir).await;
+    }
+    if let Err(e) = fs::remove_file(path_str) {
+        error!("File delete failed: {}", e);
+    }
+    for sidecar in [
+        archive::checksum_path(zip_path),
+        archive::dump_info_path(zip_path),
+    ] {
+        if sidecar.exists() {
+            if let Err(e) = fs::remove_file(&sidecar) {
+                error!("{} delete failed: {}", sidecar.display(), e);
+            }
+        }
+    }
+}
+
+/// Sends the binaries captured with the archive to the symbol store.
+/// Failures are only logged, the archive is safe by now.
+async fn upload_symbols(zip_path: &Path, prefix: &str, host_dir: &Path) {
+    let result = async {
+        let store = get_store(prefix)?;
+        let layout = env::var("SYMBOL_LAYOUT")
+            .unwrap_or_default()
+            .parse::<symbols::Layout>()?;
+        let key_prefix = env::var("SYMBOL_PREFIX").unwrap_or_default();
+        let archive = core_dump_archive::Archive::open(zip_path)?;
+        let uploaded = symbols::Uploaded::new(host_dir);
+        symbols::upload(&archive, &store, layout, &key_prefix, &uploaded).await
+    };
+    match result.await {
+        Ok(0) => {}
+        Ok(sent) => info!(
+            "Sent {} binaries of {} to the symbol store",
+            sent,
+            zip_path.display()
+        ),
+        Err(e) => error!("Symbol upload for {} failed: {}", zip_path.display(), e),
+    }
+}
+
+/// Tells the crashing pod about its stored archive with a Kubernetes
+/// Event. Failures are only logged, the archive is safe by now.
+async fn post_pod_event(zip_path: &Path, dump_info: Option<&Value>) {
+    let name = zip_path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let Some(dump_info) = dump_info else {
+        warn!("No pod event for {}: no dump-info", name);
+        return;
+    };
+    let node = env::var("NODE_NAME").unwrap_or_else(|_| "unknown".to_string());
+    let event = match kube::core_event(dump_info, &name, &node, kube::now()) {
         Some(v) => v,
         None => {
-            error!("Failed to get file name for upload");
+            info!("{} is not from a pod, no pod event", name);
             return;
         }
     };
+    let result = match kube::InCluster::from_env() {
+        Ok(cluster) => cluster.create_event(&event).await,
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(code) => info!(
+            "Posted CoreDumped event on {}/{}: {}",
+            event["involvedObject"]["namespace"]
+                .as_str()
+                .unwrap_or_default(),
+            event["involvedObject"]["name"].as_str().unwrap_or_default(),
+            code
+        ),
+        Err(e) => error!("Posting pod event for {} failed: {}", name, e),
+    }
+}
+
+/// Annotates the workload of the crashing pod with the stored crash.
+/// Failures are only logged, the archive is safe by now.
+async fn annotate_workload(zip_path: &Path, dump_info: Option<&Value>) {
+    let name = zip_path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let Some(dump_info) = dump_info else {
+        warn!("No workload annotations for {}: no dump-info", name);
+        return;
+    };
+    let Some(patch) = kube::crash_annotations(dump_info, kube::now()) else {
+        info!("{} is not from a pod, no workload annotations", name);
+        return;
+    };
+    let namespace = dump_info["namespace"].as_str().unwrap_or_default();
+    let pod = dump_info["podname"].as_str().unwrap_or_default();
+    let result = async {
+        let cluster = kube::InCluster::from_env()?;
+        let (kind, workload, path) = cluster.workload(namespace, pod).await?;
+        cluster.merge_patch(&path, &patch).await?;
+        Ok::<_, anyhow::Error>(format!("{kind} {namespace}/{workload}"))
+    };
+    match result.await {
+        Ok(annotated) => info!("Annotated {} with the crash of {}", annotated, name),
+        Err(e) => error!("Annotating the workload of {} failed: {}", name, e),
+    }
+}
 
-    let mut fasync = tokio::fs::File::open(zip_path)
-        .await
-        .expect("file was removed");
+/// Uploads an archive to the configured backends according to
+/// STORAGE_POLICY. Backends the health probe marked down are skipped and
+/// their copy is queued until they recover. An error leaves the archive in
+/// place for the next sweep.
+async fn upload_with_policy(
+    zip_path: &Path,
+    dump_info: Option<&Value>,
+    backends: &storage::Backends,
+) -> Result<(), anyhow::Error> {
+    let health = health::HealthFile::new(&backends.host_dir);
+    let catalog = catalog::Catalog::new(&backends.host_dir);
+    let record = |backend: &storage::Backend, placement| {
+        let recorded = archive_key(zip_path, dump_info, &backend.store)
+            .and_then(|key| catalog.record(zip_path, dump_info, &backend.name, &key, placement));
+        if let Err(e) = recorded {
+            warn!("Catalog update for {} failed {}", zip_path.display(), e);
+        }
+    };
+    let queue = |targets: &[storage::Backend]| -> Result<(), anyhow::Error> {
+        for (backend, _) in backends.enqueue(zip_path, targets)? {
+            record(&backend, catalog::Placement::Queued);
+        }
+        Ok(())
+    };
+    let (healthy, down): (Vec<_>, Vec<_>) = backends
+        .backends
+        .iter()
+        .cloned()
+        .partition(|b| health.is_healthy(&b.name));
 
-    // check sha256 sum
-    let val = try_digest(zip_path).unwrap();
+    match backends.policy {
+        storage::MirrorPolicy::All => {
+            if healthy.is_empty() {
+                return Err(anyhow!("No healthy backend for {}", zip_path.display()));
+            }
+            let mut failed = vec![];
+            for backend in &healthy {
+                match upload_archive(zip_path, dump_info, &backend.store).await {
+                    Ok(_) => record(backend, catalog::Placement::Stored),
+                    Err(e) => {
+                        error!("Upload to {} failed {}", backend.name, e);
+                        failed.push(backend.name.as_str());
+                    }
+                }
+            }
+            if !failed.is_empty() {
+                return Err(anyhow!("Not stored in {}", failed.join(", ")));
+            }
+            queue(&down)
+        }
+        storage::MirrorPolicy::FirstSuccess => {
+            // Unhealthy backends are only tried once every healthy one failed.
+            for backend in healthy.iter().chain(down.iter()) {
+                match upload_archive(zip_path, dump_info, &backend.store).await {
+                    Ok(_) => {
+                        info!("Stored {} in {}", zip_path.display(), backend.name);
+                        let placement = if backend.name == backends.primary().name {
+                            catalog::Placement::Stored
+                        } else {
+                            catalog::Placement::Failover
+                        };
+                        record(backend, placement);
+                        return Ok(());
+                    }
+                    Err(e) => warn!("Upload to {} failed {}", backend.name, e),
+                }
+            }
+            Err(anyhow!("No backend accepted {}", zip_path.display()))
+        }
+        storage::MirrorPolicy::PrimaryAsyncMirror => {
+            let primary = backends.primary();
+            let stored = if health.is_healthy(&primary.name) {
+                match upload_archive(zip_path, dump_info, &primary.store).await {
+                    Ok(_) => Some(primary.clone()),
+                    Err(e) => {
+                        warn!("Upload to primary {} failed {}", primary.name, e);
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+            let stored = match stored {
+                Some(v) => {
+                    record(&v, catalog::Placement::Stored);
+                    v
+                }
+                None => {
+                    let mut failover = None;
+                    for backend in healthy.iter().filter(|b| b.name != primary.name) {
+                        match upload_archive(zip_path, dump_info, &backend.store).await {
+                            Ok(_) => {
+                                warn!("Failed over {} to {}", zip_path.display(), backend.name);
+                                record(backend, catalog::Placement::Failover);
+                                failover = Some(backend.clone());
+                                break;
+                            }
+                            Err(e) => warn!("Failover to {} failed {}", backend.name, e),
+                        }
+                    }
+                    failover.ok_or_else(|| anyhow!("No backend accepted {}", zip_path.display()))?
+                }
+            };
+            let rest: Vec<storage::Backend> = backends
+                .backends
+                .iter()
+                .filter(|b| b.name != stored.name)
+                .cloned()
+                .collect();
+            queue(&rest)?;
+            let queued = backends
+                .queued()
+                .into_iter()
+                .filter(|(_, p)| p.file_name() == zip_path.file_name())
+                .collect();
+            tokio::spawn(upload_queued(queued, backends.host_dir.clone()));
+            Ok(())
+        }
+    }
+}
+
+/// Drains queue entries to backends that are healthy, leaving the rest for
+/// the next sweep.
+async fn upload_queued(queued: Vec<(storage::Backend, PathBuf)>, host_dir: PathBuf) {
+    let health = health::HealthFile::new(&host_dir);
+    let catalog = catalog::Catalog::new(&host_dir);
+    for (backend, path) in queued {
+        if !health.is_healthy(&backend.name) {
+            continue;
+        }
+        let dump_info = archive::read_dump_info(&path).ok();
+        match upload_archive(&path, dump_info.as_ref(), &backend.store).await {
+            Ok(_) => {
+                let recorded =
+                    archive_key(&path, dump_info.as_ref(), &backend.store).and_then(|key| {
+                        catalog.record(
+                            &path,
+                            dump_info.as_ref(),
+                            &backend.name,
+                            &key,
+                            catalog::Placement::Reconciled,
+                        )
+                    });
+                if let Err(e) = recorded {
+                    warn!("Catalog update for {} failed {}", path.display(), e);
+                }
+                for path in [path.clone(), archive::dump_info_path(&path)] {
+                    if !path.exists() {
+                        continue;
+                    }
+                    if let Err(e) = fs::remove_file(&path) {
+                        warn!("Removing {} from queue failed {}", path.display(), e);
+                    }
+                }
+            }
+            Err(e) => warn!("Queued upload to {} failed {}", backend.name, e),
+        }
+    }
+}
+
+/// Writes a probe object to every backend and records which accept
+/// uploads. Queued copies are sent once their backend recovers.
+async fn probe_backends(node_name: &str) -> Result<(), anyhow::Error> {
+    let backends = get_backends()?;
+    let health_file = health::HealthFile::new(&backends.host_dir);
+    let mut health = health_file.load();
+    let checked = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs();
+    for backend in &backends.backends {
+        let result = match backend
+            .store
+            .put(
+                &health::probe_key(node_name),
+                checked.to_string().as_bytes(),
+            )
+            .await
+        {
+            Ok(code) if (200..300).contains(&code) => Ok(()),
+            Ok(code) => Err(format!("probe returned {code}")),
+            Err(e) => Err(e.to_string()),
+        };
+        let was_healthy = health.get(&backend.name).map(|h| h.healthy).unwrap_or(true);
+        match &result {
+            Ok(_) if !was_healthy => info!("Backend {} recovered", backend.name),
+            Err(e) if was_healthy => error!("Backend {} is unhealthy: {}", backend.name, e),
+            _ => {}
+        }
+        health.insert(
+            backend.name.clone(),
+            health::BackendHealth {
+                healthy: result.is_ok(),
+                checked,
+                error: result.err(),
+            },
+        );
+    }
+    health_file.save(&health)?;
+    upload_queued(backends.queued(), backends.host_dir.clone()).await;
+    Ok(())
+}
+
+/// Uploads an archive with its tags and storage class, keeping the local
+/// copy. An archive not matching the checksum the composer wrote beside
+/// it is refused, the checksum is stored next to the uploaded copy.
+async fn upload_archive(
+    zip_path: &Path,
+    dump_info: Option<&Value>,
+    store: &storage::Store,
+) -> Result<u16, anyhow::Error> {
+    let val = try_digest(zip_path)?;
     info!("zip sha256 is {}", val);
+    let checksum = archive::read_checksum(zip_path)?;
+    if let Some(expected) = &checksum {
+        if *expected != val {
+            return Err(anyhow!(
+                "{} has sha256 {} but its checksum file holds {}",
+                zip_path.display(),
+                val,
+                expected
+            ));
+        }
+    }
+    let code = put_archive(zip_path, dump_info, store).await?;
+    if checksum.is_some() {
+        let key = format!(
+            "{}{}",
+            archive_key(zip_path, dump_info, store)?,
+            archive::CHECKSUM_SUFFIX
+        );
+        let content = fs::read(archive::checksum_path(zip_path))?;
+        let checksum_code = store.put(&key, &content).await?;
+        info!("Stored checksum as {}: {}", key, checksum_code);
+    }
+    Ok(code)
+}
 
-    let code = match bucket
-        .put_object_stream(&mut fasync, upload_file_name)
-        .await
-    {
-        Ok(v) => v,
-        Err(e) => {
-            error!("Upload Failed {}", e);
-            return;
+async fn put_archive(
+    zip_path: &Path,
+    dump_info: Option<&Value>,
+    store: &storage::Store,
+) -> Result<u16, anyhow::Error> {
+    let upload_file_name = zip_path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| anyhow!("Failed to get file name for upload"))?;
+    let mut fasync = tokio::fs::File::open(zip_path).await?;
+
+    let data_class = env::var("COMP_DATA_CLASS").unwrap_or_default();
+
+    if dump_info.is_none() {
+        warn!("Uploading {} without tags", upload_file_name);
+    }
+    let tags = dump_info
+        .map(|d| archive::upload_tags(d, &data_class))
+        .unwrap_or_default();
+    let key = archive_key(zip_path, dump_info, store)?;
+    let bucket = match store {
+        storage::Store::S3(bucket) => bucket,
+        storage::Store::AzBlob(container) => {
+            let code = container.put_file(zip_path, &key, &tags).await?;
+            info!(
+                "Stored {} in container {} as {} with tags {:?}: {}",
+                upload_file_name, container.name, key, tags, code
+            );
+            return Ok(code);
         }
-    };
-    match fs::remove_file(path_str) {
-        Ok(v) => v,
-        Err(e) => {
-            error!("File delete failed: {}", e);
-            return;
+        storage::Store::Gcs(gcs) => {
+            let code = gcs.put_file(zip_path, &key, &tags).await?;
+            info!(
+                "Stored {} in gcs bucket {} as {} with metadata {:?}: {}",
+                upload_file_name, gcs.name, key, tags, code
+            );
+            return Ok(code);
+        }
+        storage::Store::Sftp(target) => {
+            let code = target.put_file(zip_path, &key).await?;
+            info!("Stored {} on {} as {}", upload_file_name, target.host, key);
+            return Ok(code);
         }
+        storage::Store::Http(target) => {
+            let code = target.put_file(zip_path, &key, &tags).await?;
+            info!(
+                "Sent {} to {} with tags {:?}: {}",
+                upload_file_name,
+                target.url(&key),
+                tags,
+                code
+            );
+            return Ok(code);
+        }
+    };
+    let storage_class = env::var("S3_STORAGE_CLASS").unwrap_or_default();
+    let upload_bucket = if storage_class.is_empty() {
+        bucket.clone()
+    } else {
+        let mut b = bucket.clone();
+        b.add_header("x-amz-storage-class", &storage_class);
+        b
     };
+
+    let code = upload_bucket.put_object_stream(&mut fasync, &key).await?;
+    if !(200..300).contains(&code) {
+        return Err(anyhow!("Upload of {} returned {}", key, code));
+    }
+    if !tags.is_empty() {
+        match bucket.put_object_tagging(&key, &tags).await {
+            Ok((_, code)) => info!("Tagged {} with {:?}: {}", key, tags, code),
+            Err(e) => error!("Tagging {} failed {}", key, e),
+        }
+    }
     info!("S3 Returned: {}", code);
+    Ok(code)
+}
+
+/// Downloads the uploaded copy of an archive and compares its sha256 with
+/// the local file.
+async fn verify_archive(
+    zip_path: &Path,
+    dump_info: Option<&Value>,
+    store: &storage::Store,
+) -> Result<bool, anyhow::Error> {
+    let name = zip_path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| anyhow!("Failed to get file name for {}", zip_path.display()))?;
+    let local = try_digest(zip_path)?;
+    let mut remote = archive::Sha256Writer::new();
+    let key = archive_key(zip_path, dump_info, store)?;
+    let code = store.get_stream(&key, &mut remote).await?;
+    if code != 200 {
+        return Err(anyhow!("Fetching {} returned {}", key, code));
+    }
+    let remote_len = remote.len;
+    let remote = remote.finish();
+    info!(
+        "{}: local sha256 {} ({} bytes), remote sha256 {} ({} bytes)",
+        name,
+        local,
+        fs::metadata(zip_path)?.len(),
+        remote,
+        remote_len
+    );
+    Ok(local == remote)
+}
+
+/// The key the archive at `zip_path` is stored as in `store`.
+fn archive_key(
+    zip_path: &Path,
+    dump_info: Option<&Value>,
+    store: &storage::Store,
+) -> Result<String, anyhow::Error> {
+    let name = zip_path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| anyhow!("Failed to get file name for {}", zip_path.display()))?;
+    Ok(store.key(name, dump_info, &get_keys()?))
+}
+
+fn get_keys() -> Result<storage::Keys, anyhow::Error> {
+    let max_length = match env::var("KEY_MAX_LENGTH").unwrap_or_default().as_str() {
+        "" => storage::DEFAULT_KEY_MAX_LENGTH,
+        v => v
+            .parse()
+            .map_err(|_| anyhow!("KEY_MAX_LENGTH {} is not a number", v))?,
+    };
+    Ok(storage::Keys {
+        scheme: env::var("STORAGE_KEY").unwrap_or_default().parse()?,
+        encoding: env::var("KEY_ENCODING").unwrap_or_default().parse()?,
+        max_length,
+    })
+}
+
+fn get_backends() -> Result<storage::Backends, anyhow::Error> {
+    let names = storage::backend_names(&env::var("STORAGE_BACKENDS").unwrap_or_default());
+    let policy = env::var("STORAGE_POLICY")
+        .unwrap_or_default()
+        .parse::<storage::MirrorPolicy>()?;
+    let mut backends = vec![];
+    for name in names {
+        let store = get_store(&name)?;
+        backends.push(storage::Backend { name, store });
+    }
+    let host_dir = env::var("HOST_DIR").unwrap_or_else(|_| DEFAULT_BASE_DIR.to_string());
+    Ok(storage::Backends {
+        policy,
+        backends,
+        mirror_queue: Path::new(&host_dir).join(storage::MIRROR_QUEUE_DIR),
+        host_dir: PathBuf::from(host_dir),
+    })
 }
 
-fn get_bucket() -> Result<Bucket, anyhow::Error> {
-    let s3_access_key = env::var("S3_ACCESS_KEY").unwrap_or_default();
-    let s3_secret = env::var("S3_SECRET").unwrap_or_default();
-    let s3_bucket_name = env::var("S3_BUCKET_NAME").unwrap_or_default();
-    let s3_region = env::var("S3_REGION").unwrap_or_default();
+/// Builds the store of a backend from the `{prefix}_*` env vars, `S3_*` for
+/// the default backend.
+fn get_store(prefix: &str) -> Result<storage::Store, anyhow::Error> {
+    let var = |name: &str| env::var(format!("{prefix}_{name}")).unwrap_or_default();
+    let kind = match var("STORAGE_BACKEND") {
+        v if v.is_empty() => env::var("STORAGE_BACKEND").unwrap_or_default(),
+        v => v,
+    };
+    match kind.parse::<storage::StoreKind>()? {
+        storage::StoreKind::S3 => Ok(storage::Store::S3(Box::new(get_bucket(prefix)?))),
+        storage::StoreKind::AzBlob => {
+            Ok(storage::Store::AzBlob(azblob::Container::from_vars(var)?))
+        }
+        storage::StoreKind::Gcs => Ok(storage::Store::Gcs(gcs::GcsBucket::from_vars(var)?)),
+        storage::StoreKind::Sftp => Ok(storage::Store::Sftp(sftp::SftpTarget::from_vars(var)?)),
+        storage::StoreKind::Http => Ok(storage::Store::Http(http::HttpTarget::from_vars(var)?)),
+    }
+}
+
+fn get_bucket(prefix: &str) -> Result<Bucket, anyhow::Error> {
+    let var = |name: &str| env::var(format!("{prefix}_{name}")).unwrap_or_default();
+    let s3_access_key = var("ACCESS_KEY");
+    let s3_secret = var("SECRET");
+    let s3_bucket_name = var("BUCKET_NAME");
+    let s3_region = var("REGION");
 
-    let custom_endpoint = env::var("S3_ENDPOINT").unwrap_or_default();
+    let custom_endpoint = var("ENDPOINT");
 
     let region = if custom_endpoint.is_empty() {
         s3_region.parse().unwrap()
@@ -372,19 +1269,20 @@ fn get_bucket() -> Result<Bucket, anyhow::Error> {
         }
     };
 
-    let credentials = if env::var("AWS_WEB_IDENTITY_TOKEN_FILE").is_ok() {
-        Credentials::from_sts_env(std::env!("CARGO_PKG_NAME"))
-    } else if s3_access_key.is_empty() || s3_secret.is_empty() {
-        Credentials::new(None, None, None, None, None)
-    } else {
-        Credentials::new(
-            Some(s3_access_key.as_str()),
-            Some(s3_secret.as_str()),
-            None,
-            None,
-            None,
-        )
-    }?;
+    let credentials =
+        if prefix == storage::DEFAULT_BACKENDS && env::var("AWS_WEB_IDENTITY_TOKEN_FILE").is_ok() {
+            Credentials::from_sts_env(std::env!("CARGO_PKG_NAME"))
+        } else if s3_access_key.is_empty() || s3_secret.is_empty() {
+            Credentials::new(None, None, None, None, None)
+        } else {
+            Credentials::new(
+                Some(s3_access_key.as_str()),
+                Some(s3_secret.as_str()),
+                None,
+                None,
+                None,
+            )
+        }?;
 
     let s3 = Storage {
         name: "aws".into(),
@@ -400,7 +1298,7 @@ async fn run_polling_agent() {
     let core_location = env::var("CORE_DIR").unwrap_or_else(|_| DEFAULT_CORE_DIR.to_string());
     info!("Executing Agent with location : {}", core_location);
 
-    let bucket = match get_bucket() {
+    let backends = match get_backends() {
         Ok(v) => v,
         Err(e) => {
             error!("Bucket Creation Failed: {}", e);
@@ -419,8 +1317,9 @@ async fn run_polling_agent() {
 
     info!("Dir Content {:?}", paths);
     for zip_path in paths {
-        process_file(&zip_path, &bucket).await;
+        process_file(&zip_path, &backends).await;
     }
+    upload_queued(backends.queued(), backends.host_dir.clone()).await;
 }
 
 fn generate_crio_config(host_location: &str) -> Result<(), std::io::Error> {
@@ -435,6 +1334,55 @@ fn generate_crio_config(host_location: &str) -> Result<(), std::io::Error> {
     Ok(())
 }
 
+/// Hands the paths the composer writes to COMP_CAPTURE_USER, the user its
+/// worker runs the capture as once the core is spooled.
+fn prepare_capture_user(
+    host_location: &str,
+    core_dir: &str,
+    user: &str,
+) -> Result<(), anyhow::Error> {
+    let id = |v: &str| {
+        v.trim()
+            .parse::<u32>()
+            .map_err(|_| anyhow::anyhow!("{} is not uid[:gid]", user))
+    };
+    let (uid, gid) = match user.split_once(':') {
+        Some((uid, gid)) => (id(uid)?, id(gid)?),
+        None => (id(user)?, id(user)?),
+    };
+    let host = Path::new(host_location);
+    let event_dir =
+        env::var("COMP_CORE_EVENT_DIR").unwrap_or_else(|_| format!("{host_location}/events"));
+    for dir in [
+        PathBuf::from(core_dir),
+        PathBuf::from(event_dir),
+        host.join(spool::EVENT_SPOOL_DIR),
+        host.join("delta-bases"),
+        host.join("capture-slots"),
+    ] {
+        fs::create_dir_all(&dir)?;
+        std::os::unix::fs::chown(&dir, Some(uid), Some(gid))?;
+    }
+    // Slots a root composer made before CAPTURE_USER was set.
+    for entry in fs::read_dir(host.join("capture-slots"))? {
+        std::os::unix::fs::chown(entry?.path(), Some(uid), Some(gid))?;
+    }
+    for file in [
+        ".env",
+        "composer.log",
+        "decisions.log",
+        "sequence",
+        "ratelimit.json",
+        "signatures.json",
+    ] {
+        let path = host.join(file);
+        OpenOptions::new().create(true).append(true).open(&path)?;
+        std::os::unix::fs::chown(&path, Some(uid), Some(gid))?;
+    }
+    info!("Composer output handed to {}:{}", uid, gid);
+    Ok(())
+}
+
 fn copy_crictl_to_hostdir(host_location: &str) -> Result<(), std::io::Error> {
     let location = "./crictl".to_string();
     let destination = format!("{}/{}", host_location, "crictl");
@@ -477,15 +1425,20 @@ fn create_env_file(host_location: &str) -> Result<(), std::io::Error> {
         .unwrap_or_else(|_| "false".to_string())
         .to_lowercase();
     let filename_template = env::var("COMP_FILENAME_TEMPLATE").unwrap_or_else(|_| {
-        "{uuid}-dump-{timestamp}-{hostname}-{exe_name}-{pid}-{signal}".to_string()
+        "{uuid}-dump-{timestamp}-{hostname}-{exe_name}-{pid}-{signal}-{pod_uid}-{sequence}"
+            .to_string()
     });
     let log_length = env::var("COMP_LOG_LENGTH").unwrap_or_else(|_| "500".to_string());
+    // Quoted as set-based selectors such as `env in (prod, staging)` have spaces.
     let pod_selector_label = env::var("COMP_POD_SELECTOR_LABEL").unwrap_or_default();
     let timeout = env::var("COMP_TIMEOUT").unwrap_or_else(|_| "600".to_string());
 
     let compression = env::var("COMP_COMPRESSION")
         .unwrap_or_else(|_| "true".to_string())
         .to_lowercase();
+    let core_compression = env::var("COMP_CORE_COMPRESSION").unwrap_or_default();
+    let compression_level = env::var("COMP_COMPRESSION_LEVEL").unwrap_or_default();
+    let compression_threads = env::var("COMP_COMPRESSION_THREADS").unwrap_or_default();
 
     let core_events = env::var("COMP_CORE_EVENTS")
         .unwrap_or_else(|_| "false".to_string())
@@ -494,16 +1447,168 @@ fn create_env_file(host_location: &str) -> Result<(), std::io::Error> {
     let event_directory = env::var("COMP_CORE_EVENT_DIR")
         .unwrap_or_else(|_| format!("{}/{}", host_location, "events"))
         .to_lowercase();
+    let data_class = env::var("COMP_DATA_CLASS").unwrap_or_default();
+    let zstd_dictionary = env::var("COMP_ZSTD_DICTIONARY").unwrap_or_default();
+    let delta_cores = env::var("COMP_DELTA_CORES")
+        .unwrap_or_else(|_| "false".to_string())
+        .to_lowercase();
+    let delta_max_bases = env::var("COMP_DELTA_MAX_BASES").unwrap_or_default();
+    let delta_max_base_bytes = env::var("COMP_DELTA_MAX_BASE_BYTES").unwrap_or_default();
+    let proc_snapshot = env::var("COMP_PROC_SNAPSHOT")
+        .unwrap_or_else(|_| "true".to_string())
+        .to_lowercase();
+    let node_info = env::var("COMP_NODE_INFO").unwrap_or_else(|_| "true".to_string());
+    let dmesg_lines = env::var("COMP_DMESG_LINES").unwrap_or_default();
+    let fs_diff = env::var("COMP_FS_DIFF")
+        .unwrap_or_else(|_| "false".to_string())
+        .to_lowercase();
+    let capture_binaries = env::var("COMP_CAPTURE_BINARIES")
+        .unwrap_or_else(|_| "false".to_string())
+        .to_lowercase();
+    let backtrace = env::var("COMP_BACKTRACE")
+        .unwrap_or_else(|_| "false".to_string())
+        .to_lowercase();
+    let core_format = env::var("COMP_CORE_FORMAT").unwrap_or_else(|_| "core".to_string());
+    let encrypt_recipients = env::var("COMP_ENCRYPT_RECIPIENTS").unwrap_or_default();
+    let node_ip = env::var("NODE_IP").unwrap_or_default();
+    let config_file = env::var("COMP_CONFIG_FILE").unwrap_or_default();
+    let log_format = env::var("COMP_LOG_FORMAT").unwrap_or_else(|_| "text".to_string());
+    let log_targets = env::var("COMP_LOG_TARGETS").unwrap_or_else(|_| "file".to_string());
+    let log_identifier = env::var("COMP_LOG_IDENTIFIER").unwrap_or_default();
+    let log_max_bytes = env::var("COMP_LOG_MAX_BYTES").unwrap_or_default();
+    let log_max_age_hours = env::var("COMP_LOG_MAX_AGE_HOURS").unwrap_or_default();
+    let log_max_files = env::var("COMP_LOG_MAX_FILES").unwrap_or_default();
+    let event_format = env::var("COMP_EVENT_FORMAT").unwrap_or_else(|_| "json".to_string());
+    let dump_info_format =
+        env::var("COMP_DUMP_INFO_FORMAT").unwrap_or_else(|_| "native".to_string());
+    let pod_log_files = env::var("COMP_POD_LOG_FILES")
+        .unwrap_or_else(|_| "false".to_string())
+        .to_lowercase();
+    let journal_minutes = env::var("COMP_JOURNAL_MINUTES").unwrap_or_default();
+    let work_dir = env::var("COMP_WORK_DIR").unwrap_or_default();
+    let capture_env = env::var("COMP_CAPTURE_ENV").unwrap_or_else(|_| "off".to_string());
+    let env_mask_patterns = env::var("COMP_ENV_MASK_PATTERNS").unwrap_or_default();
+    let redact_patterns = env::var("COMP_REDACT_PATTERNS").unwrap_or_default();
+    let pause_file = get_pause_file(host_location);
+    let pause_mode = env::var("COMP_PAUSE_MODE").unwrap_or_else(|_| "metadata-only".to_string());
+    let webhook_url = env::var("COMP_WEBHOOK_URL").unwrap_or_default();
+    let webhook_secret = env::var("COMP_WEBHOOK_SECRET").unwrap_or_default();
+    let otlp_endpoint = env::var("COMP_OTLP_ENDPOINT").unwrap_or_default();
+    // The chart passes composer.collectors as JSON, which the composer reads
+    // as YAML.
+    let collectors = env::var("COMP_COLLECTORS").unwrap_or_default();
+    let collectors_file = if collectors.trim().is_empty() || collectors.trim() == "{}" {
+        String::new()
+    } else {
+        let path = format!("{host_location}/{COLLECTORS_FILE}");
+        fs::write(&path, &collectors)?;
+        path
+    };
+    let namespace_allowlist = env::var("COMP_NAMESPACE_ALLOWLIST").unwrap_or_default();
+    let namespace_denylist = env::var("COMP_NAMESPACE_DENYLIST").unwrap_or_default();
+    let exe_filter = env::var("COMP_EXE_FILTER").unwrap_or_default();
+    let signals = env::var("COMP_SIGNALS").unwrap_or_default();
+    let capture_user = env::var("COMP_CAPTURE_USER").unwrap_or_default();
+    let max_dumps_per_hour = env::var("COMP_MAX_DUMPS_PER_HOUR").unwrap_or_default();
+    let max_concurrent_captures = env::var("COMP_MAX_CONCURRENT_CAPTURES").unwrap_or_default();
+    let rate_limit_mode =
+        env::var("COMP_RATE_LIMIT_MODE").unwrap_or_else(|_| "metadata-only".to_string());
+    let unknown_pod = env::var("COMP_UNKNOWN_POD").unwrap_or_else(|_| "capture".to_string());
+    let dedup_window_minutes = env::var("COMP_DEDUP_WINDOW_MINUTES").unwrap_or_default();
+    let container_runtime =
+        env::var("COMP_CONTAINER_RUNTIME").unwrap_or_else(|_| "crictl".to_string());
+    let cri_endpoint = env::var("CRIO_ENDPOINT")
+        .unwrap_or_else(|_| "unix:///run/containerd/containerd.sock".to_string());
+    let docker_endpoint = env::var("COMP_DOCKER_ENDPOINT")
+        .unwrap_or_else(|_| "unix:///var/run/docker.sock".to_string());
+    let container_scope = env::var("COMP_CONTAINER_SCOPE").unwrap_or_else(|_| "pod".to_string());
+    let max_core_bytes = env::var("COMP_MAX_CORE_BYTES").unwrap_or_default();
+    let max_core_mode = env::var("COMP_MAX_CORE_MODE").unwrap_or_else(|_| "truncate".to_string());
+    let disk_reserve_percent =
+        env::var("COMP_DISK_RESERVE_PERCENT").unwrap_or_else(|_| "10".to_string());
+    let keep_spool = env::var("COMP_KEEP_SPOOL")
+        .unwrap_or_else(|_| "false".to_string())
+        .to_lowercase();
+    let sandbox = env::var("COMP_SANDBOX")
+        .unwrap_or_else(|_| "false".to_string())
+        .to_lowercase();
+    let drain_skipped = env::var("COMP_DRAIN_SKIPPED")
+        .unwrap_or_else(|_| "false".to_string())
+        .to_lowercase();
+    let dry_run = env::var("COMP_DRY_RUN")
+        .unwrap_or_else(|_| "false".to_string())
+        .to_lowercase();
     info!("Creating {} file with LOG_LEVEL={}", destination, loglevel);
-    let mut env_file = File::create(destination)?;
+    // Only root and CAPTURE_USER may read it, it holds WEBHOOK_SECRET.
+    let mut env_file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(destination)?;
+    // The mode only applies to a new file.
+    env_file.set_permissions(fs::Permissions::from_mode(0o600))?;
     let text = format!(
-        "LOG_LEVEL={loglevel}\nIGNORE_CRIO={ignore_crio}\nCRIO_IMAGE_CMD={crio_image}\nUSE_CRIO_CONF={use_crio_config}\nFILENAME_TEMPLATE={filename_template}\nLOG_LENGTH={log_length}\nPOD_SELECTOR_LABEL={pod_selector_label}\nTIMEOUT={timeout}\nCOMPRESSION={compression}\nCORE_EVENTS={core_events}\nEVENT_DIRECTORY={event_directory}\n");
-    info!("Writing composer .env \n{}", text);
+        "CDC_CONFIG_FILE={config_file}\nLOG_LEVEL={loglevel}\nLOG_FORMAT={log_format}\nLOG_TARGETS={log_targets}\nLOG_IDENTIFIER={log_identifier}\nLOG_MAX_BYTES={log_max_bytes}\nLOG_MAX_AGE_HOURS={log_max_age_hours}\nLOG_MAX_FILES={log_max_files}\nIGNORE_CRIO={ignore_crio}\nCRIO_IMAGE_CMD={crio_image}\nCONTAINER_RUNTIME={container_runtime}\nCRI_ENDPOINT={cri_endpoint}\nDOCKER_ENDPOINT={docker_endpoint}\nCONTAINER_SCOPE={container_scope}\nUSE_CRIO_CONF={use_crio_config}\nFILENAME_TEMPLATE={filename_template}\nLOG_LENGTH={log_length}\nPOD_LOG_FILES={pod_log_files}\nJOURNAL_MINUTES={journal_minutes}\nWORK_DIR={work_dir}\nCAPTURE_ENV={capture_env}\nENV_MASK_PATTERNS={env_mask_patterns}\nREDACT_PATTERNS='{redact_patterns}'\nPOD_SELECTOR_LABEL='{pod_selector_label}'\nNAMESPACE_ALLOWLIST={namespace_allowlist}\nNAMESPACE_DENYLIST={namespace_denylist}\nEXE_FILTER='{exe_filter}'\nSIGNALS='{signals}'\nDRAIN_SKIPPED={drain_skipped}\nDRY_RUN={dry_run}\nMAX_DUMPS_PER_HOUR={max_dumps_per_hour}\nMAX_CONCURRENT_CAPTURES={max_concurrent_captures}\nRATE_LIMIT_MODE={rate_limit_mode}\nUNKNOWN_POD={unknown_pod}\nDEDUP_WINDOW_MINUTES={dedup_window_minutes}\nMAX_CORE_BYTES={max_core_bytes}\nMAX_CORE_MODE={max_core_mode}\nDISK_RESERVE_PERCENT={disk_reserve_percent}\nTIMEOUT={timeout}\nCOMPRESSION={compression}\nCOMP_ALGO={core_compression}\nCOMP_LEVEL={compression_level}\nCOMP_THREADS={compression_threads}\nCORE_EVENTS={core_events}\nEVENT_DIRECTORY={event_directory}\nDATA_CLASS={data_class}\nZSTD_DICTIONARY={zstd_dictionary}\nDELTA_CORES={delta_cores}\nDELTA_MAX_BASES={delta_max_bases}\nDELTA_MAX_BASE_BYTES={delta_max_base_bytes}\nFS_DIFF={fs_diff}\nPROC_SNAPSHOT={proc_snapshot}\nNODE_INFO={node_info}\nDMESG_LINES={dmesg_lines}\nCAPTURE_BINARIES={capture_binaries}\nBACKTRACE={backtrace}\nCORE_FORMAT={core_format}\nENCRYPT_RECIPIENTS='{encrypt_recipients}'\nNODE_IP={node_ip}\nEVENT_FORMAT={event_format}\nDUMP_INFO_FORMAT={dump_info_format}\nPAUSE_FILE={pause_file}\nPAUSE_MODE={pause_mode}\nWEBHOOK_URL={webhook_url}\nWEBHOOK_SECRET={webhook_secret}\nOTLP_ENDPOINT={otlp_endpoint}\nCOLLECTORS_FILE={collectors_file}\nCAPTURE_USER={capture_user}\nKEEP_SPOOL={keep_spool}\nSANDBOX={sandbox}\n");
+    let logged = if webhook_secret.is_empty() {
+        text.clone()
+    } else {
+        text.replace(
+            &format!("WEBHOOK_SECRET={webhook_secret}"),
+            "WEBHOOK_SECRET=********",
+        )
+    };
+    info!("Writing composer .env \n{}", logged);
     env_file.write_all(text.as_bytes())?;
     env_file.flush()?;
     Ok(())
 }
 
+/// Overrides the composer .env with the fleet wide policy from
+/// POLICY_SOURCE.
+async fn apply_policy(
+    host_location: &str,
+    source: &str,
+    ttl: Duration,
+) -> Result<(), anyhow::Error> {
+    let cache = Path::new(host_location).join(policy::POLICY_CACHE);
+    let overrides = policy::parse(&policy::load(source, &cache, ttl).await?);
+    let destination = format!("{host_location}/.env");
+    let text = policy::apply(&fs::read_to_string(&destination)?, &overrides);
+    info!(
+        "Applying policy from {} to composer .env \n{}",
+        source, text
+    );
+    fs::write(&destination, text)?;
+    Ok(())
+}
+
+fn get_pause_file(host_location: &str) -> String {
+    env::var("COMP_PAUSE_FILE")
+        .ok()
+        .filter(|v| !v.is_empty())
+        .unwrap_or_else(|| format!("{host_location}/{}", pause::DEFAULT_PAUSE_FILE))
+}
+
+/// Trains a zstd dictionary from the metadata files (pod info, logs, ...) of
+/// earlier captures. Point COMP_ZSTD_DICTIONARY at the output to use it.
+fn train_dictionary(samples: &str, output: &str) -> Result<(), anyhow::Error> {
+    if samples.is_empty() {
+        return Err(anyhow!(
+            "train-dictionary requires a directory of sample files"
+        ));
+    }
+    info!("Training zstd dictionary from {} into {}", samples, output);
+    let status = Command::new("zstd")
+        .env("PATH", get_path())
+        .args(["--train", "-r", samples, "-o", output])
+        .status()?;
+    if !status.success() {
+        return Err(anyhow!("zstd --train failed with {}", status));
+    }
+    Ok(())
+}
+
 fn get_path() -> String {
     let mut local_bin = env::var("LOCAL_BIN").unwrap_or_else(|_| "".to_string());
     local_bin.push(':');
@@ -511,6 +1616,48 @@ fn get_path() -> String {
     local_bin
 }
 
+/// The pipes the kernel hands out at once. With MAX_CONCURRENT_CAPTURES it
+/// matches the composer's slots, crashes over it are skipped by the kernel
+/// rather than piling up composers.
+fn core_pipe_limit(max_concurrent_captures: &str) -> String {
+    match max_concurrent_captures.parse::<u32>() {
+        Ok(max) if max > 0 => max.to_string(),
+        _ => DEFAULT_CORE_PIPE_LIMIT.to_string(),
+    }
+}
+
+/// Records the settings the node ended up with, read back from the kernel.
+fn write_startup_report(
+    host_location: &str,
+    max_concurrent_captures: &str,
+) -> Result<(), anyhow::Error> {
+    let started = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs();
+    let slots = max_concurrent_captures
+        .parse::<u32>()
+        .ok()
+        .filter(|max| *max > 0);
+    let report = serde_json::json!({
+        "agent_version": env!("CARGO_PKG_VERSION"),
+        "started": started,
+        "core_pattern": get_sysctl("kernel.core_pattern")?,
+        "core_pipe_limit": get_sysctl("kernel.core_pipe_limit")?,
+        "suid_dumpable": get_sysctl("fs.suid_dumpable")?,
+        "max_concurrent_captures": slots,
+    });
+    info!(
+        "Capture limits: kernel.core_pipe_limit {}, MAX_CONCURRENT_CAPTURES {}",
+        report["core_pipe_limit"].as_str().unwrap_or_default(),
+        slots.map_or("unlimited".to_string(), |s| s.to_string())
+    );
+    fs::write(
+        format!("{host_location}/{STARTUP_REPORT}"),
+        serde_json::to_string_pretty(&report)?,
+    )?;
+    Ok(())
+}
+
 fn get_sysctl(name: &str) -> Result<String, anyhow::Error> {
     info!("Getting sysctl for {}", name);
     let output = Command::new("sysctl")
@@ -563,9 +1710,13 @@ fn remove() -> Result<(), anyhow::Error> {
     let crictl_file = format!("{host_dir}/crictl.yaml");
     let composer_file = format!("{host_dir}/composer.log");
     let crictl_exe = format!("{host_dir}/crictl");
+    let pod_cache = format!("{host_dir}/{}", podcache::POD_CACHE_FILE);
 
     fs::remove_file(exe)?;
     fs::remove_file(env_file)?;
+    // A cache left behind would go stale and be ignored by a later install.
+    let _ = fs::remove_file(pod_cache);
+    let _ = fs::remove_file(format!("{host_dir}/{STARTUP_REPORT}"));
 
     if !Path::new(&crictl_exe).exists() {
         fs::remove_file(crictl_exe)?;
@@ -589,4 +1740,4 @@ fn restore_sysctl(prefix: &str, name: &str) -> Result<(), anyhow::Error> {
     fs::remove_file(file_name)?;
 
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,50 @@
+use log::info;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+pub const DEFAULT_PAUSE_FILE: &str = "pause";
+
+/// Mirrors the presence of `source`, typically a key of a ConfigMap mounted
+/// into the agent, to the pause file the composer checks on the host.
+/// Returns whether capture is paused.
+pub fn sync(source: &Path, pause_file: &Path) -> io::Result<bool> {
+    let paused = source.exists();
+    match (paused, pause_file.exists()) {
+        (true, false) => {
+            info!("{} present, pausing core capture", source.display());
+            fs::write(pause_file, "")?;
+        }
+        (false, true) => {
+            info!("{} removed, resuming core capture", source.display());
+            fs::remove_file(pause_file)?;
+        }
+        _ => {}
+    }
+    Ok(paused)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pause::sync;
+    use std::fs;
+    use uuid::Uuid;
+
+    #[test]
+    fn sync_test() {
+        let dir = std::env::temp_dir().join(format!("pause-test-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("paused");
+        let pause_file = dir.join("pause");
+
+        assert!(!sync(&source, &pause_file).unwrap());
+        assert!(!pause_file.exists());
+        fs::write(&source, "true").unwrap();
+        assert!(sync(&source, &pause_file).unwrap());
+        assert!(pause_file.exists());
+        fs::remove_file(&source).unwrap();
+        assert!(!sync(&source, &pause_file).unwrap());
+        assert!(!pause_file.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,107 @@
+//! Keeps a map of the node's running containers to their crictl pod in
+//! `pods.cache.json` in the host directory, so the composer can name the
+//! crashing pod without running crictl while the kernel waits on it.
+//! Enabled by POD_CACHE_INTERVAL, which needs the container runtime socket
+//! mounted into the agent.
+
+use anyhow::anyhow;
+use serde_json::{json, Map, Value};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+pub const POD_CACHE_FILE: &str = "pods.cache.json";
+/// Refreshes the composer may miss before it stops trusting the cache.
+pub const MAX_AGE_INTERVALS: u64 = 3;
+
+/// The cache from the output of `crictl pods -o json` and
+/// `crictl ps -o json`. A container whose pod isn't listed is left out.
+pub fn build(pods: &Value, containers: &Value, refreshed: u64, max_age: u64) -> Value {
+    let mut by_id = Map::new();
+    for pod in pods["items"].as_array().into_iter().flatten() {
+        if let Some(id) = pod["id"].as_str() {
+            by_id.insert(id.to_string(), pod.clone());
+        }
+    }
+    let mut container_pods = Map::new();
+    for container in containers["containers"].as_array().into_iter().flatten() {
+        if let (Some(id), Some(pod_id)) =
+            (container["id"].as_str(), container["podSandboxId"].as_str())
+        {
+            if by_id.contains_key(pod_id) {
+                container_pods.insert(id.to_string(), json!(pod_id));
+            }
+        }
+    }
+    json!({
+        "refreshed": refreshed,
+        "max_age": max_age,
+        "containers": container_pods,
+        "pods": by_id,
+    })
+}
+
+fn crictl(crictl: &str, endpoint: &str, args: &[&str]) -> Result<Value, anyhow::Error> {
+    let output = Command::new(crictl)
+        .arg("--runtime-endpoint")
+        .arg(endpoint)
+        .args(args)
+        .output()
+        .map_err(|e| anyhow!("starting {}: {}", crictl, e))?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "crictl {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(serde_json::from_slice(&output.stdout)?)
+}
+
+/// Lists the pods and containers and replaces the cache. Returns the number
+/// of containers cached.
+pub fn refresh(
+    crictl_path: &str,
+    endpoint: &str,
+    host_dir: &Path,
+    max_age: u64,
+) -> Result<usize, anyhow::Error> {
+    let pods = crictl(crictl_path, endpoint, &["pods", "-o", "json"])?;
+    let containers = crictl(crictl_path, endpoint, &["ps", "-o", "json"])?;
+    let refreshed = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs();
+    let cache = build(&pods, &containers, refreshed, max_age);
+    let cached = cache["containers"].as_object().map_or(0, |c| c.len());
+    // The composer can read it at any time so it is replaced, not rewritten.
+    let tmp = host_dir.join(format!("{POD_CACHE_FILE}.tmp"));
+    fs::write(&tmp, cache.to_string())?;
+    fs::rename(&tmp, host_dir.join(POD_CACHE_FILE))?;
+    Ok(cached)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::podcache::build;
+    use serde_json::json;
+
+    #[test]
+    fn build_test() {
+        let pods = json!({"items": [
+            {"id": "p1", "metadata": {"name": "mo-0", "namespace": "mo"}},
+            {"id": "p2", "metadata": {"name": "mo-1", "namespace": "mo"}},
+        ]});
+        let containers = json!({"containers": [
+            {"id": "c1", "podSandboxId": "p1"},
+            {"id": "c2", "podSandboxId": "p2"},
+            {"id": "c3", "podSandboxId": "gone"},
+        ]});
+        let cache = build(&pods, &containers, 1706263200, 30);
+        assert_eq!(cache["refreshed"], 1706263200);
+        assert_eq!(cache["max_age"], 30);
+        assert_eq!(cache["containers"].as_object().unwrap().len(), 2);
+        assert_eq!(cache["containers"]["c2"], "p2");
+        assert_eq!(cache["pods"]["p1"]["metadata"]["name"], "mo-0");
+        assert_eq!(build(&json!({}), &json!({}), 0, 30)["pods"], json!({}));
+    }
+}
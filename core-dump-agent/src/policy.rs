@@ -0,0 +1,137 @@
+use anyhow::anyhow;
+use log::warn;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+pub const POLICY_CACHE: &str = "policy.cache";
+pub const DEFAULT_POLICY_TTL: u64 = 300;
+
+/// Reads the policy from an http(s) URL or a file, e.g. a key of a mounted
+/// ConfigMap.
+pub async fn fetch(source: &str) -> Result<String, anyhow::Error> {
+    if source.starts_with("http://") || source.starts_with("https://") {
+        let response = reqwest::get(source).await?;
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "Fetching {} returned {}",
+                source,
+                response.status()
+            ));
+        }
+        Ok(response.text().await?)
+    } else {
+        Ok(fs::read_to_string(source)?)
+    }
+}
+
+fn is_fresh(cache: &Path, ttl: Duration) -> bool {
+    fs::metadata(cache)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .map(|age| age < ttl)
+        .unwrap_or(false)
+}
+
+/// Returns the cached policy while it is younger than `ttl`, otherwise
+/// fetches it again. A stale cache is used when the source can't be reached
+/// so a node keeps the last policy it saw.
+pub async fn load(source: &str, cache: &Path, ttl: Duration) -> Result<String, anyhow::Error> {
+    if is_fresh(cache, ttl) {
+        return Ok(fs::read_to_string(cache)?);
+    }
+    match fetch(source).await {
+        Ok(policy) => {
+            fs::write(cache, &policy)?;
+            Ok(policy)
+        }
+        Err(e) => match fs::read_to_string(cache) {
+            Ok(policy) => {
+                warn!("Using cached policy, fetching {} failed: {}", source, e);
+                Ok(policy)
+            }
+            Err(_) => Err(e),
+        },
+    }
+}
+
+/// The policy uses the composer .env format, one KEY=VALUE per line.
+pub fn parse(policy: &str) -> Vec<(String, String)> {
+    policy
+        .lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .filter_map(|l| l.split_once('='))
+        .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+        .filter(|(k, _)| !k.is_empty())
+        .collect()
+}
+
+/// Overrides the matching lines of a composer .env and appends the keys it
+/// does not set.
+pub fn apply(env_file: &str, overrides: &[(String, String)]) -> String {
+    let mut lines: Vec<String> = env_file.lines().map(|l| l.to_string()).collect();
+    for (key, value) in overrides {
+        let line = format!("{key}={value}");
+        match lines
+            .iter_mut()
+            .find(|l| l.split_once('=').map(|(k, _)| k == key).unwrap_or(false))
+        {
+            Some(existing) => *existing = line,
+            None => lines.push(line),
+        }
+    }
+    let mut text = lines.join("\n");
+    text.push('\n');
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::policy::{apply, load, parse};
+    use std::fs;
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    #[test]
+    fn apply_test() {
+        let overrides =
+            parse("# incident 4711\nPOD_SELECTOR_LABEL=mo-capture\n\nPAUSE_MODE = skip\nbogus\n");
+        assert_eq!(overrides.len(), 2);
+        let env_file = "LOG_LEVEL=debug\nPOD_SELECTOR_LABEL=\nTIMEOUT=600\n";
+        assert_eq!(
+            apply(env_file, &overrides),
+            "LOG_LEVEL=debug\nPOD_SELECTOR_LABEL=mo-capture\nTIMEOUT=600\nPAUSE_MODE=skip\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn load_test() {
+        let dir = std::env::temp_dir().join(format!("policy-test-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("policy.env");
+        let cache = dir.join("policy.cache");
+        let source_str = source.to_str().unwrap();
+        fs::write(&source, "TIMEOUT=60\n").unwrap();
+
+        let ttl = Duration::from_secs(300);
+        assert_eq!(load(source_str, &cache, ttl).await.unwrap(), "TIMEOUT=60\n");
+        // Served from the cache until the ttl expires.
+        fs::write(&source, "TIMEOUT=30\n").unwrap();
+        assert_eq!(load(source_str, &cache, ttl).await.unwrap(), "TIMEOUT=60\n");
+        assert_eq!(
+            load(source_str, &cache, Duration::ZERO).await.unwrap(),
+            "TIMEOUT=30\n"
+        );
+        // A stale cache is better than no policy.
+        fs::remove_file(&source).unwrap();
+        assert_eq!(
+            load(source_str, &cache, Duration::ZERO).await.unwrap(),
+            "TIMEOUT=30\n"
+        );
+        fs::remove_file(&cache).unwrap();
+        assert!(load(source_str, &cache, Duration::ZERO).await.is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
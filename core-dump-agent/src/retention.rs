@@ -0,0 +1,341 @@
+//! Removes the dumps of deleted namespaces, enabled with
+//! NAMESPACE_GC_GRACE_MINUTES, so offboarding a tenant also removes the
+//! memory of its processes.
+//!
+//! The agent watches the namespaces with its service account. A namespace
+//! that goes away is noted in `namespace-retention.json` in the host
+//! directory, and once it has been gone for the grace period its archives
+//! on the node, their queued copies and the copies the catalog places in
+//! backends are deleted. A namespace that comes back in time is forgotten.
+//! Each agent only removes what its own node captured.
+
+use crate::catalog::Catalog;
+use crate::kube::{self, InCluster};
+use crate::storage::Backends;
+use advisory_lock::{AdvisoryFileLock, FileLockMode};
+use anyhow::anyhow;
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub const RETENTION_FILE: &str = "namespace-retention.json";
+const WATCH_TIMEOUT_SECS: u64 = 300;
+/// How often namespaces that ran out of grace are looked at.
+const PURGE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The namespaces seen gone, with when, in seconds since the epoch.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub struct Pending {
+    deleted: BTreeMap<String, u64>,
+}
+
+impl Pending {
+    /// An unreadable file starts over, the next list finds the namespaces
+    /// again.
+    pub fn load(path: &Path) -> Pending {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|c| serde_json::from_str(&c).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), anyhow::Error> {
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_string(self)?)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// Keeps the first time a namespace was seen gone.
+    pub fn gone(&mut self, namespace: &str, now: u64) {
+        self.deleted.entry(namespace.to_string()).or_insert(now);
+    }
+
+    pub fn back(&mut self, namespace: &str) -> bool {
+        self.deleted.remove(namespace).is_some()
+    }
+
+    /// The namespaces gone for `grace` seconds.
+    pub fn due(&self, now: u64, grace: u64) -> Vec<String> {
+        self.deleted
+            .iter()
+            .filter(|(_, gone)| **gone + grace <= now)
+            .map(|(ns, _)| ns.clone())
+            .collect()
+    }
+}
+
+/// The names in a NamespaceList.
+fn names(list: &Value) -> BTreeSet<String> {
+    list["items"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|n| n["metadata"]["name"].as_str())
+        .map(str::to_string)
+        .collect()
+}
+
+/// The type, namespace and resourceVersion of a line of the watch.
+fn watch_event(line: &[u8]) -> Option<(String, String, String)> {
+    let event: Value = serde_json::from_slice(line).ok()?;
+    let metadata = &event["object"]["metadata"];
+    Some((
+        event["type"].as_str()?.to_string(),
+        metadata["name"].as_str().unwrap_or_default().to_string(),
+        metadata["resourceVersion"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string(),
+    ))
+}
+
+fn namespace_of(archive: &Path) -> Option<String> {
+    let dump_info = crate::archive::read_dump_info(archive).ok()?;
+    dump_info["namespace"]
+        .as_str()
+        .filter(|n| !n.is_empty() && *n != "unknown")
+        .map(str::to_string)
+}
+
+pub struct Retention {
+    /// Seconds a namespace has to be gone.
+    pub grace: u64,
+    pub core_dir: PathBuf,
+    pub backends: Backends,
+}
+
+impl Retention {
+    fn state_file(&self) -> PathBuf {
+        self.backends.host_dir.join(RETENTION_FILE)
+    }
+
+    /// The archives waiting on the node, in the core directory and the
+    /// mirror queue.
+    fn local_archives(&self) -> Vec<PathBuf> {
+        let mut archives: Vec<PathBuf> = fs::read_dir(&self.core_dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.is_file())
+            .filter(|p| {
+                !p.to_str()
+                    .is_some_and(|p| p.ends_with(crate::archive::DUMP_INFO_SUFFIX))
+            })
+            .collect();
+        archives.extend(self.backends.queued().into_iter().map(|(_, p)| p));
+        archives
+    }
+
+    /// Every namespace the node holds dumps of.
+    fn namespaces(&self) -> BTreeSet<String> {
+        let mut namespaces = Catalog::new(&self.backends.host_dir).namespaces();
+        namespaces.extend(self.local_archives().iter().filter_map(|a| namespace_of(a)));
+        namespaces
+    }
+
+    /// Deletes the dumps of `namespace` and returns how many there were.
+    /// Anything that fails is left for the next attempt.
+    async fn purge(&self, namespace: &str) -> Result<usize, anyhow::Error> {
+        let mut removed = 0;
+        for archive in self.local_archives() {
+            if namespace_of(&archive).as_deref() != Some(namespace) {
+                continue;
+            }
+            // Not while the composer writes it or an upload reads it.
+            let file = File::open(&archive)?;
+            AdvisoryFileLock::try_lock(&file, FileLockMode::Exclusive)
+                .map_err(|e| anyhow!("{} is busy: {}", archive.display(), e))?;
+            fs::remove_file(&archive)?;
+            let dump_info = crate::archive::dump_info_path(&archive);
+            if dump_info.exists() {
+                fs::remove_file(&dump_info)?;
+            }
+            info!("Removed {} of namespace {}", archive.display(), namespace);
+            removed += 1;
+        }
+        let catalog = Catalog::new(&self.backends.host_dir);
+        for entry in catalog.stored_in(namespace) {
+            let Some(backend) = self
+                .backends
+                .backends
+                .iter()
+                .find(|b| b.name == entry.backend)
+            else {
+                warn!(
+                    "{} of namespace {} is in {}, which is no longer configured",
+                    entry.archive, namespace, entry.backend
+                );
+                continue;
+            };
+            let Some(key) = &entry.key else {
+                warn!(
+                    "{} of namespace {} was recorded without its key, remove it from {} by hand",
+                    entry.archive, namespace, entry.backend
+                );
+                continue;
+            };
+            let code = backend.store.delete(key).await?;
+            catalog.record_deleted(&entry)?;
+            info!(
+                "Deleted {} of namespace {} from {}: {}",
+                key, namespace, backend.name, code
+            );
+            removed += 1;
+        }
+        Ok(removed)
+    }
+
+    async fn purge_due(&self, pending: &mut Pending) {
+        for namespace in pending.due(kube::now(), self.grace) {
+            match self.purge(&namespace).await {
+                Ok(removed) => {
+                    info!(
+                        "Namespace {} is gone, removed its {} dumps",
+                        namespace, removed
+                    );
+                    pending.back(&namespace);
+                }
+                Err(e) => error!("Removing the dumps of {} failed: {}", namespace, e),
+            }
+        }
+        if let Err(e) = pending.save(&self.state_file()) {
+            error!("Saving {} failed: {}", RETENTION_FILE, e);
+        }
+    }
+
+    /// Lists the namespaces, so ones deleted while the agent was away are
+    /// noticed too, and watches them until the watch fails.
+    async fn follow(&self, cluster: &InCluster) -> Result<(), anyhow::Error> {
+        let list: Value =
+            serde_json::from_str(&cluster.get("/api/v1/namespaces").await?.text().await?)?;
+        let live = names(&list);
+        let mut pending = Pending::load(&self.state_file());
+        pending.deleted.retain(|ns, _| !live.contains(ns));
+        for namespace in self.namespaces().difference(&live) {
+            pending.gone(namespace, kube::now());
+        }
+        self.purge_due(&mut pending).await;
+
+        let mut version = list["metadata"]["resourceVersion"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        loop {
+            let mut response = cluster
+                .get(&format!(
+                    "/api/v1/namespaces?watch=true&allowWatchBookmarks=true&timeoutSeconds={WATCH_TIMEOUT_SECS}&resourceVersion={version}"
+                ))
+                .await?;
+            let mut buf = vec![];
+            loop {
+                let chunk = match tokio::time::timeout(PURGE_INTERVAL, response.chunk()).await {
+                    Ok(chunk) => chunk?,
+                    Err(_) => {
+                        self.purge_due(&mut pending).await;
+                        continue;
+                    }
+                };
+                let Some(chunk) = chunk else {
+                    break;
+                };
+                buf.extend_from_slice(&chunk);
+                while let Some(end) = buf.iter().position(|b| *b == b'\n') {
+                    let line: Vec<u8> = buf.drain(..=end).collect();
+                    let Some((kind, namespace, resource_version)) = watch_event(&line) else {
+                        continue;
+                    };
+                    match kind.as_str() {
+                        "DELETED" => {
+                            debug!("Namespace {} was deleted", namespace);
+                            pending.gone(&namespace, kube::now());
+                        }
+                        "ADDED" | "MODIFIED" if pending.back(&namespace) => {
+                            info!("Namespace {} is back, keeping its dumps", namespace);
+                        }
+                        // Mostly 410 Gone, the version is too old to watch from.
+                        "ERROR" => {
+                            let status = String::from_utf8_lossy(&line).trim().to_string();
+                            return Err(anyhow!("Watch ended with {}", status));
+                        }
+                        _ => {}
+                    }
+                    if !resource_version.is_empty() {
+                        version = resource_version;
+                    }
+                }
+            }
+            self.purge_due(&mut pending).await;
+        }
+    }
+
+    pub async fn run(self) {
+        info!(
+            "Removing the dumps of deleted namespaces after {}s",
+            self.grace
+        );
+        loop {
+            let followed = match InCluster::from_env() {
+                Ok(cluster) => self.follow(&cluster).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = followed {
+                warn!("Watching namespaces failed, listing again: {}", e);
+            }
+            tokio::time::sleep(Duration::from_secs(10)).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::retention::{names, watch_event, Pending};
+    use serde_json::json;
+
+    #[test]
+    fn pending_test() {
+        let path = std::env::temp_dir().join(format!("retention-{}.json", uuid::Uuid::new_v4()));
+        let mut pending = Pending::load(&path);
+        pending.gone("tenant-a", 1000);
+        pending.gone("tenant-b", 1500);
+        pending.gone("tenant-a", 1600);
+        assert_eq!(pending.due(1599, 600), Vec::<String>::new());
+        assert_eq!(pending.due(1600, 600), vec!["tenant-a"]);
+        assert!(pending.back("tenant-b"));
+        assert!(!pending.back("tenant-c"));
+        pending.save(&path).unwrap();
+        assert_eq!(Pending::load(&path), pending);
+        std::fs::write(&path, "garbage").unwrap();
+        assert_eq!(Pending::load(&path), Pending::default());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn watch_test() {
+        let list = json!({
+            "metadata": {"resourceVersion": "41"},
+            "items": [{"metadata": {"name": "default"}}, {"metadata": {"name": "mo"}}]
+        });
+        assert_eq!(
+            names(&list).into_iter().collect::<Vec<_>>(),
+            ["default", "mo"]
+        );
+        let line = br#"{"type":"DELETED","object":{"kind":"Namespace","metadata":{"name":"tenant-a","resourceVersion":"42"}}}"#;
+        assert_eq!(
+            watch_event(line),
+            Some((
+                "DELETED".to_string(),
+                "tenant-a".to_string(),
+                "42".to_string()
+            ))
+        );
+        let expired = br#"{"type":"ERROR","object":{"kind":"Status","code":410}}"#;
+        assert_eq!(watch_event(expired).unwrap().0, "ERROR");
+        assert_eq!(watch_event(b"{"), None);
+    }
+}
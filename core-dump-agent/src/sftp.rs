@@ -0,0 +1,331 @@
+//! SFTP drop destination, selected with `STORAGE_BACKEND=sftp`.
+//!
+//! The OpenSSH `sftp` client runs in batch mode with key authentication.
+//! Archives are written under a temporary `.<name>.part` name and renamed
+//! once complete, so the drop never shows a partial archive, and failed
+//! attempts remove what they left behind before they are retried.
+
+use anyhow::anyhow;
+use log::{info, warn};
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use uuid::Uuid;
+
+const DEFAULT_RETRIES: u32 = 3;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SftpTarget {
+    pub host: String,
+    pub port: u16,
+    pub user: String,
+    pub key_file: String,
+    /// Empty accepts and remembers unknown host keys.
+    pub known_hosts: String,
+    /// Where archives go, with `{namespace}`, `{podname}`, `{hostname}`,
+    /// `{node}` and `{date}` filled in from dump-info.
+    pub remote_dir: String,
+    pub retries: u32,
+}
+
+/// Days since the epoch to a `YYYY-MM-DD` date.
+pub fn civil_date(secs: u64) -> String {
+    let days = (secs / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// Quotes a path for an sftp batch command.
+fn quote(path: &str) -> String {
+    format!("\"{}\"", path.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// `-mkdir` for every parent of `dir`, sftp doesn't create them itself.
+/// The leading `-` lets the batch carry on when one exists.
+fn mkdirs(dir: &str) -> String {
+    let mut script = String::new();
+    let mut path = String::new();
+    for part in dir.split('/').filter(|p| !p.is_empty() && *p != ".") {
+        if !path.is_empty() || dir.starts_with('/') {
+            path.push('/');
+        }
+        path.push_str(part);
+        script.push_str(&format!("-mkdir {}\n", quote(&path)));
+    }
+    script
+}
+
+fn split(key: &str) -> (&str, &str) {
+    match key.rsplit_once('/') {
+        Some((dir, name)) => (dir, name),
+        None => ("", key),
+    }
+}
+
+fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else {
+        format!("{dir}/{name}")
+    }
+}
+
+impl SftpTarget {
+    /// Builds the target from the `{prefix}_*` env vars: `HOST`, `PORT`,
+    /// `USER`, `KEY_FILE`, `KNOWN_HOSTS`, `REMOTE_DIR` and `RETRIES`.
+    pub fn from_vars<F: Fn(&str) -> String>(var: F) -> Result<SftpTarget, anyhow::Error> {
+        let host = var("HOST");
+        if host.is_empty() {
+            return Err(anyhow!("No sftp host in HOST"));
+        }
+        let key_file = var("KEY_FILE");
+        if key_file.is_empty() {
+            return Err(anyhow!("No private key in KEY_FILE"));
+        }
+        let port = match var("PORT") {
+            v if v.is_empty() => 22,
+            v => v
+                .parse()
+                .map_err(|e| anyhow!("Invalid PORT {}: {}", v, e))?,
+        };
+        let retries = match var("RETRIES") {
+            v if v.is_empty() => DEFAULT_RETRIES,
+            v => v
+                .parse()
+                .map_err(|e| anyhow!("Invalid RETRIES {}: {}", v, e))?,
+        };
+        let remote_dir = match var("REMOTE_DIR") {
+            v if v.is_empty() => ".".to_string(),
+            v => v.trim_end_matches('/').to_string(),
+        };
+        Ok(SftpTarget {
+            host,
+            port,
+            user: var("USER"),
+            key_file,
+            known_hosts: var("KNOWN_HOSTS"),
+            remote_dir,
+            retries,
+        })
+    }
+
+    /// The remote path of an archive, its directory rendered from the
+    /// archive's dump-info.
+    pub fn remote_path(&self, name: &str, dump_info: Option<&Value>) -> String {
+        let field = |key: &str| {
+            dump_info
+                .and_then(|d| d[key].as_str())
+                .filter(|v| !v.is_empty())
+                .unwrap_or("unknown")
+                .replace('/', "_")
+        };
+        let date = dump_info
+            .and_then(|d| d["timestamp"].as_str())
+            .and_then(|t| t.parse::<u64>().ok())
+            .map(civil_date)
+            .unwrap_or_else(|| "unknown".to_string());
+        let dir = self
+            .remote_dir
+            .replace("{namespace}", &field("namespace"))
+            .replace("{podname}", &field("podname"))
+            .replace("{hostname}", &field("hostname"))
+            .replace("{node}", &field("node"))
+            .replace("{date}", &date);
+        join(&dir, name)
+    }
+
+    pub fn args(&self) -> Vec<String> {
+        let mut args = vec![
+            "-b".to_string(),
+            "-".to_string(),
+            "-P".to_string(),
+            self.port.to_string(),
+            "-i".to_string(),
+            self.key_file.clone(),
+            "-o".to_string(),
+            "BatchMode=yes".to_string(),
+        ];
+        if self.known_hosts.is_empty() {
+            args.extend([
+                "-o".to_string(),
+                "StrictHostKeyChecking=accept-new".to_string(),
+            ]);
+        } else {
+            args.extend([
+                "-o".to_string(),
+                format!("UserKnownHostsFile={}", self.known_hosts),
+            ]);
+        }
+        args.push(if self.user.is_empty() {
+            self.host.clone()
+        } else {
+            format!("{}@{}", self.user, self.host)
+        });
+        args
+    }
+
+    /// Runs a batch script. sftp stops at the first failing command that
+    /// isn't prefixed with `-`.
+    async fn run(&self, script: String) -> Result<(), anyhow::Error> {
+        let args = self.args();
+        let output = tokio::task::spawn_blocking(move || {
+            let mut child = Command::new("sftp")
+                .args(&args)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()?;
+            if let Some(mut stdin) = child.stdin.take() {
+                std::io::Write::write_all(&mut stdin, script.as_bytes())?;
+            }
+            child.wait_with_output()
+        })
+        .await??;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "sftp to {} exited with {}: {}",
+                self.host,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Uploads `local` as `.<name>.part` and renames it to `key`.
+    pub fn upload_script(local: &Path, key: &str) -> String {
+        let (dir, name) = split(key);
+        let part = join(dir, &format!(".{name}.part"));
+        format!(
+            "{}put {} {}\n-rm {}\nrename {} {}\n",
+            mkdirs(dir),
+            quote(&local.to_string_lossy()),
+            quote(&part),
+            quote(key),
+            quote(&part),
+            quote(key)
+        )
+    }
+
+    /// Uploads the file at `path` to `key`, retrying with a growing delay.
+    /// The partial file of a failed attempt is removed.
+    pub async fn put_file(&self, path: &Path, key: &str) -> Result<u16, anyhow::Error> {
+        let (dir, name) = split(key);
+        let part = join(dir, &format!(".{name}.part"));
+        let mut attempt = 0;
+        loop {
+            match self.run(SftpTarget::upload_script(path, key)).await {
+                Ok(_) => return Ok(200),
+                Err(e) if attempt < self.retries => {
+                    attempt += 1;
+                    warn!("Upload of {} failed, retry {}: {}", key, attempt, e);
+                    if let Err(e) = self.run(format!("-rm {}\n", quote(&part))).await {
+                        warn!("Removing {} failed: {}", part, e);
+                    }
+                    tokio::time::sleep(Duration::from_secs(1 << attempt)).await;
+                }
+                Err(e) => {
+                    let _ = self.run(format!("-rm {}\n", quote(&part))).await;
+                    return Err(anyhow!("Upload of {} failed: {}", key, e));
+                }
+            }
+        }
+    }
+
+    /// Writes a small file in one attempt, used by the health probe.
+    pub async fn put(&self, key: &str, data: &[u8]) -> Result<u16, anyhow::Error> {
+        let local = std::env::temp_dir().join(format!("sftp-{}", Uuid::new_v4()));
+        fs::write(&local, data)?;
+        let result = self.run(SftpTarget::upload_script(&local, key)).await;
+        let _ = fs::remove_file(&local);
+        result.map(|_| 200)
+    }
+
+    /// Removes `key`, ignoring a file that is already gone.
+    pub async fn delete(&self, key: &str) -> Result<u16, anyhow::Error> {
+        self.run(format!("-rm {}\n", quote(key))).await?;
+        info!("Removed {} from {}", key, self.host);
+        Ok(200)
+    }
+
+    /// Downloads `key` and streams it into `writer`.
+    pub async fn get_stream<W: AsyncWrite + Unpin>(
+        &self,
+        key: &str,
+        writer: &mut W,
+    ) -> Result<u16, anyhow::Error> {
+        let local = std::env::temp_dir().join(format!("sftp-{}", Uuid::new_v4()));
+        let script = format!("get {} {}\n", quote(key), quote(&local.to_string_lossy()));
+        let result = self.run(script).await;
+        let data = result.and_then(|_| Ok(fs::read(&local)?));
+        let _ = fs::remove_file(&local);
+        writer.write_all(&data?).await?;
+        writer.flush().await?;
+        info!("Fetched {} from {}", key, self.host);
+        Ok(200)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sftp::{civil_date, SftpTarget};
+    use serde_json::json;
+    use std::path::Path;
+
+    fn target() -> SftpTarget {
+        SftpTarget::from_vars(|name| match name {
+            "HOST" => "drop.mo.local".to_string(),
+            "USER" => "cores".to_string(),
+            "KEY_FILE" => "/etc/core-dump-handler/sftp/id_ed25519".to_string(),
+            "REMOTE_DIR" => "/incoming/{namespace}/{date}/".to_string(),
+            _ => String::new(),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn remote_path_test() {
+        assert_eq!(civil_date(0), "1970-01-01");
+        assert_eq!(civil_date(1588462466), "2020-05-02");
+        assert_eq!(civil_date(951782400), "2000-02-29");
+        let target = target();
+        assert_eq!(target.port, 22);
+        assert_eq!(target.retries, 3);
+        let dump_info = json!({"namespace": "mo", "timestamp": "1588462466"});
+        assert_eq!(
+            target.remote_path("a.tar", Some(&dump_info)),
+            "/incoming/mo/2020-05-02/a.tar"
+        );
+        assert_eq!(
+            target.remote_path("a.tar", None),
+            "/incoming/unknown/unknown/a.tar"
+        );
+        assert!(SftpTarget::from_vars(|_| String::new()).is_err());
+    }
+
+    #[test]
+    fn script_test() {
+        let target = target();
+        let args = target.args();
+        assert_eq!(args.last().unwrap(), "cores@drop.mo.local");
+        assert!(args.contains(&"StrictHostKeyChecking=accept-new".to_string()));
+        assert_eq!(
+            SftpTarget::upload_script(Path::new("/cores/a.tar"), "/incoming/mo/a.tar"),
+            "-mkdir \"/incoming\"\n-mkdir \"/incoming/mo\"\nput \"/cores/a.tar\" \"/incoming/mo/.a.tar.part\"\n-rm \"/incoming/mo/a.tar\"\nrename \"/incoming/mo/.a.tar.part\" \"/incoming/mo/a.tar\"\n"
+        );
+        assert_eq!(
+            SftpTarget::upload_script(Path::new("a.tar"), "a.tar"),
+            "put \"a.tar\" \".a.tar.part\"\n-rm \"a.tar\"\nrename \".a.tar.part\" \"a.tar\"\n"
+        );
+    }
+}
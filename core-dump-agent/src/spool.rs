@@ -0,0 +1,85 @@
+use log::{info, warn};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// The directory in HOST_DIR the composer spools events to while the event
+/// directory is unwritable, see core-dump-composer/src/events.rs.
+pub const EVENT_SPOOL_DIR: &str = "event-spool";
+
+/// Moves spooled events into `event_dir`. Events that can't be moved stay
+/// in the spool for the next attempt. Returns how many were moved.
+pub fn drain(spool: &Path, event_dir: &Path) -> io::Result<usize> {
+    let entries = match fs::read_dir(spool) {
+        Ok(v) => v,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    let mut moved = 0;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let name = entry.file_name();
+        // Partially written events are dot files.
+        if !path.is_file() || name.to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let target = event_dir.join(&name);
+        let result = fs::rename(&path, &target).or_else(|_| {
+            // The event directory may be another volume.
+            fs::copy(&path, &target)?;
+            fs::remove_file(&path)
+        });
+        match result {
+            Ok(_) => moved += 1,
+            Err(e) => {
+                warn!(
+                    "Moving {} to {} failed: {}",
+                    path.display(),
+                    event_dir.display(),
+                    e
+                );
+                break;
+            }
+        }
+    }
+    if moved > 0 {
+        info!(
+            "Delivered {} spooled events to {}",
+            moved,
+            event_dir.display()
+        );
+    }
+    Ok(moved)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::spool::drain;
+    use std::fs;
+    use uuid::Uuid;
+
+    #[test]
+    fn drain_test() {
+        let dir = std::env::temp_dir().join(format!("spool-test-{}", Uuid::new_v4()));
+        let spool = dir.join("event-spool");
+        let events = dir.join("events");
+        assert_eq!(drain(&spool, &events).unwrap(), 0);
+        fs::create_dir_all(&spool).unwrap();
+        fs::write(spool.join("a-event.json"), "{}").unwrap();
+        fs::write(spool.join(".b-event.json.tmp"), "{").unwrap();
+
+        // The event directory is still missing.
+        assert_eq!(drain(&spool, &events).unwrap(), 0);
+        assert!(spool.join("a-event.json").exists());
+
+        fs::create_dir_all(&events).unwrap();
+        assert_eq!(drain(&spool, &events).unwrap(), 1);
+        assert_eq!(
+            fs::read_to_string(events.join("a-event.json")).unwrap(),
+            "{}"
+        );
+        assert!(!spool.join("a-event.json").exists());
+        assert!(spool.join(".b-event.json.tmp").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
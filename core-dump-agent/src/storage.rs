@@ -0,0 +1,503 @@
+use crate::azblob::Container;
+use crate::gcs::GcsBucket;
+use crate::http::HttpTarget;
+use crate::sftp::SftpTarget;
+use anyhow::anyhow;
+use log::warn;
+use s3::bucket::Bucket;
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tokio::io::AsyncWrite;
+
+pub const DEFAULT_BACKENDS: &str = "S3";
+pub const MIRROR_QUEUE_DIR: &str = "mirror-queue";
+/// Short enough for the file names of sftp drops and any object store.
+pub const DEFAULT_KEY_MAX_LENGTH: usize = 255;
+/// Hex digits of the name's sha256 a hashed key ends with.
+const KEY_HASH_LENGTH: usize = 12;
+
+/// How an archive is spread over the backends in STORAGE_BACKENDS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MirrorPolicy {
+    /// Every backend must accept the archive before it is removed.
+    All,
+    /// Backends are tried in order until one accepts the archive.
+    FirstSuccess,
+    /// The first backend must accept the archive. The others are fed from
+    /// a queue on the node in the background and retried on every sweep.
+    PrimaryAsyncMirror,
+}
+
+impl FromStr for MirrorPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "" | "all" => Ok(MirrorPolicy::All),
+            "first-success" => Ok(MirrorPolicy::FirstSuccess),
+            "primary+async-mirror" | "primary-async-mirror" => Ok(MirrorPolicy::PrimaryAsyncMirror),
+            other => Err(anyhow!("Unknown STORAGE_POLICY {}", other)),
+        }
+    }
+}
+
+/// What archives are stored as, from STORAGE_KEY.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyScheme {
+    /// The archive name the composer templated.
+    Name,
+    /// `<dump id>.<extension>`, which doesn't change with
+    /// FILENAME_TEMPLATE.
+    DumpId,
+}
+
+impl FromStr for KeyScheme {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "" | "name" => Ok(KeyScheme::Name),
+            "dump-id" | "dump_id" => Ok(KeyScheme::DumpId),
+            other => Err(anyhow!("Unknown STORAGE_KEY {}", other)),
+        }
+    }
+}
+
+/// How object names are made safe for stores that restrict keys, from
+/// KEY_ENCODING. Local archives keep their name, the catalog records the
+/// key each copy is stored as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyEncoding {
+    /// Used as they are.
+    None,
+    /// Bytes other than letters, digits and `.-_~` as `%XX`, which can be
+    /// decoded back.
+    Percent,
+    /// Lower case with anything but letters, digits and `.-_` replaced,
+    /// cut to the maximum length and suffixed with a hash of the name, so
+    /// names that only differ in what was replaced or cut, or in case,
+    /// don't collide.
+    Hash,
+}
+
+impl FromStr for KeyEncoding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "" | "none" => Ok(KeyEncoding::None),
+            "percent" => Ok(KeyEncoding::Percent),
+            "hash" => Ok(KeyEncoding::Hash),
+            other => Err(anyhow!("Unknown KEY_ENCODING {}", other)),
+        }
+    }
+}
+
+/// STORAGE_KEY, KEY_ENCODING and KEY_MAX_LENGTH.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Keys {
+    pub scheme: KeyScheme,
+    pub encoding: KeyEncoding,
+    pub max_length: usize,
+}
+
+/// `name` encoded as `keys` asks. The `/` between the prefixes of a
+/// templated name are kept.
+pub fn encode_key(name: &str, keys: &Keys) -> String {
+    match keys.encoding {
+        KeyEncoding::None => name.to_string(),
+        KeyEncoding::Percent => name
+            .bytes()
+            .map(|b| match b {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'.' | b'-' | b'_' | b'~' | b'/' => {
+                    (b as char).to_string()
+                }
+                b => format!("%{b:02X}"),
+            })
+            .collect(),
+        KeyEncoding::Hash => {
+            let safe = |s: &str| -> String {
+                s.to_lowercase()
+                    .chars()
+                    .map(|c| match c {
+                        'a'..='z' | '0'..='9' | '.' | '-' | '_' => c,
+                        _ => '-',
+                    })
+                    .collect()
+            };
+            let (prefix, leaf) = match name.rsplit_once('/') {
+                Some((prefix, leaf)) => (
+                    prefix.split('/').map(|p| safe(p) + "/").collect::<String>(),
+                    leaf,
+                ),
+                None => (String::new(), name),
+            };
+            let (stem, ext) = match leaf.rsplit_once('.') {
+                Some((stem, ext)) if !stem.is_empty() => (stem, format!(".{}", safe(ext))),
+                _ => (leaf, String::new()),
+            };
+            let hash = &sha256::digest(name)[..KEY_HASH_LENGTH];
+            let room = keys
+                .max_length
+                .saturating_sub(prefix.len() + ext.len() + KEY_HASH_LENGTH + 1);
+            let stem: String = safe(stem).chars().take(room).collect();
+            format!("{prefix}{stem}-{hash}{ext}")
+        }
+    }
+}
+
+/// The object name of the archive called `name`. By name it is the
+/// `archive_key` the composer rendered, with the prefixes of
+/// FILENAME_TEMPLATE, when the dump-info has one for this archive.
+/// Archives without a dump id keep their name.
+pub fn object_name(name: &str, dump_info: Option<&Value>, scheme: KeyScheme) -> String {
+    let dump_id = dump_info.and_then(crate::archive::dump_id);
+    let archive_key = dump_info
+        .and_then(|d| d["archive_key"].as_str())
+        .filter(|key| key.replace('/', "-") == name);
+    match (scheme, dump_id, archive_key) {
+        (KeyScheme::DumpId, Some(id), _) => match Path::new(name).extension() {
+            Some(ext) => format!("{}.{}", id, ext.to_string_lossy()),
+            None => id,
+        },
+        (KeyScheme::Name, _, Some(key)) => key.to_string(),
+        _ => name.to_string(),
+    }
+}
+
+/// The kind of object store a backend talks to, from
+/// `{prefix}_STORAGE_BACKEND` or else STORAGE_BACKEND.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoreKind {
+    S3,
+    AzBlob,
+    Gcs,
+    Sftp,
+    Http,
+}
+
+impl FromStr for StoreKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "" | "s3" => Ok(StoreKind::S3),
+            "azblob" | "azure" => Ok(StoreKind::AzBlob),
+            "gcs" => Ok(StoreKind::Gcs),
+            "sftp" => Ok(StoreKind::Sftp),
+            "http" | "https" => Ok(StoreKind::Http),
+            other => Err(anyhow!("Unknown STORAGE_BACKEND {}", other)),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum Store {
+    S3(Box<Bucket>),
+    AzBlob(Container),
+    Gcs(GcsBucket),
+    Sftp(SftpTarget),
+    Http(HttpTarget),
+}
+
+impl Store {
+    /// Where the archive called `name` is kept. Only sftp drops lay
+    /// archives out in directories, rendered from the dump-info.
+    pub fn key(&self, name: &str, dump_info: Option<&Value>, keys: &Keys) -> String {
+        let name = encode_key(&object_name(name, dump_info, keys.scheme), keys);
+        match self {
+            Store::Sftp(target) => target.remote_path(&name, dump_info),
+            _ => name,
+        }
+    }
+
+    /// Writes a small object in one request and returns the status.
+    pub async fn put(&self, key: &str, data: &[u8]) -> Result<u16, anyhow::Error> {
+        match self {
+            Store::S3(bucket) => Ok(bucket.put_object(key, data).await?.1),
+            Store::AzBlob(container) => container.put_blob(key, data).await,
+            Store::Gcs(bucket) => bucket.put_object(key, data).await,
+            Store::Sftp(target) => target.put(key, data).await,
+            Store::Http(target) => target.put(key, data).await,
+        }
+    }
+
+    /// Removes the object `key` and returns the status. One that is already
+    /// gone counts as removed.
+    pub async fn delete(&self, key: &str) -> Result<u16, anyhow::Error> {
+        match self {
+            Store::S3(bucket) => {
+                let code = bucket.delete_object(key).await?.1;
+                if !(200..300).contains(&code) && code != 404 {
+                    return Err(anyhow!("Deleting {} returned {}", key, code));
+                }
+                Ok(code)
+            }
+            Store::AzBlob(container) => container.delete_blob(key).await,
+            Store::Gcs(bucket) => bucket.delete_object(key).await,
+            Store::Sftp(target) => target.delete(key).await,
+            Store::Http(target) => target.delete(key).await,
+        }
+    }
+
+    /// Streams the object `key` into `writer` and returns the status.
+    pub async fn get_stream<W: AsyncWrite + Send + Unpin>(
+        &self,
+        key: &str,
+        writer: &mut W,
+    ) -> Result<u16, anyhow::Error> {
+        match self {
+            Store::S3(bucket) => Ok(bucket.get_object_stream(key, writer).await?),
+            Store::AzBlob(container) => container.get_blob(key, writer).await,
+            Store::Gcs(bucket) => bucket.get_object(key, writer).await,
+            Store::Sftp(target) => target.get_stream(key, writer).await,
+            Store::Http(target) => target.get_stream(key, writer).await,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Backend {
+    /// The env prefix the backend is configured with, e.g. `S3` reads
+    /// S3_BUCKET_NAME, S3_REGION, S3_ENDPOINT, S3_ACCESS_KEY and S3_SECRET.
+    pub name: String,
+    pub store: Store,
+}
+
+#[derive(Clone)]
+pub struct Backends {
+    pub policy: MirrorPolicy,
+    pub backends: Vec<Backend>,
+    pub mirror_queue: PathBuf,
+    /// Where the catalog and backend health are kept.
+    pub host_dir: PathBuf,
+}
+
+/// Splits the comma separated STORAGE_BACKENDS list into env prefixes.
+pub fn backend_names(list: &str) -> Vec<String> {
+    let names: Vec<String> = list
+        .split(',')
+        .map(|n| n.trim().trim_end_matches('_').to_uppercase())
+        .filter(|n| !n.is_empty())
+        .collect();
+    let mut unique = vec![];
+    for name in names {
+        if !unique.contains(&name) {
+            unique.push(name);
+        }
+    }
+    if unique.is_empty() {
+        unique.push(DEFAULT_BACKENDS.to_string());
+    }
+    unique
+}
+
+impl Backends {
+    pub fn primary(&self) -> &Backend {
+        &self.backends[0]
+    }
+
+    pub fn queue_dir(&self, backend: &Backend) -> PathBuf {
+        self.mirror_queue.join(&backend.name)
+    }
+
+    /// Links the archive into the queue of each backend so it survives the
+    /// removal of the original once the upload policy is satisfied.
+    pub fn enqueue(
+        &self,
+        zip_path: &Path,
+        targets: &[Backend],
+    ) -> Result<Vec<(Backend, PathBuf)>, anyhow::Error> {
+        let name = zip_path
+            .file_name()
+            .ok_or_else(|| anyhow!("No file name in {}", zip_path.display()))?;
+        let mut queued = vec![];
+        for backend in targets {
+            let dir = self.queue_dir(backend);
+            fs::create_dir_all(&dir)?;
+            let target = dir.join(name);
+            if !target.exists() && fs::hard_link(zip_path, &target).is_err() {
+                fs::copy(zip_path, &target)?;
+            }
+            // The dump-info of an encrypted archive goes along, the upload
+            // keys and tags it from there.
+            let dump_info = crate::archive::dump_info_path(zip_path);
+            if dump_info.exists() {
+                fs::copy(&dump_info, crate::archive::dump_info_path(&target))?;
+            }
+            queued.push((backend.clone(), target));
+        }
+        Ok(queued)
+    }
+
+    /// Everything still waiting in the queues, mirrors and copies held back
+    /// from an unhealthy backend.
+    pub fn queued(&self) -> Vec<(Backend, PathBuf)> {
+        let mut queued = vec![];
+        for backend in &self.backends {
+            let dir = self.queue_dir(backend);
+            let entries = match fs::read_dir(&dir) {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                let dump_info = path
+                    .to_str()
+                    .is_some_and(|p| p.ends_with(crate::archive::DUMP_INFO_SUFFIX));
+                if dump_info {
+                    continue;
+                }
+                if path.is_file() {
+                    queued.push((backend.clone(), path));
+                } else {
+                    warn!("Ignoring {} in mirror queue", path.display());
+                }
+            }
+        }
+        queued
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::{
+        backend_names, encode_key, object_name, Backend, Backends, KeyEncoding, KeyScheme, Keys,
+        MirrorPolicy, Store, StoreKind,
+    };
+    use s3::bucket::Bucket;
+    use s3::creds::Credentials;
+    use s3::region::Region;
+    use std::fs;
+    use uuid::Uuid;
+
+    #[test]
+    fn config_test() {
+        assert_eq!(backend_names(""), vec!["S3"]);
+        assert_eq!(backend_names("s3, onprem_,S3"), vec!["S3", "ONPREM"]);
+        assert_eq!("".parse::<MirrorPolicy>().unwrap(), MirrorPolicy::All);
+        assert_eq!(
+            "First-Success".parse::<MirrorPolicy>().unwrap(),
+            MirrorPolicy::FirstSuccess
+        );
+        assert_eq!(
+            "primary+async-mirror".parse::<MirrorPolicy>().unwrap(),
+            MirrorPolicy::PrimaryAsyncMirror
+        );
+        assert!("some".parse::<MirrorPolicy>().is_err());
+        assert_eq!("".parse::<StoreKind>().unwrap(), StoreKind::S3);
+        assert_eq!("AzBlob".parse::<StoreKind>().unwrap(), StoreKind::AzBlob);
+        assert_eq!("GCS".parse::<StoreKind>().unwrap(), StoreKind::Gcs);
+        assert_eq!("sftp".parse::<StoreKind>().unwrap(), StoreKind::Sftp);
+        assert_eq!("HTTP".parse::<StoreKind>().unwrap(), StoreKind::Http);
+        assert!("swift".parse::<StoreKind>().is_err());
+        assert_eq!("".parse::<KeyScheme>().unwrap(), KeyScheme::Name);
+        assert_eq!("Dump-Id".parse::<KeyScheme>().unwrap(), KeyScheme::DumpId);
+        assert!("uuid".parse::<KeyScheme>().is_err());
+    }
+
+    #[test]
+    fn object_name_test() {
+        let dump_info = serde_json::json!({"dump_id": "5ad2ea44", "uuid": "5ad2ea44"});
+        let name = "default-dump.tar";
+        assert_eq!(
+            object_name(name, Some(&dump_info), KeyScheme::DumpId),
+            "5ad2ea44.tar"
+        );
+        assert_eq!(object_name(name, Some(&dump_info), KeyScheme::Name), name);
+        assert_eq!(object_name(name, None, KeyScheme::DumpId), name);
+
+        let name = "mo-mo-0-node-11-1706263200.tar";
+        let dump_info = serde_json::json!({
+            "dump_id": "5ad2ea44",
+            "archive_key": "mo/mo-0/node-11-1706263200.tar"
+        });
+        assert_eq!(
+            object_name(name, Some(&dump_info), KeyScheme::Name),
+            "mo/mo-0/node-11-1706263200.tar"
+        );
+        assert_eq!(
+            object_name(name, Some(&dump_info), KeyScheme::DumpId),
+            "5ad2ea44.tar"
+        );
+        // The key of another archive, e.g. one renamed on the node.
+        assert_eq!(
+            object_name("other.tar", Some(&dump_info), KeyScheme::Name),
+            "other.tar"
+        );
+    }
+
+    #[test]
+    fn encode_key_test() {
+        let keys = |encoding| Keys {
+            scheme: KeyScheme::Name,
+            encoding,
+            max_length: 48,
+        };
+        let name = "5ad2ea44-dump-1706263200-Müller Pod-node-4-11.tar";
+        assert_eq!(encode_key(name, &keys(KeyEncoding::None)), name);
+        assert_eq!(
+            encode_key(name, &keys(KeyEncoding::Percent)),
+            "5ad2ea44-dump-1706263200-M%C3%BCller%20Pod-node-4-11.tar"
+        );
+        let hashed = encode_key(name, &keys(KeyEncoding::Hash));
+        assert_eq!(hashed.len(), 48);
+        assert!(hashed.starts_with("5ad2ea44-dump-1706263200-m-ller-"));
+        assert!(hashed.ends_with(".tar"));
+        let upper = encode_key(&name.to_uppercase(), &keys(KeyEncoding::Hash));
+        assert_ne!(hashed, upper);
+        assert_eq!(
+            encode_key("core", &keys(KeyEncoding::Hash)),
+            format!("core-{}", &sha256::digest("core")[..12])
+        );
+        let prefixed = "Mo Ns/mo-0/node-11.tar";
+        assert_eq!(
+            encode_key(prefixed, &keys(KeyEncoding::Percent)),
+            "Mo%20Ns/mo-0/node-11.tar"
+        );
+        assert_eq!(
+            encode_key(prefixed, &keys(KeyEncoding::Hash)),
+            format!("mo-ns/mo-0/node-11-{}.tar", &sha256::digest(prefixed)[..12])
+        );
+        assert_eq!("Hash".parse::<KeyEncoding>().unwrap(), KeyEncoding::Hash);
+        assert!("base64".parse::<KeyEncoding>().is_err());
+    }
+
+    #[test]
+    fn mirror_queue_test() {
+        let dir = std::env::temp_dir().join(format!("storage-test-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let backend = |name: &str| Backend {
+            name: name.to_string(),
+            store: Store::S3(Box::new(
+                Bucket::new(
+                    "cores",
+                    Region::UsEast1,
+                    Credentials::new(Some("a"), Some("b"), None, None, None).unwrap(),
+                )
+                .unwrap(),
+            )),
+        };
+        let backends = Backends {
+            policy: MirrorPolicy::PrimaryAsyncMirror,
+            backends: vec![backend("S3"), backend("ONPREM")],
+            mirror_queue: dir.join("mirror-queue"),
+            host_dir: dir.clone(),
+        };
+        let archive = dir.join("abc-dump.zip");
+        fs::write(&archive, b"zip").unwrap();
+
+        let queued = backends.enqueue(&archive, &backends.backends[1..]).unwrap();
+        assert_eq!(queued.len(), 1);
+        assert_eq!(queued[0].0.name, "ONPREM");
+        fs::remove_file(&archive).unwrap();
+        assert_eq!(fs::read(&queued[0].1).unwrap(), b"zip");
+
+        let queued = backends.queued();
+        assert_eq!(queued.len(), 1);
+        assert!(queued[0].1.ends_with("mirror-queue/ONPREM/abc-dump.zip"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,306 @@
+//! Server streaming `SubscribeEvents` gRPC method, see
+//! proto/event_service.proto.
+//!
+//! Events written by the composer are appended to an NDJSON index in the
+//! event directory (`events.ndjson`, one `{"offset", "file", "event"}` per
+//! line). A subscriber passes the offset after the last record it processed
+//! and receives everything from there on, followed by new events as they
+//! are indexed.
+
+use hyper::body::{Bytes, HttpBody};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use log::{debug, error, info};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::convert::Infallible;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub const SUBSCRIBE_PATH: &str = "/coredump.v1.EventService/SubscribeEvents";
+pub const INDEX_FILE: &str = "events.ndjson";
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct IndexRecord {
+    pub offset: u64,
+    pub file: String,
+    pub event: Value,
+}
+
+pub struct EventIndex {
+    event_dir: PathBuf,
+}
+
+impl EventIndex {
+    pub fn new(event_dir: &str) -> EventIndex {
+        EventIndex {
+            event_dir: PathBuf::from(event_dir),
+        }
+    }
+
+    fn path(&self) -> PathBuf {
+        self.event_dir.join(INDEX_FILE)
+    }
+
+    pub fn read(&self) -> Vec<IndexRecord> {
+        fs::read_to_string(self.path())
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|l| serde_json::from_str(l).ok())
+            .collect()
+    }
+
+    /// Appends the JSON event files that are not in the index yet, oldest
+    /// first, and returns the number added.
+    pub fn sync(&self) -> Result<usize, anyhow::Error> {
+        let records = self.read();
+        let indexed: HashSet<String> = records.iter().map(|r| r.file.clone()).collect();
+        let mut next = records.len() as u64;
+
+        let mut files: Vec<(std::time::SystemTime, PathBuf)> = fs::read_dir(&self.event_dir)?
+            .flatten()
+            .map(|e| e.path())
+            .filter(|p| {
+                p.file_name()
+                    .and_then(|n| n.to_str())
+                    .map(|n| n.ends_with("-event.json") && !indexed.contains(n))
+                    .unwrap_or(false)
+            })
+            .filter_map(|p| {
+                let modified = fs::metadata(&p).and_then(|m| m.modified()).ok()?;
+                Some((modified, p))
+            })
+            .collect();
+        files.sort();
+
+        if files.is_empty() {
+            return Ok(0);
+        }
+        let mut index = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path())?;
+        let mut added = 0;
+        for (_, path) in files {
+            let event: Value = match fs::read_to_string(&path)
+                .ok()
+                .and_then(|c| serde_json::from_str(&c).ok())
+            {
+                Some(v) => v,
+                // Still being written, picked up on the next sync.
+                None => continue,
+            };
+            let record = IndexRecord {
+                offset: next,
+                file: file_name(&path),
+                event,
+            };
+            writeln!(index, "{}", serde_json::to_string(&record)?)?;
+            next += 1;
+            added += 1;
+        }
+        Ok(added)
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+fn put_varint(buf: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        buf.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+    buf.push(v as u8);
+}
+
+fn get_varint(buf: &[u8], at: &mut usize) -> Option<u64> {
+    let mut v = 0u64;
+    for shift in (0..64).step_by(7) {
+        let b = *buf.get(*at)?;
+        *at += 1;
+        v |= ((b & 0x7f) as u64) << shift;
+        if b < 0x80 {
+            return Some(v);
+        }
+    }
+    None
+}
+
+/// Reads `from_offset` (field 1) from a framed SubscribeEventsRequest.
+pub fn decode_request(body: &[u8]) -> Option<u64> {
+    if body.is_empty() {
+        return Some(0);
+    }
+    if body.len() < 5 || body[0] != 0 {
+        return None;
+    }
+    let message = &body[5..];
+    let mut at = 0;
+    let mut from_offset = 0;
+    while at < message.len() {
+        let key = get_varint(message, &mut at)?;
+        match key & 7 {
+            0 => {
+                let v = get_varint(message, &mut at)?;
+                if key >> 3 == 1 {
+                    from_offset = v;
+                }
+            }
+            2 => {
+                let len = get_varint(message, &mut at)? as usize;
+                at += len;
+            }
+            _ => return None,
+        }
+    }
+    Some(from_offset)
+}
+
+/// Frames an EventRecord { offset = 1, file = 2, event_json = 3 } as a gRPC
+/// length prefixed message.
+pub fn encode_record(record: &IndexRecord) -> Vec<u8> {
+    let mut message = vec![];
+    if record.offset != 0 {
+        put_varint(&mut message, 1 << 3);
+        put_varint(&mut message, record.offset);
+    }
+    for (field, value) in [(2u64, record.file.clone()), (3, record.event.to_string())] {
+        put_varint(&mut message, field << 3 | 2);
+        put_varint(&mut message, value.len() as u64);
+        message.extend_from_slice(value.as_bytes());
+    }
+    let mut framed = vec![0u8];
+    framed.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    framed.extend(message);
+    framed
+}
+
+fn grpc_error(status: u32, message: &str) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", "application/grpc")
+        .header("grpc-status", status.to_string())
+        .header("grpc-message", message)
+        .body(Body::empty())
+        .unwrap()
+}
+
+async fn subscribe(req: Request<Body>, event_dir: String) -> Result<Response<Body>, Infallible> {
+    if req.uri().path() != SUBSCRIBE_PATH {
+        // UNIMPLEMENTED
+        return Ok(grpc_error(12, "unknown method"));
+    }
+    let mut body = req.into_body();
+    let mut request = vec![];
+    while let Some(Ok(chunk)) = body.data().await {
+        request.extend_from_slice(&chunk);
+    }
+    let from_offset = match decode_request(&request) {
+        Some(v) => v,
+        // INVALID_ARGUMENT
+        None => return Ok(grpc_error(3, "malformed SubscribeEventsRequest")),
+    };
+    info!("Event subscriber connected from offset {}", from_offset);
+
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        let index = EventIndex::new(&event_dir);
+        let mut next = from_offset;
+        loop {
+            if let Err(e) = index.sync() {
+                error!("Event index sync failed: {}", e);
+            }
+            let from = next;
+            for record in index.read().into_iter().filter(|r| r.offset >= from) {
+                next = record.offset + 1;
+                if sender
+                    .send_data(Bytes::from(encode_record(&record)))
+                    .await
+                    .is_err()
+                {
+                    debug!("Event subscriber went away at offset {}", next);
+                    return;
+                }
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    });
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", "application/grpc")
+        .body(body)
+        .unwrap())
+}
+
+pub async fn serve(addr: SocketAddr, event_dir: String) {
+    let make_svc = make_service_fn(move |_conn| {
+        let event_dir = event_dir.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |req| subscribe(req, event_dir.clone()))) }
+    });
+    info!("Serving SubscribeEvents on {}", addr);
+    if let Err(e) = Server::bind(&addr).http2_only(true).serve(make_svc).await {
+        error!("Event subscription server failed: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::subscribe::{decode_request, encode_record, EventIndex, IndexRecord};
+    use serde_json::json;
+    use std::fs;
+    use uuid::Uuid;
+
+    #[test]
+    fn index_sync_test() {
+        let dir = std::env::temp_dir().join(format!("subscribe-test-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a-event.json"), r#"{"uuid":"a"}"#).unwrap();
+        fs::write(dir.join("b-node-crash-event.json"), r#"{"uuid":"b"}"#).unwrap();
+        fs::write(dir.join("c-event.yaml"), "uuid: c").unwrap();
+
+        let index = EventIndex::new(dir.to_str().unwrap());
+        assert_eq!(index.sync().unwrap(), 2);
+        assert_eq!(index.sync().unwrap(), 0);
+        fs::write(dir.join("d-event.json"), r#"{"uuid":"d"}"#).unwrap();
+        assert_eq!(index.sync().unwrap(), 1);
+
+        let records = index.read();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[2].offset, 2);
+        assert_eq!(records[2].file, "d-event.json");
+        assert_eq!(records[2].event["uuid"], "d");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn wire_test() {
+        // from_offset = 300
+        assert_eq!(
+            decode_request(&[0, 0, 0, 0, 3, 0x08, 0xac, 0x02]),
+            Some(300)
+        );
+        assert_eq!(decode_request(&[0, 0, 0, 0, 0]), Some(0));
+        assert_eq!(decode_request(&[]), Some(0));
+        assert_eq!(decode_request(&[1, 0, 0, 0, 0]), None);
+
+        let record = IndexRecord {
+            offset: 1,
+            file: "a".to_string(),
+            event: json!({}),
+        };
+        assert_eq!(
+            encode_record(&record),
+            vec![0, 0, 0, 0, 9, 0x08, 0x01, 0x12, 0x01, b'a', 0x1a, 0x02, b'{', b'}']
+        );
+    }
+}
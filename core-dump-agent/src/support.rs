@@ -0,0 +1,356 @@
+//! `support-bundle [--hours N] [output.tar]` packs the handler's own state
+//! into one tar to attach to a GitHub issue: the composer .env with its
+//! secrets masked, the state files in the host directory, and the lines of
+//! composer.log, the rolled over ones included, decisions.log and the
+//! catalog and the events of the last N hours (Default 24). Cores and
+//! archives are never included, only their names and sizes.
+//!
+//! The agent logs to stdout, add `kubectl logs` of the agent pod to the
+//! issue next to the bundle.
+
+use crate::catalog::CATALOG_FILE;
+use crate::health::HEALTH_FILE;
+use crate::podcache::POD_CACHE_FILE;
+use crate::policy::POLICY_CACHE;
+use crate::retention::RETENTION_FILE;
+use log::{debug, info};
+use serde_json::{json, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+pub const DEFAULT_HOURS: u64 = 24;
+const MASK: &str = "********";
+
+/// Copied whole, they only hold what the handler currently knows.
+const STATE_FILES: [&str; 10] = [
+    HEALTH_FILE,
+    RETENTION_FILE,
+    POD_CACHE_FILE,
+    POLICY_CACHE,
+    "ratelimit.json",
+    "signatures.json",
+    "core_pattern.bak",
+    "core_pipe_limit.bak",
+    "suid_dumpable.bak",
+    "startup-report.json",
+];
+
+/// Whether the value of the .env key can be a credential.
+fn is_secret(key: &str) -> bool {
+    let key = key.to_uppercase();
+    ["SECRET", "PASSWORD", "TOKEN", "CREDENTIAL", "_KEY"]
+        .iter()
+        .any(|s| key.contains(s))
+}
+
+/// The .env with the values of secret keys replaced, and the user info of
+/// URLs, which can carry a password too.
+pub fn mask_env(content: &str) -> String {
+    content
+        .lines()
+        .map(|line| match line.split_once('=') {
+            Some((key, value)) if is_secret(key) && !value.trim_matches('\'').is_empty() => {
+                format!("{key}={MASK}")
+            }
+            Some((key, value)) => match value.split_once("://") {
+                Some((scheme, rest)) if rest.split('/').next().unwrap_or("").contains('@') => {
+                    let host = rest.split_once('@').map(|(_, h)| h).unwrap_or(rest);
+                    format!("{key}={scheme}://{MASK}@{host}")
+                }
+                _ => line.to_string(),
+            },
+            None => line.to_string(),
+        })
+        .map(|line| line + "\n")
+        .collect()
+}
+
+/// Seconds since the epoch of a civil date and time.
+fn epoch(year: i64, month: i64, day: i64, secs_of_day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    (era * 146097 + doe - 719468) * 86400 + secs_of_day
+}
+
+/// The time of a composer.log line, `INFO - 2024-01-26T10:00:00.1+01:00 - `.
+fn log_time(line: &str) -> Option<u64> {
+    let stamp = line.split(" - ").nth(1)?;
+    let num = |range: std::ops::Range<usize>| stamp.get(range)?.parse::<i64>().ok();
+    let secs_of_day = num(11..13)? * 3600 + num(14..16)? * 60 + num(17..19)?;
+    let mut at = epoch(num(0..4)?, num(5..7)?, num(8..10)?, secs_of_day);
+    let zone = stamp[19..].trim_start_matches(|c: char| c == '.' || c.is_ascii_digit());
+    if let Some(sign) = zone.chars().next().filter(|c| *c == '+' || *c == '-') {
+        let offset = zone.get(1..3)?.parse::<i64>().ok()? * 3600
+            + zone
+                .get(4..6)
+                .and_then(|m| m.parse::<i64>().ok())
+                .unwrap_or(0)
+                * 60;
+        at -= if sign == '+' { offset } else { -offset };
+    }
+    u64::try_from(at).ok()
+}
+
+/// The time of a line of decisions.log or the catalog.
+fn json_time(line: &str) -> Option<u64> {
+    let v: Value = serde_json::from_str(line).ok()?;
+    match &v["time"] {
+        Value::Number(n) => n.as_u64(),
+        _ => v["timestamp"].as_str()?.parse().ok(),
+    }
+}
+
+/// Reads the time of a line, None for lines that have none.
+type TimeOf = fn(&str) -> Option<u64>;
+
+/// The lines at or after `since`. A line without a time, such as the rest
+/// of a multi-line log message, goes with the line before it.
+pub fn recent_lines(content: &str, since: u64, time_of: TimeOf) -> String {
+    let mut keep = false;
+    let mut out = String::new();
+    for line in content.lines() {
+        if let Some(time) = time_of(line) {
+            keep = time >= since;
+        }
+        if keep {
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+fn modified(path: &Path) -> u64 {
+    fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn files(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.is_file())
+        .collect();
+    files.sort();
+    files
+}
+
+fn append(
+    tar: &mut tar::Builder<fs::File>,
+    name: &str,
+    data: &[u8],
+    now: u64,
+) -> Result<(), anyhow::Error> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(now);
+    header.set_cksum();
+    tar.append_data(&mut header, format!("support-bundle/{name}"), data)?;
+    Ok(())
+}
+
+pub struct Bundle {
+    pub host_dir: PathBuf,
+    pub core_dir: PathBuf,
+    pub event_dir: PathBuf,
+    pub hours: u64,
+}
+
+impl Bundle {
+    /// Who and what the bundle is of, with the archives waiting on the
+    /// node.
+    fn summary(&self, now: u64) -> Value {
+        let read = |path: &str| {
+            fs::read_to_string(path)
+                .map(|s| s.trim().to_string())
+                .unwrap_or_default()
+        };
+        let cores: Vec<Value> = files(&self.core_dir)
+            .iter()
+            .map(|p| {
+                json!({
+                    "name": p.file_name().map(|n| n.to_string_lossy().to_string()),
+                    "size": fs::metadata(p).map(|m| m.len()).unwrap_or_default(),
+                    "modified": modified(p),
+                })
+            })
+            .collect();
+        json!({
+            "agent_version": env!("CARGO_PKG_VERSION"),
+            "created": now,
+            "since": now.saturating_sub(self.hours * 3600),
+            "node": std::env::var("NODE_NAME").unwrap_or_default(),
+            "kernel": read("/proc/sys/kernel/osrelease"),
+            "core_pattern": read("/proc/sys/kernel/core_pattern"),
+            "core_pipe_limit": read("/proc/sys/kernel/core_pipe_limit"),
+            "suid_dumpable": read("/proc/sys/fs/suid_dumpable"),
+            "arch": std::env::consts::ARCH,
+            "host_dir": self.host_dir,
+            "core_dir": self.core_dir,
+            "cores": cores,
+        })
+    }
+
+    /// Writes the bundle to `output` and returns how many files it holds.
+    pub fn write(&self, output: &Path, now: u64) -> Result<usize, anyhow::Error> {
+        let since = now.saturating_sub(self.hours * 3600);
+        let mut tar = tar::Builder::new(fs::File::create(output)?);
+        let mut count = 0;
+        let mut add = |name: &str, data: &[u8]| {
+            count += 1;
+            append(&mut tar, name, data, now)
+        };
+        add(
+            "summary.json",
+            serde_json::to_string_pretty(&self.summary(now))?.as_bytes(),
+        )?;
+        if let Ok(env) = fs::read_to_string(self.host_dir.join(".env")) {
+            add(".env", mask_env(&env).as_bytes())?;
+        }
+        for name in STATE_FILES {
+            match fs::read(self.host_dir.join(name)) {
+                Ok(data) => add(name, &data)?,
+                Err(e) => debug!("Not bundling {}: {}", name, e),
+            }
+        }
+        let windowed: [(&str, TimeOf); 3] = [
+            ("composer.log", log_time),
+            ("decisions.log", json_time),
+            (CATALOG_FILE, json_time),
+        ];
+        for (name, time_of) in windowed {
+            if let Ok(content) = fs::read_to_string(self.host_dir.join(name)) {
+                add(name, recent_lines(&content, since, time_of).as_bytes())?;
+            }
+        }
+        // The composer rolls its log over to composer.1.log and on, newest
+        // first, the window can reach back into them.
+        for name in (1..).map(|n| format!("composer.{n}.log")) {
+            let Ok(content) = fs::read_to_string(self.host_dir.join(&name)) else {
+                break;
+            };
+            let lines = recent_lines(&content, since, log_time);
+            if lines.is_empty() {
+                break;
+            }
+            add(&name, lines.as_bytes())?;
+        }
+        for event in files(&self.event_dir) {
+            if modified(&event) < since {
+                continue;
+            }
+            let name = event.file_name().unwrap_or_default().to_string_lossy();
+            add(&format!("events/{name}"), &fs::read(&event)?)?;
+        }
+        tar.finish()?;
+        info!("Wrote {} files to {}", count, output.display());
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::support::{json_time, log_time, mask_env, recent_lines, Bundle};
+    use std::fs;
+
+    #[test]
+    fn mask_env_test() {
+        let env = "LOG_LEVEL=Warn\nWEBHOOK_SECRET=s3cret\nWEBHOOK_URL=https://ops:pw@hooks.example.com/core\nOTLP_ENDPOINT=http://collector:4318\nPOD_SELECTOR_LABEL=''\nSIGNALS=''\n";
+        assert_eq!(
+            mask_env(env),
+            "LOG_LEVEL=Warn\nWEBHOOK_SECRET=********\nWEBHOOK_URL=https://********@hooks.example.com/core\nOTLP_ENDPOINT=http://collector:4318\nPOD_SELECTOR_LABEL=''\nSIGNALS=''\n"
+        );
+        assert_eq!(mask_env("WEBHOOK_SECRET=\n"), "WEBHOOK_SECRET=\n");
+    }
+
+    #[test]
+    fn window_test() {
+        assert_eq!(
+            log_time("INFO - 2020-05-02T23:34:26.123456789+00:00 - Setting host location"),
+            Some(1588462466)
+        );
+        assert_eq!(
+            log_time("ERROR - 2020-05-03T01:34:26+02:00 - Failed"),
+            Some(1588462466)
+        );
+        assert_eq!(log_time("  at the continuation of a message"), None);
+        assert_eq!(json_time(r#"{"timestamp":"1588462466"}"#), Some(1588462466));
+        assert_eq!(
+            json_time(r#"{"archive":"a.tar","time":1588462466}"#),
+            Some(1588462466)
+        );
+
+        let log = "INFO - 2020-05-02T23:00:00+00:00 - old\nINFO - 2020-05-02T23:34:26+00:00 - new\n  continued\n";
+        assert_eq!(
+            recent_lines(log, 1588462466, log_time),
+            "INFO - 2020-05-02T23:34:26+00:00 - new\n  continued\n"
+        );
+    }
+
+    #[test]
+    fn bundle_test() {
+        let dir = std::env::temp_dir().join(format!("support-{}", uuid::Uuid::new_v4()));
+        let host_dir = dir.join("host");
+        fs::create_dir_all(host_dir.join("cores")).unwrap();
+        fs::create_dir_all(host_dir.join("events")).unwrap();
+        fs::write(host_dir.join(".env"), "WEBHOOK_SECRET=s3cret\n").unwrap();
+        fs::write(host_dir.join("signatures.json"), "{}").unwrap();
+        fs::write(host_dir.join("cores/a.tar"), "not bundled").unwrap();
+        fs::write(host_dir.join("events/a-event.json"), "{}").unwrap();
+        let bundle = Bundle {
+            core_dir: host_dir.join("cores"),
+            event_dir: host_dir.join("events"),
+            host_dir: host_dir.clone(),
+            hours: 24,
+        };
+        let output = dir.join("bundle.tar");
+        let now = crate::kube::now();
+        let today = crate::sftp::civil_date(now);
+        fs::write(
+            host_dir.join("composer.1.log"),
+            format!("WARN - {today}T00:00:00+00:00 - rolled over\n"),
+        )
+        .unwrap();
+        fs::write(
+            host_dir.join("composer.2.log"),
+            "WARN - 2024-01-26T10:00:00+00:00 - too old\n",
+        )
+        .unwrap();
+        assert_eq!(bundle.write(&output, now).unwrap(), 5);
+
+        let mut names = vec![];
+        let mut archive = tar::Archive::new(fs::File::open(&output).unwrap());
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let name = entry.path().unwrap().to_string_lossy().to_string();
+            if name.ends_with(".env") {
+                let mut content = String::new();
+                std::io::Read::read_to_string(&mut entry, &mut content).unwrap();
+                assert_eq!(content, "WEBHOOK_SECRET=********\n");
+            }
+            names.push(name);
+        }
+        assert_eq!(
+            names,
+            [
+                "support-bundle/summary.json",
+                "support-bundle/.env",
+                "support-bundle/signatures.json",
+                "support-bundle/composer.1.log",
+                "support-bundle/events/a-event.json"
+            ]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
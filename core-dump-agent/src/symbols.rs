@@ -0,0 +1,156 @@
+//! Uploads the executables and libraries the composer captured with
+//! `CAPTURE_BINARIES` to a symbol store keyed by build-id, so symbols stay
+//! available after the image is pruned. `SYMBOL_STORE` names the env prefix
+//! of the store, configured like any other backend, and `SYMBOL_LAYOUT`
+//! picks the key convention.
+//!
+//! The build-ids already stored from this node are kept in
+//! `symbols.ids` in the host directory so a library shared by many
+//! crashes is only sent once.
+
+use crate::storage::Store;
+use anyhow::anyhow;
+use core_dump_archive::Archive;
+use log::{info, warn};
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+pub const SYMBOL_IDS_FILE: &str = "symbols.ids";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// `buildid/<id>/executable`, served as is by debuginfod.
+    Debuginfod,
+    /// `<file>/elf-buildid-<id>/<file>`, the simple symbol query protocol
+    /// used by symbol servers.
+    Ssqp,
+}
+
+impl FromStr for Layout {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "" | "debuginfod" => Ok(Layout::Debuginfod),
+            "ssqp" | "symstore" => Ok(Layout::Ssqp),
+            other => Err(anyhow!("Unknown SYMBOL_LAYOUT {}", other)),
+        }
+    }
+}
+
+/// The key of the binary at `path` with `build_id`, under `prefix`.
+pub fn key(layout: Layout, prefix: &str, path: &str, build_id: &str) -> String {
+    let key = match layout {
+        Layout::Debuginfod => format!("buildid/{build_id}/executable"),
+        Layout::Ssqp => {
+            let file = path.rsplit('/').next().unwrap_or(path).to_lowercase();
+            format!("{file}/elf-buildid-{build_id}/{file}")
+        }
+    };
+    match prefix.trim_matches('/') {
+        "" => key,
+        prefix => format!("{prefix}/{key}"),
+    }
+}
+
+/// The build-ids already in the symbol store.
+pub struct Uploaded {
+    path: PathBuf,
+}
+
+impl Uploaded {
+    pub fn new(host_dir: &Path) -> Uploaded {
+        Uploaded {
+            path: host_dir.join(SYMBOL_IDS_FILE),
+        }
+    }
+
+    pub fn contains(&self, build_id: &str) -> bool {
+        fs::read_to_string(&self.path)
+            .map(|ids| ids.lines().any(|l| l == build_id))
+            .unwrap_or(false)
+    }
+
+    pub fn insert(&self, build_id: &str) -> Result<(), anyhow::Error> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{build_id}")?;
+        Ok(())
+    }
+}
+
+/// Sends the binaries of `archive` that have a build-id and aren't stored
+/// yet. Returns how many were sent.
+pub async fn upload(
+    archive: &Archive,
+    store: &Store,
+    layout: Layout,
+    prefix: &str,
+    uploaded: &Uploaded,
+) -> Result<usize, anyhow::Error> {
+    let mut sent = 0;
+    for binary in &archive.dump_info().binaries {
+        let build_id = match &binary.build_id {
+            Some(v) => v,
+            None => {
+                warn!(
+                    "{} has no build-id, not sent to the symbol store",
+                    binary.path
+                );
+                continue;
+            }
+        };
+        if uploaded.contains(build_id) {
+            continue;
+        }
+        let entry = match archive.binaries().iter().find(|(p, _)| *p == binary.path) {
+            Some(&(_, entry)) => entry,
+            None => continue,
+        };
+        let key = key(layout, prefix, &binary.path, build_id);
+        let code = store.put(&key, &archive.read(entry)?).await?;
+        info!("Stored {} as {}: {}", binary.path, key, code);
+        uploaded.insert(build_id)?;
+        sent += 1;
+    }
+    Ok(sent)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::symbols::{key, Layout, Uploaded};
+    use std::fs;
+    use uuid::Uuid;
+
+    #[test]
+    fn key_test() {
+        assert_eq!(
+            key(Layout::Debuginfod, "", "/usr/lib/libc.so.6", "4f1e2a"),
+            "buildid/4f1e2a/executable"
+        );
+        assert_eq!(
+            key(Layout::Ssqp, "/symbols/", "/usr/bin/MoService", "4f1e2a"),
+            "symbols/moservice/elf-buildid-4f1e2a/moservice"
+        );
+        assert_eq!("symstore".parse::<Layout>().unwrap(), Layout::Ssqp);
+        assert!("pdb".parse::<Layout>().is_err());
+    }
+
+    #[test]
+    fn uploaded_test() {
+        let dir = std::env::temp_dir().join(format!("symbols-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let uploaded = Uploaded::new(&dir);
+        assert!(!uploaded.contains("4f1e2a"));
+        uploaded.insert("4f1e2a").unwrap();
+        uploaded.insert("9b0c").unwrap();
+        assert!(uploaded.contains("4f1e2a"));
+        assert!(!uploaded.contains("4f1e"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -5,6 +5,7 @@ use fs_extra::dir::copy;
 use fs_extra::dir::create_all;
 use fs_extra::dir::CopyOptions;
 use std::fs;
+use std::os::unix::fs::PermissionsExt;
 use std::path::Path;
 use std::process::Command;
 use std::thread;
@@ -37,8 +38,7 @@ fn basic() -> Result<(), std::io::Error> {
     create_all(&home_path, false).unwrap();
     println!("AGENT_TEST Made home folder : {}", home_path);
 
-    let mut from_paths = Vec::new();
-    from_paths.push(&sysctl_path);
+    let from_paths = vec![&sysctl_path];
     copy_items(&from_paths, &home_path, &options).unwrap();
     copy(&mocks_path, &local_path, &options).unwrap();
     println!("AGENT_TEST Copied mocks to : {}", home_path);
@@ -87,17 +87,32 @@ fn basic() -> Result<(), std::io::Error> {
 
     let env_file = format!("{}/{}", &home_path, ".env");
     let env_content = fs::read_to_string(&env_file).unwrap();
+    let mode = fs::metadata(&env_file)?.permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
     assert!(env_content.contains("LOG_LEVEL=debug"));
     assert!(env_content.contains("IGNORE_CRIO=false"));
     assert!(env_content.contains("CRIO_IMAGE_CMD=img"));
     assert!(env_content.contains("USE_CRIO_CONF=false"));
     assert!(env_content.contains(
-        "FILENAME_TEMPLATE={uuid}-dump-{timestamp}-{hostname}-{exe_name}-{pid}-{signal}"
+        "FILENAME_TEMPLATE={uuid}-dump-{timestamp}-{hostname}-{exe_name}-{pid}-{signal}-{pod_uid}-{sequence}"
     ));
     assert!(env_content.contains("LOG_LENGTH=500"));
     assert!(env_content.contains("EVENTS=false"));
     assert!(env_content.contains("EVENT_DIRECTORY=/"));
-    assert_eq!(env_content.lines().count(), 11);
+    assert!(env_content.contains("WEBHOOK_URL=\n"));
+    assert!(env_content.contains("CAPTURE_BINARIES=false"));
+    assert!(env_content.contains("BACKTRACE=false"));
+    assert!(env_content.contains("UNKNOWN_POD=capture"));
+    assert!(env_content.contains("CORE_FORMAT=core"));
+    assert!(env_content.contains("OTLP_ENDPOINT=\n"));
+    assert!(env_content.contains("NAMESPACE_ALLOWLIST=\nNAMESPACE_DENYLIST=\n"));
+    assert!(env_content.contains("COLLECTORS_FILE=\n"));
+    assert!(env_content.contains("DELTA_MAX_BASES=\nDELTA_MAX_BASE_BYTES=\n"));
+    assert_eq!(env_content.lines().count(), 70);
+
+    let report = fs::read_to_string(format!("{}/startup-report.json", &home_path)).unwrap();
+    assert!(report.contains("\"core_pipe_limit\""));
+    assert!(report.contains("\"max_concurrent_captures\": null"));
     //TODO: [No9] Test uploading of a corefile
     //TODO: [No9] Test remove option
     //TODO: [No9] Test sweep option
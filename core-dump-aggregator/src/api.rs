@@ -0,0 +1,183 @@
+//! The HTTP API over the catalog.
+//!
+//! * `GET /v1/dumps` lists dumps newest first, filtered by `namespace`,
+//!   `pod`, `exe`, `signal`, `source`, `since`, `until`, `q` and `limit`.
+//! * `GET /v1/dumps/{id}` is the full event of a dump.
+//! * `GET /v1/dumps/{id}/download` redirects to DOWNLOAD_URL.
+//! * `GET /healthz`
+//!
+//! With the `ui` feature and UI_PASSWORD set the web UI is served on `/ui`
+//! and the rest asks for the password too, see ui.rs.
+
+use crate::catalog::{Catalog, Query, Record};
+use hyper::header::{CONTENT_TYPE, LOCATION};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use log::{error, info};
+use serde_json::{json, Value};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Fills a DOWNLOAD_URL such as
+/// `https://cores.s3.eu-west-1.amazonaws.com/{name}` for a dump. `{name}`
+/// is the archive name and `{dump_id}` the dump id, for agents with
+/// STORAGE_KEY=dump-id, with `{ext}` the archive's extension.
+pub fn download_url(template: &str, record: &Record) -> Option<String> {
+    let name = record.event["key"].as_str()?;
+    let ext = Path::new(name)
+        .extension()
+        .map(|e| e.to_string_lossy().to_string())
+        .unwrap_or_default();
+    Some(
+        template
+            .replace("{name}", name)
+            .replace("{dump_id}", record.dump_id()?)
+            .replace("{ext}", &ext),
+    )
+}
+
+fn json_response(status: StatusCode, body: &Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+fn not_found(what: &str) -> Response<Body> {
+    json_response(StatusCode::NOT_FOUND, &json!({ "error": what }))
+}
+
+/// Answers one request from the catalog.
+pub fn route(
+    catalog: &Catalog,
+    download: &str,
+    method: &Method,
+    path: &str,
+    query: &str,
+) -> Response<Body> {
+    if method != Method::GET {
+        return json_response(
+            StatusCode::METHOD_NOT_ALLOWED,
+            &json!({ "error": "only GET is served" }),
+        );
+    }
+    let parts: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match parts.as_slice() {
+        ["healthz"] => json_response(StatusCode::OK, &json!({ "dumps": catalog.len() })),
+        ["v1", "dumps"] => match Query::parse(query) {
+            Ok(q) => {
+                let dumps: Vec<Value> = catalog.search(&q).iter().map(|r| r.summary()).collect();
+                json_response(StatusCode::OK, &json!({ "dumps": dumps }))
+            }
+            Err(e) => json_response(StatusCode::BAD_REQUEST, &json!({ "error": e.to_string() })),
+        },
+        ["v1", "dumps", id] => match catalog.get(id) {
+            Some(record) => json_response(StatusCode::OK, &record.event),
+            None => not_found("no such dump"),
+        },
+        ["v1", "dumps", id, "download"] => {
+            let Some(record) = catalog.get(id) else {
+                return not_found("no such dump");
+            };
+            match download_url(download, record).filter(|_| !download.is_empty()) {
+                Some(url) => Response::builder()
+                    .status(StatusCode::FOUND)
+                    .header(LOCATION, url)
+                    .body(Body::empty())
+                    .unwrap(),
+                None => not_found("DOWNLOAD_URL is not configured"),
+            }
+        }
+        _ => not_found("unknown path"),
+    }
+}
+
+/// How the API is served.
+pub struct Settings {
+    /// The DOWNLOAD_URL template, empty when unset.
+    pub download: String,
+    #[cfg(feature = "ui")]
+    pub ui: Option<crate::ui::Ui>,
+}
+
+fn respond(settings: &Settings, catalog: &Catalog, req: &Request<Body>) -> Response<Body> {
+    #[cfg(feature = "ui")]
+    if let Some(ui) = &settings.ui {
+        if let Some(response) = ui.intercept(req.method(), req.uri().path(), req.headers()) {
+            return response;
+        }
+    }
+    route(
+        catalog,
+        &settings.download,
+        req.method(),
+        req.uri().path(),
+        req.uri().query().unwrap_or_default(),
+    )
+}
+
+pub async fn serve(addr: SocketAddr, catalog: Arc<Mutex<Catalog>>, settings: Settings) {
+    let settings = Arc::new(settings);
+    let make_svc = make_service_fn(move |_conn| {
+        let catalog = catalog.clone();
+        let settings = settings.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                let response = respond(&settings, &catalog.lock().unwrap(), &req);
+                async move { Ok::<_, Infallible>(response) }
+            }))
+        }
+    });
+    info!("Serving the catalog on {}", addr);
+    if let Err(e) = Server::bind(&addr).serve(make_svc).await {
+        error!("Catalog server failed: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::route;
+    use crate::catalog::{Catalog, Record};
+    use hyper::{Method, StatusCode};
+    use serde_json::json;
+    use std::fs;
+
+    #[test]
+    fn route_test() {
+        let dir = std::env::temp_dir().join(format!("api-test-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut catalog = Catalog::load(&dir).unwrap();
+        catalog
+            .insert(Record {
+                source: "10.0.0.1:50051".to_string(),
+                offset: 0,
+                event: json!({"dump_id": "d1", "key": "d1-dump-mo-0.zip", "hostname": "mo-0"}),
+            })
+            .unwrap();
+        let template = "https://cores.example.com/{dump_id}.{ext}";
+        let get = |path: &str, query: &str| route(&catalog, template, &Method::GET, path, query);
+
+        assert_eq!(get("/v1/dumps", "pod=mo-0").status(), StatusCode::OK);
+        assert_eq!(
+            get("/v1/dumps", "limit=all").status(),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(get("/v1/dumps/d1", "").status(), StatusCode::OK);
+        assert_eq!(get("/v1/dumps/d2", "").status(), StatusCode::NOT_FOUND);
+        let download = get("/v1/dumps/d1/download", "");
+        assert_eq!(download.status(), StatusCode::FOUND);
+        assert_eq!(
+            download.headers()["location"],
+            "https://cores.example.com/d1.zip"
+        );
+        let unconfigured = route(&catalog, "", &Method::GET, "/v1/dumps/d1/download", "");
+        assert_eq!(unconfigured.status(), StatusCode::NOT_FOUND);
+        let post = route(&catalog, template, &Method::POST, "/v1/dumps", "");
+        assert_eq!(post.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(get("/healthz", "").status(), StatusCode::OK);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
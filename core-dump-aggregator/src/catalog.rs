@@ -0,0 +1,323 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+pub const CATALOG_FILE: &str = "catalog.ndjson";
+const DEFAULT_LIMIT: usize = 100;
+
+/// One line of the catalog file, an event as one agent streamed it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Record {
+    /// The agent the event came from.
+    pub source: String,
+    /// Its offset in that agent's event index.
+    pub offset: u64,
+    pub event: Value,
+}
+
+impl Record {
+    /// Events written before they carried a dump id are known by uuid.
+    pub fn dump_id(&self) -> Option<&str> {
+        self.event["dump_id"]
+            .as_str()
+            .filter(|id| !id.is_empty())
+            .or_else(|| self.event["uuid"].as_str())
+    }
+
+    fn timestamp(&self) -> u64 {
+        self.event["timestamp"]
+            .as_str()
+            .and_then(|t| t.parse().ok())
+            .unwrap_or_default()
+    }
+
+    /// Events written before they carried a type are all core dumps.
+    fn event_type(&self) -> &str {
+        self.event["event_type"].as_str().unwrap_or("core-dump")
+    }
+
+    /// The summary `GET /v1/dumps` lists.
+    pub fn summary(&self) -> Value {
+        let e = &self.event;
+        serde_json::json!({
+            "dump_id": self.dump_id(),
+            "name": e["key"],
+            "namespace": e["namespace"],
+            "pod": e["hostname"],
+            "exe": e["exe_name"],
+            "signal": e["signal"],
+            "timestamp": e["timestamp"],
+            "outcome": e["decision"]["outcome"],
+            "type": self.event_type(),
+            "source": self.source,
+        })
+    }
+}
+
+/// A `GET /v1/dumps` search. Every field set has to match.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Query {
+    pub namespace: Option<String>,
+    pub pod: Option<String>,
+    pub exe: Option<String>,
+    pub signal: Option<String>,
+    pub source: Option<String>,
+    /// `core-dump` or `unknown-pod`.
+    pub event_type: Option<String>,
+    /// Seconds since the epoch, inclusive.
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+    /// Matched against the archive name, the pod and the executable.
+    pub text: Option<String>,
+    pub limit: usize,
+}
+
+impl Query {
+    /// From a query string such as `namespace=mo&since=1706263200`.
+    /// Unknown parameters are ignored.
+    pub fn parse(query: &str) -> Result<Query, anyhow::Error> {
+        let mut q = Query {
+            limit: DEFAULT_LIMIT,
+            ..Query::default()
+        };
+        for pair in query.split('&').filter(|p| !p.is_empty()) {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value = decode(value);
+            let number = |v: &str| {
+                v.parse::<u64>()
+                    .map_err(|_| anyhow::anyhow!("{} must be a number, not {:?}", name, v))
+            };
+            match name {
+                "namespace" => q.namespace = Some(value),
+                "pod" => q.pod = Some(value),
+                "exe" => q.exe = Some(value),
+                "signal" => q.signal = Some(value),
+                "source" => q.source = Some(value),
+                "type" => q.event_type = Some(value),
+                "since" => q.since = Some(number(&value)?),
+                "until" => q.until = Some(number(&value)?),
+                "q" => q.text = Some(value),
+                "limit" => q.limit = number(&value)? as usize,
+                _ => {}
+            }
+        }
+        Ok(q)
+    }
+
+    fn matches(&self, record: &Record) -> bool {
+        let field = |name: &str| record.event[name].as_str().unwrap_or_default();
+        let equals = |want: &Option<String>, have: &str| want.as_ref().is_none_or(|w| w == have);
+        let timestamp = record.timestamp();
+        equals(&self.namespace, field("namespace"))
+            && equals(&self.pod, field("hostname"))
+            && equals(&self.exe, field("exe_name"))
+            && equals(&self.signal, field("signal"))
+            && equals(&self.source, &record.source)
+            && equals(&self.event_type, record.event_type())
+            && self.since.is_none_or(|s| timestamp >= s)
+            && self.until.is_none_or(|u| timestamp <= u)
+            && self.text.as_ref().is_none_or(|t| {
+                ["key", "hostname", "exe_name"]
+                    .iter()
+                    .any(|f| field(f).contains(t.as_str()))
+            })
+    }
+}
+
+/// Decodes `+` and `%XX` of a query string value.
+fn decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+                match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                    Some(b) => {
+                        out.push(b);
+                        i += 2;
+                    }
+                    None => out.push(b'%'),
+                }
+            }
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).to_string()
+}
+
+/// The cluster wide catalog, every event of every agent once by dump id.
+/// It is kept in memory and appended to `catalog.ndjson` in the data
+/// directory, which is read back on start.
+pub struct Catalog {
+    path: PathBuf,
+    dumps: BTreeMap<String, Record>,
+    /// The next offset to ask each agent for.
+    offsets: HashMap<String, u64>,
+}
+
+impl Catalog {
+    pub fn load(data_dir: &Path) -> Result<Catalog, anyhow::Error> {
+        let mut catalog = Catalog {
+            path: data_dir.join(CATALOG_FILE),
+            dumps: BTreeMap::new(),
+            offsets: HashMap::new(),
+        };
+        let content = match fs::read_to_string(&catalog.path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+        for record in content
+            .lines()
+            .filter_map(|l| serde_json::from_str::<Record>(l).ok())
+        {
+            catalog.remember(record);
+        }
+        Ok(catalog)
+    }
+
+    fn remember(&mut self, record: Record) -> bool {
+        let next = self.offsets.entry(record.source.clone()).or_default();
+        *next = (*next).max(record.offset + 1);
+        match record.dump_id().map(str::to_string) {
+            Some(id) if !self.dumps.contains_key(&id) => {
+                self.dumps.insert(id, record);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Adds an event streamed by `source`. An event already known, from
+    /// this agent or a replaced pod of it, is only counted as seen.
+    pub fn insert(&mut self, record: Record) -> Result<bool, anyhow::Error> {
+        let line = serde_json::to_string(&record)?;
+        if !self.remember(record) {
+            return Ok(false);
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{line}")?;
+        Ok(true)
+    }
+
+    pub fn next_offset(&self, source: &str) -> u64 {
+        self.offsets.get(source).copied().unwrap_or_default()
+    }
+
+    pub fn get(&self, dump_id: &str) -> Option<&Record> {
+        self.dumps.get(dump_id)
+    }
+
+    /// The matching records, newest first.
+    pub fn search(&self, query: &Query) -> Vec<&Record> {
+        let mut found: Vec<&Record> = self.dumps.values().filter(|r| query.matches(r)).collect();
+        found.sort_by_key(|r| std::cmp::Reverse(r.timestamp()));
+        found.truncate(query.limit);
+        found
+    }
+
+    pub fn len(&self) -> usize {
+        self.dumps.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::catalog::{decode, Catalog, Query, Record};
+    use serde_json::json;
+    use std::fs;
+
+    fn record(source: &str, offset: u64, id: &str, pod: &str, timestamp: &str) -> Record {
+        Record {
+            source: source.to_string(),
+            offset,
+            event: json!({
+                "dump_id": id,
+                "key": format!("{id}-dump-{timestamp}-{pod}.zip"),
+                "namespace": "mo",
+                "hostname": pod,
+                "exe_name": "mo-service",
+                "signal": "11",
+                "timestamp": timestamp,
+            }),
+        }
+    }
+
+    #[test]
+    fn catalog_test() {
+        let dir = std::env::temp_dir().join(format!("catalog-test-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut catalog = Catalog::load(&dir).unwrap();
+        assert!(catalog
+            .insert(record("10.0.0.1:50051", 0, "a", "mo-0", "1706263200"))
+            .unwrap());
+        assert!(catalog
+            .insert(record("10.0.0.1:50051", 1, "b", "mo-1", "1706263300"))
+            .unwrap());
+        // The same event from a restarted agent pod.
+        assert!(!catalog
+            .insert(record("10.0.0.9:50051", 0, "a", "mo-0", "1706263200"))
+            .unwrap());
+        assert_eq!(catalog.len(), 2);
+        assert_eq!(catalog.next_offset("10.0.0.1:50051"), 2);
+        assert_eq!(catalog.next_offset("10.0.0.9:50051"), 1);
+        assert_eq!(catalog.next_offset("10.0.0.2:50051"), 0);
+
+        let all = catalog.search(&Query::parse("").unwrap());
+        assert_eq!(all[0].dump_id(), Some("b"));
+        let pod = catalog.search(&Query::parse("pod=mo-0").unwrap());
+        assert_eq!(pod.len(), 1);
+        assert_eq!(
+            catalog
+                .search(&Query::parse("since=1706263250").unwrap())
+                .len(),
+            1
+        );
+        assert_eq!(
+            catalog
+                .search(&Query::parse("q=mo-1&namespace=mo").unwrap())
+                .len(),
+            1
+        );
+        assert_eq!(catalog.search(&Query::parse("limit=1").unwrap()).len(), 1);
+        assert_eq!(catalog.search(&Query::parse("signal=6").unwrap()).len(), 0);
+        assert_eq!(catalog.search(&Query::parse("signal=11").unwrap()).len(), 2);
+        assert!(Query::parse("since=yesterday").is_err());
+        assert_eq!(catalog.get("a").unwrap().summary()["pod"], "mo-0");
+        assert_eq!(catalog.get("a").unwrap().summary()["type"], "core-dump");
+        let mut unknown = record("10.0.0.1:50051", 2, "c", "unknown", "1706263400");
+        unknown.event["event_type"] = "unknown-pod".into();
+        assert!(catalog.insert(unknown).unwrap());
+        assert_eq!(
+            catalog
+                .search(&Query::parse("type=core-dump").unwrap())
+                .len(),
+            2
+        );
+        let unknown = catalog.search(&Query::parse("type=unknown-pod").unwrap());
+        assert_eq!(unknown[0].dump_id(), Some("c"));
+
+        let reloaded = Catalog::load(&dir).unwrap();
+        assert_eq!(reloaded.len(), 3);
+        assert_eq!(reloaded.next_offset("10.0.0.1:50051"), 3);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn decode_test() {
+        assert_eq!(decode("mo%2Dservice+x"), "mo-service x");
+        assert_eq!(decode("100%"), "100%");
+        assert_eq!(decode("%zz"), "%zz");
+    }
+}
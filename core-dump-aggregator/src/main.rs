@@ -0,0 +1,92 @@
+//! Optional cluster wide view of the core dumps. It follows the event
+//! stream of every agent serving EVENT_GRPC_ADDR, keeps one catalog of all
+//! of them and serves it over HTTP, see api.rs.
+//!
+//! Configured through the environment:
+//! * AGENT_ADDRS - comma separated `host:port` of the agents. A name is
+//!   resolved on every refresh, so a headless service finds all the pods.
+//! * LISTEN_ADDR - where the API is served (Default 0.0.0.0:8080)
+//! * DATA_DIR - where the catalog is kept (Default the current directory)
+//! * DOWNLOAD_URL - the template `download` redirects to (Default "")
+//! * RESOLVE_INTERVAL - seconds between looking up the agents (Default 30)
+//! * UI_USER, UI_PASSWORD - the login of the web UI, which is only served
+//!   when the password is set (Default user "admin")
+
+mod api;
+mod catalog;
+mod subscribe;
+#[cfg(feature = "ui")]
+mod ui;
+
+use catalog::Catalog;
+use log::{error, info, warn};
+use std::collections::HashSet;
+use std::env;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The agents behind AGENT_ADDRS right now.
+async fn resolve(addrs: &str) -> HashSet<String> {
+    let mut sources = HashSet::new();
+    for addr in addrs.split(',').map(str::trim).filter(|a| !a.is_empty()) {
+        match tokio::net::lookup_host(addr).await {
+            Ok(found) => sources.extend(found.map(|a| a.to_string())),
+            Err(e) => warn!("Resolving {} failed: {}", addr, e),
+        }
+    }
+    sources
+}
+
+#[tokio::main]
+async fn main() -> Result<(), anyhow::Error> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    let agent_addrs = env::var("AGENT_ADDRS").unwrap_or_default();
+    if agent_addrs.is_empty() {
+        return Err(anyhow::anyhow!("AGENT_ADDRS is not set"));
+    }
+    let listen = env::var("LISTEN_ADDR")
+        .unwrap_or_else(|_| "0.0.0.0:8080".to_string())
+        .parse()?;
+    let data_dir = PathBuf::from(env::var("DATA_DIR").unwrap_or_else(|_| ".".to_string()));
+    let download = env::var("DOWNLOAD_URL").unwrap_or_default();
+    let interval = env::var("RESOLVE_INTERVAL")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(30);
+
+    std::fs::create_dir_all(&data_dir)?;
+    let catalog = Arc::new(Mutex::new(Catalog::load(&data_dir)?));
+    info!(
+        "Catalog in {} holds {} dumps",
+        data_dir.display(),
+        catalog.lock().unwrap().len()
+    );
+    let settings = api::Settings {
+        download,
+        #[cfg(feature = "ui")]
+        ui: ui::Ui::from_env(),
+    };
+    tokio::spawn(api::serve(listen, catalog.clone(), settings));
+
+    // Each agent is followed by its own task until its stream ends, then
+    // picked up again on a later refresh if it is still there.
+    let following = Arc::new(Mutex::new(HashSet::new()));
+    loop {
+        for source in resolve(&agent_addrs).await {
+            if !following.lock().unwrap().insert(source.clone()) {
+                continue;
+            }
+            let catalog = catalog.clone();
+            let following = following.clone();
+            tokio::spawn(async move {
+                match subscribe::follow(&source, catalog).await {
+                    Ok(added) => info!("Stream of {} ended after {} new dumps", source, added),
+                    Err(e) => error!("Following {} failed: {}", source, e),
+                }
+                following.lock().unwrap().remove(&source);
+            });
+        }
+        tokio::time::sleep(Duration::from_secs(interval)).await;
+    }
+}
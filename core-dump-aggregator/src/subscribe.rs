@@ -0,0 +1,156 @@
+//! Client of the agents' `SubscribeEvents` gRPC method, see
+//! core-dump-agent/proto/event_service.proto. Each agent is followed from
+//! the offset after the last event the catalog holds from it.
+
+use crate::catalog::{Catalog, Record};
+use hyper::body::HttpBody;
+use hyper::{Body, Client, Request};
+use log::{debug, info};
+use serde_json::Value;
+use std::sync::{Arc, Mutex};
+
+pub const SUBSCRIBE_PATH: &str = "/coredump.v1.EventService/SubscribeEvents";
+
+fn put_varint(buf: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        buf.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+    buf.push(v as u8);
+}
+
+fn get_varint(buf: &[u8], at: &mut usize) -> Option<u64> {
+    let mut v = 0u64;
+    for shift in (0..64).step_by(7) {
+        let b = *buf.get(*at)?;
+        *at += 1;
+        v |= ((b & 0x7f) as u64) << shift;
+        if b < 0x80 {
+            return Some(v);
+        }
+    }
+    None
+}
+
+/// Frames a SubscribeEventsRequest { from_offset = 1 }.
+pub fn encode_request(from_offset: u64) -> Vec<u8> {
+    let mut message = vec![];
+    if from_offset != 0 {
+        put_varint(&mut message, 1 << 3);
+        put_varint(&mut message, from_offset);
+    }
+    let mut framed = vec![0u8];
+    framed.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    framed.extend(message);
+    framed
+}
+
+/// The offset and event of an EventRecord { offset = 1, file = 2,
+/// event_json = 3 } message, without its frame.
+pub fn decode_record(message: &[u8]) -> Option<(u64, Value)> {
+    let mut at = 0;
+    let mut offset = 0;
+    let mut event = None;
+    while at < message.len() {
+        let key = get_varint(message, &mut at)?;
+        match key & 7 {
+            0 => {
+                let v = get_varint(message, &mut at)?;
+                if key >> 3 == 1 {
+                    offset = v;
+                }
+            }
+            2 => {
+                let len = get_varint(message, &mut at)? as usize;
+                let value = message.get(at..at + len)?;
+                at += len;
+                if key >> 3 == 3 {
+                    event = serde_json::from_slice(value).ok();
+                }
+            }
+            _ => return None,
+        }
+    }
+    Some((offset, event?))
+}
+
+/// Takes the complete messages off the front of `buf`.
+pub fn take_messages(buf: &mut Vec<u8>) -> Vec<Vec<u8>> {
+    let mut messages = vec![];
+    while buf.len() >= 5 {
+        let len = u32::from_be_bytes([buf[1], buf[2], buf[3], buf[4]]) as usize;
+        if buf.len() < 5 + len {
+            break;
+        }
+        messages.push(buf[5..5 + len].to_vec());
+        buf.drain(..5 + len);
+    }
+    messages
+}
+
+/// Streams the events of the agent at `source` into the catalog until the
+/// stream ends. Returns the number of new dumps.
+pub async fn follow(source: &str, catalog: Arc<Mutex<Catalog>>) -> Result<usize, anyhow::Error> {
+    let from_offset = catalog.lock().unwrap().next_offset(source);
+    let client = Client::builder().http2_only(true).build_http::<Body>();
+    let request = Request::post(format!("http://{source}{SUBSCRIBE_PATH}"))
+        .header("content-type", "application/grpc")
+        .header("te", "trailers")
+        .body(Body::from(encode_request(from_offset)))?;
+    let response = client.request(request).await?;
+    if let Some(status) = response.headers().get("grpc-status") {
+        if status != "0" {
+            return Err(anyhow::anyhow!(
+                "{} answered grpc-status {:?}",
+                source,
+                status
+            ));
+        }
+    }
+    info!("Following {} from offset {}", source, from_offset);
+    let mut body = response.into_body();
+    let mut buf = vec![];
+    let mut added = 0;
+    while let Some(chunk) = body.data().await {
+        buf.extend_from_slice(&chunk?);
+        for message in take_messages(&mut buf) {
+            let Some((offset, event)) = decode_record(&message) else {
+                debug!("Skipping a malformed record from {}", source);
+                continue;
+            };
+            let record = Record {
+                source: source.to_string(),
+                offset,
+                event,
+            };
+            if catalog.lock().unwrap().insert(record)? {
+                added += 1;
+            }
+        }
+    }
+    Ok(added)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::subscribe::{decode_record, encode_request, take_messages};
+
+    #[test]
+    fn wire_test() {
+        assert_eq!(encode_request(300), vec![0, 0, 0, 0, 3, 0x08, 0xac, 0x02]);
+        assert_eq!(encode_request(0), vec![0, 0, 0, 0, 0]);
+
+        // As framed by the agent for { offset 1, file "a", event_json "{}" }.
+        let mut buf = vec![
+            0, 0, 0, 0, 9, 0x08, 0x01, 0x12, 0x01, b'a', 0x1a, 0x02, b'{', b'}',
+        ];
+        buf.extend([0, 0, 0, 0, 9, 0x08]);
+        let messages = take_messages(&mut buf);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(buf.len(), 6);
+        let (offset, event) = decode_record(&messages[0]).unwrap();
+        assert_eq!(offset, 1);
+        assert_eq!(event, serde_json::json!({}));
+        assert_eq!(decode_record(&[0x12, 0x05, b'a']), None);
+    }
+}
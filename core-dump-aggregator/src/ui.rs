@@ -0,0 +1,131 @@
+//! The web UI, built with the `ui` feature. It is a single page on `/ui`
+//! that lists the catalog through the API, filtered by namespace, signal
+//! and date, and shows the event of a dump with its download link.
+//!
+//! It is only served when UI_PASSWORD is set, and then every path but
+//! `/healthz` asks for it with basic auth, so the page and the API it
+//! reads are protected alike.
+
+use hyper::header::{HeaderMap, AUTHORIZATION, CONTENT_TYPE, WWW_AUTHENTICATE};
+use hyper::{Body, Method, Response, StatusCode};
+use log::info;
+use std::env;
+
+const PAGE: &str = include_str!("ui.html");
+
+pub struct Ui {
+    user: String,
+    password: String,
+}
+
+impl Ui {
+    /// From UI_USER (Default "admin") and UI_PASSWORD, None without a
+    /// password.
+    pub fn from_env() -> Option<Ui> {
+        let password = env::var("UI_PASSWORD").unwrap_or_default();
+        if password.is_empty() {
+            return None;
+        }
+        let user = env::var("UI_USER").unwrap_or_else(|_| "admin".to_string());
+        info!("Serving the web UI on /ui for {}", user);
+        Some(Ui { user, password })
+    }
+
+    fn authorized(&self, headers: &HeaderMap) -> bool {
+        let Some(encoded) = headers
+            .get(AUTHORIZATION)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.strip_prefix("Basic "))
+        else {
+            return false;
+        };
+        let Ok(given) = base64::decode(encoded.trim()) else {
+            return false;
+        };
+        let expected = format!("{}:{}", self.user, self.password);
+        // Compares all of it, so the time taken doesn't tell how much of
+        // a guess was right.
+        given.len() == expected.len()
+            && given
+                .iter()
+                .zip(expected.as_bytes())
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+
+    /// The answer to a request the UI handles itself, the page or a login
+    /// prompt. None passes the request on to the API.
+    pub fn intercept(
+        &self,
+        method: &Method,
+        path: &str,
+        headers: &HeaderMap,
+    ) -> Option<Response<Body>> {
+        if path == "/healthz" {
+            return None;
+        }
+        if !self.authorized(headers) {
+            return Some(
+                Response::builder()
+                    .status(StatusCode::UNAUTHORIZED)
+                    .header(
+                        WWW_AUTHENTICATE,
+                        "Basic realm=\"core-dump-handler\", charset=\"UTF-8\"",
+                    )
+                    .body(Body::empty())
+                    .unwrap(),
+            );
+        }
+        match path.trim_end_matches('/') {
+            "" | "/ui" if method == Method::GET => Some(
+                Response::builder()
+                    .header(CONTENT_TYPE, "text/html; charset=utf-8")
+                    .body(Body::from(PAGE))
+                    .unwrap(),
+            ),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ui::Ui;
+    use hyper::header::{HeaderMap, HeaderValue, AUTHORIZATION};
+    use hyper::{Method, StatusCode};
+
+    #[test]
+    fn intercept_test() {
+        let ui = Ui {
+            user: "admin".to_string(),
+            password: "s3cret".to_string(),
+        };
+        let with = |credentials: &str| {
+            let mut headers = HeaderMap::new();
+            let value = format!("Basic {}", base64::encode(credentials));
+            headers.insert(AUTHORIZATION, HeaderValue::from_str(&value).unwrap());
+            headers
+        };
+        let status = |path: &str, headers: &HeaderMap| {
+            ui.intercept(&Method::GET, path, headers)
+                .map(|r| r.status())
+        };
+
+        assert_eq!(
+            status("/ui", &HeaderMap::new()),
+            Some(StatusCode::UNAUTHORIZED)
+        );
+        assert_eq!(
+            status("/v1/dumps", &with("admin:guess")),
+            Some(StatusCode::UNAUTHORIZED)
+        );
+        assert_eq!(
+            status("/ui", &with("admin:s3cret!")),
+            Some(StatusCode::UNAUTHORIZED)
+        );
+        assert_eq!(status("/ui/", &with("admin:s3cret")), Some(StatusCode::OK));
+        assert_eq!(status("/", &with("admin:s3cret")), Some(StatusCode::OK));
+        assert_eq!(status("/v1/dumps", &with("admin:s3cret")), None);
+        assert_eq!(status("/healthz", &HeaderMap::new()), None);
+    }
+}
@@ -0,0 +1,493 @@
+//! Reads the archives the core dump composer writes, for tooling that
+//! triages captures outside the handler.
+//!
+//! An archive is a tar whose files sit under `core/`: the core itself,
+//! dump-info, the handler config, pod and runtime metadata, logs, the capture
+//! result and, as the last entry, the manifest. [`Archive::open`] indexes the
+//! tar once and parses the JSON documents, the core is streamed out on demand.
+//!
+//! ```no_run
+//! let archive = core_dump_archive::Archive::open("/cores/a.tar")?;
+//! println!("{:?} crashed on signal {:?}", archive.dump_info().exe, archive.dump_info().signal);
+//! archive.extract_core(&mut std::fs::File::create("/tmp/a.core")?)?;
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use anyhow::anyhow;
+use flate2::read::MultiGzDecoder;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+
+/// A file in the archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// The file name without the `core/` directory.
+    pub name: String,
+    pub size: u64,
+    /// Where the content starts in the tar.
+    offset: u64,
+}
+
+/// The fields of dump-info most tooling needs. Everything else is in
+/// [`Archive::dump_info_value`]. Archives of older composers miss some of
+/// them.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct DumpInfo {
+    pub dump_id: Option<String>,
+    pub uuid: Option<String>,
+    /// Seconds since the epoch, as the kernel passed it.
+    pub timestamp: Option<String>,
+    pub hostname: Option<String>,
+    pub node_hostname: Option<String>,
+    pub exe: Option<String>,
+    pub real_pid: Option<String>,
+    pub host_pid: Option<String>,
+    pub signal: Option<String>,
+    pub namespace: Option<String>,
+    pub podname: Option<String>,
+    pub pod_uid: Option<String>,
+    pub sequence: Option<u64>,
+    /// The name of the core in the archive, None for metadata only
+    /// captures.
+    pub dump_file: Option<String>,
+    pub compression: Option<String>,
+    pub data_class: Option<String>,
+    pub build_id: Option<String>,
+    pub signature: Option<String>,
+    /// The executable's path in the process's root, set with
+    /// CAPTURE_BINARIES.
+    pub exe_path: Option<String>,
+    /// The files in the sysroot with their build-ids.
+    pub binaries: Vec<Binary>,
+    /// The capture a `.core.delta` was encoded against.
+    pub delta_base: Option<DeltaBase>,
+}
+
+/// The capture that holds the full core of a delta. The composer keeps the
+/// raw core on the node as `delta-bases/<build_id>.core` until it's evicted.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct DeltaBase {
+    pub build_id: String,
+    pub uuid: String,
+    pub dump_file: String,
+}
+
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct Binary {
+    pub path: String,
+    pub build_id: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CaptureStatus {
+    Success,
+    /// Some stages failed, see [`CaptureResult::errors`].
+    Partial,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct StageError {
+    pub stage: String,
+    pub error: String,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct StageDuration {
+    pub stage: String,
+    pub duration_ms: u64,
+}
+
+/// The capture result the composer writes just before the manifest.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CaptureResult {
+    pub status: CaptureStatus,
+    #[serde(default)]
+    pub errors: Vec<StageError>,
+    #[serde(default)]
+    pub durations: Vec<StageDuration>,
+    /// `utf-8` or `binary` for each log file, by file name.
+    #[serde(default)]
+    pub encodings: BTreeMap<String, String>,
+    #[serde(default)]
+    pub total_duration_ms: u64,
+}
+
+/// How the core is stored, from dump-info.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoreCompression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+pub struct Archive {
+    path: PathBuf,
+    entries: Vec<Entry>,
+    dump_info: DumpInfo,
+    dump_info_value: Value,
+    handler_config: Option<Value>,
+    capture_result: Option<CaptureResult>,
+}
+
+fn file_name(path: &Path) -> String {
+    let path = path.to_string_lossy();
+    path.strip_prefix("core/").unwrap_or(&path).to_string()
+}
+
+impl Archive {
+    /// Indexes the archive at `path` and parses its dump-info, handler
+    /// config and capture result. Fails when there is no dump-info, which
+    /// every handler archive has.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Archive, anyhow::Error> {
+        let path = path.as_ref().to_path_buf();
+        let mut tar = tar::Archive::new(File::open(&path)?);
+        let mut entries = vec![];
+        let mut dump_info = None;
+        let mut native = false;
+        let mut handler_config = None;
+        let mut capture_result = None;
+        for entry in tar.entries()? {
+            let mut entry = entry?;
+            if entry.header().entry_type() != tar::EntryType::Regular {
+                continue;
+            }
+            let name = file_name(&entry.path()?);
+            let json = |entry: &mut tar::Entry<File>| -> Result<Value, anyhow::Error> {
+                let mut content = vec![];
+                entry.read_to_end(&mut content)?;
+                serde_json::from_slice(&content)
+                    .map_err(|e| anyhow!("{} in {} is not JSON: {}", name, path.display(), e))
+            };
+            let offset = entry.raw_file_position();
+            let size = entry.size();
+            // With DUMP_INFO_FORMAT both `-dump-info.json` only holds the
+            // fields of the IBM handler.
+            if name.ends_with("-dump-info-native.json") {
+                dump_info = Some(json(&mut entry)?);
+                native = true;
+            } else if name.ends_with("-dump-info.json") && !native {
+                dump_info = Some(json(&mut entry)?);
+            } else if name.ends_with("-handler-config.json") {
+                handler_config = Some(json(&mut entry)?);
+            } else if name.ends_with("-capture-result.json") {
+                capture_result = Some(serde_json::from_value(json(&mut entry)?)?);
+            }
+            entries.push(Entry { name, size, offset });
+        }
+        let dump_info_value =
+            dump_info.ok_or_else(|| anyhow!("No dump-info in {}", path.display()))?;
+        Ok(Archive {
+            dump_info: serde_json::from_value(dump_info_value.clone())?,
+            dump_info_value,
+            handler_config,
+            capture_result,
+            entries,
+            path,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    pub fn dump_info(&self) -> &DumpInfo {
+        &self.dump_info
+    }
+
+    pub fn dump_info_value(&self) -> &Value {
+        &self.dump_info_value
+    }
+
+    /// The settings the capture ran with, missing in archives of older
+    /// composers.
+    pub fn handler_config(&self) -> Option<&Value> {
+        self.handler_config.as_ref()
+    }
+
+    /// None when the composer stopped before it finished the archive.
+    pub fn capture_result(&self) -> Option<&CaptureResult> {
+        self.capture_result.as_ref()
+    }
+
+    /// The stable id of the capture, the uuid for archives written before
+    /// dump ids.
+    pub fn dump_id(&self) -> Option<&str> {
+        self.dump_info
+            .dump_id
+            .as_deref()
+            .or(self.dump_info.uuid.as_deref())
+    }
+
+    /// The first entry whose name ends with `suffix`, e.g. `-pod-info.json`.
+    pub fn find(&self, suffix: &str) -> Option<&Entry> {
+        self.entries.iter().find(|e| e.name.ends_with(suffix))
+    }
+
+    /// Streams an entry as stored. Files compressed with the shared
+    /// dictionary end in `.zst` and are returned compressed.
+    pub fn reader(&self, entry: &Entry) -> Result<impl Read, anyhow::Error> {
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(entry.offset))?;
+        Ok(file.take(entry.size))
+    }
+
+    pub fn read(&self, entry: &Entry) -> Result<Vec<u8>, anyhow::Error> {
+        let mut content = vec![];
+        self.reader(entry)?.read_to_end(&mut content)?;
+        Ok(content)
+    }
+
+    /// The core, None for metadata only captures.
+    pub fn core(&self) -> Option<&Entry> {
+        let dump_file = self.dump_info.dump_file.as_deref()?;
+        self.entries.iter().find(|e| e.name == dump_file)
+    }
+
+    /// The executable and libraries copied with CAPTURE_BINARIES, each with
+    /// its path in the process's root.
+    pub fn binaries(&self) -> Vec<(&str, &Entry)> {
+        self.entries
+            .iter()
+            .filter_map(|e| {
+                let at = e.name.find("-sysroot/")?;
+                Some((&e.name[at + 8..], e))
+            })
+            .collect()
+    }
+
+    pub fn is_delta(&self) -> bool {
+        !self.dump_info_value["delta_base"].is_null()
+    }
+
+    pub fn core_compression(&self) -> Result<CoreCompression, anyhow::Error> {
+        match self.dump_info.compression.as_deref() {
+            None | Some("none") => Ok(CoreCompression::None),
+            Some("gzip") => Ok(CoreCompression::Gzip),
+            Some("zstd") => Ok(CoreCompression::Zstd),
+            Some(other) => Err(anyhow!("Unknown core compression {}", other)),
+        }
+    }
+
+    /// Writes the uncompressed core into `writer` and returns its size.
+    /// zstd cores go through the `zstd` binary on the PATH. Delta cores
+    /// need their base and are refused, see [`Archive::extract_delta`].
+    pub fn extract_core<W: Write + Send>(&self, writer: &mut W) -> Result<u64, anyhow::Error> {
+        if self.is_delta() {
+            return Err(anyhow!(
+                "{} is a delta against another core, extract it with its base",
+                self.dump_info.dump_file.as_deref().unwrap_or_default()
+            ));
+        }
+        self.decompress_core(writer)
+    }
+
+    /// Writes the uncompressed delta of a delta core into `writer`, which
+    /// rebuilds the core when applied to the raw core of
+    /// [`DumpInfo::delta_base`].
+    pub fn extract_delta<W: Write + Send>(&self, writer: &mut W) -> Result<u64, anyhow::Error> {
+        if !self.is_delta() {
+            return Err(anyhow!("{} holds a full core", self.path.display()));
+        }
+        self.decompress_core(writer)
+    }
+
+    fn decompress_core<W: Write + Send>(&self, writer: &mut W) -> Result<u64, anyhow::Error> {
+        let core = self
+            .core()
+            .ok_or_else(|| anyhow!("No core in {}", self.path.display()))?;
+        let mut reader = self.reader(core)?;
+        let size = match self.core_compression()? {
+            CoreCompression::None => io::copy(&mut reader, writer)?,
+            CoreCompression::Gzip => io::copy(&mut MultiGzDecoder::new(reader), writer)?,
+            CoreCompression::Zstd => unzstd(&mut reader, writer)?,
+        };
+        Ok(size)
+    }
+
+    /// The JSON event the composer wrote for this capture into
+    /// `event_dir`, which lives outside the archive.
+    pub fn event<P: AsRef<Path>>(&self, event_dir: P) -> Result<Option<Value>, anyhow::Error> {
+        let uuid = match self.dump_info.uuid.as_deref() {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+        let path = event_dir.as_ref().join(format!("{uuid}-event.json"));
+        match std::fs::read(&path) {
+            Ok(content) => Ok(Some(serde_json::from_slice(&content)?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(anyhow!("Reading {} failed: {}", path.display(), e)),
+        }
+    }
+}
+
+fn unzstd<R: Read, W: Write + Send>(reader: &mut R, writer: &mut W) -> Result<u64, anyhow::Error> {
+    let mut child = Command::new("zstd")
+        .args(["-q", "-d", "-c"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| anyhow!("Running zstd failed: {}", e))?;
+    let (mut stdin, mut stdout) = match (child.stdin.take(), child.stdout.take()) {
+        (Some(i), Some(o)) => (i, o),
+        _ => return Err(anyhow!("zstd pipes missing")),
+    };
+    let size = thread::scope(|s| {
+        let output = s.spawn(move || io::copy(&mut stdout, writer));
+        let fed = io::copy(reader, &mut stdin);
+        drop(stdin);
+        let size = output
+            .join()
+            .map_err(|_| io::Error::other("zstd output thread panicked"))?;
+        fed.and(size)
+    })?;
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(anyhow!("zstd exited with {}", status));
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Archive, CaptureStatus, CoreCompression};
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::fs;
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    fn write_archive(files: &[(&str, &[u8])]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("archive-{}.tar", uuid::Uuid::new_v4()));
+        let mut builder = tar::Builder::new(fs::File::create(&path).unwrap());
+        for (name, content) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, format!("core/{name}"), *content)
+                .unwrap();
+        }
+        builder.finish().unwrap();
+        path
+    }
+
+    #[test]
+    fn open_test() {
+        let mut gz = GzEncoder::new(vec![], Compression::fast());
+        gz.write_all(b"ELF core").unwrap();
+        let core = gz.finish().unwrap();
+        let dump_info = br#"{"dump_id":"5ad2ea44","uuid":"5ad2ea44","exe":"node","signal":"11","namespace":"mo","sequence":3,"dump_file":"a.core.gz","compression":"gzip","delta_base":null,"arch":"x86_64","binaries":[{"path":"/usr/bin/node","build_id":"4f1e"}]}"#;
+        let capture_result = br#"{"status":"partial","errors":[{"stage":"pod","error":"no crictl"}],"durations":[],"encodings":{"a-0.log":"binary"},"total_duration_ms":12}"#;
+        let path = write_archive(&[
+            ("a.core.gz", &core),
+            ("a-0.log", b"\xff log"),
+            ("a-sysroot/usr/bin/node", b"ELF exe"),
+            ("a-dump-info.json", dump_info),
+            ("a-handler-config.json", br#"{"log_length":500}"#),
+            ("a-capture-result.json", capture_result),
+        ]);
+
+        let archive = Archive::open(&path).unwrap();
+        assert_eq!(archive.entries().len(), 6);
+        assert_eq!(archive.dump_id(), Some("5ad2ea44"));
+        assert_eq!(archive.dump_info().exe.as_deref(), Some("node"));
+        assert_eq!(archive.dump_info().sequence, Some(3));
+        assert_eq!(archive.dump_info_value()["arch"], "x86_64");
+        assert_eq!(archive.handler_config().unwrap()["log_length"], 500);
+        let result = archive.capture_result().unwrap();
+        assert_eq!(result.status, CaptureStatus::Partial);
+        assert_eq!(result.errors[0].stage, "pod");
+        assert_eq!(result.encodings["a-0.log"], "binary");
+
+        let binaries = archive.binaries();
+        assert_eq!(binaries.len(), 1);
+        assert_eq!(binaries[0].0, "/usr/bin/node");
+        assert_eq!(
+            archive.dump_info().binaries[0].build_id.as_deref(),
+            Some("4f1e")
+        );
+        assert_eq!(archive.read(binaries[0].1).unwrap(), b"ELF exe");
+
+        let log = archive.find("-0.log").unwrap();
+        assert_eq!(archive.read(log).unwrap(), b"\xff log");
+        assert_eq!(archive.core_compression().unwrap(), CoreCompression::Gzip);
+        let mut extracted = vec![];
+        assert_eq!(archive.extract_core(&mut extracted).unwrap(), 8);
+        assert_eq!(extracted, b"ELF core");
+
+        let event_dir = std::env::temp_dir().join(format!("events-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&event_dir).unwrap();
+        assert_eq!(archive.event(&event_dir).unwrap(), None);
+        fs::write(event_dir.join("5ad2ea44-event.json"), br#"{"key":"a.tar"}"#).unwrap();
+        assert_eq!(archive.event(&event_dir).unwrap().unwrap()["key"], "a.tar");
+        fs::remove_dir_all(&event_dir).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn delta_test() {
+        let path = write_archive(&[
+            ("a.core.delta", b"CDHDLT01E"),
+            (
+                "a-dump-info.json",
+                br#"{"uuid":"5ad2ea44","dump_file":"a.core.delta","compression":"none","delta_base":{"build_id":"4f1e","uuid":"0f3e9b2c","dump_file":"b.core"}}"#,
+            ),
+        ]);
+        let archive = Archive::open(&path).unwrap();
+        assert!(archive.is_delta());
+        let base = archive.dump_info().delta_base.as_ref().unwrap();
+        assert_eq!(base.uuid, "0f3e9b2c");
+        assert_eq!(base.build_id, "4f1e");
+        assert!(archive.extract_core(&mut vec![]).is_err());
+        let mut delta = vec![];
+        assert_eq!(archive.extract_delta(&mut delta).unwrap(), 9);
+        assert_eq!(delta, b"CDHDLT01E");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn metadata_only_test() {
+        let path = write_archive(&[(
+            "a-dump-info.json",
+            br#"{"uuid":"0f3e9b2c","dump_file":null}"#,
+        )]);
+        let archive = Archive::open(&path).unwrap();
+        assert_eq!(archive.dump_id(), Some("0f3e9b2c"));
+        assert_eq!(archive.core(), None);
+        assert_eq!(archive.capture_result(), None);
+        assert!(archive.extract_core(&mut vec![]).is_err());
+        fs::remove_file(&path).unwrap();
+
+        let path = write_archive(&[
+            (
+                "a-dump-info-native.json",
+                br#"{"dump_id":"5ad2ea44","sequence":3}"#,
+            ),
+            ("a-dump-info.json", br#"{"uuid":"5ad2ea44","dump_file":""}"#),
+        ]);
+        let archive = Archive::open(&path).unwrap();
+        assert_eq!(archive.dump_info().sequence, Some(3));
+        assert_eq!(archive.entries().len(), 2);
+        fs::remove_file(&path).unwrap();
+
+        let path = write_archive(&[("a-0.log", b"log")]);
+        assert!(Archive::open(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,3 @@
+#!/bin/sh
+# Stands in for age, the archive comes out as it went in.
+cat
@@ -0,0 +1,3 @@
+#!/bin/sh
+# Only has to exist for the composer to want a copy of the core.
+exit 0
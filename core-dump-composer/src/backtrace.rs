@@ -0,0 +1,109 @@
+use std::path::Path;
+use std::time::Duration;
+
+/// The event holds the start of the backtrace, the archive all of it.
+pub const EVENT_BYTES: usize = 4096;
+/// A debugger still reading the core after this is killed.
+pub const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The debugger BACKTRACE reads the stacks with, the first found in the
+/// handler's PATH. gdb comes first as it resolves the shared libraries
+/// inside the container's root.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Debugger {
+    Gdb,
+    EuStack,
+}
+
+impl Debugger {
+    pub fn find(path: &str) -> Option<Debugger> {
+        [Debugger::Gdb, Debugger::EuStack].into_iter().find(|d| {
+            path.split(':')
+                .any(|dir| Path::new(dir).join(d.binary()).is_file())
+        })
+    }
+
+    fn binary(&self) -> &'static str {
+        match self {
+            Debugger::Gdb => "gdb",
+            Debugger::EuStack => "eu-stack",
+        }
+    }
+
+    /// The command printing the stack of every thread in `core`. `root` is
+    /// the crashed process's root the libraries are looked up in.
+    pub fn command(&self, exe: &str, core: &str, root: &str) -> Vec<String> {
+        let args: Vec<String> = match self {
+            Debugger::Gdb => vec![
+                "-batch".to_string(),
+                "-nx".to_string(),
+                "-iex".to_string(),
+                "set debuginfod enabled off".to_string(),
+                "-iex".to_string(),
+                format!("set sysroot {root}"),
+                "-ex".to_string(),
+                "thread apply all bt".to_string(),
+                exe.to_string(),
+                core.to_string(),
+            ],
+            Debugger::EuStack => vec!["-e".to_string(), exe.to_string(), format!("--core={core}")],
+        };
+        std::iter::once(self.binary().to_string())
+            .chain(args)
+            .collect()
+    }
+}
+
+/// The backtrace cut to EVENT_BYTES at a line end.
+pub fn truncate(backtrace: &str) -> String {
+    if backtrace.len() <= EVENT_BYTES {
+        return backtrace.to_string();
+    }
+    let mut end = EVENT_BYTES;
+    while !backtrace.is_char_boundary(end) {
+        end -= 1;
+    }
+    let cut = &backtrace[..end];
+    let cut = cut.rfind('\n').map(|at| &cut[..=at]).unwrap_or(cut);
+    format!("{cut}...\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::backtrace::{truncate, Debugger, EVENT_BYTES};
+    use std::fs;
+    use uuid::Uuid;
+
+    #[test]
+    fn debugger_test() {
+        let dir = std::env::temp_dir().join(format!("backtrace-test-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = format!("/nonexistent:{}", dir.display());
+        assert_eq!(Debugger::find(&path), None);
+        fs::write(dir.join("eu-stack"), "").unwrap();
+        assert_eq!(Debugger::find(&path), Some(Debugger::EuStack));
+        fs::write(dir.join("gdb"), "").unwrap();
+        assert_eq!(Debugger::find(&path), Some(Debugger::Gdb));
+        fs::remove_dir_all(&dir).unwrap();
+
+        let gdb = Debugger::Gdb.command("/proc/42/exe", "/tmp/core", "/proc/42/root");
+        assert_eq!(gdb[0], "gdb");
+        assert!(gdb.contains(&"set sysroot /proc/42/root".to_string()));
+        assert_eq!(&gdb[gdb.len() - 2..], ["/proc/42/exe", "/tmp/core"]);
+        assert_eq!(
+            Debugger::EuStack.command("/proc/42/exe", "/tmp/core", "/proc/42/root"),
+            vec!["eu-stack", "-e", "/proc/42/exe", "--core=/tmp/core"]
+        );
+    }
+
+    #[test]
+    fn truncate_test() {
+        assert_eq!(truncate("#0 abort ()\n"), "#0 abort ()\n");
+        let frame = "#1  0x00007f3a in mo::engine::flush () at engine.rs:42\n";
+        let long = frame.repeat(200);
+        let cut = truncate(&long);
+        assert!(cut.len() <= EVENT_BYTES + 4);
+        assert!(cut.ends_with("engine.rs:42\n...\n"));
+        assert!(!truncate(&"é".repeat(EVENT_BYTES)).is_empty());
+    }
+}
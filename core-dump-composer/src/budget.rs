@@ -0,0 +1,136 @@
+use crate::capture::CaptureResult;
+use crate::host::{Clock, SystemClock};
+use log::info;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Percent of TIMEOUT kept for closing the archive, the upload and events.
+const FINISH_SHARE: u32 = 10;
+/// Percent of TIMEOUT kept for the runtime metadata while analyzers run.
+const RUNTIME_SHARE: u32 = 20;
+/// A stage left less than this isn't started.
+const MIN_STAGE: Duration = Duration::from_secs(1);
+
+/// How much a stage matters, most important first. The core comes before
+/// all of them and is always copied, these only start while the time the
+/// more important stages still need is left.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// The binaries, filesystem diff, environment and journal.
+    Proc,
+    /// The crictl inspect, logs, images and OOM correlation.
+    Runtime,
+    /// The collectors of COLLECTORS_FILE.
+    Analyzers,
+}
+
+/// Divides TIMEOUT between the stages of a capture so a slow node drops
+/// the least important files instead of timing out the whole capture.
+#[derive(Clone)]
+pub struct Budget {
+    clock: Arc<dyn Clock>,
+    start: Instant,
+    total: Duration,
+    /// Whether the runtime metadata is still to be read after the
+    /// analyzers, false with IGNORE_CRIO.
+    runtime_ahead: bool,
+}
+
+impl Budget {
+    pub fn new(total: Duration, runtime_ahead: bool) -> Budget {
+        Budget::with_clock(Arc::new(SystemClock), total, runtime_ahead)
+    }
+
+    /// A budget starting now on `clock`.
+    pub fn with_clock(clock: Arc<dyn Clock>, total: Duration, runtime_ahead: bool) -> Budget {
+        Budget {
+            start: clock.now(),
+            clock,
+            total,
+            runtime_ahead,
+        }
+    }
+
+    fn share(&self, percent: u32) -> Duration {
+        self.total * percent / 100
+    }
+
+    pub fn remaining(&self) -> Duration {
+        self.total.saturating_sub(self.clock.elapsed(self.start))
+    }
+
+    /// The time a stage of `priority` may use, what is left less what the
+    /// more important stages after it are kept.
+    pub fn allowance(&self, priority: Priority) -> Duration {
+        let reserve = match priority {
+            Priority::Proc | Priority::Runtime => self.share(FINISH_SHARE),
+            Priority::Analyzers if self.runtime_ahead => {
+                self.share(FINISH_SHARE) + self.share(RUNTIME_SHARE)
+            }
+            Priority::Analyzers => self.share(FINISH_SHARE),
+        };
+        self.remaining().saturating_sub(reserve)
+    }
+
+    /// Why a stage of `priority` isn't started, None when it can run.
+    pub fn skip(&self, priority: Priority) -> Option<String> {
+        if self.allowance(priority) >= MIN_STAGE {
+            return None;
+        }
+        Some(format!(
+            "skipped with {}s of the {}s capture budget left",
+            self.remaining().as_secs(),
+            self.total.as_secs()
+        ))
+    }
+
+    /// Whether a stage of `priority` can start, recording why not with the
+    /// capture.
+    pub fn allows(&self, result: &mut CaptureResult, stage: &str, priority: Priority) -> bool {
+        match self.skip(priority) {
+            Some(reason) => {
+                info!("Not capturing {}, {}", stage, reason);
+                result.record_error(stage, reason);
+                false
+            }
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::budget::{Budget, Priority};
+    use crate::host::tests::ManualClock;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn allowance_test() {
+        let total = Duration::from_secs(100);
+        let clock = ManualClock::new();
+        let budget = Budget::with_clock(Arc::new(clock.clone()), total, true);
+        let without_runtime = Budget::with_clock(Arc::new(clock.clone()), total, false);
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(budget.allowance(Priority::Proc), Duration::from_secs(30));
+        assert_eq!(
+            budget.allowance(Priority::Analyzers),
+            Duration::from_secs(10)
+        );
+        assert_eq!(budget.skip(Priority::Analyzers), None);
+
+        clock.advance(Duration::from_secs(25));
+        assert_eq!(budget.skip(Priority::Runtime), None);
+        assert_eq!(
+            budget.skip(Priority::Analyzers).unwrap(),
+            "skipped with 15s of the 100s capture budget left"
+        );
+        assert_eq!(without_runtime.skip(Priority::Analyzers), None);
+
+        clock.advance(Duration::from_secs(10));
+        assert!(budget.skip(Priority::Proc).is_some());
+        assert_eq!(budget.remaining(), Duration::from_secs(5));
+        clock.advance(Duration::from_secs(25));
+        assert_eq!(budget.remaining(), Duration::ZERO);
+    }
+}
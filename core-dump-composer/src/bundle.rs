@@ -0,0 +1,644 @@
+use crate::compression::CoreCompression;
+use crate::encrypt::SealedSpool;
+use crate::host::Storage;
+use ring::digest;
+use serde::Serialize;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use tar::{Builder, EntryType, Header};
+
+const BLOCK: u64 = 512;
+const NAME_LEN: usize = 100;
+/// Raised when the manifest changes in a way its readers have to know.
+pub const MANIFEST_SCHEMA: u32 = 1;
+
+/// A file of the archive as the manifest lists it.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    /// The path in the archive, `core/` included.
+    pub name: String,
+    pub size: u64,
+    pub sha256: String,
+    /// On the core's entry, so it can be decoded without dump-info.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compression: Option<CoreCompression>,
+    /// On the core's entry, e.g. `.core.gz` or `.core.delta.zst`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extension: Option<String>,
+}
+
+impl ManifestEntry {
+    fn new(name: String, size: u64, sha256: String) -> ManifestEntry {
+        ManifestEntry {
+            name,
+            size,
+            sha256,
+            compression: None,
+            extension: None,
+        }
+    }
+}
+
+/// The last entry of the archive, every file before it with its size and
+/// digest. An archive without it, or whose files don't match it, wasn't
+/// written to the end.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    pub schema_version: u32,
+    pub files: Vec<ManifestEntry>,
+}
+
+/// Hashes what is written through it.
+pub struct DigestWriter<W> {
+    inner: W,
+    digest: digest::Context,
+}
+
+impl<W: Write> Write for DigestWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.digest.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn hex(digest: digest::Digest) -> String {
+    digest.as_ref().iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Reads exactly `remaining` bytes, zeros once `inner` runs out, and hashes
+/// them.
+struct SizedReader<R> {
+    inner: io::Take<R>,
+    remaining: u64,
+    read: u64,
+    digest: digest::Context,
+}
+
+impl<R: Read> Read for SizedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let want = buf.len().min(self.remaining as usize);
+        if want == 0 {
+            return Ok(0);
+        }
+        let mut n = self.inner.read(&mut buf[..want])?;
+        if n == 0 {
+            buf[..want].fill(0);
+            n = want;
+        } else {
+            self.read += n as u64;
+        }
+        self.remaining -= n as u64;
+        self.digest.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Where the archive is written, the file itself or the stdin of the
+/// command encrypting it into the file.
+pub enum Sink {
+    File(Box<dyn Storage>),
+    Encrypted {
+        stdin: Option<ChildStdin>,
+        /// Collects an entry of unknown size, a pipe can't be seeked back
+        /// to its header.
+        spool: Option<Box<SealedSpool>>,
+        /// Where the spool is created, the staging directory.
+        dir: PathBuf,
+    },
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Sink::File(file) => file.write(buf),
+            Sink::Encrypted {
+                spool: Some(spool), ..
+            } => spool.write(buf),
+            Sink::Encrypted {
+                stdin: Some(stdin), ..
+            } => stdin.write(buf),
+            Sink::Encrypted { .. } => Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "the archive is closed",
+            )),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Sink::File(file) => file.flush(),
+            Sink::Encrypted {
+                stdin: Some(stdin), ..
+            } => stdin.flush(),
+            Sink::Encrypted { .. } => Ok(()),
+        }
+    }
+}
+
+impl Seek for Sink {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Sink::File(file) => file.seek(pos),
+            Sink::Encrypted { .. } => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "an encrypted archive can't be seeked",
+            )),
+        }
+    }
+}
+
+/// The capture archive. Every file is appended under `core/` as soon as it
+/// is collected, so the core only touches the disk once and concurrent
+/// captures don't share a staging directory.
+pub struct Bundle {
+    tar: Builder<Sink>,
+    files: Vec<ManifestEntry>,
+    /// The encryption command with ENCRYPT_RECIPIENTS. The archive file
+    /// stays open here so it is still locked, and not closed for the
+    /// agent, once the command exits.
+    encryption: Option<(Child, String, File)>,
+}
+
+impl Bundle {
+    pub fn new<S: Storage + 'static>(file: S) -> Bundle {
+        Bundle {
+            tar: Builder::new(Sink::File(Box::new(file))),
+            files: vec![],
+            encryption: None,
+        }
+    }
+
+    /// A bundle piped through `command` into `file`, so only its encrypted
+    /// form is written to disk. Entries of unknown size are spooled to
+    /// `dir` sealed, see SealedSpool, until they are complete.
+    pub fn encrypted(file: File, command: &[String], path: &str, dir: &Path) -> io::Result<Bundle> {
+        let mut child = Command::new(&command[0])
+            .args(&command[1..])
+            .env("PATH", path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::from(file.try_clone()?))
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| io::Error::new(e.kind(), format!("starting {}: {e}", command[0])))?;
+        let stdin = child.stdin.take();
+        Ok(Bundle {
+            tar: Builder::new(Sink::Encrypted {
+                stdin,
+                spool: None,
+                dir: dir.to_path_buf(),
+            }),
+            files: vec![],
+            encryption: Some((child, command[0].clone(), file)),
+        })
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.encryption.is_some()
+    }
+
+    fn header(size: u64) -> Header {
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Regular);
+        header.set_size(size);
+        header.set_mode(0o644);
+        header.set_mtime(now());
+        header
+    }
+
+    pub fn append(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        let mut header = Bundle::header(data.len() as u64);
+        let path = format!("core/{name}");
+        self.tar.append_data(&mut header, &path, data)?;
+        self.files.push(ManifestEntry::new(
+            path,
+            data.len() as u64,
+            hex(digest::digest(&digest::SHA256, data)),
+        ));
+        Ok(())
+    }
+
+    /// Appends `size` bytes of `data` under a header written up front, so an
+    /// encrypted archive streams it instead of holding it in memory. Data
+    /// short of `size` is padded with zeros and data past it is left
+    /// unread. Returns how much was read from `data`.
+    pub fn append_sized<R: Read>(&mut self, name: &str, size: u64, data: R) -> io::Result<u64> {
+        let path = format!("core/{name}");
+        let mut reader = SizedReader {
+            inner: data.take(size),
+            remaining: size,
+            read: 0,
+            digest: digest::Context::new(&digest::SHA256),
+        };
+        let mut header = Bundle::header(size);
+        self.tar.append_data(&mut header, &path, &mut reader)?;
+        self.files
+            .push(ManifestEntry::new(path, size, hex(reader.digest.finish())));
+        Ok(reader.read)
+    }
+
+    /// Appends an entry whose size isn't known up front, such as the
+    /// compressed core. `write` streams the content straight into the
+    /// archive and the header is filled in once it returns, an encrypted
+    /// archive can't be seeked so there it is spooled first.
+    /// Returns what `write` returned.
+    pub fn append_stream<F>(&mut self, name: &str, write: F) -> io::Result<u64>
+    where
+        F: FnOnce(&mut DigestWriter<&mut Sink>) -> io::Result<u64>,
+    {
+        let path = format!("core/{name}");
+        if path.len() > NAME_LEN {
+            // GNU long name entry, as append_data writes for long paths.
+            let mut long_name = Bundle::header(path.len() as u64 + 1);
+            long_name.set_entry_type(EntryType::GNULongName);
+            if let Some(gnu) = long_name.as_gnu_mut() {
+                gnu.name[..13].copy_from_slice(b"././@LongLink");
+            }
+            long_name.set_cksum();
+            let mut data = path.as_bytes().to_vec();
+            data.push(0);
+            self.tar.append(&long_name, data.as_slice())?;
+        }
+
+        if let Sink::Encrypted { .. } = self.tar.get_mut() {
+            return self.append_buffered(path, write);
+        }
+        let file = self.tar.get_mut();
+        let start = file.stream_position()?;
+        file.write_all(&[0u8; BLOCK as usize])?;
+        let mut out = DigestWriter {
+            inner: &mut *file,
+            digest: digest::Context::new(&digest::SHA256),
+        };
+        let result = write(&mut out)?;
+        let sha256 = hex(out.digest.finish());
+        let end = file.seek(SeekFrom::End(0))?;
+        let size = end - start - BLOCK;
+        let padding = (BLOCK - size % BLOCK) % BLOCK;
+        file.write_all(&vec![0u8; padding as usize])?;
+
+        let header = Bundle::stream_header(&path, size);
+        file.seek(SeekFrom::Start(start))?;
+        file.write_all(header.as_bytes())?;
+        file.seek(SeekFrom::End(0))?;
+        self.files.push(ManifestEntry::new(path, size, sha256));
+        Ok(result)
+    }
+
+    /// The header of a streamed entry, whose long name went in an entry of
+    /// its own.
+    fn stream_header(path: &str, size: u64) -> Header {
+        let mut header = Bundle::header(size);
+        if let Some(gnu) = header.as_gnu_mut() {
+            let name = &path.as_bytes()[..path.len().min(NAME_LEN)];
+            gnu.name[..name.len()].copy_from_slice(name);
+        }
+        header.set_cksum();
+        header
+    }
+
+    /// `append_stream` into an encrypted archive.
+    fn append_buffered<F>(&mut self, path: String, write: F) -> io::Result<u64>
+    where
+        F: FnOnce(&mut DigestWriter<&mut Sink>) -> io::Result<u64>,
+    {
+        if let Sink::Encrypted { spool, dir, .. } = self.tar.get_mut() {
+            *spool = Some(Box::new(SealedSpool::create(dir)?));
+        }
+        let mut out = DigestWriter {
+            inner: self.tar.get_mut(),
+            digest: digest::Context::new(&digest::SHA256),
+        };
+        let result = write(&mut out);
+        let sha256 = hex(out.digest.finish());
+        let spool = match self.tar.get_mut() {
+            Sink::Encrypted { spool, .. } => spool.take(),
+            Sink::File(_) => None,
+        };
+        let result = result?;
+        let spool = spool.ok_or_else(|| io::Error::other("the spool is gone"))?;
+        let size = spool.len;
+        let header = Bundle::stream_header(&path, size);
+        self.tar.append(&header, spool.into_reader()?)?;
+        self.files.push(ManifestEntry::new(path, size, sha256));
+        Ok(result)
+    }
+
+    /// Records how the core appended as `name` is stored in its manifest
+    /// entry.
+    pub fn describe_core(&mut self, name: &str, compression: CoreCompression, extension: &str) {
+        let path = format!("core/{name}");
+        if let Some(entry) = self.files.iter_mut().rev().find(|f| f.name == path) {
+            entry.compression = Some(compression);
+            entry.extension = Some(extension.to_string());
+        }
+    }
+
+    /// Appends the manifest of everything appended so far as `name` and
+    /// ends the archive.
+    pub fn finish(&mut self, name: &str) -> io::Result<()> {
+        let manifest = Manifest {
+            schema_version: MANIFEST_SCHEMA,
+            files: self.files.clone(),
+        };
+        let data = serde_json::to_vec_pretty(&manifest)?;
+        let mut header = Bundle::header(data.len() as u64);
+        self.tar
+            .append_data(&mut header, format!("core/{name}"), data.as_slice())?;
+        self.tar.finish()?;
+        if let Some((child, command, _)) = self.encryption.as_mut() {
+            if let Sink::Encrypted { stdin, .. } = self.tar.get_mut() {
+                // Closing stdin lets the command write out the rest.
+                drop(stdin.take());
+            }
+            let status = child.wait()?;
+            if !status.success() {
+                return Err(io::Error::other(format!("{command} exited with {status}")));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes `<archive>.sha256` beside the finished archive, in the format
+/// `sha256sum -c` reads, and returns the digest.
+pub fn write_checksum(archive: &Path) -> io::Result<String> {
+    let mut file = File::open(archive)?;
+    let mut out = DigestWriter {
+        inner: io::sink(),
+        digest: digest::Context::new(&digest::SHA256),
+    };
+    io::copy(&mut file, &mut out)?;
+    let sha256 = hex(out.digest.finish());
+    let name = archive
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    fs::write(checksum_path(archive), format!("{sha256}  {name}\n"))?;
+    Ok(sha256)
+}
+
+pub fn checksum_path(archive: &Path) -> PathBuf {
+    let mut path = archive.as_os_str().to_owned();
+    path.push(".sha256");
+    PathBuf::from(path)
+}
+
+/// `<archive>.dump-info.json`, the plaintext dump-info written beside an
+/// encrypted archive so the agent can tag and key it without the private
+/// key.
+pub fn dump_info_path(archive: &Path) -> PathBuf {
+    let mut path = archive.as_os_str().to_owned();
+    path.push(".dump-info.json");
+    PathBuf::from(path)
+}
+
+/// The working directory of one invocation, `WORK_DIR/<uuid>`. It is
+/// removed with everything in it when the capture ends, however it ends, and
+/// never touches the directories of captures running alongside it.
+pub struct StagingDir {
+    path: PathBuf,
+}
+
+impl StagingDir {
+    pub fn create(path: PathBuf) -> io::Result<StagingDir> {
+        fs::create_dir_all(&path)?;
+        Ok(StagingDir { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for StagingDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::budget::{Budget, Priority};
+    use crate::bundle::{checksum_path, write_checksum, Bundle, StagingDir};
+    use crate::compression::CoreCompression;
+    use crate::host::tests::{ManualClock, TestDisk};
+    use std::fs;
+    use std::fs::File;
+    use std::io::{Read, Write};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tar::Archive;
+    use uuid::Uuid;
+
+    #[test]
+    fn stream_entry_test() {
+        let path = std::env::temp_dir().join(format!("bundle-test-{}.tar", Uuid::new_v4()));
+        let long_name = format!(
+            "{}-dump-1700000000-node-mo-service-1-6.core.gz",
+            Uuid::new_v4()
+        );
+        let core = vec![3u8; 1000];
+        {
+            let mut bundle = Bundle::new(File::create(&path).unwrap());
+            bundle.append("a-dump-info.json", b"{}").unwrap();
+            let read = bundle
+                .append_stream(&long_name, |f| {
+                    f.write_all(&core)?;
+                    Ok(7)
+                })
+                .unwrap();
+            assert_eq!(read, 7);
+            bundle.describe_core(&long_name, CoreCompression::Gzip, ".core.gz");
+            bundle.append_stream("empty.core", |_| Ok(0)).unwrap();
+            bundle.append("a-0.log", b"log").unwrap();
+            bundle.finish("a-manifest.json").unwrap();
+        }
+
+        let mut archive = Archive::new(File::open(&path).unwrap());
+        let mut entries = vec![];
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let name = entry.path().unwrap().to_string_lossy().to_string();
+            let mut data = vec![];
+            entry.read_to_end(&mut data).unwrap();
+            entries.push((name, data));
+        }
+        assert_eq!(entries.len(), 5);
+        assert_eq!(entries[0].0, "core/a-dump-info.json");
+        assert_eq!(entries[1].0, format!("core/{long_name}"));
+        assert_eq!(entries[1].1, core);
+        assert_eq!(entries[2], ("core/empty.core".to_string(), vec![]));
+        assert_eq!(entries[3], ("core/a-0.log".to_string(), b"log".to_vec()));
+
+        // The manifest comes last and lists every entry before it.
+        assert_eq!(entries[4].0, "core/a-manifest.json");
+        let manifest: serde_json::Value = serde_json::from_slice(&entries[4].1).unwrap();
+        assert_eq!(manifest["schema_version"], 1);
+        let files = manifest["files"].as_array().unwrap();
+        assert_eq!(files.len(), 4);
+        assert_eq!(files[1]["name"], format!("core/{long_name}"));
+        assert_eq!(files[1]["size"], 1000);
+        assert_eq!(files[1]["compression"], "gzip");
+        assert_eq!(files[1]["extension"], ".core.gz");
+        assert!(files[0].get("compression").is_none());
+        assert_eq!(
+            files[2]["sha256"],
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            files[3]["sha256"],
+            "836ff184e7b41b1e13cb5fd89fa1de98dbbab99e9d2918913ff43b86a5c7c213"
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn encrypted_test() {
+        let path = std::env::temp_dir().join(format!("bundle-test-{}.tar", Uuid::new_v4()));
+        let bin_path = std::env::var("PATH").unwrap();
+        // cat stands in for age, the archive comes out as it went in.
+        let cat = ["cat".to_string()];
+        let dir = StagingDir::create(path.with_extension("staging")).unwrap();
+        let mut bundle =
+            Bundle::encrypted(File::create(&path).unwrap(), &cat, &bin_path, dir.path()).unwrap();
+        bundle.append("a-dump-info.json", b"{}").unwrap();
+        // Larger than a sealed chunk of the spool.
+        let core: Vec<u8> = (0..200_000).map(|i| (i % 251) as u8).collect();
+        let read = bundle
+            .append_stream("a.core.gz", |f| {
+                f.write_all(&core)?;
+                Ok(5)
+            })
+            .unwrap();
+        assert_eq!(read, 5);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+        bundle.finish("a-manifest.json").unwrap();
+
+        let mut archive = Archive::new(File::open(&path).unwrap());
+        let entries: Vec<(String, Vec<u8>)> = archive
+            .entries()
+            .unwrap()
+            .map(|e| {
+                let mut e = e.unwrap();
+                let mut data = vec![];
+                e.read_to_end(&mut data).unwrap();
+                (e.path().unwrap().display().to_string(), data)
+            })
+            .collect();
+        assert_eq!(entries[1], ("core/a.core.gz".to_string(), core));
+        assert_eq!(entries.len(), 3);
+
+        // A core of known size is streamed, short data is padded.
+        let mut bundle =
+            Bundle::encrypted(File::create(&path).unwrap(), &cat, &bin_path, dir.path()).unwrap();
+        let read = bundle
+            .append_sized("a.core", 1000, &[3u8; 600][..])
+            .unwrap();
+        assert_eq!(read, 600);
+        bundle.finish("a-manifest.json").unwrap();
+        let mut archive = Archive::new(File::open(&path).unwrap());
+        let mut entry = archive.entries().unwrap().next().unwrap().unwrap();
+        let mut data = vec![];
+        entry.read_to_end(&mut data).unwrap();
+        assert_eq!(&data[..600], &[3u8; 600][..]);
+        assert_eq!(&data[600..], &[0u8; 400][..]);
+
+        let failing = ["false".to_string()];
+        let mut bundle = Bundle::encrypted(
+            File::create(&path).unwrap(),
+            &failing,
+            &bin_path,
+            dir.path(),
+        )
+        .unwrap();
+        let _ = bundle.append("a-dump-info.json", b"{}");
+        assert!(bundle.finish("a-manifest.json").is_err());
+        let missing = ["no-such-encrypter".to_string()];
+        assert!(Bundle::encrypted(
+            File::create(&path).unwrap(),
+            &missing,
+            &bin_path,
+            dir.path()
+        )
+        .is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn disk_test() {
+        let clock = ManualClock::new();
+        let mut bundle = Bundle::new(TestDisk::new(4096, &clock, Duration::ZERO));
+        bundle.append("a-dump-info.json", b"{}").unwrap();
+        let e = bundle
+            .append_stream("a.core.gz", |f| {
+                f.write_all(&[3u8; 8192])?;
+                Ok(0)
+            })
+            .unwrap_err();
+        assert_eq!(e.raw_os_error(), Some(libc::ENOSPC));
+
+        // A core written at a second a block leaves no time for the rest.
+        let budget = Budget::with_clock(Arc::new(clock.clone()), Duration::from_secs(60), true);
+        let mut bundle = Bundle::new(TestDisk::new(1 << 20, &clock, Duration::from_secs(1)));
+        assert_eq!(budget.skip(Priority::Proc), None);
+        bundle
+            .append_stream("a.core.gz", |f| {
+                for _ in 0..55 {
+                    f.write_all(&[3u8; 512])?;
+                }
+                Ok(0)
+            })
+            .unwrap();
+        assert!(budget.skip(Priority::Proc).is_some());
+    }
+
+    #[test]
+    fn checksum_test() {
+        let path = std::env::temp_dir().join(format!("bundle-test-{}.tar", Uuid::new_v4()));
+        fs::write(&path, b"log").unwrap();
+        let sha256 = write_checksum(&path).unwrap();
+        assert_eq!(
+            sha256,
+            "836ff184e7b41b1e13cb5fd89fa1de98dbbab99e9d2918913ff43b86a5c7c213"
+        );
+        let sidecar = checksum_path(&path);
+        assert!(sidecar.to_string_lossy().ends_with(".tar.sha256"));
+        let name = path.file_name().unwrap().to_string_lossy();
+        assert_eq!(
+            fs::read_to_string(&sidecar).unwrap(),
+            format!("{sha256}  {name}\n")
+        );
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&sidecar).unwrap();
+    }
+
+    #[test]
+    fn staging_dir_test() {
+        let work_dir = std::env::temp_dir().join(format!("staging-test-{}", Uuid::new_v4()));
+        let first = StagingDir::create(work_dir.join("a")).unwrap();
+        let second = StagingDir::create(work_dir.join("b")).unwrap();
+        fs::write(first.path().join("core.raw"), b"raw").unwrap();
+        fs::write(second.path().join("core.raw"), b"raw").unwrap();
+        drop(first);
+        assert!(!work_dir.join("a").exists());
+        assert!(second.path().join("core.raw").exists());
+        drop(second);
+        fs::remove_dir_all(&work_dir).unwrap();
+    }
+}
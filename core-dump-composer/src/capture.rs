@@ -0,0 +1,437 @@
+use log::error;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::Display;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// The suffix of the record an abandoned capture leaves in the core
+/// directory, after its dump id.
+pub const FAILURE_SUFFIX: &str = ".failure.json";
+
+/// Files of the capture outside the archive and the archive itself, listed
+/// in the failure record when the capture is abandoned. Global as a timed
+/// out capture still holds its result on the other thread.
+static ARTIFACTS: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+pub fn record_artifact(path: impl Into<PathBuf>) {
+    if let Ok(mut artifacts) = ARTIFACTS.lock() {
+        artifacts.push(path.into());
+    }
+}
+
+/// The recorded artifacts that are still on disk.
+pub fn artifacts() -> Vec<String> {
+    ARTIFACTS
+        .lock()
+        .map(|a| {
+            a.iter()
+                .filter(|p| p.exists())
+                .map(|p| p.display().to_string())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// The composer's exit codes. They are stable, monitoring and the kernel's
+/// log tell the kinds of failure apart by them.
+#[derive(Serialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum ExitCode {
+    /// An error that has no stage.
+    Failed = 1,
+    /// The arguments were malformed, clap's own code.
+    Usage = 2,
+    /// The .env, a spooled capture's manifest or a replay couldn't be read.
+    Config = 3,
+    /// composer.log couldn't be opened.
+    Logger = 10,
+    /// The container runtime couldn't be reached or answered with nonsense.
+    Runtime = 11,
+    /// The core couldn't be read or stored.
+    Core = 12,
+    /// The archive or the staging directory couldn't be written.
+    Archive = 13,
+    /// A write failed with the filesystem or a quota full, in any stage.
+    DiskFull = 14,
+    /// The capture ran past TIMEOUT.
+    Timeout = 32,
+}
+
+impl ExitCode {
+    pub fn of_stage(stage: &str) -> ExitCode {
+        match stage {
+            "logger" => ExitCode::Logger,
+            "pod" | "ps" | "inspectp" => ExitCode::Runtime,
+            "core" | "delta" | "minidump" => ExitCode::Core,
+            "archive" | "staging" | "proc" | "fs_diff" | "node_info" => ExitCode::Archive,
+            _ => ExitCode::Failed,
+        }
+    }
+
+    pub fn code(self) -> i32 {
+        self as i32
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum CaptureStatus {
+    Success,
+    Partial,
+}
+
+#[derive(Serialize)]
+pub struct StageError {
+    pub stage: String,
+    pub error: String,
+}
+
+/// How the bytes of a log file in the archive should be read. Logs are
+/// stored exactly as captured, so a binary log is not valid UTF-8.
+#[derive(Serialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    #[serde(rename = "utf-8")]
+    Utf8,
+    Binary,
+}
+
+impl Encoding {
+    pub fn of(data: &[u8]) -> Encoding {
+        match std::str::from_utf8(data) {
+            Ok(_) => Encoding::Utf8,
+            Err(_) => Encoding::Binary,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct StageDuration {
+    pub stage: String,
+    pub duration_ms: u64,
+    /// Wall clock start and end, for the spans in trace.rs.
+    #[serde(skip)]
+    pub span: (SystemTime, SystemTime),
+}
+
+/// The outcome of a single capture. It is written just before the manifest
+/// that ends the archive so consumers can tell a complete archive from a
+/// degraded one.
+#[derive(Serialize)]
+pub struct CaptureResult {
+    pub status: CaptureStatus,
+    pub errors: Vec<StageError>,
+    pub durations: Vec<StageDuration>,
+    /// The encoding of each log file, by file name.
+    pub encodings: BTreeMap<String, Encoding>,
+    pub total_duration_ms: u64,
+    #[serde(skip)]
+    started: Instant,
+    #[serde(skip)]
+    pub started_at: SystemTime,
+}
+
+impl CaptureResult {
+    pub fn new() -> CaptureResult {
+        CaptureResult {
+            status: CaptureStatus::Success,
+            errors: vec![],
+            durations: vec![],
+            encodings: BTreeMap::new(),
+            total_duration_ms: 0,
+            started: Instant::now(),
+            started_at: SystemTime::now(),
+        }
+    }
+
+    /// Records a non fatal error. The capture carries on but the archive is
+    /// marked as partial.
+    pub fn record_error(&mut self, stage: &str, error: impl Display) {
+        self.status = CaptureStatus::Partial;
+        self.errors.push(StageError {
+            stage: stage.to_string(),
+            error: error.to_string(),
+        });
+    }
+
+    pub fn record_duration(&mut self, stage: &str, since: Instant) {
+        let elapsed = since.elapsed();
+        let end = SystemTime::now();
+        self.durations.push(StageDuration {
+            stage: stage.to_string(),
+            duration_ms: elapsed.as_millis() as u64,
+            span: (end.checked_sub(elapsed).unwrap_or(end), end),
+        });
+    }
+
+    pub fn record_encoding(&mut self, name: &str, data: &[u8]) {
+        self.encodings.insert(name.to_string(), Encoding::of(data));
+    }
+
+    pub fn render(&mut self) -> Result<String, serde_json::Error> {
+        self.total_duration_ms = self.started.elapsed().as_millis() as u64;
+        serde_json::to_string(&self)
+    }
+}
+
+/// The stage a fatal error happened in, the outermost context of the error
+/// that ends a capture.
+#[derive(Debug)]
+pub struct Stage(pub &'static str);
+
+impl Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+pub trait StageContext<T> {
+    fn stage(self, stage: &'static str) -> Result<T, anyhow::Error>;
+}
+
+impl<T, E: Into<anyhow::Error>> StageContext<T> for Result<T, E> {
+    fn stage(self, stage: &'static str) -> Result<T, anyhow::Error> {
+        self.map_err(|e| e.into().context(Stage(stage)))
+    }
+}
+
+/// A capture that had to be abandoned, carried to the single exit handler
+/// in main together with what was recorded up to that point.
+pub struct Failure {
+    pub stage: &'static str,
+    pub error: anyhow::Error,
+    pub result: CaptureResult,
+}
+
+impl Failure {
+    pub fn new(error: anyhow::Error, result: CaptureResult) -> Failure {
+        let stage = error
+            .downcast_ref::<Stage>()
+            .map(|s| s.0)
+            .unwrap_or("setup");
+        Failure {
+            stage,
+            error,
+            result,
+        }
+    }
+
+    /// A full disk is told apart from the stage's other failures, it is
+    /// fixed on the node rather than in the config.
+    pub fn exit_code(&self) -> ExitCode {
+        let disk_full = self.error.chain().any(|e| {
+            e.downcast_ref::<io::Error>().is_some_and(|e| {
+                matches!(
+                    e.kind(),
+                    io::ErrorKind::StorageFull | io::ErrorKind::QuotaExceeded
+                )
+            })
+        });
+        if disk_full {
+            ExitCode::DiskFull
+        } else {
+            ExitCode::of_stage(self.stage)
+        }
+    }
+
+    pub fn report(&self, dump_id: &str) -> FailureReport {
+        let causes = self.causes();
+        FailureReport {
+            dump_id: dump_id.to_string(),
+            stage: self.stage.to_string(),
+            kind: self.exit_code(),
+            exit_code: self.exit_code().code(),
+            error: causes.first().cloned().unwrap_or_default(),
+            causes,
+            artifacts: artifacts(),
+            errors: self
+                .result
+                .errors
+                .iter()
+                .map(|e| StageError {
+                    stage: e.stage.clone(),
+                    error: e.error.clone(),
+                })
+                .collect(),
+            failed_at: now(),
+        }
+    }
+
+    /// The error chain below the stage, outermost first.
+    pub fn causes(&self) -> Vec<String> {
+        let skip = usize::from(self.error.downcast_ref::<Stage>().is_some());
+        self.error
+            .chain()
+            .skip(skip)
+            .map(|e| e.to_string())
+            .collect()
+    }
+
+    pub fn log(&self) {
+        crate::logging::set_context("stage", self.stage);
+        let causes = self.causes();
+        error!(
+            "Capture failed in stage {} after {}ms: {}",
+            self.stage,
+            self.result.started.elapsed().as_millis(),
+            causes.first().map(|c| c.as_str()).unwrap_or_default()
+        );
+        for cause in causes.iter().skip(1) {
+            error!("  caused by: {}", cause);
+        }
+        for stage in &self.result.durations {
+            error!("  {} completed in {}ms", stage.stage, stage.duration_ms);
+        }
+        for stage in &self.result.errors {
+            error!("  {} failed earlier: {}", stage.stage, stage.error);
+        }
+    }
+}
+
+/// What `{dump_id}.failure.json` holds, for monitoring to pick up without
+/// reading composer.log.
+#[derive(Serialize)]
+pub struct FailureReport {
+    pub dump_id: String,
+    pub stage: String,
+    pub kind: ExitCode,
+    pub exit_code: i32,
+    pub error: String,
+    pub causes: Vec<String>,
+    /// What the capture left behind, a partial archive among them.
+    pub artifacts: Vec<String>,
+    /// The stages that failed earlier without ending the capture.
+    pub errors: Vec<StageError>,
+    pub failed_at: String,
+}
+
+impl FailureReport {
+    /// The capture thread is still running, so only the stage it was in is
+    /// known.
+    pub fn timeout(dump_id: &str, stage: &str, timeout: u32) -> FailureReport {
+        let error = format!("Capture ran past TIMEOUT {timeout}s");
+        FailureReport {
+            dump_id: dump_id.to_string(),
+            stage: stage.to_string(),
+            kind: ExitCode::Timeout,
+            exit_code: ExitCode::Timeout.code(),
+            error: error.clone(),
+            causes: vec![error],
+            artifacts: artifacts(),
+            errors: vec![],
+            failed_at: now(),
+        }
+    }
+
+    /// Writes the record into the core directory `dir`.
+    pub fn write(&self, dir: &Path) -> Result<PathBuf, anyhow::Error> {
+        let path = dir.join(format!("{}{}", self.dump_id, FAILURE_SUFFIX));
+        std::fs::write(&path, serde_json::to_vec_pretty(self)?)?;
+        Ok(path)
+    }
+}
+
+fn now() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as i64);
+    crate::cri::rfc3339(nanos)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::capture::{
+        CaptureResult, CaptureStatus, ExitCode, Failure, FailureReport, StageContext,
+    };
+    use std::time::Instant;
+
+    #[test]
+    fn success_without_errors_test() {
+        let mut result = CaptureResult::new();
+        result.record_duration("core", Instant::now());
+        assert_eq!(result.status, CaptureStatus::Success);
+        let json: serde_json::Value = serde_json::from_str(&result.render().unwrap()).unwrap();
+        assert_eq!(json["status"], "success");
+        assert_eq!(json["durations"][0]["stage"], "core");
+        assert!(json["errors"].as_array().unwrap().is_empty());
+
+        result.record_encoding("a-0.log", b"text\n");
+        result.record_encoding("a-1.log", b"\xff\x00");
+        let json: serde_json::Value = serde_json::from_str(&result.render().unwrap()).unwrap();
+        assert_eq!(json["encodings"]["a-0.log"], "utf-8");
+        assert_eq!(json["encodings"]["a-1.log"], "binary");
+    }
+
+    #[test]
+    fn error_marks_partial_test() {
+        let mut result = CaptureResult::new();
+        result.record_error("inspectp", "crictl failed");
+        assert_eq!(result.status, CaptureStatus::Partial);
+        let json: serde_json::Value = serde_json::from_str(&result.render().unwrap()).unwrap();
+        assert_eq!(json["status"], "partial");
+        assert_eq!(json["errors"][0]["stage"], "inspectp");
+        assert_eq!(json["errors"][0]["error"], "crictl failed");
+    }
+
+    #[test]
+    fn failure_stage_test() {
+        let result: Result<(), std::io::Error> = Err(std::io::Error::other("disk full"));
+        let error = result
+            .map_err(|e| anyhow::Error::new(e).context("writing core.gz"))
+            .stage("core")
+            .unwrap_err();
+        let failure = Failure::new(error, CaptureResult::new());
+        assert_eq!(failure.stage, "core");
+        assert_eq!(failure.causes(), vec!["writing core.gz", "disk full"]);
+
+        let failure = Failure::new(anyhow::anyhow!("no .env"), CaptureResult::new());
+        assert_eq!(failure.stage, "setup");
+        assert_eq!(failure.causes(), vec!["no .env"]);
+    }
+
+    #[test]
+    fn exit_code_test() {
+        let failure = Failure::new(
+            Err::<(), _>(anyhow::anyhow!("crictl: connection refused"))
+                .stage("ps")
+                .unwrap_err(),
+            CaptureResult::new(),
+        );
+        assert_eq!(failure.exit_code(), ExitCode::Runtime);
+        assert_eq!(failure.exit_code().code(), 11);
+
+        let mut result = CaptureResult::new();
+        result.record_error("inspectp", "crictl failed");
+        let full: Result<(), std::io::Error> =
+            Err(std::io::Error::from(std::io::ErrorKind::StorageFull));
+        let error = full
+            .map_err(|e| anyhow::Error::new(e).context("writing core.gz"))
+            .stage("core")
+            .unwrap_err();
+        let failure = Failure::new(error, result);
+        assert_eq!(failure.exit_code(), ExitCode::DiskFull);
+        let report = failure.report("5ad2ea44");
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["stage"], "core");
+        assert_eq!(json["kind"], "disk_full");
+        assert_eq!(json["exit_code"], 14);
+        assert_eq!(json["error"], "writing core.gz");
+        assert_eq!(json["errors"][0]["stage"], "inspectp");
+
+        let dir = std::env::temp_dir();
+        let path = FailureReport::timeout("5ad2ea44-timeout-test", "containers", 600)
+            .write(&dir)
+            .unwrap();
+        assert_eq!(path, dir.join("5ad2ea44-timeout-test.failure.json"));
+        let json: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(json["kind"], "timeout");
+        assert_eq!(json["exit_code"], 32);
+        assert_eq!(json["stage"], "containers");
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,217 @@
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+
+/// The container a process belongs to as recorded in `/proc/<pid>/cgroup`.
+#[derive(Serialize, Debug, PartialEq, Eq, Clone)]
+pub struct ContainerIdentity {
+    pub container_id: String,
+    pub pod_uid: Option<String>,
+    pub runtime: Option<String>,
+}
+
+const RUNTIME_PREFIXES: [(&str, &str); 4] = [
+    ("cri-containerd-", "containerd"),
+    ("crio-", "cri-o"),
+    ("docker-", "docker"),
+    ("containerd-", "containerd"),
+];
+
+fn is_container_id(value: &str) -> bool {
+    value.len() == 64 && value.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Extracts a container id from a single path component.
+///
+/// cgroupfs driver: `<id>`
+/// systemd driver: `cri-containerd-<id>.scope`, `crio-<id>.scope`, `docker-<id>.scope`
+fn parse_container_component(component: &str) -> Option<(String, Option<String>)> {
+    let component = component.strip_suffix(".scope").unwrap_or(component);
+    if component.starts_with("crio-conmon-") {
+        return None;
+    }
+    for (prefix, runtime) in RUNTIME_PREFIXES {
+        if let Some(id) = component.strip_prefix(prefix) {
+            if is_container_id(id) {
+                return Some((id.to_string(), Some(runtime.to_string())));
+            }
+        }
+    }
+    if is_container_id(component) {
+        return Some((component.to_string(), None));
+    }
+    None
+}
+
+/// Extracts a pod uid from a single path component.
+///
+/// cgroupfs driver: `pod0c65ce05-bd3a-4db2-ad79-131186dc2086`
+/// systemd driver: `kubepods-burstable-pod0c65ce05_bd3a_4db2_ad79_131186dc2086.slice`
+fn parse_pod_component(component: &str) -> Option<String> {
+    let component = component.strip_suffix(".slice").unwrap_or(component);
+    let start = component.rfind("pod")?;
+    if start != 0 && !component[..start].ends_with('-') {
+        return None;
+    }
+    let uid = component[start + 3..].replace('_', "-");
+    if uid.len() == 36 && uid.chars().all(|c| c.is_ascii_hexdigit() || c == '-') {
+        Some(uid)
+    } else {
+        None
+    }
+}
+
+/// Parses the contents of `/proc/<pid>/cgroup`.
+///
+/// Handles cgroup v1 (`<id>:<controllers>:<path>` per hierarchy), the unified
+/// v2 hierarchy (`0::<path>`) and hybrid systems that list both.
+pub fn parse_cgroup(contents: &str) -> Option<ContainerIdentity> {
+    for line in contents.lines() {
+        let path = match line.splitn(3, ':').nth(2) {
+            Some(v) => v,
+            None => continue,
+        };
+        let mut identity: Option<ContainerIdentity> = None;
+        let mut pod_uid = None;
+        for component in path.split('/') {
+            if let Some(uid) = parse_pod_component(component) {
+                pod_uid = Some(uid);
+            }
+            if let Some((container_id, runtime)) = parse_container_component(component) {
+                identity = Some(ContainerIdentity {
+                    container_id,
+                    pod_uid: None,
+                    runtime,
+                });
+            }
+        }
+        if let Some(mut identity) = identity {
+            identity.pod_uid = pod_uid;
+            return Some(identity);
+        }
+    }
+    None
+}
+
+/// The systemd unit owning a host process, taken from the systemd
+/// hierarchy (`0::` on v2, `name=systemd` on v1), e.g. `sshd.service` for
+/// `/system.slice/sshd.service`. The innermost unit wins so a service
+/// started by a user manager resolves to that service.
+pub fn parse_systemd_unit(contents: &str) -> Option<String> {
+    let mut unified = None;
+    for line in contents.lines() {
+        let mut parts = line.splitn(3, ':');
+        let (controllers, path) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(_), Some(c), Some(p)) => (c, p),
+            _ => continue,
+        };
+        let unit = path
+            .split('/')
+            .rfind(|c| c.ends_with(".service") || c.ends_with(".scope"))
+            .map(|c| c.to_string());
+        if controllers == "name=systemd" {
+            return unit;
+        }
+        if controllers.is_empty() {
+            unified = unit;
+        }
+    }
+    unified
+}
+
+pub fn read_systemd_unit(proc_dir: &Path) -> Option<String> {
+    let contents = fs::read_to_string(proc_dir.join("cgroup")).ok()?;
+    parse_systemd_unit(&contents)
+}
+
+pub fn read_container_identity(proc_dir: &Path) -> Option<ContainerIdentity> {
+    let contents = fs::read_to_string(proc_dir.join("cgroup")).ok()?;
+    parse_cgroup(&contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cgroup::{parse_cgroup, parse_systemd_unit};
+
+    const CONTAINER_ID: &str = "51cd8bdaa13a65518e790d307359d33f9288fc82664879c609029b1a83862db6";
+    const POD_UID: &str = "0c65ce05-bd3a-4db2-ad79-131186dc2086";
+
+    #[test]
+    fn v1_cgroupfs_test() {
+        let sample = "12:pids:/kubepods/burstable/pod0c65ce05-bd3a-4db2-ad79-131186dc2086/51cd8bdaa13a65518e790d307359d33f9288fc82664879c609029b1a83862db6
+11:memory:/kubepods/burstable/pod0c65ce05-bd3a-4db2-ad79-131186dc2086/51cd8bdaa13a65518e790d307359d33f9288fc82664879c609029b1a83862db6
+1:name=systemd:/kubepods/burstable/pod0c65ce05-bd3a-4db2-ad79-131186dc2086/51cd8bdaa13a65518e790d307359d33f9288fc82664879c609029b1a83862db6";
+        let identity = parse_cgroup(sample).unwrap();
+        assert_eq!(identity.container_id, CONTAINER_ID);
+        assert_eq!(identity.pod_uid.as_deref(), Some(POD_UID));
+        assert_eq!(identity.runtime, None);
+    }
+
+    #[test]
+    fn v1_systemd_crio_test() {
+        let sample = "11:cpuset:/kubepods.slice/kubepods-burstable.slice/kubepods-burstable-pod0c65ce05_bd3a_4db2_ad79_131186dc2086.slice/crio-51cd8bdaa13a65518e790d307359d33f9288fc82664879c609029b1a83862db6.scope
+10:devices:/kubepods.slice/kubepods-burstable.slice/kubepods-burstable-pod0c65ce05_bd3a_4db2_ad79_131186dc2086.slice/crio-51cd8bdaa13a65518e790d307359d33f9288fc82664879c609029b1a83862db6.scope";
+        let identity = parse_cgroup(sample).unwrap();
+        assert_eq!(identity.container_id, CONTAINER_ID);
+        assert_eq!(identity.pod_uid.as_deref(), Some(POD_UID));
+        assert_eq!(identity.runtime.as_deref(), Some("cri-o"));
+    }
+
+    #[test]
+    fn v2_systemd_containerd_test() {
+        let sample = "0::/kubepods.slice/kubepods-besteffort.slice/kubepods-besteffort-pod0c65ce05_bd3a_4db2_ad79_131186dc2086.slice/cri-containerd-51cd8bdaa13a65518e790d307359d33f9288fc82664879c609029b1a83862db6.scope\n";
+        let identity = parse_cgroup(sample).unwrap();
+        assert_eq!(identity.container_id, CONTAINER_ID);
+        assert_eq!(identity.pod_uid.as_deref(), Some(POD_UID));
+        assert_eq!(identity.runtime.as_deref(), Some("containerd"));
+    }
+
+    #[test]
+    fn v2_cgroupfs_guaranteed_test() {
+        // Guaranteed pods sit directly under kubepods without a QoS level.
+        let sample = "0::/kubepods/pod0c65ce05-bd3a-4db2-ad79-131186dc2086/51cd8bdaa13a65518e790d307359d33f9288fc82664879c609029b1a83862db6";
+        let identity = parse_cgroup(sample).unwrap();
+        assert_eq!(identity.container_id, CONTAINER_ID);
+        assert_eq!(identity.pod_uid.as_deref(), Some(POD_UID));
+    }
+
+    #[test]
+    fn hybrid_and_docker_test() {
+        let sample = "5:cpu,cpuacct:/system.slice/docker-51cd8bdaa13a65518e790d307359d33f9288fc82664879c609029b1a83862db6.scope
+0::/system.slice/docker-51cd8bdaa13a65518e790d307359d33f9288fc82664879c609029b1a83862db6.scope";
+        let identity = parse_cgroup(sample).unwrap();
+        assert_eq!(identity.container_id, CONTAINER_ID);
+        assert_eq!(identity.pod_uid, None);
+        assert_eq!(identity.runtime.as_deref(), Some("docker"));
+    }
+
+    #[test]
+    fn host_process_test() {
+        assert_eq!(parse_cgroup("0::/system.slice/sshd.service"), None);
+        assert_eq!(
+            parse_cgroup("0::/system.slice/crio-conmon-51cd8bdaa13a65518e790d307359d33f9288fc82664879c609029b1a83862db6.scope"),
+            None
+        );
+        assert_eq!(parse_cgroup(""), None);
+    }
+
+    #[test]
+    fn systemd_unit_test() {
+        assert_eq!(
+            parse_systemd_unit("0::/system.slice/sshd.service").as_deref(),
+            Some("sshd.service")
+        );
+        assert_eq!(
+            parse_systemd_unit("12:pids:/system.slice/kubelet.service\n1:name=systemd:/system.slice/kubelet.service").as_deref(),
+            Some("kubelet.service")
+        );
+        assert_eq!(
+            parse_systemd_unit(
+                "0::/user.slice/user-1000.slice/user@1000.service/app.slice/mo.service"
+            )
+            .as_deref(),
+            Some("mo.service")
+        );
+        assert_eq!(parse_systemd_unit("0::/"), None);
+    }
+}
@@ -0,0 +1,105 @@
+use serde::Serialize;
+use std::fs;
+
+/// The state of the node clock when the core was captured, so events from
+/// different nodes can be checked for drift before they are correlated.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ClockSanity {
+    pub wall_clock_ms: i64,
+    pub monotonic_ms: i64,
+    pub boottime_ms: i64,
+    /// Wall clock boot time derived now minus the boot time the kernel
+    /// recorded at boot. Non zero when the clock was stepped since boot.
+    pub wall_clock_step_ms: Option<i64>,
+    /// Capture start minus the kernel's %t timestamp of the crash.
+    pub capture_delay_ms: Option<i64>,
+    pub ntp_synchronized: Option<bool>,
+    pub ntp_max_error_us: Option<i64>,
+    pub ntp_est_error_us: Option<i64>,
+}
+
+// time_t and c_long are 32 bit on some targets.
+#[allow(clippy::unnecessary_cast)]
+fn clock_ms(clock: libc::clockid_t) -> i64 {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: ts is a valid timespec for the duration of the call.
+    unsafe { libc::clock_gettime(clock, &mut ts) };
+    ts.tv_sec as i64 * 1000 + ts.tv_nsec as i64 / 1_000_000
+}
+
+/// The `btime` line of `/proc/stat`.
+pub fn parse_btime(stat: &str) -> Option<i64> {
+    stat.lines()
+        .find_map(|l| l.strip_prefix("btime "))
+        .and_then(|v| v.trim().parse().ok())
+}
+
+#[allow(clippy::unnecessary_cast)]
+fn ntp_state() -> (Option<bool>, Option<i64>, Option<i64>) {
+    // SAFETY: a zeroed timex with modes 0 only reads the kernel state.
+    let mut tx: libc::timex = unsafe { std::mem::zeroed() };
+    let state = unsafe { libc::adjtimex(&mut tx) };
+    if state < 0 {
+        return (None, None, None);
+    }
+    let synchronized = state != libc::TIME_ERROR && tx.status & libc::STA_UNSYNC == 0;
+    (
+        Some(synchronized),
+        Some(tx.maxerror as i64),
+        Some(tx.esterror as i64),
+    )
+}
+
+pub fn read_clock_sanity(crash_timestamp: &str) -> ClockSanity {
+    let wall_clock_ms = clock_ms(libc::CLOCK_REALTIME);
+    let monotonic_ms = clock_ms(libc::CLOCK_MONOTONIC);
+    let boottime_ms = clock_ms(libc::CLOCK_BOOTTIME);
+    let wall_clock_step_ms = fs::read_to_string("/proc/stat")
+        .ok()
+        .and_then(|s| parse_btime(&s))
+        .map(|btime| (wall_clock_ms - boottime_ms) - btime * 1000);
+    let capture_delay_ms = crash_timestamp
+        .parse::<i64>()
+        .ok()
+        .map(|t| wall_clock_ms - t * 1000);
+    let (ntp_synchronized, ntp_max_error_us, ntp_est_error_us) = ntp_state();
+    ClockSanity {
+        wall_clock_ms,
+        monotonic_ms,
+        boottime_ms,
+        wall_clock_step_ms,
+        capture_delay_ms,
+        ntp_synchronized,
+        ntp_max_error_us,
+        ntp_est_error_us,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clock::{parse_btime, read_clock_sanity};
+
+    #[test]
+    fn parse_btime_test() {
+        let stat = "cpu  2255 34 2290 22625563 6290 127 456\nintr 114930548\nbtime 1706263200\nprocesses 3442\n";
+        assert_eq!(parse_btime(stat), Some(1706263200));
+        assert_eq!(parse_btime("cpu 1 2 3"), None);
+    }
+
+    #[test]
+    fn read_clock_sanity_test() {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let clock = read_clock_sanity(&(now - 2).to_string());
+        assert!(clock.boottime_ms >= clock.monotonic_ms);
+        let delay = clock.capture_delay_ms.unwrap();
+        assert!((1000..5000).contains(&delay));
+        assert!(clock.wall_clock_step_ms.is_some());
+        assert_eq!(read_clock_sanity("").capture_delay_ms, None);
+    }
+}
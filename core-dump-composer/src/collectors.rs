@@ -0,0 +1,207 @@
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const DEFAULT_BUDGET: u64 = 20;
+const DEFAULT_TIMEOUT: u64 = 5;
+/// Output past this is dropped so a chatty collector can't fill the node.
+const MAX_OUTPUT: u64 = 4 << 20;
+
+fn default_budget() -> u64 {
+    DEFAULT_BUDGET
+}
+
+fn default_timeout() -> u64 {
+    DEFAULT_TIMEOUT
+}
+
+/// The `collectors:` section of COLLECTORS_FILE, YAML or JSON. Collectors
+/// run in the order they are listed and share `budget_secs` between them.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct CollectorsConfig {
+    #[serde(default = "default_budget")]
+    pub budget_secs: u64,
+    #[serde(default)]
+    pub collectors: Vec<Collector>,
+}
+
+impl Default for CollectorsConfig {
+    fn default() -> Self {
+        CollectorsConfig {
+            budget_secs: DEFAULT_BUDGET,
+            collectors: vec![],
+        }
+    }
+}
+
+/// A command whose output is added to the archive as
+/// `<name>-collector-<collector>.log`. `{pid}`, `{exe}`, `{namespace}` and
+/// `{podname}` in the command are filled in from the crash.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Collector {
+    pub name: String,
+    pub command: Vec<String>,
+    #[serde(default = "default_timeout")]
+    pub timeout_secs: u64,
+}
+
+/// Parses and checks the collectors file. Errors name the collector and
+/// field at fault.
+pub fn parse(text: &str) -> Result<CollectorsConfig, anyhow::Error> {
+    let config: CollectorsConfig = serde_yaml::from_str(text)?;
+    if config.budget_secs == 0 {
+        return Err(anyhow!("budget_secs must be at least 1"));
+    }
+    let mut names = HashSet::new();
+    for (i, c) in config.collectors.iter().enumerate() {
+        let at = format!("collectors[{i}]");
+        if c.name.is_empty()
+            || !c
+                .name
+                .chars()
+                .all(|ch| ch.is_ascii_alphanumeric() || ch == '-' || ch == '_')
+        {
+            return Err(anyhow!(
+                "{at}.name: {:?} must be letters, digits, - or _",
+                c.name
+            ));
+        }
+        if !names.insert(c.name.as_str()) {
+            return Err(anyhow!("{at}.name: {} is used twice", c.name));
+        }
+        if c.command.is_empty() {
+            return Err(anyhow!("{at}.command: {} has no command", c.name));
+        }
+        if c.timeout_secs == 0 || c.timeout_secs > config.budget_secs {
+            return Err(anyhow!(
+                "{at}.timeout_secs: {} must be between 1 and budget_secs ({})",
+                c.timeout_secs,
+                config.budget_secs
+            ));
+        }
+    }
+    Ok(config)
+}
+
+pub fn render(arg: &str, vars: &[(&str, &str)]) -> String {
+    vars.iter().fold(arg.to_string(), |arg, (key, value)| {
+        arg.replace(&format!("{{{key}}}"), value)
+    })
+}
+
+/// Runs `command` for at most `timeout` and returns its stdout. A command
+/// still running at the deadline is killed.
+pub fn run(command: &[String], timeout: Duration, path: &str) -> Result<Vec<u8>, anyhow::Error> {
+    let mut child = Command::new(&command[0])
+        .args(&command[1..])
+        .env("PATH", path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| anyhow!("starting {}: {}", command[0], e))?;
+    let mut stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| anyhow!("no stdout for {}", command[0]))?;
+    let reader = thread::spawn(move || {
+        let mut output = vec![];
+        let result = (&mut stdout).take(MAX_OUTPUT).read_to_end(&mut output);
+        // Keep draining so the command isn't blocked on a full pipe.
+        let _ = std::io::copy(&mut stdout, &mut std::io::sink());
+        result.map(|_| output)
+    });
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(anyhow!(
+                "{} timed out after {}s",
+                command[0],
+                timeout.as_secs()
+            ));
+        }
+        thread::sleep(Duration::from_millis(20));
+    };
+    let output = reader
+        .join()
+        .map_err(|_| anyhow!("reading {} panicked", command[0]))??;
+    if !status.success() {
+        return Err(anyhow!("{} exited with {}", command[0], status));
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collectors::{parse, render, run};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn parse_test() {
+        let config = parse(
+            "budget_secs: 10
+collectors:
+  - name: threads
+    command: [cat, '/proc/{pid}/status']
+  - name: gstack
+    command: [gstack, '{pid}']
+    timeout_secs: 8
+",
+        )
+        .unwrap();
+        assert_eq!(config.collectors.len(), 2);
+        assert_eq!(config.collectors[0].timeout_secs, 5);
+        assert_eq!(parse("{}").unwrap().budget_secs, 20);
+
+        let err = parse("collectors:\n  - name: a\n    command: [ls]\n    timout_secs: 1\n")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("unknown field `timout_secs`"), "{}", err);
+        let err =
+            parse("collectors:\n  - {name: a, command: [ls]}\n  - {name: a, command: [ls]}\n")
+                .unwrap_err()
+                .to_string();
+        assert_eq!(err, "collectors[1].name: a is used twice");
+        let err = parse("budget_secs: 3\ncollectors:\n  - {name: a, command: [ls]}\n")
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            err,
+            "collectors[0].timeout_secs: 5 must be between 1 and budget_secs (3)"
+        );
+        assert!(parse("collectors:\n  - {name: 'a b', command: [ls]}\n").is_err());
+        assert!(parse("collectors:\n  - {name: a, command: []}\n").is_err());
+    }
+
+    #[test]
+    fn run_test() {
+        assert_eq!(
+            render("/proc/{pid}/{pid}", &[("pid", "42"), ("exe", "node")]),
+            "/proc/42/42"
+        );
+        let path = "/bin:/usr/bin";
+        let echo = vec!["echo".to_string(), "hello".to_string()];
+        assert_eq!(
+            run(&echo, Duration::from_secs(5), path).unwrap(),
+            b"hello\n"
+        );
+        let fail = vec!["false".to_string()];
+        assert!(run(&fail, Duration::from_secs(5), path).is_err());
+        let start = Instant::now();
+        let sleep = vec!["sleep".to_string(), "10".to_string()];
+        let err = run(&sleep, Duration::from_secs(1), path).unwrap_err();
+        assert!(err.to_string().contains("timed out"));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
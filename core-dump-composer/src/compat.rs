@@ -0,0 +1,93 @@
+//! DUMP_INFO_FORMAT, the layouts `-dump-info.json` is written in for
+//! tooling built against the IBM core-dump-handler. Its dump-info holds a
+//! handful of fields, all strings, which the native one carries among many
+//! others but with nulls and numbers where the IBM one has strings.
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::str::FromStr;
+
+/// The fields of the IBM handler's dump-info.
+const IBM_FIELDS: [&str; 9] = [
+    "uuid",
+    "dump_file",
+    "timestamp",
+    "hostname",
+    "exe",
+    "real_pid",
+    "signal",
+    "node_hostname",
+    "path",
+];
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DumpInfoFormat {
+    /// `-dump-info.json` holds every field of the capture.
+    Native,
+    /// `-dump-info.json` holds the IBM fields only.
+    IbmCompat,
+    /// `-dump-info.json` holds the IBM fields and `-dump-info-native.json`
+    /// every field, which the agent and the archive crate read instead.
+    Both,
+}
+
+impl FromStr for DumpInfoFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "" | "native" => Ok(DumpInfoFormat::Native),
+            "ibm-compat" | "ibm" => Ok(DumpInfoFormat::IbmCompat),
+            "both" => Ok(DumpInfoFormat::Both),
+            other => Err(anyhow::anyhow!("Unknown DUMP_INFO_FORMAT {}", other)),
+        }
+    }
+}
+
+/// The IBM dump-info of the `native` one. A missing core is an empty
+/// `dump_file`, numbers are written as strings.
+pub fn ibm(native: &str) -> Result<String, serde_json::Error> {
+    let native: Value = serde_json::from_str(native)?;
+    let fields: Map<String, Value> = IBM_FIELDS
+        .iter()
+        .map(|field| {
+            let value = match &native[field] {
+                Value::String(s) => s.clone(),
+                Value::Null => String::new(),
+                other => other.to_string(),
+            };
+            (field.to_string(), Value::String(value))
+        })
+        .collect();
+    serde_json::to_string(&Value::Object(fields))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compat::{ibm, DumpInfoFormat};
+
+    #[test]
+    fn ibm_test() {
+        let native = r#"{"dump_id":"5ad2ea44","uuid":"5ad2ea44","dump_file":null,"timestamp":"1706263200","exe":"mo-service","real_pid":"11","signal":"6","sequence":3,"oom":{"killed":true}}"#;
+        let compat: serde_json::Value = serde_json::from_str(&ibm(native).unwrap()).unwrap();
+        let fields = compat.as_object().unwrap();
+        assert_eq!(fields.len(), 9);
+        assert_eq!(compat["uuid"], "5ad2ea44");
+        assert_eq!(compat["dump_file"], "");
+        assert_eq!(compat["real_pid"], "11");
+        assert_eq!(compat["node_hostname"], "");
+        assert!(compat.get("dump_id").is_none());
+        assert!(compat.get("sequence").is_none());
+
+        assert_eq!(
+            "ibm-compat".parse::<DumpInfoFormat>().unwrap(),
+            DumpInfoFormat::IbmCompat
+        );
+        assert_eq!(
+            "".parse::<DumpInfoFormat>().unwrap(),
+            DumpInfoFormat::Native
+        );
+        assert!("legacy".parse::<DumpInfoFormat>().is_err());
+    }
+}
@@ -0,0 +1,385 @@
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::Serialize;
+use std::io;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::thread;
+
+/// How the core is stored inside the archive.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CoreCompression {
+    None,
+    Gzip,
+    /// Piped through the `zstd` binary found on the composer's bin path.
+    Zstd,
+}
+
+/// Settings that apply to whichever format compresses the core.
+pub struct CompressOptions<'a> {
+    /// Searched for external compressors.
+    pub bin_path: &'a str,
+    pub level: u32,
+    /// COMP_THREADS, `Some(0)` uses every CPU. When unset gzip writes a
+    /// single stream and zstd picks its own thread count.
+    pub threads: Option<usize>,
+}
+
+/// Whether the `zstd` binary is in one of the `:` separated directories.
+pub fn find_zstd(path: &str) -> bool {
+    path.split(':')
+        .any(|dir| Path::new(dir).join("zstd").is_file())
+}
+
+/// Uncompressed bytes per gzip member when compressing in parallel.
+pub const GZIP_CHUNK_SIZE: usize = 8 << 20;
+
+impl FromStr for CoreCompression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(CoreCompression::None),
+            "gzip" | "gz" => Ok(CoreCompression::Gzip),
+            "zstd" | "zst" => Ok(CoreCompression::Zstd),
+            other => Err(anyhow::anyhow!("Unknown core compression {other}")),
+        }
+    }
+}
+
+impl CoreCompression {
+    /// The suffix appended to the `.core` file name. This is the only place
+    /// that knows which extension belongs to which format.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            CoreCompression::None => "",
+            CoreCompression::Gzip => ".gz",
+            CoreCompression::Zstd => ".zst",
+        }
+    }
+
+    pub fn default_level(&self) -> u32 {
+        match self {
+            CoreCompression::None => 0,
+            // Compression::fast(), cores are large and the pipe is waiting.
+            CoreCompression::Gzip => 1,
+            CoreCompression::Zstd => 3,
+        }
+    }
+
+    /// Checks a COMP_LEVEL against the range of this format, falling back
+    /// to the default when none was requested.
+    pub fn level(&self, requested: Option<u32>) -> Result<u32, anyhow::Error> {
+        let range = match self {
+            CoreCompression::None => 0..=0,
+            CoreCompression::Gzip => 0..=9,
+            CoreCompression::Zstd => 1..=19,
+        };
+        match requested {
+            None => Ok(self.default_level()),
+            Some(_) if *self == CoreCompression::None => Ok(0),
+            Some(level) if range.contains(&level) => Ok(level),
+            Some(level) => Err(anyhow::anyhow!(
+                "Compression level {level} is outside {}..={} for {self:?}",
+                range.start(),
+                range.end()
+            )),
+        }
+    }
+
+    /// Copies the core from `reader` to `writer` in this format and returns
+    /// the number of uncompressed bytes read.
+    pub fn compress<R: Read, W: Write + Send>(
+        &self,
+        reader: &mut R,
+        writer: W,
+        options: &CompressOptions,
+    ) -> io::Result<u64> {
+        match self {
+            CoreCompression::None => {
+                let mut writer = writer;
+                io::copy(reader, &mut writer)
+            }
+            CoreCompression::Gzip if options.threads.is_some_and(|t| t != 1) => {
+                parallel_gzip(reader, writer, options)
+            }
+            CoreCompression::Gzip => {
+                let mut encoder = GzEncoder::new(writer, Compression::new(options.level));
+                let size = io::copy(reader, &mut encoder)?;
+                encoder.finish()?;
+                Ok(size)
+            }
+            CoreCompression::Zstd => zstd(reader, writer, options),
+        }
+    }
+}
+
+fn thread_count(options: &CompressOptions) -> usize {
+    match options.threads {
+        Some(0) | None => thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1),
+        Some(n) => n,
+    }
+}
+
+/// Fills `buf` from `reader` and returns the number of bytes read, short
+/// only at the end of the core.
+fn read_chunk<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// pigz style gzip: the core is cut into GZIP_CHUNK_SIZE chunks that are
+/// compressed on their own threads and written in order as consecutive
+/// gzip members. gunzip and multi member readers such as
+/// flate2::read::MultiGzDecoder decompress the result as one file.
+fn parallel_gzip<R: Read, W: Write>(
+    reader: &mut R,
+    mut writer: W,
+    options: &CompressOptions,
+) -> io::Result<u64> {
+    let threads = thread_count(options);
+    let level = Compression::new(options.level);
+    let mut size = 0u64;
+    loop {
+        let mut chunks = vec![];
+        for _ in 0..threads {
+            let mut chunk = vec![0u8; GZIP_CHUNK_SIZE];
+            let n = read_chunk(reader, &mut chunk)?;
+            if n == 0 {
+                break;
+            }
+            chunk.truncate(n);
+            size += n as u64;
+            chunks.push(chunk);
+        }
+        if chunks.is_empty() {
+            break;
+        }
+        let last = chunks
+            .last()
+            .map(|c| c.len() < GZIP_CHUNK_SIZE)
+            .unwrap_or(true);
+        let members = thread::scope(|s| {
+            let workers: Vec<_> = chunks
+                .iter()
+                .map(|chunk| {
+                    s.spawn(move || {
+                        let mut encoder = GzEncoder::new(vec![], level);
+                        encoder.write_all(chunk)?;
+                        encoder.finish()
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .map(|w| {
+                    w.join()
+                        .map_err(|_| io::Error::other("gzip worker panicked"))?
+                })
+                .collect::<io::Result<Vec<Vec<u8>>>>()
+        })?;
+        for member in members {
+            writer.write_all(&member)?;
+        }
+        if last {
+            break;
+        }
+    }
+    if size == 0 {
+        // An empty core is still a valid gzip file.
+        GzEncoder::new(&mut writer, level).finish()?;
+    }
+    writer.flush()?;
+    Ok(size)
+}
+
+fn zstd<R: Read, W: Write + Send>(
+    reader: &mut R,
+    writer: W,
+    options: &CompressOptions,
+) -> io::Result<u64> {
+    let mut child = Command::new("zstd")
+        .env("PATH", options.bin_path)
+        .args(["-q", "-c"])
+        .arg(format!("-T{}", options.threads.unwrap_or(0)))
+        .arg(format!("-{}", options.level))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let (mut stdin, mut stdout) = match (child.stdin.take(), child.stdout.take()) {
+        (Some(i), Some(o)) => (i, o),
+        _ => return Err(io::Error::other("zstd pipes missing")),
+    };
+    let mut writer = writer;
+    // The output is drained on its own thread so a full pipe can't stall
+    // the core being fed in.
+    let size = thread::scope(|s| {
+        let output = s.spawn(move || io::copy(&mut stdout, &mut writer));
+        let size = io::copy(reader, &mut stdin);
+        drop(stdin);
+        output
+            .join()
+            .map_err(|_| io::Error::other("zstd output thread panicked"))??;
+        size
+    })?;
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("zstd exited with {status}")));
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compression::{find_zstd, CompressOptions, CoreCompression, GZIP_CHUNK_SIZE};
+    use flate2::read::{GzDecoder, MultiGzDecoder};
+    use std::io::{Read, Write};
+    use std::process::{Command, Stdio};
+
+    #[test]
+    fn extension_test() {
+        assert_eq!(CoreCompression::None.extension(), "");
+        assert_eq!(CoreCompression::Gzip.extension(), ".gz");
+        assert_eq!(CoreCompression::Zstd.extension(), ".zst");
+        assert_eq!(
+            "ZSTD".parse::<CoreCompression>().unwrap(),
+            CoreCompression::Zstd
+        );
+        assert_eq!(
+            "gzip".parse::<CoreCompression>().unwrap(),
+            CoreCompression::Gzip
+        );
+        assert!("lz4".parse::<CoreCompression>().is_err());
+    }
+
+    #[test]
+    fn level_test() {
+        assert_eq!(CoreCompression::Gzip.level(None).unwrap(), 1);
+        assert_eq!(CoreCompression::Gzip.level(Some(9)).unwrap(), 9);
+        assert!(CoreCompression::Gzip.level(Some(10)).is_err());
+        assert_eq!(CoreCompression::Zstd.level(None).unwrap(), 3);
+        assert_eq!(CoreCompression::Zstd.level(Some(19)).unwrap(), 19);
+        assert!(CoreCompression::Zstd.level(Some(0)).is_err());
+        assert_eq!(CoreCompression::None.level(Some(9)).unwrap(), 0);
+    }
+
+    #[test]
+    fn zstd_roundtrip_test() {
+        let bin_path = std::env::var("PATH").unwrap_or_default();
+        if !find_zstd(&bin_path) {
+            // The config falls back to gzip before a capture gets here.
+            assert!(!find_zstd(""));
+            return;
+        }
+        let core = vec![7u8; 1 << 20];
+        let mut compressed = vec![];
+        let options = CompressOptions {
+            bin_path: &bin_path,
+            level: 3,
+            threads: Some(2),
+        };
+        let size = CoreCompression::Zstd
+            .compress(&mut core.as_slice(), &mut compressed, &options)
+            .unwrap();
+        assert_eq!(size, 1 << 20);
+        assert_eq!(&compressed[..4], &[0x28, 0xb5, 0x2f, 0xfd]);
+        let mut decoder = Command::new("zstd")
+            .args(["-d", "-q", "-c"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        decoder
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(&compressed)
+            .unwrap();
+        let decoded = decoder.wait_with_output().unwrap().stdout;
+        assert_eq!(decoded, core);
+    }
+
+    #[test]
+    fn gzip_roundtrip_test() {
+        let core = vec![7u8; 4096];
+        let mut compressed = vec![];
+        let options = CompressOptions {
+            bin_path: "",
+            level: 9,
+            threads: None,
+        };
+        let size = CoreCompression::Gzip
+            .compress(&mut core.as_slice(), &mut compressed, &options)
+            .unwrap();
+        assert_eq!(size, 4096);
+        assert!(compressed.len() < core.len());
+        let mut decoded = vec![];
+        GzDecoder::new(compressed.as_slice())
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, core);
+    }
+
+    #[test]
+    fn parallel_gzip_roundtrip_test() {
+        let core: Vec<u8> = (0..GZIP_CHUNK_SIZE * 2 + 100)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let mut compressed = vec![];
+        let options = CompressOptions {
+            bin_path: "",
+            level: 1,
+            threads: Some(2),
+        };
+        let size = CoreCompression::Gzip
+            .compress(&mut core.as_slice(), &mut compressed, &options)
+            .unwrap();
+        assert_eq!(size, core.len() as u64);
+        let mut decoded = vec![];
+        MultiGzDecoder::new(compressed.as_slice())
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, core);
+
+        let mut compressed = vec![];
+        CoreCompression::Gzip
+            .compress(&mut [].as_slice(), &mut compressed, &options)
+            .unwrap();
+        let mut decoded = vec![];
+        MultiGzDecoder::new(compressed.as_slice())
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert!(decoded.is_empty());
+    }
+
+    #[test]
+    fn none_is_verbatim_test() {
+        let core = b"a core".to_vec();
+        let mut out = vec![];
+        CoreCompression::None
+            .compress(
+                &mut core.as_slice(),
+                &mut out,
+                &CompressOptions {
+                    bin_path: "",
+                    level: 0,
+                    threads: None,
+                },
+            )
+            .unwrap();
+        assert_eq!(out, core);
+    }
+}
@@ -1,12 +1,46 @@
 extern crate dotenv;
 
+use crate::cgroup::ContainerIdentity;
+use crate::clock::ClockSanity;
+use crate::collectors::CollectorsConfig;
+use crate::compat::DumpInfoFormat;
+use crate::compression::{self, CompressOptions, CoreCompression};
+use crate::configfile;
+use crate::cri;
+use crate::decision::Decision;
+use crate::delta::{DeltaBase, DEFAULT_MAX_BASES};
+use crate::diskspace;
+use crate::docker;
+use crate::encrypt::Recipients;
+use crate::environ::{CaptureEnv, DEFAULT_MASK_PATTERNS};
+use crate::events::EventFormat;
+use crate::filter::{ExeFilter, NamespaceFilter, SignalFilter};
+use crate::journal::DEFAULT_JOURNAL_MINUTES;
+use crate::logging::{LogFormat, LogRotation};
+use crate::logtarget::{LogTarget, DEFAULT_LOG_IDENTIFIER};
+use crate::mappings::MappingSummary;
+use crate::minidump::CoreFormat;
+use crate::network::NetworkIdentity;
+use crate::oom::OomCorrelation;
+use crate::podlogs::DEFAULT_POD_LOG_DIR;
+use crate::redact::{Redactor, DEFAULT_REDACT_PATTERNS};
+use crate::resources::ContainerResources;
+use crate::runtime::RuntimeKind;
+use crate::selector::Selector;
+use crate::signature::Duplicate;
+use crate::split::{CaptureUser, Manifest};
+use crate::trace::TraceConfig;
+use crate::upload::UploadConfig;
+use crate::volumes::Volume;
+use crate::webhook::WebhookConfig;
 use clap::{App, Arg, ArgMatches};
 use libcrio::ImageCommand;
-use log::error;
-use serde::Serialize;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
 use std::env;
 use std::ffi::OsString;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use tinytemplate::TinyTemplate;
 use uuid::Uuid;
@@ -17,26 +51,177 @@ pub struct CoreConfig {
     pub base_path: PathBuf,
     pub crictl_config_path: PathBuf,
     pub log_level: String,
+    pub log_format: LogFormat,
+    pub log_targets: Vec<LogTarget>,
+    /// The syslog identifier of the composer's syslog and journald lines.
+    pub log_identifier: String,
+    pub log_rotation: LogRotation,
     pub log_length: u32,
-    pub pod_selector_label: String,
+    /// Also copy the kubelet's log files for each container.
+    pub pod_log_files: bool,
+    pub pod_log_dir: PathBuf,
+    /// Holds the staging directory of each invocation. Only the raw delta of
+    /// a delta core is staged since the archive is streamed.
+    pub work_dir: PathBuf,
+    pub upload: Option<UploadConfig>,
+    pub webhook: Option<WebhookConfig>,
+    pub trace: Option<TraceConfig>,
+    pub pod_selector: Selector,
+    /// Why POD_SELECTOR_LABEL was rejected, recorded with the capture.
+    #[serde(skip)]
+    pub pod_selector_error: Option<String>,
+    pub namespace_filter: NamespaceFilter,
+    pub exe_filter: ExeFilter,
+    pub signal_filter: SignalFilter,
+    /// Why SIGNALS was rejected, recorded with the capture.
+    #[serde(skip)]
+    pub signal_filter_error: Option<String>,
+    /// Read the core to the end before exiting for a crash the filters
+    /// skipped, for kernels that log a truncated core otherwise.
+    pub drain_skipped: bool,
     pub use_crio_config: bool,
     pub ignore_crio: bool,
     pub core_events: bool,
+    pub event_format: EventFormat,
+    pub dump_info_format: DumpInfoFormat,
+    pub pause_file: Option<PathBuf>,
+    pub pause_mode: PauseMode,
+    pub paused: Option<PauseMode>,
+    pub decisions_log: PathBuf,
+    /// Full captures a pod's executable gets per hour, None for no limit.
+    pub max_dumps_per_hour: Option<usize>,
+    /// MAX_CONCURRENT_CAPTURES, None for no limit.
+    pub max_concurrent_captures: Option<usize>,
+    pub rate_limit_mode: PauseMode,
+    /// Set when this crash was over the limit.
+    pub rate_limited: Option<PauseMode>,
+    /// UNKNOWN_POD, what happens to a crash the runtime has no pod for.
+    /// None captures it.
+    pub unknown_pod_mode: Option<PauseMode>,
+    /// Set when this crash had no pod and UNKNOWN_POD is metadata-only.
+    pub unknown_pod: Option<PauseMode>,
+    /// MAX_CORE_BYTES, None for no limit.
+    pub max_core_bytes: Option<u64>,
+    pub core_limit_mode: CoreLimitMode,
+    /// Set when this core ran into the limit.
+    pub core_limited: Option<CoreLimitMode>,
+    /// The core's size from its program headers.
+    pub core_size: Option<u64>,
+    /// DISK_RESERVE_PERCENT, the share of the core and work volumes a core
+    /// may not take.
+    pub disk_reserve_percent: u8,
+    /// Set when the core would have left less than the reserve free.
+    pub disk_low: bool,
+    /// DEDUP_WINDOW_MINUTES, how long a full capture stands in for later
+    /// crashes with its signature. None captures every crash.
+    pub dedup_window_minutes: Option<u64>,
     pub timeout: u32,
     pub compression: bool,
+    pub core_compression: CoreCompression,
+    pub compression_level: u32,
+    pub compression_threads: Option<usize>,
+    pub zstd_dictionary: Option<PathBuf>,
+    pub delta_cores: bool,
+    /// DELTA_MAX_BASES and DELTA_MAX_BASE_BYTES, None for no limit.
+    pub delta_max_bases: Option<usize>,
+    pub delta_max_base_bytes: Option<u64>,
+    pub fs_diff: bool,
+    /// PROC_SNAPSHOT, the `-proc.json` and `-maps.txt` of the process.
+    pub proc_snapshot: bool,
+    /// NODE_INFO, the `-node-info.json` of the node.
+    pub node_info: bool,
+    /// DMESG_LINES, how much of the kernel log goes into `-dmesg.txt`, 0
+    /// for none.
+    pub dmesg_lines: usize,
+    /// Also copy the executable and the shared libraries it had mapped, so
+    /// the core can be opened after the image is gone.
+    pub capture_binaries: bool,
+    /// Also read the stacks of the core with gdb or eu-stack into
+    /// `-backtrace.txt`, see backtrace.rs.
+    pub backtrace: bool,
+    /// CORE_FORMAT, the core, a minidump of it or both, see minidump.rs.
+    pub core_format: CoreFormat,
+    /// Set when the minidump was written.
+    pub minidump_file: Option<String>,
+    /// ENCRYPT_RECIPIENTS, the keys the archive is encrypted to.
+    pub encrypt: Option<Recipients>,
+    /// Where the executable lives inside the process's root.
+    pub exe_path: Option<String>,
+    /// The files copied into the sysroot of the archive.
+    pub binaries: Vec<Binary>,
+    pub build_id: Option<String>,
+    pub delta_base: Option<DeltaBase>,
+    pub mapping_summary: Option<MappingSummary>,
     pub event_location: PathBuf,
     pub image_command: ImageCommand,
+    pub container_runtime: RuntimeKind,
+    /// The CRI socket read with CONTAINER_RUNTIME=cri.
+    pub cri_endpoint: String,
+    /// The Docker Engine socket read with CONTAINER_RUNTIME=docker.
+    pub docker_endpoint: String,
+    pub container_scope: ContainerScope,
     pub bin_path: String,
     pub os_hostname: String,
+    pub node_ip: Option<String>,
     pub filename_template: String,
+    pub container_identity: Option<ContainerIdentity>,
+    /// The systemd unit of a crashing host process.
+    pub systemd_unit: Option<String>,
+    /// Minutes of the unit's journal added to host process captures.
+    pub journal_minutes: u32,
+    pub capture_env: CaptureEnv,
+    pub env_mask_patterns: Vec<String>,
+    /// REDACT_PATTERNS, applied to the JSON of the archive and to events.
+    pub redact: Redactor,
+    pub collectors: CollectorsConfig,
+    /// Split the capture: the kernel-invoked composer only spools the core
+    /// and starts a worker as this user for the rest.
+    pub capture_user: Option<CaptureUser>,
+    #[serde(skip)]
+    pub capture_user_error: Option<String>,
+    /// The spooled capture this worker is processing, from `--spool`.
+    pub spool: Option<PathBuf>,
+    /// The manifest of that capture.
+    #[serde(skip)]
+    pub manifest: Option<Manifest>,
+    /// The capture this one replays, from `replay <spool-id>`.
+    pub replay_of: Option<Uuid>,
+    /// Leave spooled captures in place for `replay` once the worker is done.
+    pub keep_spool: bool,
+    /// Why COLLECTORS_FILE was rejected, recorded with the capture.
+    #[serde(skip)]
+    pub collectors_error: Option<String>,
+    /// Go through the pod lookup, filters and runtime queries, logging what
+    /// would be captured, but discard the core and write no archive.
+    pub dry_run: bool,
+    /// Confine the composer with seccomp and Landlock before the capture.
+    pub sandbox: bool,
+    /// Why COMP_ALGO couldn't be used, recorded with the capture.
+    #[serde(skip)]
+    pub compression_error: Option<String>,
+    /// Why the sandbox couldn't be applied in full, recorded with the capture.
+    #[serde(skip)]
+    pub sandbox_error: Option<String>,
+    /// The settings of CDC_CONFIG_FILE that were invalid and left out.
+    #[serde(skip)]
+    pub config_file_error: Option<String>,
     pub params: CoreParams,
 }
 
+/// An executable or library copied with CAPTURE_BINARIES. The agent uploads
+/// it to the symbol store under its build-id.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Binary {
+    pub path: String,
+    pub build_id: Option<String>,
+}
+
 #[derive(Serialize)]
 pub struct CoreParams {
     pub limit_size: String,
     pub exe_name: String,
     pub pid: String,
+    pub host_pid: String,
     pub signal: String,
     pub timestamp: String,
     pub directory: String,
@@ -44,29 +229,159 @@ pub struct CoreParams {
     pub pathname: String,
     pub namespace: Option<String>,
     pub podname: Option<String>,
+    pub pod_uid: Option<String>,
+    /// From the node wide counter, see sequence.rs. Tells apart captures
+    /// that would otherwise render the same name.
+    pub sequence: u64,
+    pub data_class: Option<String>,
+    pub volumes: Vec<Volume>,
+    pub network: Option<NetworkIdentity>,
+    pub clock: Option<ClockSanity>,
+    pub oom: Option<OomCorrelation>,
+    /// The CPU and memory requests and limits of the crashed container.
+    pub resources: Option<ContainerResources>,
+    pub decision: Decision,
+    /// See signature.rs.
+    pub signature: Option<String>,
+    /// Set when the crash was not captured as it repeats a recent one.
+    pub duplicate: Option<Duplicate>,
+    /// Set when the runtime had no pod for the crash, see UNKNOWN_POD.
+    pub unknown_pod: bool,
+    /// The start of `-backtrace.txt` with BACKTRACE.
+    pub backtrace: Option<String>,
+    /// The digest of the finished archive, also in `<archive>.sha256`.
+    pub archive_sha256: Option<String>,
     pub uuid: Uuid,
 }
 
-static DEFAULT_TEMPLATE: &str = "{uuid}-dump-{timestamp}-{hostname}-{exe_name}-{pid}-{signal}";
+/// What the composer does with a crash while PAUSE_FILE exists, or with one
+/// over MAX_DUMPS_PER_HOUR.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum PauseMode {
+    /// Capture everything but the core itself.
+    MetadataOnly,
+    /// Exit without writing anything.
+    Skip,
+}
+
+impl FromStr for PauseMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "metadata" | "metadata-only" => Ok(PauseMode::MetadataOnly),
+            "skip" => Ok(PauseMode::Skip),
+            other => Err(anyhow::anyhow!("Unknown pause mode {other}")),
+        }
+    }
+}
+
+/// What the composer does with a core larger than MAX_CORE_BYTES.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum CoreLimitMode {
+    /// Capture everything but the core, read to the end and thrown away.
+    Skip,
+    /// Keep the first MAX_CORE_BYTES of the core.
+    Truncate,
+}
+
+impl FromStr for CoreLimitMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "skip" => Ok(CoreLimitMode::Skip),
+            "truncate" => Ok(CoreLimitMode::Truncate),
+            other => Err(anyhow::anyhow!("Unknown core limit mode {other}")),
+        }
+    }
+}
+
+/// Which containers of the pod a capture records, set with CONTAINER_SCOPE.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ContainerScope {
+    /// The logs and images of every running container of the pod.
+    Pod,
+    /// Only those of the container that crashed, when its cgroup names it.
+    Container,
+}
+
+impl FromStr for ContainerScope {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "" | "pod" => Ok(ContainerScope::Pod),
+            "container" => Ok(ContainerScope::Container),
+            other => Err(anyhow::anyhow!("Unknown container scope {other}")),
+        }
+    }
+}
+
+impl CoreParams {
+    /// The executable's path from `%E`, where the kernel replaced each `/`
+    /// with a `!`.
+    pub fn exe_path(&self) -> String {
+        self.pathname.replace('!', "/")
+    }
+}
+
+static DEFAULT_TEMPLATE: &str =
+    "{uuid}-dump-{timestamp}-{hostname}-{exe_name}-{pid}-{signal}-{pod_uid}-{sequence}";
 
 impl CoreConfig {
     pub fn new() -> Result<CoreConfig, anyhow::Error> {
-        let matches = try_get_matches()?;
+        let mut matches = try_get_matches()?;
+        // Before a spooled capture's own arguments replace them.
+        let dry_run_flag = matches.is_present("dry-run");
+        let replay = matches
+            .subcommand_matches("replay")
+            .and_then(|m| m.value_of("spool-id"))
+            .map(replay_dir)
+            .transpose()?;
+        let spool = replay
+            .clone()
+            .or_else(|| matches.value_of("spool").map(PathBuf::from));
+        let manifest = match &spool {
+            Some(dir) => {
+                // A worker takes the crash's arguments from the manifest.
+                let manifest = Manifest::read(dir)?;
+                if replay.is_some() {
+                    manifest.restore_env();
+                }
+                matches = try_get_matches_from(&manifest.args)?;
+                Some(manifest)
+            }
+            None => None,
+        };
         let limit_size = matches.value_of("limit-size").unwrap_or("").to_string();
         let exe_name = matches.value_of("exe-name").unwrap_or("").to_string();
         let pid = matches.value_of("pid").unwrap_or("").to_string();
+        let host_pid = matches.value_of("host-pid").unwrap_or("").to_string();
         let signal = matches.value_of("signal").unwrap_or("").to_string();
         let timestamp = matches.value_of("timestamp").unwrap_or("").to_string();
         let directory = matches.value_of("directory").unwrap_or("").to_string();
         let hostname = matches.value_of("hostname").unwrap_or("").to_string();
         let pathname = matches.value_of("pathname").unwrap_or("").to_string();
 
-        let uuid = Uuid::new_v4();
+        // A replay is a capture of its own next to the original.
+        let replay_of = manifest
+            .as_ref()
+            .filter(|_| replay.is_some())
+            .map(|m| m.uuid);
+        let uuid = match (&manifest, replay_of) {
+            (Some(m), None) => m.uuid,
+            _ => Uuid::new_v4(),
+        };
 
-        let params = CoreParams {
+        let mut params = CoreParams {
             limit_size,
             exe_name,
             pid,
+            host_pid,
             signal,
             timestamp,
             directory,
@@ -74,6 +389,20 @@ impl CoreConfig {
             pathname,
             namespace: None,
             podname: None,
+            pod_uid: None,
+            sequence: 0,
+            data_class: None,
+            volumes: vec![],
+            network: None,
+            clock: None,
+            oom: None,
+            resources: None,
+            decision: Decision::default(),
+            signature: None,
+            duplicate: None,
+            unknown_pod: false,
+            backtrace: None,
+            archive_sha256: None,
             uuid,
         };
 
@@ -81,6 +410,8 @@ impl CoreConfig {
         dot_env_path.pop();
         dot_env_path.push(".env");
 
+        // Before the .env, which only fills in what the file left unset.
+        let config_file_error = configfile::load(&dot_env_path);
         match dotenv::from_path(dot_env_path.clone()) {
             Ok(v) => v,
             Err(e) => error!("error loading .env file {}", e),
@@ -91,8 +422,37 @@ impl CoreConfig {
         let mut base_path = env::current_exe()?;
         base_path.pop();
 
-        let pod_selector_label = env::var("POD_SELECTOR_LABEL").unwrap_or_default();
+        let (pod_selector, pod_selector_error) = match env::var("POD_SELECTOR_LABEL")
+            .unwrap_or_default()
+            .parse::<Selector>()
+        {
+            Ok(v) => (v, None),
+            Err(e) => (Selector::default(), Some(e.to_string())),
+        };
         let log_level = env::var("LOG_LEVEL").unwrap_or_default();
+        let log_format = env::var("LOG_FORMAT")
+            .unwrap_or_default()
+            .parse::<LogFormat>()
+            .unwrap_or(LogFormat::Text);
+        let log_targets = LogTarget::parse_list(&env::var("LOG_TARGETS").unwrap_or_default())
+            .unwrap_or_else(|_| vec![LogTarget::File]);
+        let log_identifier = env::var("LOG_IDENTIFIER")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .unwrap_or_else(|| DEFAULT_LOG_IDENTIFIER.to_string());
+        let mut log_rotation = LogRotation::default();
+        if let Some(v) = env::var("LOG_MAX_BYTES").ok().and_then(|v| v.parse().ok()) {
+            log_rotation.max_bytes = v;
+        }
+        if let Some(v) = env::var("LOG_MAX_AGE_HOURS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+        {
+            log_rotation.max_age_hours = v;
+        }
+        if let Some(v) = env::var("LOG_MAX_FILES").ok().and_then(|v| v.parse().ok()) {
+            log_rotation.max_files = v;
+        }
         let ignore_crio = env::var("IGNORE_CRIO")
             .unwrap_or_else(|_| "false".to_string())
             .to_lowercase()
@@ -102,6 +462,106 @@ impl CoreConfig {
             .unwrap_or_else(|_| "500".to_string())
             .parse::<u32>()
             .unwrap();
+        let pod_log_files = env::var("POD_LOG_FILES")
+            .unwrap_or_else(|_| "false".to_string())
+            .to_lowercase()
+            .parse::<bool>()
+            .unwrap_or(false);
+        let pod_log_dir = PathBuf::from(
+            env::var("POD_LOG_DIR")
+                .ok()
+                .filter(|v| !v.is_empty())
+                .unwrap_or_else(|| DEFAULT_POD_LOG_DIR.to_string()),
+        );
+        let work_dir = env::var("WORK_DIR")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(env::temp_dir);
+        let journal_minutes = env::var("JOURNAL_MINUTES")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .map(|v| {
+                v.parse::<u32>().unwrap_or_else(|e| {
+                    error!("Invalid JOURNAL_MINUTES {}: {}", v, e);
+                    DEFAULT_JOURNAL_MINUTES
+                })
+            })
+            .unwrap_or(DEFAULT_JOURNAL_MINUTES);
+        let capture_env = env::var("CAPTURE_ENV")
+            .unwrap_or_default()
+            .parse::<CaptureEnv>()
+            .unwrap_or_else(|e| {
+                error!("{}, not capturing the environment", e);
+                CaptureEnv::Off
+            });
+        let env_mask_patterns = crate::environ::mask_patterns(
+            &env::var("ENV_MASK_PATTERNS")
+                .ok()
+                .filter(|v| !v.is_empty())
+                .unwrap_or_else(|| DEFAULT_MASK_PATTERNS.to_string()),
+        );
+        let redact = Redactor::new(
+            &env::var("REDACT_PATTERNS")
+                .ok()
+                .filter(|v| !v.is_empty())
+                .unwrap_or_else(|| DEFAULT_REDACT_PATTERNS.to_string()),
+        );
+        let namespace_filter = NamespaceFilter::new(
+            &env::var("NAMESPACE_ALLOWLIST").unwrap_or_default(),
+            &env::var("NAMESPACE_DENYLIST").unwrap_or_default(),
+        );
+        let (capture_user, capture_user_error) =
+            match env::var("CAPTURE_USER").ok().filter(|v| !v.is_empty()) {
+                None => (None, None),
+                Some(v) => match v.parse::<CaptureUser>() {
+                    Ok(user) => (Some(user), None),
+                    Err(e) => (None, Some(e.to_string())),
+                },
+            };
+        let exe_filter = ExeFilter::new(&env::var("EXE_FILTER").unwrap_or_default());
+        let (signal_filter, signal_filter_error) = match env::var("SIGNALS")
+            .unwrap_or_default()
+            .parse::<SignalFilter>()
+        {
+            Ok(v) => (v, None),
+            Err(e) => (SignalFilter::default(), Some(e.to_string())),
+        };
+        let keep_spool = env::var("KEEP_SPOOL")
+            .unwrap_or_else(|_| "false".to_string())
+            .to_lowercase()
+            .parse::<bool>()
+            .unwrap();
+        let sandbox = env::var("SANDBOX")
+            .unwrap_or_else(|_| "false".to_string())
+            .to_lowercase()
+            .parse::<bool>()
+            .unwrap();
+        let dry_run = dry_run_flag
+            || env::var("DRY_RUN")
+                .unwrap_or_else(|_| "false".to_string())
+                .to_lowercase()
+                .parse::<bool>()
+                .unwrap_or_else(|e| {
+                    error!("Invalid DRY_RUN: {}", e);
+                    false
+                });
+        let drain_skipped = env::var("DRAIN_SKIPPED")
+            .unwrap_or_else(|_| "false".to_string())
+            .to_lowercase()
+            .parse::<bool>()
+            .unwrap();
+        let (collectors, collectors_error) =
+            match env::var("COLLECTORS_FILE").ok().filter(|v| !v.is_empty()) {
+                None => (CollectorsConfig::default(), None),
+                Some(path) => match std::fs::read_to_string(&path)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|text| crate::collectors::parse(&text))
+                {
+                    Ok(v) => (v, None),
+                    Err(e) => (CollectorsConfig::default(), Some(format!("{path}: {e}"))),
+                },
+            };
         let image_command_string = env::var("CRIO_IMAGE_CMD").unwrap_or_else(|_| "img".to_string());
         let use_crio_config = env::var("USE_CRIO_CONF")
             .unwrap_or_else(|_| "false".to_string().to_lowercase())
@@ -110,11 +570,77 @@ impl CoreConfig {
         let compression = env::var("COMPRESSION")
             .unwrap_or_else(|_| "true".to_string().to_lowercase())
             .parse::<bool>()
-            .unwrap();
-        let timeout = env::var("TIMEOUT")
-            .unwrap_or_else(|_| "600".to_string())
+            .unwrap()
+            && !matches.is_present("disable-compression");
+        // CORE_COMPRESSION is the name COMP_ALGO had before levels could be set.
+        let algo = env::var("COMP_ALGO")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .or_else(|| env::var("CORE_COMPRESSION").ok())
+            .unwrap_or_default();
+        let encrypt = Recipients::parse(&env::var("ENCRYPT_RECIPIENTS").unwrap_or_default());
+        let base_path_str = base_path
+            .clone()
+            .into_os_string()
+            .into_string()
+            .unwrap_or_else(|_| "/var/mnt/core-dump-handler".to_string());
+
+        let bin_path = format!(
+            "/bin:/sbin:/usr/bin:/usr/sbin:/usr/local/bin:/home/kubernetes/bin:{base_path_str}"
+        );
+        let mut compression_error = None;
+        let core_compression = match algo {
+            _ if !compression => CoreCompression::None,
+            v if v.is_empty() => CoreCompression::Gzip,
+            v => match v.parse::<CoreCompression>() {
+                // Checked here so the level and extension are gzip's too.
+                Ok(CoreCompression::Zstd) if !compression::find_zstd(&bin_path) => {
+                    compression_error = Some(
+                        "No zstd in the PATH or the host directory, compressing the core with gzip"
+                            .to_string(),
+                    );
+                    CoreCompression::Gzip
+                }
+                Ok(v) => v,
+                Err(e) => {
+                    error!("{}, compressing the core with gzip", e);
+                    CoreCompression::Gzip
+                }
+            },
+        };
+        let compression_level = env::var("COMP_LEVEL")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .map(|v| {
+                v.parse::<u32>()
+                    .map_err(|e| anyhow::anyhow!("Invalid COMP_LEVEL {v}: {e}"))
+                    .and_then(|level| core_compression.level(Some(level)))
+            })
+            .unwrap_or_else(|| core_compression.level(None))
+            .unwrap_or_else(|e| {
+                error!("{}, using the default level", e);
+                core_compression.default_level()
+            });
+        let compression_threads = match env::var("COMP_THREADS").unwrap_or_default().as_str() {
+            "" => None,
+            v => v.parse::<usize>().map(Some).unwrap_or_else(|e| {
+                error!(
+                    "Invalid COMP_THREADS {}: {}, compressing on one thread",
+                    v, e
+                );
+                None
+            }),
+        };
+        let timeout = matches
+            .value_of("timeout")
+            .map(str::to_string)
+            .or_else(|| env::var("TIMEOUT").ok())
+            .unwrap_or_else(|| "600".to_string())
             .parse::<u32>()
-            .unwrap();
+            .unwrap_or_else(|e| {
+                error!("Invalid TIMEOUT: {}, waiting 600s", e);
+                600
+            });
         let core_events = env::var("CORE_EVENTS")
             .unwrap_or_else(|_| "false".to_string())
             .to_lowercase()
@@ -124,53 +650,487 @@ impl CoreConfig {
             .unwrap_or_else(|_| OsString::from_str("unknown").unwrap_or_default())
             .into_string()
             .unwrap_or_else(|_| "unknown".to_string());
-
-        let base_path_str = base_path
-            .clone()
-            .into_os_string()
-            .into_string()
-            .unwrap_or_else(|_| "/var/mnt/core-dump-handler".to_string());
-
-        let bin_path = format!(
-            "/bin:/sbin:/usr/bin:/usr/sbin:/usr/local/bin:/home/kubernetes/bin:{base_path_str}"
-        );
         let image_command =
             ImageCommand::from_str(&image_command_string).unwrap_or(ImageCommand::Img);
         let filename_template =
             env::var("FILENAME_TEMPLATE").unwrap_or_else(|_| String::from(DEFAULT_TEMPLATE));
+        params.data_class = env::var("DATA_CLASS").ok().filter(|v| !v.is_empty());
+        let zstd_dictionary = env::var("ZSTD_DICTIONARY")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from);
+        let delta_cores = env::var("DELTA_CORES")
+            .unwrap_or_else(|_| "false".to_string())
+            .to_lowercase()
+            .parse::<bool>()
+            .unwrap();
+        let delta_max_bases = env::var("DELTA_MAX_BASES")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .map_or(Some(DEFAULT_MAX_BASES), |v| {
+                v.parse::<usize>()
+                    .map_err(|e| error!("Invalid DELTA_MAX_BASES {}: {}, no limit", v, e))
+                    .ok()
+            })
+            .filter(|v| *v != 0);
+        let delta_max_base_bytes = env::var("DELTA_MAX_BASE_BYTES")
+            .ok()
+            .filter(|v| !v.is_empty() && v != "0")
+            .and_then(|v| {
+                v.parse::<u64>()
+                    .map_err(|e| error!("Invalid DELTA_MAX_BASE_BYTES {}: {}, no limit", v, e))
+                    .ok()
+            });
+        let fs_diff = env::var("FS_DIFF")
+            .unwrap_or_else(|_| "false".to_string())
+            .to_lowercase()
+            .parse::<bool>()
+            .unwrap();
+        let proc_snapshot = env::var("PROC_SNAPSHOT")
+            .unwrap_or_else(|_| "true".to_string())
+            .to_lowercase()
+            .parse::<bool>()
+            .unwrap_or_else(|_| {
+                error!("PROC_SNAPSHOT is not true or false, taking the snapshot");
+                true
+            });
+        let node_info = env::var("NODE_INFO")
+            .unwrap_or_else(|_| "true".to_string())
+            .to_lowercase()
+            .parse::<bool>()
+            .unwrap_or_else(|_| {
+                error!("NODE_INFO is not true or false, recording the node");
+                true
+            });
+        let dmesg_lines = env::var("DMESG_LINES")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .and_then(|v| {
+                v.parse::<usize>()
+                    .map_err(|e| error!("Invalid DMESG_LINES {}: {}, no dmesg", v, e))
+                    .ok()
+            })
+            .unwrap_or_default();
+        let capture_binaries = env::var("CAPTURE_BINARIES")
+            .unwrap_or_else(|_| "false".to_string())
+            .to_lowercase()
+            .parse::<bool>()
+            .unwrap();
+        let backtrace = env::var("BACKTRACE")
+            .unwrap_or_else(|_| "false".to_string())
+            .to_lowercase()
+            .parse::<bool>()
+            .unwrap_or_else(|e| {
+                error!("Invalid BACKTRACE: {}, no backtrace", e);
+                false
+            });
+        let core_format = env::var("CORE_FORMAT")
+            .unwrap_or_default()
+            .parse::<CoreFormat>()
+            .unwrap_or_else(|e| {
+                error!("{}, keeping the core only", e);
+                CoreFormat::Core
+            });
+        let node_ip = env::var("NODE_IP").ok().filter(|v| !v.is_empty());
+        let container_runtime = env::var("CONTAINER_RUNTIME")
+            .unwrap_or_default()
+            .parse::<RuntimeKind>()
+            .unwrap_or_else(|e| {
+                error!("{}, using crictl", e);
+                RuntimeKind::Crictl
+            });
+        let cri_endpoint = env::var("CRI_ENDPOINT")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .unwrap_or_else(|| cri::DEFAULT_ENDPOINT.to_string());
+        let docker_endpoint = env::var("DOCKER_ENDPOINT")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .unwrap_or_else(|| docker::DEFAULT_ENDPOINT.to_string());
+        let container_scope = env::var("CONTAINER_SCOPE")
+            .unwrap_or_default()
+            .parse::<ContainerScope>()
+            .unwrap_or_else(|e| {
+                error!("{}, capturing the whole pod", e);
+                ContainerScope::Pod
+            });
+        let event_format = env::var("EVENT_FORMAT")
+            .unwrap_or_else(|_| "json".to_string())
+            .parse::<EventFormat>()
+            .unwrap_or_else(|e| {
+                error!("{}, writing events as json", e);
+                EventFormat::Json
+            });
+        let dump_info_format = env::var("DUMP_INFO_FORMAT")
+            .unwrap_or_default()
+            .parse::<DumpInfoFormat>()
+            .unwrap_or_else(|e| {
+                error!("{}, writing the native dump-info", e);
+                DumpInfoFormat::Native
+            });
+        let pause_file = env::var("PAUSE_FILE")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from);
+        let pause_mode = env::var("PAUSE_MODE")
+            .unwrap_or_else(|_| "metadata-only".to_string())
+            .parse::<PauseMode>()
+            .unwrap_or_else(|e| {
+                error!("{}, pausing to metadata only", e);
+                PauseMode::MetadataOnly
+            });
+        let max_dumps_per_hour = env::var("MAX_DUMPS_PER_HOUR")
+            .ok()
+            .filter(|v| !v.is_empty() && v != "0")
+            .and_then(|v| {
+                v.parse::<usize>()
+                    .map_err(|e| error!("Invalid MAX_DUMPS_PER_HOUR {}: {}, no limit", v, e))
+                    .ok()
+            });
+        let max_concurrent_captures = env::var("MAX_CONCURRENT_CAPTURES")
+            .ok()
+            .filter(|v| !v.is_empty() && v != "0")
+            .and_then(|v| {
+                v.parse::<usize>()
+                    .map_err(|e| error!("Invalid MAX_CONCURRENT_CAPTURES {}: {}, no limit", v, e))
+                    .ok()
+            });
+        let rate_limit_mode = env::var("RATE_LIMIT_MODE")
+            .unwrap_or_else(|_| "metadata-only".to_string())
+            .parse::<PauseMode>()
+            .unwrap_or_else(|e| {
+                error!("{}, limiting to metadata only", e);
+                PauseMode::MetadataOnly
+            });
+        let unknown_pod_mode = match env::var("UNKNOWN_POD").unwrap_or_default().as_str() {
+            "" | "capture" => None,
+            mode => mode
+                .parse::<PauseMode>()
+                .map_err(|e| error!("{}, capturing crashes of unknown pods", e))
+                .ok(),
+        };
+        let dedup_window_minutes = env::var("DEDUP_WINDOW_MINUTES")
+            .ok()
+            .filter(|v| !v.is_empty() && v != "0")
+            .and_then(|v| {
+                v.parse::<u64>()
+                    .map_err(|e| error!("Invalid DEDUP_WINDOW_MINUTES {}: {}, no dedup", v, e))
+                    .ok()
+            });
+        let max_core_bytes = env::var("MAX_CORE_BYTES")
+            .ok()
+            .filter(|v| !v.is_empty() && v != "0")
+            .and_then(|v| {
+                v.parse::<u64>()
+                    .map_err(|e| error!("Invalid MAX_CORE_BYTES {}: {}, no limit", v, e))
+                    .ok()
+            });
+        let disk_reserve_percent = env::var("DISK_RESERVE_PERCENT")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .and_then(|v| {
+                v.parse::<u8>().ok().filter(|p| *p <= 100).or_else(|| {
+                    error!(
+                        "Invalid DISK_RESERVE_PERCENT {}, keeping {}%",
+                        v,
+                        diskspace::DEFAULT_RESERVE_PERCENT
+                    );
+                    None
+                })
+            })
+            .unwrap_or(diskspace::DEFAULT_RESERVE_PERCENT);
+        let core_limit_mode = env::var("MAX_CORE_MODE")
+            .unwrap_or_else(|_| "truncate".to_string())
+            .parse::<CoreLimitMode>()
+            .unwrap_or_else(|e| {
+                error!("{}, truncating", e);
+                CoreLimitMode::Truncate
+            });
+        let decisions_log = PathBuf::from(
+            env::var("DECISIONS_LOG")
+                .ok()
+                .filter(|v| !v.is_empty())
+                .unwrap_or_else(|| format!("{base_path_str}/decisions.log")),
+        );
         let event_location = PathBuf::from(
             env::var("EVENT_DIRECTORY").unwrap_or_else(|_| format!("{base_path_str}/events")),
         );
         Ok(CoreConfig {
             log_level,
-            pod_selector_label,
+            log_format,
+            log_targets,
+            log_identifier,
+            log_rotation,
+            pod_selector,
+            pod_selector_error,
+            namespace_filter,
+            exe_filter,
+            signal_filter,
+            signal_filter_error,
+            drain_skipped,
+            dry_run,
             ignore_crio,
             dot_env_path,
             image_command,
+            container_runtime,
+            cri_endpoint,
+            docker_endpoint,
+            container_scope,
             use_crio_config,
             crictl_config_path,
             base_path,
             bin_path,
             os_hostname,
+            node_ip,
             filename_template,
+            container_identity: None,
+            systemd_unit: None,
+            journal_minutes,
+            capture_env,
+            env_mask_patterns,
+            redact,
+            collectors,
+            collectors_error,
+            capture_user,
+            capture_user_error,
+            sandbox,
+            compression_error,
+            sandbox_error: None,
+            config_file_error,
+            spool,
+            manifest,
+            replay_of,
+            keep_spool,
             log_length,
+            pod_log_files,
+            pod_log_dir,
+            work_dir,
+            upload: UploadConfig::from_env(),
+            webhook: WebhookConfig::from_env(),
+            trace: TraceConfig::from_env(),
             params,
             compression,
+            core_compression,
+            compression_level,
+            compression_threads,
+            zstd_dictionary,
+            delta_cores,
+            delta_max_bases,
+            delta_max_base_bytes,
+            fs_diff,
+            proc_snapshot,
+            node_info,
+            dmesg_lines,
+            capture_binaries,
+            backtrace,
+            core_format,
+            minidump_file: None,
+            encrypt,
+            exe_path: None,
+            binaries: vec![],
+            build_id: None,
+            delta_base: None,
+            mapping_summary: None,
             core_events,
+            event_format,
+            dump_info_format,
+            pause_file,
+            pause_mode,
+            paused: None,
+            max_dumps_per_hour,
+            max_concurrent_captures,
+            rate_limit_mode,
+            rate_limited: None,
+            unknown_pod_mode,
+            unknown_pod: None,
+            dedup_window_minutes,
+            max_core_bytes,
+            core_limit_mode,
+            core_limited: None,
+            core_size: None,
+            disk_reserve_percent,
+            disk_low: false,
+            decisions_log,
             event_location,
             timeout,
         })
     }
 
+    /// The settings this capture ran with. Everything learned about the
+    /// crash itself is left to dump-info and upload credentials are masked.
+    pub fn get_handler_config(&self) -> String {
+        let mut config = match serde_json::to_value(self) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("Failed to serialise the handler config {}", e);
+                return "{}".to_string();
+            }
+        };
+        if let Some(fields) = config.as_object_mut() {
+            for name in [
+                "params",
+                "paused",
+                "replay_of",
+                "rate_limited",
+                "unknown_pod",
+                "minidump_file",
+                "core_limited",
+                "core_size",
+                "build_id",
+                "delta_base",
+                "mapping_summary",
+                "exe_path",
+                "binaries",
+                "container_identity",
+                "systemd_unit",
+            ] {
+                fields.remove(name);
+            }
+        }
+        serde_json::to_string_pretty(&config).unwrap_or_else(|_| "{}".to_string())
+    }
+
     pub fn get_dump_info(&self) -> String {
-        format!(
-            "{{\"uuid\":\"{}\", \"dump_file\":\"{}\", \"timestamp\": \"{}\",
-        \"hostname\": \"{}\", \"exe\": \"{}\", \"real_pid\": \"{}\", \"signal\": \"{}\", \"node_hostname\": \"{}\", \"path\": \"{}\" }}",
-            self.params.uuid, self.get_core_filename(), self.params.timestamp, self.params.hostname, self.params.exe_name, self.params.pid, self.params.signal, self.os_hostname, self.params.pathname
-        )
+        json!({
+            "dump_id": self.get_dump_id(),
+            "uuid": self.params.uuid,
+            "archive_key": self.get_archive_key(),
+            "dump_file": match (
+                self.paused.or(self.rate_limited).or(self.unknown_pod),
+                self.core_limited,
+            ) {
+                (Some(_), _) | (_, Some(CoreLimitMode::Skip)) => None,
+                _ if !self.core_format.core() && self.minidump_file.is_some() => None,
+                _ => Some(self.get_core_filename()),
+            },
+            "minidump_file": self.minidump_file,
+            "paused": self.paused,
+            "rate_limited": self.rate_limited,
+            "unknown_pod": self.params.unknown_pod,
+            "replay_of": self.replay_of,
+            "core_size": self.core_size,
+            "core_limit": self.core_limited.map(|mode| json!({
+                "max_core_bytes": self.max_core_bytes,
+                "mode": mode,
+            })),
+            "truncated": self.core_limited == Some(CoreLimitMode::Truncate),
+            "decision": self.params.decision,
+            "timestamp": self.params.timestamp,
+            "hostname": self.params.hostname,
+            "exe": self.params.exe_name,
+            "real_pid": self.params.pid,
+            "host_pid": self.params.host_pid,
+            "namespace": self.params.namespace,
+            "podname": self.params.podname,
+            "pod_uid": self.params.pod_uid,
+            "sequence": self.params.sequence,
+            "container": self.container_identity,
+            "systemd_unit": self.systemd_unit,
+            "volumes": self.params.volumes,
+            "network": self.params.network,
+            "clock": self.params.clock,
+            "oom_correlated": self.params.oom.as_ref().map(|o| o.oom_correlated),
+            "oom": self.params.oom,
+            "resources": self.params.resources,
+            "signal": self.params.signal,
+            "node_hostname": self.os_hostname,
+            "path": self.params.pathname,
+            "arch": env::consts::ARCH,
+            "compression": self.core_compression,
+            "compression_level": self.compression_level,
+            "compression_threads": self.compression_threads,
+            "extension": self.get_core_extension(),
+            "data_class": self.params.data_class,
+            "build_id": self.build_id,
+            "signature": self.params.signature,
+            "delta_base": self.delta_base,
+            "mappings": self.mapping_summary,
+            "exe_path": self.exe_path,
+            "binaries": self.binaries,
+            "metadata_dictionary": self
+                .zstd_dictionary
+                .as_ref()
+                .and_then(|d| d.file_name())
+                .map(|n| n.to_string_lossy().to_string()),
+        })
+        .to_string()
     }
 
-    pub fn get_templated_name(&self) -> String {
+    /// Checked once per capture so an operator can flip the pause file while
+    /// dumps are in flight.
+    pub fn check_paused(&mut self) -> Option<PauseMode> {
+        self.paused = match &self.pause_file {
+            Some(file) if file.exists() => Some(self.pause_mode),
+            _ => None,
+        };
+        self.paused
+    }
+
+    /// How much of the core is read into the archive. A core of unknown
+    /// size is truncated in skip mode too, the limit holds.
+    pub fn core_limit(&self) -> u64 {
+        match self.core_limited {
+            None if !self.disk_low => self.max_core_bytes.unwrap_or(u64::MAX),
+            _ => 0,
+        }
+    }
+
+    pub fn compress_options(&self) -> CompressOptions<'_> {
+        CompressOptions {
+            bin_path: &self.bin_path,
+            level: self.compression_level,
+            threads: self.compression_threads,
+        }
+    }
+
+    /// Appends the decision for this invocation to the decisions log. Called
+    /// on every path out of the composer, including the ones that skip the
+    /// capture. A dry run only logs it.
+    pub fn record_decision(&self) {
+        let decision = &self.params.decision;
+        info!("Decision: {}", decision.record(&self.params));
+        if self.dry_run {
+            return;
+        }
+        if let Err(e) = decision.append(&self.params, &self.decisions_log) {
+            error!(
+                "Failed to write decision to {}: {}",
+                self.decisions_log.display(),
+                e
+            );
+        }
+    }
+
+    /// The stable id of the capture. Unlike the templated name it never
+    /// changes with FILENAME_TEMPLATE, so the catalog, events and object
+    /// keys refer to captures by it.
+    pub fn get_dump_id(&self) -> String {
+        self.params.uuid.to_string()
+    }
+
+    /// The values FILENAME_TEMPLATE renders, the params plus the shorter
+    /// `pod`, `exe` and `dump_id`. A `/` in a value would add a path
+    /// segment the template didn't ask for so it is replaced with `-`.
+    fn template_context(&self) -> serde_json::Value {
+        let mut context = serde_json::to_value(&self.params).unwrap_or_default();
+        if let Some(fields) = context.as_object_mut() {
+            fields.insert("pod".to_string(), json!(self.params.podname));
+            fields.insert("exe".to_string(), json!(self.params.exe_name));
+            fields.insert("dump_id".to_string(), json!(self.get_dump_id()));
+            for value in fields.values_mut() {
+                if let Some(s) = value.as_str().filter(|s| s.contains('/')) {
+                    *value = json!(s.replace('/', "-"));
+                }
+            }
+        }
+        context
+    }
+
+    /// FILENAME_TEMPLATE rendered with the `/` it has kept, so
+    /// `{namespace}/{pod}/{exe}-{signal}-{timestamp}` partitions the
+    /// uploaded archives by prefix. Segments left empty by unset values
+    /// are dropped.
+    fn render_template(&self) -> String {
         let mut tt = TinyTemplate::new();
         match tt.add_template("name", &self.filename_template) {
             Ok(v) => v,
@@ -183,8 +1143,19 @@ impl CoreConfig {
                 return self.params.uuid.to_string();
             }
         }
-        match tt.render("name", &self.params) {
-            Ok(v) => v,
+        match tt.render("name", &self.template_context()) {
+            Ok(v) => {
+                let name = v
+                    .split('/')
+                    .filter(|segment| !segment.is_empty())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                if name.is_empty() {
+                    self.params.uuid.to_string()
+                } else {
+                    name
+                }
+            }
             Err(e) => {
                 error!(
                     "Templating name failed. Using uuid {} {}",
@@ -195,6 +1166,13 @@ impl CoreConfig {
             }
         }
     }
+
+    /// The rendered FILENAME_TEMPLATE as a single file name, the files of a
+    /// capture sit flat in the core directory and the archive.
+    pub fn get_templated_name(&self) -> String {
+        self.render_template().replace('/', "-")
+    }
+
     pub fn set_namespace(&mut self, namespace: String) {
         self.params.namespace = Some(namespace)
     }
@@ -203,12 +1181,88 @@ impl CoreConfig {
         self.params.podname = Some(podname)
     }
 
+    pub fn set_pod_uid(&mut self, pod_uid: String) {
+        self.params.pod_uid = Some(pod_uid)
+    }
+
+    pub fn get_pod_cache_file(&self) -> PathBuf {
+        self.base_path.join(crate::podcache::POD_CACHE_FILE)
+    }
+
+    /// Where the split capture spools each crash for its worker.
+    pub fn get_capture_spool_dir(&self) -> PathBuf {
+        self.base_path
+            .join("capture-spool")
+            .join(self.params.uuid.to_string())
+    }
+
+    /// The `/proc` directory of the crashed process, or the copy of it a
+    /// worker was handed.
+    pub fn proc_dir(&self) -> Option<PathBuf> {
+        match &self.spool {
+            Some(dir) => Some(dir.join(crate::split::PROC_DIR)),
+            None if self.params.host_pid.is_empty() => None,
+            None => Some(PathBuf::from(format!("/proc/{}", self.params.host_pid))),
+        }
+    }
+
+    /// The directories a sandboxed composer may write to.
+    pub fn sandbox_paths(&self) -> Vec<PathBuf> {
+        let mut paths = vec![
+            PathBuf::from(&self.params.directory),
+            self.base_path.clone(),
+            self.event_location.clone(),
+            self.work_dir.clone(),
+        ];
+        paths.extend(self.decisions_log.parent().map(Path::to_path_buf));
+        paths
+    }
+
+    pub fn get_rate_limit_file(&self) -> PathBuf {
+        self.base_path.join(crate::ratelimit::RATE_LIMIT_FILE)
+    }
+
+    pub fn get_slots_dir(&self) -> PathBuf {
+        self.base_path.join(crate::slots::SLOTS_DIR)
+    }
+
+    pub fn get_signature_file(&self) -> PathBuf {
+        self.base_path.join(crate::signature::SIGNATURE_FILE)
+    }
+
+    pub fn get_sequence_file(&self) -> PathBuf {
+        self.base_path.join("sequence")
+    }
+
     pub fn get_dump_info_filename(&self) -> String {
         format!("{}-dump-info.json", self.get_templated_name())
     }
 
+    /// Where the full dump-info goes when `-dump-info.json` holds the IBM
+    /// fields with DUMP_INFO_FORMAT both.
+    pub fn get_native_dump_info_filename(&self) -> String {
+        format!("{}-dump-info-native.json", self.get_templated_name())
+    }
+
+    pub fn get_core_extension(&self) -> String {
+        if self.delta_base.is_some() {
+            format!(".core.delta{}", self.core_compression.extension())
+        } else {
+            format!(".core{}", self.core_compression.extension())
+        }
+    }
+
+    pub fn get_delta_base_dir(&self) -> PathBuf {
+        self.base_path.join("delta-bases")
+    }
+
+    /// Unique to the invocation so concurrent crashes never share files.
+    pub fn get_staging_dir(&self) -> PathBuf {
+        self.work_dir.join(self.params.uuid.to_string())
+    }
+
     pub fn get_core_filename(&self) -> String {
-        format!("{}.core", self.get_templated_name())
+        format!("{}{}", self.get_templated_name(), self.get_core_extension())
     }
 
     pub fn get_pod_filename(&self) -> String {
@@ -230,6 +1284,79 @@ impl CoreConfig {
     pub fn get_log_filename(&self, counter: usize) -> String {
         format!("{}-{}.log", self.get_templated_name(), counter)
     }
+
+    pub fn get_node_log_filename(&self, counter: usize) -> String {
+        format!("{}-{}-node.log", self.get_templated_name(), counter)
+    }
+
+    pub fn get_environ_filename(&self) -> String {
+        format!("{}-environ.json", self.get_templated_name())
+    }
+
+    /// Events that couldn't be written to the event directory, moved there
+    /// by the agent. See core-dump-agent/src/spool.rs.
+    pub fn get_event_spool_dir(&self) -> PathBuf {
+        self.base_path.join("event-spool")
+    }
+
+    pub fn get_node_info_filename(&self) -> String {
+        format!("{}-node-info.json", self.get_templated_name())
+    }
+
+    pub fn get_dmesg_filename(&self) -> String {
+        format!("{}-dmesg.txt", self.get_templated_name())
+    }
+
+    pub fn get_journal_filename(&self) -> String {
+        format!("{}-journal.log", self.get_templated_name())
+    }
+
+    pub fn get_backtrace_filename(&self) -> String {
+        format!("{}-backtrace.txt", self.get_templated_name())
+    }
+
+    pub fn get_manifest_filename(&self) -> String {
+        format!("{}-manifest.json", self.get_templated_name())
+    }
+
+    pub fn get_minidump_filename(&self) -> String {
+        format!("{}-minidump.dmp", self.get_templated_name())
+    }
+
+    pub fn get_modules_filename(&self) -> String {
+        format!("{}-modules.json", self.get_templated_name())
+    }
+
+    pub fn get_collector_filename(&self, collector: &str) -> String {
+        format!("{}-collector-{}.log", self.get_templated_name(), collector)
+    }
+
+    pub fn get_fs_diff_filename(&self) -> String {
+        format!("{}-fs-diff.json", self.get_templated_name())
+    }
+
+    pub fn get_proc_filename(&self) -> String {
+        format!("{}-proc.json", self.get_templated_name())
+    }
+
+    pub fn get_maps_filename(&self) -> String {
+        format!("{}-maps.txt", self.get_templated_name())
+    }
+
+    /// The executable and libraries are stored under this directory at
+    /// their paths in the process's root.
+    pub fn get_sysroot_dirname(&self) -> String {
+        format!("{}-sysroot", self.get_templated_name())
+    }
+
+    pub fn get_handler_config_filename(&self) -> String {
+        format!("{}-handler-config.json", self.get_templated_name())
+    }
+
+    pub fn get_capture_result_filename(&self) -> String {
+        format!("{}-capture-result.json", self.get_templated_name())
+    }
+    #[allow(dead_code)]
     pub fn get_zip_full_path(&self) -> String {
         format!(
             "{}/{}.zip",
@@ -238,27 +1365,73 @@ impl CoreConfig {
         )
     }
 
+    /// The archive's name, `.age` or `.gpg` is added when it is encrypted.
+    pub fn get_tar_filename(&self) -> String {
+        let suffix = self.encrypt.as_ref().map_or("", |r| r.suffix());
+        format!("{}.tar{}", self.get_templated_name(), suffix)
+    }
+
+    /// The object key the archive is uploaded under, the archive name with
+    /// the prefixes FILENAME_TEMPLATE's `/` separate.
+    pub fn get_archive_key(&self) -> String {
+        let suffix = self.encrypt.as_ref().map_or("", |r| r.suffix());
+        format!("{}.tar{}", self.render_template(), suffix)
+    }
+
     pub fn get_tar_full_path(&self) -> String {
-        format!(
-            "{}/{}.tar",
-            self.params.directory,
-            self.get_templated_name()
-        )
+        format!("{}/{}", self.params.directory, self.get_tar_filename())
+    }
+}
+
+/// The spooled capture `replay` names, a uuid in capture-spool next to the
+/// composer or a path.
+fn replay_dir(id: &str) -> Result<PathBuf, anyhow::Error> {
+    if id.contains('/') {
+        return Ok(PathBuf::from(id));
+    }
+    let mut dir = env::current_exe()?;
+    dir.pop();
+    Ok(dir.join("capture-spool").join(id))
+}
+
+/// Checks a numeric argument such as `%p` is a whole number from `min` to
+/// `max`. It is kept as the string the kernel passed, which is what the
+/// filename template renders.
+fn numeric(min: u64, max: u64) -> impl Fn(&str) -> Result<String, String> + Clone {
+    move |v: &str| match v.parse::<u64>() {
+        Ok(n) if (min..=max).contains(&n) => Ok(v.to_string()),
+        Ok(_) => Err(format!("must be from {min} to {max}")),
+        Err(_) => Err("must be a whole number".to_string()),
     }
 }
 
 pub fn try_get_matches() -> clap::Result<ArgMatches> {
+    try_get_matches_from(env::args())
+}
+
+pub fn try_get_matches_from<I, T>(args: I) -> clap::Result<ArgMatches>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
     App::new("Core Dump Composer")
         .version("0.1.0")
         .author("Anton Whalley <anton@venshare.com>")
         .about("Processes Core Dumps in a K8s System")
+        .after_help(
+            "The agent installs the composer in kernel.core_pattern as\n\n    \
+             |cdc -c=%c -e=%e -p=%p -P=%P -s=%s -t=%t -d=<core dir> -h=%h -E=%E\n\n\
+             and the kernel pipes the core to its stdin. The rest of the settings come from \
+             the .env next to it, CDC_CONFIG_FILE and the environment.",
+        )
         .arg(
             Arg::new("limit-size")
                 .short('c')
                 .long("limit-size")
                 .required(false)
                 .takes_value(true)
-                .help("Core file size soft resource limit of crashing process"),
+                .value_parser(numeric(0, u64::MAX))
+                .help("Core file size soft resource limit of crashing process, %c"),
         )
         .arg(
             Arg::new("exe-name")
@@ -267,9 +1440,9 @@ pub fn try_get_matches() -> clap::Result<ArgMatches> {
                 .required(false)
                 .takes_value(true)
                 .help(
-                    "The process or thread's comm value, which typically is the
-            same as the executable filename (without path prefix, and
-            truncated to a maximum of 15 characters)",
+                    "The process or thread's comm value, which typically is the \
+                     same as the executable filename (without path prefix, and \
+                     truncated to a maximum of 15 characters), %e",
                 ),
         )
         .arg(
@@ -278,18 +1451,29 @@ pub fn try_get_matches() -> clap::Result<ArgMatches> {
                 .long("pid")
                 .required(false)
                 .takes_value(true)
+                .value_parser(numeric(1, u32::MAX.into()))
                 .help(
-                    "PID of dumped process, as seen in the PID namespace in which
-            the process resides.",
+                    "PID of dumped process, as seen in the PID namespace in which \
+                     the process resides, %p",
                 ),
         )
+        .arg(
+            Arg::new("host-pid")
+                .short('P')
+                .long("host-pid")
+                .required(false)
+                .takes_value(true)
+                .value_parser(numeric(1, u32::MAX.into()))
+                .help("PID of dumped process, as seen in the initial PID namespace, %P"),
+        )
         .arg(
             Arg::new("signal")
                 .short('s')
                 .long("signal")
                 .required(false)
                 .takes_value(true)
-                .help("Number of signal causing dump."),
+                .value_parser(numeric(1, 64))
+                .help("Number of signal causing dump, %s"),
         )
         .arg(
             Arg::new("timestamp")
@@ -297,7 +1481,8 @@ pub fn try_get_matches() -> clap::Result<ArgMatches> {
                 .long("timestamp")
                 .required(false)
                 .takes_value(true)
-                .help("Time of dump, expressed as seconds since the Epoch."),
+                .value_parser(numeric(0, u64::MAX))
+                .help("Time of dump, expressed as seconds since the Epoch, %t"),
         )
         .arg(
             Arg::new("directory")
@@ -313,7 +1498,7 @@ pub fn try_get_matches() -> clap::Result<ArgMatches> {
                 .long("hostname")
                 .required(false)
                 .takes_value(true)
-                .help("Hostname (same as nodename returned by uname(2))"),
+                .help("Hostname (same as nodename returned by uname(2)), %h"),
         )
         .arg(
             Arg::new("pathname")
@@ -321,7 +1506,7 @@ pub fn try_get_matches() -> clap::Result<ArgMatches> {
                 .long("pathname")
                 .required(false)
                 .takes_value(true)
-                .help("Hostname (same as nodename returned by uname(2))"),
+                .help("Pathname of the executable, with slashes ('/') replaced by exclamation marks ('!'), %E"),
         )
         .arg(
             Arg::new("timeout")
@@ -329,7 +1514,8 @@ pub fn try_get_matches() -> clap::Result<ArgMatches> {
                 .long("timeout")
                 .required(false)
                 .takes_value(true)
-                .help("Timeout in seconds to wait for processing of the Coredump"),
+                .value_parser(numeric(1, u32::MAX.into()))
+                .help("Timeout in seconds to wait for processing of the Coredump, overrides TIMEOUT"),
         )
         .arg(
             Arg::new("test-threads")
@@ -345,12 +1531,66 @@ pub fn try_get_matches() -> clap::Result<ArgMatches> {
                 .takes_value(false)
                 .help("Disables deflate compression in resulting zip file and stores data uncompressed."),
         )
-        .try_get_matches()
+        .arg(
+            Arg::new("dry-run")
+                .long("dry-run")
+                .global(true)
+                .takes_value(false)
+                .help("Logs what would be captured and discards the core, same as DRY_RUN=true"),
+        )
+        .arg(
+            Arg::new("spool")
+                .long("spool")
+                .required(false)
+                .takes_value(true)
+                .help("Processes a capture spooled by the composer as CAPTURE_USER."),
+        )
+        .subcommand(
+            App::new("replay")
+                .about("Runs a spooled capture again with the current composer and config")
+                .arg(
+                    Arg::new("spool-id")
+                        .required(true)
+                        .takes_value(true)
+                        .help("The uuid of a capture in capture-spool, or its directory"),
+                ),
+        )
+        .subcommand(
+            App::new("npd-check")
+                .about("Reports frequent core dumps as a node-problem-detector custom plugin")
+                .arg(
+                    Arg::new("window-minutes")
+                        .long("window-minutes")
+                        .takes_value(true)
+                        .default_value("60")
+                        .help("How far back crashes are counted"),
+                )
+                .arg(
+                    Arg::new("threshold")
+                        .long("threshold")
+                        .takes_value(true)
+                        .default_value("3")
+                        .help("Crashes in the window that make the node NonOK"),
+                )
+                .arg(
+                    Arg::new("decisions-log")
+                        .long("decisions-log")
+                        .takes_value(true)
+                        .help("The decisions log, decisions.log next to the composer by default"),
+                ),
+        )
+        .try_get_matches_from(args)
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::config::CoreConfig;
+    use crate::compression::CoreCompression;
+    use crate::config::{
+        replay_dir, try_get_matches_from, ContainerScope, CoreConfig, CoreLimitMode, PauseMode,
+    };
+    use crate::delta::DeltaBase;
+    use crate::minidump::CoreFormat;
+    use std::path::PathBuf;
     #[test]
     fn namespace_is_rendered() {
         let mut config = match CoreConfig::new() {
@@ -365,8 +1605,29 @@ mod tests {
         assert_eq!(just_namespace, "anamespace".to_string());
     }
     #[test]
+    fn prefixed_template_test() {
+        let mut config = CoreConfig::new().unwrap();
+        config.filename_template = "{namespace}/{pod}/{exe}-{signal}".to_string();
+        config.set_namespace("mo".to_string());
+        config.set_podname("mo-0".to_string());
+        config.params.exe_name = "node".to_string();
+        config.params.signal = "11".to_string();
+        assert_eq!(config.get_templated_name(), "mo-mo-0-node-11");
+        assert_eq!(config.get_archive_key(), "mo/mo-0/node-11.tar");
+        assert_eq!(config.get_tar_filename(), "mo-mo-0-node-11.tar");
+        let dump_info: serde_json::Value = serde_json::from_str(&config.get_dump_info()).unwrap();
+        assert_eq!(dump_info["archive_key"], "mo/mo-0/node-11.tar");
+
+        // Unset values leave no empty segment and values can't add one.
+        config.params.podname = None;
+        config.params.exe_name = "a/b".to_string();
+        assert_eq!(config.get_archive_key(), "mo/a-b-11.tar");
+        config.filename_template = "{pod}/".to_string();
+        assert_eq!(config.get_templated_name(), config.get_dump_id());
+    }
+    #[test]
     fn default_template_test() {
-        // "{uuid}-dump-{timestamp}-{hostname}-{exe_name}-{pid}-{signal}";
+        // "{uuid}-dump-{timestamp}-{hostname}-{exe_name}-{pid}-{signal}-{pod_uid}-{sequence}";
         let mut config = match CoreConfig::new() {
             Ok(v) => v,
             Err(e) => panic!("Generation of CoreConfig failed. {}", e),
@@ -376,9 +1637,174 @@ mod tests {
         config.params.exe_name = "anexe".to_string();
         config.params.pid = "2".to_string();
         config.params.signal = "9".to_string();
+        config.set_pod_uid("d4a7c0f2".to_string());
+        config.params.sequence = 7;
 
         let templated_name = config.get_templated_name();
-        assert!(templated_name.contains("-dump-123123123-ahostname-anexe-2-9"));
+        assert!(templated_name.contains("-dump-123123123-ahostname-anexe-2-9-d4a7c0f2-7"));
+        config.params.pathname = "!usr!local!bin!anexe".to_string();
+        assert_eq!(config.params.exe_path(), "/usr/local/bin/anexe");
+    }
+    #[test]
+    fn replay_args_test() {
+        let id = "6dbd1ab3-f2f1-4d5d-9e36-c1e9e6d3b0f1";
+        let matches = try_get_matches_from(["cdc", "replay", id]).unwrap();
+        let replay = matches.subcommand_matches("replay").unwrap();
+        assert_eq!(replay.value_of("spool-id"), Some(id));
+        assert!(replay_dir(id)
+            .unwrap()
+            .ends_with(format!("capture-spool/{id}")));
+        assert_eq!(
+            replay_dir("/tmp/spool/x").unwrap(),
+            PathBuf::from("/tmp/spool/x")
+        );
+        assert!(try_get_matches_from(["cdc", "replay"]).is_err());
+    }
+    #[test]
+    fn args_test() {
+        let matches = try_get_matches_from([
+            "cdc",
+            "-c=18446744073709551615",
+            "-p=4",
+            "-P=2871",
+            "-s=11",
+            "-t=1588462466",
+            "-h=node-1",
+        ])
+        .unwrap();
+        assert_eq!(matches.value_of("pid"), Some("4"));
+        assert_eq!(matches.value_of("limit-size"), Some("18446744073709551615"));
+        assert_eq!(matches.value_of("hostname"), Some("node-1"));
+
+        let e = try_get_matches_from(["cdc", "-p=four"]).unwrap_err();
+        assert_eq!(e.kind(), clap::ErrorKind::ValueValidation);
+        let message = e.to_string();
+        assert!(message.contains("--pid"), "{message}");
+        assert!(message.contains("must be a whole number"), "{message}");
+        let e = try_get_matches_from(["cdc", "-s=65"]).unwrap_err();
+        assert!(e.to_string().contains("must be from 1 to 64"));
+        let replay = try_get_matches_from(["cdc", "replay", "5ad2ea44", "--dry-run"]).unwrap();
+        assert!(replay.is_present("dry-run"));
+        let e = try_get_matches_from(["cdc", "--help"]).unwrap_err();
+        assert_eq!(e.kind(), clap::ErrorKind::DisplayHelp);
+        assert!(e.to_string().contains("kernel.core_pattern"));
+    }
+    #[test]
+    fn dump_info_test() {
+        let mut config = match CoreConfig::new() {
+            Ok(v) => v,
+            Err(e) => panic!("Generation of CoreConfig failed. {}", e),
+        };
+        config.params.pid = "2".to_string();
+        config.params.signal = "9".to_string();
+        let dump_info: serde_json::Value =
+            serde_json::from_str(&config.get_dump_info()).expect("dump info should be JSON");
+        assert_eq!(dump_info["real_pid"], "2");
+        assert_eq!(dump_info["signal"], "9");
+        assert_eq!(dump_info["uuid"], config.params.uuid.to_string());
+        assert_eq!(dump_info["dump_id"], config.params.uuid.to_string());
+        assert_eq!(dump_info["arch"], std::env::consts::ARCH);
+        assert_eq!(dump_info["compression"], "gzip");
+        assert_eq!(dump_info["compression_level"], 1);
+        assert_eq!(dump_info["extension"], ".core.gz");
+        assert_eq!(dump_info["data_class"], serde_json::Value::Null);
+
+        config.params.data_class = Some("confidential".to_string());
+        let dump_info: serde_json::Value =
+            serde_json::from_str(&config.get_dump_info()).expect("dump info should be JSON");
+        assert_eq!(dump_info["data_class"], "confidential");
+        assert_eq!(dump_info["metadata_dictionary"], serde_json::Value::Null);
+
+        config.zstd_dictionary = Some(std::path::PathBuf::from("/var/mnt/mo.dict"));
+        let dump_info: serde_json::Value =
+            serde_json::from_str(&config.get_dump_info()).expect("dump info should be JSON");
+        assert_eq!(dump_info["metadata_dictionary"], "mo.dict");
+    }
+    #[test]
+    fn handler_config_test() {
+        let mut config = match CoreConfig::new() {
+            Ok(v) => v,
+            Err(e) => panic!("Generation of CoreConfig failed. {}", e),
+        };
+        config.params.pid = "2".to_string();
+        config.upload = crate::upload::UploadConfig::from_vars(|name| match name {
+            "UPLOAD_BUCKET_NAME" => "cores".to_string(),
+            "UPLOAD_ACCESS_KEY" => "AKIA".to_string(),
+            _ => String::new(),
+        });
+        let handler_config = config.get_handler_config();
+        assert!(!handler_config.contains("AKIA"));
+        let handler_config: serde_json::Value = serde_json::from_str(&handler_config).unwrap();
+        assert_eq!(handler_config["params"], serde_json::Value::Null);
+        assert_eq!(handler_config["timeout"], config.timeout);
+        assert_eq!(handler_config["upload"]["bucket_name"], "cores");
+        assert_eq!(handler_config["upload"]["access_key"], "********");
+    }
+    #[test]
+    fn pause_file_test() {
+        let mut config = match CoreConfig::new() {
+            Ok(v) => v,
+            Err(e) => panic!("Generation of CoreConfig failed. {}", e),
+        };
+        let pause_file = std::env::temp_dir().join(format!("pause-{}", config.params.uuid));
+        config.pause_file = Some(pause_file.clone());
+        assert_eq!(config.check_paused(), None);
+        let dump_info: serde_json::Value = serde_json::from_str(&config.get_dump_info()).unwrap();
+        assert_eq!(dump_info["paused"], serde_json::Value::Null);
+        assert!(dump_info["dump_file"].is_string());
+
+        std::fs::write(&pause_file, "").unwrap();
+        assert_eq!(config.check_paused(), Some(PauseMode::MetadataOnly));
+        let dump_info: serde_json::Value = serde_json::from_str(&config.get_dump_info()).unwrap();
+        assert_eq!(dump_info["paused"], "metadata-only");
+        assert_eq!(dump_info["dump_file"], serde_json::Value::Null);
+        config.pause_mode = "skip".parse().unwrap();
+        assert_eq!(config.check_paused(), Some(PauseMode::Skip));
+        std::fs::remove_file(&pause_file).unwrap();
+        assert!("sometimes".parse::<PauseMode>().is_err());
+    }
+    #[test]
+    fn core_limit_test() {
+        let mut config = match CoreConfig::new() {
+            Ok(v) => v,
+            Err(e) => panic!("Generation of CoreConfig failed. {}", e),
+        };
+        let dump_info: serde_json::Value = serde_json::from_str(&config.get_dump_info()).unwrap();
+        assert_eq!(dump_info["truncated"], false);
+        assert_eq!(dump_info["core_limit"], serde_json::Value::Null);
+
+        config.max_core_bytes = Some(1 << 30);
+        config.core_size = Some(200 << 30);
+        config.core_limited = Some(CoreLimitMode::Truncate);
+        let dump_info: serde_json::Value = serde_json::from_str(&config.get_dump_info()).unwrap();
+        assert_eq!(dump_info["truncated"], true);
+        assert_eq!(dump_info["core_size"], 200u64 << 30);
+        assert_eq!(dump_info["core_limit"]["max_core_bytes"], 1 << 30);
+        assert!(dump_info["dump_file"].is_string());
+
+        config.core_format = CoreFormat::Minidump;
+        config.minidump_file = Some(config.get_minidump_filename());
+        let dump_info: serde_json::Value = serde_json::from_str(&config.get_dump_info()).unwrap();
+        assert_eq!(dump_info["dump_file"], serde_json::Value::Null);
+        assert!(dump_info["minidump_file"].is_string());
+        config.core_format = CoreFormat::Core;
+        config.minidump_file = None;
+
+        config.core_limited = Some(CoreLimitMode::Skip);
+        let dump_info: serde_json::Value = serde_json::from_str(&config.get_dump_info()).unwrap();
+        assert_eq!(dump_info["truncated"], false);
+        assert_eq!(dump_info["core_limit"]["mode"], "skip");
+        assert_eq!(dump_info["dump_file"], serde_json::Value::Null);
+        assert!("sometimes".parse::<CoreLimitMode>().is_err());
+    }
+    #[test]
+    fn container_scope_test() {
+        assert_eq!("".parse::<ContainerScope>().unwrap(), ContainerScope::Pod);
+        assert_eq!(
+            "Container".parse::<ContainerScope>().unwrap(),
+            ContainerScope::Container
+        );
+        assert!("node".parse::<ContainerScope>().is_err());
     }
     #[test]
     fn get_files_test() {
@@ -391,31 +1817,86 @@ mod tests {
         config.params.exe_name = "anexe".to_string();
         config.params.pid = "2".to_string();
         config.params.signal = "9".to_string();
+        config.set_pod_uid("d4a7c0f2".to_string());
+        config.params.sequence = 1;
         let dump_info_name = config.get_dump_info_filename();
-        assert!(dump_info_name.contains("-dump-123123123-ahostname-anexe-2-9-dump-info.json"));
+        assert!(dump_info_name
+            .contains("-dump-123123123-ahostname-anexe-2-9-d4a7c0f2-1-dump-info.json"));
+        let native_name = config.get_native_dump_info_filename();
+        assert!(native_name
+            .ends_with("-dump-123123123-ahostname-anexe-2-9-d4a7c0f2-1-dump-info-native.json"));
+
+        config.core_compression = CoreCompression::None;
+        let core_file_name = config.get_core_filename();
+        assert!(core_file_name.ends_with("-dump-123123123-ahostname-anexe-2-9-d4a7c0f2-1.core"));
+
+        config.core_compression = CoreCompression::Gzip;
+        let core_file_name = config.get_core_filename();
+        assert!(core_file_name.ends_with("-dump-123123123-ahostname-anexe-2-9-d4a7c0f2-1.core.gz"));
 
+        config.delta_base = Some(DeltaBase {
+            build_id: "deadbeef".to_string(),
+            uuid: "base".to_string(),
+            dump_file: "base.core.gz".to_string(),
+        });
         let core_file_name = config.get_core_filename();
-        assert!(core_file_name.contains("-dump-123123123-ahostname-anexe-2-9.core"));
+        assert!(core_file_name
+            .ends_with("-dump-123123123-ahostname-anexe-2-9-d4a7c0f2-1.core.delta.gz"));
+        config.delta_base = None;
 
         let pod_file_name = config.get_pod_filename();
-        assert!(pod_file_name.contains("-dump-123123123-ahostname-anexe-2-9-pod-info.json"));
+        assert!(
+            pod_file_name.contains("-dump-123123123-ahostname-anexe-2-9-d4a7c0f2-1-pod-info.json")
+        );
 
         let inspect_file_name = config.get_inspect_pod_filename();
-        assert!(inspect_file_name.contains("-dump-123123123-ahostname-anexe-2-9-runtime-info.json"));
+        assert!(inspect_file_name
+            .contains("-dump-123123123-ahostname-anexe-2-9-d4a7c0f2-1-runtime-info.json"));
 
         let inspect_file_name = config.get_inspect_pod_filename();
-        assert!(inspect_file_name.contains("-dump-123123123-ahostname-anexe-2-9-runtime-info.json"));
+        assert!(inspect_file_name
+            .contains("-dump-123123123-ahostname-anexe-2-9-d4a7c0f2-1-runtime-info.json"));
 
         let ps_file_name = config.get_ps_filename();
-        assert!(ps_file_name.contains("-dump-123123123-ahostname-anexe-2-9-ps-info.json"));
+        assert!(
+            ps_file_name.contains("-dump-123123123-ahostname-anexe-2-9-d4a7c0f2-1-ps-info.json")
+        );
 
         let img_file_name = config.get_image_filename(0);
-        assert!(img_file_name.contains("-dump-123123123-ahostname-anexe-2-9-0-image-info.json"));
+        assert!(img_file_name
+            .contains("-dump-123123123-ahostname-anexe-2-9-d4a7c0f2-1-0-image-info.json"));
 
         let log_file_name = config.get_log_filename(0);
-        assert!(log_file_name.contains("-dump-123123123-ahostname-anexe-2-9-0.log"));
+        assert!(log_file_name.contains("-dump-123123123-ahostname-anexe-2-9-d4a7c0f2-1-0.log"));
+
+        let fs_diff_name = config.get_fs_diff_filename();
+        assert!(
+            fs_diff_name.contains("-dump-123123123-ahostname-anexe-2-9-d4a7c0f2-1-fs-diff.json")
+        );
+
+        let proc_name = config.get_proc_filename();
+        assert!(proc_name.ends_with("-dump-123123123-ahostname-anexe-2-9-d4a7c0f2-1-proc.json"));
+
+        let maps_name = config.get_maps_filename();
+        assert!(maps_name.ends_with("-dump-123123123-ahostname-anexe-2-9-d4a7c0f2-1-maps.txt"));
+        let node_info_name = config.get_node_info_filename();
+        assert!(node_info_name
+            .ends_with("-dump-123123123-ahostname-anexe-2-9-d4a7c0f2-1-node-info.json"));
+        let dmesg_name = config.get_dmesg_filename();
+        assert!(dmesg_name.ends_with("-dump-123123123-ahostname-anexe-2-9-d4a7c0f2-1-dmesg.txt"));
+
+        let collector_name = config.get_collector_filename("threads");
+        assert!(collector_name
+            .ends_with("-dump-123123123-ahostname-anexe-2-9-d4a7c0f2-1-collector-threads.log"));
+
+        let sysroot_name = config.get_sysroot_dirname();
+        assert!(sysroot_name.ends_with("-dump-123123123-ahostname-anexe-2-9-d4a7c0f2-1-sysroot"));
+
+        let capture_result_name = config.get_capture_result_filename();
+        assert!(capture_result_name
+            .contains("-dump-123123123-ahostname-anexe-2-9-d4a7c0f2-1-capture-result.json"));
 
         let zip_file_name = config.get_zip_full_path();
-        assert!(zip_file_name.contains("-dump-123123123-ahostname-anexe-2-9.zip"));
+        assert!(zip_file_name.contains("-dump-123123123-ahostname-anexe-2-9-d4a7c0f2-1.zip"));
     }
 }
@@ -0,0 +1,390 @@
+//! CDC_CONFIG_FILE, the composer's settings as a TOML or YAML file instead
+//! of the `.env` the agent writes. The keys are those of the `.env`, in any
+//! case. An environment variable of the same name overrides the file, which
+//! overrides the `.env`.
+//!
+//! The TOML read is the flat subset the settings need: `KEY = value` lines
+//! with strings, integers and booleans, and `#` comments.
+
+use anyhow::anyhow;
+use serde_yaml::Value;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+pub const CONFIG_FILE_VAR: &str = "CDC_CONFIG_FILE";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Text,
+    Bool,
+    Number,
+}
+
+/// The settings a config file may hold and what their values must be.
+const KEYS: &[(&str, Kind)] = &[
+    ("BACKTRACE", Kind::Bool),
+    ("CAPTURE_BINARIES", Kind::Bool),
+    ("CAPTURE_ENV", Kind::Text),
+    ("CAPTURE_USER", Kind::Text),
+    ("COLLECTORS_FILE", Kind::Text),
+    ("COMPRESSION", Kind::Bool),
+    ("COMP_ALGO", Kind::Text),
+    ("COMP_LEVEL", Kind::Number),
+    ("COMP_THREADS", Kind::Number),
+    ("CONTAINER_RUNTIME", Kind::Text),
+    ("CONTAINER_SCOPE", Kind::Text),
+    ("CORE_COMPRESSION", Kind::Text),
+    ("CORE_EVENTS", Kind::Bool),
+    ("CORE_FORMAT", Kind::Text),
+    ("CRIO_IMAGE_CMD", Kind::Text),
+    ("CRI_ENDPOINT", Kind::Text),
+    ("DATA_CLASS", Kind::Text),
+    ("DECISIONS_LOG", Kind::Text),
+    ("DEDUP_WINDOW_MINUTES", Kind::Number),
+    ("DELTA_CORES", Kind::Bool),
+    ("DELTA_MAX_BASES", Kind::Number),
+    ("DELTA_MAX_BASE_BYTES", Kind::Number),
+    ("DISK_RESERVE_PERCENT", Kind::Number),
+    ("DMESG_LINES", Kind::Number),
+    ("DOCKER_ENDPOINT", Kind::Text),
+    ("DRAIN_SKIPPED", Kind::Bool),
+    ("DRY_RUN", Kind::Bool),
+    ("DUMP_INFO_FORMAT", Kind::Text),
+    ("ENCRYPT_RECIPIENTS", Kind::Text),
+    ("ENV_MASK_PATTERNS", Kind::Text),
+    ("EVENT_DIRECTORY", Kind::Text),
+    ("EVENT_FORMAT", Kind::Text),
+    ("EXE_FILTER", Kind::Text),
+    ("FILENAME_TEMPLATE", Kind::Text),
+    ("FS_DIFF", Kind::Bool),
+    ("IGNORE_CRIO", Kind::Bool),
+    ("JOURNAL_MINUTES", Kind::Number),
+    ("KEEP_SPOOL", Kind::Bool),
+    ("LOG_FORMAT", Kind::Text),
+    ("LOG_IDENTIFIER", Kind::Text),
+    ("LOG_LENGTH", Kind::Number),
+    ("LOG_LEVEL", Kind::Text),
+    ("LOG_MAX_AGE_HOURS", Kind::Number),
+    ("LOG_MAX_BYTES", Kind::Number),
+    ("LOG_MAX_FILES", Kind::Number),
+    ("LOG_TARGETS", Kind::Text),
+    ("MAX_CONCURRENT_CAPTURES", Kind::Number),
+    ("MAX_CORE_BYTES", Kind::Number),
+    ("MAX_CORE_MODE", Kind::Text),
+    ("MAX_DUMPS_PER_HOUR", Kind::Number),
+    ("NAMESPACE_ALLOWLIST", Kind::Text),
+    ("NAMESPACE_DENYLIST", Kind::Text),
+    ("NODE_INFO", Kind::Bool),
+    ("NODE_IP", Kind::Text),
+    ("OTLP_ENDPOINT", Kind::Text),
+    ("OTLP_TIMEOUT", Kind::Number),
+    ("PAUSE_FILE", Kind::Text),
+    ("PAUSE_MODE", Kind::Text),
+    ("POD_LOG_DIR", Kind::Text),
+    ("POD_LOG_FILES", Kind::Bool),
+    ("POD_SELECTOR_LABEL", Kind::Text),
+    ("PROC_SNAPSHOT", Kind::Bool),
+    ("RATE_LIMIT_MODE", Kind::Text),
+    ("REDACT_PATTERNS", Kind::Text),
+    ("SANDBOX", Kind::Bool),
+    ("SIGNALS", Kind::Text),
+    ("TIMEOUT", Kind::Number),
+    ("UNKNOWN_POD", Kind::Text),
+    ("UPLOAD_ACCESS_KEY", Kind::Text),
+    ("UPLOAD_BUCKET_NAME", Kind::Text),
+    ("UPLOAD_ENDPOINT", Kind::Text),
+    ("UPLOAD_KEEP", Kind::Bool),
+    ("UPLOAD_PREFIX", Kind::Text),
+    ("UPLOAD_REGION", Kind::Text),
+    ("UPLOAD_SECRET", Kind::Text),
+    ("USE_CRIO_CONF", Kind::Bool),
+    ("WEBHOOK_SECRET", Kind::Text),
+    ("WEBHOOK_TIMEOUT", Kind::Number),
+    ("WEBHOOK_URL", Kind::Text),
+    ("WORK_DIR", Kind::Text),
+    ("ZSTD_DICTIONARY", Kind::Text),
+];
+
+/// A value as the file wrote it, before it is checked against its key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Scalar {
+    Text(String),
+    Bool(bool),
+    Number(String),
+}
+
+/// The settings of the file at `path` as environment values. Settings that
+/// fail validation are left out and each is described in the errors, which
+/// name the key.
+pub fn read(path: &Path) -> Result<(BTreeMap<String, String>, Vec<String>), anyhow::Error> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| anyhow!("Reading {} failed: {}", path.display(), e))?;
+    let toml = path.extension().is_some_and(|e| e == "toml");
+    let entries = if toml {
+        parse_toml(&content)?
+    } else {
+        parse_yaml(&content)?
+    };
+    let mut settings = BTreeMap::new();
+    let mut errors = vec![];
+    for (key, value) in entries {
+        match validate(&key, value) {
+            Ok(value) => {
+                settings.insert(key.to_uppercase(), value);
+            }
+            Err(e) => errors.push(format!("{key}: {e}")),
+        }
+    }
+    Ok((settings, errors))
+}
+
+/// Sets the settings of the file named by CDC_CONFIG_FILE, in the
+/// environment or `dot_env`, that aren't in the environment already.
+/// Returns the validation errors, if any.
+pub fn load(dot_env: &Path) -> Option<String> {
+    let path = env::var(CONFIG_FILE_VAR)
+        .ok()
+        .or_else(|| {
+            fs::read_to_string(dot_env)
+                .ok()?
+                .lines()
+                .find_map(|line| line.strip_prefix(CONFIG_FILE_VAR)?.strip_prefix('='))
+                .map(|v| v.trim().trim_matches(['"', '\'']).to_string())
+        })
+        .filter(|v| !v.is_empty())?;
+    match read(Path::new(&path)) {
+        Ok((settings, errors)) => {
+            for (key, value) in settings {
+                if env::var_os(&key).is_none() {
+                    env::set_var(key, value);
+                }
+            }
+            if errors.is_empty() {
+                None
+            } else {
+                Some(format!("{path}: {}", errors.join(", ")))
+            }
+        }
+        Err(e) => Some(format!("{path}: {e}")),
+    }
+}
+
+fn validate(key: &str, value: Scalar) -> Result<String, anyhow::Error> {
+    let kind = KEYS
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(key))
+        .map(|(_, kind)| *kind)
+        .ok_or_else(|| anyhow!("unknown setting"))?;
+    match (kind, value) {
+        (Kind::Text, Scalar::Text(v) | Scalar::Number(v)) => Ok(v),
+        (Kind::Text, Scalar::Bool(v)) => Ok(v.to_string()),
+        (Kind::Bool, Scalar::Bool(v)) => Ok(v.to_string()),
+        (Kind::Bool, Scalar::Text(v)) if v.to_lowercase().parse::<bool>().is_ok() => Ok(v),
+        (Kind::Bool, v) => Err(anyhow!("expected true or false, got {}", show(&v))),
+        (Kind::Number, Scalar::Number(v)) if v.parse::<u64>().is_ok() => Ok(v),
+        (Kind::Number, Scalar::Text(v)) if v.parse::<u64>().is_ok() => Ok(v),
+        (Kind::Number, v) => Err(anyhow!("expected a whole number, got {}", show(&v))),
+    }
+}
+
+fn show(value: &Scalar) -> String {
+    match value {
+        Scalar::Text(v) => format!("{v:?}"),
+        Scalar::Bool(v) => v.to_string(),
+        Scalar::Number(v) => v.clone(),
+    }
+}
+
+fn parse_yaml(content: &str) -> Result<Vec<(String, Scalar)>, anyhow::Error> {
+    let mapping = match serde_yaml::from_str::<Value>(content)? {
+        Value::Mapping(m) => m,
+        Value::Null => return Ok(vec![]),
+        _ => return Err(anyhow!("expected a mapping of settings")),
+    };
+    let mut entries = vec![];
+    for (key, value) in mapping {
+        let key = match key {
+            Value::String(k) => k,
+            k => return Err(anyhow!("setting names must be strings, got {:?}", k)),
+        };
+        let value = match value {
+            Value::String(v) => Scalar::Text(v),
+            Value::Bool(v) => Scalar::Bool(v),
+            Value::Number(v) => Scalar::Number(v.to_string()),
+            _ => return Err(anyhow!("{key}: expected a string, number or boolean")),
+        };
+        entries.push((key, value));
+    }
+    Ok(entries)
+}
+
+fn parse_toml(content: &str) -> Result<Vec<(String, Scalar)>, anyhow::Error> {
+    let mut entries: Vec<(String, Scalar)> = vec![];
+    for (number, line) in content.lines().enumerate() {
+        let at = |e: &str| anyhow!("line {}: {}", number + 1, e);
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('[') {
+            return Err(at("tables are not supported, settings go at the top level"));
+        }
+        let (key, rest) = line
+            .split_once('=')
+            .ok_or_else(|| at("expected KEY = value"))?;
+        let key = key.trim().trim_matches('"').to_string();
+        if key.is_empty() {
+            return Err(at("missing setting name"));
+        }
+        let (value, rest) = toml_value(rest.trim()).map_err(|e| at(&format!("{key}: {e}")))?;
+        let rest = rest.trim_start();
+        if !rest.is_empty() && !rest.starts_with('#') {
+            return Err(at(&format!("{key}: unexpected {rest}")));
+        }
+        if entries.iter().any(|(k, _)| k.eq_ignore_ascii_case(&key)) {
+            return Err(at(&format!("{key}: set twice")));
+        }
+        entries.push((key, value));
+    }
+    Ok(entries)
+}
+
+/// The value at the start of `s` and what follows it.
+fn toml_value(s: &str) -> Result<(Scalar, &str), anyhow::Error> {
+    if let Some(literal) = s.strip_prefix('\'') {
+        let end = literal
+            .find('\'')
+            .ok_or_else(|| anyhow!("unterminated string"))?;
+        return Ok((
+            Scalar::Text(literal[..end].to_string()),
+            &literal[end + 1..],
+        ));
+    }
+    if let Some(basic) = s.strip_prefix('"') {
+        let mut text = String::new();
+        let mut chars = basic.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => return Ok((Scalar::Text(text), &basic[i + 1..])),
+                '\\' => match chars.next().map(|(_, c)| c) {
+                    Some('n') => text.push('\n'),
+                    Some('t') => text.push('\t'),
+                    Some('"') => text.push('"'),
+                    Some('\\') => text.push('\\'),
+                    other => return Err(anyhow!("unsupported escape \\{}", other.unwrap_or(' '))),
+                },
+                c => text.push(c),
+            }
+        }
+        return Err(anyhow!("unterminated string"));
+    }
+    let end = s
+        .find(|c: char| c.is_whitespace() || c == '#')
+        .unwrap_or(s.len());
+    let (word, rest) = s.split_at(end);
+    match word {
+        "true" => Ok((Scalar::Bool(true), rest)),
+        "false" => Ok((Scalar::Bool(false), rest)),
+        w if !w.is_empty()
+            && w.trim_start_matches(['+', '-'])
+                .chars()
+                .all(|c| c.is_ascii_digit() || c == '_') =>
+        {
+            Ok((
+                Scalar::Number(w.trim_start_matches('+').replace('_', "")),
+                rest,
+            ))
+        }
+        "" => Err(anyhow!("missing value")),
+        w => Err(anyhow!("expected a string, integer or boolean, got {w}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::configfile::{read, KEYS};
+    use std::fs;
+    use uuid::Uuid;
+
+    #[test]
+    fn read_test() {
+        let dir = std::env::temp_dir().join(format!("configfile-test-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let toml = dir.join("composer.toml");
+        fs::write(
+            &toml,
+            r#"
+# Written by ops, reviewed in git.
+LOG_LEVEL = "Info"
+timeout = 1_200 # seconds
+COMPRESSION = false
+FILENAME_TEMPLATE = '{namespace}/{pod}/{uuid}'
+SIGNALS = "SIGSEGV, SIGABRT"
+DMESG_LINES = "ten"
+IGNORE_CRIO = "maybe"
+MAX_CORE_BITS = 1
+"#,
+        )
+        .unwrap();
+        let (settings, errors) = read(&toml).unwrap();
+        assert_eq!(settings["LOG_LEVEL"], "Info");
+        assert_eq!(settings["TIMEOUT"], "1200");
+        assert_eq!(settings["COMPRESSION"], "false");
+        assert_eq!(settings["FILENAME_TEMPLATE"], "{namespace}/{pod}/{uuid}");
+        assert_eq!(settings["SIGNALS"], "SIGSEGV, SIGABRT");
+        assert_eq!(settings.len(), 5);
+        assert_eq!(
+            errors,
+            [
+                "DMESG_LINES: expected a whole number, got \"ten\"",
+                "IGNORE_CRIO: expected true or false, got \"maybe\"",
+                "MAX_CORE_BITS: unknown setting"
+            ]
+        );
+
+        fs::write(&toml, "[composer]\nTIMEOUT = 5\n").unwrap();
+        assert_eq!(
+            read(&toml).unwrap_err().to_string(),
+            "line 1: tables are not supported, settings go at the top level"
+        );
+        fs::write(&toml, "SIGNALS = [\"SIGSEGV\"]\n").unwrap();
+        assert_eq!(
+            read(&toml).unwrap_err().to_string(),
+            "line 1: SIGNALS: expected a string, integer or boolean, got [\"SIGSEGV\"]"
+        );
+        fs::write(&toml, "TIMEOUT = 5\ntimeout = 6\n").unwrap();
+        assert_eq!(
+            read(&toml).unwrap_err().to_string(),
+            "line 2: timeout: set twice"
+        );
+
+        let yaml = dir.join("composer.yaml");
+        fs::write(
+            &yaml,
+            "log_level: Debug\nmax_core_bytes: 1073741824\nnode_info: true\nsignals: [SIGSEGV]\n",
+        )
+        .unwrap();
+        assert_eq!(
+            read(&yaml).unwrap_err().to_string(),
+            "signals: expected a string, number or boolean"
+        );
+        fs::write(
+            &yaml,
+            "log_level: Debug\nmax_core_bytes: 1073741824\nnode_info: true\n",
+        )
+        .unwrap();
+        let (settings, errors) = read(&yaml).unwrap();
+        assert!(errors.is_empty());
+        assert_eq!(settings["MAX_CORE_BYTES"], "1073741824");
+        assert_eq!(settings["NODE_INFO"], "true");
+        fs::remove_dir_all(&dir).unwrap();
+
+        let mut names: Vec<&str> = KEYS.iter().map(|(name, _)| *name).collect();
+        names.sort_unstable();
+        names.dedup();
+        assert_eq!(names.len(), KEYS.len());
+    }
+}
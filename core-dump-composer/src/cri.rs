@@ -0,0 +1,924 @@
+//! A client of the CRI v1 gRPC API of the runtime's socket, used with
+//! CONTAINER_RUNTIME=cri instead of running crictl. The few calls
+//! the capture needs are encoded by hand like the events, see proto.rs,
+//! and their responses are turned into the JSON crictl prints for them so
+//! the rest of the capture reads the same fields from either.
+
+use crate::podlogs;
+use crate::proto::{Encode, ProtoWriter};
+use anyhow::anyhow;
+use hyper::body::HttpBody;
+use hyper::{Body, Request};
+use serde_json::{json, Map, Value};
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::net::UnixStream;
+
+pub const DEFAULT_ENDPOINT: &str = "unix:///run/containerd/containerd.sock";
+const SERVICE_PREFIX: &str = "/runtime.v1.";
+const CALL_TIMEOUT: Duration = Duration::from_secs(5);
+const RETRY_DELAY: Duration = Duration::from_millis(200);
+
+enum Wire<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+}
+
+/// The gRPC status codes, by number.
+const STATUS_CODES: &[&str] = &[
+    "OK",
+    "Cancelled",
+    "Unknown",
+    "InvalidArgument",
+    "DeadlineExceeded",
+    "NotFound",
+    "AlreadyExists",
+    "PermissionDenied",
+    "ResourceExhausted",
+    "FailedPrecondition",
+    "Aborted",
+    "OutOfRange",
+    "Unimplemented",
+    "Internal",
+    "Unavailable",
+    "DataLoss",
+    "Unauthenticated",
+];
+const DEADLINE_EXCEEDED: u32 = 4;
+const NOT_FOUND: u32 = 5;
+const INTERNAL: u32 = 13;
+const UNAVAILABLE: u32 = 14;
+
+/// Why a call failed, unlike crictl's stderr a runtime's answer keeps its
+/// gRPC status.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The socket couldn't be reached or the connection broke.
+    Transport(String),
+    Timeout,
+    /// The runtime answered with a status other than OK.
+    Status {
+        code: u32,
+        message: String,
+    },
+    /// The answer wasn't the message asked for.
+    Malformed(&'static str),
+    /// The call succeeded but had nothing matching.
+    Missing,
+}
+
+impl Error {
+    /// The gRPC code, Unavailable for a socket that can't be reached.
+    pub fn code(&self) -> u32 {
+        match self {
+            Error::Transport(_) => UNAVAILABLE,
+            Error::Timeout => DEADLINE_EXCEEDED,
+            Error::Status { code, .. } => *code,
+            Error::Malformed(_) => INTERNAL,
+            Error::Missing => NOT_FOUND,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Transport(e) => write!(f, "unreachable, {e}"),
+            Error::Timeout => write!(f, "timed out after {}s", CALL_TIMEOUT.as_secs()),
+            Error::Status { code, message } => {
+                let name = STATUS_CODES.get(*code as usize).unwrap_or(&"Unknown");
+                write!(f, "failed with {name}: {message}")
+            }
+            Error::Malformed(what) => write!(f, "answered {what}"),
+            Error::Missing => write!(f, "found nothing"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+fn transport(e: impl fmt::Display) -> Error {
+    Error::Transport(e.to_string())
+}
+
+/// A decoded protobuf message, its fields in wire order.
+#[derive(Default)]
+struct Message<'a>(Vec<(u32, Wire<'a>)>);
+
+fn varint(buf: &[u8], at: &mut usize) -> Option<u64> {
+    let mut v = 0u64;
+    for shift in (0..64).step_by(7) {
+        let b = *buf.get(*at)?;
+        *at += 1;
+        v |= ((b & 0x7f) as u64) << shift;
+        if b < 0x80 {
+            return Some(v);
+        }
+    }
+    None
+}
+
+impl<'a> Message<'a> {
+    /// Fixed width fields are skipped, the CRI messages read here have none.
+    fn parse(buf: &'a [u8]) -> Option<Message<'a>> {
+        let mut fields = vec![];
+        let mut at = 0;
+        while at < buf.len() {
+            let key = varint(buf, &mut at)?;
+            let field = (key >> 3) as u32;
+            match key & 7 {
+                0 => fields.push((field, Wire::Varint(varint(buf, &mut at)?))),
+                1 => at += 8,
+                2 => {
+                    let len = varint(buf, &mut at)? as usize;
+                    let end = at.checked_add(len)?;
+                    fields.push((field, Wire::Bytes(buf.get(at..end)?)));
+                    at = end;
+                }
+                5 => at += 4,
+                _ => return None,
+            }
+        }
+        Some(Message(fields))
+    }
+
+    fn bytes(&self, field: u32) -> impl Iterator<Item = &'a [u8]> + '_ {
+        self.0.iter().filter_map(move |(f, w)| match w {
+            Wire::Bytes(b) if *f == field => Some(*b),
+            _ => None,
+        })
+    }
+
+    fn string(&self, field: u32) -> String {
+        self.bytes(field)
+            .last()
+            .map(|b| String::from_utf8_lossy(b).to_string())
+            .unwrap_or_default()
+    }
+
+    fn strings(&self, field: u32) -> Vec<String> {
+        self.bytes(field)
+            .map(|b| String::from_utf8_lossy(b).to_string())
+            .collect()
+    }
+
+    fn varint(&self, field: u32) -> u64 {
+        self.0
+            .iter()
+            .filter_map(|(f, w)| match w {
+                Wire::Varint(v) if *f == field => Some(*v),
+                _ => None,
+            })
+            .next_back()
+            .unwrap_or_default()
+    }
+
+    fn message(&self, field: u32) -> Message<'a> {
+        self.bytes(field)
+            .last()
+            .and_then(Message::parse)
+            .unwrap_or_default()
+    }
+
+    fn messages(&self, field: u32) -> Vec<Message<'a>> {
+        self.bytes(field).filter_map(Message::parse).collect()
+    }
+
+    fn map(&self, field: u32) -> Map<String, Value> {
+        self.messages(field)
+            .iter()
+            .map(|e| (e.string(1), Value::String(e.string(2))))
+            .collect()
+    }
+}
+
+/// The enum names crictl prints.
+fn enum_name(names: &[&str], v: u64) -> String {
+    names.get(v as usize).unwrap_or(&"UNKNOWN").to_string()
+}
+
+const SANDBOX_STATES: &[&str] = &["SANDBOX_READY", "SANDBOX_NOTREADY"];
+const CONTAINER_STATES: &[&str] = &[
+    "CONTAINER_CREATED",
+    "CONTAINER_RUNNING",
+    "CONTAINER_EXITED",
+    "CONTAINER_UNKNOWN",
+];
+const NAMESPACE_MODES: &[&str] = &["POD", "CONTAINER", "NODE", "TARGET"];
+
+/// Nanoseconds since the epoch as the RFC 3339 time `crictl inspect`
+/// prints.
+pub fn rfc3339(nanos: i64) -> String {
+    let secs = nanos.div_euclid(1_000_000_000);
+    let (days, rem) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    // Days to civil date, H. Hinnant's algorithm.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:09}Z",
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60,
+        nanos.rem_euclid(1_000_000_000)
+    )
+}
+
+fn pod_metadata(m: &Message) -> Value {
+    json!({
+        "name": m.string(1),
+        "uid": m.string(2),
+        "namespace": m.string(3),
+        "attempt": m.varint(4),
+    })
+}
+
+/// A PodSandbox as `crictl pods -o json` lists it.
+fn pod_sandbox(m: &Message) -> Value {
+    json!({
+        "id": m.string(1),
+        "metadata": pod_metadata(&m.message(2)),
+        "state": enum_name(SANDBOX_STATES, m.varint(3)),
+        "createdAt": (m.varint(4) as i64).to_string(),
+        "labels": m.map(5),
+        "annotations": m.map(6),
+        "runtimeHandler": m.string(7),
+    })
+}
+
+/// The sandbox of the pod `name` in a ListPodSandboxResponse, the ready
+/// one and then the newest when the pod was recreated.
+fn select_pod(response: &Message, name: &str) -> Option<Value> {
+    response
+        .messages(1)
+        .iter()
+        .map(pod_sandbox)
+        .filter(|p| p["metadata"]["name"] == name)
+        .max_by_key(|p| {
+            let created = p["createdAt"].as_str().and_then(|c| c.parse::<i64>().ok());
+            (p["state"] == "SANDBOX_READY", created)
+        })
+}
+
+/// The verbose info of a status response. containerd puts its JSON under
+/// one `info` key, which crictl prints unwrapped.
+fn verbose_info(m: &Message, field: u32) -> Value {
+    let info: Map<String, Value> = m
+        .messages(field)
+        .iter()
+        .map(|e| {
+            let value = e.string(2);
+            let parsed = serde_json::from_str(&value).unwrap_or(Value::String(value));
+            (e.string(1), parsed)
+        })
+        .collect();
+    match info.get("info") {
+        Some(v) if info.len() == 1 => v.clone(),
+        _ => Value::Object(info),
+    }
+}
+
+/// A PodSandboxStatusResponse as `crictl inspectp` prints it.
+fn pod_sandbox_status(response: &Message) -> Value {
+    let m = response.message(1);
+    let network = m.message(5);
+    let options = m.message(6).message(1).message(2);
+    json!({
+        "status": {
+            "id": m.string(1),
+            "metadata": pod_metadata(&m.message(2)),
+            "state": enum_name(SANDBOX_STATES, m.varint(3)),
+            "createdAt": rfc3339(m.varint(4) as i64),
+            "network": {
+                "ip": network.string(1),
+                "additionalIps": network
+                    .messages(2)
+                    .iter()
+                    .map(|ip| json!({ "ip": ip.string(1) }))
+                    .collect::<Vec<_>>(),
+            },
+            "linux": {
+                "namespaces": {
+                    "options": {
+                        "network": enum_name(NAMESPACE_MODES, options.varint(1)),
+                        "pid": enum_name(NAMESPACE_MODES, options.varint(2)),
+                        "ipc": enum_name(NAMESPACE_MODES, options.varint(3)),
+                        "targetId": options.string(4),
+                    }
+                }
+            },
+            "labels": m.map(7),
+            "annotations": m.map(8),
+            "runtimeHandler": m.string(9),
+        },
+        "info": verbose_info(response, 2),
+    })
+}
+
+/// A Container as `crictl ps -o json` lists it.
+fn container(m: &Message) -> Value {
+    let metadata = m.message(3);
+    let image = m.message(4);
+    json!({
+        "id": m.string(1),
+        "podSandboxId": m.string(2),
+        "metadata": { "name": metadata.string(1), "attempt": metadata.varint(2) },
+        "image": { "image": image.string(1), "annotations": image.map(2) },
+        "imageRef": m.string(5),
+        "state": enum_name(CONTAINER_STATES, m.varint(6)),
+        "createdAt": (m.varint(7) as i64).to_string(),
+        "labels": m.map(8),
+        "annotations": m.map(9),
+    })
+}
+
+/// A ContainerStatusResponse as `crictl inspect` prints it.
+fn container_status(response: &Message) -> Value {
+    let m = response.message(1);
+    let metadata = m.message(2);
+    let time = |field| match m.varint(field) as i64 {
+        0 => "0001-01-01T00:00:00Z".to_string(),
+        nanos => rfc3339(nanos),
+    };
+    json!({
+        "status": {
+            "id": m.string(1),
+            "metadata": { "name": metadata.string(1), "attempt": metadata.varint(2) },
+            "state": enum_name(CONTAINER_STATES, m.varint(3)),
+            "createdAt": time(4),
+            "startedAt": time(5),
+            "finishedAt": time(6),
+            "exitCode": m.varint(7) as i32,
+            "image": { "image": m.message(8).string(1) },
+            "imageRef": m.string(9),
+            "reason": m.string(10),
+            "message": m.string(11),
+            "labels": m.map(12),
+            "annotations": m.map(13),
+            "logPath": m.string(15),
+        },
+        "info": verbose_info(response, 2),
+    })
+}
+
+/// An Image as `crictl img -o json` lists it.
+fn image(m: &Message) -> Value {
+    json!({
+        "id": m.string(1),
+        "repoTags": m.strings(2),
+        "repoDigests": m.strings(3),
+        "size": m.varint(4).to_string(),
+        "username": m.string(6),
+        "pinned": m.varint(8) != 0,
+    })
+}
+
+/// Turns the lines of a CRI log file, `<time> <stream> <P|F> <text>`, back
+/// into what the container wrote. Partial lines are joined.
+fn decode_log(raw: &[u8]) -> Vec<u8> {
+    let mut out = vec![];
+    for line in raw.split(|b| *b == b'\n').filter(|l| !l.is_empty()) {
+        let mut parts = line.splitn(4, |b| *b == b' ');
+        let (_, _, tag) = (parts.next(), parts.next(), parts.next());
+        match (tag, parts.next()) {
+            (Some(tag), Some(text)) => {
+                out.extend_from_slice(text);
+                if tag != b"P" {
+                    out.push(b'\n');
+                }
+            }
+            // Not CRI formatted, kept as it is.
+            _ => {
+                out.extend_from_slice(line);
+                out.push(b'\n');
+            }
+        }
+    }
+    out
+}
+
+struct FilterRequest<'a> {
+    /// The field of the filter in the request.
+    field: u32,
+    id: &'a str,
+    pod_sandbox_id: Option<&'a str>,
+}
+
+impl Encode for FilterRequest<'_> {
+    fn encode(&self, w: &mut ProtoWriter) {
+        struct Filter<'a>(&'a FilterRequest<'a>);
+        impl Encode for Filter<'_> {
+            fn encode(&self, w: &mut ProtoWriter) {
+                w.string(1, self.0.id);
+                if let Some(pod) = self.0.pod_sandbox_id {
+                    w.string(3, pod);
+                }
+            }
+        }
+        w.message(self.field, &Filter(self));
+    }
+}
+
+struct StatusRequest<'a> {
+    id: &'a str,
+    verbose: bool,
+}
+
+impl Encode for StatusRequest<'_> {
+    fn encode(&self, w: &mut ProtoWriter) {
+        w.string(1, self.id);
+        w.bool(2, self.verbose);
+    }
+}
+
+struct ImageSpec<'a>(&'a str);
+
+impl Encode for ImageSpec<'_> {
+    fn encode(&self, w: &mut ProtoWriter) {
+        w.string(1, self.0);
+    }
+}
+
+struct ImageStatusRequest<'a>(ImageSpec<'a>);
+
+impl Encode for ImageStatusRequest<'_> {
+    fn encode(&self, w: &mut ProtoWriter) {
+        w.message(1, &self.0);
+    }
+}
+
+struct VersionRequest;
+
+impl Encode for VersionRequest {
+    fn encode(&self, w: &mut ProtoWriter) {
+        w.string(1, "v1");
+    }
+}
+
+pub struct CriClient {
+    socket: PathBuf,
+    runtime: tokio::runtime::Runtime,
+}
+
+impl CriClient {
+    /// For an endpoint such as `unix:///run/containerd/containerd.sock`.
+    pub fn new(endpoint: &str) -> Result<CriClient, anyhow::Error> {
+        let socket = endpoint.strip_prefix("unix://").unwrap_or(endpoint);
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        Ok(CriClient {
+            socket: PathBuf::from(socket),
+            runtime,
+        })
+    }
+
+    /// One unary call, returning the response message.
+    fn call<T: Encode>(&self, method: &str, request: &T) -> Result<Vec<u8>, Error> {
+        let mut w = ProtoWriter::default();
+        request.encode(&mut w);
+        let mut framed = vec![0u8];
+        framed.extend_from_slice(&(w.buf.len() as u32).to_be_bytes());
+        framed.extend(w.buf);
+        let call = async {
+            let stream = UnixStream::connect(&self.socket).await.map_err(transport)?;
+            let (mut sender, connection) = hyper::client::conn::Builder::new()
+                .http2_only(true)
+                .handshake::<_, Body>(stream)
+                .await
+                .map_err(transport)?;
+            tokio::spawn(connection);
+            let request = Request::post(format!("http://localhost{method}"))
+                .header("content-type", "application/grpc")
+                .header("te", "trailers")
+                .body(Body::from(framed))
+                .map_err(transport)?;
+            let response = sender.send_request(request).await.map_err(transport)?;
+            let mut status = grpc_status(response.headers());
+            let mut body = response.into_body();
+            let mut buf = vec![];
+            while let Some(chunk) = body.data().await {
+                buf.extend_from_slice(&chunk.map_err(transport)?);
+            }
+            if let Some(trailers) = body.trailers().await.map_err(transport)? {
+                status = status.or_else(|| grpc_status(&trailers));
+            }
+            unframe(status, &buf)
+        };
+        self.runtime
+            .block_on(async { tokio::time::timeout(CALL_TIMEOUT, call).await })
+            .map_err(|_| Error::Timeout)?
+    }
+
+    /// A call whose response `f` turns into crictl's JSON, None being
+    /// nothing found.
+    fn calls<T: Encode>(
+        &self,
+        method: &str,
+        request: &T,
+        f: impl FnOnce(&Message) -> Option<Value>,
+    ) -> Result<Value, Error> {
+        let method = format!("{SERVICE_PREFIX}{method}");
+        let response = match self.call(&method, request) {
+            // What a runtime that is restarting answers, tried once more.
+            Err(e) if e.code() == UNAVAILABLE => {
+                std::thread::sleep(RETRY_DELAY);
+                self.call(&method, request)?
+            }
+            response => response?,
+        };
+        let message = Message::parse(&response).ok_or(Error::Malformed("garbage"))?;
+        f(&message).ok_or(Error::Missing)
+    }
+
+    /// The pod sandbox whose pod is `name`, the running one when a pod was
+    /// recreated. Unlike `crictl pods --name` the name has to match
+    /// exactly.
+    pub fn pod(&self, name: &str) -> Result<Value, Error> {
+        let request = FilterRequest {
+            field: 1,
+            id: "",
+            pod_sandbox_id: None,
+        };
+        self.calls("RuntimeService/ListPodSandbox", &request, |m| {
+            select_pod(m, name)
+        })
+    }
+
+    /// The pod sandbox the container `container_id` runs in.
+    pub fn container_pod(&self, container_id: &str) -> Result<Value, Error> {
+        let request = FilterRequest {
+            field: 1,
+            id: container_id,
+            pod_sandbox_id: None,
+        };
+        let found = self.calls("RuntimeService/ListContainers", &request, |m| {
+            m.messages(1).first().map(container)
+        })?;
+        let request = FilterRequest {
+            field: 1,
+            id: found["podSandboxId"].as_str().unwrap_or_default(),
+            pod_sandbox_id: None,
+        };
+        self.calls("RuntimeService/ListPodSandbox", &request, |m| {
+            m.messages(1).first().map(pod_sandbox)
+        })
+    }
+
+    pub fn inspect_pod(&self, pod_id: &str) -> Result<Value, Error> {
+        let request = StatusRequest {
+            id: pod_id,
+            verbose: true,
+        };
+        self.calls("RuntimeService/PodSandboxStatus", &request, |m| {
+            Some(pod_sandbox_status(m))
+        })
+    }
+
+    /// The containers of the pod, in any state when `all`.
+    pub fn pod_containers(&self, pod_id: &str, all: bool) -> Result<Value, Error> {
+        let request = FilterRequest {
+            field: 1,
+            id: "",
+            pod_sandbox_id: Some(pod_id),
+        };
+        self.calls("RuntimeService/ListContainers", &request, |m| {
+            let containers: Vec<Value> = m
+                .messages(1)
+                .iter()
+                .map(container)
+                .filter(|c| all || c["state"] == "CONTAINER_RUNNING")
+                .collect();
+            Some(json!({ "containers": containers }))
+        })
+    }
+
+    pub fn inspect_container(&self, container_id: &str) -> Result<Value, Error> {
+        let request = StatusRequest {
+            id: container_id,
+            verbose: false,
+        };
+        self.calls("RuntimeService/ContainerStatus", &request, |m| {
+            Some(container_status(m))
+        })
+    }
+
+    /// The image by id or digest, as ps reports it in `imageRef`.
+    pub fn image(&self, image_ref: &str) -> Result<Value, Error> {
+        let request = ImageStatusRequest(ImageSpec(image_ref));
+        self.calls("ImageService/ImageStatus", &request, |m| {
+            let found = m.message(1);
+            (!found.0.is_empty()).then(|| image(&found))
+        })
+    }
+
+    /// The runtime's name and version as `crictl version -o json` prints
+    /// them.
+    pub fn version(&self) -> Result<Value, Error> {
+        self.calls("RuntimeService/Version", &VersionRequest, |m| {
+            Some(json!({
+                "version": m.string(1),
+                "runtimeName": m.string(2),
+                "runtimeVersion": m.string(3),
+                "runtimeApiVersion": m.string(4),
+            }))
+        })
+    }
+
+    /// The last `count` lines of the container's log, read from the file
+    /// the runtime writes it to.
+    pub fn logs(&self, container_id: &str, count: u32) -> Result<Vec<u8>, anyhow::Error> {
+        let status = self.inspect_container(container_id)?;
+        let path = status["status"]["logPath"]
+            .as_str()
+            .filter(|p| !p.is_empty())
+            .ok_or_else(|| anyhow!("no log path for container {}", container_id))?;
+        let raw = podlogs::tail(&[PathBuf::from(path)], count as usize)?;
+        Ok(decode_log(&raw))
+    }
+}
+
+fn grpc_status(headers: &hyper::HeaderMap) -> Option<(String, String)> {
+    let code = headers.get("grpc-status")?.to_str().ok()?.to_string();
+    let message = headers
+        .get("grpc-message")
+        .and_then(|m| m.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    Some((code, message))
+}
+
+/// The message of a response with `status`, from its headers or trailers.
+fn unframe(status: Option<(String, String)>, buf: &[u8]) -> Result<Vec<u8>, Error> {
+    if let Some((code, message)) = status.filter(|(code, _)| code != "0") {
+        return Err(Error::Status {
+            code: code.parse().unwrap_or(2),
+            message,
+        });
+    }
+    if buf.len() < 5 || buf[0] != 0 {
+        return Err(Error::Malformed("no uncompressed message"));
+    }
+    let len = u32::from_be_bytes([buf[1], buf[2], buf[3], buf[4]]) as usize;
+    buf.get(5..5 + len)
+        .map(|m| m.to_vec())
+        .ok_or(Error::Malformed("a truncated message"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cri::{
+        container, container_status, decode_log, image, pod_sandbox_status, rfc3339, select_pod,
+        CriClient, Error, Message,
+    };
+    use crate::proto::ProtoWriter;
+    use hyper::service::service_fn;
+    use hyper::{Body, HeaderMap, Request, Response};
+    use std::convert::Infallible;
+    use std::path::PathBuf;
+
+    fn message(build: impl FnOnce(&mut ProtoWriter)) -> Vec<u8> {
+        let mut w = ProtoWriter::default();
+        build(&mut w);
+        w.buf
+    }
+
+    fn sandbox(id: &str, name: &str, state: i64, created: i64) -> Vec<u8> {
+        message(|w| {
+            w.string(1, id);
+            w.bytes(
+                2,
+                &message(|m| {
+                    m.string(1, name);
+                    m.string(2, "f00d");
+                    m.string(3, "mo");
+                }),
+            );
+            w.int64(3, state);
+            w.int64(4, created);
+            w.map_entry(5, "app", "mo");
+            w.map_entry(6, "coredump.matrixorigin.io/enabled", "true");
+        })
+    }
+
+    #[test]
+    fn pods_test() {
+        let response = message(|w| {
+            w.bytes(1, &sandbox("old", "mo-0", 1, 100));
+            w.bytes(1, &sandbox("new", "mo-0", 0, 50));
+            w.bytes(1, &sandbox("other", "mo-01", 0, 200));
+        });
+        let response = Message::parse(&response).unwrap();
+        let pod = select_pod(&response, "mo-0").unwrap();
+        assert_eq!(pod["id"], "new");
+        assert_eq!(pod["state"], "SANDBOX_READY");
+        assert_eq!(pod["metadata"]["namespace"], "mo");
+        assert_eq!(pod["metadata"]["uid"], "f00d");
+        assert_eq!(pod["labels"]["app"], "mo");
+        assert_eq!(
+            pod["annotations"]["coredump.matrixorigin.io/enabled"],
+            "true"
+        );
+        assert!(select_pod(&response, "mo").is_none());
+    }
+
+    #[test]
+    fn inspectp_test() {
+        let status = message(|w| {
+            w.string(1, "new");
+            w.int64(3, 0);
+            w.int64(4, 1_706_263_200_000_000_000);
+            w.bytes(
+                5,
+                &message(|n| {
+                    n.string(1, "10.0.0.7");
+                    n.bytes(2, &message(|ip| ip.string(1, "fd00::7")));
+                }),
+            );
+            let options = message(|o| o.int64(1, 2));
+            let namespaces = message(|n| n.bytes(2, &options));
+            w.bytes(6, &message(|l| l.bytes(1, &namespaces)));
+        });
+        let response = message(|w| {
+            w.bytes(1, &status);
+            w.map_entry(2, "info", r#"{"pid": 42, "runtimeSpec": {"mounts": []}}"#);
+        });
+        let inspect = pod_sandbox_status(&Message::parse(&response).unwrap());
+        assert_eq!(inspect["status"]["network"]["ip"], "10.0.0.7");
+        assert_eq!(
+            inspect["status"]["network"]["additionalIps"][0]["ip"],
+            "fd00::7"
+        );
+        assert_eq!(
+            inspect["status"]["linux"]["namespaces"]["options"]["network"],
+            "NODE"
+        );
+        assert_eq!(
+            inspect["status"]["createdAt"],
+            "2024-01-26T10:00:00.000000000Z"
+        );
+        assert_eq!(inspect["info"]["pid"], 42);
+    }
+
+    #[test]
+    fn containers_test() {
+        let c = message(|w| {
+            w.string(1, "c1");
+            w.string(2, "new");
+            w.bytes(3, &message(|m| m.string(1, "main")));
+            w.bytes(4, &message(|i| i.string(1, "docker.io/mo/mo:1")));
+            w.string(5, "sha256:3b8a");
+            w.int64(6, 1);
+        });
+        let c = container(&Message::parse(&c).unwrap());
+        assert_eq!(c["imageRef"], "sha256:3b8a");
+        assert_eq!(c["state"], "CONTAINER_RUNNING");
+        assert_eq!(c["metadata"]["name"], "main");
+        assert_eq!(c["image"]["image"], "docker.io/mo/mo:1");
+
+        let status = message(|w| {
+            w.string(1, "c0");
+            w.bytes(2, &message(|m| m.string(1, "main")));
+            w.int64(3, 2);
+            w.int64(6, 1_709_209_685_000_000_000);
+            w.string(10, "OOMKilled");
+            w.string(15, "/var/log/pods/mo_mo-0_f00d/main/0.log");
+        });
+        let response = message(|w| w.bytes(1, &status));
+        let inspect = container_status(&Message::parse(&response).unwrap());
+        assert_eq!(inspect["status"]["reason"], "OOMKilled");
+        assert_eq!(
+            inspect["status"]["finishedAt"],
+            "2024-02-29T12:28:05.000000000Z"
+        );
+        assert_eq!(inspect["status"]["startedAt"], "0001-01-01T00:00:00Z");
+        assert_eq!(
+            inspect["status"]["logPath"],
+            "/var/log/pods/mo_mo-0_f00d/main/0.log"
+        );
+
+        let img = message(|w| {
+            w.string(1, "sha256:3b8a");
+            w.string(3, "docker.io/mo/mo@sha256:9f1c");
+            w.int64(4, 1024);
+        });
+        let img = image(&Message::parse(&img).unwrap());
+        assert_eq!(img["repoDigests"][0], "docker.io/mo/mo@sha256:9f1c");
+        assert_eq!(img["size"], "1024");
+    }
+
+    #[test]
+    fn decode_log_test() {
+        let raw = b"2024-01-26T10:00:00.1Z stdout F started\n\
+2024-01-26T10:00:00.2Z stderr P panic: \n\
+2024-01-26T10:00:00.3Z stderr F runtime error\n\
+raw line\n";
+        assert_eq!(
+            decode_log(raw),
+            b"started\npanic: runtime error\nraw line\n".to_vec()
+        );
+        assert_eq!(rfc3339(0), "1970-01-01T00:00:00.000000000Z");
+        assert_eq!(
+            rfc3339(951_782_400_500_000_000),
+            "2000-02-29T00:00:00.500000000Z"
+        );
+    }
+
+    /// The message for a method, or the status code and message.
+    type Answer = fn(&str) -> Result<Vec<u8>, (u32, &'static str)>;
+
+    /// Serves `answer` on a unix socket like a runtime, a message with OK in
+    /// the trailers or a status without one.
+    fn serve(answer: Answer) -> PathBuf {
+        let socket = std::env::temp_dir().join(format!("cri-{}.sock", uuid::Uuid::new_v4()));
+        let listener = std::os::unix::net::UnixListener::bind(&socket).unwrap();
+        listener.set_nonblocking(true).unwrap();
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            runtime.block_on(async move {
+                let listener = tokio::net::UnixListener::from_std(listener).unwrap();
+                loop {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    let service = service_fn(move |req: Request<Body>| async move {
+                        let response =
+                            Response::builder().header("content-type", "application/grpc");
+                        let response = match answer(req.uri().path()) {
+                            Ok(message) => {
+                                let mut framed = vec![0u8];
+                                framed.extend_from_slice(&(message.len() as u32).to_be_bytes());
+                                framed.extend(message);
+                                let (mut sender, body) = Body::channel();
+                                tokio::spawn(async move {
+                                    sender.send_data(framed.into()).await.unwrap();
+                                    let mut trailers = HeaderMap::new();
+                                    trailers.insert("grpc-status", "0".parse().unwrap());
+                                    sender.send_trailers(trailers).await.unwrap();
+                                });
+                                response.body(body)
+                            }
+                            Err((code, message)) => response
+                                .header("grpc-status", code.to_string())
+                                .header("grpc-message", message)
+                                .body(Body::empty()),
+                        };
+                        Ok::<_, Infallible>(response.unwrap())
+                    });
+                    tokio::spawn(
+                        hyper::server::conn::Http::new()
+                            .http2_only(true)
+                            .serve_connection(stream, service),
+                    );
+                }
+            });
+        });
+        socket
+    }
+
+    #[test]
+    fn client_test() {
+        let socket = serve(|method| match method {
+            "/runtime.v1.RuntimeService/ListPodSandbox" => Ok(message(|w| {
+                w.bytes(1, &sandbox("p1", "mo-0", 0, 100));
+            })),
+            "/runtime.v1.RuntimeService/ListContainers" => Ok(message(|w| {
+                w.bytes(
+                    1,
+                    &message(|c| {
+                        c.string(1, "c1");
+                        c.string(2, "p1");
+                    }),
+                );
+            })),
+            "/runtime.v1.RuntimeService/Version" => Ok(message(|w| {
+                w.string(1, "0.1.0");
+                w.string(2, "containerd");
+                w.string(3, "v1.7.13");
+                w.string(4, "v1");
+            })),
+            _ => Err((5, "no such image")),
+        });
+        let cri = CriClient::new(&format!("unix://{}", socket.display())).unwrap();
+        assert_eq!(cri.pod("mo-0").unwrap()["id"], "p1");
+        assert_eq!(cri.pod("mo-1"), Err(Error::Missing));
+        assert_eq!(cri.container_pod("c1").unwrap()["id"], "p1");
+        assert_eq!(cri.version().unwrap()["runtimeVersion"], "v1.7.13");
+        let missing = cri.image("sha256:3b8a").unwrap_err();
+        assert_eq!(missing.code(), 5);
+        assert_eq!(missing.to_string(), "failed with NotFound: no such image");
+        std::fs::remove_file(&socket).unwrap();
+
+        let gone = cri.pod("mo-0").unwrap_err();
+        assert!(matches!(gone, Error::Transport(_)));
+        assert_eq!(gone.code(), 14);
+    }
+}
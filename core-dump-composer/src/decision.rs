@@ -0,0 +1,102 @@
+use crate::config::CoreParams;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Outcome {
+    #[default]
+    Captured,
+    MetadataOnly,
+    Skipped,
+}
+
+impl Outcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Captured => "captured",
+            Outcome::MetadataOnly => "metadata-only",
+            Outcome::Skipped => "skipped",
+        }
+    }
+}
+
+/// One rule or filter evaluated for the invocation.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub name: String,
+    pub passed: bool,
+    pub detail: String,
+}
+
+/// Why a crash was or wasn't captured, in evaluation order, so "where is my
+/// core dump?" can be answered from the decisions log alone.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct Decision {
+    pub outcome: Outcome,
+    pub checks: Vec<Check>,
+}
+
+impl Decision {
+    pub fn check(&mut self, name: &str, passed: bool, detail: impl Into<String>) {
+        self.checks.push(Check {
+            name: name.to_string(),
+            passed,
+            detail: detail.into(),
+        });
+    }
+
+    /// The decisions log line, keyed by the fields operators search by.
+    pub fn record(&self, params: &CoreParams) -> String {
+        json!({
+            "uuid": params.uuid,
+            "timestamp": params.timestamp,
+            "exe": params.exe_name,
+            "host_pid": params.host_pid,
+            "signal": params.signal,
+            "namespace": params.namespace,
+            "podname": params.podname,
+            "outcome": self.outcome,
+            "checks": self.checks,
+        })
+        .to_string()
+    }
+
+    pub fn append(&self, params: &CoreParams, log: &Path) -> std::io::Result<()> {
+        let mut file = OpenOptions::new().create(true).append(true).open(log)?;
+        writeln!(file, "{}", self.record(params))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::CoreConfig;
+    use crate::decision::{Decision, Outcome};
+    use std::fs;
+
+    #[test]
+    fn decision_log_test() {
+        let mut config = CoreConfig::new().unwrap();
+        config.params.exe_name = "mo-service".to_string();
+        let mut decision = Decision::default();
+        decision.check("pause", true, "no pause file");
+        decision.check("pod_selector", false, "label capture missing");
+        decision.outcome = Outcome::Skipped;
+
+        let log = std::env::temp_dir().join(format!("decisions-{}.log", config.params.uuid));
+        decision.append(&config.params, &log).unwrap();
+        decision.append(&config.params, &log).unwrap();
+        let content = fs::read_to_string(&log).unwrap();
+        assert_eq!(content.lines().count(), 2);
+        let line: serde_json::Value =
+            serde_json::from_str(content.lines().next().unwrap()).unwrap();
+        assert_eq!(line["outcome"], "skipped");
+        assert_eq!(line["exe"], "mo-service");
+        assert_eq!(line["checks"][1]["name"], "pod_selector");
+        assert_eq!(line["checks"][1]["passed"], false);
+        fs::remove_file(&log).unwrap();
+    }
+}
@@ -0,0 +1,420 @@
+//! Experimental delta encoding of cores against an earlier core of the same
+//! build, for crash loops where consecutive cores are mostly identical.
+//!
+//! Core segments are page aligned so the base is indexed in page sized
+//! blocks and every block of the new core is either copied from the base or
+//! stored literally. The stream format is:
+//!
+//! ```text
+//! "CDHDLT01"
+//! 'C' <base offset: u64 le> <length: u64 le>   copy from the base core
+//! 'L' <length: u64 le> <bytes>                 literal bytes
+//! 'E' <total length: u64 le>                   end of stream
+//! ```
+//!
+//! `core-dump-agent reconstruct` applies a delta to its base.
+
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+pub const MAGIC: &[u8; 8] = b"CDHDLT01";
+const BLOCK: usize = 4096;
+const MAX_LITERAL: usize = 1 << 20;
+/// The bases kept without DELTA_MAX_BASES.
+pub const DEFAULT_MAX_BASES: usize = 8;
+
+/// The capture that holds the full core a delta was encoded against.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DeltaBase {
+    pub build_id: String,
+    pub uuid: String,
+    pub dump_file: String,
+}
+
+/// Stores one raw base core per build-id under `dir`. The modification time
+/// of a base is when a capture last used it, the least recently used go
+/// first when the store is full.
+pub struct BaseStore {
+    dir: PathBuf,
+}
+
+impl BaseStore {
+    pub fn new(dir: PathBuf) -> BaseStore {
+        BaseStore { dir }
+    }
+
+    pub fn core_path(&self, build_id: &str) -> PathBuf {
+        self.dir.join(format!("{build_id}.core"))
+    }
+
+    fn info_path(&self, build_id: &str) -> PathBuf {
+        self.dir.join(format!("{build_id}.json"))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The base of `build_id`, marked as used.
+    pub fn get(&self, build_id: &str) -> Option<DeltaBase> {
+        let core = File::options()
+            .write(true)
+            .open(self.core_path(build_id))
+            .ok()?;
+        let content = fs::read_to_string(self.info_path(build_id)).ok()?;
+        let base = serde_json::from_str(&content).ok()?;
+        let _ = core.set_modified(SystemTime::now());
+        Some(base)
+    }
+
+    /// The build-id, size and last use of every base, least recently used
+    /// first.
+    fn bases(&self) -> io::Result<Vec<(String, u64, SystemTime)>> {
+        let mut bases = vec![];
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("core") {
+                continue;
+            }
+            let Some(build_id) = path.file_stem().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            let metadata = fs::metadata(&path)?;
+            bases.push((build_id.to_string(), metadata.len(), metadata.modified()?));
+        }
+        bases.sort_by_key(|(_, _, used)| *used);
+        Ok(bases)
+    }
+
+    /// Evicts the least recently used bases until one more of `size` bytes
+    /// fits in `max_bases` and `max_bytes`, DELTA_MAX_BASES and
+    /// DELTA_MAX_BASE_BYTES. Returns the build-ids evicted.
+    pub fn make_room(
+        &self,
+        max_bases: Option<usize>,
+        max_bytes: Option<u64>,
+        size: u64,
+    ) -> io::Result<Vec<String>> {
+        if let Some(max) = max_bytes.filter(|max| size > *max) {
+            return Err(io::Error::other(format!(
+                "a base of {size} bytes is over DELTA_MAX_BASE_BYTES {max}"
+            )));
+        }
+        fs::create_dir_all(&self.dir)?;
+        let bases = self.bases()?;
+        let mut count = bases.len();
+        let mut total: u64 = bases.iter().map(|(_, len, _)| len).sum();
+        let mut evicted = vec![];
+        for (build_id, len, _) in bases {
+            let full = max_bases.is_some_and(|max| count >= max)
+                || max_bytes.is_some_and(|max| total + size > max);
+            if !full {
+                break;
+            }
+            for path in [self.core_path(&build_id), self.info_path(&build_id)] {
+                match fs::remove_file(&path) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+            count -= 1;
+            total -= len;
+            evicted.push(build_id);
+        }
+        Ok(evicted)
+    }
+
+    /// Creates the file the base core is written to while it is captured.
+    pub fn create(&self, base: &DeltaBase) -> io::Result<File> {
+        fs::create_dir_all(&self.dir)?;
+        fs::write(self.info_path(&base.build_id), serde_json::to_vec(base)?)?;
+        File::create(self.core_path(&base.build_id))
+    }
+}
+
+/// Copies everything read through it into `copy`, used to keep the first
+/// core of a build as the base while it is compressed into the archive.
+pub struct TeeReader<R: Read, W: Write> {
+    reader: R,
+    copy: W,
+}
+
+impl<R: Read, W: Write> TeeReader<R, W> {
+    pub fn new(reader: R, copy: W) -> TeeReader<R, W> {
+        TeeReader { reader, copy }
+    }
+}
+
+impl<R: Read, W: Write> Read for TeeReader<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        self.copy.write_all(&buf[..n])?;
+        Ok(n)
+    }
+}
+
+fn block_hash(block: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    block.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn read_block<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
+
+struct Encoder<W: Write> {
+    writer: W,
+    copy: Option<(u64, u64)>,
+    literal: Vec<u8>,
+}
+
+impl<W: Write> Encoder<W> {
+    fn flush_copy(&mut self) -> io::Result<()> {
+        if let Some((offset, len)) = self.copy.take() {
+            self.writer.write_all(b"C")?;
+            self.writer.write_all(&offset.to_le_bytes())?;
+            self.writer.write_all(&len.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn flush_literal(&mut self) -> io::Result<()> {
+        if !self.literal.is_empty() {
+            self.writer.write_all(b"L")?;
+            self.writer
+                .write_all(&(self.literal.len() as u64).to_le_bytes())?;
+            self.writer.write_all(&self.literal)?;
+            self.literal.clear();
+        }
+        Ok(())
+    }
+
+    fn copy(&mut self, offset: u64, len: u64) -> io::Result<()> {
+        self.flush_literal()?;
+        match self.copy {
+            Some((start, l)) if start + l == offset => self.copy = Some((start, l + len)),
+            _ => {
+                self.flush_copy()?;
+                self.copy = Some((offset, len));
+            }
+        }
+        Ok(())
+    }
+
+    fn literal(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.flush_copy()?;
+        self.literal.extend_from_slice(bytes);
+        if self.literal.len() >= MAX_LITERAL {
+            self.flush_literal()?;
+        }
+        Ok(())
+    }
+}
+
+/// Statistics of an encoded delta.
+#[derive(Serialize, Debug, Default, PartialEq, Eq)]
+pub struct DeltaStats {
+    pub core_size: u64,
+    pub copied: u64,
+    pub literal: u64,
+}
+
+/// Encodes the core read from `reader` as a delta against the core at `base`.
+pub fn encode<R: Read, W: Write>(base: &Path, reader: &mut R, writer: W) -> io::Result<DeltaStats> {
+    let mut base_file = File::open(base)?;
+    let mut index: HashMap<u64, u64> = HashMap::new();
+    let mut buf = vec![0u8; BLOCK];
+    let mut offset = 0u64;
+    loop {
+        let n = read_block(&mut base_file, &mut buf)?;
+        if n < BLOCK {
+            break;
+        }
+        index.entry(block_hash(&buf)).or_insert(offset);
+        offset += BLOCK as u64;
+    }
+
+    let mut encoder = Encoder {
+        writer,
+        copy: None,
+        literal: vec![],
+    };
+    encoder.writer.write_all(MAGIC)?;
+    let mut stats = DeltaStats::default();
+    let mut candidate = vec![0u8; BLOCK];
+    loop {
+        let n = read_block(reader, &mut buf)?;
+        if n == 0 {
+            break;
+        }
+        stats.core_size += n as u64;
+        let block = &buf[..n];
+        let found = if n == BLOCK {
+            match index.get(&block_hash(block)) {
+                Some(at) => {
+                    // Guard against hash collisions by comparing the bytes.
+                    base_file.seek(SeekFrom::Start(*at))?;
+                    base_file.read_exact(&mut candidate)?;
+                    (candidate == block).then_some(*at)
+                }
+                None => None,
+            }
+        } else {
+            None
+        };
+        match found {
+            Some(at) => {
+                stats.copied += n as u64;
+                encoder.copy(at, n as u64)?;
+            }
+            None => {
+                stats.literal += n as u64;
+                encoder.literal(block)?;
+            }
+        }
+        if n < BLOCK {
+            break;
+        }
+    }
+    encoder.flush_copy()?;
+    encoder.flush_literal()?;
+    encoder.writer.write_all(b"E")?;
+    encoder.writer.write_all(&stats.core_size.to_le_bytes())?;
+    encoder.writer.flush()?;
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::delta::{encode, BaseStore, DeltaBase, TeeReader, BLOCK, MAGIC};
+    use std::fs;
+    use std::io::{Read, Write};
+    use std::time::{Duration, SystemTime};
+    use uuid::Uuid;
+
+    fn u64_at(buf: &[u8], at: usize) -> u64 {
+        let mut b = [0u8; 8];
+        b.copy_from_slice(&buf[at..at + 8]);
+        u64::from_le_bytes(b)
+    }
+
+    // Mirrors the decoder in the agent.
+    fn apply(base: &[u8], delta: &[u8]) -> Vec<u8> {
+        assert_eq!(&delta[..8], MAGIC);
+        let mut out = vec![];
+        let mut at = 8;
+        loop {
+            match delta[at] {
+                b'C' => {
+                    let offset = u64_at(delta, at + 1) as usize;
+                    let len = u64_at(delta, at + 9) as usize;
+                    out.extend_from_slice(&base[offset..offset + len]);
+                    at += 17;
+                }
+                b'L' => {
+                    let len = u64_at(delta, at + 1) as usize;
+                    out.extend_from_slice(&delta[at + 9..at + 9 + len]);
+                    at += 9 + len;
+                }
+                b'E' => {
+                    assert_eq!(u64_at(delta, at + 1) as usize, out.len());
+                    return out;
+                }
+                t => panic!("unexpected record {t}"),
+            }
+        }
+    }
+
+    #[test]
+    fn delta_roundtrip_test() {
+        let dir = std::env::temp_dir().join(format!("delta-test-{}", Uuid::new_v4()));
+        let store = BaseStore::new(dir.clone());
+        let base_info = DeltaBase {
+            build_id: "deadbeef".to_string(),
+            uuid: Uuid::new_v4().to_string(),
+            dump_file: "first.core.gz".to_string(),
+        };
+
+        // Pages 0..8 with a distinct pattern each.
+        let base: Vec<u8> = (0..8 * BLOCK).map(|i| (i / BLOCK) as u8).collect();
+        let mut copy = store.create(&base_info).unwrap();
+        let mut tee = TeeReader::new(base.as_slice(), &mut copy);
+        let mut sink = vec![];
+        tee.read_to_end(&mut sink).unwrap();
+        assert_eq!(store.get("deadbeef"), Some(base_info));
+        assert_eq!(store.get("cafebabe"), None);
+
+        // The next core moves two pages, changes one and has a short tail.
+        let mut core = vec![];
+        core.extend_from_slice(&base[2 * BLOCK..4 * BLOCK]);
+        core.extend_from_slice(&base[..2 * BLOCK]);
+        core.extend(vec![0xffu8; BLOCK]);
+        core.extend_from_slice(&base[5 * BLOCK..]);
+        core.extend_from_slice(b"tail");
+
+        let mut delta = vec![];
+        let stats = encode(
+            &store.core_path("deadbeef"),
+            &mut core.as_slice(),
+            &mut delta,
+        )
+        .unwrap();
+        assert_eq!(stats.core_size, core.len() as u64);
+        assert_eq!(stats.copied, 7 * BLOCK as u64);
+        assert_eq!(stats.literal, BLOCK as u64 + 4);
+        assert!(delta.len() < 2 * BLOCK);
+        assert_eq!(apply(&base, &delta), core);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn make_room_test() {
+        let dir = std::env::temp_dir().join(format!("delta-test-{}", Uuid::new_v4()));
+        let store = BaseStore::new(dir.clone());
+        let now = SystemTime::now();
+        for (age, build_id) in [(3, "a1"), (1, "b2"), (2, "c3")] {
+            let base = DeltaBase {
+                build_id: build_id.to_string(),
+                uuid: Uuid::new_v4().to_string(),
+                dump_file: format!("{build_id}.core.gz"),
+            };
+            let mut file = store.create(&base).unwrap();
+            file.write_all(&[0u8; 100]).unwrap();
+            file.set_modified(now - Duration::from_secs(age * 60))
+                .unwrap();
+        }
+        assert_eq!(
+            store.make_room(None, None, 100).unwrap(),
+            Vec::<String>::new()
+        );
+        // Using a base makes it the most recent.
+        assert!(store.get("a1").is_some());
+        assert_eq!(store.make_room(Some(3), None, 100).unwrap(), vec!["c3"]);
+        assert!(store.get("c3").is_none());
+        assert_eq!(store.make_room(None, Some(250), 100).unwrap(), vec!["b2"]);
+        assert_eq!(
+            store.make_room(None, Some(250), 100).unwrap(),
+            Vec::<String>::new()
+        );
+        assert!(store.make_room(None, Some(99), 100).is_err());
+        assert!(store.get("a1").is_some());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,63 @@
+use anyhow::anyhow;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+
+/// Whether an archive entry is compressed with the shared dictionary. The
+/// core has its own compression and dump-info stays plain so the agent and
+/// downstream tooling can read it without the dictionary.
+pub fn is_candidate(name: &str, skip: &[String]) -> bool {
+    !skip.iter().any(|s| s == name) && !name.ends_with(".zst")
+}
+
+/// `data` compressed against `dictionary`, stored as `<name>.zst`.
+pub fn compress(data: &[u8], dictionary: &Path, bin_path: &str) -> Result<Vec<u8>, anyhow::Error> {
+    let mut child = Command::new("zstd")
+        .env("PATH", bin_path)
+        .arg("-q")
+        .arg("-c")
+        .arg("-D")
+        .arg(dictionary)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child
+        .stdin
+        .take()
+        .ok_or_else(|| anyhow!("zstd stdin missing"))?;
+    let output = thread::scope(|s| {
+        s.spawn(move || stdin.write_all(data));
+        child.wait_with_output()
+    })?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "zstd failed with {}: {}",
+            dictionary.display(),
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dictionary::is_candidate;
+
+    #[test]
+    fn candidates_test() {
+        let skip = vec!["a-dump-info.json".to_string(), "a.core.gz".to_string()];
+        let names: Vec<&str> = [
+            "a-dump-info.json",
+            "a.core.gz",
+            "a-pod-info.json",
+            "a-0.log",
+            "a-ps-info.json.zst",
+        ]
+        .into_iter()
+        .filter(|n| is_candidate(n, &skip))
+        .collect();
+        assert_eq!(names, vec!["a-pod-info.json", "a-0.log"]);
+    }
+}
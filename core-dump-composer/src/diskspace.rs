@@ -0,0 +1,110 @@
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_RESERVE_PERCENT: u8 = 10;
+
+/// The filesystem holding a directory the capture writes to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Volume {
+    pub path: PathBuf,
+    pub device: u64,
+    pub total: u64,
+    /// What an unprivileged write can still use.
+    pub available: u64,
+}
+
+impl Volume {
+    pub fn of(path: &Path) -> io::Result<Volume> {
+        let c_path = CString::new(path.as_os_str().as_bytes())?;
+        let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+        if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let block = stat.f_frsize as u64;
+        Ok(Volume {
+            path: path.to_path_buf(),
+            device: std::fs::metadata(path)?.dev(),
+            total: (stat.f_blocks as u64).saturating_mul(block),
+            available: (stat.f_bavail as u64).saturating_mul(block),
+        })
+    }
+
+    /// The space that would be left after writing `needed` bytes, less
+    /// the reserve.
+    fn headroom(&self, needed: u64, reserve_percent: u8) -> i128 {
+        let reserve = self.total as i128 * reserve_percent as i128 / 100;
+        self.available as i128 - needed as i128 - reserve
+    }
+}
+
+/// Checks a core of `needed` bytes leaves `reserve_percent` of each volume
+/// free, so a large core doesn't fill the node's disk under the kubelet.
+/// Volumes on one device are checked once. Returns whether they all passed
+/// and the detail for the decision.
+pub fn check(volumes: &[Volume], needed: u64, reserve_percent: u8) -> (bool, String) {
+    let mut seen = vec![];
+    let mut details = vec![];
+    let mut passed = true;
+    for volume in volumes {
+        if seen.contains(&volume.device) {
+            continue;
+        }
+        seen.push(volume.device);
+        let fits = volume.headroom(needed, reserve_percent) >= 0;
+        passed &= fits;
+        details.push(format!(
+            "{} {} bytes free of {}",
+            volume.path.display(),
+            volume.available,
+            volume.total
+        ));
+    }
+    let detail = format!(
+        "{}, core of {} bytes, DISK_RESERVE_PERCENT {}",
+        details.join(", "),
+        needed,
+        reserve_percent
+    );
+    (passed, detail)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diskspace::{check, Volume};
+    use std::path::PathBuf;
+
+    fn volume(path: &str, device: u64, total: u64, available: u64) -> Volume {
+        Volume {
+            path: PathBuf::from(path),
+            device,
+            total,
+            available,
+        }
+    }
+
+    #[test]
+    fn check_test() {
+        let volumes = [volume("/core", 1, 1000, 300), volume("/work", 1, 1000, 300)];
+        assert_eq!(
+            check(&volumes, 200, 10),
+            (
+                true,
+                "/core 300 bytes free of 1000, core of 200 bytes, DISK_RESERVE_PERCENT 10"
+                    .to_string()
+            )
+        );
+        assert!(!check(&volumes[..1], 201, 10).0);
+        assert!(check(&volumes[..1], 300, 0).0);
+
+        let full = [volumes[0].clone(), volume("/work", 2, 1000, 50)];
+        let (passed, detail) = check(&full, 0, 10);
+        assert!(!passed);
+        assert!(detail.contains("/work 50 bytes free of 1000"));
+
+        let tmp = Volume::of(&std::env::temp_dir()).unwrap();
+        assert!(tmp.total > 0 && tmp.available <= tmp.total);
+    }
+}
@@ -0,0 +1,561 @@
+//! A client of the Docker Engine API on its unix socket, used with
+//! CONTAINER_RUNTIME=docker on nodes whose pods run through dockershim or
+//! cri-dockerd. Those keep a pod as a sandbox container plus its app
+//! containers, all labelled with the pod, so the lookups are label filters
+//! and their answers are turned into the JSON crictl prints like cri.rs.
+
+use hyper::body::HttpBody;
+use hyper::{Body, Request};
+use serde_json::{json, Map, Value};
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::net::UnixStream;
+
+pub const DEFAULT_ENDPOINT: &str = "unix:///var/run/docker.sock";
+const CALL_TIMEOUT: Duration = Duration::from_secs(5);
+
+const POD_NAME: &str = "io.kubernetes.pod.name";
+const POD_NAMESPACE: &str = "io.kubernetes.pod.namespace";
+const POD_UID: &str = "io.kubernetes.pod.uid";
+const SANDBOX_ID: &str = "io.kubernetes.sandbox.id";
+const CONTAINER_NAME: &str = "io.kubernetes.container.name";
+const DOCKER_TYPE: &str = "io.kubernetes.docker.type";
+/// Pod annotations are kept as labels with this prefix.
+const ANNOTATION_PREFIX: &str = "annotation.";
+
+/// The pod's own labels and its annotations, from a container's labels.
+fn labels_and_annotations(labels: &Value) -> (Map<String, Value>, Map<String, Value>) {
+    let mut own = Map::new();
+    let mut annotations = Map::new();
+    for (key, value) in labels.as_object().into_iter().flatten() {
+        match key.strip_prefix(ANNOTATION_PREFIX) {
+            Some(annotation) => {
+                annotations.insert(annotation.to_string(), value.clone());
+            }
+            None => {
+                own.insert(key.clone(), value.clone());
+            }
+        }
+    }
+    (own, annotations)
+}
+
+/// The attempt at the end of a container name such as
+/// `/k8s_POD_mo-0_mo_f00d_1`.
+fn attempt(c: &Value) -> u64 {
+    c["Names"][0]
+        .as_str()
+        .or_else(|| c["Name"].as_str())
+        .and_then(|n| n.rsplit('_').next())
+        .and_then(|a| a.parse().ok())
+        .unwrap_or_default()
+}
+
+fn label<'a>(labels: &'a Value, key: &str) -> &'a str {
+    labels[key].as_str().unwrap_or_default()
+}
+
+/// A sandbox container of `GET /containers/json` as `crictl pods -o json`
+/// lists a pod.
+fn pod_sandbox(c: &Value) -> Value {
+    let (labels, annotations) = labels_and_annotations(&c["Labels"]);
+    let state = if c["State"] == "running" {
+        "SANDBOX_READY"
+    } else {
+        "SANDBOX_NOTREADY"
+    };
+    json!({
+        "id": c["Id"],
+        "metadata": {
+            "name": label(&c["Labels"], POD_NAME),
+            "uid": label(&c["Labels"], POD_UID),
+            "namespace": label(&c["Labels"], POD_NAMESPACE),
+            "attempt": attempt(c),
+        },
+        "state": state,
+        "createdAt": (c["Created"].as_i64().unwrap_or_default() * 1_000_000_000).to_string(),
+        "labels": labels,
+        "annotations": annotations,
+        "runtimeHandler": "docker",
+    })
+}
+
+/// The sandbox of the pod `name`, the running one and then the newest.
+fn select_pod(sandboxes: &Value, name: &str) -> Option<Value> {
+    sandboxes
+        .as_array()?
+        .iter()
+        .map(pod_sandbox)
+        .filter(|p| p["metadata"]["name"] == name)
+        .max_by_key(|p| {
+            let created = p["createdAt"].as_str().and_then(|c| c.parse::<i64>().ok());
+            (p["state"] == "SANDBOX_READY", created)
+        })
+}
+
+fn container_state(status: &str) -> &'static str {
+    match status {
+        "created" => "CONTAINER_CREATED",
+        "running" | "paused" | "restarting" => "CONTAINER_RUNNING",
+        "exited" | "dead" => "CONTAINER_EXITED",
+        _ => "CONTAINER_UNKNOWN",
+    }
+}
+
+/// An app container of `GET /containers/json` as `crictl ps -o json`
+/// lists it.
+fn container(c: &Value) -> Value {
+    let (labels, annotations) = labels_and_annotations(&c["Labels"]);
+    json!({
+        "id": c["Id"],
+        "podSandboxId": label(&c["Labels"], SANDBOX_ID),
+        "metadata": { "name": label(&c["Labels"], CONTAINER_NAME), "attempt": attempt(c) },
+        "image": { "image": c["Image"] },
+        "imageRef": c["ImageID"],
+        "state": container_state(c["State"].as_str().unwrap_or_default()),
+        "createdAt": (c["Created"].as_i64().unwrap_or_default() * 1_000_000_000).to_string(),
+        "labels": labels,
+        "annotations": annotations,
+    })
+}
+
+/// The mounts of `GET /containers/{id}/json` as crictl prints them.
+fn mounts(inspect: &Value) -> Vec<Value> {
+    inspect["Mounts"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|m| {
+            json!({
+                "containerPath": m["Destination"],
+                "hostPath": m["Source"],
+                "readonly": !m["RW"].as_bool().unwrap_or(true),
+            })
+        })
+        .collect()
+}
+
+/// `GET /containers/{id}/json` of a sandbox as `crictl inspectp` prints
+/// it. `mounts` are those of the pod's containers, the sandbox itself has
+/// none of the pod's volumes.
+fn pod_sandbox_status(inspect: &Value, mounts: Vec<Value>) -> Value {
+    let labels = &inspect["Config"]["Labels"];
+    let (own, annotations) = labels_and_annotations(labels);
+    let host_network = inspect["HostConfig"]["NetworkMode"] == "host";
+    let state = if inspect["State"]["Running"].as_bool().unwrap_or(false) {
+        "SANDBOX_READY"
+    } else {
+        "SANDBOX_NOTREADY"
+    };
+    json!({
+        "status": {
+            "id": inspect["Id"],
+            "metadata": {
+                "name": label(labels, POD_NAME),
+                "uid": label(labels, POD_UID),
+                "namespace": label(labels, POD_NAMESPACE),
+                "attempt": attempt(inspect),
+            },
+            "state": state,
+            "createdAt": inspect["Created"],
+            "network": { "ip": inspect["NetworkSettings"]["IPAddress"], "additionalIps": [] },
+            "linux": {
+                "namespaces": {
+                    "options": { "network": if host_network { "NODE" } else { "POD" } }
+                }
+            },
+            "labels": own,
+            "annotations": annotations,
+            "runtimeHandler": "docker",
+        },
+        "info": { "pid": inspect["State"]["Pid"], "mounts": mounts },
+    })
+}
+
+/// `GET /containers/{id}/json` as `crictl inspect` prints it.
+fn container_status(inspect: &Value) -> Value {
+    let labels = &inspect["Config"]["Labels"];
+    let (own, annotations) = labels_and_annotations(labels);
+    let state = &inspect["State"];
+    let host = &inspect["HostConfig"];
+    let exit_code = state["ExitCode"].as_i64().unwrap_or_default();
+    let status = state["Status"].as_str().unwrap_or_default();
+    // As dockershim reported them.
+    let reason = match (state["OOMKilled"].as_bool(), status, exit_code) {
+        (Some(true), _, _) => "OOMKilled",
+        (_, "exited", 0) => "Completed",
+        (_, "exited", _) => "Error",
+        _ => "",
+    };
+    json!({
+        "status": {
+            "id": inspect["Id"],
+            "metadata": { "name": label(labels, CONTAINER_NAME), "attempt": attempt(inspect) },
+            "state": container_state(status),
+            "createdAt": inspect["Created"],
+            "startedAt": state["StartedAt"],
+            "finishedAt": state["FinishedAt"],
+            "exitCode": exit_code,
+            "image": { "image": inspect["Config"]["Image"] },
+            "imageRef": inspect["Image"],
+            "reason": reason,
+            "message": state["Error"],
+            "labels": own,
+            "annotations": annotations,
+            "mounts": mounts(inspect),
+            "logPath": inspect["LogPath"],
+            "resources": { "linux": {
+                "cpuPeriod": host["CpuPeriod"],
+                "cpuQuota": host["CpuQuota"],
+                "cpuShares": host["CpuShares"],
+                "memoryLimitInBytes": host["Memory"],
+            } },
+        },
+        "info": { "pid": state["Pid"] },
+    })
+}
+
+/// `GET /images/{name}/json` as `crictl img -o json` lists it.
+fn image(inspect: &Value) -> Value {
+    json!({
+        "id": inspect["Id"],
+        "repoTags": inspect["RepoTags"],
+        "repoDigests": inspect["RepoDigests"],
+        "size": inspect["Size"].as_u64().unwrap_or_default().to_string(),
+    })
+}
+
+/// `GET /version` as `crictl version -o json` prints it.
+fn version(version: &Value) -> Value {
+    json!({
+        "version": version["ApiVersion"],
+        "runtimeName": "docker",
+        "runtimeVersion": version["Version"],
+        "runtimeApiVersion": version["ApiVersion"],
+    })
+}
+
+/// The output of `GET /containers/{id}/logs`. Without a TTY stdout and
+/// stderr come in frames behind an 8 byte header, which are joined again.
+fn demux_log(raw: &[u8]) -> Vec<u8> {
+    let framed = |buf: &[u8]| buf.len() >= 8 && buf[0] <= 2 && buf[1..4] == [0, 0, 0];
+    if !framed(raw) {
+        return raw.to_vec();
+    }
+    let mut out = vec![];
+    let mut at = 0;
+    while framed(&raw[at..]) {
+        let len = u32::from_be_bytes([raw[at + 4], raw[at + 5], raw[at + 6], raw[at + 7]]) as usize;
+        let end = (at + 8 + len).min(raw.len());
+        out.extend_from_slice(&raw[at + 8..end]);
+        at = end;
+    }
+    out
+}
+
+/// The `filters` of a list, URL encoded.
+fn query(filters: &Value) -> String {
+    filters
+        .to_string()
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            b => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+pub struct DockerClient {
+    socket: PathBuf,
+    runtime: tokio::runtime::Runtime,
+}
+
+impl DockerClient {
+    /// For an endpoint such as `unix:///var/run/docker.sock`.
+    pub fn new(endpoint: &str) -> Result<DockerClient, anyhow::Error> {
+        let socket = endpoint.strip_prefix("unix://").unwrap_or(endpoint);
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        Ok(DockerClient {
+            socket: PathBuf::from(socket),
+            runtime,
+        })
+    }
+
+    /// GETs `path`, the body of a success or the engine's message.
+    fn get(&self, path: &str) -> Result<Vec<u8>, String> {
+        let call = async {
+            let stream = UnixStream::connect(&self.socket)
+                .await
+                .map_err(|e| e.to_string())?;
+            let (mut sender, connection) = hyper::client::conn::Builder::new()
+                .handshake::<_, Body>(stream)
+                .await
+                .map_err(|e| e.to_string())?;
+            tokio::spawn(connection);
+            let request = Request::get(path)
+                .header("host", "docker")
+                .body(Body::empty())
+                .map_err(|e| e.to_string())?;
+            let response = sender
+                .send_request(request)
+                .await
+                .map_err(|e| e.to_string())?;
+            let status = response.status();
+            let mut body = response.into_body();
+            let mut buf = vec![];
+            while let Some(chunk) = body.data().await {
+                buf.extend_from_slice(&chunk.map_err(|e| e.to_string())?);
+            }
+            if !status.is_success() {
+                let message = serde_json::from_slice::<Value>(&buf)
+                    .ok()
+                    .and_then(|m| m["message"].as_str().map(str::to_string))
+                    .unwrap_or_else(|| String::from_utf8_lossy(&buf).to_string());
+                return Err(format!("answered {}: {}", status.as_u16(), message.trim()));
+            }
+            Ok(buf)
+        };
+        self.runtime
+            .block_on(async { tokio::time::timeout(CALL_TIMEOUT, call).await })
+            .map_err(|_| format!("timed out after {}s", CALL_TIMEOUT.as_secs()))?
+            .map_err(|e| format!("GET {path} on {} {e}", self.socket.display()))
+    }
+
+    fn get_json(&self, path: &str) -> Result<Value, String> {
+        let body = self.get(path)?;
+        serde_json::from_slice(&body).map_err(|e| format!("GET {path} answered garbage: {e}"))
+    }
+
+    fn list(&self, filters: Value) -> Result<Value, String> {
+        self.get_json(&format!(
+            "/containers/json?all=1&filters={}",
+            query(&filters)
+        ))
+    }
+
+    /// The sandbox of the pod `name`, matched exactly.
+    pub fn pod(&self, name: &str) -> Result<Value, String> {
+        let sandboxes = self.list(json!({
+            "label": [format!("{DOCKER_TYPE}=podsandbox"), format!("{POD_NAME}={name}")]
+        }))?;
+        select_pod(&sandboxes, name).ok_or_else(|| format!("no pod sandbox named {name}"))
+    }
+
+    /// The sandbox the container `container_id` runs in.
+    pub fn container_pod(&self, container_id: &str) -> Result<Value, String> {
+        let inspect = self.get_json(&format!("/containers/{container_id}/json"))?;
+        let pod_id = label(&inspect["Config"]["Labels"], SANDBOX_ID);
+        if pod_id.is_empty() {
+            return Err(format!("container {container_id} is not in a pod"));
+        }
+        let sandboxes = self.list(json!({ "id": [pod_id] }))?;
+        sandboxes
+            .as_array()
+            .and_then(|s| s.first())
+            .map(pod_sandbox)
+            .ok_or_else(|| format!("no pod sandbox {pod_id}"))
+    }
+
+    pub fn inspect_pod(&self, pod_id: &str) -> Result<Value, String> {
+        let inspect = self.get_json(&format!("/containers/{pod_id}/json"))?;
+        let mut found = vec![];
+        let containers = self.list(json!({ "label": [format!("{SANDBOX_ID}={pod_id}")] }))?;
+        for c in containers.as_array().into_iter().flatten() {
+            if let Some(id) = c["Id"].as_str().filter(|id| *id != pod_id) {
+                found.extend(mounts(&self.get_json(&format!("/containers/{id}/json"))?));
+            }
+        }
+        Ok(pod_sandbox_status(&inspect, found))
+    }
+
+    /// The app containers of the pod, in any state when `all`.
+    pub fn pod_containers(&self, pod_id: &str, all: bool) -> Result<Value, String> {
+        let listed = self.list(json!({
+            "label": [format!("{DOCKER_TYPE}=container"), format!("{SANDBOX_ID}={pod_id}")]
+        }))?;
+        let containers: Vec<Value> = listed
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(container)
+            .filter(|c| all || c["state"] == "CONTAINER_RUNNING")
+            .collect();
+        Ok(json!({ "containers": containers }))
+    }
+
+    pub fn inspect_container(&self, container_id: &str) -> Result<Value, String> {
+        Ok(container_status(
+            &self.get_json(&format!("/containers/{container_id}/json"))?,
+        ))
+    }
+
+    pub fn image(&self, image_ref: &str) -> Result<Value, String> {
+        Ok(image(&self.get_json(&format!("/images/{image_ref}/json"))?))
+    }
+
+    pub fn version(&self) -> Result<Value, String> {
+        Ok(version(&self.get_json("/version")?))
+    }
+
+    /// The last `count` lines of the container's output.
+    pub fn logs(&self, container_id: &str, count: u32) -> Result<Vec<u8>, anyhow::Error> {
+        let raw = self
+            .get(&format!(
+                "/containers/{container_id}/logs?stdout=1&stderr=1&tail={count}"
+            ))
+            .map_err(anyhow::Error::msg)?;
+        Ok(demux_log(&raw))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::docker::{
+        container, container_status, demux_log, image, pod_sandbox_status, query, select_pod,
+        version,
+    };
+    use serde_json::json;
+
+    fn sandbox(id: &str, name: &str, state: &str, created: i64) -> serde_json::Value {
+        json!({
+            "Id": id,
+            "Names": [format!("/k8s_POD_{name}_mo_f00d_1")],
+            "State": state,
+            "Created": created,
+            "Labels": {
+                "io.kubernetes.docker.type": "podsandbox",
+                "io.kubernetes.pod.name": name,
+                "io.kubernetes.pod.namespace": "mo",
+                "io.kubernetes.pod.uid": "f00d",
+                "app": "mo",
+                "annotation.coredump.matrixorigin.io/enabled": "true",
+            },
+        })
+    }
+
+    #[test]
+    fn pods_test() {
+        let listed = json!([
+            sandbox("old", "mo-0", "exited", 200),
+            sandbox("new", "mo-0", "running", 100),
+            sandbox("other", "mo-01", "running", 300),
+        ]);
+        let pod = select_pod(&listed, "mo-0").unwrap();
+        assert_eq!(pod["id"], "new");
+        assert_eq!(pod["state"], "SANDBOX_READY");
+        assert_eq!(pod["createdAt"], "100000000000");
+        assert_eq!(pod["metadata"]["namespace"], "mo");
+        assert_eq!(pod["metadata"]["attempt"], 1);
+        assert_eq!(pod["labels"]["app"], "mo");
+        assert_eq!(
+            pod["annotations"]["coredump.matrixorigin.io/enabled"],
+            "true"
+        );
+        assert!(pod["labels"]["annotation.coredump.matrixorigin.io/enabled"].is_null());
+        assert!(select_pod(&listed, "mo").is_none());
+
+        let inspect = json!({
+            "Id": "new",
+            "Name": "/k8s_POD_mo-0_mo_f00d_1",
+            "Created": "2024-01-26T10:00:00.000000000Z",
+            "State": {"Running": true, "Pid": 42},
+            "Config": {"Labels": sandbox("new", "mo-0", "running", 0)["Labels"]},
+            "HostConfig": {"NetworkMode": "host"},
+            "NetworkSettings": {"IPAddress": ""},
+        });
+        let mounts = vec![
+            json!({"containerPath": "/data", "hostPath": "/var/lib/kubelet/pods/f00d/volumes/kubernetes.io~empty-dir/data", "readonly": false}),
+        ];
+        let inspectp = pod_sandbox_status(&inspect, mounts);
+        assert_eq!(
+            inspectp["status"]["linux"]["namespaces"]["options"]["network"],
+            "NODE"
+        );
+        assert_eq!(inspectp["info"]["pid"], 42);
+        assert_eq!(crate::volumes::from_inspect(&inspectp).len(), 1);
+    }
+
+    #[test]
+    fn containers_test() {
+        let listed = json!({
+            "Id": "c1",
+            "Names": ["/k8s_main_mo-0_mo_f00d_0"],
+            "Image": "docker.io/mo/mo:1",
+            "ImageID": "sha256:3b8a",
+            "State": "running",
+            "Created": 1706263200,
+            "Labels": {
+                "io.kubernetes.container.name": "main",
+                "io.kubernetes.sandbox.id": "new",
+                "annotation.io.kubernetes.container.restartCount": "0",
+            },
+        });
+        let c = container(&listed);
+        assert_eq!(c["podSandboxId"], "new");
+        assert_eq!(c["metadata"]["name"], "main");
+        assert_eq!(c["imageRef"], "sha256:3b8a");
+        assert_eq!(c["state"], "CONTAINER_RUNNING");
+
+        let inspect = json!({
+            "Id": "c0",
+            "Name": "/k8s_main_mo-0_mo_f00d_0",
+            "Created": "2024-02-29T12:00:00.000000000Z",
+            "Image": "sha256:3b8a",
+            "LogPath": "/var/lib/docker/containers/c0/c0-json.log",
+            "State": {
+                "Status": "exited",
+                "OOMKilled": true,
+                "ExitCode": 137,
+                "StartedAt": "2024-02-29T12:00:01.000000000Z",
+                "FinishedAt": "2024-02-29T12:28:05.000000000Z",
+                "Pid": 0,
+            },
+            "Config": {"Image": "docker.io/mo/mo:1", "Labels": listed["Labels"]},
+            "HostConfig": {"CpuPeriod": 100000, "CpuQuota": 50000, "CpuShares": 256, "Memory": 536870912},
+            "Mounts": [{"Source": "/var/lib/kubelet/pods/f00d/volumes/kubernetes.io~configmap/conf", "Destination": "/etc/mo", "RW": false}],
+        });
+        let status = container_status(&inspect);
+        assert_eq!(status["status"]["reason"], "OOMKilled");
+        assert_eq!(status["status"]["state"], "CONTAINER_EXITED");
+        assert_eq!(
+            status["status"]["finishedAt"],
+            "2024-02-29T12:28:05.000000000Z"
+        );
+        assert_eq!(status["status"]["mounts"][0]["readonly"], true);
+        assert_eq!(
+            status["status"]["resources"]["linux"]["memoryLimitInBytes"],
+            536870912
+        );
+
+        let img = image(&json!({
+            "Id": "sha256:3b8a",
+            "RepoTags": ["docker.io/mo/mo:1"],
+            "RepoDigests": ["docker.io/mo/mo@sha256:9f1c"],
+            "Size": 1024,
+        }));
+        assert_eq!(img["size"], "1024");
+        assert_eq!(img["repoDigests"][0], "docker.io/mo/mo@sha256:9f1c");
+
+        let v = version(&json!({"Version": "24.0.7", "ApiVersion": "1.43"}));
+        assert_eq!(v["runtimeName"], "docker");
+        assert_eq!(v["runtimeVersion"], "24.0.7");
+    }
+
+    #[test]
+    fn logs_test() {
+        let mut raw = vec![1, 0, 0, 0, 0, 0, 0, 8];
+        raw.extend(b"started\n");
+        raw.extend([2, 0, 0, 0, 0, 0, 0, 7]);
+        raw.extend(b"panic!\n");
+        assert_eq!(demux_log(&raw), b"started\npanic!\n".to_vec());
+        assert_eq!(demux_log(b"tty output\n"), b"tty output\n".to_vec());
+        assert_eq!(
+            query(&json!({"label": ["io.kubernetes.pod.name=mo-0"]})),
+            "%7B%22label%22%3A%5B%22io.kubernetes.pod.name%3Dmo-0%22%5D%7D"
+        );
+    }
+}
@@ -0,0 +1,186 @@
+use std::fs::File;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+pub const PT_LOAD: u32 = 1;
+pub const PT_NOTE: u32 = 4;
+pub const PF_X: u32 = 1;
+pub const NT_FILE: u32 = 0x4649_4c45;
+const NT_GNU_BUILD_ID: u32 = 3;
+
+/// The parts of a 64 bit little endian ELF program header we use.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgramHeader {
+    pub p_type: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub vaddr: u64,
+    pub filesz: u64,
+    pub memsz: u64,
+}
+
+pub fn u16_at(buf: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([buf[at], buf[at + 1]])
+}
+
+pub fn u32_at(buf: &[u8], at: usize) -> u32 {
+    let mut b = [0u8; 4];
+    b.copy_from_slice(&buf[at..at + 4]);
+    u32::from_le_bytes(b)
+}
+
+pub fn u64_at(buf: &[u8], at: usize) -> u64 {
+    let mut b = [0u8; 8];
+    b.copy_from_slice(&buf[at..at + 8]);
+    u64::from_le_bytes(b)
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// Parses the program headers from the start of an ELF file. `header` must
+/// hold at least the ELF header and the program header table.
+pub fn program_headers(header: &[u8]) -> io::Result<Vec<ProgramHeader>> {
+    if header.len() < 64 || &header[0..4] != b"\x7fELF" {
+        return Err(invalid("not an ELF file"));
+    }
+    if header[4] != 2 || header[5] != 1 {
+        return Err(invalid("only 64 bit little endian ELF is supported"));
+    }
+    let phoff = u64_at(header, 32) as usize;
+    let phentsize = u16_at(header, 54) as usize;
+    let phnum = u16_at(header, 56) as usize;
+    if phentsize < 56 || phoff + phentsize * phnum > header.len() {
+        return Err(invalid("program header table out of range"));
+    }
+    Ok((0..phnum)
+        .map(|i| {
+            let at = phoff + i * phentsize;
+            ProgramHeader {
+                p_type: u32_at(header, at),
+                flags: u32_at(header, at + 4),
+                offset: u64_at(header, at + 8),
+                vaddr: u64_at(header, at + 16),
+                filesz: u64_at(header, at + 32),
+                memsz: u64_at(header, at + 40),
+            }
+        })
+        .collect())
+}
+
+/// Walks the entries of a note segment calling `f(type, name, desc)`.
+pub fn for_each_note<F: FnMut(u32, &[u8], &[u8])>(notes: &[u8], mut f: F) {
+    let align = |v: usize| (v + 3) & !3;
+    let mut at = 0;
+    while at + 12 <= notes.len() {
+        let namesz = u32_at(notes, at) as usize;
+        let descsz = u32_at(notes, at + 4) as usize;
+        let n_type = u32_at(notes, at + 8);
+        let name_at = at + 12;
+        let desc_at = name_at + align(namesz);
+        let end = desc_at + align(descsz);
+        if desc_at + descsz > notes.len() {
+            break;
+        }
+        f(
+            n_type,
+            &notes[name_at..name_at + namesz],
+            &notes[desc_at..desc_at + descsz],
+        );
+        at = end;
+    }
+}
+
+/// Reads the GNU build-id of an executable, e.g. `/proc/<pid>/exe`.
+pub fn read_build_id(path: &Path) -> io::Result<Option<String>> {
+    let mut file = File::open(path)?;
+    let mut header = vec![0u8; 64];
+    file.read_exact(&mut header)?;
+    if &header[0..4] != b"\x7fELF" {
+        return Err(invalid("not an ELF file"));
+    }
+    let phoff = u64_at(&header, 32);
+    let table_len = u16_at(&header, 54) as u64 * u16_at(&header, 56) as u64;
+    if phoff + table_len > 1 << 20 {
+        return Err(invalid("program header table out of range"));
+    }
+    header.resize((phoff + table_len) as usize, 0);
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut header)?;
+
+    for ph in program_headers(&header)? {
+        if ph.p_type != PT_NOTE || ph.filesz > 1 << 20 {
+            continue;
+        }
+        let mut notes = vec![0u8; ph.filesz as usize];
+        file.seek(SeekFrom::Start(ph.offset))?;
+        file.read_exact(&mut notes)?;
+        let mut build_id = None;
+        for_each_note(&notes, |n_type, name, desc| {
+            if n_type == NT_GNU_BUILD_ID && name.starts_with(b"GNU") {
+                build_id = Some(desc.iter().map(|b| format!("{b:02x}")).collect());
+            }
+        });
+        if build_id.is_some() {
+            return Ok(build_id);
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::elf::{program_headers, read_build_id, PT_NOTE};
+    use std::fs;
+    use std::path::Path;
+    use uuid::Uuid;
+
+    /// Builds a minimal ELF64 file with the given program headers
+    /// `(type, flags, vaddr, filesz, memsz)` followed by `data`.
+    pub fn build_elf(headers: &[(u32, u32, u64, u64, u64)], data: &[u8]) -> Vec<u8> {
+        let mut elf = vec![0u8; 64];
+        elf[0..4].copy_from_slice(b"\x7fELF");
+        elf[4] = 2;
+        elf[5] = 1;
+        elf[32..40].copy_from_slice(&64u64.to_le_bytes());
+        elf[54..56].copy_from_slice(&56u16.to_le_bytes());
+        elf[56..58].copy_from_slice(&(headers.len() as u16).to_le_bytes());
+        let data_at = 64 + 56 * headers.len() as u64;
+        for (p_type, flags, vaddr, filesz, memsz) in headers {
+            let mut ph = vec![0u8; 56];
+            ph[0..4].copy_from_slice(&p_type.to_le_bytes());
+            ph[4..8].copy_from_slice(&flags.to_le_bytes());
+            ph[8..16].copy_from_slice(&data_at.to_le_bytes());
+            ph[16..24].copy_from_slice(&vaddr.to_le_bytes());
+            ph[32..40].copy_from_slice(&filesz.to_le_bytes());
+            ph[40..48].copy_from_slice(&memsz.to_le_bytes());
+            elf.extend(ph);
+        }
+        elf.extend_from_slice(data);
+        elf
+    }
+
+    #[test]
+    fn build_id_test() {
+        let mut note = vec![];
+        note.extend(4u32.to_le_bytes());
+        note.extend(4u32.to_le_bytes());
+        note.extend(3u32.to_le_bytes());
+        note.extend(b"GNU\0");
+        note.extend([0xde, 0xad, 0xbe, 0xef]);
+        let elf = build_elf(&[(PT_NOTE, 4, 0, note.len() as u64, 0)], &note);
+
+        let headers = program_headers(&elf).unwrap();
+        assert_eq!(headers.len(), 1);
+        assert_eq!(headers[0].offset, 120);
+
+        let path = std::env::temp_dir().join(format!("elf-test-{}", Uuid::new_v4()));
+        fs::write(&path, &elf).unwrap();
+        assert_eq!(read_build_id(&path).unwrap().as_deref(), Some("deadbeef"));
+        fs::remove_file(&path).unwrap();
+
+        assert!(read_build_id(Path::new("/proc/self/cmdline")).is_err());
+    }
+}
@@ -0,0 +1,270 @@
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::Serialize;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use uuid::Uuid;
+
+/// The plaintext sealed at a time in a spool, its tag follows it.
+const SEAL_CHUNK: usize = 64 * 1024;
+
+/// The tool ENCRYPT_RECIPIENTS encrypts archives with, found in the
+/// handler's PATH.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Tool {
+    Age,
+    Gpg,
+}
+
+/// The public keys archives are encrypted to. Any one of the private keys
+/// decrypts them.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Recipients {
+    pub tool: Tool,
+    pub recipients: Vec<String>,
+}
+
+impl Recipients {
+    /// Parses the comma separated ENCRYPT_RECIPIENTS. age and ssh public
+    /// keys are encrypted to with age, anything else is taken as a GPG key
+    /// id, fingerprint or email. A mix of both goes to gpg, which fails the
+    /// capture rather than writing it unencrypted.
+    pub fn parse(value: &str) -> Option<Recipients> {
+        let recipients: Vec<String> = value
+            .split(',')
+            .map(|r| r.trim().to_string())
+            .filter(|r| !r.is_empty())
+            .collect();
+        if recipients.is_empty() {
+            return None;
+        }
+        let age = recipients
+            .iter()
+            .all(|r| r.starts_with("age1") || r.starts_with("ssh-"));
+        Some(Recipients {
+            tool: if age { Tool::Age } else { Tool::Gpg },
+            recipients,
+        })
+    }
+
+    /// Appended to the archive name, `.tar.age` or `.tar.gpg`.
+    pub fn suffix(&self) -> &'static str {
+        match self.tool {
+            Tool::Age => ".age",
+            Tool::Gpg => ".gpg",
+        }
+    }
+
+    /// The command encrypting its stdin to its stdout.
+    pub fn command(&self) -> Vec<String> {
+        let mut command = match self.tool {
+            Tool::Age => vec!["age".to_string()],
+            Tool::Gpg => vec![
+                "gpg".to_string(),
+                "--batch".to_string(),
+                "--no-tty".to_string(),
+                "--trust-model".to_string(),
+                "always".to_string(),
+                "--encrypt".to_string(),
+            ],
+        };
+        for recipient in &self.recipients {
+            command.push("-r".to_string());
+            command.push(recipient.clone());
+        }
+        command
+    }
+}
+
+fn nonce(chunk: u64) -> Nonce {
+    let mut nonce = [0u8; 12];
+    nonce[4..].copy_from_slice(&chunk.to_be_bytes());
+    Nonce::assume_unique_for_key(nonce)
+}
+
+/// An entry of unknown size on its way into an encrypted archive, spooled
+/// so its header can be written before it. It is sealed with a key only
+/// this process holds and the file is unlinked as soon as it is created,
+/// so no plaintext reaches the disk and nothing outlives the capture.
+pub struct SealedSpool {
+    file: File,
+    key: LessSafeKey,
+    chunk: Vec<u8>,
+    sealed: u64,
+    /// The plaintext written so far.
+    pub len: u64,
+}
+
+impl SealedSpool {
+    pub fn create(dir: &Path) -> io::Result<SealedSpool> {
+        let path = dir.join(format!("sealed-{}", Uuid::new_v4()));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)?;
+        fs::remove_file(&path)?;
+        let mut key = [0u8; 32];
+        SystemRandom::new()
+            .fill(&mut key)
+            .map_err(|_| io::Error::other("no random key for the spool"))?;
+        let key = UnboundKey::new(&CHACHA20_POLY1305, &key)
+            .map_err(|_| io::Error::other("no key for the spool"))?;
+        Ok(SealedSpool {
+            file,
+            key: LessSafeKey::new(key),
+            chunk: Vec::with_capacity(SEAL_CHUNK),
+            sealed: 0,
+            len: 0,
+        })
+    }
+
+    fn seal(&mut self) -> io::Result<()> {
+        let tag = self
+            .key
+            .seal_in_place_separate_tag(nonce(self.sealed), Aad::empty(), &mut self.chunk)
+            .map_err(|_| io::Error::other("sealing the spool failed"))?;
+        self.file.write_all(&self.chunk)?;
+        self.file.write_all(tag.as_ref())?;
+        self.sealed += 1;
+        self.chunk.clear();
+        Ok(())
+    }
+
+    /// Seals the rest and reads the plaintext back from the start.
+    pub fn into_reader(mut self) -> io::Result<Unsealed> {
+        if !self.chunk.is_empty() {
+            self.seal()?;
+        }
+        self.file.seek(SeekFrom::Start(0))?;
+        Ok(Unsealed {
+            file: self.file,
+            key: self.key,
+            opened: 0,
+            chunk: vec![],
+            pos: 0,
+        })
+    }
+}
+
+impl Write for SealedSpool {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(SEAL_CHUNK - self.chunk.len());
+        self.chunk.extend_from_slice(&buf[..n]);
+        self.len += n as u64;
+        if self.chunk.len() == SEAL_CHUNK {
+            self.seal()?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The plaintext of a SealedSpool.
+pub struct Unsealed {
+    file: File,
+    key: LessSafeKey,
+    opened: u64,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for Unsealed {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.chunk.len() {
+            self.chunk.clear();
+            self.pos = 0;
+            let sealed = (SEAL_CHUNK + CHACHA20_POLY1305.tag_len()) as u64;
+            if (&mut self.file).take(sealed).read_to_end(&mut self.chunk)? == 0 {
+                return Ok(0);
+            }
+            let plain = self
+                .key
+                .open_in_place(nonce(self.opened), Aad::empty(), &mut self.chunk)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "the spool was altered"))?
+                .len();
+            self.chunk.truncate(plain);
+            self.opened += 1;
+        }
+        let n = buf.len().min(self.chunk.len() - self.pos);
+        buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::encrypt::{Recipients, SealedSpool, Tool, SEAL_CHUNK};
+    use std::fs;
+    use std::io::{Read, Write};
+    use uuid::Uuid;
+
+    #[test]
+    fn sealed_spool_test() {
+        let dir = std::env::temp_dir().join(format!("sealed-test-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let data: Vec<u8> = (0..SEAL_CHUNK * 2 + 100).map(|i| (i % 251) as u8).collect();
+        let mut spool = SealedSpool::create(&dir).unwrap();
+        spool.write_all(&data).unwrap();
+        assert_eq!(spool.len, data.len() as u64);
+        // Unlinked as soon as it is created.
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        let mut read = vec![];
+        spool.into_reader().unwrap().read_to_end(&mut read).unwrap();
+        assert_eq!(read, data);
+
+        let mut read = vec![];
+        let spool = SealedSpool::create(&dir).unwrap();
+        spool.into_reader().unwrap().read_to_end(&mut read).unwrap();
+        assert!(read.is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn parse_test() {
+        assert_eq!(Recipients::parse(""), None);
+        assert_eq!(Recipients::parse(" , "), None);
+
+        let age = Recipients::parse(
+            "age1ql3z7hjy54pw3hyww5ayyfg7zqgvc7w3j2elw8zmrj2kg5sfn9aqmcac8p, ssh-ed25519 AAAA",
+        )
+        .unwrap();
+        assert_eq!(age.tool, Tool::Age);
+        assert_eq!(age.suffix(), ".age");
+        assert_eq!(
+            age.command(),
+            vec![
+                "age",
+                "-r",
+                "age1ql3z7hjy54pw3hyww5ayyfg7zqgvc7w3j2elw8zmrj2kg5sfn9aqmcac8p",
+                "-r",
+                "ssh-ed25519 AAAA"
+            ]
+        );
+
+        let gpg = Recipients::parse("ops@matrixorigin.io").unwrap();
+        assert_eq!(gpg.tool, Tool::Gpg);
+        assert_eq!(gpg.suffix(), ".gpg");
+        assert_eq!(gpg.command()[0], "gpg");
+        assert_eq!(
+            &gpg.command()[gpg.command().len() - 2..],
+            ["-r", "ops@matrixorigin.io"]
+        );
+        assert_eq!(
+            Recipients::parse("age1abc,ops@matrixorigin.io")
+                .unwrap()
+                .tool,
+            Tool::Gpg
+        );
+    }
+}
@@ -0,0 +1,121 @@
+use serde::Serialize;
+use serde_json::json;
+use std::str::FromStr;
+
+pub const DEFAULT_MASK_PATTERNS: &str = "PASSWORD,TOKEN,KEY,SECRET";
+const MASK: &str = "********";
+
+/// How much of `/proc/<pid>/environ` goes into the archive, from
+/// CAPTURE_ENV.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CaptureEnv {
+    Off,
+    /// Names are kept, the values of variables matching ENV_MASK_PATTERNS
+    /// are replaced.
+    Masked,
+    Full,
+}
+
+impl FromStr for CaptureEnv {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "" | "off" | "false" => Ok(CaptureEnv::Off),
+            "masked" => Ok(CaptureEnv::Masked),
+            "full" => Ok(CaptureEnv::Full),
+            other => Err(anyhow::anyhow!("Unknown CAPTURE_ENV {other}")),
+        }
+    }
+}
+
+/// Splits the comma separated ENV_MASK_PATTERNS list.
+pub fn mask_patterns(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(|p| p.trim().to_uppercase())
+        .filter(|p| !p.is_empty())
+        .collect()
+}
+
+/// The NUL separated `NAME=value` entries of an environ file. Values that
+/// aren't UTF-8 are converted lossily.
+pub fn parse(environ: &[u8]) -> Vec<(String, String)> {
+    environ
+        .split(|b| *b == 0)
+        .filter(|e| !e.is_empty())
+        .map(|e| {
+            let e = String::from_utf8_lossy(e);
+            match e.split_once('=') {
+                Some((name, value)) => (name.to_string(), value.to_string()),
+                None => (e.to_string(), String::new()),
+            }
+        })
+        .collect()
+}
+
+/// A name matches when it contains one of the patterns, ignoring case.
+pub fn is_masked(name: &str, patterns: &[String]) -> bool {
+    let name = name.to_uppercase();
+    patterns.iter().any(|p| name.contains(p.as_str()))
+}
+
+/// The `-environ.json` content, None when CAPTURE_ENV is off.
+pub fn render(environ: &[u8], mode: CaptureEnv, patterns: &[String]) -> Option<String> {
+    let variables: Vec<_> = match mode {
+        CaptureEnv::Off => return None,
+        CaptureEnv::Full => parse(environ),
+        CaptureEnv::Masked => parse(environ)
+            .into_iter()
+            .map(|(name, value)| {
+                if is_masked(&name, patterns) {
+                    (name, MASK.to_string())
+                } else {
+                    (name, value)
+                }
+            })
+            .collect(),
+    };
+    let variables: Vec<_> = variables
+        .into_iter()
+        .map(|(name, value)| json!({ "name": name, "value": value }))
+        .collect();
+    Some(json!({ "mode": mode, "variables": variables }).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::environ::{mask_patterns, render, CaptureEnv};
+    use crate::redact::{Redactor, DEFAULT_REDACT_PATTERNS};
+
+    #[test]
+    fn masked_test() {
+        let environ = b"PATH=/usr/bin\0DB_PASSWORD=hunter2\0api_token=abc=d\0HOME=/root\0";
+        let patterns = mask_patterns(" password, TOKEN,,key");
+        let json: serde_json::Value =
+            serde_json::from_str(&render(environ, CaptureEnv::Masked, &patterns).unwrap()).unwrap();
+        assert_eq!(json["mode"], "masked");
+        let variables = json["variables"].as_array().unwrap();
+        assert_eq!(variables.len(), 4);
+        assert_eq!(variables[0]["value"], "/usr/bin");
+        assert_eq!(variables[1]["name"], "DB_PASSWORD");
+        assert_eq!(variables[1]["value"], "********");
+        assert_eq!(variables[2]["name"], "api_token");
+        assert_eq!(variables[2]["value"], "********");
+
+        let json: serde_json::Value =
+            serde_json::from_str(&render(environ, CaptureEnv::Full, &patterns).unwrap()).unwrap();
+        assert_eq!(json["variables"][2]["value"], "abc=d");
+        // What add_file makes of it with the default REDACT_PATTERNS.
+        let full = render(environ, CaptureEnv::Full, &[]).unwrap();
+        let redactor = Redactor::new(DEFAULT_REDACT_PATTERNS);
+        let json: serde_json::Value =
+            serde_json::from_slice(&redactor.redact_json(full.as_bytes()).unwrap()).unwrap();
+        assert_eq!(json["variables"][1]["value"], "********");
+        assert_eq!(json["variables"][2]["value"], "********");
+        assert_eq!(json["variables"][3]["value"], "/root");
+        assert_eq!(render(environ, CaptureEnv::Off, &patterns), None);
+        assert_eq!("Masked".parse::<CaptureEnv>().unwrap(), CaptureEnv::Masked);
+        assert!("some".parse::<CaptureEnv>().is_err());
+    }
+}
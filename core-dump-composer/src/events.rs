@@ -1,13 +1,78 @@
+use crate::clock::ClockSanity;
 use crate::config::CoreParams;
+use crate::decision::Decision;
+use crate::network::NetworkIdentity;
+use crate::oom::OomCorrelation;
+use crate::proto::{Encode, ProtoWriter};
+use crate::redact::Redactor;
+use crate::resources::ContainerResources;
+use crate::signature::Duplicate;
+use crate::volumes::Volume;
 use advisory_lock::{AdvisoryFileLock, FileLockMode};
+use log::{error, warn};
 use serde::Serialize;
 use serde_json::Value;
 use std::collections::HashMap;
+use std::fs;
 use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use uuid::Uuid;
 
+/// The serialization used for event files, set with EVENT_FORMAT.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EventFormat {
+    Json,
+    Yaml,
+    Protobuf,
+}
+
+impl EventFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            EventFormat::Json => "json",
+            EventFormat::Yaml => "yaml",
+            EventFormat::Protobuf => "pb",
+        }
+    }
+}
+
+impl FromStr for EventFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(EventFormat::Json),
+            "yaml" | "yml" => Ok(EventFormat::Yaml),
+            "protobuf" | "proto" | "pb" => Ok(EventFormat::Protobuf),
+            other => Err(anyhow::anyhow!("Unknown event format {other}")),
+        }
+    }
+}
+
+/// What an event reports. Crashes the runtime had no pod for get their own
+/// type so they can be told apart from the pods' downstream.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum EventType {
+    CoreDump,
+    UnknownPod,
+}
+
+impl EventType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventType::CoreDump => "core-dump",
+            EventType::UnknownPod => "unknown-pod",
+        }
+    }
+}
+
 #[derive(Serialize)]
 pub struct CoreEvent {
+    event_type: EventType,
     image_list: Vec<String>,
     key: String,
     exe_path: String,
@@ -19,7 +84,31 @@ pub struct CoreEvent {
     timestamp: String,
     hostname: String,
     namespace: Option<String>,
+    data_class: Option<String>,
+    volumes: Vec<Volume>,
+    network: Option<NetworkIdentity>,
+    clock: Option<ClockSanity>,
+    decision: Decision,
+    oom_correlated: bool,
+    oom: Option<OomCorrelation>,
+    resources: Option<ContainerResources>,
     uuid: Uuid,
+    /// The stable id of the capture, see `CoreConfig::get_dump_id`.
+    dump_id: String,
+    signature: Option<String>,
+    duplicate: Option<Duplicate>,
+    /// The start of the backtrace, the archive has all of it.
+    backtrace: Option<String>,
+    archive_sha256: Option<String>,
+}
+
+/// The repo digest of an image as `crictl img` lists it. Images loaded
+/// into the node rather than pulled have none, their id stands in.
+pub fn image_digest(image: &Value) -> &str {
+    image["repoDigests"][0]
+        .as_str()
+        .or_else(|| image["id"].as_str())
+        .unwrap_or_default()
 }
 
 impl CoreEvent {
@@ -27,6 +116,7 @@ impl CoreEvent {
         let images: Vec<String> = vec![];
         let hm = HashMap::new();
         CoreEvent {
+            event_type: EventType::CoreDump,
             image_list: images.to_vec(),
             key: zip_name,
             exe_path: core.pathname,
@@ -38,6 +128,19 @@ impl CoreEvent {
             timestamp: core.timestamp,
             hostname: core.hostname,
             namespace: core.namespace,
+            data_class: core.data_class,
+            volumes: core.volumes,
+            network: core.network,
+            clock: core.clock,
+            decision: core.decision,
+            oom_correlated: core.oom.as_ref().is_some_and(|o| o.oom_correlated),
+            oom: core.oom,
+            resources: core.resources,
+            dump_id: core.uuid.to_string(),
+            signature: core.signature,
+            duplicate: core.duplicate,
+            backtrace: core.backtrace,
+            archive_sha256: core.archive_sha256,
             uuid: core.uuid,
         }
     }
@@ -64,11 +167,15 @@ impl CoreEvent {
         let mut images: Vec<String> = vec![];
 
         for img in image_info {
-            let img_digest = img["repoDigests"][0].as_str().unwrap_or_default();
-            images.push(img_digest.to_string());
+            images.push(image_digest(&img).to_string());
         }
 
         CoreEvent {
+            event_type: if core.unknown_pod {
+                EventType::UnknownPod
+            } else {
+                EventType::CoreDump
+            },
             image_list: images.to_vec(),
             key: zip_name,
             exe_path: core.pathname,
@@ -80,28 +187,178 @@ impl CoreEvent {
             timestamp: core.timestamp,
             hostname: core.hostname,
             namespace: core.namespace,
+            data_class: core.data_class,
+            volumes: core.volumes,
+            network: core.network,
+            clock: core.clock,
+            decision: core.decision,
+            oom_correlated: core.oom.as_ref().is_some_and(|o| o.oom_correlated),
+            oom: core.oom,
+            resources: core.resources,
+            dump_id: core.uuid.to_string(),
+            signature: core.signature,
+            duplicate: core.duplicate,
+            backtrace: core.backtrace,
+            archive_sha256: core.archive_sha256,
             uuid: core.uuid,
         }
     }
 
-    pub fn write_event(&self, eventlocation: &str) -> Result<(), anyhow::Error> {
-        let full_path = format!("{}/{}-event.json", eventlocation, self.uuid);
-        let file = File::create(full_path)?;
-        file.lock(FileLockMode::Exclusive)?;
-        serde_json::to_writer(&file, &self)?;
-        file.unlock()?;
+    /// Hides the values of labels matching REDACT_PATTERNS.
+    pub fn redacted(mut self, redactor: &Redactor) -> CoreEvent {
+        for (name, value) in self.labels.iter_mut() {
+            if redactor.matches(name) {
+                *value = "********".to_string();
+            }
+        }
+        self
+    }
+
+    pub fn serialize(&self, format: EventFormat) -> Result<Vec<u8>, anyhow::Error> {
+        Ok(match format {
+            EventFormat::Json => serde_json::to_vec(&self)?,
+            EventFormat::Yaml => serde_yaml::to_string(&self)?.into_bytes(),
+            EventFormat::Protobuf => {
+                let mut w = ProtoWriter::default();
+                self.encode(&mut w);
+                w.buf
+            }
+        })
+    }
+
+    pub fn write_event(
+        &self,
+        eventlocation: &str,
+        format: EventFormat,
+    ) -> Result<(), anyhow::Error> {
+        let full_path = format!(
+            "{}/{}-event.{}",
+            eventlocation,
+            self.uuid,
+            format.extension()
+        );
+        let content = self.serialize(format)?;
+        let mut file = File::create(full_path)?;
+        AdvisoryFileLock::lock(&file, FileLockMode::Exclusive)?;
+        file.write_all(&content)?;
+        AdvisoryFileLock::unlock(&file)?;
         Ok(())
     }
+
+    /// Writes the event to `eventlocation` and never loses it silently. An
+    /// unwritable event directory spools the event to `spool`, which the
+    /// agent moves into the event directory once it can, and when that
+    /// fails too the event is logged as JSON.
+    pub fn deliver(&self, eventlocation: &str, spool: &Path, format: EventFormat) -> Delivery {
+        let e = match self.write_event(eventlocation, format) {
+            Ok(_) => return Delivery::Written,
+            Err(e) => e,
+        };
+        error!("Writing event to {} failed: {}", eventlocation, e);
+        let spooled = fs::create_dir_all(spool)
+            .map_err(anyhow::Error::from)
+            .and_then(|_| {
+                let name = format!("{}-event.{}", self.uuid, format.extension());
+                let partial = spool.join(format!(".{name}.tmp"));
+                fs::write(&partial, self.serialize(format)?)?;
+                fs::rename(&partial, spool.join(&name))?;
+                Ok(spool.join(name))
+            });
+        match spooled {
+            Ok(path) => {
+                warn!("Spooled event to {}", path.display());
+                Delivery::Spooled(path)
+            }
+            Err(e) => {
+                error!("Spooling event to {} failed: {}", spool.display(), e);
+                let json = serde_json::to_string(&self).unwrap_or_default();
+                error!("Undelivered event: {}", json);
+                eprintln!("{json}");
+                Delivery::Logged
+            }
+        }
+    }
+}
+
+/// Where an event ended up.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Delivery {
+    Written,
+    Spooled(PathBuf),
+    Logged,
+}
+
+impl Encode for CoreEvent {
+    fn encode(&self, w: &mut ProtoWriter) {
+        for image in &self.image_list {
+            w.string(1, image);
+        }
+        w.string(2, &self.key);
+        w.string(3, &self.exe_path);
+        let mut labels: Vec<_> = self.labels.iter().collect();
+        labels.sort();
+        for (k, v) in labels {
+            w.map_entry(4, k, v);
+        }
+        w.string(5, &self.limit_size);
+        w.string(6, &self.exe_name);
+        w.string(7, &self.pid);
+        w.string(8, &self.signal);
+        w.string(9, &self.timestamp);
+        w.string(10, &self.hostname);
+        w.opt_string(11, &self.namespace);
+        w.opt_string(12, &self.data_class);
+        for volume in &self.volumes {
+            w.message(13, volume);
+        }
+        if let Some(network) = &self.network {
+            w.message(14, network);
+        }
+        if let Some(clock) = &self.clock {
+            w.message(15, clock);
+        }
+        w.string(16, &self.uuid.to_string());
+        w.message(17, &self.decision);
+        w.bool(18, self.oom_correlated);
+        if let Some(oom) = &self.oom {
+            w.message(19, oom);
+        }
+        w.string(20, &self.dump_id);
+        w.opt_string(21, &self.signature);
+        if let Some(duplicate) = &self.duplicate {
+            w.message(22, duplicate);
+        }
+        w.opt_string(23, &self.backtrace);
+        w.string(24, self.event_type.as_str());
+        if let Some(resources) = &self.resources {
+            w.message(25, resources);
+        }
+        w.opt_string(26, &self.archive_sha256);
+    }
+}
+
+impl Encode for Duplicate {
+    fn encode(&self, w: &mut ProtoWriter) {
+        w.string(1, &self.dump_id);
+        w.int64(2, self.captured as i64);
+        w.int64(3, self.count as i64);
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::events::CoreEvent;
     use crate::events::CoreParams;
+    use crate::events::Decision;
+    use crate::events::Delivery;
+    use crate::events::EventFormat;
+    use crate::events::EventType;
+    use crate::redact::Redactor;
     use serde_json::json;
     use serde_json::Value;
     use std::fs;
     use std::path::Path;
+    use std::str::FromStr;
     use uuid::Uuid;
 
     #[test]
@@ -111,7 +368,7 @@ mod tests {
             .unwrap()
             .to_string_lossy()
             .to_string();
-        event.write_event(&dir).unwrap();
+        event.write_event(&dir, EventFormat::Json).unwrap();
         let full_path = format!("{}/{}-event.json", dir, event.uuid);
 
         assert!(Path::new(&full_path).exists());
@@ -119,6 +376,27 @@ mod tests {
         fs::remove_file(full_path).unwrap();
     }
 
+    #[test]
+    fn spool_test() {
+        let event = setup_with_labels();
+        let root = std::env::temp_dir().join(format!("events-test-{}", Uuid::new_v4()));
+        let missing = root.join("missing");
+        let spool = root.join("event-spool");
+        let delivery = event.deliver(&missing.to_string_lossy(), &spool, EventFormat::Json);
+        let spooled = spool.join(format!("{}-event.json", event.uuid));
+        assert_eq!(delivery, Delivery::Spooled(spooled.clone()));
+        let json: Value = serde_json::from_slice(&fs::read(&spooled).unwrap()).unwrap();
+        assert_eq!(json["exe_name"], "exe-name");
+        assert_eq!(fs::read_dir(&spool).unwrap().count(), 1);
+
+        // A file where the spool directory should be.
+        fs::remove_dir_all(&spool).unwrap();
+        fs::write(&spool, "").unwrap();
+        let delivery = event.deliver(&missing.to_string_lossy(), &spool, EventFormat::Json);
+        assert_eq!(delivery, Delivery::Logged);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
     #[test]
     fn create_coreevent_without_labels_test() {
         let event = setup_without_labels();
@@ -132,7 +410,11 @@ mod tests {
             event.labels["info.coredump.repo"],
             "core-dump-handler".to_string()
         );
-        assert_eq!(event.labels["info.coredump.owner"], "no9".to_string())
+        assert_eq!(event.labels["info.coredump.owner"], "no9".to_string());
+
+        let event = event.redacted(&Redactor::new("*OWNER"));
+        assert_eq!(event.labels["info.coredump.owner"], "********");
+        assert_eq!(event.labels["info.coredump.repo"], "core-dump-handler");
     }
 
     #[test]
@@ -142,11 +424,95 @@ mod tests {
         assert_eq!(event.image_list[1], "icr.io/ibm/ibmcloud-object-storage-driver@sha256:c796a4c693b4b7bf366c89208e96648d082836ebcb3bd03d8b63aca6883a69b0".to_string());
     }
 
+    #[test]
+    fn unknown_pod_test() {
+        let event = setup_with_labels();
+        assert_eq!(event.event_type, EventType::CoreDump);
+        assert_eq!(
+            serde_json::to_value(&event).unwrap()["event_type"],
+            "core-dump"
+        );
+
+        let params = CoreParams {
+            limit_size: "0".to_string(),
+            exe_name: "mo-service".to_string(),
+            pid: "1".to_string(),
+            host_pid: "4242".to_string(),
+            signal: "11".to_string(),
+            timestamp: "1706263200".to_string(),
+            directory: "/var/mnt/core-dump-handler/cores".to_string(),
+            hostname: "worker-2".to_string(),
+            pathname: "!usr!bin!mo-service".to_string(),
+            namespace: Some("unknown".to_string()),
+            uuid: Uuid::new_v4(),
+            podname: Some("unknown".to_string()),
+            pod_uid: Some("unknown".to_string()),
+            sequence: 0,
+            data_class: None,
+            volumes: vec![],
+            network: None,
+            clock: None,
+            oom: None,
+            resources: None,
+            decision: Decision::default(),
+            signature: None,
+            duplicate: None,
+            unknown_pod: true,
+            backtrace: None,
+            archive_sha256: None,
+        };
+        let event = CoreEvent::new(params, "a.tar".to_string(), json!({}), vec![]);
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["event_type"], "unknown-pod");
+    }
+
+    #[test]
+    fn create_coreevent_with_data_class_test() {
+        let event = setup_with_labels();
+        assert_eq!(event.data_class.as_deref(), Some("confidential"));
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["data_class"], "confidential");
+    }
+
+    #[test]
+    fn event_formats_test() {
+        let event = setup_with_labels();
+        assert_eq!(EventFormat::from_str("YAML").unwrap(), EventFormat::Yaml);
+        assert!(EventFormat::from_str("xml").is_err());
+
+        let yaml = String::from_utf8(event.serialize(EventFormat::Yaml).unwrap()).unwrap();
+        let parsed: serde_yaml::Value = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(parsed["exe_name"].as_str(), Some("exe-name"));
+        assert_eq!(parsed["data_class"].as_str(), Some("confidential"));
+
+        let pb = event.serialize(EventFormat::Protobuf).unwrap();
+        // Field 1, length delimited: the first image digest.
+        assert_eq!(pb[0], 0x0a);
+        // Field 24, the event type, closes the message.
+        let event_type = [&[0xc2, 0x01, 9][..], b"core-dump"].concat();
+        assert!(pb.ends_with(&event_type));
+        let pb = &pb[..pb.len() - event_type.len()];
+        // Field 20, the dump id, comes before it.
+        let dump_id = [&[0xa2, 0x01, 36][..], event.uuid.to_string().as_bytes()].concat();
+        assert!(pb.ends_with(&dump_id));
+        let pb = &pb[..pb.len() - dump_id.len()];
+        // Field 17, the decision, comes before it.
+        let decision = [&[0x8a, 0x01, 10, 0x0a, 8][..], b"captured"].concat();
+        assert!(pb.ends_with(&decision));
+        let pb = &pb[..pb.len() - decision.len()];
+        let uuid = event.uuid.to_string();
+        assert!(pb.ends_with(uuid.as_bytes()));
+        // Field 16 needs a two byte key followed by the length.
+        let at = pb.len() - uuid.len() - 3;
+        assert_eq!(&pb[at..at + 3], &[0x82, 0x01, uuid.len() as u8]);
+    }
+
     fn setup_without_labels() -> CoreEvent {
         let zip_name = "afile.zip".to_string();
         let limit_size = "limit-size".to_string();
         let exe_name = "exe-name".to_string();
         let pid = "pid".to_string();
+        let host_pid = "host_pid".to_string();
         let signal = "signal".to_string();
         let timestamp = "timestamp".to_string();
         let directory = "directory".to_string();
@@ -159,6 +525,7 @@ mod tests {
             limit_size,
             exe_name,
             pid,
+            host_pid,
             signal,
             timestamp,
             directory,
@@ -167,6 +534,20 @@ mod tests {
             namespace: None,
             uuid,
             podname: Some(podname),
+            pod_uid: None,
+            sequence: 0,
+            data_class: None,
+            volumes: vec![],
+            network: None,
+            clock: None,
+            oom: None,
+            resources: None,
+            decision: Decision::default(),
+            signature: None,
+            duplicate: None,
+            unknown_pod: false,
+            backtrace: None,
+            archive_sha256: None,
         };
         let pod = json!(
            {
@@ -221,7 +602,7 @@ mod tests {
         });
         let images: Vec<Value> = vec![image1, image2];
 
-        return CoreEvent::new(params, zip_name, pod, images);
+        CoreEvent::new(params, zip_name, pod, images)
     }
 
     fn setup_with_labels() -> CoreEvent {
@@ -229,6 +610,7 @@ mod tests {
         let limit_size = "limit-size".to_string();
         let exe_name = "exe-name".to_string();
         let pid = "pid".to_string();
+        let host_pid = "host_pid".to_string();
         let signal = "signal".to_string();
         let timestamp = "timestamp".to_string();
         let directory = "directory".to_string();
@@ -241,6 +623,7 @@ mod tests {
             limit_size,
             exe_name,
             pid,
+            host_pid,
             signal,
             timestamp,
             directory,
@@ -249,6 +632,20 @@ mod tests {
             namespace: None,
             uuid,
             podname: Some(podname),
+            pod_uid: None,
+            sequence: 0,
+            data_class: Some("confidential".to_string()),
+            volumes: vec![],
+            network: None,
+            clock: None,
+            oom: None,
+            resources: None,
+            decision: Decision::default(),
+            signature: None,
+            duplicate: None,
+            unknown_pod: false,
+            backtrace: None,
+            archive_sha256: None,
         };
         let image1 = json!({
           "id": "sha256:3b8adc6c30f4e7e4afb57daef9d1c8af783a4a647a4670780e9df085c0525efa",
@@ -305,6 +702,6 @@ mod tests {
            }
         );
 
-        return CoreEvent::new(params, zip_name, pod, images);
+        CoreEvent::new(params, zip_name, pod, images)
     }
 }
@@ -0,0 +1,316 @@
+use anyhow::anyhow;
+use serde::Serialize;
+use serde_json::Value;
+use std::str::FromStr;
+
+/// Lets a team opt its pods in or out of capture from the pod spec. `true`
+/// captures the pod whatever the pod selector and namespace lists say,
+/// `false` skips it.
+pub const ENABLED_ANNOTATION: &str = "coredump.matrixorigin.io/enabled";
+
+/// Which namespaces are captured, from NAMESPACE_ALLOWLIST and
+/// NAMESPACE_DENYLIST. Both are comma separated names, a trailing `*`
+/// matches any namespace starting with the rest.
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct NamespaceFilter {
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+}
+
+fn names(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(|n| n.trim().to_string())
+        .filter(|n| !n.is_empty())
+        .collect()
+}
+
+fn matches(pattern: &str, namespace: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => namespace.starts_with(prefix),
+        None => pattern == namespace,
+    }
+}
+
+impl NamespaceFilter {
+    pub fn new(allow: &str, deny: &str) -> NamespaceFilter {
+        NamespaceFilter {
+            allow: names(allow),
+            deny: names(deny),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    /// Whether `namespace` is captured and why. The deny list wins over
+    /// the allow list.
+    pub fn check(&self, namespace: &str) -> (bool, String) {
+        if let Some(p) = self.deny.iter().find(|p| matches(p, namespace)) {
+            return (false, format!("{namespace} denied by {p}"));
+        }
+        if self.allow.is_empty() {
+            return (true, format!("{namespace} not denied"));
+        }
+        match self.allow.iter().find(|p| matches(p, namespace)) {
+            Some(p) => (true, format!("{namespace} allowed by {p}")),
+            None => (false, format!("{namespace} not in the allowlist")),
+        }
+    }
+}
+
+/// Which executables are captured, from the comma separated globs of
+/// EXE_FILTER. `*` matches any run of characters and `?` one, a pattern
+/// with a `/` is matched against the full path and others against the
+/// file name. A leading `!` excludes and wins over the other patterns.
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExeFilter {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+/// Matches `name` against a glob of `*` and `?`.
+pub fn glob(pattern: &str, name: &str) -> bool {
+    let (p, n): (Vec<char>, Vec<char>) = (pattern.chars().collect(), name.chars().collect());
+    let (mut pi, mut ni) = (0, 0);
+    // Where the last `*` was and the name position it is retried from.
+    let mut star: Option<(usize, usize)> = None;
+    while ni < n.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == n[ni]) {
+            pi += 1;
+            ni += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ni));
+            pi += 1;
+        } else if let Some((sp, sn)) = star {
+            pi = sp + 1;
+            ni = sn + 1;
+            star = Some((sp, sn + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|c| *c == '*')
+}
+
+impl ExeFilter {
+    pub fn new(patterns: &str) -> ExeFilter {
+        let (exclude, include): (Vec<String>, Vec<String>) = names(patterns)
+            .into_iter()
+            .partition(|p| p.starts_with('!'));
+        ExeFilter {
+            include,
+            exclude: exclude.into_iter().map(|p| p[1..].to_string()).collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    /// Whether the executable is captured and why. `name` is `%e`, which the
+    /// kernel cuts to 15 characters, so the file name of `path` is tried
+    /// too.
+    pub fn check(&self, name: &str, path: &str) -> (bool, String) {
+        let file = path.rsplit('/').next().unwrap_or_default();
+        let matches = |p: &String| {
+            if p.contains('/') {
+                glob(p, path)
+            } else {
+                glob(p, name) || (!file.is_empty() && glob(p, file))
+            }
+        };
+        if let Some(p) = self.exclude.iter().find(|p| matches(p)) {
+            return (false, format!("{name} excluded by !{p}"));
+        }
+        if self.include.is_empty() {
+            return (true, format!("{name} not excluded"));
+        }
+        match self.include.iter().find(|p| matches(p)) {
+            Some(p) => (true, format!("{name} matched {p}")),
+            None => (false, format!("{name} matched no EXE_FILTER pattern")),
+        }
+    }
+}
+
+/// The signals whose default action dumps a core, see signal(7).
+const CORE_SIGNALS: [(&str, i32); 11] = [
+    ("QUIT", libc::SIGQUIT),
+    ("ILL", libc::SIGILL),
+    ("TRAP", libc::SIGTRAP),
+    ("ABRT", libc::SIGABRT),
+    ("IOT", libc::SIGIOT),
+    ("BUS", libc::SIGBUS),
+    ("FPE", libc::SIGFPE),
+    ("SEGV", libc::SIGSEGV),
+    ("XCPU", libc::SIGXCPU),
+    ("XFSZ", libc::SIGXFSZ),
+    ("SYS", libc::SIGSYS),
+];
+
+/// The signals captured, from the comma separated names or numbers of
+/// SIGNALS such as `SIGSEGV,SIGABRT,7`. Empty captures every signal.
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct SignalFilter {
+    pub signals: Vec<i32>,
+}
+
+fn signal_name(signal: i32) -> String {
+    CORE_SIGNALS
+        .iter()
+        .find(|(_, n)| *n == signal)
+        .map(|(name, _)| format!("SIG{name}"))
+        .unwrap_or_else(|| signal.to_string())
+}
+
+impl FromStr for SignalFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let signals = names(s)
+            .iter()
+            .map(|v| {
+                let upper = v.to_uppercase();
+                let name = upper.strip_prefix("SIG").unwrap_or(&upper);
+                match CORE_SIGNALS.iter().find(|(n, _)| *n == name) {
+                    Some((_, signal)) => Ok(*signal),
+                    None => v
+                        .parse::<i32>()
+                        .ok()
+                        .filter(|n| CORE_SIGNALS.iter().any(|(_, s)| s == n))
+                        .ok_or_else(|| {
+                            anyhow!("{} in SIGNALS is not a signal that dumps a core", v)
+                        }),
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(SignalFilter { signals })
+    }
+}
+
+impl SignalFilter {
+    pub fn is_empty(&self) -> bool {
+        self.signals.is_empty()
+    }
+
+    /// Whether a crash from `signal`, the `%s` of the core_pattern, is
+    /// captured and why.
+    pub fn check(&self, signal: &str) -> (bool, String) {
+        match signal.parse::<i32>() {
+            Ok(n) if self.signals.contains(&n) => (true, format!("{} selected", signal_name(n))),
+            Ok(n) => (false, format!("{} not in SIGNALS", signal_name(n))),
+            // Not one we can tell apart, so not one to drop.
+            Err(_) => (true, format!("signal {signal:?} unknown, captured")),
+        }
+    }
+}
+
+/// The value of ENABLED_ANNOTATION on the crictl pod, None when it isn't
+/// set.
+pub fn pod_opt_in(pod: &Value) -> Result<Option<bool>, String> {
+    match pod["annotations"][ENABLED_ANNOTATION].as_str() {
+        None => Ok(None),
+        Some(v) => v
+            .trim()
+            .to_lowercase()
+            .parse::<bool>()
+            .map(Some)
+            .map_err(|_| format!("{ENABLED_ANNOTATION} is {v:?}, expected \"true\" or \"false\"")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::filter::{glob, pod_opt_in, ExeFilter, NamespaceFilter, SignalFilter};
+    use serde_json::json;
+
+    #[test]
+    fn check_test() {
+        let filter = NamespaceFilter::new("", "");
+        assert!(filter.is_empty());
+        assert!(filter.check("default").0);
+
+        let filter = NamespaceFilter::new(" mo, team-* ", "team-sandbox");
+        assert_eq!(filter.allow, vec!["mo", "team-*"]);
+        assert_eq!(filter.check("mo"), (true, "mo allowed by mo".to_string()));
+        assert!(filter.check("team-db").0);
+        assert_eq!(
+            filter.check("team-sandbox"),
+            (false, "team-sandbox denied by team-sandbox".to_string())
+        );
+        assert_eq!(
+            filter.check("kube-system"),
+            (false, "kube-system not in the allowlist".to_string())
+        );
+
+        let filter = NamespaceFilter::new("", "kube-*");
+        assert!(!filter.check("kube-system").0);
+        assert!(filter.check("unknown").0);
+    }
+
+    #[test]
+    fn exe_filter_test() {
+        assert!(glob("mo-*", "mo-service"));
+        assert!(glob("*sh", "bash"));
+        assert!(glob("m?-serv*e", "mo-service"));
+        assert!(glob("*", ""));
+        assert!(!glob("mo-*", "nginx"));
+        assert!(!glob("*sh", "shell"));
+
+        let filter = ExeFilter::new("");
+        assert!(filter.is_empty());
+        assert!(filter.check("sh", "/bin/sh").0);
+
+        let filter = ExeFilter::new("mo-service, /opt/mo/bin/*, !*sh");
+        assert_eq!(filter.exclude, vec!["*sh"]);
+        assert_eq!(
+            filter.check("mo-service", "/usr/bin/mo-service"),
+            (true, "mo-service matched mo-service".to_string())
+        );
+        assert!(filter.check("mo-tool", "/opt/mo/bin/mo-tool").0);
+        assert_eq!(
+            filter.check("bash", "/bin/bash"),
+            (false, "bash excluded by !*sh".to_string())
+        );
+        assert_eq!(
+            filter.check("envoy", "/usr/local/bin/envoy"),
+            (false, "envoy matched no EXE_FILTER pattern".to_string())
+        );
+        // %e is cut to 15 characters, the path isn't.
+        let filter = ExeFilter::new("matrixone-server");
+        assert!(filter.check("matrixone-serve", "/mo/matrixone-server").0);
+
+        let filter = ExeFilter::new("!istio-*");
+        assert!(!filter.check("istio-proxy", "").0);
+        assert!(filter.check("mo-service", "").0);
+    }
+
+    #[test]
+    fn signal_filter_test() {
+        assert!("".parse::<SignalFilter>().unwrap().is_empty());
+        let filter: SignalFilter = "SIGSEGV, abrt,7".parse().unwrap();
+        assert_eq!(filter.signals, vec![11, 6, 7]);
+        assert_eq!(filter.check("11"), (true, "SIGSEGV selected".to_string()));
+        assert_eq!(
+            filter.check("3"),
+            (false, "SIGQUIT not in SIGNALS".to_string())
+        );
+        assert!(filter.check("").0);
+        assert!("SIGTERM".parse::<SignalFilter>().is_err());
+        assert!("15".parse::<SignalFilter>().is_err());
+    }
+
+    #[test]
+    fn pod_opt_in_test() {
+        assert_eq!(pod_opt_in(&json!({})), Ok(None));
+        let pod = json!({"annotations": {"coredump.matrixorigin.io/enabled": "False"}});
+        assert_eq!(pod_opt_in(&pod), Ok(Some(false)));
+        let pod = json!({"annotations": {"coredump.matrixorigin.io/enabled": "true"}});
+        assert_eq!(pod_opt_in(&pod), Ok(Some(true)));
+        let pod = json!({"annotations": {"coredump.matrixorigin.io/enabled": "yes"}});
+        assert_eq!(
+            pod_opt_in(&pod).unwrap_err(),
+            "coredump.matrixorigin.io/enabled is \"yes\", expected \"true\" or \"false\""
+        );
+    }
+}
@@ -0,0 +1,189 @@
+use serde::Serialize;
+use std::fs;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::{Path, PathBuf};
+
+/// Stop walking the upper layer after this many changes.
+const MAX_CHANGES: usize = 10_000;
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Added,
+    Modified,
+    Deleted,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct ChangedFile {
+    pub path: String,
+    pub kind: ChangeKind,
+    pub size: u64,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct FsDiff {
+    pub upperdir: String,
+    pub truncated: bool,
+    pub changes: Vec<ChangedFile>,
+}
+
+/// The overlay layers of the root filesystem of a process.
+#[derive(Debug, PartialEq, Eq)]
+pub struct OverlayRoot {
+    pub upperdir: String,
+    pub lowerdirs: Vec<String>,
+}
+
+/// Finds the overlay mounted at `/` in `/proc/<pid>/mountinfo`. Both CRI-O
+/// and containerd use overlayfs snapshots so the upper layer holds exactly
+/// the files the container changed.
+pub fn parse_overlay_root(mountinfo: &str) -> Option<OverlayRoot> {
+    for line in mountinfo.lines() {
+        let (mount, sup) = match line.split_once(" - ") {
+            Some(v) => v,
+            None => continue,
+        };
+        if mount.split_whitespace().nth(4) != Some("/") {
+            continue;
+        }
+        let mut sup = sup.split_whitespace();
+        if sup.next() != Some("overlay") {
+            continue;
+        }
+        let options = sup.nth(1).unwrap_or_default();
+        let mut upperdir = None;
+        let mut lowerdirs = vec![];
+        for option in options.split(',') {
+            if let Some(v) = option.strip_prefix("upperdir=") {
+                upperdir = Some(v.to_string());
+            } else if let Some(v) = option.strip_prefix("lowerdir=") {
+                lowerdirs = v.split(':').map(|d| d.to_string()).collect();
+            }
+        }
+        if let Some(upperdir) = upperdir {
+            return Some(OverlayRoot {
+                upperdir,
+                lowerdirs,
+            });
+        }
+    }
+    None
+}
+
+fn in_lower(root: &OverlayRoot, relative: &Path) -> bool {
+    root.lowerdirs
+        .iter()
+        .any(|l| fs::symlink_metadata(Path::new(l).join(relative)).is_ok())
+}
+
+/// Lists the files the container added, modified or deleted.
+pub fn changed_files(root: &OverlayRoot) -> FsDiff {
+    let upper = PathBuf::from(&root.upperdir);
+    let mut diff = FsDiff {
+        upperdir: root.upperdir.clone(),
+        truncated: false,
+        changes: vec![],
+    };
+    let mut pending = vec![upper.clone()];
+    while let Some(dir) = pending.pop() {
+        let mut entries: Vec<_> = match fs::read_dir(&dir) {
+            Ok(v) => v.flatten().collect(),
+            Err(_) => continue,
+        };
+        entries.sort_by_key(|e| e.file_name());
+        for entry in entries {
+            let path = entry.path();
+            let metadata = match fs::symlink_metadata(&path) {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            let relative = path.strip_prefix(&upper).unwrap_or(&path).to_path_buf();
+            if metadata.is_dir() {
+                pending.push(path);
+                if in_lower(root, &relative) {
+                    continue;
+                }
+            }
+            if diff.changes.len() >= MAX_CHANGES {
+                diff.truncated = true;
+                return diff;
+            }
+            // overlayfs records deletions as 0:0 character devices.
+            let kind = if metadata.file_type().is_char_device() && metadata.rdev() == 0 {
+                ChangeKind::Deleted
+            } else if in_lower(root, &relative) {
+                ChangeKind::Modified
+            } else {
+                ChangeKind::Added
+            };
+            let size = if kind == ChangeKind::Deleted {
+                0
+            } else {
+                metadata.len()
+            };
+            diff.changes.push(ChangedFile {
+                path: format!("/{}", relative.display()),
+                kind,
+                size,
+            });
+        }
+    }
+    diff
+}
+
+pub fn read_fs_diff(proc_dir: &Path) -> Option<FsDiff> {
+    let mountinfo = fs::read_to_string(proc_dir.join("mountinfo")).ok()?;
+    parse_overlay_root(&mountinfo).map(|root| changed_files(&root))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fsdiff::{changed_files, parse_overlay_root, ChangeKind, OverlayRoot};
+    use std::fs;
+    use uuid::Uuid;
+
+    #[test]
+    fn parse_overlay_root_test() {
+        let mountinfo = "2100 1900 0:250 / / rw,relatime master:600 - overlay overlay rw,lowerdir=/var/lib/containers/storage/overlay/l/A:/var/lib/containers/storage/overlay/l/B,upperdir=/var/lib/containers/storage/overlay/0c65/diff,workdir=/var/lib/containers/storage/overlay/0c65/work
+2101 2100 0:252 / /proc rw,nosuid,nodev,noexec,relatime - proc proc rw";
+        let root = parse_overlay_root(mountinfo).unwrap();
+        assert_eq!(
+            root.upperdir,
+            "/var/lib/containers/storage/overlay/0c65/diff"
+        );
+        assert_eq!(root.lowerdirs.len(), 2);
+
+        let host = "22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw";
+        assert_eq!(parse_overlay_root(host), None);
+    }
+
+    #[test]
+    fn changed_files_test() {
+        let base = std::env::temp_dir().join(format!("fsdiff-test-{}", Uuid::new_v4()));
+        let lower = base.join("lower");
+        let upper = base.join("upper");
+        fs::create_dir_all(lower.join("etc")).unwrap();
+        fs::write(lower.join("etc/mo.toml"), "original").unwrap();
+        fs::create_dir_all(upper.join("etc")).unwrap();
+        fs::write(upper.join("etc/mo.toml"), "changed by runtime").unwrap();
+        fs::create_dir_all(upper.join("data")).unwrap();
+        fs::write(upper.join("data/wal.log"), "x").unwrap();
+
+        let root = OverlayRoot {
+            upperdir: upper.display().to_string(),
+            lowerdirs: vec![lower.display().to_string()],
+        };
+        let diff = changed_files(&root);
+        assert!(!diff.truncated);
+        let find = |p: &str| diff.changes.iter().find(|c| c.path == p);
+        assert_eq!(find("/etc/mo.toml").unwrap().kind, ChangeKind::Modified);
+        assert_eq!(find("/etc/mo.toml").unwrap().size, 18);
+        assert_eq!(find("/data").unwrap().kind, ChangeKind::Added);
+        assert_eq!(find("/data/wal.log").unwrap().kind, ChangeKind::Added);
+        // Directories that exist in the image are not reported themselves.
+        assert!(find("/etc").is_none());
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+}
@@ -0,0 +1,132 @@
+use std::io::{Seek, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The time the capture runs against. The composer uses `SystemClock`,
+/// tests one they move forward themselves so timeouts are deterministic.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+    fn sleep(&self, duration: Duration);
+
+    fn elapsed(&self, since: Instant) -> Duration {
+        self.now().saturating_duration_since(since)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration)
+    }
+}
+
+/// The file an archive is written to. The composer writes a `File`, tests
+/// a buffer that runs out of space or takes its time.
+pub trait Storage: Write + Seek + Send {}
+
+impl<T: Write + Seek + Send> Storage for T {}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::host::Clock;
+    use std::io;
+    use std::io::{Cursor, Seek, SeekFrom, Write};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    /// A clock that only moves when it is slept on or advanced.
+    #[derive(Clone)]
+    pub struct ManualClock {
+        start: Instant,
+        offset: Arc<Mutex<Duration>>,
+    }
+
+    impl ManualClock {
+        pub fn new() -> ManualClock {
+            ManualClock {
+                start: Instant::now(),
+                offset: Arc::new(Mutex::new(Duration::ZERO)),
+            }
+        }
+
+        pub fn advance(&self, duration: Duration) {
+            *self.offset.lock().unwrap() += duration;
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> Instant {
+            self.start + *self.offset.lock().unwrap()
+        }
+
+        fn sleep(&self, duration: Duration) {
+            self.advance(duration)
+        }
+    }
+
+    /// An archive on a disk with `free` bytes left, writes past it fail
+    /// with ENOSPC. Each write also takes `per_write` on `clock`, for
+    /// streams slow enough to run into the budget.
+    pub struct TestDisk {
+        pub data: Cursor<Vec<u8>>,
+        pub free: u64,
+        pub clock: ManualClock,
+        pub per_write: Duration,
+    }
+
+    impl TestDisk {
+        pub fn new(free: u64, clock: &ManualClock, per_write: Duration) -> TestDisk {
+            TestDisk {
+                data: Cursor::new(vec![]),
+                free,
+                clock: clock.clone(),
+                per_write,
+            }
+        }
+    }
+
+    impl Write for TestDisk {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.clock.advance(self.per_write);
+            let end = self.data.position() + buf.len() as u64;
+            if end > self.free.max(self.data.get_ref().len() as u64) {
+                return Err(io::Error::from_raw_os_error(libc::ENOSPC));
+            }
+            self.data.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Seek for TestDisk {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.data.seek(pos)
+        }
+    }
+
+    #[test]
+    fn manual_clock_test() {
+        let clock = ManualClock::new();
+        let start = clock.now();
+        clock.sleep(Duration::from_secs(5));
+        assert_eq!(clock.elapsed(start), Duration::from_secs(5));
+
+        let mut disk = TestDisk::new(4, &clock, Duration::from_secs(1));
+        disk.write_all(b"core").unwrap();
+        assert_eq!(clock.elapsed(start), Duration::from_secs(6));
+        let e = disk.write_all(b"!").unwrap_err();
+        assert_eq!(e.raw_os_error(), Some(libc::ENOSPC));
+        // Rewriting a header in place needs no new space.
+        disk.seek(SeekFrom::Start(0)).unwrap();
+        disk.write_all(b"CORE").unwrap();
+        assert_eq!(disk.data.get_ref(), b"CORE");
+    }
+}
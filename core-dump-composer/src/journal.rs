@@ -0,0 +1,48 @@
+use anyhow::anyhow;
+use std::process::Command;
+
+pub const DEFAULT_JOURNAL_MINUTES: u32 = 10;
+
+pub fn journalctl_args(unit: &str, minutes: u32) -> Vec<String> {
+    vec![
+        "--no-pager".to_string(),
+        "--output=short-iso-precise".to_string(),
+        format!("--unit={unit}"),
+        format!("--since=-{minutes}min"),
+    ]
+}
+
+/// The journal of `unit` over the last `minutes`, so a crashing host daemon
+/// comes with the context a pod gets from its container logs.
+pub fn read(unit: &str, minutes: u32, bin_path: &str) -> Result<Vec<u8>, anyhow::Error> {
+    let output = Command::new("journalctl")
+        .env("PATH", bin_path)
+        .args(journalctl_args(unit, minutes))
+        .output()?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "journalctl failed for {}: {}",
+            unit,
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::journal::journalctl_args;
+
+    #[test]
+    fn args_test() {
+        assert_eq!(
+            journalctl_args("mo.service", 10),
+            vec![
+                "--no-pager",
+                "--output=short-iso-precise",
+                "--unit=mo.service",
+                "--since=-10min"
+            ]
+        );
+    }
+}
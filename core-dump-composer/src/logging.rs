@@ -1,13 +1,212 @@
-use log::LevelFilter;
+use crate::logtarget::{
+    JournaldAppender, LogTarget, SyslogAppender, JOURNALD_SOCKET, SYSLOG_SOCKET,
+};
+use log::{LevelFilter, Record};
 use log4rs::append::file::FileAppender;
+use log4rs::append::rolling_file::policy::compound::roll::delete::DeleteRoller;
+use log4rs::append::rolling_file::policy::compound::roll::fixed_window::FixedWindowRoller;
+use log4rs::append::rolling_file::policy::compound::roll::Roll;
+use log4rs::append::rolling_file::policy::compound::trigger::Trigger;
+use log4rs::append::rolling_file::policy::compound::CompoundPolicy;
+use log4rs::append::rolling_file::{LogFile, RollingFileAppender};
+use log4rs::append::Append;
 use log4rs::config::{Appender, Config, Root};
 use log4rs::encode::pattern::PatternEncoder;
+use log4rs::encode::{Encode, Write};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
 use std::env;
+use std::fs;
+use std::path::Path;
 use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-pub fn init_logger(loglevel: String) -> Result<String, anyhow::Error> {
+/// How composer.log is written, set with LOG_FORMAT.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    /// One JSON object per line with the capture's context, for log
+    /// shippers on the node.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "" | "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            other => Err(anyhow::anyhow!("Unknown log format {other}")),
+        }
+    }
+}
+
+/// The capture every line is about. The capture runs on its own thread and
+/// its failure is logged from main, so this is shared rather than per
+/// thread.
+static CONTEXT: Mutex<BTreeMap<&'static str, String>> = Mutex::new(BTreeMap::new());
+
+/// Adds `key` to the fields of the JSON lines that follow. The text format
+/// leaves them out.
+pub fn set_context(key: &'static str, value: &str) {
+    if let Ok(mut context) = CONTEXT.lock() {
+        context.insert(key, value.to_string());
+    }
+}
+
+/// The fields `set_context` added so far.
+pub fn context() -> BTreeMap<&'static str, String> {
+    CONTEXT.lock().map(|c| c.clone()).unwrap_or_default()
+}
+
+/// Marks the start of a capture stage, the lines that follow carry it as
+/// `stage`. Returns the start for `CaptureResult::record_duration`.
+pub fn enter_stage(stage: &str) -> Instant {
+    set_context("stage", stage);
+    Instant::now()
+}
+
+/// Writes `{"timestamp", "level", "message", "target", ...}` with the
+/// context, `stage`, `namespace`, `pod` and `dump_id` once they are known.
+#[derive(Debug)]
+struct JsonEncoder;
+
+impl JsonEncoder {
+    fn line(record: &Record, now: SystemTime) -> Value {
+        let nanos = now
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as i64)
+            .unwrap_or_default();
+        let mut line = Map::new();
+        line.insert("timestamp".to_string(), json!(crate::cri::rfc3339(nanos)));
+        line.insert("level".to_string(), json!(record.level().as_str()));
+        line.insert("message".to_string(), json!(record.args().to_string()));
+        line.insert("target".to_string(), json!(record.target()));
+        if let Ok(context) = CONTEXT.lock() {
+            for (key, value) in context.iter() {
+                line.insert(key.to_string(), json!(value));
+            }
+        }
+        Value::Object(line)
+    }
+}
+
+impl Encode for JsonEncoder {
+    fn encode(&self, w: &mut dyn Write, record: &Record) -> anyhow::Result<()> {
+        serde_json::to_writer(&mut *w, &JsonEncoder::line(record, SystemTime::now()))?;
+        w.write_all(b"\n")?;
+        Ok(())
+    }
+}
+
+/// When composer.log is rolled over, from LOG_MAX_BYTES, LOG_MAX_AGE_HOURS
+/// and LOG_MAX_FILES. Every crash appends to the log, so without a limit a
+/// crash looping node fills its disk with it.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogRotation {
+    /// Rolls over once the log is bigger, 0 for no size limit.
+    pub max_bytes: u64,
+    /// Rolls over once the log was started longer ago, 0 for no age limit.
+    pub max_age_hours: u64,
+    /// How many rolled over logs are kept as composer.1.log and on, 0
+    /// deletes the log instead.
+    pub max_files: u32,
+}
+
+pub const DEFAULT_LOG_MAX_BYTES: u64 = 10 * 1024 * 1024;
+pub const DEFAULT_LOG_MAX_AGE_HOURS: u64 = 7 * 24;
+pub const DEFAULT_LOG_MAX_FILES: u32 = 5;
+
+impl Default for LogRotation {
+    fn default() -> Self {
+        LogRotation {
+            max_bytes: DEFAULT_LOG_MAX_BYTES,
+            max_age_hours: DEFAULT_LOG_MAX_AGE_HOURS,
+            max_files: DEFAULT_LOG_MAX_FILES,
+        }
+    }
+}
+
+/// Rolls the log over when it is over either limit of a `LogRotation`. The
+/// age is taken from the file's birth time, filesystems without one only
+/// roll over by size.
+#[derive(Debug)]
+struct RotationTrigger {
+    max_bytes: u64,
+    max_age: Duration,
+}
+
+impl RotationTrigger {
+    fn due(&self, len: u64, age: Option<Duration>) -> bool {
+        (self.max_bytes > 0 && len > self.max_bytes)
+            || (!self.max_age.is_zero() && age.is_some_and(|age| age > self.max_age))
+    }
+}
+
+impl Trigger for RotationTrigger {
+    fn trigger(&self, file: &LogFile) -> anyhow::Result<bool> {
+        let age = || {
+            let created = fs::metadata(file.path()).ok()?.created().ok()?;
+            SystemTime::now().duration_since(created).ok()
+        };
+        Ok(self.due(
+            file.len_estimate(),
+            if self.max_age.is_zero() { None } else { age() },
+        ))
+    }
+}
+
+impl LogRotation {
+    /// The appender writing `log_path`, rolled over to `composer.<n>.log`
+    /// beside it.
+    fn appender(
+        &self,
+        log_path: &Path,
+        encoder: Box<dyn Encode>,
+    ) -> Result<Box<dyn Append>, anyhow::Error> {
+        if self.max_bytes == 0 && self.max_age_hours == 0 {
+            return Ok(Box::new(
+                FileAppender::builder().encoder(encoder).build(log_path)?,
+            ));
+        }
+        let trigger = RotationTrigger {
+            max_bytes: self.max_bytes,
+            max_age: Duration::from_secs(self.max_age_hours * 3600),
+        };
+        let roller: Box<dyn Roll> = if self.max_files == 0 {
+            Box::new(DeleteRoller::new())
+        } else {
+            let pattern = log_path.with_file_name("composer.{}.log");
+            Box::new(
+                FixedWindowRoller::builder()
+                    .base(1)
+                    .build(&pattern.to_string_lossy(), self.max_files)?,
+            )
+        };
+        Ok(Box::new(
+            RollingFileAppender::builder().encoder(encoder).build(
+                log_path,
+                Box::new(CompoundPolicy::new(Box::new(trigger), roller)),
+            )?,
+        ))
+    }
+}
+
+/// Sets up logging to `targets`. A socket target whose socket is missing
+/// is left out, and when that leaves nothing the file is used so a node
+/// without syslog or journald still has a log. Returns where the log goes.
+pub fn init_logger(
+    loglevel: String,
+    format: LogFormat,
+    targets: &[LogTarget],
+    identifier: &str,
+    rotation: LogRotation,
+) -> Result<String, anyhow::Error> {
     let logfilter = match LevelFilter::from_str(loglevel.as_str()) {
-        Ok(v) => v,
         Ok(v) => v,
         Err(_) => LevelFilter::Debug,
     };
@@ -16,14 +215,129 @@ pub fn init_logger(loglevel: String) -> Result<String, anyhow::Error> {
     log_path.pop();
     log_path.push("composer.log");
 
-    let logfile = FileAppender::builder()
-        .encoder(Box::new(PatternEncoder::new("{l} - {d} - {m}\n")))
-        .build(&log_path)?;
+    let mut targets: Vec<LogTarget> = targets
+        .iter()
+        .copied()
+        .filter(|t| t.socket().is_none_or(Path::exists))
+        .collect();
+    if targets.is_empty() {
+        targets.push(LogTarget::File);
+    }
+
+    let mut config = Config::builder();
+    let mut root = Root::builder();
+    let mut destinations = vec![];
+    for target in &targets {
+        let appender: Box<dyn Append> = match target {
+            LogTarget::File => {
+                let encoder: Box<dyn Encode> = match format {
+                    LogFormat::Text => Box::new(PatternEncoder::new("{l} - {d} - {m}\n")),
+                    LogFormat::Json => Box::new(JsonEncoder),
+                };
+                destinations.push(log_path.display().to_string());
+                rotation.appender(&log_path, encoder)?
+            }
+            LogTarget::Syslog => {
+                // The daemon adds the time and level.
+                let encoder: Box<dyn Encode> = match format {
+                    LogFormat::Text => Box::new(PatternEncoder::new("{m}")),
+                    LogFormat::Json => Box::new(JsonEncoder),
+                };
+                destinations.push(SYSLOG_SOCKET.to_string());
+                Box::new(SyslogAppender::new(
+                    Path::new(SYSLOG_SOCKET),
+                    identifier,
+                    encoder,
+                ))
+            }
+            LogTarget::Journald => {
+                destinations.push(JOURNALD_SOCKET.to_string());
+                Box::new(JournaldAppender::new(
+                    Path::new(JOURNALD_SOCKET),
+                    identifier,
+                ))
+            }
+        };
+        let name = format!("{target:?}").to_lowercase();
+        config = config.appender(Appender::builder().build(&name, appender));
+        root = root.appender(name);
+    }
+
+    log4rs::init_config(config.build(root.build(logfilter))?)?;
+    Ok(destinations.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::logging::{
+        enter_stage, set_context, JsonEncoder, LogFormat, LogRotation, RotationTrigger,
+    };
+    use log::{Level, Record};
+    use log4rs::encode::pattern::PatternEncoder;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn json_line_test() {
+        assert_eq!("".parse::<LogFormat>().unwrap(), LogFormat::Text);
+        assert_eq!("JSON".parse::<LogFormat>().unwrap(), LogFormat::Json);
+        assert!("logfmt".parse::<LogFormat>().is_err());
+
+        set_context("dump_id", "5ad2ea44");
+        set_context("namespace", "mo");
+        enter_stage("core");
+        let line = JsonEncoder::line(
+            &Record::builder()
+                .args(format_args!("Capture failed"))
+                .level(Level::Error)
+                .target("core_dump_composer")
+                .build(),
+            UNIX_EPOCH + Duration::from_millis(1_706_263_200_500),
+        );
+        assert_eq!(line["timestamp"], "2024-01-26T10:00:00.500000000Z");
+        assert_eq!(line["level"], "ERROR");
+        assert_eq!(line["message"], "Capture failed");
+        assert_eq!(line["stage"], "core");
+        assert_eq!(line["namespace"], "mo");
+        assert_eq!(line["dump_id"], "5ad2ea44");
+    }
 
-    let config = Config::builder()
-        .appender(Appender::builder().build("logfile", Box::new(logfile)))
-        .build(Root::builder().appender("logfile").build(logfilter))?;
+    #[test]
+    fn rotation_test() {
+        let trigger = RotationTrigger {
+            max_bytes: 100,
+            max_age: Duration::from_secs(3600),
+        };
+        assert!(!trigger.due(100, Some(Duration::from_secs(60))));
+        assert!(trigger.due(101, None));
+        assert!(trigger.due(0, Some(Duration::from_secs(3601))));
+        let unlimited = RotationTrigger {
+            max_bytes: 0,
+            max_age: Duration::ZERO,
+        };
+        assert!(!unlimited.due(u64::MAX, Some(Duration::MAX)));
 
-    log4rs::init_config(config)?;
-    Ok(format!("{:?}", &log_path))
+        let dir = std::env::temp_dir().join(format!("logging-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let log_path = dir.join("composer.log");
+        let rotation = LogRotation {
+            max_bytes: 10,
+            max_age_hours: 0,
+            max_files: 2,
+        };
+        let appender = rotation
+            .appender(&log_path, Box::new(PatternEncoder::new("{m}\n")))
+            .unwrap();
+        for line in ["first line", "second line", "third line", "fourth"] {
+            appender
+                .append(&Record::builder().args(format_args!("{}", line)).build())
+                .unwrap();
+        }
+        // The lines over the limit were rolled over once written.
+        let read = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap();
+        assert_eq!(read("composer.log"), "fourth\n");
+        assert_eq!(read("composer.1.log"), "third line\n");
+        assert_eq!(read("composer.2.log"), "second line\n");
+        assert!(!dir.join("composer.3.log").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
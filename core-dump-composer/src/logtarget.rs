@@ -0,0 +1,238 @@
+use log::{Level, Record};
+use log4rs::append::Append;
+use log4rs::encode::writer::simple::SimpleWriter;
+use log4rs::encode::Encode;
+use serde::Serialize;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+
+pub const SYSLOG_SOCKET: &str = "/dev/log";
+pub const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+pub const DEFAULT_LOG_IDENTIFIER: &str = "core-dump-composer";
+/// LOG_DAEMON, the syslog facility the composer logs as.
+const FACILITY: u8 = 3;
+
+/// Where the composer's log goes, from the comma separated LOG_TARGETS.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogTarget {
+    /// composer.log next to the composer.
+    File,
+    /// The local syslog socket, /dev/log.
+    Syslog,
+    /// journald's native socket, which keeps the capture's context as
+    /// fields.
+    Journald,
+}
+
+impl FromStr for LogTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "file" => Ok(LogTarget::File),
+            "syslog" => Ok(LogTarget::Syslog),
+            "journald" | "journal" => Ok(LogTarget::Journald),
+            other => Err(anyhow::anyhow!("Unknown log target {other}")),
+        }
+    }
+}
+
+impl LogTarget {
+    /// Parses LOG_TARGETS, an empty list logs to the file.
+    pub fn parse_list(value: &str) -> Result<Vec<LogTarget>, anyhow::Error> {
+        let mut targets = vec![];
+        for target in value.split(',').filter(|t| !t.trim().is_empty()) {
+            let target = target.parse::<LogTarget>()?;
+            if !targets.contains(&target) {
+                targets.push(target);
+            }
+        }
+        if targets.is_empty() {
+            targets.push(LogTarget::File);
+        }
+        Ok(targets)
+    }
+
+    pub fn socket(&self) -> Option<&'static Path> {
+        match self {
+            LogTarget::File => None,
+            LogTarget::Syslog => Some(Path::new(SYSLOG_SOCKET)),
+            LogTarget::Journald => Some(Path::new(JOURNALD_SOCKET)),
+        }
+    }
+}
+
+/// The syslog severity of `level`.
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// Sends each line to the local syslog daemon as
+/// `<PRI>identifier[pid]: message`, leaving the time to the daemon.
+#[derive(Debug)]
+pub struct SyslogAppender {
+    socket: PathBuf,
+    identifier: String,
+    encoder: Box<dyn Encode>,
+}
+
+impl SyslogAppender {
+    pub fn new(socket: &Path, identifier: &str, encoder: Box<dyn Encode>) -> SyslogAppender {
+        SyslogAppender {
+            socket: socket.to_path_buf(),
+            identifier: identifier.to_string(),
+            encoder,
+        }
+    }
+
+    fn datagram(&self, record: &Record) -> anyhow::Result<Vec<u8>> {
+        let mut datagram = format!(
+            "<{}>{}[{}]: ",
+            FACILITY * 8 + severity(record.level()),
+            self.identifier,
+            process::id()
+        )
+        .into_bytes();
+        self.encoder
+            .encode(&mut SimpleWriter(&mut datagram), record)?;
+        while datagram.last() == Some(&b'\n') {
+            datagram.pop();
+        }
+        Ok(datagram)
+    }
+}
+
+impl Append for SyslogAppender {
+    fn append(&self, record: &Record) -> anyhow::Result<()> {
+        let socket = UnixDatagram::unbound()?;
+        socket.send_to(&self.datagram(record)?, &self.socket)?;
+        Ok(())
+    }
+
+    fn flush(&self) {}
+}
+
+/// Sends each line to journald over its native protocol, with the
+/// capture's context as `DUMP_ID`, `STAGE`, `NAMESPACE` and `POD` fields
+/// that `journalctl DUMP_ID=...` selects on.
+#[derive(Debug)]
+pub struct JournaldAppender {
+    socket: PathBuf,
+    identifier: String,
+}
+
+impl JournaldAppender {
+    pub fn new(socket: &Path, identifier: &str) -> JournaldAppender {
+        JournaldAppender {
+            socket: socket.to_path_buf(),
+            identifier: identifier.to_string(),
+        }
+    }
+
+    fn datagram(&self, record: &Record) -> Vec<u8> {
+        let mut datagram = vec![];
+        let mut field = |name: &str, value: &str| {
+            datagram.extend_from_slice(name.as_bytes());
+            if value.contains('\n') {
+                // Values with newlines are sent length prefixed.
+                datagram.push(b'\n');
+                datagram.extend_from_slice(&(value.len() as u64).to_le_bytes());
+            } else {
+                datagram.push(b'=');
+            }
+            datagram.extend_from_slice(value.as_bytes());
+            datagram.push(b'\n');
+        };
+        field("MESSAGE", &record.args().to_string());
+        field("PRIORITY", &severity(record.level()).to_string());
+        field("SYSLOG_IDENTIFIER", &self.identifier);
+        field("SYSLOG_FACILITY", &FACILITY.to_string());
+        field("TARGET", record.target());
+        for (key, value) in crate::logging::context() {
+            field(&key.to_uppercase(), &value);
+        }
+        datagram
+    }
+}
+
+impl Append for JournaldAppender {
+    fn append(&self, record: &Record) -> anyhow::Result<()> {
+        let socket = UnixDatagram::unbound()?;
+        socket.send_to(&self.datagram(record), &self.socket)?;
+        Ok(())
+    }
+
+    fn flush(&self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::logtarget::{JournaldAppender, LogTarget, SyslogAppender};
+    use log::{Level, Record};
+    use log4rs::append::Append;
+    use log4rs::encode::pattern::PatternEncoder;
+    use std::os::unix::net::UnixDatagram;
+    use std::path::Path;
+    use std::process;
+    use uuid::Uuid;
+
+    #[test]
+    fn parse_list_test() {
+        assert_eq!(LogTarget::parse_list("").unwrap(), vec![LogTarget::File]);
+        assert_eq!(
+            LogTarget::parse_list("file, Journal,file").unwrap(),
+            vec![LogTarget::File, LogTarget::Journald]
+        );
+        assert!(LogTarget::parse_list("syslog,kafka").is_err());
+    }
+
+    #[test]
+    fn appender_test() {
+        let path = std::env::temp_dir().join(format!("logtarget-test-{}.sock", Uuid::new_v4()));
+        let server = UnixDatagram::bind(&path).unwrap();
+        let mut buf = [0u8; 512];
+
+        let syslog = SyslogAppender::new(&path, "cdc", Box::new(PatternEncoder::new("{m}\n")));
+        syslog
+            .append(
+                &Record::builder()
+                    .args(format_args!("Capture failed"))
+                    .level(Level::Error)
+                    .build(),
+            )
+            .unwrap();
+        let len = server.recv(&mut buf).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&buf[..len]).unwrap(),
+            format!("<27>cdc[{}]: Capture failed", process::id())
+        );
+
+        let journald = JournaldAppender::new(&path, "cdc");
+        journald
+            .append(
+                &Record::builder()
+                    .args(format_args!("two\nlines"))
+                    .level(Level::Info)
+                    .target("core_dump_composer")
+                    .build(),
+            )
+            .unwrap();
+        let len = server.recv(&mut buf).unwrap();
+        let datagram = &buf[..len];
+        assert!(datagram.starts_with(b"MESSAGE\n\x09\0\0\0\0\0\0\0two\nlines\nPRIORITY=6\n"));
+        assert!(datagram.windows(21).any(|w| w == b"SYSLOG_IDENTIFIER=cdc"));
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(JournaldAppender::new(Path::new("/nonexistent.sock"), "cdc")
+            .append(&Record::builder().args(format_args!("lost")).build())
+            .is_err());
+    }
+}
@@ -1,52 +1,463 @@
+// get_dump_info has outgrown the json! macro's default limit.
+#![recursion_limit = "256"]
+
 extern crate dotenv;
 
-use crate::events::CoreEvent;
+use crate::budget::{Budget, Priority};
+use crate::bundle::{Bundle, StagingDir};
+use crate::capture::{CaptureResult, ExitCode, Failure, FailureReport, StageContext};
+use crate::events::{CoreEvent, EventFormat};
+use crate::host::SystemClock;
+use crate::split::CaptureUser;
 
+use crate::cri::CriClient;
+use crate::docker::DockerClient;
+use crate::runtime::{Runtime, RuntimeKind};
 use advisory_lock::{AdvisoryFileLock, FileLockMode};
+use anyhow::{anyhow, Context};
 use libcrio::Cli;
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use serde_json::json;
 use serde_json::Value;
 use std::env;
-use std::fs::{File, write, remove_dir_all, create_dir, create_dir_all};
+use std::fs::{File, OpenOptions};
 use std::io;
-use std::io::prelude::*;
+use std::io::Read;
+use std::path::{Path, PathBuf};
 use std::process;
 use std::sync::mpsc::channel;
 use std::thread;
-use std::time::Duration;
-use tar::Builder;
-use flate2::Compression;
-use flate2::write::GzEncoder;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+mod backtrace;
+mod budget;
+mod bundle;
+mod capture;
+mod cgroup;
+mod clock;
+mod collectors;
+mod compat;
+mod compression;
 mod config;
+mod configfile;
+mod cri;
+mod decision;
+mod delta;
+mod dictionary;
+mod diskspace;
+mod docker;
+mod elf;
+mod encrypt;
+mod environ;
 mod events;
+mod filter;
+mod fsdiff;
+mod host;
+mod journal;
 mod logging;
+mod logtarget;
+mod mappings;
+mod minidump;
+mod network;
+mod nodeinfo;
+mod npd;
+mod oom;
+mod podcache;
+mod podlogs;
+mod procinfo;
+mod proto;
+mod ratelimit;
+mod redact;
+mod resources;
+mod runtime;
+mod sandbox;
+mod selector;
+mod sequence;
+mod signature;
+mod slots;
+mod split;
+mod trace;
+mod upload;
+mod volumes;
+mod webhook;
+mod xattr;
 
 fn main() -> Result<(), anyhow::Error> {
+    // Prints --help and --version, or what is wrong with the arguments, and
+    // exits.
+    let matches = config::try_get_matches().unwrap_or_else(|e| {
+        if !e.use_stderr() {
+            e.exit();
+        }
+        let _ = e.print();
+        process::exit(ExitCode::Usage.code());
+    });
+    if let Some(matches) = matches.subcommand_matches("npd-check") {
+        process::exit(npd_check(matches)?);
+    }
     let (send, recv) = channel();
-    let cc = config::CoreConfig::new()?;
-    let recv_time: u64 = cc.timeout as u64;
+    let mut cc = config::CoreConfig::new().unwrap_or_else(|e| {
+        eprintln!("Error: {e:?}");
+        process::exit(ExitCode::Config.code());
+    });
+    if cc.sandbox {
+        // Before the capture thread starts, so it inherits the Landlock rules.
+        cc.sandbox_error = match sandbox::apply(&cc.sandbox_paths()) {
+            Ok(Some(_)) => None,
+            Ok(None) => Some("no Landlock in this kernel, seccomp only".to_string()),
+            Err(e) => Some(e.to_string()),
+        };
+    }
+    // A dry run keeps nothing to hand over to a worker.
+    let split_user = match (&cc.spool, cc.capture_user, cc.dry_run) {
+        (None, Some(user), false) => Some(user),
+        _ => None,
+    };
+    let spool = cc
+        .spool
+        .clone()
+        .filter(|_| !cc.keep_spool && cc.replay_of.is_none());
+    // The stub's spool is the worker's once it is started.
+    let stub_spool = split_user.map(|_| cc.get_capture_spool_dir());
+    let timeout = cc.timeout;
+    let dump_id = cc.get_dump_id();
+    // A dry run leaves no failure record either.
+    let directory = Some(PathBuf::from(&cc.params.directory)).filter(|_| !cc.dry_run);
     thread::spawn(move || {
-        let result = handle(cc);
+        let result = match split_user {
+            Some(user) => {
+                split_capture(cc, user).map_err(|e| Box::new(Failure::new(e, CaptureResult::new())))
+            }
+            None => handle(cc),
+        };
         send.send(result).unwrap();
     });
 
-    let result = recv.recv_timeout(Duration::from_secs(recv_time));
+    let result = recv.recv_timeout(Duration::from_secs(timeout as u64));
+    // A worker's spooled capture is done with whatever the outcome, unless kept
+    // for replay.
+    if let Some(dir) = spool {
+        if let Err(e) = std::fs::remove_dir_all(&dir) {
+            error!("Removing {} failed: {}", dir.display(), e);
+        }
+    }
+    if let Some(dir) = stub_spool.filter(|d| d.exists() && !matches!(result, Ok(Ok(())))) {
+        if let Err(e) = std::fs::remove_dir_all(&dir) {
+            error!("Removing {} failed: {}", dir.display(), e);
+        }
+    }
 
     match result {
-        Ok(inner_result) => inner_result,
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(failure)) => {
+            failure.log();
+            report_failure(&failure.report(&dump_id), directory.as_deref());
+            process::exit(failure.exit_code().code());
+        }
         Err(_error) => {
             error!("Timeout error during coredump processing.");
-            process::exit(32);
+            let stage = logging::context().remove("stage").unwrap_or_default();
+            report_failure(
+                &FailureReport::timeout(&dump_id, &stage, timeout),
+                directory.as_deref(),
+            );
+            process::exit(ExitCode::Timeout.code());
+        }
+    }
+}
+
+/// Leaves `{dump_id}.failure.json` in the core directory for monitoring.
+fn report_failure(report: &FailureReport, directory: Option<&Path>) {
+    let Some(directory) = directory else {
+        return;
+    };
+    match report.write(directory) {
+        Ok(path) => info!("Wrote {}", path.display()),
+        Err(e) => error!("Writing the failure record failed: {}", e),
+    }
+}
+
+/// Prints the node-problem-detector status line and returns its exit code.
+fn npd_check(matches: &clap::ArgMatches) -> Result<i32, anyhow::Error> {
+    let window: u64 = matches.value_of_t("window-minutes")?;
+    let threshold: usize = matches.value_of_t("threshold")?;
+    let log = match matches.value_of("decisions-log") {
+        Some(path) => PathBuf::from(path),
+        None => env::current_exe()?.with_file_name("decisions.log"),
+    };
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let status = npd::check_log(&log, now, window * 60, threshold);
+    println!("{}", status.message);
+    Ok(status.code)
+}
+
+/// The privileged half of a CAPTURE_USER capture: checks the pause file,
+/// the filters and the size of the core, spools what is to be kept with the
+/// process's /proc files, then leaves the rest to a worker running as the
+/// user so the kernel is released as soon as the core is on disk.
+fn split_capture(mut cc: config::CoreConfig, user: CaptureUser) -> Result<(), anyhow::Error> {
+    logging::init_logger(
+        cc.log_level.clone(),
+        cc.log_format,
+        &cc.log_targets,
+        &cc.log_identifier,
+        cc.log_rotation,
+    )
+    .stage("logger")?;
+    logging::set_context("dump_id", &cc.get_dump_id());
+    if let Some(e) = &cc.sandbox_error {
+        error!("Sandbox incomplete, {}", e);
+    }
+    if !admit(&mut cc) {
+        return Ok(());
+    }
+    let dir = cc.get_capture_spool_dir();
+    let proc_dir = cc
+        .proc_dir()
+        .ok_or_else(|| anyhow!("No host pid to capture"))
+        .stage("spool")?;
+    let mut input = io::stdin().lock();
+    let prefix = mappings::read_prefix(&mut input).stage("core")?;
+    cc.core_size = mappings::core_size(&prefix);
+    gate_core(&mut cc);
+    let mut manifest = split::Manifest {
+        uuid: cc.params.uuid,
+        args: env::args().collect(),
+        build_id: elf::read_build_id(&proc_dir.join("exe")).unwrap_or_else(|e| {
+            debug!("No build-id for {}: {}", proc_dir.display(), e);
+            None
+        }),
+        env: split::recorded_env(env::vars(), &cc.env_mask_patterns),
+        gates: Some(split::Gates {
+            decision: cc.params.decision.clone(),
+            paused: cc.paused,
+            core_size: cc.core_size,
+            core_limited: cc.core_limited,
+            disk_low: cc.disk_low,
+            truncated: false,
+        }),
+    };
+    split::spool(
+        &dir,
+        &proc_dir,
+        &mut manifest,
+        user,
+        &mut prefix.as_slice().chain(input),
+        // A paused core is left to the worker's metadata-only capture.
+        if cc.paused.is_some() {
+            0
+        } else {
+            cc.core_limit()
+        },
+    )
+    .stage("spool")?;
+    let pid = split::start_worker(&env::current_exe()?, &dir, user).stage("spool")?;
+    info!(
+        "Spooled {} for worker {} running as {}:{}",
+        cc.params.uuid, pid, user.uid, user.gid
+    );
+    Ok(())
+}
+
+/// The checks that need neither the pod nor the core, the pause file and
+/// the signal and exe filters, run by the stub of a split capture before it
+/// spools the core. Returns false once the core is skipped.
+fn admit(cc: &mut config::CoreConfig) -> bool {
+    let pause_file = cc
+        .pause_file
+        .as_ref()
+        .map(|p| p.display().to_string())
+        .unwrap_or_default();
+    match cc.check_paused() {
+        Some(config::PauseMode::Skip) => {
+            info!("Pause file present, skipping core {}", cc.params.uuid);
+            let decision = &mut cc.params.decision;
+            decision.check("pause", false, format!("{pause_file} present, mode skip"));
+            decision.outcome = decision::Outcome::Skipped;
+            cc.record_decision();
+            drain(cc);
+            return false;
+        }
+        Some(config::PauseMode::MetadataOnly) => {
+            info!("Pause file present, capturing metadata only");
+            let decision = &mut cc.params.decision;
+            decision.check(
+                "pause",
+                false,
+                format!("{pause_file} present, mode metadata-only"),
+            );
+            decision.outcome = decision::Outcome::MetadataOnly;
+        }
+        None => {
+            let detail = if pause_file.is_empty() {
+                "no pause file configured".to_string()
+            } else {
+                format!("{pause_file} absent")
+            };
+            cc.params.decision.check("pause", true, detail);
+        }
+    }
+    if !cc.signal_filter.is_empty() {
+        let (passed, detail) = cc.signal_filter.check(&cc.params.signal);
+        cc.params.decision.check("signal", passed, detail);
+        if !passed {
+            info!("Skipping core for signal {}", cc.params.signal);
+            cc.params.decision.outcome = decision::Outcome::Skipped;
+            cc.record_decision();
+            drain(cc);
+            return false;
+        }
+    }
+    if !cc.exe_filter.is_empty() {
+        let (passed, detail) = cc
+            .exe_filter
+            .check(&cc.params.exe_name, &cc.params.exe_path());
+        cc.params.decision.check("exe", passed, detail);
+        if !passed {
+            info!("Skipping core of {}", cc.params.exe_name);
+            cc.params.decision.outcome = decision::Outcome::Skipped;
+            cc.record_decision();
+            drain(cc);
+            return false;
         }
     }
+    true
 }
 
-fn handle(mut cc: config::CoreConfig) -> Result<(), anyhow::Error> {
+/// Holds the core back when it is over MAX_CORE_BYTES in skip mode or
+/// would leave less than DISK_RESERVE_PERCENT free, by the size of
+/// `cc.core_size`.
+fn gate_core(cc: &mut config::CoreConfig) {
+    if let Some(max) = cc.max_core_bytes {
+        let size = cc
+            .core_size
+            .map_or("of unknown size".to_string(), |s| format!("of {s} bytes"));
+        if cc.core_limit_mode == config::CoreLimitMode::Skip
+            && cc.core_size.is_some_and(|s| s > max)
+        {
+            info!("Skipping the core {} over MAX_CORE_BYTES", size);
+            cc.core_limited = Some(config::CoreLimitMode::Skip);
+            cc.params.decision.outcome = decision::Outcome::MetadataOnly;
+        }
+        cc.params.decision.check(
+            "core_size",
+            cc.core_limited.is_none(),
+            format!("core {size}, MAX_CORE_BYTES {max}"),
+        );
+    }
+    if cc.params.decision.outcome == decision::Outcome::Captured {
+        // The size from the program headers, else the core rlimit unless
+        // it is unlimited.
+        let needed = cc
+            .core_size
+            .or_else(|| {
+                cc.params
+                    .limit_size
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|l| *l != u64::MAX)
+            })
+            .map_or(0, |size| {
+                cc.max_core_bytes.map_or(size, |max| size.min(max))
+            });
+        // The stub of a split capture spools the core under the host
+        // directory first.
+        let spool_root = cc.capture_user.map(|_| cc.base_path.clone());
+        let volumes: Vec<diskspace::Volume> = [Path::new(&cc.params.directory), &cc.work_dir]
+            .into_iter()
+            .chain(spool_root.as_deref())
+            .filter_map(|dir| {
+                diskspace::Volume::of(dir)
+                    .map_err(|e| debug!("No free space of {}: {}", dir.display(), e))
+                    .ok()
+            })
+            .collect();
+        if !volumes.is_empty() {
+            let (passed, detail) = diskspace::check(&volumes, needed, cc.disk_reserve_percent);
+            if !passed {
+                info!("Skipping the core, {}", detail);
+                cc.disk_low = true;
+                cc.params.decision.outcome = decision::Outcome::MetadataOnly;
+            }
+            cc.params.decision.check("disk_space", passed, detail);
+        }
+    }
+}
+
+/// Evicts the least recently used delta bases over DELTA_MAX_BASES and
+/// DELTA_MAX_BASE_BYTES and checks a new base of `size` bytes leaves
+/// DISK_RESERVE_PERCENT free, on the core's disk on top of the core.
+fn make_room_for_base(
+    cc: &config::CoreConfig,
+    store: &delta::BaseStore,
+    size: u64,
+) -> Result<(), anyhow::Error> {
+    let evicted = store.make_room(cc.delta_max_bases, cc.delta_max_base_bytes, size)?;
+    if !evicted.is_empty() {
+        info!("Evicted the delta bases of {}", evicted.join(", "));
+    }
+    let volume = diskspace::Volume::of(store.dir())?;
+    let shared = diskspace::Volume::of(Path::new(&cc.params.directory))
+        .is_ok_and(|core| core.device == volume.device);
+    let needed = if shared { size.saturating_mul(2) } else { size };
+    let (passed, detail) = diskspace::check(&[volume], needed, cc.disk_reserve_percent);
+    if !passed {
+        return Err(anyhow!("No room for the delta base, {}", detail));
+    }
+    Ok(())
+}
+
+fn handle(cc: config::CoreConfig) -> Result<(), Box<Failure>> {
+    let trace = cc.trace.clone();
+    let uuid = cc.params.uuid;
+    let hostname = cc.os_hostname.clone();
+    let mut capture_result = CaptureResult::new();
+    let failure = match capture(cc, &mut capture_result) {
+        Ok(()) => None,
+        Err(e) => {
+            let result = std::mem::replace(&mut capture_result, CaptureResult::new());
+            Some(Box::new(Failure::new(e, result)))
+        }
+    };
+    if let Some(trace) = trace {
+        let result = failure
+            .as_ref()
+            .map(|f| &f.result)
+            .unwrap_or(&capture_result);
+        let body = trace::spans(
+            result,
+            &uuid,
+            &hostname,
+            failure.as_deref(),
+            SystemTime::now(),
+        );
+        if let Err(e) = trace.export(&body) {
+            error!("Exporting the capture spans failed: {}", e);
+        }
+    }
+    match failure {
+        Some(failure) => Err(failure),
+        None => Ok(()),
+    }
+}
+
+fn capture(
+    mut cc: config::CoreConfig,
+    capture_result: &mut CaptureResult,
+) -> Result<(), anyhow::Error> {
+    let budget = Budget::new(Duration::from_secs(cc.timeout as u64), !cc.ignore_crio);
+    cc.params.clock = Some(clock::read_clock_sanity(&cc.params.timestamp));
     cc.set_namespace("default".to_string());
     let l_log_level = cc.log_level.clone();
-    let log_path = logging::init_logger(l_log_level)?;
+    let log_path = logging::init_logger(
+        l_log_level,
+        cc.log_format,
+        &cc.log_targets,
+        &cc.log_identifier,
+        cc.log_rotation,
+    )
+    .stage("logger")?;
+    logging::set_context("dump_id", &cc.get_dump_id());
     debug!("Arguments: {:?}", env::args());
 
     info!(
@@ -54,7 +465,78 @@ fn handle(mut cc: config::CoreConfig) -> Result<(), anyhow::Error> {
         cc.ignore_crio, cc.image_command, cc.use_crio_config
     );
 
-    info!("Set logfile to: {:?}", &log_path);
+    info!("Logging to {}", &log_path);
+    if let Some(e) = &cc.sandbox_error {
+        error!("Sandbox incomplete, {}", e);
+        capture_result.record_error("sandbox", e);
+    }
+    if let Some(e) = &cc.compression_error {
+        error!("{}", e);
+        capture_result.record_error("compression", e);
+    }
+    if let Some(e) = &cc.config_file_error {
+        error!("Config file settings ignored, {}", e);
+        capture_result.record_error("config_file", e);
+    }
+    if let Some(e) = &cc.signal_filter_error {
+        error!("Signal filter ignored, {}", e);
+        capture_result.record_error("signals", e);
+    }
+    let gates = cc.manifest.as_ref().and_then(|m| m.gates.clone());
+    match &gates {
+        // The stub checked these before it spooled the core.
+        Some(gates) => {
+            cc.params.decision = gates.decision.clone();
+            cc.paused = gates.paused;
+            cc.core_limited = gates.core_limited;
+            cc.disk_low = gates.disk_low;
+        }
+        None if !admit(&mut cc) => return Ok(()),
+        None => {}
+    }
+    if let Some(e) = &cc.capture_user_error {
+        error!("Capturing as root, {}", e);
+        capture_result.record_error("capture_user", e);
+    }
+    let proc_dir = cc.proc_dir();
+    cc.container_identity = proc_dir
+        .as_deref()
+        .and_then(cgroup::read_container_identity);
+    debug!(
+        "Container identity from cgroup: {:?}",
+        cc.container_identity
+    );
+    if cc.container_identity.is_none() {
+        cc.systemd_unit = proc_dir.as_deref().and_then(cgroup::read_systemd_unit);
+        debug!("Host process in systemd unit {:?}", cc.systemd_unit);
+    }
+    // Before the runtime lookups, the process goes when the composer exits
+    // or a slow lookup runs into the timeout.
+    let proc_snapshot = match &proc_dir {
+        Some(dir) if cc.proc_snapshot => Some(procinfo::snapshot(dir, &cc.env_mask_patterns)),
+        _ => None,
+    };
+    if let Some(manifest) = &cc.manifest {
+        // The stub read it while the process was still there.
+        cc.build_id = manifest.build_id.clone();
+    } else if let Some(dir) = &proc_dir {
+        let exe = dir.join("exe");
+        cc.build_id = elf::read_build_id(&exe).unwrap_or_else(|e| {
+            debug!("No build-id for {}: {}", exe.display(), e);
+            None
+        });
+    }
+    let delta_store = delta::BaseStore::new(cc.get_delta_base_dir());
+    if cc.delta_cores && cc.encrypt.is_some() {
+        // The base would be a plaintext core on the node.
+        capture_result.record_error("delta", "Not stored with ENCRYPT_RECIPIENTS");
+        cc.delta_cores = false;
+    }
+    if cc.delta_cores {
+        if let Some(build_id) = &cc.build_id {
+            cc.delta_base = delta_store.get(build_id);
+        }
+    }
     debug!("Creating dump for {}", cc.get_templated_name());
 
     let l_crictl_config_path = cc.crictl_config_path.clone();
@@ -76,232 +558,984 @@ fn handle(mut cc: config::CoreConfig) -> Result<(), anyhow::Error> {
         config_path,
         image_command: l_image_command,
     };
-    let pod_object = cli.pod(&cc.params.hostname).unwrap_or_else(|e| {
-        error!("{}", e);
-        // We fall through here as the coredump and info can still be captured.
-        json!({})
+    let runtime = match cc.container_runtime {
+        RuntimeKind::Crictl => Runtime::Crictl(cli),
+        RuntimeKind::Cri => match CriClient::new(&cc.cri_endpoint) {
+            Ok(cri) => Runtime::Cri(cri),
+            Err(e) => {
+                error!("Falling back to crictl, {}", e);
+                capture_result.record_error("runtime", &e);
+                Runtime::Crictl(cli)
+            }
+        },
+        RuntimeKind::Docker => match DockerClient::new(&cc.docker_endpoint) {
+            Ok(docker) => Runtime::Docker(docker),
+            Err(e) => {
+                error!("Falling back to crictl, {}", e);
+                capture_result.record_error("runtime", &e);
+                Runtime::Crictl(cli)
+            }
+        },
+    };
+    let stage_start = logging::enter_stage("pod");
+    let cached = podcache::read(&cc.get_pod_cache_file()).and_then(|cache| {
+        podcache::lookup(
+            &cache,
+            cc.container_identity
+                .as_ref()
+                .map(|c| c.container_id.as_str()),
+            &cc.params.hostname,
+            SystemTime::now(),
+        )
     });
+    let pod_object = match cached {
+        Some(pod) => {
+            debug!("Pod found in the agent's pod cache");
+            pod
+        }
+        None => {
+            // The hostname is not the pod's with hostNetwork or a custom
+            // hostname, the cgroup names the container either way.
+            let by_container = cc
+                .container_identity
+                .as_ref()
+                .map(|c| runtime.container_pod(&c.container_id));
+            match by_container {
+                Some(Ok(pod)) => {
+                    debug!("Pod found through the crashing container");
+                    pod
+                }
+                other => {
+                    if let Some(Err(e)) = other {
+                        warn!("Looking up the pod by hostname, {}", e);
+                    }
+                    runtime.pod(&cc.params.hostname).unwrap_or_else(|e| {
+                        error!("{}", e);
+                        capture_result.record_error("pod", &e);
+                        // We fall through here as the coredump and info can still be captured.
+                        json!({})
+                    })
+                }
+            }
+        }
+    };
+    capture_result.record_duration("pod", stage_start);
+
+    let namespace = pod_object["metadata"]["namespace"]
+        .as_str()
+        .unwrap_or("unknown");
 
-    // match the label filter if there's one, and skip the whole process if it doesn't match
-    if !cc.pod_selector_label.is_empty() {
+    cc.set_namespace(namespace.to_string());
+
+    let podname = pod_object["metadata"]["name"].as_str().unwrap_or("unknown");
+
+    cc.set_podname(podname.to_string());
+    logging::set_context("namespace", namespace);
+    logging::set_context("pod", podname);
+
+    cc.params.unknown_pod = pod_object["metadata"]["name"].as_str().is_none();
+    if cc.params.unknown_pod {
+        match cc.unknown_pod_mode {
+            None => {
+                cc.params
+                    .decision
+                    .check("unknown_pod", true, "no pod found, UNKNOWN_POD capture")
+            }
+            Some(config::PauseMode::Skip) => {
+                info!("Skipping core as no pod was found for it");
+                let decision = &mut cc.params.decision;
+                decision.check("unknown_pod", false, "no pod found, UNKNOWN_POD skip");
+                decision.outcome = decision::Outcome::Skipped;
+                cc.record_decision();
+                drain(&cc);
+                return Ok(());
+            }
+            Some(mode) => {
+                info!("Capturing metadata only as no pod was found for the core");
+                cc.params.decision.check(
+                    "unknown_pod",
+                    false,
+                    "no pod found, UNKNOWN_POD metadata-only",
+                );
+                cc.unknown_pod = Some(mode);
+                cc.params.decision.outcome = decision::Outcome::MetadataOnly;
+            }
+        }
+    }
+
+    // the pod's own annotation wins over the node wide filters
+    let opt_in = filter::pod_opt_in(&pod_object).unwrap_or_else(|e| {
+        error!("Annotation ignored, {}", e);
+        capture_result.record_error("annotation", &e);
+        None
+    });
+    match opt_in {
+        Some(false) => {
+            info!(
+                "Skipping pod as it opted out with {}",
+                filter::ENABLED_ANNOTATION
+            );
+            let decision = &mut cc.params.decision;
+            decision.check(
+                "annotation",
+                false,
+                format!("pod opted out with {}", filter::ENABLED_ANNOTATION),
+            );
+            decision.outcome = decision::Outcome::Skipped;
+            cc.record_decision();
+            drain(&cc);
+            return Ok(());
+        }
+        Some(true) => cc.params.decision.check(
+            "annotation",
+            true,
+            format!("pod opted in with {}", filter::ENABLED_ANNOTATION),
+        ),
+        None => {}
+    }
+
+    // match the label selector if there's one, and skip the whole process if it doesn't match
+    if let Some(e) = &cc.pod_selector_error {
+        error!("Pod selector ignored, {}", e);
+        capture_result.record_error("pod_selector", e);
+    }
+    if opt_in == Some(true) {
+        debug!("Pod opted in, selector and namespace lists not checked");
+    } else if !cc.pod_selector.is_empty() {
         debug!(
-            "Pod selector specified. Will record only if pod has label {}",
-            &cc.pod_selector_label
+            "Pod selector specified. Will record only if pod labels match {}",
+            &cc.pod_selector
         );
-        let pod_labels = pod_object["labels"].as_object().unwrap();
-        // check if pod_labels has pod_selector_label
-        if pod_labels.get(&cc.pod_selector_label).is_none() {
+        let pod_labels = pod_object["labels"]
+            .as_object()
+            .cloned()
+            .unwrap_or_default();
+        if let Some(unmet) = cc.pod_selector.unmet(&pod_labels) {
             info!(
-                "Skipping pod as it did not match selector label {}",
-                &cc.pod_selector_label
+                "Skipping pod as its labels did not match selector {}",
+                &cc.pod_selector
             );
-            process::exit(0);
+            let decision = &mut cc.params.decision;
+            decision.check(
+                "pod_selector",
+                false,
+                format!("pod labels do not match {}", unmet),
+            );
+            decision.outcome = decision::Outcome::Skipped;
+            cc.record_decision();
+            drain(&cc);
+            return Ok(());
         }
+        cc.params.decision.check(
+            "pod_selector",
+            true,
+            format!("pod labels match {}", cc.pod_selector),
+        );
     } else {
         debug!("No pod selector specified, selecting all pods");
+        cc.params
+            .decision
+            .check("pod_selector", true, "no selector, all pods captured");
     }
 
-    let namespace = pod_object["metadata"]["namespace"]
-        .as_str()
-        .unwrap_or("unknown");
+    if !cc.namespace_filter.is_empty() && opt_in != Some(true) {
+        let (passed, detail) = cc.namespace_filter.check(namespace);
+        cc.params.decision.check("namespace", passed, detail);
+        if !passed {
+            info!("Skipping core from namespace {}", namespace);
+            cc.params.decision.outcome = decision::Outcome::Skipped;
+            cc.record_decision();
+            drain(&cc);
+            return Ok(());
+        }
+    }
 
-    cc.set_namespace(namespace.to_string());
+    if cc.dry_run {
+        return dry_run(cc, &runtime, &pod_object, capture_result);
+    }
 
-    let podname = pod_object["metadata"]["name"].as_str().unwrap_or("unknown");
+    // The program headers lead the core so they can be summarized into
+    // dump-info before the core itself is written. Its notes also hold the
+    // top of the stack for the signature.
+    let mut input: Box<dyn Read> = match &cc.spool {
+        Some(dir) => Box::new(File::open(dir.join(split::CORE_FILE)).stage("core")?),
+        None => Box::new(io::stdin().lock()),
+    };
+    let prefix = mappings::read_prefix(&mut input).stage("core")?;
+    let frame = signature::crash_frame(&prefix);
+    let crash_signature = signature::compute(
+        cc.build_id.as_deref(),
+        &cc.params.exe_name,
+        &cc.params.signal,
+        frame.as_deref(),
+    );
+    debug!("Signature {} at {:?}", crash_signature, frame);
+    cc.params.signature = Some(crash_signature.clone());
 
-    cc.set_podname(podname.to_string());
+    if let Some(window) = cc.dedup_window_minutes {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        match signature::check(
+            &cc.get_signature_file(),
+            &crash_signature,
+            &cc.get_dump_id(),
+            now,
+            window * 60,
+        ) {
+            Ok(None) => cc.params.decision.check(
+                "signature",
+                true,
+                format!("no capture of {crash_signature} in the last {window}m"),
+            ),
+            Ok(Some(duplicate)) => {
+                info!(
+                    "Skipping core with signature {}, captured as {}",
+                    crash_signature, duplicate.dump_id
+                );
+                cc.params.decision.check(
+                    "signature",
+                    false,
+                    format!(
+                        "{crash_signature} captured as {} {}s ago, {} crashes since, DEDUP_WINDOW_MINUTES {window}",
+                        duplicate.dump_id,
+                        now.saturating_sub(duplicate.captured),
+                        duplicate.count
+                    ),
+                );
+                cc.params.decision.outcome = decision::Outcome::Skipped;
+                cc.params.duplicate = Some(duplicate);
+                cc.record_decision();
+                drop(input);
+                drain(&cc);
+                // Only the count goes into the event stream, there's no
+                // archive to point at.
+                if cc.core_events {
+                    let evtdir = format!("{}", cc.event_location.display());
+                    let spool = cc.get_event_spool_dir();
+                    let evt = CoreEvent::new(cc.params, String::new(), pod_object, vec![])
+                        .redacted(&cc.redact);
+                    evt.deliver(&evtdir, &spool, cc.event_format);
+                }
+                return Ok(());
+            }
+            Err(e) => {
+                error!("Checking the crash signature failed: {}", e);
+                capture_result.record_error("signature", &e);
+            }
+        }
+    }
 
-    // Create the base tar file that we are going to put everything into
-    let file = match File::create(cc.get_tar_full_path()) {
-        Ok(v) => v,
-        Err(e) => {
-            error!("Failed to create file: {}", e);
-            process::exit(1);
+    // Held until the capture returns, the budget keeps half its time for
+    // the capture itself.
+    let _slot = match cc.max_concurrent_captures {
+        Some(max) => {
+            let start = Instant::now();
+            let wait = Duration::from_secs(cc.timeout as u64 / 2);
+            match slots::acquire(&cc.get_slots_dir(), max, wait, &SystemClock) {
+                Ok(Some(slot)) => {
+                    cc.params.decision.check(
+                        "concurrency",
+                        true,
+                        format!(
+                            "slot {} of MAX_CONCURRENT_CAPTURES {max} after {}ms",
+                            slot.index,
+                            start.elapsed().as_millis()
+                        ),
+                    );
+                    Some(slot)
+                }
+                Ok(None) => {
+                    info!(
+                        "Skipping core, all {} capture slots busy for {}s",
+                        max,
+                        wait.as_secs()
+                    );
+                    cc.params.decision.check(
+                        "concurrency",
+                        false,
+                        format!("MAX_CONCURRENT_CAPTURES {max} busy for {}s", wait.as_secs()),
+                    );
+                    cc.params.decision.outcome = decision::Outcome::Skipped;
+                    cc.record_decision();
+                    drop(input);
+                    drain(&cc);
+                    return Ok(());
+                }
+                Err(e) => {
+                    // Captures that can't see the slots would run unlimited.
+                    error!("Skipping core, taking a capture slot failed: {}", e);
+                    cc.params.decision.check(
+                        "concurrency",
+                        false,
+                        format!("MAX_CONCURRENT_CAPTURES {max} slot not taken: {e}"),
+                    );
+                    cc.params.decision.outcome = decision::Outcome::Skipped;
+                    cc.record_decision();
+                    drop(input);
+                    drain(&cc);
+                    return Ok(());
+                }
+            }
         }
+        None => None,
     };
-    file.lock(FileLockMode::Exclusive)?;
-    let mut tar_core = Builder::new(file);
 
-    match create_dir_all("/tmp/core") {
-        Ok(_) => println!("Folder is created successfully."),
-        Err(e) => println!("Error while creating folder: {}", e),
+    if let Some(max) = cc.max_dumps_per_hour {
+        let key = ratelimit::key(namespace, podname, &cc.params.exe_name);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        match ratelimit::admit(&cc.get_rate_limit_file(), &key, now, max) {
+            Ok((true, recent)) => {
+                cc.params.decision.check(
+                    "rate_limit",
+                    true,
+                    format!("{recent} of {max} captures of {key} in the last hour"),
+                );
+            }
+            Ok((false, recent)) => {
+                let mode = cc.rate_limit_mode;
+                let detail = format!(
+                    "{recent} captures of {key} in the last hour, MAX_DUMPS_PER_HOUR {max}, mode {}",
+                    match mode {
+                        config::PauseMode::MetadataOnly => "metadata-only",
+                        config::PauseMode::Skip => "skip",
+                    }
+                );
+                cc.params.decision.check("rate_limit", false, detail);
+                if mode == config::PauseMode::Skip {
+                    info!("Skipping core of {} over MAX_DUMPS_PER_HOUR", key);
+                    cc.params.decision.outcome = decision::Outcome::Skipped;
+                    cc.record_decision();
+                    drop(input);
+                    drain(&cc);
+                    return Ok(());
+                }
+                info!(
+                    "Capturing metadata only for {} over MAX_DUMPS_PER_HOUR",
+                    key
+                );
+                cc.rate_limited = Some(mode);
+                cc.params.decision.outcome = decision::Outcome::MetadataOnly;
+            }
+            Err(e) => {
+                error!("Checking the rate limit failed: {}", e);
+                capture_result.record_error("rate_limit", &e);
+            }
+        }
     }
 
-    debug!(
-        "Create a JSON file to store the dump meta data\n{}",
-        cc.get_dump_info_filename()
-    );
+    let pod_uid = pod_object["metadata"]["uid"].as_str().unwrap_or("unknown");
 
-    match write(format!("{}/{}","/tmp/core",cc.get_dump_info_filename()), cc.get_dump_info().as_bytes()) {
-        Ok(v) => v,
-        Err(e) => {
-            error!("Error starting dump file in temp file \n{}", e);
-            tar_core.finish()?;
-            // file.unlock()?;
-            remove_dir_all("/tmp/core").unwrap();
-            process::exit(1);
+    cc.set_pod_uid(pod_uid.to_string());
+
+    cc.params.sequence = sequence::next(&cc.get_sequence_file()).unwrap_or_else(|e| {
+        error!("Reading {} failed: {}", cc.get_sequence_file().display(), e);
+        capture_result.record_error("sequence", &e);
+        0
+    });
+
+    // Create the base tar file that we are going to put everything into.
+    // An existing archive is never overwritten, the name template has to
+    // tell captures apart.
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(cc.get_tar_full_path())
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::AlreadyExists => anyhow!(
+                "{} exists, refusing to overwrite it",
+                cc.get_tar_full_path()
+            ),
+            _ => anyhow::Error::from(e).context(format!("creating {}", cc.get_tar_full_path())),
+        })
+        .stage("archive")?;
+    capture::record_artifact(cc.get_tar_full_path());
+    AdvisoryFileLock::lock(&file, FileLockMode::Exclusive).stage("archive")?;
+    let staging = StagingDir::create(cc.get_staging_dir())
+        .with_context(|| format!("creating {}", cc.get_staging_dir().display()))
+        .stage("staging")?;
+    let mut bundle = match &cc.encrypt {
+        Some(recipients) => {
+            Bundle::encrypted(file, &recipients.command(), &cc.bin_path, staging.path())
+                .context("encrypting the archive")
+                .stage("archive")?
         }
+        None => Bundle::new(file),
     };
 
+    let maps = proc_dir
+        .as_ref()
+        .and_then(|d| std::fs::read_to_string(d.join("maps")).ok());
+    cc.mapping_summary = mappings::summarize(&prefix, maps.as_deref());
+    if let Some(snapshot) = &proc_snapshot {
+        let data = serde_json::to_vec(snapshot).stage("proc")?;
+        add_file(
+            &mut bundle,
+            &cc,
+            capture_result,
+            &cc.get_proc_filename(),
+            &data,
+        )?;
+        if let Some(maps) = &snapshot.maps {
+            add_file(
+                &mut bundle,
+                &cc,
+                capture_result,
+                &cc.get_maps_filename(),
+                maps.as_bytes(),
+            )?;
+        }
+    }
+    match &gates {
+        Some(gates) => cc.core_size = gates.core_size,
+        None => {
+            cc.core_size = mappings::core_size(&prefix);
+            gate_core(&mut cc);
+        }
+    }
+    let limit = cc.core_limit();
+    let mut core_stream = prefix.as_slice().chain(input).take(limit);
+    let mut core_copy = None;
+    let mut debugger = None;
+    let mut minidump = false;
 
-    // Pipe the core file to zip
-    let core_file = match File::create(format!("{}/{}.gz","/tmp/core",cc.get_core_filename())) {
-        Ok(v) => v,
-        Err(e) => {
-            error!("Failed to create core file: {}", e);
-            remove_dir_all("/tmp/core").unwrap();
-            process::exit(1);
+    if cc.paused.is_some() {
+        capture_result.record_error("core", "Not captured while the pause file exists");
+        if let Err(e) = io::copy(core_stream.get_mut(), &mut io::sink()) {
+            error!("Draining the paused core failed: {}", e);
         }
-    };
-    core_file.lock(FileLockMode::Exclusive)?;
-    let mut encoder = GzEncoder::new(&core_file, Compression::fast());
+    } else if cc.rate_limited.is_some() {
+        capture_result.record_error("core", "Not captured, over MAX_DUMPS_PER_HOUR");
+        if let Err(e) = io::copy(core_stream.get_mut(), &mut io::sink()) {
+            error!("Draining the rate limited core failed: {}", e);
+        }
+    } else if cc.unknown_pod.is_some() {
+        capture_result.record_error("core", "Not captured, no pod found with UNKNOWN_POD");
+        if let Err(e) = io::copy(core_stream.get_mut(), &mut io::sink()) {
+            error!("Draining the core of the unknown pod failed: {}", e);
+        }
+    } else if cc.disk_low {
+        capture_result.record_error(
+            "core",
+            "Not captured, it would have left less than DISK_RESERVE_PERCENT free",
+        );
+        if let Err(e) = io::copy(core_stream.get_mut(), &mut io::sink()) {
+            error!("Draining the core failed: {}", e);
+        }
+    } else if cc.core_limited.is_some() {
+        capture_result.record_error("core", "Not captured, larger than MAX_CORE_BYTES");
+        // Read to the end so the kernel isn't left writing into a closed pipe.
+        if let Err(e) = io::copy(core_stream.get_mut(), &mut io::sink()) {
+            error!("Draining the skipped core failed: {}", e);
+        }
+    } else {
+        // Pipe the core through the configured compression into the archive
+        let stage_start = logging::enter_stage("core");
+        let options = cc.compress_options();
+        let compression = cc.core_compression;
+        // The debugger and core2md need the core as a file, it is copied as
+        // it streams.
+        let copy_path = staging.path().join("copy.core");
+        if cc.backtrace && cc.spool.is_some() {
+            capture_result.record_error("backtrace", "Not read by a CAPTURE_USER worker");
+        } else if cc.backtrace && cc.encrypt.is_some() {
+            // The debugger and core2md would need a plaintext copy on disk.
+            capture_result.record_error("backtrace", "Not read with ENCRYPT_RECIPIENTS");
+        } else if cc.backtrace {
+            debugger = backtrace::Debugger::find(&cc.bin_path);
+            if debugger.is_none() {
+                capture_result.record_error("backtrace", "No gdb or eu-stack in the PATH");
+            }
+        }
+        if cc.core_format.minidump() && cc.spool.is_some() {
+            capture_result.record_error("minidump", "Not converted by a CAPTURE_USER worker");
+        } else if cc.core_format.minidump() && cc.encrypt.is_some() {
+            capture_result.record_error("minidump", "Not converted with ENCRYPT_RECIPIENTS");
+        } else if cc.core_format.minidump() {
+            minidump = minidump::find(&cc.bin_path);
+            if !minidump {
+                capture_result.record_error("minidump", "No core2md in the PATH");
+            }
+        }
+        let mut copy: Box<dyn io::Write> = Box::new(io::sink());
+        if debugger.is_some() || minidump {
+            match File::create(&copy_path) {
+                Ok(file) => {
+                    copy = Box::new(file);
+                    core_copy = Some(copy_path);
+                }
+                Err(e) => {
+                    let stage = if minidump { "minidump" } else { "backtrace" };
+                    capture_result.record_error(stage, &e);
+                    debugger = None;
+                    minidump = false;
+                }
+            }
+        }
+        // Without a minidump to replace it the core is kept.
+        let keep_core = cc.core_format.core() || !minidump;
+        // An encrypted archive takes an uncompressed core at the size from
+        // its program headers, streamed rather than spooled for the header.
+        let sized = cc
+            .core_size
+            .map(|size| size.min(limit))
+            .filter(|_| keep_core && bundle.is_encrypted())
+            .filter(|_| compression == compression::CoreCompression::None);
+        let mut core_reader = delta::TeeReader::new(&mut core_stream, copy);
+        let written = match (&cc.delta_base, &cc.build_id) {
+            _ if !keep_core => io::copy(&mut core_reader, &mut io::sink()),
+            _ if sized.is_some() => bundle.append_sized(
+                &cc.get_core_filename(),
+                sized.unwrap_or_default(),
+                &mut core_reader,
+            ),
+            (Some(base), _) => {
+                info!("Storing core as a delta against {}", base.dump_file);
+                // The delta encoder needs a seekable output, so only deltas
+                // are staged before they are compressed.
+                let delta_path = staging
+                    .path()
+                    .join(format!("{}.raw", cc.get_core_filename()));
+                delta::encode(
+                    &delta_store.core_path(&base.build_id),
+                    &mut core_reader,
+                    File::create(&delta_path).stage("delta")?,
+                )
+                .and_then(|stats| {
+                    debug!("Delta stats {:?}", stats);
+                    let mut raw = File::open(&delta_path)?;
+                    bundle.append_stream(&cc.get_core_filename(), |out| {
+                        compression.compress(&mut raw, out, &options)
+                    })
+                })
+            }
+            (None, Some(build_id)) if cc.delta_cores => {
+                let base = delta::DeltaBase {
+                    build_id: build_id.clone(),
+                    uuid: cc.params.uuid.to_string(),
+                    dump_file: cc.get_core_filename(),
+                };
+                let size = cc.core_size.map_or(0, |s| s.min(limit));
+                match make_room_for_base(&cc, &delta_store, size)
+                    .and_then(|_| delta_store.create(&base).map_err(anyhow::Error::from))
+                {
+                    Ok(base_file) => {
+                        capture::record_artifact(delta_store.core_path(build_id));
+                        info!("Keeping core as the delta base for build-id {}", build_id);
+                        let mut tee = delta::TeeReader::new(&mut core_reader, base_file);
+                        bundle.append_stream(&cc.get_core_filename(), |out| {
+                            compression.compress(&mut tee, out, &options)
+                        })
+                    }
+                    Err(e) => {
+                        error!("Failed to create delta base: {}", e);
+                        capture_result.record_error("delta", &e);
+                        bundle.append_stream(&cc.get_core_filename(), |out| {
+                            compression.compress(&mut core_reader, out, &options)
+                        })
+                    }
+                }
+            }
+            _ => bundle.append_stream(&cc.get_core_filename(), |out| {
+                compression.compress(&mut core_reader, out, &options)
+            }),
+        };
+        let read = written
+            .with_context(|| format!("writing {}", cc.get_core_filename()))
+            .stage("core")?;
+        if keep_core {
+            bundle.describe_core(
+                &cc.get_core_filename(),
+                cc.core_compression,
+                &cc.get_core_extension(),
+            );
+        }
+        drop(core_reader);
+        if let Some(size) = sized.filter(|size| read < *size) {
+            capture_result.record_error(
+                "core",
+                format!("{read} of {size} bytes read, padded with zeros"),
+            );
+        }
+        let truncated = gates.as_ref().is_some_and(|g| g.truncated);
+        if truncated || (core_stream.limit() == 0 && core_stream.get_mut().read(&mut [0u8])? > 0) {
+            info!("Core truncated at MAX_CORE_BYTES {}", limit);
+            cc.core_limited = Some(config::CoreLimitMode::Truncate);
+            capture_result.record_error("core", "Truncated at MAX_CORE_BYTES");
+        } else if sized.is_some() && core_stream.get_mut().read(&mut [0u8])? > 0 {
+            capture_result
+                .record_error("core", "Longer than its program headers, the rest dropped");
+            if let Err(e) = io::copy(core_stream.get_mut(), &mut io::sink()) {
+                error!("Draining the rest of the core failed: {}", e);
+            }
+        }
+        capture_result.record_duration("core", stage_start);
+    }
 
-    let stdin = io::stdin();
-    let mut stdin = stdin.lock();
+    if let Some((debugger, core_copy)) = debugger
+        .zip(core_copy.as_ref())
+        .filter(|_| budget.allows(capture_result, "backtrace", Priority::Proc))
+    {
+        let stage_start = logging::enter_stage("backtrace");
+        let proc_dir = format!("/proc/{}", cc.params.host_pid);
+        let command = debugger.command(
+            &format!("{proc_dir}/exe"),
+            &core_copy.to_string_lossy(),
+            &format!("{proc_dir}/root"),
+        );
+        let timeout = backtrace::TIMEOUT.min(budget.allowance(Priority::Proc));
+        match collectors::run(&command, timeout, &cc.bin_path) {
+            Ok(output) => {
+                cc.params.backtrace = Some(backtrace::truncate(&String::from_utf8_lossy(&output)));
+                add_file(
+                    &mut bundle,
+                    &cc,
+                    capture_result,
+                    &cc.get_backtrace_filename(),
+                    &output,
+                )?;
+            }
+            Err(e) => {
+                error!("Failed to read the backtrace: {}", e);
+                capture_result.record_error("backtrace", &e);
+            }
+        }
+        capture_result.record_duration("backtrace", stage_start);
+    }
 
-    match io::copy(&mut stdin, &mut encoder) {
-        Ok(v) => v,
-        Err(e) => {
-            error!("Error writing core file \n{}", e);
-            core_file.unlock();
-            remove_dir_all("/tmp/core").unwrap();
-            process::exit(1);
+    if let Some(core_copy) = core_copy.as_ref().filter(|_| minidump) {
+        let stage_start = logging::enter_stage("minidump");
+        if budget.allows(capture_result, "minidump", Priority::Proc) {
+            write_minidump(
+                &mut bundle,
+                &mut cc,
+                &prefix,
+                core_copy,
+                &budget,
+                capture_result,
+            )?;
         }
-    };
-    encoder.finish()?;
-    core_file.unlock()?;
+        if cc.minidump_file.is_none() && !cc.core_format.core() {
+            info!("Keeping the core as no minidump was written");
+            let options = cc.compress_options();
+            let compression = cc.core_compression;
+            let mut core = File::open(core_copy).stage("core")?;
+            bundle
+                .append_stream(&cc.get_core_filename(), |out| {
+                    compression.compress(&mut core, out, &options)
+                })
+                .with_context(|| format!("writing {}", cc.get_core_filename()))
+                .stage("core")?;
+        }
+        capture_result.record_duration("minidump", stage_start);
+    }
+    if let Some(core_copy) = core_copy {
+        if let Err(e) = std::fs::remove_file(&core_copy) {
+            debug!("Removing {}: {}", core_copy.display(), e);
+        }
+    }
+
+    if cc.capture_binaries && cc.spool.is_some() {
+        capture_result.record_error("binaries", "Not captured by a CAPTURE_USER worker");
+    } else if cc.capture_binaries
+        && cc.paused.is_none()
+        && cc.rate_limited.is_none()
+        && cc.unknown_pod.is_none()
+        && !cc.disk_low
+        && cc.core_limited != Some(config::CoreLimitMode::Skip)
+        && budget.allows(capture_result, "binaries", Priority::Proc)
+    {
+        let stage_start = logging::enter_stage("binaries");
+        copy_binaries(
+            &mut bundle,
+            &mut cc,
+            &prefix,
+            maps.as_deref(),
+            capture_result,
+        )?;
+        capture_result.record_duration("binaries", stage_start);
+    }
 
+    if cc.fs_diff && budget.allows(capture_result, "fs_diff", Priority::Proc) {
+        let stage_start = logging::enter_stage("fs_diff");
+        match proc_dir.as_deref().and_then(fsdiff::read_fs_diff) {
+            Some(diff) => {
+                debug!("Container changed {} files", diff.changes.len());
+                let data = serde_json::to_vec(&diff).stage("fs_diff")?;
+                add_file(
+                    &mut bundle,
+                    &cc,
+                    capture_result,
+                    &cc.get_fs_diff_filename(),
+                    &data,
+                )?;
+            }
+            None => {
+                capture_result.record_error("fs_diff", "No overlay root filesystem found");
+            }
+        }
+        capture_result.record_duration("fs_diff", stage_start);
+    }
+
+    if cc.capture_env != environ::CaptureEnv::Off
+        && budget.allows(capture_result, "environ", Priority::Proc)
+    {
+        let environ = proc_dir
+            .as_ref()
+            .map(|d| d.join("environ"))
+            .unwrap_or_default();
+        match std::fs::read(environ) {
+            Ok(data) => {
+                if let Some(vars) = environ::render(&data, cc.capture_env, &cc.env_mask_patterns) {
+                    add_file(
+                        &mut bundle,
+                        &cc,
+                        capture_result,
+                        &cc.get_environ_filename(),
+                        vars.as_bytes(),
+                    )?;
+                }
+            }
+            Err(e) => {
+                error!(
+                    "Failed to read the environment of {}: {}",
+                    cc.params.host_pid, e
+                );
+                capture_result.record_error("environ", &e);
+            }
+        }
+    }
+
+    let journal_unit = cc.systemd_unit.as_ref().filter(|_| cc.journal_minutes > 0);
+    if let Some(unit) =
+        journal_unit.filter(|_| budget.allows(capture_result, "journal", Priority::Proc))
+    {
+        let stage_start = logging::enter_stage("journal");
+        match journal::read(unit, cc.journal_minutes, &cc.bin_path) {
+            Ok(log) => {
+                capture_result.record_encoding(&cc.get_journal_filename(), &log);
+                add_file(
+                    &mut bundle,
+                    &cc,
+                    capture_result,
+                    &cc.get_journal_filename(),
+                    &log,
+                )?
+            }
+            Err(e) => {
+                error!("{}", e);
+                capture_result.record_error("journal", &e);
+            }
+        }
+        capture_result.record_duration("journal", stage_start);
+    }
+
+    if cc.node_info && budget.allows(capture_result, "node_info", Priority::Runtime) {
+        let stage_start = logging::enter_stage("node_info");
+        let info = nodeinfo::read(Path::new("/"), runtime.version());
+        let data = serde_json::to_vec_pretty(&info).stage("node_info")?;
+        add_file(
+            &mut bundle,
+            &cc,
+            capture_result,
+            &cc.get_node_info_filename(),
+            &data,
+        )?;
+        capture_result.record_duration("node_info", stage_start);
+    }
+
+    if cc.dmesg_lines > 0 && budget.allows(capture_result, "dmesg", Priority::Proc) {
+        let stage_start = logging::enter_stage("dmesg");
+        match nodeinfo::dmesg(cc.dmesg_lines, &cc.bin_path) {
+            Ok(log) => {
+                capture_result.record_encoding(&cc.get_dmesg_filename(), &log);
+                add_file(
+                    &mut bundle,
+                    &cc,
+                    capture_result,
+                    &cc.get_dmesg_filename(),
+                    &log,
+                )?
+            }
+            Err(e) => {
+                error!("Reading the kernel log failed: {}", e);
+                capture_result.record_error("dmesg", &e);
+            }
+        }
+        capture_result.record_duration("dmesg", stage_start);
+    }
+
+    if let Some(e) = &cc.collectors_error {
+        error!("Collectors not run, {}", e);
+        capture_result.record_error("collectors", e);
+    }
+    if cc.spool.is_some() && !cc.collectors.collectors.is_empty() {
+        // They need the live process.
+        capture_result.record_error("collectors", "Not run by a CAPTURE_USER worker");
+    } else {
+        run_collectors(&mut bundle, &cc, &budget, capture_result)?;
+    }
 
     if cc.ignore_crio {
-        if cc.core_events {
-            let tar_name = format!("{}.tar", cc.get_templated_name());
+        finish(&mut bundle, &mut cc, capture_result)?;
+        let stage_start = logging::enter_stage("upload");
+        upload(&cc);
+        capture_result.record_duration("upload", stage_start);
+        // file.unlock()?;
+        cc.record_decision();
+        if cc.core_events || cc.webhook.is_some() {
+            let stage_start = logging::enter_stage("events");
+            let tar_name = cc.get_archive_key();
             let evtdir = format!("{}", cc.event_location.display());
-            let evt = CoreEvent::new_no_crio(cc.params, tar_name);
-            evt.write_event(&evtdir)?;
+            let spool = cc.get_event_spool_dir();
+            let evt = CoreEvent::new_no_crio(cc.params, tar_name).redacted(&cc.redact);
+            if cc.core_events {
+                evt.deliver(&evtdir, &spool, cc.event_format);
+            }
+            notify(&evt, cc.webhook.as_ref());
+            capture_result.record_duration("events", stage_start);
         }
-        tar_core.append_dir_all("core","/tmp/core").unwrap();
-        tar_core.finish()?;
-        remove_dir_all("/tmp/core").unwrap();
-        // file.unlock()?;
-        process::exit(0);
+        return Ok(());
     }
 
     debug!("Using runtime_file_name:{}", cc.get_pod_filename());
-
-    match write(format!("{}/{}","/tmp/core",cc.get_pod_filename()), pod_object.to_string().as_bytes()) {
-        Ok(v) => v,
-        Err(e) => {
-            error!("Error starting dump file in temp file \n{}", e);
-            tar_core.finish()?;
-            // file.unlock()?;
-            process::exit(1);
-        }
-    };
+    add_file(
+        &mut bundle,
+        &cc,
+        capture_result,
+        &cc.get_pod_filename(),
+        pod_object.to_string().as_bytes(),
+    )?;
 
     // TODO: Check logging of more than one pod retured
-    let pod_id = match pod_object["id"].as_str() {
-        Some(v) => v,
-        None => {
-            error!("Failed to get pod id");
-            tar_core.finish()?;
-            // file.unlock()?;
-            remove_dir_all("/tmp/core").unwrap();
-            process::exit(1);
-        }
-    };
+    let pod_id = pod_object["id"]
+        .as_str()
+        .ok_or_else(|| anyhow!("No pod id in the crictl pods output"))
+        .stage("pod")?;
 
     // With the pod_id get the runtime information from crictl
     debug!("Getting inspectp output using pod_id:{}", pod_id);
 
-    let inspectp = cli.inspect_pod(pod_id).unwrap_or_else(|e| {
-        error!("Failed to inspect pod {}", e);
+    let stage_start = logging::enter_stage("inspectp");
+    let inspectp = if budget.allows(capture_result, "inspectp", Priority::Runtime) {
+        let inspectp = runtime.inspect_pod(pod_id).unwrap_or_else(|e| {
+            error!("Failed to inspect pod {}", e);
+            capture_result.record_error("inspectp", &e);
+            json!({})
+        });
+        capture_result.record_duration("inspectp", stage_start);
+        inspectp
+    } else {
         json!({})
-    });
-    debug!("Starting inspectp file\n{}", cc.get_inspect_pod_filename());
-
-    match write(format!("{}/{}","/tmp/core",cc.get_inspect_pod_filename()), cc.get_inspect_pod_filename()) {
-        Ok(v) => v,
-        Err(e) => {
-            error!("Error starting dump file in temp file \n{}", e);
-            tar_core.finish()?;
-            // file.unlock()?;
-            remove_dir_all("/tmp/core").unwrap();
-            process::exit(1);
-        }
     };
-
+    cc.params.volumes = volumes::from_inspect(&inspectp);
+    cc.params.network = Some(network::from_inspect(&inspectp, cc.node_ip.clone()));
+    debug!("Starting inspectp file\n{}", cc.get_inspect_pod_filename());
+    add_file(
+        &mut bundle,
+        &cc,
+        capture_result,
+        &cc.get_inspect_pod_filename(),
+        inspectp.to_string().as_bytes(),
+    )?;
 
     // Get the container_image_name based on the pod_id
-    let ps_object = match cli.pod_containers(pod_id) {
-        Ok(v) => v,
-        Err(e) => {
-            error!("{}", e);
-            tar_core.finish()?;
-            // file.unlock()?;
-            remove_dir_all("/tmp/core").unwrap();
-            process::exit(1);
-        }
-    };
+    let stage_start = logging::enter_stage("ps");
+    let ps_object = runtime
+        .pod_containers(pod_id)
+        .map_err(anyhow::Error::msg)
+        .stage("ps")?;
 
     debug!("Starting ps file \n{}", cc.get_ps_filename());
-    match write(format!("{}/{}","/tmp/core",cc.get_ps_filename()), ps_object.to_string().as_bytes()) {
-        Ok(v) => v,
-        Err(e) => {
-            error!("Error starting dump file in temp file \n{}", e);
-            tar_core.finish()?;
-            // file.unlock()?;
-            remove_dir_all("/tmp/core").unwrap();
-            process::exit(1);
-        }
-    };
+    add_file(
+        &mut bundle,
+        &cc,
+        capture_result,
+        &cc.get_ps_filename(),
+        ps_object.to_string().as_bytes(),
+    )?;
+
+    capture_result.record_duration("ps", stage_start);
 
     // this still have bug, please do not use it
     debug!("Successfully got the process details {}", ps_object);
+    let stage_start = logging::enter_stage("containers");
     let mut images: Vec<Value> = vec![];
     if let Some(containers) = ps_object["containers"].as_array() {
-        for (counter, container) in containers.iter().enumerate() {
-            let img_ref = match container["imageRef"].as_str() {
+        let crashed = cc.container_identity.as_ref().and_then(|identity| {
+            containers
+                .iter()
+                .find(|c| c["id"].as_str() == Some(identity.container_id.as_str()))
+        });
+        let containers: Vec<&Value> = match (cc.container_scope, crashed) {
+            (config::ContainerScope::Container, Some(crashed)) => vec![crashed],
+            (config::ContainerScope::Container, None) => {
+                debug!("Crashing container not among the pod's, capturing them all");
+                containers.iter().collect()
+            }
+            (config::ContainerScope::Pod, _) => containers.iter().collect(),
+        };
+        for (counter, container) in containers.into_iter().enumerate() {
+            if !budget.allows(capture_result, "containers", Priority::Runtime) {
+                break;
+            }
+            let img_ref = match runtime::image_ref(container) {
                 Some(v) => v,
                 None => {
                     error!("Failed to get containerid {}", "");
+                    capture_result.record_error("containers", "Failed to get imageRef");
                     break;
                 }
             };
-            let log =
-                cli.tail_logs(container["id"].as_str().unwrap_or_default(), cc.log_length).unwrap_or_else(|e| {
+            let log = runtime
+                .logs(
+                    container["id"].as_str().unwrap_or_default(),
+                    cc.log_length,
+                    staging.path(),
+                )
+                .unwrap_or_else(|e| {
                     error!("Error finding logs:\n{}", e);
-                    "".to_string()
+                    capture_result.record_error("logs", &e);
+                    vec![]
                 });
             debug!("Starting log file \n{}", cc.get_log_filename(counter));
-            match write(format!("{}/{}","/tmp/core",cc.get_log_filename(counter)), log.to_string().as_bytes()) {
-                Ok(v) => v,
-                Err(e) => {
-                    error!("Error starting dump file in temp file \n{}", e);
-                    tar_core.finish()?;
-                    // file.unlock()?;
-                    remove_dir_all("/tmp/core").unwrap();
-                    process::exit(1);
-                }
-            };
+            capture_result.record_encoding(&cc.get_log_filename(counter), &log);
+            add_file(
+                &mut bundle,
+                &cc,
+                capture_result,
+                &cc.get_log_filename(counter),
+                &log,
+            )?;
+            if cc.pod_log_files {
+                copy_node_log(
+                    &mut bundle,
+                    &cc,
+                    &pod_object,
+                    container,
+                    counter,
+                    capture_result,
+                )?;
+            }
             debug!("found img_id {}", img_ref);
-            let image = cli.image(img_ref).unwrap_or_else(|e| {
+            let image = runtime.image(img_ref).unwrap_or_else(|e| {
                 error!("Error finding image:\n{}", e);
+                capture_result.record_error("images", &e);
                 json!({})
             });
 
             let img_clone = image.clone();
             images.push(img_clone);
             debug!("Starting image file \n{}", cc.get_image_filename(counter));
-            match write(format!("{}/{}","/tmp/core",cc.get_image_filename(counter)), image.to_string().as_bytes()) {
-                Ok(v) => v,
-                Err(e) => {
-                    error!("Error starting dump file in temp file \n{}", e);
-                    tar_core.finish()?;
-                    // file.unlock()?;
-                    remove_dir_all("/tmp/core").unwrap();
-                    process::exit(1);
-                }
-            };
+            add_file(
+                &mut bundle,
+                &cc,
+                capture_result,
+                &cc.get_image_filename(counter),
+                image.to_string().as_bytes(),
+            )?;
 
             debug!(
                 "Getting logs for container id {}",
@@ -309,19 +1543,525 @@ fn handle(mut cc: config::CoreConfig) -> Result<(), anyhow::Error> {
             );
         }
     };
+    capture_result.record_duration("containers", stage_start);
 
-    tar_core.append_dir_all("core","/tmp/core").unwrap();
-    tar_core.finish()?;
-    match remove_dir_all("/tmp/core") {
-        Ok(_) => println!("Folder is deleted successfully."),
-        Err(e) => println!("Error while deleting folder: {}", e),
+    if budget.allows(capture_result, "resources", Priority::Runtime) {
+        let stage_start = logging::enter_stage("resources");
+        let inspect = cc
+            .container_identity
+            .as_ref()
+            .and_then(|identity| runtime.inspect_container(&identity.container_id))
+            .unwrap_or_default();
+        cc.params.resources = resources::from_inspect(&inspect, &inspectp);
+        capture_result.record_duration("resources", stage_start);
     }
+
+    if budget.allows(capture_result, "oom", Priority::Runtime) {
+        let stage_start = logging::enter_stage("oom");
+        let oom = oom::correlate(&runtime, pod_id, proc_dir.as_deref(), &cc.params.timestamp);
+        if oom.oom_correlated {
+            info!("Crash is OOM correlated {:?}", oom);
+        }
+        cc.params.oom = Some(oom);
+        capture_result.record_duration("oom", stage_start);
+    }
+
+    finish(&mut bundle, &mut cc, capture_result)?;
+    let stage_start = logging::enter_stage("upload");
+    upload(&cc);
+    capture_result.record_duration("upload", stage_start);
     // file.unlock()?;
-    if cc.core_events {
-        let tar_name = format!("{}.tar", cc.get_templated_name());
+    cc.record_decision();
+    if cc.core_events || cc.webhook.is_some() {
+        let stage_start = logging::enter_stage("events");
+        let tar_name = cc.get_archive_key();
         let evtdir = format!("{}", cc.event_location.display());
-        let evt = CoreEvent::new(cc.params, tar_name, pod_object, images);
-        evt.write_event(&evtdir)?;
+        let spool = cc.get_event_spool_dir();
+        let evt = CoreEvent::new(cc.params, tar_name, pod_object, images).redacted(&cc.redact);
+        if cc.core_events {
+            evt.deliver(&evtdir, &spool, cc.event_format);
+        }
+        notify(&evt, cc.webhook.as_ref());
+        capture_result.record_duration("events", stage_start);
+    }
+    Ok(())
+}
+
+/// Logs the archive the capture would write and the runtime output that
+/// would go into it, then discards the core. The signature, rate limit and
+/// concurrency checks record each capture so they are left out.
+fn dry_run(
+    mut cc: config::CoreConfig,
+    runtime: &Runtime,
+    pod_object: &Value,
+    capture_result: &mut CaptureResult,
+) -> Result<(), anyhow::Error> {
+    let mut input: Box<dyn Read> = match &cc.spool {
+        Some(dir) => Box::new(File::open(dir.join(split::CORE_FILE)).stage("core")?),
+        None => Box::new(io::stdin().lock()),
+    };
+    let prefix = mappings::read_prefix(&mut input).stage("core")?;
+    drop(input);
+    cc.core_size = mappings::core_size(&prefix);
+    let frame = signature::crash_frame(&prefix);
+    cc.params.signature = Some(signature::compute(
+        cc.build_id.as_deref(),
+        &cc.params.exe_name,
+        &cc.params.signal,
+        frame.as_deref(),
+    ));
+    info!(
+        "Dry run, would write {} as {} with {} of {}",
+        cc.get_tar_full_path(),
+        cc.get_archive_key(),
+        cc.get_core_filename(),
+        cc.core_size
+            .map_or("unknown size".to_string(), |s| format!("{s} bytes"))
+    );
+    if !cc.ignore_crio {
+        if let Some(pod_id) = pod_object["id"].as_str() {
+            info!("Dry run, would add {}", cc.get_pod_filename());
+            let stage_start = logging::enter_stage("inspectp");
+            match runtime.inspect_pod(pod_id) {
+                Ok(_) => info!("Dry run, would add {}", cc.get_inspect_pod_filename()),
+                Err(e) => {
+                    error!("Failed to inspect pod {}", e);
+                    capture_result.record_error("inspectp", &e);
+                }
+            }
+            capture_result.record_duration("inspectp", stage_start);
+            let stage_start = logging::enter_stage("ps");
+            match runtime.pod_containers(pod_id) {
+                Ok(ps_object) => {
+                    info!("Dry run, would add {}", cc.get_ps_filename());
+                    let containers = ps_object["containers"]
+                        .as_array()
+                        .cloned()
+                        .unwrap_or_default();
+                    for (counter, container) in containers.iter().enumerate() {
+                        let image = runtime::image_ref(container)
+                            .ok_or_else(|| "Failed to get imageRef".to_string())
+                            .and_then(|img_ref| runtime.image(img_ref));
+                        if let Err(e) = image {
+                            error!("Error finding image:\n{}", e);
+                            capture_result.record_error("images", &e);
+                        }
+                        info!(
+                            "Dry run, would add {} and {} of container {}",
+                            cc.get_log_filename(counter),
+                            cc.get_image_filename(counter),
+                            container["metadata"]["name"].as_str().unwrap_or_default()
+                        );
+                    }
+                }
+                Err(e) => {
+                    error!("Failed to list the pod's containers {}", e);
+                    capture_result.record_error("ps", &e);
+                }
+            }
+            capture_result.record_duration("ps", stage_start);
+        }
+    }
+    let decision = &mut cc.params.decision;
+    decision.check("dry_run", false, "DRY_RUN, core discarded");
+    decision.outcome = decision::Outcome::Skipped;
+    cc.record_decision();
+    drain(&cc);
+    Ok(())
+}
+
+/// Reads the rest of a skipped core without keeping it, when DRAIN_SKIPPED
+/// is set.
+fn drain(cc: &config::CoreConfig) {
+    if !cc.drain_skipped {
+        return;
+    }
+    if let Err(e) = io::copy(&mut io::stdin().lock(), &mut io::sink()) {
+        error!("Draining the skipped core failed: {}", e);
+    }
+}
+
+/// Runs the collectors of COLLECTORS_FILE in order. Each gets its own
+/// timeout or what is left of the shared budget, whichever is shorter, and
+/// none run into the time the capture keeps for the runtime metadata.
+fn run_collectors(
+    bundle: &mut Bundle,
+    cc: &config::CoreConfig,
+    capture: &Budget,
+    capture_result: &mut CaptureResult,
+) -> Result<(), anyhow::Error> {
+    let budget = Duration::from_secs(cc.collectors.budget_secs);
+    let start = Instant::now();
+    let vars = [
+        ("pid", cc.params.host_pid.as_str()),
+        ("exe", cc.params.exe_name.as_str()),
+        (
+            "namespace",
+            cc.params.namespace.as_deref().unwrap_or("unknown"),
+        ),
+        ("podname", cc.params.podname.as_deref().unwrap_or("unknown")),
+    ];
+    for collector in &cc.collectors.collectors {
+        let stage = format!("collector:{}", collector.name);
+        let remaining = budget.saturating_sub(start.elapsed());
+        if remaining.is_zero() {
+            capture_result.record_error(&stage, "collector budget exhausted");
+            continue;
+        }
+        if !capture.allows(capture_result, &stage, Priority::Analyzers) {
+            continue;
+        }
+        let timeout = remaining
+            .min(capture.allowance(Priority::Analyzers))
+            .min(Duration::from_secs(collector.timeout_secs));
+        let command: Vec<String> = collector
+            .command
+            .iter()
+            .map(|arg| collectors::render(arg, &vars))
+            .collect();
+        let stage_start = logging::enter_stage(&stage);
+        match collectors::run(&command, timeout, &cc.bin_path) {
+            Ok(output) => {
+                let name = cc.get_collector_filename(&collector.name);
+                capture_result.record_encoding(&name, &output);
+                add_file(bundle, cc, capture_result, &name, &output)?;
+            }
+            Err(e) => {
+                error!("Collector {} failed: {}", collector.name, e);
+                capture_result.record_error(&stage, &e);
+            }
+        }
+        capture_result.record_duration(&stage, stage_start);
+    }
+    Ok(())
+}
+
+/// Appends a metadata file to the archive, compressed with the shared
+/// dictionary when one is configured.
+fn add_file(
+    bundle: &mut Bundle,
+    cc: &config::CoreConfig,
+    capture_result: &mut CaptureResult,
+    name: &str,
+    data: &[u8],
+) -> Result<(), anyhow::Error> {
+    // The environ file too, REDACT_PATTERNS applies on top of CAPTURE_ENV.
+    let redacted = if name.ends_with(".json") {
+        cc.redact.redact_json(data)
+    } else {
+        None
+    };
+    let data = redacted.as_deref().unwrap_or(data);
+    let mut entry = (name.to_string(), None);
+    if let Some(dictionary) = &cc.zstd_dictionary {
+        let skip = vec![
+            cc.get_dump_info_filename(),
+            cc.get_native_dump_info_filename(),
+            cc.get_handler_config_filename(),
+            cc.get_core_filename(),
+        ];
+        if dictionary::is_candidate(name, &skip) {
+            match dictionary::compress(data, dictionary, &cc.bin_path) {
+                Ok(compressed) => entry = (format!("{name}.zst"), Some(compressed)),
+                Err(e) => {
+                    // The file is stored uncompressed.
+                    error!("{}", e);
+                    capture_result.record_error("dictionary", &e);
+                }
+            }
+        }
+    }
+    let (name, compressed) = entry;
+    bundle
+        .append(&name, compressed.as_deref().unwrap_or(data))
+        .with_context(|| format!("adding {name}"))
+        .stage("archive")
+}
+
+/// Converts the staged copy of the core into `-minidump.dmp` with core2md
+/// and lists its modules in `-modules.json`.
+fn write_minidump(
+    bundle: &mut Bundle,
+    cc: &mut config::CoreConfig,
+    prefix: &[u8],
+    core_copy: &Path,
+    budget: &Budget,
+    capture_result: &mut CaptureResult,
+) -> Result<(), anyhow::Error> {
+    let proc_dir = format!("/proc/{}", cc.params.host_pid);
+    let modules = minidump::modules(prefix, &Path::new(&proc_dir).join("root"));
+    let data = serde_json::to_vec_pretty(&modules).stage("minidump")?;
+    add_file(
+        bundle,
+        cc,
+        capture_result,
+        &cc.get_modules_filename(),
+        &data,
+    )?;
+    let out = core_copy.with_extension("dmp");
+    let command = minidump::command(
+        &core_copy.to_string_lossy(),
+        &proc_dir,
+        &out.to_string_lossy(),
+    );
+    let timeout = minidump::TIMEOUT.min(budget.allowance(Priority::Proc));
+    match collectors::run(&command, timeout, &cc.bin_path).and_then(|_| Ok(std::fs::read(&out)?)) {
+        Ok(data) => {
+            add_file(
+                bundle,
+                cc,
+                capture_result,
+                &cc.get_minidump_filename(),
+                &data,
+            )?;
+            cc.minidump_file = Some(cc.get_minidump_filename());
+        }
+        Err(e) => {
+            error!("Failed to write the minidump: {}", e);
+            capture_result.record_error("minidump", &e);
+        }
+    }
+    if let Err(e) = std::fs::remove_file(&out) {
+        debug!("Removing {}: {}", out.display(), e);
+    }
+    Ok(())
+}
+
+/// Copies the executable and the shared libraries it had mapped into the
+/// sysroot directory of the archive, at their paths in the process's root.
+/// A file that can't be read is recorded and skipped.
+fn copy_binaries(
+    bundle: &mut Bundle,
+    cc: &mut config::CoreConfig,
+    prefix: &[u8],
+    maps: Option<&str>,
+    capture_result: &mut CaptureResult,
+) -> Result<(), anyhow::Error> {
+    let proc_dir = format!("/proc/{}", cc.params.host_pid);
+    cc.exe_path = std::fs::read_link(format!("{proc_dir}/exe"))
+        .ok()
+        .map(|p| p.to_string_lossy().to_string());
+    // The maps are gone once the process is reaped, the core's NT_FILE
+    // note lists the same files.
+    let files = match maps {
+        Some(maps) => mappings::executable_files(maps),
+        None => mappings::note_files(prefix),
+    };
+    if files.is_empty() {
+        capture_result.record_error(
+            "binaries",
+            "No maps or NT_FILE note to find the binaries in",
+        );
+        return Ok(());
+    }
+    for path in files {
+        let source = format!("{proc_dir}/root{path}");
+        let mut file = match File::open(&source) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("Failed to open {}: {}", path, e);
+                capture_result.record_error("binaries", format!("{path}: {e}"));
+                continue;
+            }
+        };
+        let name = format!("{}{}", cc.get_sysroot_dirname(), path);
+        let size = file.metadata().map(|m| m.len()).unwrap_or_default();
+        bundle
+            .append_sized(&name, size, &mut file)
+            .with_context(|| format!("adding {name}"))
+            .stage("archive")?;
+        let build_id = elf::read_build_id(Path::new(&source)).unwrap_or_else(|e| {
+            debug!("No build-id for {}: {}", path, e);
+            None
+        });
+        cc.binaries.push(config::Binary { path, build_id });
+    }
+    Ok(())
+}
+
+/// dump-info is written last so it holds everything learned during the
+/// capture, followed by the capture result and the manifest.
+fn finish(
+    bundle: &mut Bundle,
+    cc: &mut config::CoreConfig,
+    capture_result: &mut CaptureResult,
+) -> Result<(), anyhow::Error> {
+    // Only the trace sees this stage, the capture result is written in it.
+    let stage_start = logging::enter_stage("finish");
+    debug!(
+        "Create a JSON file to store the dump meta data\n{}",
+        cc.get_dump_info_filename()
+    );
+    let dump_info = cc.get_dump_info();
+    let (dump_info, native) = match cc.dump_info_format {
+        compat::DumpInfoFormat::Native => (dump_info, None),
+        compat::DumpInfoFormat::IbmCompat => (compat::ibm(&dump_info)?, None),
+        compat::DumpInfoFormat::Both => (compat::ibm(&dump_info)?, Some(dump_info)),
+    };
+    add_file(
+        bundle,
+        cc,
+        capture_result,
+        &cc.get_dump_info_filename(),
+        dump_info.as_bytes(),
+    )?;
+    if let Some(native) = &native {
+        add_file(
+            bundle,
+            cc,
+            capture_result,
+            &cc.get_native_dump_info_filename(),
+            native.as_bytes(),
+        )?;
+    }
+    if bundle.is_encrypted() {
+        let sidecar = native.as_deref().unwrap_or(&dump_info).as_bytes();
+        let redacted = cc.redact.redact_json(sidecar);
+        let path = bundle::dump_info_path(Path::new(&cc.get_tar_full_path()));
+        if let Err(e) = std::fs::write(&path, redacted.as_deref().unwrap_or(sidecar)) {
+            error!("Failed to write {}: {}", path.display(), e);
+            capture_result.record_error("archive", &e);
+        }
+    }
+    add_file(
+        bundle,
+        cc,
+        capture_result,
+        &cc.get_handler_config_filename(),
+        cc.get_handler_config().as_bytes(),
+    )?;
+    let result = capture_result.render().stage("archive")?;
+    bundle
+        .append(&cc.get_capture_result_filename(), result.as_bytes())
+        .stage("archive")?;
+    bundle
+        .finish(&cc.get_manifest_filename())
+        .stage("archive")?;
+    // Written before the archive is closed, the agent picks the archive up
+    // on close.
+    match bundle::write_checksum(Path::new(&cc.get_tar_full_path())) {
+        Ok(sha256) => cc.params.archive_sha256 = Some(sha256),
+        Err(e) => error!("Failed to write the archive checksum: {}", e),
+    }
+    let uuid = cc.params.uuid.to_string();
+    let tags = xattr::archive_tags(
+        cc.params.namespace.as_deref(),
+        cc.params.podname.as_deref(),
+        &uuid,
+    );
+    match xattr::tag(Path::new(&cc.get_tar_full_path()), &tags) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::Unsupported => {
+            debug!("The core directory has no user xattrs: {}", e)
+        }
+        Err(e) => warn!("Failed to tag the archive: {}", e),
+    }
+    capture_result.record_duration("finish", stage_start);
+    Ok(())
+}
+
+/// Pushes the finished archive when UPLOAD_BUCKET_NAME is set. A failed
+/// upload leaves the archive on the node for the agent.
+fn upload(cc: &config::CoreConfig) {
+    let upload = match &cc.upload {
+        Some(v) => v,
+        None => return,
+    };
+    let path = PathBuf::from(cc.get_tar_full_path());
+    let name = cc.get_archive_key();
+    let stage_start = Instant::now();
+    match upload.upload(&path, &name) {
+        Ok(key) => {
+            info!(
+                "Uploaded {} to {} in {}ms",
+                path.display(),
+                key,
+                stage_start.elapsed().as_millis()
+            );
+            let checksum = bundle::checksum_path(&path);
+            if cc.params.archive_sha256.is_some() {
+                if let Err(e) = upload.upload(&checksum, &format!("{name}.sha256")) {
+                    error!("Upload of {} failed: {:#}", checksum.display(), e);
+                }
+            }
+            if !upload.keep {
+                let mut written = vec![path.clone(), checksum];
+                let sidecar = bundle::dump_info_path(&path);
+                if sidecar.exists() {
+                    written.push(sidecar);
+                }
+                for path in &written {
+                    if let Err(e) = std::fs::remove_file(path) {
+                        error!("Failed to remove uploaded {}: {}", path.display(), e);
+                    }
+                }
+            }
+        }
+        Err(e) => error!("Direct upload of {} failed: {:#}", path.display(), e),
+    }
+}
+
+/// Sends the event to WEBHOOK_URL. The capture is complete by now, a
+/// failed notification is only logged.
+fn notify(evt: &CoreEvent, webhook: Option<&webhook::WebhookConfig>) {
+    let webhook = match webhook {
+        Some(v) => v,
+        None => return,
+    };
+    let stage_start = Instant::now();
+    let result = evt
+        .serialize(EventFormat::Json)
+        .and_then(|body| webhook.send(body));
+    match result {
+        Ok(code) => info!(
+            "Notified {} in {}ms: {}",
+            webhook.url,
+            stage_start.elapsed().as_millis(),
+            code
+        ),
+        Err(e) => error!("Webhook notification failed: {:#}", e),
+    }
+}
+
+/// Tails the kubelet's log files for `container`, which survive when the
+/// runtime's log API fails.
+fn copy_node_log(
+    bundle: &mut Bundle,
+    cc: &config::CoreConfig,
+    pod_object: &Value,
+    container: &Value,
+    counter: usize,
+    capture_result: &mut CaptureResult,
+) -> Result<(), anyhow::Error> {
+    let dir = podlogs::container_log_dir(
+        &cc.pod_log_dir,
+        pod_object["metadata"]["namespace"]
+            .as_str()
+            .unwrap_or_default(),
+        pod_object["metadata"]["name"].as_str().unwrap_or_default(),
+        pod_object["metadata"]["uid"].as_str().unwrap_or_default(),
+        container["metadata"]["name"].as_str().unwrap_or_default(),
+    );
+    let files = podlogs::log_files(&dir);
+    if files.is_empty() {
+        capture_result.record_error("node_logs", format!("No log files in {}", dir.display()));
+        return Ok(());
+    }
+    match podlogs::tail(&files, cc.log_length as usize) {
+        Ok(log) => {
+            capture_result.record_encoding(&cc.get_node_log_filename(counter), &log);
+            add_file(
+                bundle,
+                cc,
+                capture_result,
+                &cc.get_node_log_filename(counter),
+                &log,
+            )?
+        }
+        Err(e) => {
+            error!("Error copying node logs from {}:\n{}", dir.display(), e);
+            capture_result.record_error("node_logs", &e);
+        }
     }
     Ok(())
 }
@@ -0,0 +1,349 @@
+use crate::elf;
+use crate::elf::{ProgramHeader, NT_FILE, PF_X, PT_LOAD, PT_NOTE};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io;
+use std::io::Read;
+
+/// Notes larger than this are not buffered ahead of the core.
+const MAX_PREFIX: u64 = 16 << 20;
+
+#[derive(Serialize, Debug, Default, PartialEq, Eq)]
+pub struct MappingCategory {
+    pub count: u64,
+    /// Size of the mappings in the process.
+    pub memsz: u64,
+    /// Bytes of the mappings actually present in the core.
+    pub filesz: u64,
+}
+
+/// Counts and sizes of the core's PT_LOAD segments by kind of mapping.
+#[derive(Serialize, Debug, Default, PartialEq, Eq)]
+pub struct MappingSummary {
+    pub categories: BTreeMap<String, MappingCategory>,
+}
+
+fn read_up_to<R: Read>(reader: &mut R, buf: &mut Vec<u8>, len: u64) -> io::Result<()> {
+    let missing = len.saturating_sub(buf.len() as u64);
+    reader.take(missing).read_to_end(buf)?;
+    Ok(())
+}
+
+/// Reads the ELF header, program headers and notes from the start of the
+/// core. The caller must feed the returned bytes back in front of the rest
+/// of the stream.
+pub fn read_prefix<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut prefix = vec![];
+    read_up_to(reader, &mut prefix, 64)?;
+    if prefix.len() < 64 || &prefix[0..4] != b"\x7fELF" || prefix[4] != 2 {
+        return Ok(prefix);
+    }
+    let phoff = elf::u64_at(&prefix, 32);
+    let table_end = phoff + elf::u16_at(&prefix, 54) as u64 * elf::u16_at(&prefix, 56) as u64;
+    if table_end > MAX_PREFIX {
+        return Ok(prefix);
+    }
+    read_up_to(reader, &mut prefix, table_end)?;
+    let notes_end = match elf::program_headers(&prefix) {
+        Ok(headers) => headers
+            .iter()
+            .filter(|h| h.p_type == PT_NOTE)
+            .map(|h| h.offset + h.filesz)
+            .max()
+            .unwrap_or_default(),
+        Err(_) => return Ok(prefix),
+    };
+    if notes_end <= MAX_PREFIX {
+        read_up_to(reader, &mut prefix, notes_end)?;
+    }
+    Ok(prefix)
+}
+
+/// The file backed mappings listed in the NT_FILE note, their address range
+/// and path.
+fn file_notes(prefix: &[u8], headers: &[ProgramHeader]) -> Vec<(u64, u64, String)> {
+    let mut mappings = vec![];
+    for h in headers.iter().filter(|h| h.p_type == PT_NOTE) {
+        let (start, end) = (h.offset as usize, (h.offset + h.filesz) as usize);
+        if end > prefix.len() {
+            continue;
+        }
+        elf::for_each_note(&prefix[start..end], |n_type, _, desc| {
+            if n_type != NT_FILE || desc.len() < 16 {
+                return;
+            }
+            let count = elf::u64_at(desc, 0) as usize;
+            // The names follow the table of ranges, NUL terminated.
+            let names_at = 16usize.saturating_add(count.saturating_mul(24));
+            let mut names = desc
+                .get(names_at..)
+                .unwrap_or_default()
+                .split(|b| *b == 0)
+                .map(|n| String::from_utf8_lossy(n).to_string());
+            for i in 0..count {
+                let at = 16 + i * 24;
+                if at + 16 > desc.len() {
+                    break;
+                }
+                let name = names.next().unwrap_or_default();
+                mappings.push((elf::u64_at(desc, at), elf::u64_at(desc, at + 8), name));
+            }
+        });
+    }
+    mappings
+}
+
+/// The file backed mappings of the core's NT_FILE note.
+pub fn file_mappings(prefix: &[u8]) -> Vec<(u64, u64, String)> {
+    match elf::program_headers(prefix) {
+        Ok(headers) => file_notes(prefix, &headers),
+        Err(_) => vec![],
+    }
+}
+
+/// The address ranges of file backed mappings listed in the NT_FILE note.
+fn file_ranges(prefix: &[u8], headers: &[ProgramHeader]) -> Vec<(u64, u64)> {
+    file_notes(prefix, headers)
+        .into_iter()
+        .map(|(start, end, _)| (start, end))
+        .collect()
+}
+
+/// Labels from `/proc/<pid>/maps` for the special mappings, keyed by start.
+fn special_mappings(maps: &str) -> BTreeMap<u64, String> {
+    let mut labels = BTreeMap::new();
+    for line in maps.lines() {
+        let mut fields = line.split_whitespace();
+        let range = fields.next().unwrap_or_default();
+        let path = fields.nth(4).unwrap_or_default();
+        let label = if path == "[heap]" {
+            "heap"
+        } else if path.starts_with("[stack") {
+            "stack"
+        } else if path.starts_with('[') {
+            "other"
+        } else {
+            continue;
+        };
+        if let Some(start) = range
+            .split('-')
+            .next()
+            .and_then(|s| u64::from_str_radix(s, 16).ok())
+        {
+            labels.insert(start, label.to_string());
+        }
+    }
+    labels
+}
+
+/// The files mapped executable in `/proc/<pid>/maps`, the executable
+/// and its shared libraries, in the order they are first mapped. Files
+/// deleted since they were mapped are left out.
+pub fn executable_files(maps: &str) -> Vec<String> {
+    let mut files: Vec<String> = vec![];
+    for line in maps.lines() {
+        let fields: Vec<&str> = line.splitn(6, ' ').collect();
+        if fields.len() < 6 || !fields[1].contains('x') {
+            continue;
+        }
+        let path = fields[5].trim_start();
+        if !path.starts_with('/') || path.ends_with(" (deleted)") {
+            continue;
+        }
+        if !files.iter().any(|f| f == path) {
+            files.push(path.to_string());
+        }
+    }
+    files
+}
+
+/// The files mapped executable according to the core's NT_FILE note, for
+/// when `/proc/<pid>/maps` could not be read. The note has no permissions,
+/// a file counts when it overlaps a PT_LOAD segment with PF_X set.
+pub fn note_files(prefix: &[u8]) -> Vec<String> {
+    let headers = match elf::program_headers(prefix) {
+        Ok(v) => v,
+        Err(_) => return vec![],
+    };
+    let executable: Vec<&ProgramHeader> = headers
+        .iter()
+        .filter(|h| h.p_type == PT_LOAD && h.flags & PF_X != 0)
+        .collect();
+    let mut files: Vec<String> = vec![];
+    for (start, end, path) in file_notes(prefix, &headers) {
+        if !path.starts_with('/') || path.ends_with(" (deleted)") {
+            continue;
+        }
+        let mapped = executable
+            .iter()
+            .any(|h| h.vaddr < end && start < h.vaddr + h.memsz);
+        if mapped && !files.contains(&path) {
+            files.push(path);
+        }
+    }
+    files
+}
+
+/// Summarizes the mappings of a core from its prefix. The maps of the
+/// crashing process are used to tell heap and stack apart from other
+/// anonymous memory when they are readable.
+pub fn summarize(prefix: &[u8], maps: Option<&str>) -> Option<MappingSummary> {
+    let headers = elf::program_headers(prefix).ok()?;
+    let files = file_ranges(prefix, &headers);
+    let special = maps.map(special_mappings).unwrap_or_default();
+    let mut summary = MappingSummary::default();
+    for h in headers.iter().filter(|h| h.p_type == PT_LOAD) {
+        let end = h.vaddr + h.memsz;
+        let category = if let Some(label) = special.get(&h.vaddr) {
+            label.as_str()
+        } else if files.iter().any(|(s, e)| h.vaddr < *e && *s < end) {
+            "file"
+        } else {
+            "anon"
+        };
+        let entry = summary.categories.entry(category.to_string()).or_default();
+        entry.count += 1;
+        entry.memsz += h.memsz;
+        entry.filesz += h.filesz;
+    }
+    Some(summary)
+}
+
+/// The size of the whole core, the end of its last segment, from its
+/// prefix.
+pub fn core_size(prefix: &[u8]) -> Option<u64> {
+    elf::program_headers(prefix)
+        .ok()?
+        .iter()
+        .map(|h| h.offset + h.filesz)
+        .max()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::elf::tests::build_elf;
+    use crate::elf::{NT_FILE, PT_LOAD, PT_NOTE};
+    use crate::mappings::{core_size, executable_files, note_files, read_prefix, summarize};
+    use std::io::Read;
+
+    pub fn nt_file(files: &[(u64, u64, &str)]) -> Vec<u8> {
+        let mut desc = vec![];
+        desc.extend((files.len() as u64).to_le_bytes());
+        desc.extend(4096u64.to_le_bytes());
+        for (start, end, _) in files {
+            desc.extend(start.to_le_bytes());
+            desc.extend(end.to_le_bytes());
+            desc.extend(0u64.to_le_bytes());
+        }
+        for (_, _, path) in files {
+            desc.extend(path.as_bytes());
+            desc.push(0);
+        }
+        while desc.len() % 4 != 0 {
+            desc.push(0);
+        }
+        let mut note = vec![];
+        note.extend(5u32.to_le_bytes());
+        note.extend((desc.len() as u32).to_le_bytes());
+        note.extend(NT_FILE.to_le_bytes());
+        note.extend(b"CORE\0\0\0\0");
+        note.extend(desc);
+        note
+    }
+
+    #[test]
+    fn summarize_test() {
+        let note = nt_file(&[(0x400000, 0x401000, "/usr/bin/mo-service")]);
+        let elf = build_elf(
+            &[
+                (PT_NOTE, 0, 0, note.len() as u64, 0),
+                (PT_LOAD, 5, 0x400000, 0x1000, 0x1000),
+                (PT_LOAD, 6, 0x600000, 0x3000, 0x3000),
+                (PT_LOAD, 6, 0x7ffd0000, 0x2000, 0x2000),
+                (PT_LOAD, 6, 0x7f0000000000, 0, 0x10000),
+            ],
+            &note,
+        );
+        let mut core = elf.clone();
+        core.extend(vec![0u8; 100]);
+
+        let mut reader = core.as_slice();
+        let prefix = read_prefix(&mut reader).unwrap();
+        assert_eq!(prefix, elf);
+        let mut rest = vec![];
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest.len(), 100);
+        assert_eq!(core_size(&prefix), Some(64 + 56 * 5 + 0x3000));
+        assert_eq!(core_size(b"not a core"), None);
+
+        let maps = "00400000-00401000 r-xp 00000000 08:01 123 /usr/bin/mo-service
+00600000-00603000 rw-p 00000000 00:00 0 [heap]
+7ffd0000-7ffd2000 rw-p 00000000 00:00 0 [stack]
+7f0000000000-7f0000010000 rw-p 00000000 00:00 0";
+        let summary = summarize(&prefix, Some(maps)).unwrap();
+        assert_eq!(summary.categories["file"].count, 1);
+        assert_eq!(summary.categories["heap"].memsz, 0x3000);
+        assert_eq!(summary.categories["stack"].filesz, 0x2000);
+        assert_eq!(summary.categories["anon"].memsz, 0x10000);
+        assert_eq!(summary.categories["anon"].filesz, 0);
+
+        // Without the maps heap and stack are reported as anonymous memory.
+        let summary = summarize(&prefix, None).unwrap();
+        assert_eq!(summary.categories["anon"].count, 3);
+    }
+
+    #[test]
+    fn executable_files_test() {
+        let maps =
+            "00400000-00401000 r-xp 00000000 08:01 123                        /usr/bin/mo-service
+00401000-00402000 r--p 00001000 08:01 123                        /usr/bin/mo-service
+7f0000000000-7f0000010000 r--p 00000000 08:01 456                /usr/share/locale/C.mo
+7f0000010000-7f0000020000 r-xp 00000000 08:01 789                /usr/lib/libc.so.6
+7f0000020000-7f0000030000 r-xp 00000000 08:01 790                /tmp/jit map (deleted)
+7f0000030000-7f0000040000 r-xp 00000000 08:01 791                /opt/my app/libplugin.so
+7ffd0000-7ffd2000 r-xp 00000000 00:00 0                          [vdso]";
+        assert_eq!(
+            executable_files(maps),
+            vec![
+                "/usr/bin/mo-service",
+                "/usr/lib/libc.so.6",
+                "/opt/my app/libplugin.so"
+            ]
+        );
+    }
+
+    #[test]
+    fn note_files_test() {
+        let note = nt_file(&[
+            (0x400000, 0x401000, "/usr/bin/mo-service"),
+            (0x401000, 0x402000, "/usr/bin/mo-service"),
+            (0x7f0000000000, 0x7f0000010000, "/usr/share/locale/C.mo"),
+            (0x7f0000010000, 0x7f0000020000, "/usr/lib/libc.so.6"),
+            (0x7f0000020000, 0x7f0000030000, "/tmp/jit (deleted)"),
+        ]);
+        let elf = build_elf(
+            &[
+                (PT_NOTE, 0, 0, note.len() as u64, 0),
+                (PT_LOAD, 5, 0x400000, 0x1000, 0x1000),
+                (PT_LOAD, 4, 0x401000, 0x1000, 0x1000),
+                (PT_LOAD, 4, 0x7f0000000000, 0, 0x10000),
+                (PT_LOAD, 5, 0x7f0000010000, 0x1000, 0x10000),
+                (PT_LOAD, 5, 0x7f0000020000, 0x1000, 0x10000),
+            ],
+            &note,
+        );
+        assert_eq!(
+            note_files(&elf),
+            vec!["/usr/bin/mo-service", "/usr/lib/libc.so.6"]
+        );
+        assert!(note_files(b"not a core").is_empty());
+    }
+
+    #[test]
+    fn not_elf_test() {
+        let mut reader = &b"not a core"[..];
+        let prefix = read_prefix(&mut reader).unwrap();
+        assert_eq!(prefix, b"not a core");
+        assert_eq!(summarize(&prefix, None), None);
+    }
+}
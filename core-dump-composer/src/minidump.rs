@@ -0,0 +1,186 @@
+//! CORE_FORMAT, whether the archive holds the core, a Breakpad minidump
+//! of it or both. The minidump is written by Breakpad's `core2md` from the
+//! core and the crashed process's `/proc` directory, and is a few MB where
+//! the core can be many GB.
+
+use crate::elf;
+use crate::mappings;
+use serde::Serialize;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// A `core2md` still converting after this is killed.
+pub const TIMEOUT: Duration = Duration::from_secs(60);
+const CORE2MD: &str = "core2md";
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum CoreFormat {
+    Core,
+    /// The minidump replaces the core. The core is kept when it couldn't
+    /// be converted.
+    Minidump,
+    Both,
+}
+
+impl FromStr for CoreFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "" | "core" => Ok(CoreFormat::Core),
+            "minidump" => Ok(CoreFormat::Minidump),
+            "both" => Ok(CoreFormat::Both),
+            other => Err(anyhow::anyhow!("Unknown CORE_FORMAT {}", other)),
+        }
+    }
+}
+
+impl CoreFormat {
+    pub fn minidump(&self) -> bool {
+        *self != CoreFormat::Core
+    }
+
+    pub fn core(&self) -> bool {
+        *self != CoreFormat::Minidump
+    }
+}
+
+/// Whether `core2md` is in the handler's PATH.
+pub fn find(path: &str) -> bool {
+    path.split(':')
+        .any(|dir| Path::new(dir).join(CORE2MD).is_file())
+}
+
+/// The command writing the minidump of `core` to `out`. `proc_dir` is the
+/// crashed process's `/proc/<pid>` the maps and auxv are read from.
+pub fn command(core: &str, proc_dir: &str, out: &str) -> Vec<String> {
+    [CORE2MD, core, proc_dir, out]
+        .iter()
+        .map(|a| a.to_string())
+        .collect()
+}
+
+/// A module of `-modules.json`, in the layout of a Sentry `debug_meta`
+/// image so the list can be sent on as it is.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Module {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub code_file: String,
+    /// The GNU build-id.
+    pub code_id: Option<String>,
+    /// The Breakpad module id symbol servers index the symbols by.
+    pub debug_id: Option<String>,
+    pub image_addr: String,
+    pub image_size: u64,
+}
+
+/// The executable and the shared libraries mapped in the core, from its
+/// NT_FILE note. The build-ids are read from the files under `root`, the
+/// crashed process's root, as `core2md` looks them up on the node.
+pub fn modules(prefix: &[u8], root: &Path) -> Vec<Module> {
+    let ranges = mappings::file_mappings(prefix);
+    mappings::note_files(prefix)
+        .into_iter()
+        .map(|path| {
+            let (start, end) = ranges
+                .iter()
+                .filter(|(_, _, p)| *p == path)
+                .fold((u64::MAX, 0), |(s, e), (start, end, _)| {
+                    (s.min(*start), e.max(*end))
+                });
+            let code_id = elf::read_build_id(&root.join(path.trim_start_matches('/')))
+                .ok()
+                .flatten();
+            Module {
+                kind: "elf",
+                debug_id: code_id.as_deref().and_then(debug_id),
+                code_id,
+                code_file: path,
+                image_addr: format!("{start:#x}"),
+                image_size: end.saturating_sub(start),
+            }
+        })
+        .collect()
+}
+
+/// The Breakpad module id of a build-id: its first 16 bytes as a GUID, the
+/// first three fields byte swapped, followed by the age 0.
+pub fn debug_id(build_id: &str) -> Option<String> {
+    if !build_id.is_ascii() || !build_id.len().is_multiple_of(2) {
+        return None;
+    }
+    let mut bytes = (0..build_id.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&build_id[i..i + 2], 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    bytes.resize(16, 0);
+    bytes[0..4].reverse();
+    bytes[4..6].reverse();
+    bytes[6..8].reverse();
+    let guid: String = bytes.iter().map(|b| format!("{b:02X}")).collect();
+    Some(format!("{guid}0"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::elf::tests::build_elf;
+    use crate::elf::{PT_LOAD, PT_NOTE};
+    use crate::mappings::tests::nt_file;
+    use crate::minidump::{command, debug_id, modules, CoreFormat};
+    use std::path::Path;
+
+    #[test]
+    fn core_format_test() {
+        assert_eq!("".parse::<CoreFormat>().unwrap(), CoreFormat::Core);
+        assert_eq!(
+            "Minidump".parse::<CoreFormat>().unwrap(),
+            CoreFormat::Minidump
+        );
+        assert!("both".parse::<CoreFormat>().unwrap().core());
+        assert!(!CoreFormat::Minidump.core());
+        assert!(!CoreFormat::Core.minidump());
+        assert!("mdmp".parse::<CoreFormat>().is_err());
+        assert_eq!(
+            command("/tmp/core", "/proc/42", "/tmp/core.dmp"),
+            vec!["core2md", "/tmp/core", "/proc/42", "/tmp/core.dmp"]
+        );
+    }
+
+    #[test]
+    fn modules_test() {
+        assert_eq!(
+            debug_id("b4ff4f7e0e9d4a6b8ee6d27f2a1a7c0b12345678").as_deref(),
+            Some("7E4FFFB49D0E6B4A8EE6D27F2A1A7C0B0")
+        );
+        assert_eq!(
+            debug_id("deadbeef").as_deref(),
+            Some("EFBEADDE0000000000000000000000000")
+        );
+        assert_eq!(debug_id("xyz"), None);
+
+        let note = nt_file(&[
+            (0x400000, 0x401000, "/usr/bin/mo-service"),
+            (0x401000, 0x403000, "/usr/bin/mo-service"),
+            (0x7f0000000000, 0x7f0000010000, "/usr/share/locale/C.mo"),
+        ]);
+        let elf = build_elf(
+            &[
+                (PT_NOTE, 0, 0, note.len() as u64, 0),
+                (PT_LOAD, 5, 0x400000, 0x1000, 0x1000),
+                (PT_LOAD, 4, 0x401000, 0x2000, 0x2000),
+                (PT_LOAD, 4, 0x7f0000000000, 0, 0x10000),
+            ],
+            &note,
+        );
+        let found = modules(&elf, Path::new("/nonexistent"));
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].code_file, "/usr/bin/mo-service");
+        assert_eq!(found[0].image_addr, "0x400000");
+        assert_eq!(found[0].image_size, 0x3000);
+        assert_eq!(found[0].code_id, None);
+        assert!(modules(b"not a core", Path::new("/")).is_empty());
+    }
+}
@@ -0,0 +1,85 @@
+use serde::Serialize;
+use serde_json::Value;
+
+/// Where the crashed pod sat on the network at crash time.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct NetworkIdentity {
+    pub pod_ip: Option<String>,
+    pub additional_ips: Vec<String>,
+    pub host_network: Option<bool>,
+    pub node_ip: Option<String>,
+}
+
+/// Reads the pod network from `crictl inspectp` output. A pod with
+/// `hostNetwork: true` runs its sandbox in the node's network namespace.
+pub fn from_inspect(inspect: &Value, node_ip: Option<String>) -> NetworkIdentity {
+    let network = &inspect["status"]["network"];
+    let pod_ip = network["ip"]
+        .as_str()
+        .filter(|ip| !ip.is_empty())
+        .map(|ip| ip.to_string());
+    let additional_ips = network["additionalIps"]
+        .as_array()
+        .map(|ips| {
+            ips.iter()
+                .filter_map(|ip| ip["ip"].as_str().or_else(|| ip.as_str()))
+                .map(|ip| ip.to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+    let host_network = inspect["status"]["linux"]["namespaces"]["options"]["network"]
+        .as_str()
+        .map(|mode| mode == "NODE");
+    // Host network pods report the node address as their own.
+    let pod_ip = match (pod_ip, host_network, &node_ip) {
+        (None, Some(true), Some(node)) => Some(node.clone()),
+        (ip, _, _) => ip,
+    };
+    NetworkIdentity {
+        pod_ip,
+        additional_ips,
+        host_network,
+        node_ip,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::network::from_inspect;
+    use serde_json::json;
+
+    #[test]
+    fn pod_network_test() {
+        let inspect = json!({
+            "status": {
+                "network": {
+                    "additionalIps": [{"ip": "fd00::1c"}],
+                    "ip": "172.30.154.12"
+                },
+                "linux": {"namespaces": {"options": {"network": "POD", "pid": "CONTAINER"}}}
+            }
+        });
+        let network = from_inspect(&inspect, Some("10.0.0.4".to_string()));
+        assert_eq!(network.pod_ip.as_deref(), Some("172.30.154.12"));
+        assert_eq!(network.additional_ips, vec!["fd00::1c"]);
+        assert_eq!(network.host_network, Some(false));
+        assert_eq!(network.node_ip.as_deref(), Some("10.0.0.4"));
+    }
+
+    #[test]
+    fn host_network_test() {
+        let inspect = json!({
+            "status": {
+                "network": {"ip": ""},
+                "linux": {"namespaces": {"options": {"network": "NODE"}}}
+            }
+        });
+        let network = from_inspect(&inspect, Some("10.0.0.4".to_string()));
+        assert_eq!(network.host_network, Some(true));
+        assert_eq!(network.pod_ip.as_deref(), Some("10.0.0.4"));
+
+        let network = from_inspect(&json!({}), None);
+        assert_eq!(network.pod_ip, None);
+        assert_eq!(network.host_network, None);
+    }
+}
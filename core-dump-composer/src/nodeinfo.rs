@@ -0,0 +1,164 @@
+//! The `-node-info.json` of the node the crash happened on, its kernel, OS
+//! and container runtime, and the tail of the kernel log as `-dmesg.txt`.
+//! OOM kills, hung tasks and hardware errors logged around the crash are
+//! often the reason for it.
+
+use anyhow::anyhow;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+#[derive(Serialize, Debug, Default)]
+pub struct Kernel {
+    pub sysname: String,
+    pub release: String,
+    pub version: String,
+    pub machine: String,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct NodeInfo {
+    pub hostname: String,
+    pub kernel: Kernel,
+    pub os_release: BTreeMap<String, String>,
+    pub uptime_secs: Option<u64>,
+    /// As `crictl version -o json` prints it.
+    pub container_runtime: Option<Value>,
+}
+
+/// The `KEY=value` lines of an os-release file, unquoted.
+fn parse_os_release(content: &str) -> BTreeMap<String, String> {
+    content
+        .lines()
+        .filter(|l| !l.trim_start().starts_with('#'))
+        .filter_map(|l| l.split_once('='))
+        .map(|(k, v)| {
+            let v = v.trim();
+            let v = v
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .or_else(|| v.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')))
+                .unwrap_or(v);
+            (k.trim().to_string(), v.to_string())
+        })
+        .collect()
+}
+
+/// Reads the node's side from `root`, `/` outside the tests, which the
+/// composer shares with the node.
+pub fn read(root: &Path, container_runtime: Option<Value>) -> NodeInfo {
+    let text = |path: &str| {
+        fs::read_to_string(root.join(path))
+            .map(|s| s.trim().to_string())
+            .unwrap_or_default()
+    };
+    let os_release = ["etc/os-release", "usr/lib/os-release"]
+        .iter()
+        .map(|p| text(p))
+        .find(|c| !c.is_empty())
+        .map(|c| parse_os_release(&c))
+        .unwrap_or_default();
+    NodeInfo {
+        hostname: text("proc/sys/kernel/hostname"),
+        kernel: Kernel {
+            sysname: text("proc/sys/kernel/ostype"),
+            release: text("proc/sys/kernel/osrelease"),
+            version: text("proc/sys/kernel/version"),
+            machine: std::env::consts::ARCH.to_string(),
+        },
+        os_release,
+        uptime_secs: text("proc/uptime")
+            .split('.')
+            .next()
+            .and_then(|s| s.parse().ok()),
+        container_runtime,
+    }
+}
+
+/// The last `lines` lines of the kernel log, with wall clock times where
+/// dmesg can tell them.
+pub fn dmesg(lines: usize, bin_path: &str) -> Result<Vec<u8>, anyhow::Error> {
+    let run = |args: &[&str]| {
+        Command::new("dmesg")
+            .env("PATH", bin_path)
+            .args(args)
+            .output()
+    };
+    let output = match run(&["--time-format=iso"])? {
+        output if output.status.success() => output,
+        // busybox's dmesg has no time formats.
+        _ => run(&[])?,
+    };
+    if !output.status.success() {
+        return Err(anyhow!(
+            "dmesg failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    Ok(tail(&output.stdout, lines).to_vec())
+}
+
+/// The last `lines` lines of `log`.
+fn tail(log: &[u8], lines: usize) -> &[u8] {
+    if lines == 0 {
+        return &[];
+    }
+    let end = match log.last() {
+        Some(b'\n') => log.len() - 1,
+        _ => log.len(),
+    };
+    let start = log[..end]
+        .iter()
+        .enumerate()
+        .rev()
+        .filter(|(_, b)| **b == b'\n')
+        .nth(lines - 1)
+        .map_or(0, |(i, _)| i + 1);
+    &log[start..]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::nodeinfo::{parse_os_release, read, tail};
+    use serde_json::json;
+    use std::fs;
+
+    #[test]
+    fn read_test() {
+        let release = parse_os_release(
+            "# comment\nNAME=\"Red Hat Enterprise Linux CoreOS\"\nVERSION_ID='4.14'\nID=rhcos\n",
+        );
+        assert_eq!(release["NAME"], "Red Hat Enterprise Linux CoreOS");
+        assert_eq!(release["VERSION_ID"], "4.14");
+        assert_eq!(release["ID"], "rhcos");
+
+        let root = std::env::temp_dir().join(format!("nodeinfo-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(root.join("proc/sys/kernel")).unwrap();
+        fs::create_dir_all(root.join("usr/lib")).unwrap();
+        fs::write(root.join("proc/sys/kernel/hostname"), "node-1\n").unwrap();
+        fs::write(root.join("proc/sys/kernel/osrelease"), "5.14.0-284.el9\n").unwrap();
+        fs::write(root.join("proc/uptime"), "350735.47 234388.90\n").unwrap();
+        fs::write(root.join("usr/lib/os-release"), "ID=fedora\n").unwrap();
+        let runtime = json!({"runtimeName": "cri-o", "runtimeVersion": "1.28.1"});
+        let info = read(&root, Some(runtime));
+        assert_eq!(info.hostname, "node-1");
+        assert_eq!(info.kernel.release, "5.14.0-284.el9");
+        assert!(info.kernel.version.is_empty());
+        assert_eq!(info.os_release["ID"], "fedora");
+        assert_eq!(info.uptime_secs, Some(350735));
+        assert_eq!(info.container_runtime.unwrap()["runtimeName"], "cri-o");
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn tail_test() {
+        let log = b"one\ntwo\nthree\n";
+        assert_eq!(tail(log, 2), b"two\nthree\n");
+        assert_eq!(tail(log, 5), log);
+        assert_eq!(tail(b"one\ntwo", 1), b"two");
+        assert!(tail(log, 0).is_empty());
+    }
+}
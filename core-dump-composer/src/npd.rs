@@ -0,0 +1,106 @@
+//! `cdc npd-check`, a node-problem-detector custom plugin. NPD runs it from
+//! the host directory and turns the exit code into the FrequentCoreDumps
+//! node condition, so autoscalers and remediation see crash heavy nodes.
+
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// The exit codes of the custom plugin protocol.
+pub const OK: i32 = 0;
+pub const NON_OK: i32 = 1;
+pub const UNKNOWN: i32 = 2;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Status {
+    pub code: i32,
+    /// NPD keeps the first 80 characters by default.
+    pub message: String,
+}
+
+/// Counts the crashes of the decisions log in the last `window` seconds,
+/// whatever was captured of them. NonOK from `threshold` crashes on.
+pub fn check(log: &str, now: u64, window: u64, threshold: usize) -> Status {
+    let since = now.saturating_sub(window);
+    let mut by_exe: BTreeMap<String, usize> = BTreeMap::new();
+    for line in log.lines() {
+        let Ok(record) = serde_json::from_str::<Value>(line) else {
+            continue;
+        };
+        let timestamp = record["timestamp"]
+            .as_str()
+            .and_then(|t| t.parse::<u64>().ok());
+        if timestamp.is_some_and(|t| t >= since) {
+            let exe = record["exe"].as_str().unwrap_or("unknown").to_string();
+            *by_exe.entry(exe).or_default() += 1;
+        }
+    }
+    let crashes: usize = by_exe.values().sum();
+    let minutes = window / 60;
+    if crashes < threshold.max(1) {
+        return Status {
+            code: OK,
+            message: format!("{crashes} core dumps in the last {minutes}m"),
+        };
+    }
+    let (exe, count) = by_exe
+        .iter()
+        .max_by_key(|(_, count)| **count)
+        .map(|(exe, count)| (exe.as_str(), *count))
+        .unwrap_or_default();
+    Status {
+        code: NON_OK,
+        message: format!("{crashes} core dumps in the last {minutes}m, {count} from {exe}"),
+    }
+}
+
+/// `check` on the decisions log at `path`. A node that never crashed has no
+/// log yet, a log that can't be read is Unknown.
+pub fn check_log(path: &Path, now: u64, window: u64, threshold: usize) -> Status {
+    match fs::read_to_string(path) {
+        Ok(log) => check(&log, now, window, threshold),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => check("", now, window, threshold),
+        Err(e) => Status {
+            code: UNKNOWN,
+            message: format!("reading {}: {}", path.display(), e),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::npd::{check, check_log, NON_OK, OK, UNKNOWN};
+    use std::path::Path;
+
+    #[test]
+    fn check_test() {
+        let log = [
+            r#"{"timestamp":"1706263000","exe":"mo-service","outcome":"captured"}"#,
+            r#"{"timestamp":"1706263100","exe":"mo-service","outcome":"skipped"}"#,
+            r#"{"timestamp":"1706263150","exe":"mo-log","outcome":"captured"}"#,
+            r#"{"timestamp":"1706259000","exe":"mo-service","outcome":"captured"}"#,
+            "not json",
+        ]
+        .join("\n");
+        let now = 1706263200;
+        let status = check(&log, now, 3600, 3);
+        assert_eq!(status.code, NON_OK);
+        assert_eq!(
+            status.message,
+            "3 core dumps in the last 60m, 2 from mo-service"
+        );
+        assert!(status.message.len() <= 80);
+        assert_eq!(check(&log, now, 3600, 4).code, OK);
+        assert_eq!(
+            check(&log, now, 60, 3).message,
+            "1 core dumps in the last 1m"
+        );
+        assert_eq!(check("", now, 3600, 0).code, OK);
+
+        let missing = check_log(Path::new("/nonexistent/decisions.log"), now, 3600, 1);
+        assert_eq!(missing.code, OK);
+        assert_eq!(check_log(Path::new("/"), now, 3600, 1).code, UNKNOWN);
+    }
+}
@@ -0,0 +1,197 @@
+use crate::runtime::Runtime;
+use serde::Serialize;
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// How far from the crash a container termination still counts as related.
+pub const OOM_WINDOW_SECS: i64 = 300;
+
+/// Memory pressure around the crash. A SIGABRT next to an OOM kill is
+/// usually an allocation failure rather than a logic error and is triaged
+/// differently.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct OomCorrelation {
+    pub oom_correlated: bool,
+    /// Containers of the pod the runtime last saw terminated with
+    /// OOMKilled within OOM_WINDOW_SECS of the crash.
+    pub oom_killed_containers: Vec<String>,
+    /// The oom_kill counter of the crashed process's memory cgroup.
+    pub cgroup_oom_kills: Option<u64>,
+    pub memory_limit_bytes: Option<u64>,
+    /// memory.min, which the kubelet only sets to the request when the
+    /// MemoryQoS feature is enabled.
+    pub memory_request_bytes: Option<u64>,
+}
+
+/// The memory cgroup directory from the contents of `/proc/<pid>/cgroup`.
+pub fn memory_cgroup_dir(cgroup: &str) -> Option<PathBuf> {
+    let mut unified = None;
+    for line in cgroup.lines() {
+        let mut parts = line.splitn(3, ':');
+        let (_, controllers, path) = (parts.next()?, parts.next()?, parts.next()?);
+        if controllers.split(',').any(|c| c == "memory") {
+            return Some(PathBuf::from(format!("/sys/fs/cgroup/memory{path}")));
+        }
+        if controllers.is_empty() {
+            unified = Some(PathBuf::from(format!("/sys/fs/cgroup{path}")));
+        }
+    }
+    unified
+}
+
+/// A memory control value, `max` and the cgroup v1 "unlimited" page
+/// counter meaning no value was set.
+pub fn parse_memory_value(value: &str) -> Option<u64> {
+    match value.trim().parse::<u64>() {
+        Ok(0) => None,
+        Ok(v) if v >= 0x7FFF_FFFF_FFFF_F000 => None,
+        Ok(v) => Some(v),
+        Err(_) => None,
+    }
+}
+
+/// The `oom_kill` line of memory.events (v2) or memory.oom_control (v1).
+pub fn parse_oom_kills(events: &str) -> Option<u64> {
+    events
+        .lines()
+        .find_map(|l| l.strip_prefix("oom_kill "))
+        .and_then(|v| v.trim().parse().ok())
+}
+
+/// Seconds since the epoch of an RFC 3339 timestamp in UTC such as the
+/// runtime's `finishedAt`, the fraction is dropped.
+pub fn parse_rfc3339(ts: &str) -> Option<i64> {
+    let ts = ts.strip_suffix('Z')?;
+    let (date, time) = ts.split_once('T')?;
+    let mut date = date.split('-').map(|v| v.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    let time = time.split('.').next()?;
+    let mut time = time.split(':').map(|v| v.parse::<i64>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+    // Days from civil, see http://howardhinnant.github.io/date_algorithms.html
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    Some(days * 86400 + hour * 3600 + minute * 60 + second)
+}
+
+/// Names of the containers in `inspects` terminated with OOMKilled within
+/// OOM_WINDOW_SECS of `crash_secs`.
+pub fn oom_killed(inspects: &[Value], crash_secs: i64) -> Vec<String> {
+    inspects
+        .iter()
+        .filter(|i| i["status"]["reason"].as_str() == Some("OOMKilled"))
+        .filter(|i| {
+            i["status"]["finishedAt"]
+                .as_str()
+                .and_then(parse_rfc3339)
+                .map(|finished| (finished - crash_secs).abs() <= OOM_WINDOW_SECS)
+                .unwrap_or(false)
+        })
+        .map(|i| {
+            i["status"]["metadata"]["name"]
+                .as_str()
+                .or_else(|| i["status"]["id"].as_str())
+                .unwrap_or_default()
+                .to_string()
+        })
+        .collect()
+}
+
+/// The pod's exited containers as `crictl inspect` reports them. The
+/// containers listed for the capture only include running ones.
+fn exited_containers(runtime: &Runtime, pod_id: &str) -> Vec<Value> {
+    let ps = match runtime.all_containers(pod_id) {
+        Some(v) => v,
+        None => return vec![],
+    };
+    ps["containers"]
+        .as_array()
+        .map(|c| c.as_slice())
+        .unwrap_or_default()
+        .iter()
+        .filter(|c| c["state"].as_str() == Some("CONTAINER_EXITED"))
+        .filter_map(|c| c["id"].as_str())
+        .filter_map(|id| runtime.inspect_container(id))
+        .collect()
+}
+
+pub fn correlate(
+    runtime: &Runtime,
+    pod_id: &str,
+    proc_dir: Option<&Path>,
+    timestamp: &str,
+) -> OomCorrelation {
+    let mut oom = OomCorrelation::default();
+    if let Some(dir) = proc_dir
+        .and_then(|d| fs::read_to_string(d.join("cgroup")).ok())
+        .and_then(|c| memory_cgroup_dir(&c))
+    {
+        let read = |name: &str| fs::read_to_string(dir.join(name)).ok();
+        oom.memory_limit_bytes = read("memory.max")
+            .or_else(|| read("memory.limit_in_bytes"))
+            .and_then(|v| parse_memory_value(&v));
+        oom.memory_request_bytes = read("memory.min").and_then(|v| parse_memory_value(&v));
+        oom.cgroup_oom_kills = read("memory.events")
+            .or_else(|| read("memory.oom_control"))
+            .and_then(|v| parse_oom_kills(&v));
+    }
+    if let Ok(crash_secs) = timestamp.parse::<i64>() {
+        oom.oom_killed_containers = oom_killed(&exited_containers(runtime, pod_id), crash_secs);
+    }
+    oom.oom_correlated =
+        !oom.oom_killed_containers.is_empty() || oom.cgroup_oom_kills.unwrap_or(0) > 0;
+    oom
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::oom::{
+        memory_cgroup_dir, oom_killed, parse_memory_value, parse_oom_kills, parse_rfc3339,
+    };
+    use serde_json::json;
+    use std::path::PathBuf;
+
+    #[test]
+    fn oom_correlation_test() {
+        assert_eq!(parse_rfc3339("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(
+            parse_rfc3339("2024-02-29T12:30:05.123456789Z"),
+            Some(1709209805)
+        );
+        assert_eq!(parse_rfc3339("2024-02-29 12:30:05"), None);
+
+        let inspects = vec![
+            json!({"status": {"metadata": {"name": "mo"}, "reason": "OOMKilled",
+                "finishedAt": "2024-02-29T12:28:05Z"}}),
+            json!({"status": {"metadata": {"name": "old"}, "reason": "OOMKilled",
+                "finishedAt": "2024-02-28T12:28:05Z"}}),
+            json!({"status": {"metadata": {"name": "sidecar"}, "reason": "Completed",
+                "finishedAt": "2024-02-29T12:30:00Z"}}),
+        ];
+        assert_eq!(oom_killed(&inspects, 1709209805), vec!["mo"]);
+
+        assert_eq!(
+            memory_cgroup_dir("0::/kubepods.slice/cri-containerd-abc.scope\n"),
+            Some(PathBuf::from(
+                "/sys/fs/cgroup/kubepods.slice/cri-containerd-abc.scope"
+            ))
+        );
+        assert_eq!(
+            memory_cgroup_dir("5:cpu,cpuacct:/kubepods/a\n4:memory:/kubepods/a\n"),
+            Some(PathBuf::from("/sys/fs/cgroup/memory/kubepods/a"))
+        );
+        assert_eq!(parse_memory_value("max\n"), None);
+        assert_eq!(parse_memory_value("9223372036854771712\n"), None);
+        assert_eq!(parse_memory_value("536870912\n"), Some(536870912));
+        assert_eq!(
+            parse_oom_kills("low 0\nhigh 0\nmax 3\noom 1\noom_kill 1\n"),
+            Some(1)
+        );
+    }
+}
@@ -0,0 +1,68 @@
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const POD_CACHE_FILE: &str = "pods.cache.json";
+
+/// The crictl pod of the crashing container from the cache the agent keeps
+/// with POD_CACHE_INTERVAL. The container id from the cgroup is looked up
+/// first, then a pod named like the process's hostname. None when the cache
+/// is missing, older than its `max_age` or doesn't know the pod, and the
+/// composer asks crictl instead.
+pub fn lookup(
+    cache: &Value,
+    container_id: Option<&str>,
+    hostname: &str,
+    now: SystemTime,
+) -> Option<Value> {
+    let now = now.duration_since(UNIX_EPOCH).ok()?.as_secs();
+    let refreshed = cache["refreshed"].as_u64()?;
+    if now.saturating_sub(refreshed) > cache["max_age"].as_u64()? {
+        return None;
+    }
+    let pods = cache["pods"].as_object()?;
+    if let Some(pod_id) = container_id.and_then(|id| cache["containers"][id].as_str()) {
+        if let Some(pod) = pods.get(pod_id) {
+            return Some(pod.clone());
+        }
+    }
+    pods.values()
+        .find(|pod| pod["metadata"]["name"].as_str() == Some(hostname))
+        .cloned()
+}
+
+pub fn read(path: &Path) -> Option<Value> {
+    serde_json::from_slice(&fs::read(path).ok()?).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::podcache::lookup;
+    use serde_json::json;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn lookup_test() {
+        let cache = json!({
+            "refreshed": 1706263200,
+            "max_age": 30,
+            "containers": {"c1": "p1"},
+            "pods": {
+                "p1": {"id": "p1", "metadata": {"name": "mo-0"}},
+                "p2": {"id": "p2", "metadata": {"name": "mo-1"}},
+            },
+        });
+        let now = UNIX_EPOCH + Duration::from_secs(1706263210);
+        assert_eq!(
+            lookup(&cache, Some("c1"), "other", now).unwrap()["id"],
+            "p1"
+        );
+        assert_eq!(lookup(&cache, Some("c9"), "mo-1", now).unwrap()["id"], "p2");
+        assert_eq!(lookup(&cache, None, "mo-1", now).unwrap()["id"], "p2");
+        assert_eq!(lookup(&cache, None, "mo-2", now), None);
+        let stale = UNIX_EPOCH + Duration::from_secs(1706263300);
+        assert_eq!(lookup(&cache, Some("c1"), "mo-0", stale), None);
+        assert_eq!(lookup(&json!({}), Some("c1"), "mo-0", now), None);
+    }
+}
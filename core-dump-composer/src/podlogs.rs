@@ -0,0 +1,247 @@
+use anyhow::anyhow;
+use flate2::read::MultiGzDecoder;
+use libcrio::Cli;
+use std::collections::VecDeque;
+use std::fs;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+pub const DEFAULT_POD_LOG_DIR: &str = "/var/log/pods";
+const TAIL_BLOCK: u64 = 64 << 10;
+
+/// The kubelet's log directory for a container,
+/// `<root>/<namespace>_<pod>_<uid>/<container>`.
+pub fn container_log_dir(
+    root: &Path,
+    namespace: &str,
+    pod: &str,
+    uid: &str,
+    container: &str,
+) -> PathBuf {
+    root.join(format!("{namespace}_{pod}_{uid}"))
+        .join(container)
+}
+
+/// Orders `<restart>.log` and its rotations `<restart>.log.<timestamp>` and
+/// `<restart>.log.<timestamp>.gz`: by restart, then rotation time, with the
+/// file being written last.
+fn sort_key(name: &str) -> Option<(u64, String)> {
+    let (restart, rest) = name.split_once(".log")?;
+    let restart = restart.parse().ok()?;
+    let rotation = match rest.strip_prefix('.') {
+        None if rest.is_empty() => "~".to_string(),
+        Some(ts) => {
+            let ts = ts.trim_end_matches(".gz");
+            if ts.is_empty() || !ts.chars().all(|c| c.is_ascii_digit() || c == '-') {
+                return None;
+            }
+            ts.to_string()
+        }
+        None => return None,
+    };
+    Some((restart, rotation))
+}
+
+/// The container's log files oldest first, previous restarts included.
+pub fn log_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<((u64, String), PathBuf)> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .flatten()
+            .map(|e| e.path())
+            .filter(|p| p.is_file())
+            .filter_map(|p| {
+                let key = sort_key(&p.file_name()?.to_string_lossy())?;
+                Some((key, p))
+            })
+            .collect(),
+        Err(_) => vec![],
+    };
+    files.sort();
+    files.into_iter().map(|(_, p)| p).collect()
+}
+
+/// Splits on `\n` keeping every other byte, `\r` and invalid UTF-8
+/// included.
+fn split_lines(buf: &[u8]) -> Vec<Vec<u8>> {
+    let buf = buf.strip_suffix(b"\n").unwrap_or(buf);
+    if buf.is_empty() {
+        return vec![];
+    }
+    buf.split(|b| *b == b'\n').map(|l| l.to_vec()).collect()
+}
+
+/// The last `count` lines of a plain file, read backwards in blocks so the
+/// size of the file doesn't matter.
+fn tail_plain(path: &Path, count: usize) -> std::io::Result<Vec<Vec<u8>>> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let mut start = len;
+    let mut buf = vec![];
+    while start > 0 && buf.iter().filter(|b| **b == b'\n').count() <= count {
+        let block = TAIL_BLOCK.min(start);
+        start -= block;
+        file.seek(SeekFrom::Start(start))?;
+        let mut chunk = vec![0u8; block as usize];
+        file.read_exact(&mut chunk)?;
+        chunk.extend(buf);
+        buf = chunk;
+    }
+    let mut lines = split_lines(&buf);
+    // The first line is partial unless the file was read from the start.
+    if start > 0 && !lines.is_empty() {
+        lines.remove(0);
+    }
+    let skip = lines.len().saturating_sub(count);
+    Ok(lines.split_off(skip))
+}
+
+/// Rotations compressed by the kubelet have to be read in full.
+fn tail_gzip(path: &Path, count: usize) -> std::io::Result<Vec<Vec<u8>>> {
+    let mut reader = BufReader::new(MultiGzDecoder::new(File::open(path)?));
+    let mut lines = VecDeque::with_capacity(count);
+    loop {
+        let mut line = vec![];
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        if line.last() == Some(&b'\n') {
+            line.pop();
+        }
+        if lines.len() == count {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+    Ok(lines.into())
+}
+
+/// The last `count` lines over the rotated files, so logs written just
+/// before a rotation are kept. The bytes are returned as they were logged.
+pub fn tail(files: &[PathBuf], count: usize) -> std::io::Result<Vec<u8>> {
+    let mut collected: Vec<Vec<Vec<u8>>> = vec![];
+    let mut remaining = count;
+    for path in files.iter().rev() {
+        if remaining == 0 {
+            break;
+        }
+        let lines = if path.extension().is_some_and(|e| e == "gz") {
+            tail_gzip(path, remaining)?
+        } else {
+            tail_plain(path, remaining)?
+        };
+        remaining -= lines.len();
+        collected.push(lines);
+    }
+    let mut out = vec![];
+    for line in collected.iter().rev().flatten() {
+        out.extend_from_slice(line);
+        out.push(b'\n');
+    }
+    Ok(out)
+}
+
+/// `crictl logs --tail` for a container as raw bytes. The container's
+/// stdout and stderr share one file in `dir` so they stay interleaved, and
+/// nothing is decoded so binary output survives.
+pub fn crictl_tail(
+    cli: &Cli,
+    container_id: &str,
+    count: u32,
+    dir: &Path,
+) -> Result<Vec<u8>, anyhow::Error> {
+    let path = dir.join(format!("{container_id}.log"));
+    let out = File::create(&path)?;
+    let mut command = Command::new("crictl");
+    command.env("PATH", &cli.bin_path);
+    if let Some(config) = &cli.config_path {
+        command.args(["-c", config]);
+    }
+    let status = command
+        .args(["logs", &format!("--tail={count}"), container_id])
+        .stdin(Stdio::null())
+        .stdout(Stdio::from(out.try_clone()?))
+        .stderr(Stdio::from(out))
+        .status();
+    let log = fs::read(&path);
+    let _ = fs::remove_file(&path);
+    let (status, log) = (status?, log?);
+    if !status.success() {
+        return Err(anyhow!(
+            "crictl logs {} exited with {}: {}",
+            container_id,
+            status,
+            String::from_utf8_lossy(&log).trim()
+        ));
+    }
+    Ok(log)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::podlogs::{container_log_dir, log_files, tail};
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::fs;
+    use std::io::Write;
+    use uuid::Uuid;
+
+    #[test]
+    fn rotated_tail_test() {
+        let root = std::env::temp_dir().join(format!("podlogs-test-{}", Uuid::new_v4()));
+        let dir = container_log_dir(&root, "mo", "cn-0", "1234", "main");
+        assert!(dir.ends_with("mo_cn-0_1234/main"));
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut gz = GzEncoder::new(vec![], Compression::fast());
+        gz.write_all(b"r0 a\nr0 b\n").unwrap();
+        fs::write(dir.join("0.log.20240101-000000.gz"), gz.finish().unwrap()).unwrap();
+        fs::write(dir.join("0.log.20240102-000000"), "r0 c\nr0 d\n").unwrap();
+        fs::write(dir.join("0.log"), "r0 e\n").unwrap();
+        let long: String = (0..20000).map(|i| format!("r1 {i}\n")).collect();
+        fs::write(dir.join("1.log"), long).unwrap();
+        fs::write(dir.join("1.log.tmp"), "ignored\n").unwrap();
+
+        let files = log_files(&dir);
+        let names: Vec<String> = files
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "0.log.20240101-000000.gz",
+                "0.log.20240102-000000",
+                "0.log",
+                "1.log"
+            ]
+        );
+
+        assert_eq!(tail(&files, 2).unwrap(), b"r1 19998\nr1 19999\n");
+        let all = tail(&files, 20004).unwrap();
+        assert!(all.starts_with(b"r0 b\nr0 c\nr0 d\nr0 e\nr1 0\n"));
+        assert_eq!(all.split(|b| *b == b'\n').count(), 20005);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn binary_tail_test() {
+        let dir = std::env::temp_dir().join(format!("podlogs-test-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let log = b"text\r\n\xff\xfe\x00bin\nlast".to_vec();
+        fs::write(dir.join("0.log"), &log).unwrap();
+        let mut gz = GzEncoder::new(vec![], Compression::fast());
+        gz.write_all(b"\xc3\x28\n").unwrap();
+        let gz_path = dir.join("0.log.20240101-000000.gz");
+        fs::write(&gz_path, gz.finish().unwrap()).unwrap();
+
+        let files = log_files(&dir);
+        let mut expected = b"\xc3\x28\n".to_vec();
+        expected.extend_from_slice(&log);
+        expected.push(b'\n');
+        assert_eq!(tail(&files, 10).unwrap(), expected);
+        assert_eq!(tail(&files, 1).unwrap(), b"last\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,231 @@
+//! The `-proc.json` snapshot of `/proc/<pid>`, taken as soon as the
+//! composer starts while the kernel still holds the crashed process for the
+//! pipe: its command line, status, limits and open file descriptors. Its
+//! `maps` goes next to it as `-maps.txt`. All of it is gone once the
+//! composer exits, and it says what the process was doing when it died.
+//!
+//! Arguments that look like credentials, `--password=...` or `--token ...`
+//! matching ENV_MASK_PATTERNS, are masked like the environment.
+
+use crate::environ;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// The descriptors of the live process as the split capture spools them,
+/// the links of `fd/` can't be copied.
+pub const FDS_FILE: &str = "fds.json";
+const MASK: &str = "********";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Fd {
+    pub fd: u32,
+    /// What the link points at, `socket:[4711]`, `pipe:[42]` or a path.
+    pub target: String,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Limit {
+    pub name: String,
+    pub soft: String,
+    pub hard: String,
+    pub units: String,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct Snapshot {
+    pub cmdline: Vec<String>,
+    pub status: BTreeMap<String, String>,
+    pub limits: Vec<Limit>,
+    pub fds: Vec<Fd>,
+    /// Kept for `-maps.txt`.
+    #[serde(skip)]
+    pub maps: Option<String>,
+}
+
+/// The NUL separated arguments of a cmdline file.
+fn parse_cmdline(cmdline: &[u8]) -> Vec<String> {
+    let Some(end) = cmdline.iter().rposition(|b| *b != 0) else {
+        return vec![];
+    };
+    cmdline[..=end]
+        .split(|b| *b == 0)
+        .map(|a| String::from_utf8_lossy(a).to_string())
+        .collect()
+}
+
+/// Masks the values of options whose name matches `patterns`, given as
+/// `--name=value` or as the argument after `--name`.
+fn mask_cmdline(args: Vec<String>, patterns: &[String]) -> Vec<String> {
+    let mut masked = Vec::with_capacity(args.len());
+    let mut mask_next = false;
+    for arg in args {
+        if mask_next && !arg.starts_with('-') {
+            masked.push(MASK.to_string());
+            mask_next = false;
+            continue;
+        }
+        mask_next = false;
+        match arg.split_once('=') {
+            Some((name, _)) if name.starts_with('-') && environ::is_masked(name, patterns) => {
+                masked.push(format!("{name}={MASK}"));
+            }
+            None if arg.starts_with('-') && environ::is_masked(&arg, patterns) => {
+                mask_next = true;
+                masked.push(arg);
+            }
+            _ => masked.push(arg),
+        }
+    }
+    masked
+}
+
+/// The `Name:\tvalue` lines of a status file.
+fn parse_status(status: &str) -> BTreeMap<String, String> {
+    status
+        .lines()
+        .filter_map(|l| l.split_once(':'))
+        .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+        .collect()
+}
+
+/// The table of a limits file, whose columns line up with its header.
+fn parse_limits(limits: &str) -> Vec<Limit> {
+    let mut lines = limits.lines();
+    let Some(header) = lines.next() else {
+        return vec![];
+    };
+    let (Some(soft), Some(hard), Some(units)) = (
+        header.find("Soft Limit"),
+        header.find("Hard Limit"),
+        header.find("Units"),
+    ) else {
+        return vec![];
+    };
+    let column = |line: &str, from: usize, to: usize| {
+        line.get(from..to.min(line.len()))
+            .unwrap_or_default()
+            .trim()
+            .to_string()
+    };
+    lines
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| Limit {
+            name: column(l, 0, soft),
+            soft: column(l, soft, hard),
+            hard: column(l, hard, units),
+            units: column(l, units, l.len()),
+        })
+        .collect()
+}
+
+/// The descriptors of `fd/`, or of the spooled FDS_FILE.
+pub fn read_fds(proc_dir: &Path) -> Vec<Fd> {
+    let Ok(entries) = fs::read_dir(proc_dir.join("fd")) else {
+        return fs::read(proc_dir.join(FDS_FILE))
+            .ok()
+            .and_then(|f| serde_json::from_slice(&f).ok())
+            .unwrap_or_default();
+    };
+    let mut fds: Vec<Fd> = entries
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            Some(Fd {
+                fd: e.file_name().to_str()?.parse().ok()?,
+                target: fs::read_link(e.path()).ok()?.display().to_string(),
+            })
+        })
+        .collect();
+    fds.sort_by_key(|f| f.fd);
+    fds
+}
+
+/// Reads what the process still has, anything missing is left empty.
+pub fn snapshot(proc_dir: &Path, patterns: &[String]) -> Snapshot {
+    let text = |name: &str| fs::read_to_string(proc_dir.join(name)).ok();
+    Snapshot {
+        cmdline: fs::read(proc_dir.join("cmdline"))
+            .map(|c| mask_cmdline(parse_cmdline(&c), patterns))
+            .unwrap_or_default(),
+        status: text("status").map(|s| parse_status(&s)).unwrap_or_default(),
+        limits: text("limits").map(|l| parse_limits(&l)).unwrap_or_default(),
+        fds: read_fds(proc_dir),
+        maps: text("maps"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::procinfo::{
+        mask_cmdline, parse_cmdline, parse_limits, parse_status, snapshot, FDS_FILE,
+    };
+    use std::fs;
+
+    #[test]
+    fn parse_test() {
+        let args = parse_cmdline(b"/usr/bin/mo-service\0-cfg\0/etc/mo.toml\0\0");
+        assert_eq!(args, ["/usr/bin/mo-service", "-cfg", "/etc/mo.toml"]);
+        let patterns = vec!["PASSWORD".to_string(), "TOKEN".to_string()];
+        let masked = mask_cmdline(
+            parse_cmdline(b"mo\0--password=hunter2\0--token\0abc\0--port\08080\0"),
+            &patterns,
+        );
+        assert_eq!(
+            masked,
+            [
+                "mo",
+                "--password=********",
+                "--token",
+                "********",
+                "--port",
+                "8080"
+            ]
+        );
+
+        let status =
+            parse_status("Name:\tmo-service\nState:\tD (disk sleep)\nVmRSS:\t  204800 kB\n");
+        assert_eq!(status["State"], "D (disk sleep)");
+        assert_eq!(status["VmRSS"], "204800 kB");
+
+        let limits = parse_limits(
+            "Limit                     Soft Limit           Hard Limit           Units     \n\
+             Max cpu time              unlimited            unlimited            seconds   \n\
+             Max open files            1048576              1048576              files     \n\
+             Max core file size        unlimited            unlimited            bytes     \n",
+        );
+        assert_eq!(limits.len(), 3);
+        assert_eq!(limits[1].name, "Max open files");
+        assert_eq!(limits[1].soft, "1048576");
+        assert_eq!(limits[2].units, "bytes");
+        assert!(parse_limits("").is_empty());
+    }
+
+    #[test]
+    fn snapshot_test() {
+        let dir = std::env::temp_dir().join(format!("procinfo-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("cmdline"), b"mo\0").unwrap();
+        fs::write(
+            dir.join("maps"),
+            "00400000-00452000 r-xp 00000000 08:02 173521 /usr/bin/mo\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join(FDS_FILE),
+            r#"[{"fd":0,"target":"/dev/null"},{"fd":3,"target":"socket:[4711]"}]"#,
+        )
+        .unwrap();
+        let snap = snapshot(&dir, &[]);
+        assert_eq!(snap.cmdline, ["mo"]);
+        assert_eq!(snap.fds[1].target, "socket:[4711]");
+        assert!(snap.maps.unwrap().contains("/usr/bin/mo"));
+        assert!(snap.status.is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+
+        let live = snapshot(std::path::Path::new("/proc/self"), &[]);
+        assert!(!live.fds.is_empty());
+        assert!(live.status.contains_key("Pid"));
+        assert!(!live.limits.is_empty());
+    }
+}
@@ -0,0 +1,191 @@
+//! Protobuf wire encoding for events, following proto/core_event.proto.
+//! The schema is small and stable so it is encoded by hand rather than
+//! pulling a code generator into the build of a binary that runs on every
+//! node.
+
+use crate::clock::ClockSanity;
+use crate::decision::{Check, Decision};
+use crate::network::NetworkIdentity;
+use crate::oom::OomCorrelation;
+use crate::resources::ContainerResources;
+use crate::volumes::Volume;
+
+const VARINT: u64 = 0;
+const LEN: u64 = 2;
+
+#[derive(Default)]
+pub struct ProtoWriter {
+    pub buf: Vec<u8>,
+}
+
+pub trait Encode {
+    fn encode(&self, w: &mut ProtoWriter);
+}
+
+impl ProtoWriter {
+    fn varint(&mut self, mut v: u64) {
+        while v >= 0x80 {
+            self.buf.push((v as u8) | 0x80);
+            v >>= 7;
+        }
+        self.buf.push(v as u8);
+    }
+
+    fn key(&mut self, field: u32, wire_type: u64) {
+        self.varint(((field as u64) << 3) | wire_type);
+    }
+
+    pub fn string(&mut self, field: u32, v: &str) {
+        if v.is_empty() {
+            return;
+        }
+        self.bytes(field, v.as_bytes());
+    }
+
+    pub fn opt_string(&mut self, field: u32, v: &Option<String>) {
+        if let Some(v) = v {
+            self.bytes(field, v.as_bytes());
+        }
+    }
+
+    pub fn bytes(&mut self, field: u32, v: &[u8]) {
+        self.key(field, LEN);
+        self.varint(v.len() as u64);
+        self.buf.extend_from_slice(v);
+    }
+
+    pub fn int64(&mut self, field: u32, v: i64) {
+        if v != 0 {
+            self.key(field, VARINT);
+            self.varint(v as u64);
+        }
+    }
+
+    pub fn opt_int64(&mut self, field: u32, v: Option<i64>) {
+        if let Some(v) = v {
+            self.key(field, VARINT);
+            self.varint(v as u64);
+        }
+    }
+
+    pub fn opt_bool(&mut self, field: u32, v: Option<bool>) {
+        if let Some(v) = v {
+            self.key(field, VARINT);
+            self.varint(v as u64);
+        }
+    }
+
+    pub fn bool(&mut self, field: u32, v: bool) {
+        if v {
+            self.opt_bool(field, Some(v));
+        }
+    }
+
+    pub fn message<T: Encode>(&mut self, field: u32, v: &T) {
+        let mut inner = ProtoWriter::default();
+        v.encode(&mut inner);
+        self.bytes(field, &inner.buf);
+    }
+
+    pub fn map_entry(&mut self, field: u32, key: &str, value: &str) {
+        let mut inner = ProtoWriter::default();
+        inner.string(1, key);
+        inner.string(2, value);
+        self.bytes(field, &inner.buf);
+    }
+}
+
+impl Encode for Volume {
+    fn encode(&self, w: &mut ProtoWriter) {
+        w.string(1, &self.name);
+        w.string(2, &self.kind);
+        w.string(3, &self.container_path);
+        w.string(4, &self.host_path);
+        w.bool(5, self.readonly);
+    }
+}
+
+impl Encode for NetworkIdentity {
+    fn encode(&self, w: &mut ProtoWriter) {
+        w.opt_string(1, &self.pod_ip);
+        for ip in &self.additional_ips {
+            w.string(2, ip);
+        }
+        w.opt_bool(3, self.host_network);
+        w.opt_string(4, &self.node_ip);
+    }
+}
+
+impl Encode for ClockSanity {
+    fn encode(&self, w: &mut ProtoWriter) {
+        w.int64(1, self.wall_clock_ms);
+        w.int64(2, self.monotonic_ms);
+        w.int64(3, self.boottime_ms);
+        w.opt_int64(4, self.wall_clock_step_ms);
+        w.opt_int64(5, self.capture_delay_ms);
+        w.opt_bool(6, self.ntp_synchronized);
+        w.opt_int64(7, self.ntp_max_error_us);
+        w.opt_int64(8, self.ntp_est_error_us);
+    }
+}
+
+impl Encode for OomCorrelation {
+    fn encode(&self, w: &mut ProtoWriter) {
+        w.bool(1, self.oom_correlated);
+        for name in &self.oom_killed_containers {
+            w.string(2, name);
+        }
+        w.opt_int64(3, self.cgroup_oom_kills.map(|v| v as i64));
+        w.opt_int64(4, self.memory_limit_bytes.map(|v| v as i64));
+        w.opt_int64(5, self.memory_request_bytes.map(|v| v as i64));
+    }
+}
+
+impl Encode for ContainerResources {
+    fn encode(&self, w: &mut ProtoWriter) {
+        w.string(1, &self.scope);
+        w.opt_int64(2, self.cpu_request_millis.map(|v| v as i64));
+        w.opt_int64(3, self.cpu_limit_millis.map(|v| v as i64));
+        w.opt_int64(4, self.memory_request_bytes.map(|v| v as i64));
+        w.opt_int64(5, self.memory_limit_bytes.map(|v| v as i64));
+    }
+}
+
+impl Encode for Check {
+    fn encode(&self, w: &mut ProtoWriter) {
+        w.string(1, &self.name);
+        w.bool(2, self.passed);
+        w.string(3, &self.detail);
+    }
+}
+
+impl Encode for Decision {
+    fn encode(&self, w: &mut ProtoWriter) {
+        w.string(1, self.outcome.as_str());
+        for check in &self.checks {
+            w.message(2, check);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::proto::ProtoWriter;
+
+    #[test]
+    fn wire_format_test() {
+        let mut w = ProtoWriter::default();
+        w.int64(1, 150);
+        w.string(2, "testing");
+        w.string(3, "");
+        w.opt_bool(4, Some(false));
+        w.int64(5, -1);
+        assert_eq!(
+            w.buf,
+            vec![
+                0x08, 0x96, 0x01, 0x12, 0x07, b't', b'e', b's', b't', b'i', b'n', b'g', 0x20, 0x00,
+                0x28, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01
+            ]
+        );
+    }
+}
@@ -0,0 +1,76 @@
+use advisory_lock::{AdvisoryFileLock, FileLockMode};
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+pub const RATE_LIMIT_FILE: &str = "ratelimit.json";
+const WINDOW_SECS: u64 = 3600;
+
+/// The capture times of the last hour per pod and executable.
+type State = BTreeMap<String, Vec<u64>>;
+
+/// The rate limit key of a crash, the pod and the executable.
+pub fn key(namespace: &str, podname: &str, exe: &str) -> String {
+    format!("{namespace}/{podname}/{exe}")
+}
+
+/// Decides whether `key` may have another full capture at `now` under
+/// MAX_DUMPS_PER_HOUR and records it when it may. Returns that and the
+/// captures of the last hour before this one. The state in `path` stays
+/// locked in between so concurrent crashes of one pod are counted once
+/// each, and an unreadable state starts over rather than blocking capture.
+pub fn admit(path: &Path, key: &str, now: u64, max: usize) -> Result<(bool, usize), anyhow::Error> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+    AdvisoryFileLock::lock(&file, FileLockMode::Exclusive)?;
+    let mut content = String::new();
+    file.read_to_string(&mut content)?;
+    let mut state: State = serde_json::from_str(&content).unwrap_or_default();
+    let since = now.saturating_sub(WINDOW_SECS);
+    for times in state.values_mut() {
+        times.retain(|t| *t > since);
+    }
+    state.retain(|_, times| !times.is_empty());
+    let recent = state.get(key).map_or(0, Vec::len);
+    let admitted = recent < max;
+    if admitted {
+        state.entry(key.to_string()).or_default().push(now);
+    }
+    file.seek(SeekFrom::Start(0))?;
+    file.set_len(0)?;
+    file.write_all(serde_json::to_string(&state)?.as_bytes())?;
+    AdvisoryFileLock::unlock(&file)?;
+    Ok((admitted, recent))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ratelimit::{admit, key};
+    use std::fs;
+
+    #[test]
+    fn admit_test() {
+        let path = std::env::temp_dir().join(format!("ratelimit-{}", uuid::Uuid::new_v4()));
+        let pod = key("mo", "mo-0", "mo-service");
+        let other = key("mo", "mo-1", "mo-service");
+        let now = 1706263200;
+        assert_eq!(admit(&path, &pod, now, 2).unwrap(), (true, 0));
+        assert_eq!(admit(&path, &pod, now + 10, 2).unwrap(), (true, 1));
+        assert_eq!(admit(&path, &pod, now + 20, 2).unwrap(), (false, 2));
+        // Refused crashes don't count, the pod is let through an hour after
+        // its first capture.
+        assert_eq!(admit(&path, &other, now + 30, 2).unwrap(), (true, 0));
+        assert_eq!(admit(&path, &pod, now + 3601, 2).unwrap(), (true, 1));
+
+        fs::write(&path, "garbage").unwrap();
+        assert_eq!(admit(&path, &pod, now, 2).unwrap(), (true, 0));
+        fs::write(&path, "").unwrap();
+        assert_eq!(admit(&path, &pod, now, 2).unwrap(), (true, 0));
+        fs::remove_file(&path).unwrap();
+    }
+}
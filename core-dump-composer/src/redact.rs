@@ -0,0 +1,172 @@
+use serde::Serialize;
+use serde_json::Value;
+
+pub const DEFAULT_REDACT_PATTERNS: &str =
+    "*PASSWORD*,*PASSWD*,*TOKEN*,*SECRET*,*CREDENTIAL*,*APIKEY*,*API_KEY*,*PRIVATE_KEY*";
+const REDACTED: &str = "********";
+
+/// Hides the values of keys matching REDACT_PATTERNS in the JSON written
+/// into the archive and in events. The runtime's inspect output carries
+/// the container's environment and the pod spec its env entries, both of
+/// which end up holding credentials.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Redactor {
+    patterns: Vec<String>,
+}
+
+impl Redactor {
+    /// Parses the comma separated, case insensitive glob patterns, `*`
+    /// matching anything. `none` turns redaction off.
+    pub fn new(list: &str) -> Redactor {
+        if list.trim().eq_ignore_ascii_case("none") {
+            return Redactor { patterns: vec![] };
+        }
+        Redactor {
+            patterns: list
+                .split(',')
+                .map(|p| p.trim().to_uppercase())
+                .filter(|p| !p.is_empty())
+                .collect(),
+        }
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        let name = name.to_uppercase();
+        self.patterns.iter().any(|p| glob(p, &name))
+    }
+
+    /// Redacts `value` in place and returns how many values were hidden.
+    /// Besides matching keys this covers the shapes environments come in:
+    /// `NAME=value` strings, as in the OCI spec, and `name`/`key` with
+    /// `value` objects, as in pod specs and CRI container configs.
+    pub fn redact(&self, value: &mut Value) -> usize {
+        if self.patterns.is_empty() {
+            return 0;
+        }
+        match value {
+            Value::Object(fields) => {
+                let named = ["name", "key"]
+                    .iter()
+                    .filter_map(|k| fields.get(*k).and_then(Value::as_str))
+                    .any(|name| self.matches(name));
+                let mut hidden = 0;
+                for (key, field) in fields.iter_mut() {
+                    let secret = self.matches(key) || (named && key == "value");
+                    if secret && !field.is_null() {
+                        *field = Value::String(REDACTED.to_string());
+                        hidden += 1;
+                    } else {
+                        hidden += self.redact(field);
+                    }
+                }
+                hidden
+            }
+            Value::Array(items) => items
+                .iter_mut()
+                .map(|item| match item.as_str().and_then(|s| s.split_once('=')) {
+                    Some((name, _)) if self.matches(name) => {
+                        *item = Value::String(format!("{name}={REDACTED}"));
+                        1
+                    }
+                    _ => self.redact(item),
+                })
+                .sum(),
+            _ => 0,
+        }
+    }
+
+    /// The redacted form of a JSON file, None when it isn't JSON or had
+    /// nothing to hide so it is stored as it is.
+    pub fn redact_json(&self, data: &[u8]) -> Option<Vec<u8>> {
+        let mut value: Value = serde_json::from_slice(data).ok()?;
+        if self.redact(&mut value) == 0 {
+            return None;
+        }
+        if data.contains(&b'\n') {
+            serde_json::to_vec_pretty(&value).ok()
+        } else {
+            serde_json::to_vec(&value).ok()
+        }
+    }
+}
+
+/// Matches `name` against `pattern`, where `*` is any run of characters.
+fn glob(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::redact::{glob, Redactor, DEFAULT_REDACT_PATTERNS};
+    use serde_json::json;
+
+    #[test]
+    fn glob_test() {
+        assert!(glob("*TOKEN*", "SA_TOKEN_PATH"));
+        assert!(glob("*_PASSWORD", "DB_PASSWORD"));
+        assert!(!glob("*_PASSWORD", "DB_PASSWORD_FILE"));
+        assert!(glob("AWS_*_KEY", "AWS_SECRET_ACCESS_KEY"));
+        assert!(!glob("AWS_*_KEY", "AWS_KEY"));
+        assert!(glob("KEY", "KEY"));
+        assert!(!glob("KEY", "KEYS"));
+    }
+
+    #[test]
+    fn redact_test() {
+        let redactor = Redactor::new(DEFAULT_REDACT_PATTERNS);
+        let mut inspect = json!({
+            "info": {"runtimeSpec": {"process": {"env": [
+                "PATH=/usr/bin",
+                "DB_PASSWORD=hunter2",
+                "NOT_A_PAIR"
+            ]}}},
+            "status": {"config": {"envs": [{"key": "mo_token", "value": "abc"}]}},
+            "spec": {"containers": [{"env": [
+                {"name": "API_KEY", "value": "k"},
+                {"name": "HOME", "value": "/root"}
+            ]}]},
+            "clientSecret": {"nested": true},
+            "tokenExpirationSeconds": null,
+            "key": "5ad2ea44.tar"
+        });
+        assert_eq!(redactor.redact(&mut inspect), 4);
+        let env = &inspect["info"]["runtimeSpec"]["process"]["env"];
+        assert_eq!(
+            env,
+            &json!(["PATH=/usr/bin", "DB_PASSWORD=********", "NOT_A_PAIR"])
+        );
+        assert_eq!(inspect["status"]["config"]["envs"][0]["value"], "********");
+        assert_eq!(inspect["status"]["config"]["envs"][0]["key"], "mo_token");
+        assert_eq!(
+            inspect["spec"]["containers"][0]["env"][0]["value"],
+            "********"
+        );
+        assert_eq!(inspect["spec"]["containers"][0]["env"][1]["value"], "/root");
+        assert_eq!(inspect["clientSecret"], "********");
+        assert_eq!(inspect["key"], "5ad2ea44.tar");
+
+        assert_eq!(redactor.redact_json(br#"{"exe":"node"}"#), None);
+        assert_eq!(redactor.redact_json(b"not json"), None);
+        assert_eq!(
+            redactor.redact_json(br#"{"token":"t"}"#).unwrap(),
+            br#"{"token":"********"}"#
+        );
+        let none = Redactor::new("None");
+        assert_eq!(none.redact_json(br#"{"token":"t"}"#), None);
+    }
+}
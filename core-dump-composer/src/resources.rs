@@ -0,0 +1,140 @@
+use crate::oom::parse_memory_value;
+use serde::Serialize;
+use serde_json::Value;
+
+/// The kubelet gives containers without a CPU request the minimum shares.
+const MIN_SHARES: u64 = 2;
+
+/// The CPU and memory the crashed container was given, to tell crashes of
+/// containers running at their limits apart.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct ContainerResources {
+    /// `container`, or `pod` when only the sandbox's totals for all its
+    /// containers were found.
+    pub scope: String,
+    pub cpu_request_millis: Option<u64>,
+    pub cpu_limit_millis: Option<u64>,
+    /// memory.min, which the kubelet only sets to the request when the
+    /// MemoryQoS feature is enabled.
+    pub memory_request_bytes: Option<u64>,
+    pub memory_limit_bytes: Option<u64>,
+}
+
+/// Runtimes print int64 fields as strings or numbers.
+fn number(v: &Value) -> Option<u64> {
+    match v {
+        Value::String(s) => parse_memory_value(s),
+        v => v.as_u64().filter(|v| *v > 0),
+    }
+}
+
+/// From CRI `LinuxContainerResources` in camelCase, as `crictl` prints
+/// them, or snake_case, as containerd's `info` holds them.
+fn from_cri(r: &Value, scope: &str) -> ContainerResources {
+    let field = |camel: &str, snake: &str| number(&r[camel]).or_else(|| number(&r[snake]));
+    let shares = field("cpuShares", "cpu_shares");
+    let quota = field("cpuQuota", "cpu_quota");
+    let period = field("cpuPeriod", "cpu_period");
+    ContainerResources {
+        scope: scope.to_string(),
+        cpu_request_millis: shares.filter(|s| *s > MIN_SHARES).map(to_millis),
+        cpu_limit_millis: quota.zip(period).map(|(q, p)| q * 1000 / p),
+        memory_request_bytes: r["unified"]["memory.min"]
+            .as_str()
+            .and_then(parse_memory_value),
+        memory_limit_bytes: field("memoryLimitInBytes", "memory_limit_in_bytes"),
+    }
+}
+
+/// From the OCI runtime spec the container was started with.
+fn from_oci(r: &Value, scope: &str) -> ContainerResources {
+    let shares = number(&r["cpu"]["shares"]);
+    ContainerResources {
+        scope: scope.to_string(),
+        cpu_request_millis: shares.filter(|s| *s > MIN_SHARES).map(to_millis),
+        cpu_limit_millis: number(&r["cpu"]["quota"])
+            .zip(number(&r["cpu"]["period"]))
+            .map(|(q, p)| q * 1000 / p),
+        memory_request_bytes: r["unified"]["memory.min"]
+            .as_str()
+            .and_then(parse_memory_value),
+        memory_limit_bytes: number(&r["memory"]["limit"]),
+    }
+}
+
+/// The inverse of the kubelet's `millis * 1024 / 1000`.
+fn to_millis(shares: u64) -> u64 {
+    (shares * 1000).div_ceil(1024)
+}
+
+/// Reads the resources of the crashed container from its `crictl inspect`
+/// output, the CRI status first and then the OCI spec. Without those the
+/// totals of `crictl inspectp` are used.
+pub fn from_inspect(inspect: &Value, inspectp: &Value) -> Option<ContainerResources> {
+    let status = &inspect["status"]["resources"]["linux"];
+    let spec = &inspect["info"]["runtimeSpec"]["linux"]["resources"];
+    let pod = &inspectp["info"]["config"]["linux"]["resources"];
+    let resources = if status.is_object() {
+        from_cri(status, "container")
+    } else if spec.is_object() {
+        from_oci(spec, "container")
+    } else if pod.is_object() {
+        from_cri(pod, "pod")
+    } else {
+        return None;
+    };
+    Some(resources)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::resources::{from_inspect, ContainerResources};
+    use serde_json::json;
+
+    #[test]
+    fn resources_test() {
+        // crictl prints the int64 fields of the CRI status as strings.
+        let inspect = json!({"status": {"resources": {"linux": {
+            "cpuPeriod": "100000",
+            "cpuQuota": "50000",
+            "cpuShares": "256",
+            "memoryLimitInBytes": "536870912",
+            "unified": {"memory.min": "268435456"}
+        }}}});
+        assert_eq!(
+            from_inspect(&inspect, &json!({})),
+            Some(ContainerResources {
+                scope: "container".to_string(),
+                cpu_request_millis: Some(250),
+                cpu_limit_millis: Some(500),
+                memory_request_bytes: Some(268435456),
+                memory_limit_bytes: Some(536870912),
+            })
+        );
+
+        // A best effort container in the OCI spec, no request or limit.
+        let inspect = json!({"status": {"resources": null}, "info": {"runtimeSpec": {"linux":
+            {"resources": {"cpu": {"shares": 2, "period": 100000}, "memory": {}}}}}});
+        let resources = from_inspect(&inspect, &json!({})).unwrap();
+        assert_eq!(resources.cpu_request_millis, None);
+        assert_eq!(resources.cpu_limit_millis, None);
+        assert_eq!(resources.memory_limit_bytes, None);
+
+        let inspect = json!({"info": {"runtimeSpec": {"linux": {"resources":
+            {"cpu": {"shares": 102, "quota": 200000, "period": 100000},
+             "memory": {"limit": 1073741824}}}}}});
+        let resources = from_inspect(&inspect, &json!({})).unwrap();
+        assert_eq!(resources.cpu_request_millis, Some(100));
+        assert_eq!(resources.cpu_limit_millis, Some(2000));
+        assert_eq!(resources.memory_limit_bytes, Some(1073741824));
+
+        let inspectp = json!({"info": {"config": {"linux": {"resources":
+            {"cpu_period": 100000, "cpu_quota": 150000, "memory_limit_in_bytes": 805306368}}}}});
+        let resources = from_inspect(&json!({}), &inspectp).unwrap();
+        assert_eq!(resources.scope, "pod");
+        assert_eq!(resources.cpu_limit_millis, Some(1500));
+        assert_eq!(resources.memory_limit_bytes, Some(805306368));
+
+        assert_eq!(from_inspect(&json!({}), &json!({})), None);
+    }
+}
@@ -0,0 +1,298 @@
+use crate::cri::CriClient;
+use crate::docker::DockerClient;
+use crate::podlogs;
+use libcrio::Cli;
+use serde::Serialize;
+use serde_json::Value;
+use std::path::Path;
+use std::process::Command;
+use std::str::FromStr;
+
+/// How the runtime metadata is read, set with CONTAINER_RUNTIME.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RuntimeKind {
+    /// Through crictl, which is what CRI-O nodes get.
+    Crictl,
+    /// Through the runtime's CRI socket at CRI_ENDPOINT, any runtime
+    /// serving the CRI v1 API.
+    Cri,
+    /// Through the Docker Engine API at DOCKER_ENDPOINT, for nodes whose
+    /// pods run through dockershim or cri-dockerd.
+    Docker,
+}
+
+impl FromStr for RuntimeKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "" | "crictl" | "crio" | "cri-o" => Ok(RuntimeKind::Crictl),
+            "cri" | "containerd" => Ok(RuntimeKind::Cri),
+            "docker" | "dockershim" | "cri-dockerd" => Ok(RuntimeKind::Docker),
+            other => Err(anyhow::anyhow!("Unknown container runtime {other}")),
+        }
+    }
+}
+
+/// The container runtime the capture reads pods, containers and images
+/// from. All give the JSON `crictl` prints.
+pub enum Runtime {
+    Crictl(Cli),
+    Cri(CriClient),
+    Docker(DockerClient),
+}
+
+impl Runtime {
+    pub fn pod(&self, hostname: &str) -> Result<Value, String> {
+        match self {
+            Runtime::Crictl(cli) => cli.pod(hostname),
+            Runtime::Cri(cri) => cri
+                .pod(hostname)
+                .map_err(|e| format!("ListPodSandbox for {hostname} {e}")),
+            Runtime::Docker(docker) => docker.pod(hostname),
+        }
+    }
+
+    /// The pod the container `container_id` runs in, as `crictl pods`
+    /// lists it.
+    pub fn container_pod(&self, container_id: &str) -> Result<Value, String> {
+        match self {
+            Runtime::Crictl(cli) => {
+                let ps = crictl(cli, &["ps", "-a", "-o", "json", "--id", container_id])
+                    .ok_or_else(|| format!("crictl ps failed for container {container_id}"))?;
+                let pod_id = ps["containers"][0]["podSandboxId"]
+                    .as_str()
+                    .ok_or_else(|| format!("crictl found no container {container_id}"))?;
+                let pods = crictl(cli, &["pods", "-o", "json", "--id", pod_id])
+                    .ok_or_else(|| format!("crictl pods failed for pod {pod_id}"))?;
+                pods["items"]
+                    .get(0)
+                    .cloned()
+                    .ok_or_else(|| format!("crictl found no pod {pod_id}"))
+            }
+            Runtime::Cri(cri) => cri
+                .container_pod(container_id)
+                .map_err(|e| format!("ListContainers for {container_id} {e}")),
+            Runtime::Docker(docker) => docker.container_pod(container_id),
+        }
+    }
+
+    pub fn inspect_pod(&self, pod_id: &str) -> Result<Value, String> {
+        match self {
+            Runtime::Crictl(cli) => cli.inspect_pod(pod_id),
+            Runtime::Cri(cri) => cri
+                .inspect_pod(pod_id)
+                .map_err(|e| format!("PodSandboxStatus of {pod_id} {e}")),
+            Runtime::Docker(docker) => docker.inspect_pod(pod_id),
+        }
+    }
+
+    /// The running containers of the pod.
+    pub fn pod_containers(&self, pod_id: &str) -> Result<Value, String> {
+        match self {
+            Runtime::Crictl(cli) => cli.pod_containers(pod_id),
+            Runtime::Cri(cri) => cri
+                .pod_containers(pod_id, false)
+                .map_err(|e| format!("ListContainers of {pod_id} {e}")),
+            Runtime::Docker(docker) => docker.pod_containers(pod_id, false),
+        }
+    }
+
+    pub fn image(&self, image_ref: &str) -> Result<Value, String> {
+        match self {
+            Runtime::Crictl(cli) => {
+                let command = cli.image_command.to_string();
+                let images = crictl(cli, &[&command, "-o", "json"])
+                    .ok_or_else(|| format!("crictl {command} failed"))?;
+                select_image(&images, image_ref)
+                    .cloned()
+                    .ok_or_else(|| format!("no images matched {image_ref} in crictl {command}"))
+            }
+            Runtime::Cri(cri) => cri
+                .image(image_ref)
+                .map_err(|e| format!("ImageStatus of {image_ref} {e}")),
+            Runtime::Docker(docker) => docker.image(image_ref),
+        }
+    }
+
+    /// The tail of a container's log, see `podlogs::crictl_tail`.
+    pub fn logs(
+        &self,
+        container_id: &str,
+        count: u32,
+        dir: &Path,
+    ) -> Result<Vec<u8>, anyhow::Error> {
+        match self {
+            Runtime::Crictl(cli) => podlogs::crictl_tail(cli, container_id, count, dir),
+            Runtime::Cri(cri) => cri.logs(container_id, count),
+            Runtime::Docker(docker) => docker.logs(container_id, count),
+        }
+    }
+
+    /// The runtime's name and version as `crictl version -o json` prints
+    /// them.
+    pub fn version(&self) -> Option<Value> {
+        match self {
+            Runtime::Crictl(cli) => crictl(cli, &["version", "-o", "json"]),
+            Runtime::Cri(cri) => cri.version().ok(),
+            Runtime::Docker(docker) => docker.version().ok(),
+        }
+    }
+
+    /// The pod's containers in any state, as `crictl ps -a` lists them.
+    pub fn all_containers(&self, pod_id: &str) -> Option<Value> {
+        match self {
+            Runtime::Crictl(cli) => crictl(cli, &["ps", "-a", "-o", "json", "-p", pod_id]),
+            Runtime::Cri(cri) => cri.pod_containers(pod_id, true).ok(),
+            Runtime::Docker(docker) => docker.pod_containers(pod_id, true).ok(),
+        }
+    }
+
+    /// A container as `crictl inspect` prints it.
+    pub fn inspect_container(&self, container_id: &str) -> Option<Value> {
+        match self {
+            Runtime::Crictl(cli) => crictl(cli, &["inspect", "-o", "json", container_id]),
+            Runtime::Cri(cri) => cri.inspect_container(container_id).ok(),
+            Runtime::Docker(docker) => docker.inspect_container(container_id).ok(),
+        }
+    }
+}
+
+/// The image a container of `crictl ps` runs. `imageRef` is the image id
+/// or its repo digest depending on the runtime and is left empty by some,
+/// `image.image` is then the best there is.
+pub fn image_ref(container: &Value) -> Option<&str> {
+    container["imageRef"]
+        .as_str()
+        .filter(|r| !r.is_empty())
+        .or_else(|| container["image"]["image"].as_str())
+        .filter(|r| !r.is_empty())
+}
+
+/// Finds `image_ref` in the `crictl img` list by id or repo digest. CRI-O
+/// lists ids without the `sha256:` its containers report them with.
+pub fn select_image<'a>(images: &'a Value, image_ref: &str) -> Option<&'a Value> {
+    let id = image_ref.strip_prefix("sha256:").unwrap_or(image_ref);
+    images["images"].as_array()?.iter().find(|image| {
+        let image_id = image["id"].as_str().unwrap_or_default();
+        image_id.strip_prefix("sha256:").unwrap_or(image_id) == id
+            || image["repoDigests"]
+                .as_array()
+                .is_some_and(|digests| digests.iter().any(|d| d == image_ref))
+    })
+}
+
+fn crictl(cli: &Cli, args: &[&str]) -> Option<Value> {
+    let mut command = Command::new("crictl");
+    command.env("PATH", &cli.bin_path);
+    if let Some(config) = &cli.config_path {
+        command.args(["-c", config]);
+    }
+    let output = command.args(args).output().ok()?;
+    if !output.status.success() {
+        log::debug!(
+            "crictl {:?} failed: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        return None;
+    }
+    serde_json::from_slice(&output.stdout).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::events::image_digest;
+    use crate::filter::pod_opt_in;
+    use crate::network::from_inspect;
+    use crate::oom::oom_killed;
+    use crate::runtime::{image_ref, select_image};
+    use serde_json::{json, Value};
+    use std::fs;
+    use std::path::Path;
+
+    /// Runs the metadata extraction against the crictl output of each
+    /// runtime in `mocks/fixtures`, whose layouts differ in small ways.
+    /// Each directory's `expected.json` holds what should come out.
+    #[test]
+    fn fixtures_test() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("mocks/fixtures");
+        let mut variants = 0;
+        for dir in fs::read_dir(root).unwrap() {
+            let dir = dir.unwrap().path();
+            let read = |name: &str| -> Value {
+                serde_json::from_str(&fs::read_to_string(dir.join(name)).unwrap()).unwrap()
+            };
+            let name = dir.file_name().unwrap().to_string_lossy().to_string();
+            let expected = read("expected.json");
+
+            let pods = read("pods.json");
+            let pod = &pods["items"][0];
+            assert_eq!(
+                pod["metadata"]["namespace"], expected["namespace"],
+                "{name}"
+            );
+            assert_eq!(pod["metadata"]["name"], expected["name"], "{name}");
+            assert_eq!(pod["metadata"]["uid"], expected["uid"], "{name}");
+            assert_eq!(
+                json!(pod_opt_in(pod).unwrap()),
+                expected["opt_in"],
+                "{name}"
+            );
+
+            let network = from_inspect(&read("inspectp.json"), Some("10.0.0.4".to_string()));
+            assert_eq!(json!(network.pod_ip), expected["pod_ip"], "{name}");
+            assert_eq!(
+                json!(network.additional_ips),
+                expected["additional_ips"],
+                "{name}"
+            );
+            assert_eq!(
+                json!(network.host_network),
+                expected["host_network"],
+                "{name}"
+            );
+
+            let images = read("img.json");
+            let digests: Vec<String> = read("ps.json")["containers"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|c| {
+                    let image_ref = image_ref(c).unwrap();
+                    let image = select_image(&images, image_ref)
+                        .unwrap_or_else(|| panic!("{name}: no image {image_ref}"));
+                    image_digest(image).to_string()
+                })
+                .collect();
+            assert_eq!(json!(digests), expected["images"], "{name}");
+
+            let crash_time = expected["crash_time"].as_i64().unwrap();
+            assert_eq!(
+                json!(oom_killed(&[read("inspect.json")], crash_time)),
+                expected["oom_killed"],
+                "{name}"
+            );
+            variants += 1;
+        }
+        assert_eq!(variants, 4);
+    }
+
+    #[test]
+    fn image_ref_test() {
+        let container = json!({"image": {"image": "docker.io/mo/mo:1"}, "imageRef": ""});
+        assert_eq!(image_ref(&container), Some("docker.io/mo/mo:1"));
+        assert_eq!(
+            image_ref(&json!({"imageRef": "sha256:9f1c"})),
+            Some("sha256:9f1c")
+        );
+        assert_eq!(image_ref(&json!({})), None);
+
+        let images = json!({"images": [{"id": "9f1c", "repoDigests": []}]});
+        assert!(select_image(&images, "sha256:9f1c").is_some());
+        assert!(select_image(&images, "9f1c").is_some());
+        assert!(select_image(&images, "sha256:0000").is_none());
+        assert!(select_image(&json!({}), "9f1c").is_none());
+    }
+}
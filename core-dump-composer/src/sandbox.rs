@@ -0,0 +1,304 @@
+use anyhow::anyhow;
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+/// Syscalls neither the composer nor the tools it runs need, refused with
+/// EPERM. ptrace stays allowed for collectors such as gstack.
+const DENIED_SYSCALLS: [libc::c_long; 25] = [
+    libc::SYS_kexec_load,
+    libc::SYS_init_module,
+    libc::SYS_finit_module,
+    libc::SYS_delete_module,
+    libc::SYS_mount,
+    libc::SYS_umount2,
+    libc::SYS_pivot_root,
+    libc::SYS_open_tree,
+    libc::SYS_move_mount,
+    libc::SYS_fsopen,
+    libc::SYS_reboot,
+    libc::SYS_swapon,
+    libc::SYS_swapoff,
+    libc::SYS_setns,
+    libc::SYS_unshare,
+    libc::SYS_keyctl,
+    libc::SYS_add_key,
+    libc::SYS_request_key,
+    libc::SYS_process_vm_writev,
+    libc::SYS_acct,
+    libc::SYS_settimeofday,
+    libc::SYS_clock_settime,
+    libc::SYS_bpf,
+    libc::SYS_perf_event_open,
+    libc::SYS_open_by_handle_at,
+];
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xc000_003e;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xc000_00b7;
+/// x32 syscalls on x86_64 are numbered from here and refused outright.
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+const LANDLOCK_CREATE_RULESET_VERSION: u32 = 1;
+const LANDLOCK_RULE_PATH_BENEATH: libc::c_int = 1;
+const ACCESS_FS_EXECUTE: u64 = 1 << 0;
+const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+const ACCESS_FS_READ_FILE: u64 = 1 << 2;
+const ACCESS_FS_READ_DIR: u64 = 1 << 3;
+/// Every filesystem right of Landlock ABI 1.
+const ACCESS_FS_V1: u64 = (1 << 13) - 1;
+const ACCESS_FS_TRUNCATE: u64 = 1 << 14;
+
+#[repr(C)]
+struct RulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+struct PathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: i32,
+}
+
+fn stmt(code: u32, k: u32) -> libc::sock_filter {
+    libc::sock_filter {
+        code: code as u16,
+        jt: 0,
+        jf: 0,
+        k,
+    }
+}
+
+fn jump(code: u32, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+    libc::sock_filter {
+        code: code as u16,
+        jt,
+        jf,
+        k,
+    }
+}
+
+/// The seccomp program refusing `denied` and any syscall of another ABI.
+pub fn seccomp_filter(denied: &[libc::c_long]) -> Vec<libc::sock_filter> {
+    let deny = libc::SECCOMP_RET_ERRNO | libc::EPERM as u32;
+    let n = denied.len() as u8;
+    // seccomp_data is laid out as the syscall number then the arch.
+    let mut program = vec![
+        stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, 4),
+        jump(
+            libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K,
+            AUDIT_ARCH,
+            1,
+            0,
+        ),
+        stmt(libc::BPF_RET | libc::BPF_K, deny),
+        stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, 0),
+        jump(
+            libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K,
+            X32_SYSCALL_BIT,
+            n + 1,
+            0,
+        ),
+    ];
+    for (i, nr) in denied.iter().enumerate() {
+        // Jump to the deny after the allow, else test the next one.
+        let to_deny = n - i as u8;
+        program.push(jump(
+            libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K,
+            *nr as u32,
+            to_deny,
+            0,
+        ));
+    }
+    program.push(stmt(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_ALLOW));
+    program.push(stmt(libc::BPF_RET | libc::BPF_K, deny));
+    program
+}
+
+fn no_new_privs() -> Result<(), anyhow::Error> {
+    if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+        return Err(anyhow!(
+            "PR_SET_NO_NEW_PRIVS: {}",
+            io::Error::last_os_error()
+        ));
+    }
+    Ok(())
+}
+
+fn apply_seccomp() -> Result<(), anyhow::Error> {
+    let mut program = seccomp_filter(&DENIED_SYSCALLS);
+    let prog = libc::sock_fprog {
+        len: program.len() as u16,
+        filter: program.as_mut_ptr(),
+    };
+    // TSYNC puts every thread of the composer under the filter.
+    let rc = unsafe {
+        libc::syscall(
+            libc::SYS_seccomp,
+            libc::SECCOMP_SET_MODE_FILTER,
+            libc::SECCOMP_FILTER_FLAG_TSYNC,
+            &prog as *const libc::sock_fprog,
+        )
+    };
+    if rc != 0 {
+        return Err(anyhow!("seccomp: {}", io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+fn add_rule(ruleset: libc::c_long, path: &Path, access: u64) -> Result<(), anyhow::Error> {
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    let fd = unsafe { libc::open(c_path.as_ptr(), libc::O_PATH | libc::O_CLOEXEC) };
+    if fd < 0 {
+        return Err(anyhow!(
+            "{}: {}",
+            path.display(),
+            io::Error::last_os_error()
+        ));
+    }
+    let rule = PathBeneathAttr {
+        allowed_access: access,
+        parent_fd: fd,
+    };
+    let rc = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_add_rule,
+            ruleset,
+            LANDLOCK_RULE_PATH_BENEATH,
+            &rule as *const PathBeneathAttr,
+            0,
+        )
+    };
+    let err = io::Error::last_os_error();
+    unsafe { libc::close(fd) };
+    if rc != 0 {
+        return Err(anyhow!("landlock rule for {}: {}", path.display(), err));
+    }
+    Ok(())
+}
+
+/// Lets the calling thread and what it starts write under `writable` and
+/// `/dev` only, reading and running anything. Returns the Landlock ABI,
+/// None when the kernel has no Landlock.
+pub fn apply_landlock(writable: &[PathBuf]) -> Result<Option<i64>, anyhow::Error> {
+    let abi = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            std::ptr::null::<RulesetAttr>(),
+            0,
+            LANDLOCK_CREATE_RULESET_VERSION,
+        )
+    };
+    if abi < 1 {
+        return Ok(None);
+    }
+    let mut handled = ACCESS_FS_V1;
+    if abi >= 3 {
+        handled |= ACCESS_FS_TRUNCATE;
+    }
+    let attr = RulesetAttr {
+        handled_access_fs: handled,
+    };
+    let ruleset = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            &attr as *const RulesetAttr,
+            std::mem::size_of::<RulesetAttr>(),
+            0,
+        )
+    };
+    if ruleset < 0 {
+        return Err(anyhow!("landlock ruleset: {}", io::Error::last_os_error()));
+    }
+    let read = ACCESS_FS_EXECUTE | ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR;
+    let rules = add_rule(ruleset, Path::new("/"), read)
+        .and_then(|_| add_rule(ruleset, Path::new("/dev"), read | ACCESS_FS_WRITE_FILE))
+        .and_then(|_| {
+            writable
+                .iter()
+                .filter(|p| p.is_dir())
+                .try_for_each(|p| add_rule(ruleset, p, handled))
+        })
+        .and_then(|_| no_new_privs());
+    let rc = match rules {
+        Ok(()) => unsafe { libc::syscall(libc::SYS_landlock_restrict_self, ruleset, 0) },
+        Err(e) => {
+            unsafe { libc::close(ruleset as libc::c_int) };
+            return Err(e);
+        }
+    };
+    let err = io::Error::last_os_error();
+    unsafe { libc::close(ruleset as libc::c_int) };
+    if rc != 0 {
+        return Err(anyhow!("landlock: {}", err));
+    }
+    Ok(Some(abi))
+}
+
+/// SANDBOX: confines the composer before it reads the core. Call it before
+/// the capture thread starts so the thread inherits the Landlock rules.
+pub fn apply(writable: &[PathBuf]) -> Result<Option<i64>, anyhow::Error> {
+    let abi = apply_landlock(writable)?;
+    no_new_privs()?;
+    apply_seccomp()?;
+    Ok(abi)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sandbox::{apply_landlock, seccomp_filter, DENIED_SYSCALLS};
+    use std::fs;
+    use uuid::Uuid;
+
+    #[test]
+    fn seccomp_filter_test() {
+        let program = seccomp_filter(&DENIED_SYSCALLS);
+        assert_eq!(program.len(), 5 + DENIED_SYSCALLS.len() + 2);
+        let allow = &program[program.len() - 2];
+        assert_eq!(allow.k, libc::SECCOMP_RET_ALLOW);
+        let deny = program.len() - 1;
+        // Every comparison and the x32 check land on the final deny.
+        for (i, insn) in program
+            .iter()
+            .enumerate()
+            .skip(4)
+            .take(1 + DENIED_SYSCALLS.len())
+        {
+            assert_eq!(i + 1 + insn.jt as usize, deny, "instruction {}", i);
+        }
+        assert_eq!(
+            program[deny].k,
+            libc::SECCOMP_RET_ERRNO | libc::EPERM as u32
+        );
+    }
+
+    #[test]
+    fn landlock_test() {
+        let dir = std::env::temp_dir().join(format!("sandbox-{}", Uuid::new_v4()));
+        let (allowed, denied) = (dir.join("allowed"), dir.join("denied"));
+        fs::create_dir_all(&allowed).unwrap();
+        fs::create_dir_all(&denied).unwrap();
+        let l_allowed = allowed.clone();
+        let l_denied = denied.clone();
+        // Landlock confines the calling thread, so the test gets its own.
+        let result = std::thread::spawn(move || {
+            let abi = apply_landlock(std::slice::from_ref(&l_allowed)).unwrap();
+            let wrote = fs::write(l_allowed.join("core"), b"core").is_ok();
+            let escaped = fs::write(l_denied.join("core"), b"core").is_ok();
+            (abi, wrote, escaped)
+        })
+        .join()
+        .unwrap();
+        match result {
+            (None, _, _) => {} // No Landlock in this kernel.
+            (Some(_), wrote, escaped) => {
+                assert!(wrote);
+                assert!(!escaped);
+            }
+        }
+        assert!(fs::read_dir(&denied).is_ok());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
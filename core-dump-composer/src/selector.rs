@@ -0,0 +1,208 @@
+use anyhow::anyhow;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::fmt;
+use std::str::FromStr;
+
+/// One requirement of a label selector, as in `kubectl get -l`.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Requirement {
+    Exists(String),
+    NotExists(String),
+    Equals(String, String),
+    NotEquals(String, String),
+    In(String, Vec<String>),
+    NotIn(String, Vec<String>),
+}
+
+/// POD_SELECTOR_LABEL, comma separated requirements that must all hold:
+/// `key`, `!key`, `key=value`, `key!=value`, `key in (a,b)` and
+/// `key notin (a,b)`. A bare key is what the setting used to accept.
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Selector {
+    pub requirements: Vec<Requirement>,
+}
+
+fn key(s: &str) -> Result<String, anyhow::Error> {
+    let key = s.trim();
+    if key.is_empty() || key.contains(char::is_whitespace) {
+        return Err(anyhow!("invalid label key {:?}", key));
+    }
+    Ok(key.to_string())
+}
+
+fn values(s: &str) -> Result<Vec<String>, anyhow::Error> {
+    let inner = s
+        .trim()
+        .strip_prefix('(')
+        .and_then(|v| v.strip_suffix(')'))
+        .ok_or_else(|| anyhow!("expected a (value, ...) list, got {:?}", s.trim()))?;
+    Ok(inner
+        .split(',')
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+        .collect())
+}
+
+/// Splits on the commas that aren't inside a value list.
+fn split_top_level(s: &str) -> Vec<&str> {
+    let mut parts = vec![];
+    let (mut depth, mut start) = (0, 0);
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+impl FromStr for Requirement {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(k) = s.strip_prefix('!') {
+            return Ok(Requirement::NotExists(key(k)?));
+        }
+        if let Some((k, rest)) = s.split_once(char::is_whitespace) {
+            let rest = rest.trim_start();
+            if let Some(list) = rest.strip_prefix("notin") {
+                return Ok(Requirement::NotIn(key(k)?, values(list)?));
+            }
+            if let Some(list) = rest.strip_prefix("in") {
+                return Ok(Requirement::In(key(k)?, values(list)?));
+            }
+        }
+        if let Some((k, v)) = s.split_once("!=") {
+            return Ok(Requirement::NotEquals(key(k)?, v.trim().to_string()));
+        }
+        if let Some((k, v)) = s.split_once("==").or_else(|| s.split_once('=')) {
+            return Ok(Requirement::Equals(key(k)?, v.trim().to_string()));
+        }
+        Ok(Requirement::Exists(key(s)?))
+    }
+}
+
+impl FromStr for Selector {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let requirements = split_top_level(s)
+            .into_iter()
+            .filter(|r| !r.trim().is_empty())
+            .map(|r| {
+                r.parse::<Requirement>()
+                    .map_err(|e| anyhow!("{:?} in POD_SELECTOR_LABEL: {}", r.trim(), e))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Selector { requirements })
+    }
+}
+
+impl Requirement {
+    pub fn matches(&self, labels: &Map<String, Value>) -> bool {
+        let label = |k: &str| labels.get(k).and_then(|v| v.as_str());
+        let one_of = |k: &str, vs: &[String]| label(k).is_some_and(|l| vs.iter().any(|v| v == l));
+        match self {
+            Requirement::Exists(k) => labels.contains_key(k),
+            Requirement::NotExists(k) => !labels.contains_key(k),
+            Requirement::Equals(k, v) => label(k) == Some(v.as_str()),
+            Requirement::NotEquals(k, v) => label(k) != Some(v.as_str()),
+            Requirement::In(k, vs) => one_of(k, vs),
+            Requirement::NotIn(k, vs) => !one_of(k, vs),
+        }
+    }
+}
+
+impl fmt::Display for Requirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Requirement::Exists(k) => write!(f, "{k}"),
+            Requirement::NotExists(k) => write!(f, "!{k}"),
+            Requirement::Equals(k, v) => write!(f, "{k}={v}"),
+            Requirement::NotEquals(k, v) => write!(f, "{k}!={v}"),
+            Requirement::In(k, vs) => write!(f, "{k} in ({})", vs.join(",")),
+            Requirement::NotIn(k, vs) => write!(f, "{k} notin ({})", vs.join(",")),
+        }
+    }
+}
+
+impl Selector {
+    pub fn is_empty(&self) -> bool {
+        self.requirements.is_empty()
+    }
+
+    /// The first requirement `labels` fail, None when the pod is selected.
+    pub fn unmet(&self, labels: &Map<String, Value>) -> Option<&Requirement> {
+        self.requirements.iter().find(|r| !r.matches(labels))
+    }
+}
+
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let requirements: Vec<String> = self.requirements.iter().map(|r| r.to_string()).collect();
+        write!(f, "{}", requirements.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::selector::{Requirement, Selector};
+    use serde_json::json;
+
+    #[test]
+    fn parse_test() {
+        let selector: Selector =
+            "app=mo, tier != db,env in (prod, staging),!canary,team notin (a),core-dump"
+                .parse()
+                .unwrap();
+        assert_eq!(
+            selector.requirements,
+            vec![
+                Requirement::Equals("app".to_string(), "mo".to_string()),
+                Requirement::NotEquals("tier".to_string(), "db".to_string()),
+                Requirement::In(
+                    "env".to_string(),
+                    vec!["prod".to_string(), "staging".to_string()]
+                ),
+                Requirement::NotExists("canary".to_string()),
+                Requirement::NotIn("team".to_string(), vec!["a".to_string()]),
+                Requirement::Exists("core-dump".to_string()),
+            ]
+        );
+        assert_eq!(
+            selector.to_string(),
+            "app=mo,tier!=db,env in (prod,staging),!canary,team notin (a),core-dump"
+        );
+        assert!("".parse::<Selector>().unwrap().is_empty());
+        assert!("env in prod".parse::<Selector>().is_err());
+        assert!("=mo".parse::<Selector>().is_err());
+    }
+
+    #[test]
+    fn unmet_test() {
+        let labels = json!({"app": "mo", "env": "prod", "core-dump": "true"});
+        let labels = labels.as_object().unwrap();
+        let selector: Selector = "app=mo,env in (prod,staging),!canary,core-dump"
+            .parse()
+            .unwrap();
+        assert_eq!(selector.unmet(labels), None);
+        let selector: Selector = "app==mo,env notin (prod)".parse().unwrap();
+        assert_eq!(
+            selector.unmet(labels).unwrap().to_string(),
+            "env notin (prod)"
+        );
+        let selector: Selector = "tier in (db),app!=other".parse().unwrap();
+        assert_eq!(selector.unmet(labels).unwrap().to_string(), "tier in (db)");
+        let selector: Selector = "tier notin (db),tier!=db".parse().unwrap();
+        assert_eq!(selector.unmet(labels), None);
+    }
+}
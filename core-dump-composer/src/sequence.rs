@@ -0,0 +1,47 @@
+use advisory_lock::{AdvisoryFileLock, FileLockMode};
+use std::fs::OpenOptions;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Hands out the next number of the node wide capture counter kept in
+/// `path`. The file is locked while it is bumped so concurrent crashes
+/// never share a number.
+pub fn next(path: &Path) -> Result<u64, anyhow::Error> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+    AdvisoryFileLock::lock(&file, FileLockMode::Exclusive)?;
+    let mut content = String::new();
+    file.read_to_string(&mut content)?;
+    let sequence = match content.trim() {
+        "" => 1,
+        v => v.parse::<u64>()? + 1,
+    };
+    file.seek(SeekFrom::Start(0))?;
+    file.set_len(0)?;
+    file.write_all(sequence.to_string().as_bytes())?;
+    AdvisoryFileLock::unlock(&file)?;
+    Ok(sequence)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sequence::next;
+    use std::fs;
+
+    #[test]
+    fn next_test() {
+        let path = std::env::temp_dir().join(format!("sequence-{}", uuid::Uuid::new_v4()));
+        assert_eq!(next(&path).unwrap(), 1);
+        assert_eq!(next(&path).unwrap(), 2);
+        fs::write(&path, "41\n").unwrap();
+        assert_eq!(next(&path).unwrap(), 42);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "42");
+        fs::write(&path, "garbage").unwrap();
+        assert!(next(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,247 @@
+use crate::elf;
+use crate::elf::{NT_FILE, PT_NOTE};
+use advisory_lock::{AdvisoryFileLock, FileLockMode};
+use ring::digest::{digest, SHA256};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+pub const SIGNATURE_FILE: &str = "signatures.json";
+const NT_PRSTATUS: u32 = 1;
+const EM_X86_64: u16 = 62;
+const EM_AARCH64: u16 = 183;
+
+/// The last full capture of a signature and the crashes skipped since.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Duplicate {
+    /// The dump id of the full capture.
+    pub dump_id: String,
+    /// When it was captured, seconds since the epoch.
+    pub captured: u64,
+    /// The crashes with this signature since, this one included.
+    pub count: u64,
+}
+
+/// Where the instruction pointer sits in the NT_PRSTATUS note, after the
+/// 112 bytes ahead of pr_reg, for the machines we know the registers of.
+fn pc_offset(machine: u16) -> Option<usize> {
+    match machine {
+        // rip of user_regs_struct
+        EM_X86_64 => Some(112 + 16 * 8),
+        // pc of user_pt_regs
+        EM_AARCH64 => Some(112 + 32 * 8),
+        _ => None,
+    }
+}
+
+/// The top of the stack of the crashing thread as `file+0xoffset`, from
+/// the first NT_PRSTATUS of the core and the NT_FILE mapping it falls in,
+/// so it stays the same across address space randomization. None when the
+/// notes aren't in `prefix` or the pc is outside any mapped file.
+pub fn crash_frame(prefix: &[u8]) -> Option<String> {
+    let headers = elf::program_headers(prefix).ok()?;
+    let pc_at = pc_offset(elf::u16_at(prefix, 18))?;
+    let mut pc = None;
+    let mut files = vec![];
+    for h in headers.iter().filter(|h| h.p_type == PT_NOTE) {
+        let notes = prefix.get(h.offset as usize..(h.offset + h.filesz) as usize)?;
+        elf::for_each_note(notes, |n_type, _, desc| match n_type {
+            NT_PRSTATUS if pc.is_none() && desc.len() >= pc_at + 8 => {
+                pc = Some(elf::u64_at(desc, pc_at));
+            }
+            NT_FILE if files.is_empty() => files = file_mappings(desc),
+            _ => {}
+        });
+    }
+    let pc = pc?;
+    let (file, start, offset) = files
+        .iter()
+        .find(|(_, start, end, _)| (*start..*end).contains(&pc))
+        .map(|(file, start, _, offset)| (file, start, offset))?;
+    let name = file.rsplit('/').next().unwrap_or(file);
+    Some(format!("{}+0x{:x}", name, pc - start + offset))
+}
+
+/// The `(file, start, end, file offset)` entries of an NT_FILE note.
+fn file_mappings(desc: &[u8]) -> Vec<(String, u64, u64, u64)> {
+    if desc.len() < 16 {
+        return vec![];
+    }
+    let count = elf::u64_at(desc, 0) as usize;
+    let page_size = elf::u64_at(desc, 8);
+    let names_at = 16 + count.saturating_mul(24);
+    let Some(names) = desc.get(names_at..) else {
+        return vec![];
+    };
+    names
+        .split(|b| *b == 0)
+        .take(count)
+        .enumerate()
+        .map(|(i, name)| {
+            let at = 16 + i * 24;
+            (
+                String::from_utf8_lossy(name).to_string(),
+                elf::u64_at(desc, at),
+                elf::u64_at(desc, at + 8),
+                elf::u64_at(desc, at + 16).wrapping_mul(page_size),
+            )
+        })
+        .collect()
+}
+
+/// The signature of a crash, a short hash of the executable's build-id, or
+/// its name without one, the signal and the top of the stack when known.
+pub fn compute(build_id: Option<&str>, exe: &str, signal: &str, frame: Option<&str>) -> String {
+    let input = format!(
+        "{}\n{}\n{}",
+        build_id.unwrap_or(exe),
+        signal,
+        frame.unwrap_or_default()
+    );
+    digest(&SHA256, input.as_bytes()).as_ref()[..8]
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Decides whether a crash at `now` repeats `signature` of a full capture
+/// less than `window` seconds ago. A repeat counts one more crash and is
+/// returned, anything else records `dump_id` as the signature's capture.
+/// Like the rate limit the state stays locked in between and an unreadable
+/// one starts over.
+pub fn check(
+    path: &Path,
+    signature: &str,
+    dump_id: &str,
+    now: u64,
+    window: u64,
+) -> Result<Option<Duplicate>, anyhow::Error> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+    AdvisoryFileLock::lock(&file, FileLockMode::Exclusive)?;
+    let mut content = String::new();
+    file.read_to_string(&mut content)?;
+    let mut state: BTreeMap<String, Duplicate> = serde_json::from_str(&content).unwrap_or_default();
+    state.retain(|_, d| d.captured + window > now);
+    let duplicate = match state.get_mut(signature) {
+        Some(d) => {
+            d.count += 1;
+            Some(d.clone())
+        }
+        None => {
+            state.insert(
+                signature.to_string(),
+                Duplicate {
+                    dump_id: dump_id.to_string(),
+                    captured: now,
+                    count: 0,
+                },
+            );
+            None
+        }
+    };
+    file.seek(SeekFrom::Start(0))?;
+    file.set_len(0)?;
+    file.write_all(serde_json::to_string(&state)?.as_bytes())?;
+    AdvisoryFileLock::unlock(&file)?;
+    Ok(duplicate)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::signature::{check, compute, crash_frame};
+    use std::fs;
+
+    fn note(n_type: u32, desc: &[u8]) -> Vec<u8> {
+        let mut n = vec![];
+        n.extend(5u32.to_le_bytes());
+        n.extend((desc.len() as u32).to_le_bytes());
+        n.extend(n_type.to_le_bytes());
+        n.extend(b"CORE\0\0\0\0");
+        n.extend(desc);
+        n
+    }
+
+    /// A core prefix with a PT_NOTE holding an NT_PRSTATUS with `pc` and an
+    /// NT_FILE mapping /usr/bin/mo-service from page 2 at 0x400000.
+    fn core(machine: u16, pc: u64) -> Vec<u8> {
+        let mut prstatus = vec![0u8; 336];
+        prstatus[240..248].copy_from_slice(&pc.to_le_bytes());
+        let mut file = vec![];
+        for v in [1u64, 0x1000, 0x400000, 0x401000, 2] {
+            file.extend(v.to_le_bytes());
+        }
+        file.extend(b"/usr/bin/mo-service\0");
+        let mut notes = note(1, &prstatus);
+        notes.extend(note(0x4649_4c45, &file));
+
+        let mut elf = vec![0u8; 64 + 56];
+        elf[0..4].copy_from_slice(b"\x7fELF");
+        elf[4] = 2;
+        elf[5] = 1;
+        elf[18..20].copy_from_slice(&machine.to_le_bytes());
+        elf[32..40].copy_from_slice(&64u64.to_le_bytes());
+        elf[54..56].copy_from_slice(&56u16.to_le_bytes());
+        elf[56..58].copy_from_slice(&1u16.to_le_bytes());
+        elf[64..68].copy_from_slice(&4u32.to_le_bytes());
+        elf[72..80].copy_from_slice(&120u64.to_le_bytes());
+        elf[96..104].copy_from_slice(&(notes.len() as u64).to_le_bytes());
+        elf.extend(notes);
+        elf
+    }
+
+    #[test]
+    fn crash_frame_test() {
+        assert_eq!(
+            crash_frame(&core(62, 0x400123)).as_deref(),
+            Some("mo-service+0x2123")
+        );
+        assert_eq!(crash_frame(&core(62, 0x7f0000000000)), None);
+        assert_eq!(crash_frame(&core(8, 0x400123)), None);
+        assert_eq!(crash_frame(b"\x7fELF"), None);
+    }
+
+    #[test]
+    fn compute_test() {
+        let a = compute(
+            Some("4f1e2a"),
+            "mo-service",
+            "11",
+            Some("mo-service+0x2123"),
+        );
+        assert_eq!(a.len(), 16);
+        assert_eq!(
+            a,
+            compute(Some("4f1e2a"), "other", "11", Some("mo-service+0x2123"))
+        );
+        assert_ne!(a, compute(Some("4f1e2a"), "mo-service", "6", None));
+        assert_ne!(
+            compute(None, "mo-service", "11", None),
+            compute(None, "mo-proxy", "11", None)
+        );
+    }
+
+    #[test]
+    fn check_test() {
+        let path = std::env::temp_dir().join(format!("signatures-{}", uuid::Uuid::new_v4()));
+        let now = 1706263200;
+        assert_eq!(check(&path, "a", "d1", now, 600).unwrap(), None);
+        let d = check(&path, "a", "d2", now + 10, 600).unwrap().unwrap();
+        assert_eq!((d.dump_id.as_str(), d.count), ("d1", 1));
+        let d = check(&path, "a", "d3", now + 20, 600).unwrap().unwrap();
+        assert_eq!(d.count, 2);
+        assert_eq!(check(&path, "b", "d4", now + 30, 600).unwrap(), None);
+        // The window runs from the full capture, not the last crash.
+        assert_eq!(check(&path, "a", "d5", now + 600, 600).unwrap(), None);
+
+        fs::write(&path, "garbage").unwrap();
+        assert_eq!(check(&path, "a", "d6", now, 600).unwrap(), None);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,80 @@
+use crate::host::Clock;
+use advisory_lock::{AdvisoryFileLock, FileLockError, FileLockMode};
+use std::fs::{File, OpenOptions};
+use std::path::Path;
+use std::time::Duration;
+
+pub const SLOTS_DIR: &str = "capture-slots";
+const POLL: Duration = Duration::from_millis(100);
+
+/// One of the MAX_CONCURRENT_CAPTURES slots, held until it is dropped. The
+/// lock goes with the descriptor, so a composer that dies frees its slot.
+pub struct Slot {
+    pub index: usize,
+    _file: File,
+}
+
+/// Takes a free slot of `max` in `dir`, None when all are held.
+pub fn try_acquire(dir: &Path, max: usize) -> Result<Option<Slot>, anyhow::Error> {
+    std::fs::create_dir_all(dir)?;
+    for index in 0..max {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(dir.join(format!("slot-{index}")))?;
+        match AdvisoryFileLock::try_lock(&file, FileLockMode::Exclusive) {
+            Ok(()) => return Ok(Some(Slot { index, _file: file })),
+            Err(FileLockError::AlreadyLocked) => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(None)
+}
+
+/// Waits up to `wait` for a free slot. The kernel stops handing out pipes at
+/// core_pipe_limit, the slots hold back what it doesn't count: split capture
+/// workers, replays and the cores the agent feeds in file mode.
+pub fn acquire(
+    dir: &Path,
+    max: usize,
+    wait: Duration,
+    clock: &dyn Clock,
+) -> Result<Option<Slot>, anyhow::Error> {
+    let deadline = clock.now() + wait;
+    loop {
+        if let Some(slot) = try_acquire(dir, max)? {
+            return Ok(Some(slot));
+        }
+        if clock.now() >= deadline {
+            return Ok(None);
+        }
+        clock.sleep(POLL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::host::tests::ManualClock;
+    use crate::host::Clock;
+    use crate::slots::{acquire, try_acquire};
+    use std::fs;
+    use std::time::Duration;
+
+    #[test]
+    fn acquire_test() {
+        let dir = std::env::temp_dir().join(format!("slots-{}", uuid::Uuid::new_v4()));
+        let first = try_acquire(&dir, 2).unwrap().unwrap();
+        let second = try_acquire(&dir, 2).unwrap().unwrap();
+        assert_eq!((first.index, second.index), (0, 1));
+        let clock = ManualClock::new();
+        let start = clock.now();
+        assert!(acquire(&dir, 2, Duration::from_secs(30), &clock)
+            .unwrap()
+            .is_none());
+        assert_eq!(clock.elapsed(start), Duration::from_secs(30));
+        drop(first);
+        assert_eq!(try_acquire(&dir, 2).unwrap().unwrap().index, 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
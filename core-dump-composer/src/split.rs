@@ -0,0 +1,292 @@
+use crate::config::{CoreLimitMode, PauseMode};
+use crate::decision::Decision;
+use crate::procinfo;
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::os::unix::fs::{chown, DirBuilderExt, PermissionsExt};
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use uuid::Uuid;
+
+pub const MANIFEST_FILE: &str = "manifest.json";
+pub const CORE_FILE: &str = "core";
+pub const PROC_DIR: &str = "proc";
+/// The files of `/proc/<pid>` the worker reads in place of the live
+/// process's, which is gone by the time it runs.
+pub const PROC_FILES: [&str; 7] = [
+    "maps",
+    "cgroup",
+    "environ",
+    "mountinfo",
+    "status",
+    "limits",
+    "cmdline",
+];
+
+/// CAPTURE_USER, `uid[:gid]`, the user the worker runs the capture as.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CaptureUser {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl FromStr for CaptureUser {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let id = |v: &str| {
+            v.trim()
+                .parse::<u32>()
+                .map_err(|_| anyhow!("CAPTURE_USER {:?} is not uid[:gid]", s))
+        };
+        let (uid, gid) = match s.split_once(':') {
+            Some((uid, gid)) => (id(uid)?, id(gid)?),
+            None => (id(s)?, id(s)?),
+        };
+        if uid == 0 {
+            return Err(anyhow!("CAPTURE_USER must not be root"));
+        }
+        Ok(CaptureUser { uid, gid })
+    }
+}
+
+/// Written last into a spooled capture, so a directory without one was
+/// never completed.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    pub uuid: Uuid,
+    /// The composer's arguments from the core_pattern.
+    pub args: Vec<String>,
+    pub build_id: Option<String>,
+    /// The composer's environment with the .env loaded, for `replay`.
+    /// Variables matching ENV_MASK_PATTERNS are left out.
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// What the stub decided before spooling, missing from spools of
+    /// composers that left every check to the worker.
+    #[serde(default)]
+    pub gates: Option<Gates>,
+}
+
+/// The checks the stub runs before it spools the core, the pause file, the
+/// filters, MAX_CORE_BYTES and DISK_RESERVE_PERCENT. The worker carries on
+/// from them rather than checking again.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct Gates {
+    pub decision: Decision,
+    pub paused: Option<PauseMode>,
+    /// From the program headers, the core may be held back or cut short.
+    pub core_size: Option<u64>,
+    pub core_limited: Option<CoreLimitMode>,
+    pub disk_low: bool,
+    /// The core was cut at MAX_CORE_BYTES.
+    pub truncated: bool,
+}
+
+impl Manifest {
+    pub fn read(dir: &Path) -> Result<Manifest, anyhow::Error> {
+        let path = dir.join(MANIFEST_FILE);
+        let text =
+            fs::read_to_string(&path).with_context(|| format!("reading {}", path.display()))?;
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    /// Sets the recorded environment for a replay. Variables already set
+    /// win, so a replay can be run against a changed config.
+    pub fn restore_env(&self) {
+        for (name, value) in &self.env {
+            if env::var_os(name).is_none() {
+                env::set_var(name, value);
+            }
+        }
+    }
+}
+
+/// The variables of `vars` worth recording for a replay.
+pub fn recorded_env(
+    vars: impl Iterator<Item = (String, String)>,
+    patterns: &[String],
+) -> BTreeMap<String, String> {
+    vars.filter(|(name, _)| !crate::environ::is_masked(name, patterns))
+        .collect()
+}
+
+fn chown_all(dir: &Path, user: CaptureUser) -> io::Result<()> {
+    chown(dir, Some(user.uid), Some(user.gid))?;
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            chown_all(&path, user)?;
+        } else {
+            chown(&path, Some(user.uid), Some(user.gid))?;
+        }
+    }
+    Ok(())
+}
+
+/// The privileged half of a split capture. Copies up to `limit` bytes of
+/// the core from `input` and the process's `/proc` files into `dir`, hands
+/// them to `user` and writes the manifest. The rest of the core is read
+/// and thrown away, a core cut short of a non-zero `limit` is marked
+/// truncated in the manifest's gates.
+pub fn spool(
+    dir: &Path,
+    proc_dir: &Path,
+    manifest: &mut Manifest,
+    user: CaptureUser,
+    input: &mut impl Read,
+    limit: u64,
+) -> Result<(), anyhow::Error> {
+    if let Some(root) = dir.parent() {
+        // The worker removes its capture once it is done.
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(root)?;
+        chown(root, Some(user.uid), Some(user.gid))?;
+    }
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir.join(PROC_DIR))
+        .with_context(|| format!("creating {}", dir.display()))?;
+    let mut core = File::create(dir.join(CORE_FILE))?;
+    core.set_permissions(fs::Permissions::from_mode(0o600))?;
+    io::copy(&mut input.take(limit), &mut core).context("spooling the core")?;
+    core.sync_all()?;
+    // Read to the end so the kernel isn't left writing into a closed pipe.
+    let rest = io::copy(input, &mut io::sink()).context("draining the core")?;
+    if let Some(gates) = manifest.gates.as_mut() {
+        gates.truncated = rest > 0 && limit > 0;
+    }
+    for name in PROC_FILES {
+        // A file the process didn't have is left out, the worker copes.
+        let _ = fs::copy(proc_dir.join(name), dir.join(PROC_DIR).join(name));
+    }
+    fs::write(
+        dir.join(PROC_DIR).join(procinfo::FDS_FILE),
+        serde_json::to_vec(&procinfo::read_fds(proc_dir))?,
+    )?;
+    let tmp = dir.join(format!("{MANIFEST_FILE}.tmp"));
+    fs::write(&tmp, serde_json::to_vec(manifest)?)?;
+    chown_all(dir, user)?;
+    fs::rename(&tmp, dir.join(MANIFEST_FILE))?;
+    Ok(())
+}
+
+/// Starts the unprivileged worker on a spooled capture. It runs on after
+/// the kernel is released.
+pub fn start_worker(exe: &Path, dir: &Path, user: CaptureUser) -> Result<u32, anyhow::Error> {
+    let child = Command::new(exe)
+        .arg("--spool")
+        .arg(dir)
+        .uid(user.uid)
+        .gid(user.gid)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .process_group(0)
+        .spawn()
+        .with_context(|| format!("starting the worker for {}", dir.display()))?;
+    Ok(child.id())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::split::{recorded_env, spool, CaptureUser, Gates, Manifest, CORE_FILE, PROC_DIR};
+    use std::collections::BTreeMap;
+    use std::fs;
+    use uuid::Uuid;
+
+    #[test]
+    fn capture_user_test() {
+        assert_eq!(
+            "1000:2000".parse::<CaptureUser>().unwrap(),
+            CaptureUser {
+                uid: 1000,
+                gid: 2000
+            }
+        );
+        assert_eq!("65534".parse::<CaptureUser>().unwrap().gid, 65534);
+        assert!("0".parse::<CaptureUser>().is_err());
+        assert!("nobody".parse::<CaptureUser>().is_err());
+    }
+
+    #[test]
+    fn recorded_env_test() {
+        let vars = [
+            ("TIMEOUT", "600"),
+            ("S3_SECRET", "hunter2"),
+            ("WEBHOOK_TOKEN", "abc"),
+        ]
+        .map(|(k, v)| (k.to_string(), v.to_string()));
+        let patterns = crate::environ::mask_patterns(crate::environ::DEFAULT_MASK_PATTERNS);
+        let env = recorded_env(vars.into_iter(), &patterns);
+        assert_eq!(env.len(), 1);
+        assert_eq!(env["TIMEOUT"], "600");
+    }
+
+    #[test]
+    fn spool_test() {
+        let root = std::env::temp_dir().join(format!("split-{}", Uuid::new_v4()));
+        let proc_dir = root.join("proc-1");
+        fs::create_dir_all(&proc_dir).unwrap();
+        fs::write(
+            proc_dir.join("maps"),
+            "00400000-00452000 r-xp 0 08:02 1 /bin/sh\n",
+        )
+        .unwrap();
+        let manifest = Manifest {
+            uuid: Uuid::new_v4(),
+            args: vec!["cdc".to_string(), "-e=sh".to_string()],
+            build_id: Some("4f1e2a".to_string()),
+            env: BTreeMap::from([("COMPRESSION".to_string(), "true".to_string())]),
+            gates: None,
+        };
+        let dir = root.join("spool").join(manifest.uuid.to_string());
+        // Handing the files to the current user works without root.
+        let user = CaptureUser {
+            uid: unsafe { libc::getuid() },
+            gid: unsafe { libc::getgid() },
+        };
+        let mut spooled = manifest.clone();
+        spool(
+            &dir,
+            &proc_dir,
+            &mut spooled,
+            user,
+            &mut &b"core bytes"[..],
+            100,
+        )
+        .unwrap();
+        assert_eq!(fs::read(dir.join(CORE_FILE)).unwrap(), b"core bytes");
+        assert!(dir.join(PROC_DIR).join("maps").exists());
+        assert!(!dir.join(PROC_DIR).join("environ").exists());
+        assert!(dir.join(PROC_DIR).join(crate::procinfo::FDS_FILE).exists());
+        assert_eq!(Manifest::read(&dir).unwrap(), manifest);
+
+        // Cut at MAX_CORE_BYTES, or held back by the stub's gates.
+        for (limit, core, truncated) in [(4, &b"core"[..], true), (0, b"", false)] {
+            fs::remove_dir_all(&dir).unwrap();
+            let mut input = &b"core bytes"[..];
+            let mut spooled = Manifest {
+                gates: Some(Gates::default()),
+                ..manifest.clone()
+            };
+            spool(&dir, &proc_dir, &mut spooled, user, &mut input, limit).unwrap();
+            assert!(input.is_empty());
+            assert_eq!(fs::read(dir.join(CORE_FILE)).unwrap(), core);
+            let gates = Manifest::read(&dir).unwrap().gates.unwrap();
+            assert_eq!(gates.truncated, truncated);
+        }
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,255 @@
+use crate::capture::{CaptureResult, CaptureStatus, Failure};
+use anyhow::anyhow;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::env;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+const DEFAULT_TIMEOUT: u64 = 2;
+const SERVICE_NAME: &str = "core-dump-composer";
+
+/// Where the spans of each capture are exported with OTLP over HTTP, so the
+/// stage that eats the timeout budget on a busy node shows up in tracing.
+/// Enabled by setting OTLP_ENDPOINT.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct TraceConfig {
+    pub endpoint: String,
+    pub timeout_secs: u64,
+}
+
+fn nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn span_id() -> String {
+    Uuid::new_v4().simple().to_string()[..16].to_string()
+}
+
+fn attribute(key: &str, value: &str) -> Value {
+    json!({"key": key, "value": {"stringValue": value}})
+}
+
+/// The OTLP/JSON export request for a capture. Every stage timed in
+/// `result` becomes a child of one `capture` span and the trace id is the
+/// capture's uuid, so a trace can be found from the archive name.
+pub fn spans(
+    result: &CaptureResult,
+    uuid: &Uuid,
+    hostname: &str,
+    failure: Option<&Failure>,
+    end: SystemTime,
+) -> Value {
+    let trace_id = uuid.simple().to_string();
+    let root_id = span_id();
+    let span = |id: &str, name: &str, start: SystemTime, end: SystemTime, error: Option<String>| {
+        let status = match error {
+            Some(message) => json!({"code": 2, "message": message}),
+            None => json!({"code": 1}),
+        };
+        json!({
+            "traceId": trace_id,
+            "spanId": id,
+            "name": name,
+            "kind": 1,
+            "startTimeUnixNano": nanos(start),
+            "endTimeUnixNano": nanos(end),
+            "status": status,
+        })
+    };
+    let error = |stage: &str| {
+        result
+            .errors
+            .iter()
+            .find(|e| e.stage == stage)
+            .map(|e| e.error.clone())
+    };
+    let mut root = span(
+        &root_id,
+        "capture",
+        result.started_at,
+        end,
+        failure.map(|f| format!("{}: {}", f.stage, f.causes().join(": "))),
+    );
+    root["attributes"] = json!([
+        attribute("core_dump.uuid", &trace_id),
+        attribute(
+            "core_dump.status",
+            if failure.is_some() {
+                "failed"
+            } else {
+                match result.status {
+                    CaptureStatus::Success => "success",
+                    CaptureStatus::Partial => "partial",
+                }
+            }
+        ),
+    ]);
+    // Stages that failed without being timed, such as the sequence, are
+    // kept as events of the capture.
+    root["events"] = result
+        .errors
+        .iter()
+        .filter(|e| !result.durations.iter().any(|d| d.stage == e.stage))
+        .map(|e| {
+            json!({
+                "timeUnixNano": nanos(result.started_at),
+                "name": "stage error",
+                "attributes": [attribute("stage", &e.stage), attribute("error", &e.error)],
+            })
+        })
+        .collect();
+    let mut spans = vec![root];
+    for stage in &result.durations {
+        let mut child = span(
+            &span_id(),
+            &stage.stage,
+            stage.span.0,
+            stage.span.1,
+            error(&stage.stage),
+        );
+        child["parentSpanId"] = json!(root_id);
+        spans.push(child);
+    }
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [
+                    attribute("service.name", SERVICE_NAME),
+                    attribute("host.name", hostname),
+                ],
+            },
+            "scopeSpans": [{
+                "scope": {"name": SERVICE_NAME},
+                "spans": spans,
+            }],
+        }],
+    })
+}
+
+impl TraceConfig {
+    pub fn from_vars<F: Fn(&str) -> String>(var: F) -> Option<TraceConfig> {
+        let endpoint = var("OTLP_ENDPOINT");
+        if endpoint.is_empty() {
+            return None;
+        }
+        Some(TraceConfig {
+            endpoint,
+            timeout_secs: var("OTLP_TIMEOUT").parse().unwrap_or(DEFAULT_TIMEOUT),
+        })
+    }
+
+    pub fn from_env() -> Option<TraceConfig> {
+        TraceConfig::from_vars(|name| env::var(name).unwrap_or_default())
+    }
+
+    /// The collector's trace path, appended unless the endpoint has it.
+    pub fn traces_url(&self) -> String {
+        let endpoint = self.endpoint.trim_end_matches('/');
+        if endpoint.ends_with("/v1/traces") {
+            endpoint.to_string()
+        } else {
+            format!("{endpoint}/v1/traces")
+        }
+    }
+
+    pub fn export(&self, body: &Value) -> Result<u16, anyhow::Error> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(self.timeout_secs))
+            .build()?;
+        let request = client
+            .post(self.traces_url())
+            .header("Content-Type", "application/json")
+            .body(body.to_string());
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let status = runtime.block_on(async { request.send().await })?.status();
+        if !status.is_success() {
+            return Err(anyhow!("{} returned {}", self.traces_url(), status));
+        }
+        Ok(status.as_u16())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::capture::{CaptureResult, Failure, Stage};
+    use crate::trace::{spans, TraceConfig};
+    use std::time::{Instant, SystemTime};
+    use uuid::Uuid;
+
+    #[test]
+    fn config_test() {
+        assert_eq!(TraceConfig::from_vars(|_| String::new()), None);
+        let config = TraceConfig::from_vars(|name| match name {
+            "OTLP_ENDPOINT" => "http://otel-collector:4318/".to_string(),
+            _ => String::new(),
+        })
+        .unwrap();
+        assert_eq!(config.timeout_secs, 2);
+        assert_eq!(config.traces_url(), "http://otel-collector:4318/v1/traces");
+        let config = TraceConfig {
+            endpoint: "http://otel-collector:4318/v1/traces".to_string(),
+            timeout_secs: 1,
+        };
+        assert_eq!(config.traces_url(), "http://otel-collector:4318/v1/traces");
+    }
+
+    #[test]
+    fn spans_test() {
+        let uuid = Uuid::parse_str("5ad2ea44-9e4f-4d36-b6b0-3bb8ef4e73ff").unwrap();
+        let mut result = CaptureResult::new();
+        result.record_duration("pod", Instant::now());
+        result.record_error("inspectp", "crictl failed");
+        result.record_duration("inspectp", Instant::now());
+        result.record_error("sequence", "garbage");
+        let body = spans(&result, &uuid, "node-1", None, SystemTime::now());
+        let resource = &body["resourceSpans"][0];
+        assert_eq!(
+            resource["resource"]["attributes"][1]["value"]["stringValue"],
+            "node-1"
+        );
+        let exported = resource["scopeSpans"][0]["spans"].as_array().unwrap();
+        assert_eq!(exported.len(), 3);
+        let root = &exported[0];
+        assert_eq!(root["name"], "capture");
+        assert_eq!(root["traceId"], "5ad2ea449e4f4d36b6b03bb8ef4e73ff");
+        assert_eq!(root["attributes"][1]["value"]["stringValue"], "partial");
+        assert_eq!(root["events"].as_array().unwrap().len(), 1);
+        assert_eq!(exported[1]["name"], "pod");
+        assert_eq!(exported[1]["parentSpanId"], root["spanId"]);
+        assert_eq!(exported[1]["status"]["code"], 1);
+        assert_eq!(exported[2]["status"]["message"], "crictl failed");
+        let start: u128 = exported[1]["startTimeUnixNano"]
+            .as_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        let end: u128 = exported[1]["endTimeUnixNano"]
+            .as_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(start <= end);
+
+        let failure = Failure::new(
+            anyhow::anyhow!("disk full").context(Stage("core")),
+            CaptureResult::new(),
+        );
+        let body = spans(
+            &failure.result,
+            &uuid,
+            "node-1",
+            Some(&failure),
+            SystemTime::now(),
+        );
+        let root = &body["resourceSpans"][0]["scopeSpans"][0]["spans"][0];
+        assert_eq!(root["status"]["code"], 2);
+        assert_eq!(root["status"]["message"], "core: disk full");
+        assert_eq!(root["attributes"][1]["value"]["stringValue"], "failed");
+    }
+}
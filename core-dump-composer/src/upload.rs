@@ -0,0 +1,135 @@
+use anyhow::anyhow;
+use s3::bucket::Bucket;
+use s3::creds::Credentials;
+use s3::region::Region;
+use serde::{Serialize, Serializer};
+use std::env;
+use std::path::Path;
+
+/// Where the composer pushes a finished archive itself, for clusters that
+/// don't run the agent. Enabled by setting UPLOAD_BUCKET_NAME.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct UploadConfig {
+    pub bucket_name: String,
+    pub region: String,
+    pub endpoint: String,
+    pub prefix: String,
+    #[serde(serialize_with = "mask")]
+    pub access_key: String,
+    #[serde(serialize_with = "mask")]
+    pub secret: String,
+    /// Keep the archive on the node after it was uploaded.
+    pub keep: bool,
+}
+
+/// Credentials only show whether they were set, so the config can be
+/// written into archives.
+pub fn mask<S: Serializer>(value: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    if value.is_empty() {
+        serializer.serialize_str("")
+    } else {
+        serializer.serialize_str("********")
+    }
+}
+
+impl UploadConfig {
+    pub fn from_vars<F: Fn(&str) -> String>(var: F) -> Option<UploadConfig> {
+        let bucket_name = var("UPLOAD_BUCKET_NAME");
+        if bucket_name.is_empty() {
+            return None;
+        }
+        Some(UploadConfig {
+            bucket_name,
+            region: var("UPLOAD_REGION"),
+            endpoint: var("UPLOAD_ENDPOINT"),
+            prefix: var("UPLOAD_PREFIX"),
+            access_key: var("UPLOAD_ACCESS_KEY"),
+            secret: var("UPLOAD_SECRET"),
+            keep: var("UPLOAD_KEEP").to_lowercase() == "true",
+        })
+    }
+
+    pub fn from_env() -> Option<UploadConfig> {
+        UploadConfig::from_vars(|name| env::var(name).unwrap_or_default())
+    }
+
+    pub fn key(&self, file_name: &str) -> String {
+        let prefix = self.prefix.trim_matches('/');
+        if prefix.is_empty() {
+            file_name.to_string()
+        } else {
+            format!("{prefix}/{file_name}")
+        }
+    }
+
+    fn bucket(&self) -> Result<Bucket, anyhow::Error> {
+        let region = if self.endpoint.is_empty() {
+            self.region.parse()?
+        } else {
+            Region::Custom {
+                region: self.region.clone(),
+                endpoint: self.endpoint.clone(),
+            }
+        };
+        let credentials = if self.access_key.is_empty() || self.secret.is_empty() {
+            // Falls back to the instance profile or the usual AWS env vars.
+            Credentials::new(None, None, None, None, None)
+        } else {
+            Credentials::new(Some(&self.access_key), Some(&self.secret), None, None, None)
+        }?;
+        Ok(Bucket::new(&self.bucket_name, region, credentials)?)
+    }
+
+    /// Streams the file at `path` to the bucket as `name`, which may hold
+    /// the prefixes of FILENAME_TEMPLATE, and returns its key.
+    pub fn upload(&self, path: &Path, name: &str) -> Result<String, anyhow::Error> {
+        let key = self.key(name);
+        let bucket = self.bucket()?;
+        // The composer is synchronous, a runtime is only started for the
+        // upload.
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let code = runtime.block_on(async {
+            let mut file = tokio::fs::File::open(path).await?;
+            bucket.put_object_stream(&mut file, &key).await
+        })?;
+        if !(200..300).contains(&code) {
+            return Err(anyhow!("Upload of {} returned {}", key, code));
+        }
+        Ok(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::upload::UploadConfig;
+
+    #[test]
+    fn config_test() {
+        assert_eq!(UploadConfig::from_vars(|_| String::new()), None);
+        let config = UploadConfig::from_vars(|name| match name {
+            "UPLOAD_BUCKET_NAME" => "cores".to_string(),
+            "UPLOAD_PREFIX" => "/cluster-a/".to_string(),
+            "UPLOAD_KEEP" => "True".to_string(),
+            _ => String::new(),
+        })
+        .unwrap();
+        assert!(config.keep);
+        assert_eq!(config.key("a.tar"), "cluster-a/a.tar");
+        assert_eq!(config.key("mo/mo-0/a.tar"), "cluster-a/mo/mo-0/a.tar");
+        let config = UploadConfig {
+            prefix: String::new(),
+            ..config
+        };
+        assert_eq!(config.key("a.tar"), "a.tar");
+
+        let config = UploadConfig {
+            secret: "s3cr3t".to_string(),
+            ..config
+        };
+        let json = serde_json::to_value(&config).unwrap();
+        assert_eq!(json["secret"], "********");
+        assert_eq!(json["access_key"], "");
+    }
+}
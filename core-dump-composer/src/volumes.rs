@@ -0,0 +1,166 @@
+use serde::Serialize;
+use serde_json::Value;
+
+/// A volume mounted into the crashed pod.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Volume {
+    pub name: String,
+    /// persistentVolumeClaim, emptyDir, hostPath, configMap, secret,
+    /// projected or the kubelet plugin name for anything else.
+    pub kind: String,
+    pub container_path: String,
+    pub host_path: String,
+    pub readonly: bool,
+}
+
+/// Classifies a host path by the kubelet volume layout
+/// `/var/lib/kubelet/pods/<uid>/volumes/<plugin>/<name>`.
+fn classify(host_path: &str) -> Option<(String, String)> {
+    if let Some(at) = host_path.find("/volumes/") {
+        if host_path[..at].contains("/pods/") {
+            let mut parts = host_path[at + 9..].split('/');
+            let plugin = parts.next().unwrap_or_default();
+            let name = parts.next().unwrap_or_default().to_string();
+            let kind = match plugin {
+                "kubernetes.io~csi"
+                | "kubernetes.io~aws-ebs"
+                | "kubernetes.io~gce-pd"
+                | "kubernetes.io~azure-disk"
+                | "kubernetes.io~nfs"
+                | "kubernetes.io~iscsi"
+                | "kubernetes.io~local-volume" => "persistentVolumeClaim",
+                "kubernetes.io~empty-dir" => "emptyDir",
+                "kubernetes.io~configmap" => "configMap",
+                "kubernetes.io~secret" => "secret",
+                "kubernetes.io~projected" => "projected",
+                other => other.trim_start_matches("kubernetes.io~"),
+            };
+            return Some((name, kind.to_string()));
+        }
+    }
+    // Files the kubelet manages for every pod are not volumes.
+    if host_path.contains("/pods/")
+        && (host_path.contains("/etc-hosts") || host_path.contains("/containers/"))
+    {
+        return None;
+    }
+    let name = host_path.rsplit('/').next().unwrap_or_default().to_string();
+    Some((name, "hostPath".to_string()))
+}
+
+fn str_field<'a>(mount: &'a Value, keys: &[&str]) -> &'a str {
+    keys.iter()
+        .find_map(|k| mount[*k].as_str())
+        .unwrap_or_default()
+}
+
+fn collect_mounts<'a>(value: &'a Value, found: &mut Vec<&'a Value>) {
+    match value {
+        Value::Object(map) => {
+            for (key, v) in map {
+                if key == "mounts" {
+                    if let Some(mounts) = v.as_array() {
+                        found.extend(mounts.iter());
+                        continue;
+                    }
+                }
+                collect_mounts(v, found);
+            }
+        }
+        Value::Array(items) => items.iter().for_each(|v| collect_mounts(v, found)),
+        _ => {}
+    }
+}
+
+/// Extracts the volume mounts from the runtime's inspect output. CRI-O and
+/// containerd nest the mounts differently (`status.mounts`,
+/// `info.runtimeSpec.mounts`, `info.config.mounts`) so every `mounts` list
+/// is considered and duplicates are dropped.
+pub fn from_inspect(inspect: &Value) -> Vec<Volume> {
+    let mut mounts = vec![];
+    collect_mounts(inspect, &mut mounts);
+    let mut volumes: Vec<Volume> = vec![];
+    for mount in mounts {
+        let host_path = str_field(mount, &["hostPath", "host_path", "source"]);
+        let container_path = str_field(mount, &["containerPath", "container_path", "destination"]);
+        if !host_path.starts_with('/') || container_path.is_empty() {
+            continue;
+        }
+        let (name, kind) = match classify(host_path) {
+            Some(v) => v,
+            None => continue,
+        };
+        let readonly = mount["readonly"].as_bool().unwrap_or(false)
+            || mount["options"]
+                .as_array()
+                .map(|o| o.iter().any(|v| v == "ro"))
+                .unwrap_or(false);
+        let volume = Volume {
+            name,
+            kind,
+            container_path: container_path.to_string(),
+            host_path: host_path.to_string(),
+            readonly,
+        };
+        if !volumes.contains(&volume) {
+            volumes.push(volume);
+        }
+    }
+    volumes
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::volumes::from_inspect;
+    use serde_json::json;
+
+    #[test]
+    fn from_inspect_test() {
+        let inspect = json!({
+            "status": {
+                "mounts": [
+                    {
+                        "containerPath": "/var/lib/matrixone",
+                        "hostPath": "/var/lib/kubelet/pods/0c65ce05-bd3a-4db2-ad79-131186dc2086/volumes/kubernetes.io~csi/pvc-6f1c/mount",
+                        "readonly": false
+                    },
+                    {
+                        "containerPath": "/etc/hosts",
+                        "hostPath": "/var/lib/kubelet/pods/0c65ce05-bd3a-4db2-ad79-131186dc2086/etc-hosts",
+                        "readonly": false
+                    }
+                ]
+            },
+            "info": {
+                "runtimeSpec": {
+                    "mounts": [
+                        {"destination": "/proc", "type": "proc", "source": "proc"},
+                        {
+                            "destination": "/scratch",
+                            "source": "/var/lib/kubelet/pods/0c65ce05-bd3a-4db2-ad79-131186dc2086/volumes/kubernetes.io~empty-dir/scratch",
+                            "options": ["rbind", "rw"]
+                        },
+                        {
+                            "destination": "/etc/mo",
+                            "source": "/var/lib/kubelet/pods/0c65ce05-bd3a-4db2-ad79-131186dc2086/volumes/kubernetes.io~configmap/mo-config",
+                            "options": ["rbind", "ro"]
+                        },
+                        {"destination": "/host/logs", "source": "/var/log/mo", "options": ["rbind"]}
+                    ]
+                }
+            }
+        });
+        let volumes = from_inspect(&inspect);
+        assert_eq!(volumes.len(), 4);
+        let find = |p: &str| volumes.iter().find(|v| v.container_path == p).unwrap();
+        assert_eq!(find("/var/lib/matrixone").kind, "persistentVolumeClaim");
+        assert_eq!(find("/var/lib/matrixone").name, "pvc-6f1c");
+        assert_eq!(find("/scratch").kind, "emptyDir");
+        assert_eq!(find("/etc/mo").kind, "configMap");
+        assert!(find("/etc/mo").readonly);
+        assert_eq!(find("/host/logs").kind, "hostPath");
+        assert_eq!(find("/host/logs").name, "mo");
+
+        assert!(from_inspect(&json!({})).is_empty());
+    }
+}
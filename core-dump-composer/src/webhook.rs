@@ -0,0 +1,95 @@
+use crate::upload::mask;
+use anyhow::anyhow;
+use ring::hmac;
+use serde::Serialize;
+use std::env;
+use std::time::Duration;
+
+const DEFAULT_TIMEOUT: u64 = 5;
+
+/// Where the event of a finished capture is POSTed, so on-call tooling
+/// hears about a crash without polling the event directory. Enabled by
+/// setting WEBHOOK_URL.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// Signs the body with HMAC-SHA256 when set.
+    #[serde(serialize_with = "mask")]
+    pub secret: String,
+    pub timeout_secs: u64,
+}
+
+/// The `X-Core-Dump-Signature` header value of `body`.
+pub fn signature(secret: &str, body: &[u8]) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    let tag = hmac::sign(&key, body);
+    let hex: String = tag.as_ref().iter().map(|b| format!("{b:02x}")).collect();
+    format!("sha256={hex}")
+}
+
+impl WebhookConfig {
+    pub fn from_vars<F: Fn(&str) -> String>(var: F) -> Option<WebhookConfig> {
+        let url = var("WEBHOOK_URL");
+        if url.is_empty() {
+            return None;
+        }
+        Some(WebhookConfig {
+            url,
+            secret: var("WEBHOOK_SECRET"),
+            timeout_secs: var("WEBHOOK_TIMEOUT").parse().unwrap_or(DEFAULT_TIMEOUT),
+        })
+    }
+
+    pub fn from_env() -> Option<WebhookConfig> {
+        WebhookConfig::from_vars(|name| env::var(name).unwrap_or_default())
+    }
+
+    /// POSTs the JSON event and returns the status.
+    pub fn send(&self, body: Vec<u8>) -> Result<u16, anyhow::Error> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(self.timeout_secs))
+            .build()?;
+        let mut request = client
+            .post(&self.url)
+            .header("Content-Type", "application/json");
+        if !self.secret.is_empty() {
+            request = request.header("X-Core-Dump-Signature", signature(&self.secret, &body));
+        }
+        // Like the direct upload, a runtime is only started for the request.
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let status = runtime
+            .block_on(async { request.body(body).send().await })?
+            .status();
+        if !status.is_success() {
+            return Err(anyhow!("Webhook {} returned {}", self.url, status));
+        }
+        Ok(status.as_u16())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::webhook::{signature, WebhookConfig};
+
+    #[test]
+    fn config_test() {
+        assert_eq!(WebhookConfig::from_vars(|_| String::new()), None);
+        let config = WebhookConfig::from_vars(|name| match name {
+            "WEBHOOK_URL" => "https://oncall.mo.local/crash".to_string(),
+            "WEBHOOK_SECRET" => "s3cr3t".to_string(),
+            _ => String::new(),
+        })
+        .unwrap();
+        assert_eq!(config.timeout_secs, 5);
+        let json = serde_json::to_value(&config).unwrap();
+        assert_eq!(json["secret"], "********");
+
+        // RFC 4231 test case 2.
+        assert_eq!(
+            signature("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}
@@ -0,0 +1,97 @@
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+/// The namespace of the extended attributes set on archives. `user.` is
+/// the only one unprivileged tools on the node can read.
+pub const PREFIX: &str = "user.coredump.";
+
+/// The attributes an archive is tagged with, empty values are left out.
+pub fn archive_tags<'a>(
+    namespace: Option<&'a str>,
+    pod: Option<&'a str>,
+    uuid: &'a str,
+) -> Vec<(&'static str, &'a str)> {
+    [
+        ("namespace", namespace.unwrap_or_default()),
+        ("pod", pod.unwrap_or_default()),
+        ("uuid", uuid),
+    ]
+    .into_iter()
+    .filter(|(_, value)| !value.is_empty())
+    .collect()
+}
+
+/// Sets `user.coredump.<name>` on `path` for each tag. Stops at the first
+/// failure, which is `Unsupported` on filesystems without user xattrs.
+pub fn tag(path: &Path, tags: &[(&str, &str)]) -> io::Result<()> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    for (name, value) in tags {
+        let name = CString::new(format!("{PREFIX}{name}"))?;
+        // SAFETY: path and name are NUL terminated and value outlives the
+        // call.
+        let res = unsafe {
+            libc::setxattr(
+                path.as_ptr(),
+                name.as_ptr(),
+                value.as_ptr() as *const libc::c_void,
+                value.len(),
+                0,
+            )
+        };
+        if res != 0 {
+            let e = io::Error::last_os_error();
+            if e.raw_os_error() == Some(libc::ENOTSUP) {
+                return Err(io::Error::new(io::ErrorKind::Unsupported, e));
+            }
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::xattr::{archive_tags, tag, PREFIX};
+    use std::ffi::CString;
+    use std::fs;
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use uuid::Uuid;
+
+    #[test]
+    fn tag_test() {
+        assert_eq!(
+            archive_tags(Some("mo"), None, "5ad2ea44"),
+            vec![("namespace", "mo"), ("uuid", "5ad2ea44")]
+        );
+
+        let path = std::env::temp_dir().join(format!("xattr-test-{}.tar", Uuid::new_v4()));
+        fs::write(&path, b"tar").unwrap();
+        let tags = archive_tags(Some("mo"), Some("mo-0"), "5ad2ea44");
+        match tag(&path, &tags) {
+            Ok(()) => {}
+            // tmpfs before 6.6 has no user xattrs.
+            Err(e) if e.kind() == io::ErrorKind::Unsupported => {
+                fs::remove_file(&path).unwrap();
+                return;
+            }
+            Err(e) => panic!("{e}"),
+        }
+        let c_path = CString::new(path.as_os_str().as_bytes()).unwrap();
+        let name = CString::new(format!("{PREFIX}pod")).unwrap();
+        let mut value = [0u8; 16];
+        // SAFETY: value is writable for its length.
+        let len = unsafe {
+            libc::getxattr(
+                c_path.as_ptr(),
+                name.as_ptr(),
+                value.as_mut_ptr() as *mut libc::c_void,
+                value.len(),
+            )
+        };
+        assert_eq!(&value[..len as usize], b"mo-0");
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -69,19 +69,18 @@ fn default_scenario() -> Result<(), std::io::Error> {
     println!("{}", String::from_utf8_lossy(&cdc.stdout));
     println!("{}", String::from_utf8_lossy(&cdc.stderr));
 
-    Command::new("unzip")
-        .arg("output/*.zip")
-        .arg("-d")
-        .arg("output")
+    Command::new("sh")
+        .arg("-c")
+        .arg("tar -xf output/*.tar -C output --strip-components=1 && gunzip output/*.core.gz")
         .output()
-        .expect("unzip failed");
+        .expect("tar extract failed");
 
     let paths = fs::read_dir("./output").unwrap();
     println!("{:?}", paths);
     // Test to see if files are available
     let mut file_counter = 0;
     for path in paths {
-        file_counter = file_counter + 1;
+        file_counter += 1;
         let current_path = format!("{}", path.unwrap().path().display());
         if current_path.contains("dump-info.json") {
             let l_current_path = current_path.clone();
@@ -107,7 +106,17 @@ fn default_scenario() -> Result<(), std::io::Error> {
             let dump_name = json
                 .get("dump_file")
                 .expect("dump-info.json should have dump_name key");
-            assert!(dump_name.to_string().contains("4-10.core"));
+            let pod_uid = json
+                .get("pod_uid")
+                .expect("dump-info.json should have pod_uid key");
+            assert_eq!("0c65ce05-bd3a-4db2-ad79-131186dc2086", pod_uid);
+            let sequence = json["sequence"]
+                .as_u64()
+                .expect("dump-info.json should have a sequence");
+            assert!(sequence > 0);
+            assert!(dump_name.to_string().contains(&format!(
+                "4-10-0c65ce05-bd3a-4db2-ad79-131186dc2086-{sequence}.core"
+            )));
 
             let path = json
                 .get("path")
@@ -148,6 +157,69 @@ fn default_scenario() -> Result<(), std::io::Error> {
             let contents = fs::read_to_string(l_current_path)?;
             assert_eq!("A LOG\n", contents.as_str());
         }
+        if current_path.contains("capture-result.json") {
+            let l_current_path = current_path.clone();
+            println!("Testing: {}", l_current_path);
+            let file = File::open(l_current_path).expect("file should open read only");
+            let json: serde_json::Value =
+                serde_json::from_reader(file).expect("file should be proper JSON");
+            let status = json
+                .get("status")
+                .expect("capture-result.json should have status key");
+            assert_eq!("success", status);
+        }
+
+        if current_path.contains("manifest.json") {
+            let file = File::open(&current_path).expect("file should open read only");
+            let json: serde_json::Value =
+                serde_json::from_reader(file).expect("file should be proper JSON");
+            assert_eq!(json["schema_version"], 1);
+            let files = json["files"].as_array().unwrap();
+            assert_eq!(files.len(), 10);
+            // The manifest ends the archive, the capture result is just before it.
+            let last = files.last().unwrap()["name"].as_str().unwrap();
+            assert!(last.ends_with("-capture-result.json"), "{last}");
+            for entry in files {
+                let name = entry["name"].as_str().unwrap();
+                assert_eq!(entry["sha256"].as_str().unwrap().len(), 64);
+                if name.ends_with(".core.gz") {
+                    assert_eq!(entry["compression"], "gzip");
+                    assert_eq!(entry["extension"], ".core.gz");
+                }
+                // The core was gunzipped after the extraction.
+                if !name.ends_with(".gz") {
+                    let path = format!("./output/{}", name.trim_start_matches("core/"));
+                    assert_eq!(entry["size"], fs::metadata(path)?.len(), "{name}");
+                }
+            }
+        }
+
+        if current_path.ends_with(".tar.sha256") {
+            let check = Command::new("sha256sum")
+                .arg("-c")
+                .arg(current_path.rsplit('/').next().unwrap())
+                .current_dir("./output")
+                .output()
+                .expect("sha256sum failed");
+            assert!(check.status.success());
+        }
+
+        if current_path.contains("node-info.json") {
+            let file = File::open(&current_path).expect("file should open read only");
+            let json: serde_json::Value =
+                serde_json::from_reader(file).expect("file should be proper JSON");
+            assert!(json["kernel"]["release"].as_str().is_some());
+            assert!(json.get("container_runtime").is_some());
+        }
+
+        if current_path.contains("handler-config.json") {
+            let file = File::open(&current_path).expect("file should open read only");
+            let json: serde_json::Value =
+                serde_json::from_reader(file).expect("file should be proper JSON");
+            assert!(json.get("params").is_none());
+            assert!(json.get("log_length").is_some());
+        }
+
         if current_path.contains(".core") {
             let l_current_path = current_path.clone();
             println!("Testing: {}", l_current_path);
@@ -160,7 +232,7 @@ fn default_scenario() -> Result<(), std::io::Error> {
             assert!(String::from_utf8_lossy(&diff.stdout).is_empty());
         }
     }
-    assert_eq!(8, file_counter);
+    assert_eq!(13, file_counter);
     fs::remove_dir_all("./output")?;
     Ok(())
 }
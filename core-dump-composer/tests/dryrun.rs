@@ -0,0 +1,63 @@
+use std::env;
+use std::fs;
+use std::process::{Command, Stdio};
+
+#[test]
+fn dry_run_scenario() -> Result<(), std::io::Error> {
+    let current_dir = env::current_dir()?;
+    let new_path = format!(
+        "{}/mocks:{}/target/debug:{}",
+        current_dir.display(),
+        current_dir.display(),
+        env::var("PATH").unwrap_or_default()
+    );
+    // Its own folder, a dry run must leave it empty.
+    let output_folder = "./output-dryrun";
+    fs::create_dir_all(output_folder)?;
+    Command::new("cp")
+        .arg("-f")
+        .arg("./mocks/crictl-default.sh")
+        .arg("../target/debug/crictl")
+        .output()
+        .expect("cp failed");
+
+    let cat = Command::new("cat")
+        .env("PATH", &new_path)
+        .arg("./mocks/test.core")
+        .stdout(Stdio::piped())
+        .spawn()?
+        .stdout
+        .unwrap();
+
+    let cdc = Command::new("../target/debug/core-dump-composer")
+        .env("PATH", &new_path)
+        .arg("--dry-run")
+        .arg("-c")
+        .arg("1000000000")
+        .arg("-e")
+        .arg("node")
+        .arg("-p")
+        .arg("4")
+        .arg("-s")
+        .arg("10")
+        .arg("-E")
+        .arg("/target/debug/core-dump-composer")
+        .arg("-d")
+        .arg(output_folder)
+        .arg("-t")
+        .arg("1588462466")
+        .arg("-h")
+        .arg("crashing-app-699c49b4ff-86wrh")
+        .stdin(cat)
+        .output()
+        .expect("failed to execute core dump composer");
+
+    println!("{}", String::from_utf8_lossy(&cdc.stdout));
+    println!("{}", String::from_utf8_lossy(&cdc.stderr));
+    assert!(cdc.status.success());
+
+    let written: Vec<_> = fs::read_dir(output_folder)?.collect();
+    assert!(written.is_empty(), "dry run wrote {:?}", written);
+    fs::remove_dir_all(output_folder)?;
+    Ok(())
+}
@@ -0,0 +1,140 @@
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::path::Path;
+use std::process::{self, Command, Stdio};
+
+/// The .core files in `dir` and below.
+fn cores(dir: &Path) -> Vec<String> {
+    let mut found = vec![];
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                found.extend(cores(&path));
+            } else if path.to_string_lossy().contains(".core") {
+                found.push(path.display().to_string());
+            }
+        }
+    }
+    found
+}
+
+#[test]
+fn encrypted_scenario() -> Result<(), std::io::Error> {
+    let output_folder = "./output-encrypted";
+    let work_dir = env::current_dir()?.join("work-encrypted");
+    let delta_store = Path::new("../target/debug/delta-bases");
+    fs::create_dir_all(output_folder)?;
+    fs::create_dir_all(&work_dir)?;
+    let delta_cores = cores(delta_store);
+    for (mock, name) in [
+        ("./mocks/crictl-default.sh", "crictl"),
+        ("./mocks/age.sh", "age"),
+        ("./mocks/gdb.sh", "gdb"),
+    ] {
+        Command::new("cp")
+            .arg("-f")
+            .arg(mock)
+            .arg(format!("../target/debug/{name}"))
+            .output()
+            .expect("cp failed");
+    }
+
+    let cat = Command::new("cat")
+        .arg("./mocks/test.core")
+        .stdout(Stdio::piped())
+        .spawn()?
+        .stdout
+        .unwrap();
+
+    // The test process stands in for the crashed one, its executable has
+    // the build-id a delta base is kept under.
+    let cdc = Command::new("../target/debug/core-dump-composer")
+        .env(
+            "ENCRYPT_RECIPIENTS",
+            "age1qyqszqgpqyqszqgpqyqszqgpqyqszqgpqyqszqgpqyqszqgpqyqs3290gq",
+        )
+        .env("BACKTRACE", "true")
+        .env("CORE_FORMAT", "both")
+        .env("DELTA_CORES", "true")
+        .env("WORK_DIR", &work_dir)
+        .arg("-c")
+        .arg("1000000000")
+        .arg("-e")
+        .arg("node")
+        .arg("-p")
+        .arg("4")
+        .arg("-P")
+        .arg(process::id().to_string())
+        .arg("-s")
+        .arg("10")
+        .arg("-E")
+        .arg("/target/debug/core-dump-composer")
+        .arg("-d")
+        .arg(output_folder)
+        .arg("-t")
+        .arg("1588462466")
+        .arg("-h")
+        .arg("crashing-app-699c49b4ff-86wrh")
+        .stdin(cat)
+        .output()
+        .expect("failed to execute core dump composer");
+    fs::remove_file("../target/debug/age")?;
+    fs::remove_file("../target/debug/gdb")?;
+
+    println!("{}", String::from_utf8_lossy(&cdc.stdout));
+    println!("{}", String::from_utf8_lossy(&cdc.stderr));
+    assert!(cdc.status.success());
+
+    // No plaintext core is left beside the archive.
+    assert!(cores(&work_dir).is_empty(), "{:?}", cores(&work_dir));
+    assert_eq!(cores(delta_store), delta_cores);
+
+    // The agent reads dump-info from beside the archive it cannot open.
+    let sidecar = fs::read_dir(output_folder)?
+        .map(|entry| entry.unwrap().path())
+        .find(|path| path.to_string_lossy().ends_with(".tar.age.dump-info.json"))
+        .expect("no dump-info beside the archive");
+    let dump_info: serde_json::Value = serde_json::from_reader(File::open(&sidecar)?)?;
+    assert_eq!(dump_info["exe"], "node");
+
+    Command::new("sh")
+        .arg("-c")
+        .arg(format!(
+            "tar -xf {output_folder}/*.tar.age -C {output_folder} --strip-components=1"
+        ))
+        .output()
+        .expect("tar extract failed");
+    let mut skipped = vec![];
+    let mut core_found = false;
+    for path in fs::read_dir(output_folder)? {
+        let current_path = format!("{}", path.unwrap().path().display());
+        if current_path.contains("capture-result.json") {
+            let file = File::open(&current_path)?;
+            let json: serde_json::Value = serde_json::from_reader(file)?;
+            for error in json["errors"].as_array().unwrap() {
+                skipped.push(error["stage"].as_str().unwrap().to_string());
+            }
+        }
+        if current_path.ends_with(".core.gz") {
+            // Compressed as it would be unencrypted.
+            let diff = Command::new("sh")
+                .arg("-c")
+                .arg(format!(
+                    "gunzip -c {current_path} | cmp ./mocks/test.core -"
+                ))
+                .output()
+                .expect("cmp failed");
+            assert!(diff.status.success());
+            core_found = true;
+        }
+    }
+    assert!(core_found);
+    for stage in ["backtrace", "minidump", "delta"] {
+        assert!(skipped.iter().any(|s| s == stage), "{stage} in {skipped:?}");
+    }
+    fs::remove_dir_all(output_folder)?;
+    fs::remove_dir_all(&work_dir)?;
+    Ok(())
+}
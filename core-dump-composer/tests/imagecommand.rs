@@ -61,19 +61,18 @@ fn image_command_scenario() -> Result<(), std::io::Error> {
     println!("{}", String::from_utf8_lossy(&cdc.stdout));
     println!("{}", String::from_utf8_lossy(&cdc.stderr));
 
-    Command::new("unzip")
-        .arg("output/*.zip")
-        .arg("-d")
-        .arg("output")
+    Command::new("sh")
+        .arg("-c")
+        .arg("tar -xf output/*.tar -C output --strip-components=1 && gunzip output/*.core.gz")
         .output()
-        .expect("unzip failed");
+        .expect("tar extract failed");
 
     let paths = fs::read_dir("./output").unwrap();
     println!("{:?}", paths);
     // Test to see if files are available
     let mut file_counter = 0;
     for path in paths {
-        file_counter = file_counter + 1;
+        file_counter += 1;
         let current_path = format!("{}", path.unwrap().path().display());
         if current_path.contains("dump-info.json") {
             let l_current_path = current_path.clone();
@@ -144,7 +143,7 @@ fn image_command_scenario() -> Result<(), std::io::Error> {
             assert!(String::from_utf8_lossy(&diff.stdout).is_empty());
         }
     }
-    assert_eq!(8, file_counter);
+    assert_eq!(13, file_counter);
     fs::remove_dir_all("./output")?;
     Ok(())
 }
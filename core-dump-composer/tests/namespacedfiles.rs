@@ -70,19 +70,18 @@ fn namespaced_files_scenario() -> Result<(), std::io::Error> {
     println!("{}", String::from_utf8_lossy(&cdc.stdout));
     println!("{}", String::from_utf8_lossy(&cdc.stderr));
 
-    Command::new("unzip")
-        .arg("output/*.zip")
-        .arg("-d")
-        .arg("output")
+    Command::new("sh")
+        .arg("-c")
+        .arg("tar -xf output/*.tar -C output --strip-components=1 && gunzip output/*.core.gz")
         .output()
-        .expect("unzip failed");
+        .expect("tar extract failed");
 
     let paths = fs::read_dir("./output").unwrap();
     println!("{:?}", paths);
     // Test to see if files are available
     let mut file_counter = 0;
     for path in paths {
-        file_counter = file_counter + 1;
+        file_counter += 1;
         let l_path = path.unwrap().path();
         let current_path = format!("{}", l_path.display());
         if current_path.contains("dump-info.json") {
@@ -193,7 +192,7 @@ fn namespaced_files_scenario() -> Result<(), std::io::Error> {
             assert_eq!(extension, Some(OsStr::new("zip")));
         }
     }
-    assert_eq!(8, file_counter);
+    assert_eq!(13, file_counter);
     fs::remove_dir_all("./output")?;
     Ok(())
 }
@@ -0,0 +1,116 @@
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+/// Runs the composer with `vars` while `pause_file` exists, writing the
+/// core into its stdin. Returns its output and whether all of the core was
+/// read.
+fn run_paused(
+    output_folder: &str,
+    pause_file: &Path,
+    vars: &[(&str, &str)],
+) -> Result<(Output, std::io::Result<()>), std::io::Error> {
+    fs::create_dir_all(output_folder)?;
+    File::create(pause_file)?;
+    Command::new("cp")
+        .arg("-f")
+        .arg("./mocks/crictl-default.sh")
+        .arg("../target/debug/crictl")
+        .output()
+        .expect("cp failed");
+
+    // Written by the test rather than cat, the kernel's pipe breaks the
+    // same way if the composer exits without reading the core.
+    let core = fs::read("./mocks/test.core")?;
+    let mut cdc = Command::new("../target/debug/core-dump-composer")
+        .env("PAUSE_FILE", pause_file)
+        .envs(vars.iter().copied())
+        .arg("-c")
+        .arg("1000000000")
+        .arg("-e")
+        .arg("node")
+        .arg("-p")
+        .arg("4")
+        .arg("-s")
+        .arg("10")
+        .arg("-E")
+        .arg("/target/debug/core-dump-composer")
+        .arg("-d")
+        .arg(output_folder)
+        .arg("-t")
+        .arg("1588462466")
+        .arg("-h")
+        .arg("crashing-app-699c49b4ff-86wrh")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to execute core dump composer");
+
+    let written = cdc.stdin.take().unwrap().write_all(&core);
+    let cdc = cdc.wait_with_output()?;
+    fs::remove_file(pause_file)?;
+
+    println!("{}", String::from_utf8_lossy(&cdc.stdout));
+    println!("{}", String::from_utf8_lossy(&cdc.stderr));
+    Ok((cdc, written))
+}
+
+#[test]
+fn paused_scenario() -> Result<(), std::io::Error> {
+    let output_folder = "./output-paused";
+    let pause_file = env::current_dir()?.join("pause-paused");
+    let (cdc, written) = run_paused(output_folder, &pause_file, &[])?;
+    assert!(cdc.status.success());
+    assert!(written.is_ok(), "core not read: {:?}", written);
+
+    Command::new("sh")
+        .arg("-c")
+        .arg(format!(
+            "tar -xf {output_folder}/*.tar -C {output_folder} --strip-components=1"
+        ))
+        .output()
+        .expect("tar extract failed");
+    let mut errors = vec![];
+    for path in fs::read_dir(output_folder)? {
+        let current_path = format!("{}", path.unwrap().path().display());
+        assert!(!current_path.contains(".core"), "{current_path} stored");
+        if current_path.contains("capture-result.json") {
+            let file = File::open(&current_path)?;
+            let json: serde_json::Value = serde_json::from_reader(file)?;
+            for error in json["errors"].as_array().unwrap() {
+                errors.push(error["error"].as_str().unwrap().to_string());
+            }
+        }
+    }
+    assert!(
+        errors.iter().any(|e| e.contains("pause file")),
+        "{errors:?}"
+    );
+    fs::remove_dir_all(output_folder)?;
+    Ok(())
+}
+
+#[test]
+fn paused_skip_scenario() -> Result<(), std::io::Error> {
+    let output_folder = "./output-paused-skip";
+    let pause_file = env::current_dir()?.join("pause-paused-skip");
+    let (cdc, written) = run_paused(
+        output_folder,
+        &pause_file,
+        &[("PAUSE_MODE", "skip"), ("DRAIN_SKIPPED", "true")],
+    )?;
+    assert!(cdc.status.success());
+    assert!(written.is_ok(), "core not read: {:?}", written);
+
+    let archives: Vec<_> = fs::read_dir(output_folder)?
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.to_string_lossy().ends_with(".tar"))
+        .collect();
+    assert!(archives.is_empty(), "skipped core archived: {archives:?}");
+    fs::remove_dir_all(output_folder)?;
+    Ok(())
+}
@@ -1,4 +1,5 @@
 use std::env;
+use std::fs;
 use std::process::{Command, Stdio};
 
 #[test]
@@ -67,6 +68,15 @@ fn timeout_scenario() -> Result<(), std::io::Error> {
 
     println!("{}", String::from_utf8_lossy(&cdc.stdout));
     println!("{}", String::from_utf8_lossy(&cdc.stderr));
-    assert_eq!(32, *&cdc.status.code().unwrap());
+    assert_eq!(32, cdc.status.code().unwrap());
+
+    let record = fs::read_dir(&output_folder)?
+        .map(|entry| entry.unwrap().path())
+        .find(|path| path.to_string_lossy().ends_with(".failure.json"))
+        .expect("a timed out capture should leave a failure record");
+    let json: serde_json::Value = serde_json::from_slice(&fs::read(&record)?)?;
+    assert_eq!("timeout", json["kind"]);
+    assert_eq!(32, json["exit_code"]);
+    fs::remove_file(record)?;
     Ok(())
 }
@@ -70,19 +70,18 @@ fn without_crio_scenario() -> Result<(), std::io::Error> {
     println!("{}", String::from_utf8_lossy(&cdc.stdout));
     println!("{}", String::from_utf8_lossy(&cdc.stderr));
 
-    Command::new("unzip")
-        .arg("output/*.zip")
-        .arg("-d")
-        .arg("output")
+    Command::new("sh")
+        .arg("-c")
+        .arg("tar -xf output/*.tar -C output --strip-components=1 && gunzip output/*.core.gz")
         .output()
-        .expect("unzip failed");
+        .expect("tar extract failed");
 
     let paths = fs::read_dir("./output").unwrap();
     println!("{:?}", paths);
     // Test to see if files are available
     let mut file_counter = 0;
     for path in paths {
-        file_counter = file_counter + 1;
+        file_counter += 1;
         let current_path = format!("{}", path.unwrap().path().display());
         if current_path.contains("dump-info.json") {
             let l_current_path = current_path.clone();
@@ -107,6 +106,18 @@ fn without_crio_scenario() -> Result<(), std::io::Error> {
             assert_eq!("10", signal);
         }
 
+        if current_path.contains("capture-result.json") {
+            let l_current_path = current_path.clone();
+            println!("Testing: {}", l_current_path);
+            let file = File::open(l_current_path).expect("file should open read only");
+            let json: serde_json::Value =
+                serde_json::from_reader(file).expect("file should be proper JSON");
+            let status = json
+                .get("status")
+                .expect("capture-result.json should have status key");
+            assert_eq!("success", status);
+        }
+
         if current_path.contains(".core") {
             let l_current_path = current_path.clone();
             println!("Testing: {}", l_current_path);
@@ -119,7 +130,7 @@ fn without_crio_scenario() -> Result<(), std::io::Error> {
             assert!(String::from_utf8_lossy(&diff.stdout).is_empty());
         }
     }
-    assert_eq!(3, file_counter);
+    assert_eq!(8, file_counter);
     fs::remove_dir_all("./output")?;
     Ok(())
 }